@@ -45,6 +45,7 @@ pub struct RunConfig<C> {
 }
 
 impl<C> RunConfig<C> {
+    #[allow(clippy::result_large_err)]
     pub fn run(
         self,
         command: &C,
@@ -0,0 +1,103 @@
+use aquatic_common::cli::LogLevel;
+use aquatic_http::config::Config as HttpConfig;
+use aquatic_toml_config::TomlConfig;
+use aquatic_udp::config::Config as UdpConfig;
+use aquatic_ws::config::Config as WsConfig;
+use serde::Deserialize;
+
+/// aquatic configuration for running several protocols in a single process
+///
+/// Each protocol runs with its own state. The log level settings of the
+/// protocol sections are ignored.
+///
+/// Since privileges are dropped for the whole process, combining privilege
+/// dropping with binding to privileged ports only works reliably when a
+/// single protocol is run.
+///
+/// When using systemd socket activation for several protocols, name the
+/// sockets `udp`, `http` and `ws` with `FileDescriptorName=` in the socket
+/// units. Readiness is reported to systemd once all protocols are ready.
+/// Signals are received by all protocols, e.g., `SIGUSR1` reloads the access
+/// lists of all of them and `SIGTERM` shuts all of them down.
+#[derive(Clone, Debug, PartialEq, TomlConfig, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub log_level: LogLevel,
+    /// Run UDP tracker
+    pub run_udp: bool,
+    /// Run HTTP tracker
+    pub run_http: bool,
+    /// Run WebTorrent tracker
+    pub run_ws: bool,
+    pub udp: UdpConfig,
+    pub http: HttpConfig,
+    pub ws: WsConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let mut udp = UdpConfig::default();
+        let mut http = HttpConfig::default();
+        let mut ws = WsConfig::default();
+
+        // Don't let default listen addresses collide
+        udp.network.address.set_port(3000);
+        http.network.address.set_port(3001);
+        ws.network.address.set_port(3002);
+
+        Self {
+            log_level: LogLevel::default(),
+            run_udp: true,
+            run_http: false,
+            run_ws: false,
+            udp,
+            http,
+            ws,
+        }
+    }
+}
+
+impl aquatic_common::cli::Config for Config {
+    fn get_log_level(&self) -> Option<LogLevel> {
+        Some(self.log_level)
+    }
+    fn validation_errors(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if !(self.run_udp || self.run_http || self.run_ws) {
+            errors.push("at least one of run_udp, run_http and run_ws must be set to true".into());
+        }
+
+        let sections = [
+            (self.run_udp, "udp", self.udp.validation_errors()),
+            (self.run_http, "http", self.http.validation_errors()),
+            (self.run_ws, "ws", self.ws.validation_errors()),
+        ];
+
+        for (active, name, section_errors) in sections {
+            if active {
+                errors.extend(
+                    section_errors
+                        .into_iter()
+                        .map(|error| format!("{}.{}", name, error)),
+                );
+            }
+        }
+
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use aquatic_common::cli::Config as _;
+
+    use super::Config;
+
+    ::aquatic_toml_config::gen_serialize_deserialize_test!(Config);
+
+    #[test]
+    fn test_default_config_is_valid() {
+        assert_eq!(Config::default().validation_errors(), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,299 @@
+//! Checks for common deployment problems (`aquatic doctor`)
+
+use std::fmt::Display;
+use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
+use std::path::Path;
+
+use aquatic_common::access_list::{AccessList, AccessListConfig};
+use aquatic_common::ban_list::{BanList, BanListConfig};
+use aquatic_common::rustls_config::create_rustls_config;
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::config::Config;
+
+/// Warn if open file limit is lower than this when running protocols that
+/// use one file descriptor per connection
+const MIN_RECOMMENDED_OPEN_FILES: libc::rlim_t = 10_000;
+
+#[derive(Clone, Copy)]
+enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+#[derive(Default)]
+struct Report {
+    num_failed: usize,
+}
+
+impl Report {
+    fn add(&mut self, status: Status, check: &str, details: impl Display) {
+        let label = match status {
+            Status::Ok => " OK ",
+            Status::Warn => "WARN",
+            Status::Fail => {
+                self.num_failed += 1;
+
+                "FAIL"
+            }
+        };
+
+        println!("[{}] {}: {}", label, check, details);
+    }
+}
+
+/// Print report on environment and config of protocols enabled in config
+///
+/// Invalid configuration is reported before this is called.
+pub fn run_doctor(config: Config) -> anyhow::Result<()> {
+    let mut report = Report::default();
+
+    report.add(Status::Ok, "config", "valid");
+
+    check_open_file_limit(&mut report, config.run_http || config.run_ws);
+
+    if config.run_udp {
+        let network = &config.udp.network;
+
+        if network.systemd_socket_activation {
+            report.add(
+                Status::Ok,
+                "udp: bind",
+                "skipped (systemd socket activation)",
+            );
+        } else {
+            for address in network.addresses() {
+                check_bind(&mut report, "udp", address, network.only_ipv6, Type::DGRAM);
+            }
+        }
+
+        check_access_list(&mut report, "udp", &config.udp.access_list);
+        check_ban_list(&mut report, "udp", &config.udp.ban_list);
+    }
+    if config.run_http {
+        let network = &config.http.network;
+
+        if network.systemd_socket_activation {
+            report.add(
+                Status::Ok,
+                "http: bind",
+                "skipped (systemd socket activation)",
+            );
+        } else {
+            check_bind(
+                &mut report,
+                "http",
+                network.address,
+                network.only_ipv6,
+                Type::STREAM,
+            );
+        }
+
+        if network.enable_tls {
+            check_tls(
+                &mut report,
+                "http",
+                &network.tls_certificate_path,
+                &network.tls_private_key_path,
+            );
+        }
+
+        check_access_list(&mut report, "http", &config.http.access_list);
+        check_ban_list(&mut report, "http", &config.http.ban_list);
+    }
+    if config.run_ws {
+        let network = &config.ws.network;
+
+        if network.systemd_socket_activation {
+            report.add(
+                Status::Ok,
+                "ws: bind",
+                "skipped (systemd socket activation)",
+            );
+        } else {
+            check_bind(
+                &mut report,
+                "ws",
+                network.address,
+                network.only_ipv6,
+                Type::STREAM,
+            );
+        }
+
+        if network.enable_tls {
+            check_tls(
+                &mut report,
+                "ws",
+                &network.tls_certificate_path,
+                &network.tls_private_key_path,
+            );
+        }
+
+        check_access_list(&mut report, "ws", &config.ws.access_list);
+        check_ban_list(&mut report, "ws", &config.ws.ban_list);
+    }
+
+    if report.num_failed > 0 {
+        Err(anyhow::anyhow!("{} check(s) failed", report.num_failed))
+    } else {
+        println!("\nAll checks passed");
+
+        Ok(())
+    }
+}
+
+fn check_open_file_limit(report: &mut Report, one_file_per_connection: bool) {
+    let check = "open file limit";
+
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        report.add(Status::Fail, check, io::Error::last_os_error());
+
+        return;
+    }
+
+    let details = format!(
+        "soft limit {}, hard limit {}",
+        limit.rlim_cur, limit.rlim_max
+    );
+
+    if one_file_per_connection && limit.rlim_cur < MIN_RECOMMENDED_OPEN_FILES {
+        report.add(
+            Status::Warn,
+            check,
+            format!(
+                "{}. HTTP and WebTorrent trackers use one file descriptor per connection, consider raising it",
+                details
+            ),
+        );
+    } else {
+        report.add(Status::Ok, check, details);
+    }
+}
+
+/// Check that address is free by binding to it without SO_REUSEPORT, then
+/// bind two sockets with SO_REUSEPORT, as socket workers do
+fn check_bind(
+    report: &mut Report,
+    protocol_name: &str,
+    address: SocketAddr,
+    only_ipv6: bool,
+    socket_type: Type,
+) {
+    let check = format!("{}: bind to {}", protocol_name, address);
+
+    let create_socket = |reuse_port: bool| -> io::Result<Socket> {
+        let protocol = if socket_type == Type::DGRAM {
+            Protocol::UDP
+        } else {
+            Protocol::TCP
+        };
+
+        let socket = Socket::new(Domain::for_address(address), socket_type, Some(protocol))?;
+
+        if only_ipv6 && address.is_ipv6() {
+            socket.set_only_v6(true)?;
+        }
+
+        if reuse_port {
+            socket.set_reuse_port(true)?;
+        }
+
+        socket.bind(&address.into())?;
+
+        Ok(socket)
+    };
+
+    match create_socket(false) {
+        Ok(socket) => drop(socket),
+        Err(err) if err.kind() == ErrorKind::PermissionDenied => {
+            report.add(
+                Status::Fail,
+                &check,
+                format!(
+                    "{}. Binding to ports below 1024 usually requires running as root or the CAP_NET_BIND_SERVICE capability",
+                    err
+                ),
+            );
+
+            return;
+        }
+        Err(err) if err.kind() == ErrorKind::AddrInUse => {
+            report.add(
+                Status::Fail,
+                &check,
+                format!(
+                    "{}. Is another process (e.g., a running tracker) listening on it?",
+                    err
+                ),
+            );
+
+            return;
+        }
+        Err(err) => {
+            report.add(Status::Fail, &check, err);
+
+            return;
+        }
+    };
+
+    let sockets = create_socket(true).and_then(|first| Ok((first, create_socket(true)?)));
+
+    match sockets {
+        Ok(_) => report.add(Status::Ok, &check, "possible, SO_REUSEPORT is available"),
+        Err(err) => report.add(
+            Status::Fail,
+            &check,
+            format!("binding with SO_REUSEPORT failed: {}", err),
+        ),
+    }
+}
+
+fn check_tls(report: &mut Report, protocol_name: &str, certificate_path: &Path, key_path: &Path) {
+    let check = format!("{}: TLS", protocol_name);
+
+    match create_rustls_config(certificate_path, key_path) {
+        Ok(_) => report.add(Status::Ok, &check, "certificate and private key loaded"),
+        Err(err) => report.add(Status::Fail, &check, format!("{:#}", err)),
+    }
+}
+
+fn check_ban_list(report: &mut Report, protocol_name: &str, config: &BanListConfig) {
+    if !config.enabled {
+        return;
+    }
+
+    let check = format!("{}: ban list", protocol_name);
+
+    match BanList::create_from_path(&config.path) {
+        Ok(ban_list) => report.add(
+            Status::Ok,
+            &check,
+            format!("{} entries loaded", ban_list.len()),
+        ),
+        Err(err) => report.add(Status::Fail, &check, format!("{:#}", err)),
+    }
+}
+
+fn check_access_list(report: &mut Report, protocol_name: &str, config: &AccessListConfig) {
+    if !config.mode.is_on() {
+        return;
+    }
+
+    let check = format!("{}: access list", protocol_name);
+
+    match AccessList::create_from_config(config) {
+        Ok(access_list) => report.add(
+            Status::Ok,
+            &check,
+            format!("{} info hashes loaded", access_list.len()),
+        ),
+        Err(err) => report.add(Status::Fail, &check, format!("{:#}", err)),
+    }
+}
@@ -1,12 +1,24 @@
+mod config;
+mod doctor;
+
+use std::sync::mpsc::channel;
+use std::thread::{Builder, JoinHandle};
+use std::time::Duration;
+
+use anyhow::Context;
 use aquatic_common::cli::{print_help, run_app_with_cli_and_config, Options};
+use aquatic_common::systemd::{self, StatusNotifier};
 use aquatic_http::config::Config as HttpConfig;
 use aquatic_udp::config::Config as UdpConfig;
 use aquatic_ws::config::Config as WsConfig;
 
+use config::Config as CombinedConfig;
+
 #[global_allocator]
 static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
 const APP_NAME: &str = "aquatic: BitTorrent tracker";
+const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 fn main() {
     ::std::process::exit(match run() {
@@ -59,6 +71,18 @@ fn run() -> Result<(), Option<String>> {
             aquatic_ws::run,
             Some(options),
         ),
+        "all" => run_app_with_cli_and_config::<CombinedConfig>(
+            APP_NAME,
+            APP_VERSION,
+            run_combined,
+            Some(options),
+        ),
+        "doctor" => run_app_with_cli_and_config::<CombinedConfig>(
+            APP_NAME,
+            APP_VERSION,
+            doctor::run_doctor,
+            Some(options),
+        ),
         arg => {
             let opt_err = if arg == "-h" || arg == "--help" {
                 None
@@ -86,6 +110,150 @@ fn gen_info() -> String {
     info.push_str("\n    udp                   BitTorrent over UDP");
     info.push_str("\n    http                  BitTorrent over HTTP");
     info.push_str("\n    ws                    WebTorrent");
+    info.push_str("\n    all                   Protocols enabled in config, in one process");
+    info.push_str("\n    doctor                Check config and environment for protocols");
+    info.push_str("\n                          enabled in config, then quit");
 
     info
 }
+
+fn run_combined(config: CombinedConfig) -> anyhow::Result<()> {
+    let socket_activated_protocols = [
+        (
+            config.run_udp,
+            "udp",
+            config.udp.network.systemd_socket_activation,
+        ),
+        (
+            config.run_http,
+            "http",
+            config.http.network.systemd_socket_activation,
+        ),
+        (
+            config.run_ws,
+            "ws",
+            config.ws.network.systemd_socket_activation,
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(run, name, socket_activation)| (run && socket_activation).then_some(name))
+    .collect::<Vec<_>>();
+
+    // Without names, every protocol would get the same socket
+    if socket_activated_protocols.len() > 1 {
+        for name in socket_activated_protocols {
+            if !systemd::has_listen_fd_named(name) {
+                return Err(anyhow::anyhow!(
+                    "systemd socket activation: no file descriptor named {} was passed. Set FileDescriptorName= in socket units when using socket activation for several protocols.",
+                    name
+                ));
+            }
+        }
+    }
+
+    // Trackers report readiness through this channel and leave notifying
+    // systemd to this function, so that notifications are sent once
+    let (ready_sender, ready_receiver) = channel();
+
+    let mut join_handles: Vec<(&str, JoinHandle<anyhow::Result<()>>)> = Vec::new();
+
+    if config.run_udp {
+        let config = config.udp.clone();
+        let status_notifier = StatusNotifier::Embedded(ready_sender.clone());
+
+        let handle = Builder::new()
+            .name("udp".into())
+            .spawn(move || aquatic_udp::run_with_status_notifier(config, status_notifier))
+            .context("spawn udp tracker")?;
+
+        join_handles.push(("udp tracker", handle));
+    }
+    if config.run_http {
+        let config = config.http.clone();
+        let status_notifier = StatusNotifier::Embedded(ready_sender.clone());
+
+        let handle = Builder::new()
+            .name("http".into())
+            .spawn(move || aquatic_http::run_with_status_notifier(config, status_notifier))
+            .context("spawn http tracker")?;
+
+        join_handles.push(("http tracker", handle));
+    }
+    if config.run_ws {
+        let config = config.ws.clone();
+        let status_notifier = StatusNotifier::Embedded(ready_sender.clone());
+
+        let handle = Builder::new()
+            .name("ws".into())
+            .spawn(move || aquatic_ws::run_with_status_notifier(config, status_notifier))
+            .context("spawn ws tracker")?;
+
+        join_handles.push(("ws tracker", handle));
+    }
+
+    if join_handles.is_empty() {
+        return Err(anyhow::anyhow!(
+            "configuration: at least one of run_udp, run_http and run_ws must be set to true"
+        ));
+    }
+
+    let num_trackers = join_handles.len();
+    let mut num_ready = 0;
+    let mut shutting_down = false;
+
+    let opt_watchdog_interval = systemd::watchdog_interval();
+    let sleep_duration = opt_watchdog_interval
+        .unwrap_or(Duration::MAX)
+        .min(Duration::from_secs(5));
+
+    // Quit application if any tracker fails or panics. Trackers return Ok
+    // when shutting down after receiving SIGTERM or SIGINT, which they all
+    // receive, so wait for the others to finish too in that case.
+    loop {
+        let opt_finished = join_handles
+            .iter()
+            .position(|(_, handle)| handle.is_finished());
+
+        if let Some(i) = opt_finished {
+            let (name, handle) = join_handles.remove(i);
+
+            if !shutting_down {
+                shutting_down = true;
+
+                systemd::notify_or_log("STOPPING=1");
+            }
+
+            match handle.join() {
+                Ok(Ok(())) => {
+                    ::log::info!("{} shut down", name);
+                }
+                Ok(Err(err)) => {
+                    return Err(err.context(format!("{} stopped", name)));
+                }
+                Err(_) => {
+                    return Err(anyhow::anyhow!("{} panicked", name));
+                }
+            }
+
+            if join_handles.is_empty() {
+                return Ok(());
+            }
+
+            continue;
+        }
+
+        if opt_watchdog_interval.is_some() {
+            systemd::notify_or_log("WATCHDOG=1");
+        }
+
+        // Sender is kept alive above, so this doesn't return early because
+        // of disconnection
+        if let Ok(()) = ready_receiver.recv_timeout(sleep_duration) {
+            num_ready += 1;
+
+            if num_ready == num_trackers {
+                systemd::notify_or_log("READY=1");
+            }
+        }
+    }
+}
@@ -1,7 +1,9 @@
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, Read};
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::thread::{sleep, Builder, JoinHandle};
+use std::time::Duration;
 
 use anyhow::Context;
 use aquatic_toml_config::TomlConfig;
@@ -9,6 +11,13 @@ use arc_swap::{ArcSwap, Cache};
 use hashbrown::HashSet;
 use serde::{Deserialize, Serialize};
 
+use crate::http_client::describe_request_error;
+
+/// Maximum size of access list fetched from URL
+const MAX_FETCHED_ACCESS_LIST_SIZE: u64 = 1024 * 1024 * 1024;
+/// Timeout for fetching access list from URL, including download
+const FETCH_TIMEOUT: Duration = Duration::from_secs(60);
+
 /// Access list mode. Available modes are allow, deny and off.
 #[derive(Clone, Copy, Debug, PartialEq, TomlConfig, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -25,6 +34,22 @@ impl AccessListMode {
     pub fn is_on(&self) -> bool {
         !matches!(self, Self::Off)
     }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Allow => "allow",
+            Self::Deny => "deny",
+            Self::Off => "off",
+        }
+    }
+}
+
+/// Result of checking info hash against access list
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AccessListCheck {
+    pub allowed: bool,
+    /// Info hash is present in list. Always false if mode is off.
+    pub hit: bool,
 }
 
 #[derive(Clone, Debug, PartialEq, TomlConfig, Deserialize, Serialize)]
@@ -35,6 +60,42 @@ pub struct AccessListConfig {
     ///
     /// If using chroot mode, path must be relative to new root.
     pub path: PathBuf,
+    /// Fetch access list from this HTTPS (or HTTP) URL instead of reading it
+    /// from file. Leave empty to disable.
+    ///
+    /// The response body has the same format as the file. The list is
+    /// fetched on start, when the program receives `SIGUSR1` and every
+    /// `sync_interval` seconds. Periodic fetches send `If-None-Match` and
+    /// `If-Modified-Since` headers, so that unchanged lists aren't
+    /// downloaded again. Lists larger than 1 GiB are rejected. If a fetch
+    /// after start fails, the previous list is kept.
+    ///
+    /// If using chroot mode, resolving the host name might require files
+    /// such as `/etc/resolv.conf` to be present in new root.
+    pub url: String,
+    /// Fetch access list from URL this often (seconds). Set to 0 to only
+    /// fetch it on start and on `SIGUSR1`.
+    pub sync_interval: u64,
+}
+
+impl AccessListConfig {
+    /// Access list should be fetched from URL periodically
+    pub fn sync_active(&self) -> bool {
+        self.mode.is_on() && !self.url.is_empty() && self.sync_interval != 0
+    }
+
+    pub fn validation_errors(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if self.mode.is_on()
+            && !self.url.is_empty()
+            && !(self.url.starts_with("https://") || self.url.starts_with("http://"))
+        {
+            errors.push("access_list.url must start with https:// or http://".into());
+        }
+
+        errors
+    }
 }
 
 impl Default for AccessListConfig {
@@ -42,24 +103,45 @@ impl Default for AccessListConfig {
         Self {
             path: "./access-list.txt".into(),
             mode: AccessListMode::Off,
+            url: "".into(),
+            sync_interval: 300,
         }
     }
 }
 
 #[derive(Default, Clone)]
-pub struct AccessList(HashSet<[u8; 20]>);
+pub struct AccessList {
+    info_hashes: HashSet<[u8; 20]>,
+    /// Cache validators of the response the list was fetched from, if any
+    opt_etag: Option<String>,
+    opt_last_modified: Option<String>,
+}
 
 impl AccessList {
     pub fn insert_from_line(&mut self, line: &str) -> anyhow::Result<()> {
-        self.0.insert(parse_info_hash(line)?);
+        self.info_hashes.insert(parse_info_hash(line)?);
 
         Ok(())
     }
 
+    /// Read access list from file or fetch it from URL, depending on config
+    pub fn create_from_config(config: &AccessListConfig) -> anyhow::Result<Self> {
+        if config.url.is_empty() {
+            Self::create_from_path(&config.path)
+        } else {
+            AccessListFetcher::new(&config.url)
+                .fetch(None)?
+                .ok_or_else(|| anyhow::anyhow!("server reported unchanged access list"))
+        }
+    }
+
     pub fn create_from_path(path: &PathBuf) -> anyhow::Result<Self> {
         let file = File::open(path)?;
-        let reader = BufReader::new(file);
 
+        Self::create_from_reader(BufReader::new(file))
+    }
+
+    fn create_from_reader(reader: impl BufRead) -> anyhow::Result<Self> {
         let mut new_list = Self::default();
 
         for line in reader.lines() {
@@ -79,16 +161,30 @@ impl AccessList {
     }
 
     pub fn allows(&self, mode: AccessListMode, info_hash: &[u8; 20]) -> bool {
-        match mode {
-            AccessListMode::Allow => self.0.contains(info_hash),
-            AccessListMode::Deny => !self.0.contains(info_hash),
-            AccessListMode::Off => true,
+        self.check(mode, info_hash).allowed
+    }
+
+    /// Check if info hash is allowed, also returning if it is present in
+    /// list, e.g., for hit counters
+    pub fn check(&self, mode: AccessListMode, info_hash: &[u8; 20]) -> AccessListCheck {
+        if !mode.is_on() {
+            return AccessListCheck {
+                allowed: true,
+                hit: false,
+            };
+        }
+
+        let hit = self.info_hashes.contains(info_hash);
+
+        AccessListCheck {
+            allowed: hit == matches!(mode, AccessListMode::Allow),
+            hit,
         }
     }
 
     #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
-        self.0.len()
+        self.info_hashes.len()
     }
 }
 
@@ -102,15 +198,15 @@ pub type AccessListCache = Cache<Arc<AccessListArcSwap>, Arc<AccessList>>;
 
 impl AccessListQuery for AccessListArcSwap {
     fn update(&self, config: &AccessListConfig) -> anyhow::Result<()> {
-        self.store(Arc::new(AccessList::create_from_path(&config.path)?));
+        self.store(Arc::new(AccessList::create_from_config(config)?));
 
         Ok(())
     }
 
     fn allows(&self, mode: AccessListMode, info_hash_bytes: &[u8; 20]) -> bool {
         match mode {
-            AccessListMode::Allow => self.load().0.contains(info_hash_bytes),
-            AccessListMode::Deny => !self.load().0.contains(info_hash_bytes),
+            AccessListMode::Allow => self.load().info_hashes.contains(info_hash_bytes),
+            AccessListMode::Deny => !self.load().info_hashes.contains(info_hash_bytes),
             AccessListMode::Off => true,
         }
     }
@@ -140,6 +236,107 @@ pub fn update_access_list(
     Ok(())
 }
 
+/// Fetches access list from URL, skipping the download if the list hasn't
+/// changed since it was previously fetched
+pub struct AccessListFetcher {
+    agent: ureq::Agent,
+    url: String,
+    max_size: u64,
+}
+
+impl AccessListFetcher {
+    pub fn new(url: &str) -> Self {
+        Self {
+            agent: ureq::AgentBuilder::new().timeout(FETCH_TIMEOUT).build(),
+            url: url.into(),
+            max_size: MAX_FETCHED_ACCESS_LIST_SIZE,
+        }
+    }
+
+    /// Fetch access list. Returns `None` if server reports that it hasn't
+    /// changed since `opt_previous` was fetched.
+    ///
+    /// Fails without reading further if the body exceeds the maximum size.
+    pub fn fetch(&self, opt_previous: Option<&AccessList>) -> anyhow::Result<Option<AccessList>> {
+        let mut request = self.agent.get(&self.url);
+
+        if let Some(previous) = opt_previous {
+            if let Some(etag) = previous.opt_etag.as_ref() {
+                request = request.set("If-None-Match", etag);
+            }
+            if let Some(last_modified) = previous.opt_last_modified.as_ref() {
+                request = request.set("If-Modified-Since", last_modified);
+            }
+        }
+
+        let response = request
+            .call()
+            .map_err(describe_request_error)
+            .context("request access list")?;
+
+        match response.status() {
+            200 => (),
+            304 => return Ok(None),
+            status => return Err(anyhow::anyhow!("unexpected response status {}", status)),
+        }
+
+        let opt_etag = response.header("ETag").map(Into::into);
+        let opt_last_modified = response.header("Last-Modified").map(Into::into);
+
+        // Read one byte past the limit to detect oversized lists instead of
+        // silently truncating them
+        let mut reader = BufReader::new(response.into_reader().take(self.max_size + 1));
+
+        let result = AccessList::create_from_reader(&mut reader);
+
+        if reader.get_ref().limit() == 0 {
+            return Err(anyhow::anyhow!(
+                "access list exceeds maximum size of {} bytes",
+                self.max_size
+            ));
+        }
+
+        let mut access_list = result?;
+
+        access_list.opt_etag = opt_etag;
+        access_list.opt_last_modified = opt_last_modified;
+
+        Ok(Some(access_list))
+    }
+}
+
+/// Spawn thread fetching access list from URL every
+/// `config.sync_interval` seconds
+pub fn spawn_access_list_sync_worker(
+    config: &AccessListConfig,
+    access_list: Arc<AccessListArcSwap>,
+) -> anyhow::Result<JoinHandle<anyhow::Result<()>>> {
+    let interval = Duration::from_secs(config.sync_interval);
+    let fetcher = AccessListFetcher::new(&config.url);
+
+    Builder::new()
+        .name("access-list-sync".into())
+        .spawn(move || loop {
+            sleep(interval);
+
+            // Send validators of the current list, which might have been
+            // fetched on start or on SIGUSR1
+            match fetcher.fetch(Some(&access_list.load())) {
+                Ok(Some(new_list)) => {
+                    ::log::info!(
+                        "Access list updated from URL ({} info hashes)",
+                        new_list.len()
+                    );
+
+                    access_list.store(Arc::new(new_list));
+                }
+                Ok(None) => ::log::debug!("Access list at URL unchanged"),
+                Err(err) => ::log::error!("Fetching access list from URL failed: {:#}", err),
+            }
+        })
+        .context("spawn access list sync worker")
+}
+
 fn parse_info_hash(line: &str) -> anyhow::Result<[u8; 20]> {
     let mut bytes = [0u8; 20];
 
@@ -150,6 +347,8 @@ fn parse_info_hash(line: &str) -> anyhow::Result<[u8; 20]> {
 
 #[cfg(test)]
 mod tests {
+    use crate::http_client::test_helpers::spawn_test_http_server;
+
     use super::*;
 
     #[test]
@@ -170,8 +369,8 @@ mod tests {
         let b = parse_info_hash("bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb").unwrap();
         let c = parse_info_hash("cccccccccccccccccccccccccccccccccccccccc").unwrap();
 
-        access_list.0.insert(a);
-        access_list.0.insert(b);
+        access_list.info_hashes.insert(a);
+        access_list.info_hashes.insert(b);
 
         let access_list = Arc::new(ArcSwap::new(Arc::new(access_list)));
 
@@ -194,4 +393,118 @@ mod tests {
         assert!(access_list_cache.load().allows(AccessListMode::Deny, &a));
         assert!(access_list_cache.load().allows(AccessListMode::Deny, &b));
     }
+
+    #[test]
+    fn test_check_hit() {
+        let mut access_list = AccessList::default();
+
+        let a = parse_info_hash("aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa").unwrap();
+        let b = parse_info_hash("bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb").unwrap();
+
+        access_list.info_hashes.insert(a);
+
+        let check = |mode, info_hash| {
+            let AccessListCheck { allowed, hit } = access_list.check(mode, info_hash);
+
+            (allowed, hit)
+        };
+
+        assert_eq!(check(AccessListMode::Allow, &a), (true, true));
+        assert_eq!(check(AccessListMode::Allow, &b), (false, false));
+        assert_eq!(check(AccessListMode::Deny, &a), (false, true));
+        assert_eq!(check(AccessListMode::Deny, &b), (true, false));
+        assert_eq!(check(AccessListMode::Off, &a), (true, false));
+    }
+
+    #[test]
+    fn test_config_validation() {
+        let mut config = AccessListConfig {
+            mode: AccessListMode::Allow,
+            url: "ftp://example.com/list.txt".into(),
+            ..Default::default()
+        };
+
+        assert_eq!(config.validation_errors().len(), 1);
+        assert!(config.sync_active());
+
+        config.url = "https://example.com/list.txt".into();
+
+        assert!(config.validation_errors().is_empty());
+
+        config.sync_interval = 0;
+
+        assert!(!config.sync_active());
+
+        config.url = "".into();
+        config.sync_interval = 300;
+
+        assert!(config.validation_errors().is_empty());
+        assert!(!config.sync_active());
+    }
+
+    #[test]
+    fn test_fetcher() {
+        let (addr, server) = spawn_test_http_server(
+            [
+                "HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Length: 41\r\nConnection: close\r\n\r\naaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\n",
+                "HTTP/1.1 304 Not Modified\r\nConnection: close\r\n\r\n",
+                "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            ]
+            .map(Into::into)
+            .to_vec(),
+        );
+
+        let fetcher = AccessListFetcher::new(&format!("http://{}/list.txt", addr));
+
+        let access_list = fetcher.fetch(None).unwrap().unwrap();
+        let a = parse_info_hash("aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa").unwrap();
+
+        assert_eq!(access_list.len(), 1);
+        assert!(access_list.allows(AccessListMode::Allow, &a));
+
+        assert!(fetcher.fetch(Some(&access_list)).unwrap().is_none());
+        match fetcher.fetch(Some(&access_list)) {
+            Err(err) => assert!(!format!("{:#}", err).contains("list.txt")),
+            Ok(_) => panic!("fetch succeeded"),
+        }
+
+        let requests = server.join().unwrap();
+
+        assert!(!requests[0]
+            .head
+            .to_ascii_lowercase()
+            .contains("if-none-match"));
+        assert!(requests[1]
+            .head
+            .to_ascii_lowercase()
+            .contains("if-none-match: \"v1\""));
+    }
+
+    #[test]
+    fn test_fetcher_size_limit() {
+        let body =
+            "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\nbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb\n";
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+
+        let (addr, server) = spawn_test_http_server(vec![response.clone(), response]);
+
+        let mut fetcher = AccessListFetcher::new(&format!("http://{}/list.txt", addr));
+
+        fetcher.max_size = body.len() as u64;
+
+        assert_eq!(fetcher.fetch(None).unwrap().unwrap().len(), 2);
+
+        fetcher.max_size = body.len() as u64 - 1;
+
+        match fetcher.fetch(None) {
+            Err(err) => assert!(format!("{:#}", err).contains("exceeds maximum size")),
+            Ok(_) => panic!("fetch succeeded"),
+        }
+
+        server.join().unwrap();
+    }
 }
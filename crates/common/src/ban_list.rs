@@ -0,0 +1,331 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use aquatic_toml_config::TomlConfig;
+use arc_swap::{ArcSwap, Cache};
+use hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::ip_network::IpNetwork;
+use crate::CanonicalSocketAddr;
+
+#[derive(Clone, Debug, PartialEq, TomlConfig, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BanListConfig {
+    /// Ignore requests and connections from IP addresses in ban list file
+    pub enabled: bool,
+    /// Path to ban list file
+    ///
+    /// Each line consists of an IPv4 or IPv6 address or network in CIDR
+    /// notation (e.g., `192.0.2.0/24`), optionally followed by whitespace
+    /// and a Unix timestamp (seconds) after which the entry expires. Empty
+    /// lines and lines starting with `#` are ignored. Networks with host
+    /// bits set (e.g., `192.0.2.1/24`) are rejected.
+    ///
+    /// The file is read on start and when the program receives `SIGUSR1`,
+    /// so bans can be added and removed at runtime by editing it. Expired
+    /// entries are ignored, but not removed from the file.
+    ///
+    /// If using chroot mode, path must be relative to new root.
+    pub path: PathBuf,
+}
+
+impl Default for BanListConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: "./ban-list.txt".into(),
+        }
+    }
+}
+
+/// Banned networks with expiry timestamps, if any
+#[derive(Default, Clone)]
+pub struct BanList {
+    /// Networks by prefix length
+    ipv4: BTreeMap<u8, HashMap<IpNetwork, Expiry>>,
+    ipv6: BTreeMap<u8, HashMap<IpNetwork, Expiry>>,
+    len: usize,
+}
+
+impl BanList {
+    pub fn create_from_path(path: &PathBuf) -> anyhow::Result<Self> {
+        let file = File::open(path)?;
+
+        Self::create_from_reader(BufReader::new(file))
+    }
+
+    fn create_from_reader(reader: impl BufRead) -> anyhow::Result<Self> {
+        let mut ban_list = Self::default();
+
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            ban_list
+                .insert_line(line)
+                .with_context(|| format!("Invalid line {}: {}", i + 1, line))?;
+        }
+
+        Ok(ban_list)
+    }
+
+    fn insert_line(&mut self, line: &str) -> anyhow::Result<()> {
+        let mut parts = line.split_whitespace();
+
+        let network = parts.next().with_context(|| "no address")?;
+        let expiry = match parts.next() {
+            Some(timestamp) => Expiry::At(timestamp.parse().with_context(|| "parse expiry")?),
+            None => Expiry::Never,
+        };
+
+        if parts.next().is_some() {
+            return Err(anyhow::anyhow!("trailing data"));
+        }
+
+        let network = match network.split_once('/') {
+            Some((address, _)) => {
+                let address = address.parse::<IpAddr>().with_context(|| "parse address")?;
+                let network = network.parse::<IpNetwork>()?;
+
+                // Most likely a typo, so don't guess what was meant
+                if network.address() != address {
+                    return Err(anyhow::anyhow!(
+                        "host bits set (network would be {})",
+                        network
+                    ));
+                }
+
+                network
+            }
+            None => {
+                let address = network.parse::<IpAddr>().with_context(|| "parse address")?;
+
+                IpNetwork::containing(address, u8::MAX)
+            }
+        };
+
+        let networks = if network.address().is_ipv4() {
+            &mut self.ipv4
+        } else {
+            &mut self.ipv6
+        };
+
+        let inserted = insert(networks, network, expiry);
+
+        if inserted {
+            self.len += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Check if IP address is in an unexpired banned network
+    pub fn is_banned(&self, addr: CanonicalSocketAddr) -> bool {
+        if self.len == 0 {
+            return false;
+        }
+
+        let ip = addr.get().ip();
+
+        let networks = if ip.is_ipv4() { &self.ipv4 } else { &self.ipv6 };
+
+        // Only look up time if a network is found, since that is rare
+        let mut opt_now = None;
+
+        networks.iter().any(|(prefix_len, networks)| {
+            match networks.get(&IpNetwork::containing(ip, *prefix_len)) {
+                Some(Expiry::Never) => true,
+                Some(Expiry::At(timestamp)) => {
+                    *timestamp > *opt_now.get_or_insert_with(unix_timestamp_now)
+                }
+                None => false,
+            }
+        })
+    }
+
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Expiry {
+    /// Unix timestamp (seconds)
+    At(u64),
+    Never,
+}
+
+/// Insert network, keeping latest expiry of duplicate entries. Returns true
+/// if network wasn't present before.
+fn insert(
+    networks: &mut BTreeMap<u8, HashMap<IpNetwork, Expiry>>,
+    network: IpNetwork,
+    expiry: Expiry,
+) -> bool {
+    match networks
+        .entry(network.prefix_len())
+        .or_default()
+        .entry(network)
+    {
+        hashbrown::hash_map::Entry::Occupied(mut entry) => {
+            let previous = entry.get_mut();
+
+            *previous = (*previous).max(expiry);
+
+            false
+        }
+        hashbrown::hash_map::Entry::Vacant(entry) => {
+            entry.insert(expiry);
+
+            true
+        }
+    }
+}
+
+fn unix_timestamp_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+pub type BanListArcSwap = ArcSwap<BanList>;
+pub type BanListCache = Cache<Arc<BanListArcSwap>, Arc<BanList>>;
+
+pub fn create_ban_list_cache(arc_swap: &Arc<BanListArcSwap>) -> BanListCache {
+    Cache::from(Arc::clone(arc_swap))
+}
+
+pub fn update_ban_list(
+    config: &BanListConfig,
+    ban_list: &Arc<BanListArcSwap>,
+) -> anyhow::Result<()> {
+    if config.enabled {
+        match BanList::create_from_path(&config.path)
+            .with_context(|| format!("read ban list file {}", config.path.display()))
+        {
+            Ok(new_ban_list) => {
+                ::log::info!("Ban list updated ({} entries)", new_ban_list.len());
+
+                ban_list.store(Arc::new(new_ban_list));
+            }
+            Err(err) => {
+                ::log::error!("Updating ban list failed: {:#}", err);
+
+                return Err(err);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+
+    fn addr(ip: &str) -> CanonicalSocketAddr {
+        CanonicalSocketAddr::new(SocketAddr::new(ip.parse().unwrap(), 6881))
+    }
+
+    fn create(contents: &str) -> anyhow::Result<BanList> {
+        BanList::create_from_reader(contents.as_bytes())
+    }
+
+    #[test]
+    fn test_ban_list_is_banned() {
+        let now = unix_timestamp_now();
+
+        let ban_list = create(&format!(
+            "# comment\n\
+            \n\
+            192.0.2.1\n\
+            198.51.100.0/24\n\
+            203.0.113.1 {}\n\
+            203.0.113.2 {}\n\
+            2001:db8::/32\n\
+            2001:db9::1/128 {}\n",
+            now - 1,
+            now + 3600,
+            now - 1,
+        ))
+        .unwrap();
+
+        assert_eq!(ban_list.len(), 6);
+
+        let banned = |ip: &str| ban_list.is_banned(addr(ip));
+
+        assert!(banned("192.0.2.1"));
+        assert!(!banned("192.0.2.2"));
+        assert!(banned("198.51.100.0"));
+        assert!(banned("198.51.100.255"));
+        assert!(!banned("198.51.101.0"));
+        assert!(!banned("203.0.113.1"));
+        assert!(banned("203.0.113.2"));
+        assert!(banned("2001:db8:ffff::1"));
+        assert!(!banned("2001:db9::1"));
+        assert!(banned("::ffff:192.0.2.1"));
+    }
+
+    #[test]
+    fn test_ban_list_duplicates_keep_latest_expiry() {
+        let ban_list = create("192.0.2.1 1\n192.0.2.1\n192.0.2.1 2\n").unwrap();
+
+        assert_eq!(ban_list.len(), 1);
+        assert!(ban_list.is_banned(addr("192.0.2.1")));
+    }
+
+    #[test]
+    fn test_ban_list_expired_network_keeps_narrower_bans() {
+        let now = unix_timestamp_now();
+
+        let ban_list = create(&format!(
+            "10.0.0.0/8 {}
+            10.1.2.3
+            10.1.0.0/16 {}
+",
+            now - 1,
+            now + 3600,
+        ))
+        .unwrap();
+
+        let banned = |ip: &str| ban_list.is_banned(addr(ip));
+
+        assert!(banned("10.1.2.3"));
+        assert!(banned("10.1.5.5"));
+        assert!(!banned("10.2.0.1"));
+    }
+
+    #[test]
+    fn test_ban_list_whole_address_space() {
+        let ban_list = create("0.0.0.0/0\n").unwrap();
+
+        assert!(ban_list.is_banned(addr("203.0.113.1")));
+        assert!(!ban_list.is_banned(addr("2001:db8::1")));
+    }
+
+    #[test]
+    fn test_ban_list_invalid_lines() {
+        assert!(create("192.0.2.1/33\n").is_err());
+        assert!(create("198.51.100.77/24\n").is_err());
+        assert!(create("2001:db8::1/32\n").is_err());
+        assert!(create("2001:db8::/129\n").is_err());
+        assert!(create("192.0.2.256\n").is_err());
+        assert!(create("192.0.2.1 tomorrow\n").is_err());
+        assert!(create("192.0.2.1 1 2\n").is_err());
+    }
+}
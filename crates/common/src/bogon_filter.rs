@@ -0,0 +1,139 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use aquatic_toml_config::TomlConfig;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Default, PartialEq, TomlConfig, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BogonFilterConfig {
+    /// Don't store peers announcing from reserved (bogon) addresses, so that
+    /// they are never returned to other peers
+    ///
+    /// Covers addresses that can't be reached over the internet, such as
+    /// "this network" (0.0.0.0/8), loopback, link-local, multicast,
+    /// documentation and other reserved ranges as well as private networks
+    /// (RFC 1918, shared address space 100.64.0.0/10 and IPv6 unique local
+    /// addresses), unless `allow_private_networks` is set. Such peers still
+    /// get announce responses.
+    pub enabled: bool,
+    /// Store peers announcing from private networks, e.g., for trackers
+    /// serving a LAN
+    pub allow_private_networks: bool,
+}
+
+impl BogonFilterConfig {
+    /// Returns true if filter is enabled and peers announcing from IP
+    /// address should not be stored
+    pub fn blocks(&self, ip: IpAddr) -> bool {
+        if !self.enabled {
+            return false;
+        }
+
+        match ip {
+            IpAddr::V4(ip) => self.blocks_ipv4(ip),
+            IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+                Some(ip) => self.blocks_ipv4(ip),
+                None => self.blocks_ipv6(ip),
+            },
+        }
+    }
+
+    fn blocks_ipv4(&self, ip: Ipv4Addr) -> bool {
+        let [a, b, c, _] = ip.octets();
+
+        let private = ip.is_private() || (a == 100 && (b & 0b1100_0000) == 64);
+
+        let reserved = a == 0
+            || ip.is_loopback()
+            || ip.is_link_local()
+            || (a == 192 && b == 0 && c == 0)
+            || ip.is_documentation()
+            || (a == 198 && (b & 0b1111_1110) == 18)
+            || ip.is_multicast()
+            || a >= 240;
+
+        reserved || (private && !self.allow_private_networks)
+    }
+
+    fn blocks_ipv6(&self, ip: Ipv6Addr) -> bool {
+        let segments = ip.segments();
+
+        let private = (segments[0] & 0xfe00) == 0xfc00;
+
+        let reserved = ip.is_unspecified()
+            || ip.is_loopback()
+            || (segments[0] & 0xffc0) == 0xfe80
+            || ip.is_multicast()
+            || (segments[0] == 0x2001 && segments[1] == 0xdb8)
+            || (segments[..4] == [0x100, 0, 0, 0]);
+
+        reserved || (private && !self.allow_private_networks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bogon_filter() {
+        let mut config = BogonFilterConfig::default();
+
+        let blocked = |config: &BogonFilterConfig, ip: &str| config.blocks(ip.parse().unwrap());
+
+        assert!(!blocked(&config, "127.0.0.1"));
+
+        config.enabled = true;
+
+        for ip in [
+            "0.1.2.3",
+            "127.0.0.1",
+            "169.254.1.1",
+            "192.0.2.1",
+            "198.19.0.1",
+            "224.0.0.1",
+            "240.0.0.1",
+            "255.255.255.255",
+            "::",
+            "::1",
+            "::ffff:127.0.0.1",
+            "fe80::1",
+            "ff02::1",
+            "2001:db8::1",
+            "100::1",
+        ] {
+            assert!(blocked(&config, ip), "{}", ip);
+        }
+
+        let private = [
+            "10.0.0.1",
+            "172.16.0.1",
+            "192.168.1.1",
+            "100.64.0.1",
+            "fd00::1",
+        ];
+        let public = [
+            "1.1.1.1",
+            "100.128.0.1",
+            "172.32.0.1",
+            "198.20.0.1",
+            "2a00::1",
+            "::ffff:1.1.1.1",
+        ];
+
+        for ip in private {
+            assert!(blocked(&config, ip), "{}", ip);
+        }
+        for ip in public {
+            assert!(!blocked(&config, ip), "{}", ip);
+        }
+
+        config.allow_private_networks = true;
+
+        for ip in private.iter().chain(public.iter()) {
+            assert!(!blocked(&config, ip), "{}", ip);
+        }
+
+        assert!(blocked(&config, "127.0.0.1"));
+    }
+}
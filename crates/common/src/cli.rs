@@ -9,27 +9,27 @@ use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use simplelog::{ColorChoice, TermLogger, TerminalMode, ThreadLogMode};
 
 /// Log level. Available values are off, error, warn, info, debug and trace.
-#[derive(Debug, Clone, Copy, PartialEq, TomlConfig, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, TomlConfig, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum LogLevel {
     Off,
     Error,
+    #[default]
     Warn,
     Info,
     Debug,
     Trace,
 }
 
-impl Default for LogLevel {
-    fn default() -> Self {
-        Self::Warn
-    }
-}
-
 pub trait Config: Default + TomlConfig + DeserializeOwned + std::fmt::Debug {
     fn get_log_level(&self) -> Option<LogLevel> {
         None
     }
+    /// Check invariants that can't be expressed through types, returning
+    /// a human-readable message for each problem found
+    fn validation_errors(&self) -> Vec<String> {
+        Vec::new()
+    }
 }
 
 #[derive(Debug, Default)]
@@ -144,11 +144,20 @@ where
 
         Ok(())
     } else {
-        let config = if let Some(path) = options.config_file {
-            config_from_toml_file(path)?
-        } else {
-            T::default()
-        };
+        let config: T = load_config(options.config_file, ::std::env::vars())?;
+
+        let validation_errors = config.validation_errors();
+
+        if !validation_errors.is_empty() {
+            let mut message = "Invalid configuration:".to_string();
+
+            for error in validation_errors {
+                message.push_str("\n  - ");
+                message.push_str(&error);
+            }
+
+            return Err(anyhow::anyhow!(message));
+        }
 
         if let Some(log_level) = config.get_log_level() {
             start_logger(log_level)?;
@@ -175,24 +184,106 @@ where
     println!("    -P                    Print parsed config");
     println!("    -v, --version         Print version information");
 
+    println!("\nConfig values can be overridden with environment variables, e.g.,");
+    println!("AQUATIC__NETWORK__ADDRESS=0.0.0.0:3000 for address in section network.");
+
     if let Some(error) = opt_error {
         println!("\nError: {}.", error);
     }
 }
 
-fn config_from_toml_file<T>(path: String) -> anyhow::Result<T>
+/// Prefix of environment variables overriding config file values
+///
+/// Path segments are separated by double underscores, e.g.,
+/// `AQUATIC__NETWORK__ADDRESS=0.0.0.0:3000` sets `address` in the `network`
+/// section.
+const ENV_OVERRIDE_PREFIX: &str = "AQUATIC__";
+
+/// Load config from file (if given), with overrides from environment
+/// variables applied on top
+fn load_config<T, I>(opt_path: Option<String>, env_vars: I) -> anyhow::Result<T>
 where
-    T: DeserializeOwned,
+    T: Config,
+    I: Iterator<Item = (String, String)>,
 {
-    let mut file = File::open(path.clone())
-        .with_context(|| format!("Couldn't open config file {}", path.clone()))?;
+    let mut value = if let Some(path) = opt_path.as_ref() {
+        config_value_from_toml_file(path)?
+    } else {
+        toml::Value::Table(Default::default())
+    };
+
+    let num_overrides = apply_env_overrides(&mut value, env_vars)?;
+
+    if opt_path.is_none() && num_overrides == 0 {
+        return Ok(T::default());
+    }
+
+    value.try_into().with_context(|| match opt_path {
+        Some(path) => format!("Couldn't parse config file {}", path),
+        None => "Couldn't parse config".to_string(),
+    })
+}
+
+fn config_value_from_toml_file(path: &str) -> anyhow::Result<toml::Value> {
+    let mut file =
+        File::open(path).with_context(|| format!("Couldn't open config file {}", path))?;
 
     let mut data = String::new();
 
     file.read_to_string(&mut data)
-        .with_context(|| format!("Couldn't read config file {}", path.clone()))?;
+        .with_context(|| format!("Couldn't read config file {}", path))?;
+
+    toml::from_str(&data).with_context(|| format!("Couldn't parse config file {}", path))
+}
+
+/// Set values in config for all environment variables starting with
+/// ENV_OVERRIDE_PREFIX. Returns number of overrides applied.
+///
+/// Values are parsed as TOML if possible (e.g., numbers, booleans and
+/// arrays) and are otherwise treated as strings.
+fn apply_env_overrides<I>(config: &mut toml::Value, env_vars: I) -> anyhow::Result<usize>
+where
+    I: Iterator<Item = (String, String)>,
+{
+    let mut num_overrides = 0;
+
+    for (key, raw_value) in env_vars {
+        let Some(path) = key.strip_prefix(ENV_OVERRIDE_PREFIX) else {
+            continue;
+        };
+
+        let path: Vec<String> = path.split("__").map(|s| s.to_lowercase()).collect();
+
+        if path.iter().any(|segment| segment.is_empty()) {
+            return Err(anyhow::anyhow!(
+                "Invalid config override environment variable name {}",
+                key
+            ));
+        }
 
-    toml::from_str(&data).with_context(|| format!("Couldn't parse config file {}", path.clone()))
+        let value = toml::from_str::<toml::Value>(&format!("v = {}", raw_value))
+            .ok()
+            .and_then(|mut table| table.as_table_mut()?.remove("v"))
+            .unwrap_or(toml::Value::String(raw_value));
+
+        let (field, tables) = path.split_last().unwrap();
+
+        let mut table = config.as_table_mut().unwrap();
+
+        for segment in tables {
+            table = table
+                .entry(segment.as_str())
+                .or_insert_with(|| toml::Value::Table(Default::default()))
+                .as_table_mut()
+                .with_context(|| format!("Config override {}: {} is not a table", key, segment))?;
+        }
+
+        table.insert(field.clone(), value);
+
+        num_overrides += 1;
+    }
+
+    Ok(num_overrides)
 }
 
 fn default_config_as_toml<T>() -> String
@@ -253,3 +344,48 @@ fn get_commit_info() -> String {
 fn first_8_chars(input: &str) -> String {
     input.chars().take(8).collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_env_overrides() {
+        let mut config: toml::Value = toml::from_str(
+            "
+            log_level = 'info'
+
+            [network]
+            address = '127.0.0.1:3000'
+            ",
+        )
+        .unwrap();
+
+        let env_vars = [
+            ("AQUATIC__NETWORK__ADDRESS", "0.0.0.0:3000"),
+            ("AQUATIC__SOCKET_WORKERS", "4"),
+            ("AQUATIC__CLEANING__ENABLED", "true"),
+            ("HOME", "/root"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()));
+
+        assert_eq!(apply_env_overrides(&mut config, env_vars).unwrap(), 3);
+
+        let expected: toml::Value = toml::from_str(
+            "
+            log_level = 'info'
+            socket_workers = 4
+
+            [network]
+            address = '0.0.0.0:3000'
+
+            [cleaning]
+            enabled = true
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(config, expected);
+    }
+}
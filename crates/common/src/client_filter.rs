@@ -0,0 +1,132 @@
+use aquatic_toml_config::TomlConfig;
+use serde::{Deserialize, Serialize};
+
+/// Client filter mode. Available modes are allow, deny and off.
+#[derive(Clone, Copy, Debug, PartialEq, TomlConfig, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientFilterMode {
+    /// Only serve peers with peer id starting with one of the prefixes
+    Allow,
+    /// Do not serve peers with peer id starting with one of the prefixes
+    Deny,
+    /// Turn off client filter functionality
+    Off,
+}
+
+impl ClientFilterMode {
+    pub fn is_on(&self) -> bool {
+        !matches!(self, Self::Off)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, TomlConfig, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientFilterConfig {
+    pub mode: ClientFilterMode,
+    /// Peer id prefixes identifying client software, e.g., "-TR" for
+    /// Transmission, "-qB" for qBittorrent or "-DE" for Deluge
+    ///
+    /// Prefixes are compared to the start of the peer id bytes and are case
+    /// sensitive.
+    pub peer_id_prefixes: Vec<String>,
+}
+
+impl Default for ClientFilterConfig {
+    fn default() -> Self {
+        Self {
+            mode: ClientFilterMode::Off,
+            peer_id_prefixes: Vec::new(),
+        }
+    }
+}
+
+impl ClientFilterConfig {
+    pub fn allows(&self, peer_id: &[u8; 20]) -> bool {
+        let matches = || {
+            self.peer_id_prefixes
+                .iter()
+                .any(|prefix| peer_id.starts_with(prefix.as_bytes()))
+        };
+
+        match self.mode {
+            ClientFilterMode::Allow => matches(),
+            ClientFilterMode::Deny => !matches(),
+            ClientFilterMode::Off => true,
+        }
+    }
+
+    /// Failure reason to send to peers that are not allowed
+    pub fn failure_reason(&self) -> &'static str {
+        match self.mode {
+            ClientFilterMode::Allow => "Client not allowed: peer id prefix not in allowlist",
+            ClientFilterMode::Deny | ClientFilterMode::Off => {
+                "Client not allowed: peer id prefix in denylist"
+            }
+        }
+    }
+
+    pub fn validation_errors(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if self.mode.is_on() {
+            for prefix in self.peer_id_prefixes.iter() {
+                if prefix.is_empty() || prefix.len() > 20 {
+                    errors.push(format!(
+                        "client_filter.peer_id_prefixes: prefix must be 1 to 20 bytes long: {:?}",
+                        prefix
+                    ));
+                }
+            }
+        }
+
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_filter_allows() {
+        let mut config = ClientFilterConfig {
+            mode: ClientFilterMode::Off,
+            peer_id_prefixes: vec!["-TR".into(), "-qB".into()],
+        };
+
+        let transmission = *b"-TR4040-aaaaaaaaaaaa";
+        let qbittorrent = *b"-qB4630-aaaaaaaaaaaa";
+        let deluge = *b"-DE211s-aaaaaaaaaaaa";
+
+        assert!(config.allows(&transmission));
+        assert!(config.allows(&deluge));
+
+        config.mode = ClientFilterMode::Allow;
+
+        assert!(config.allows(&transmission));
+        assert!(config.allows(&qbittorrent));
+        assert!(!config.allows(&deluge));
+        assert!(!config.allows(b"-tr4040-aaaaaaaaaaaa"));
+
+        config.mode = ClientFilterMode::Deny;
+
+        assert!(!config.allows(&transmission));
+        assert!(!config.allows(&qbittorrent));
+        assert!(config.allows(&deluge));
+    }
+
+    #[test]
+    fn test_client_filter_validation_errors() {
+        let mut config = ClientFilterConfig {
+            mode: ClientFilterMode::Deny,
+            peer_id_prefixes: vec!["-TR".into()],
+        };
+
+        assert!(config.validation_errors().is_empty());
+
+        config.peer_id_prefixes.push("".into());
+        config.peer_id_prefixes.push("a".repeat(21));
+
+        assert_eq!(config.validation_errors().len(), 2);
+    }
+}
@@ -3,23 +3,19 @@
 use aquatic_toml_config::TomlConfig;
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Copy, Debug, PartialEq, TomlConfig, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, TomlConfig, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum CpuPinningDirection {
+    #[default]
     Ascending,
     Descending,
 }
 
-impl Default for CpuPinningDirection {
-    fn default() -> Self {
-        Self::Ascending
-    }
-}
-
 pub trait CpuPinningConfig {
     fn active(&self) -> bool;
     fn direction(&self) -> CpuPinningDirection;
     fn core_offset(&self) -> usize;
+    fn core_list(&self) -> &[usize];
 }
 
 // Do these shenanigans for compatibility with aquatic_toml_config
@@ -33,10 +29,23 @@ pub mod mod_name {
 
     /// Experimental cpu pinning
     #[derive(Clone, Debug, PartialEq, TomlConfig, Deserialize, Serialize)]
+    #[serde(default, deny_unknown_fields)]
     pub struct struct_name {
+        /// Pin worker threads to physical cpu cores
         pub active: bool,
+        /// Assign cores starting from the first (ascending) or last
+        /// (descending) one
         pub direction: CpuPinningDirection,
+        /// Skip this many cores before starting assignment
         pub core_offset: usize,
+        /// Pin workers to these physical core indices instead of spreading
+        /// them automatically
+        ///
+        /// Indices are consumed in order: first by socket workers, then by
+        /// swarm workers and finally by utility threads. Workers beyond the
+        /// end of the list are pinned to the last core in it. When non-empty,
+        /// `direction` and `core_offset` are ignored.
+        pub core_list: Vec<usize>,
     }
 
     impl Default for struct_name {
@@ -45,6 +54,7 @@ pub mod mod_name {
                 active: false,
                 direction: cpu_pinning_direction,
                 core_offset: 0,
+                core_list: Vec::new(),
             }
         }
     }
@@ -58,6 +68,9 @@ pub mod mod_name {
         fn core_offset(&self) -> usize {
             self.core_offset
         }
+        fn core_list(&self) -> &[usize] {
+            &self.core_list
+        }
     }
 }
 
@@ -76,14 +89,26 @@ impl WorkerIndex {
         swarm_workers: usize,
         num_cores: usize,
     ) -> usize {
-        let ascending_index = match self {
-            Self::SocketWorker(index) => config.core_offset() + index,
-            Self::SwarmWorker(index) => config.core_offset() + socket_workers + index,
-            Self::Util => config.core_offset() + socket_workers + swarm_workers,
+        let worker_position = match self {
+            Self::SocketWorker(index) => *index,
+            Self::SwarmWorker(index) => socket_workers + index,
+            Self::Util => socket_workers + swarm_workers,
         };
 
         let max_core_index = num_cores - 1;
 
+        if let Some(last) = config.core_list().last() {
+            let core_index = config
+                .core_list()
+                .get(worker_position)
+                .unwrap_or(last)
+                .to_owned();
+
+            return core_index.min(max_core_index);
+        }
+
+        let ascending_index = config.core_offset() + worker_position;
+
         let ascending_index = ascending_index.min(max_core_index);
 
         match config.direction() {
@@ -238,3 +263,55 @@ pub fn socket_attach_cbpf<S: ::std::os::unix::prelude::AsRawFd>(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_core_index() {
+        let mut config = asc::CpuPinningConfigAsc {
+            active: true,
+            core_offset: 1,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            WorkerIndex::SocketWorker(0).get_core_index(&config, 2, 2, 8),
+            1
+        );
+        assert_eq!(
+            WorkerIndex::SwarmWorker(1).get_core_index(&config, 2, 2, 8),
+            4
+        );
+        assert_eq!(WorkerIndex::Util.get_core_index(&config, 2, 2, 8), 5);
+        assert_eq!(WorkerIndex::Util.get_core_index(&config, 2, 2, 4), 3);
+
+        config.direction = CpuPinningDirection::Descending;
+
+        assert_eq!(
+            WorkerIndex::SocketWorker(0).get_core_index(&config, 2, 2, 8),
+            6
+        );
+
+        config.core_list = vec![7, 3, 5];
+
+        assert_eq!(
+            WorkerIndex::SocketWorker(0).get_core_index(&config, 2, 2, 8),
+            7
+        );
+        assert_eq!(
+            WorkerIndex::SocketWorker(1).get_core_index(&config, 2, 2, 8),
+            3
+        );
+        assert_eq!(
+            WorkerIndex::SwarmWorker(0).get_core_index(&config, 2, 2, 8),
+            5
+        );
+        assert_eq!(WorkerIndex::Util.get_core_index(&config, 2, 2, 8), 5);
+        assert_eq!(
+            WorkerIndex::SocketWorker(0).get_core_index(&config, 2, 2, 4),
+            3
+        );
+    }
+}
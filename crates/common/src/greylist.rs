@@ -0,0 +1,251 @@
+use aquatic_toml_config::TomlConfig;
+use hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::ip_network::IpNetwork;
+use crate::{CanonicalSocketAddr, SecondsSinceServerStart};
+
+#[derive(Clone, Debug, PartialEq, TomlConfig, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GreylistConfig {
+    /// Ignore all requests (aquatic_udp) or connections (aquatic_http and
+    /// aquatic_ws) from a network prefix (/24 for IPv4, /48 for IPv6) for
+    /// `duration` seconds after this many failures were counted for it
+    /// within `window` seconds. 0 = disabled
+    ///
+    /// Counted failures are malformed requests, requests with invalid
+    /// connection IDs (aquatic_udp) and failed TLS and WebSocket handshakes
+    /// (aquatic_http and aquatic_ws). Failures are counted per socket
+    /// worker. Since source addresses of UDP requests haven't been verified,
+    /// spoofed requests can get legitimate peers greylisted.
+    ///
+    /// Trusted reverse proxies are never greylisted. Failures are counted
+    /// for the peer IPs they forward instead, once they are known.
+    pub max_failures: u32,
+    /// Window for counting failures of a network prefix (seconds)
+    pub window: u32,
+    /// Ignore requests from greylisted network prefixes this long (seconds)
+    pub duration: u32,
+    /// Maximum number of network prefixes to count failures for per socket
+    /// worker. When it is reached, failures from further prefixes are
+    /// ignored until entries expire.
+    pub max_prefixes: usize,
+}
+
+impl Default for GreylistConfig {
+    fn default() -> Self {
+        Self {
+            max_failures: 0,
+            window: 60,
+            duration: 60 * 10,
+            max_prefixes: 65_536,
+        }
+    }
+}
+
+impl GreylistConfig {
+    pub fn active(&self) -> bool {
+        self.max_failures != 0
+    }
+
+    pub fn validation_errors(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if self.active() {
+            if self.window == 0 {
+                errors.push("greylist.window must be greater than 0".into());
+            }
+            if self.duration == 0 {
+                errors.push("greylist.duration must be greater than 0".into());
+            }
+            if self.max_prefixes == 0 {
+                errors.push("greylist.max_prefixes must be greater than 0".into());
+            }
+        }
+
+        errors
+    }
+}
+
+/// Network prefix that failures are counted for
+fn prefix(addr: CanonicalSocketAddr) -> IpNetwork {
+    let ip = addr.get().ip();
+
+    // Canonical addresses are never IPv4-mapped
+    let prefix_len = if ip.is_ipv4() { 24 } else { 48 };
+
+    IpNetwork::containing(ip, prefix_len)
+}
+
+#[derive(Clone, Copy, Debug)]
+struct PrefixState {
+    window_start: u32,
+    failures: u32,
+    greylisted_until: u32,
+}
+
+/// Temporarily ignores requests from network prefixes that repeatedly
+/// misbehave
+///
+/// Meant to be used per socket worker. Bounded to `greylist.max_prefixes`
+/// prefixes. When full, expired entries are removed and failures from
+/// further prefixes are not counted until there is room again.
+#[derive(Clone)]
+pub struct Greylist {
+    max_failures: u32,
+    window: u32,
+    duration: u32,
+    max_prefixes: usize,
+    prefixes: HashMap<IpNetwork, PrefixState>,
+}
+
+impl Greylist {
+    pub fn new(config: &GreylistConfig) -> Self {
+        Self {
+            max_failures: config.max_failures,
+            window: config.window,
+            duration: config.duration,
+            max_prefixes: config.max_prefixes,
+            prefixes: Default::default(),
+        }
+    }
+
+    pub fn contains(&self, addr: CanonicalSocketAddr, now: SecondsSinceServerStart) -> bool {
+        self.prefixes
+            .get(&prefix(addr))
+            .is_some_and(|state| state.greylisted_until > now.0)
+    }
+
+    /// Count failure for prefix of address, greylisting the prefix if the
+    /// limit for the current window is reached
+    ///
+    /// Returns true if the prefix was greylisted.
+    pub fn record_failure(
+        &mut self,
+        addr: CanonicalSocketAddr,
+        now: SecondsSinceServerStart,
+    ) -> bool {
+        let now = now.0;
+        let prefix = prefix(addr);
+
+        if !self.prefixes.contains_key(&prefix) && self.prefixes.len() >= self.max_prefixes {
+            self.clean(SecondsSinceServerStart(now));
+
+            if self.prefixes.len() >= self.max_prefixes {
+                return false;
+            }
+        }
+
+        let state = self.prefixes.entry(prefix).or_insert(PrefixState {
+            window_start: now,
+            failures: 0,
+            greylisted_until: 0,
+        });
+
+        if now.saturating_sub(state.window_start) >= self.window {
+            state.window_start = now;
+            state.failures = 0;
+        }
+
+        state.failures += 1;
+
+        if state.failures >= self.max_failures {
+            state.greylisted_until = now.saturating_add(self.duration);
+            state.window_start = now;
+            state.failures = 0;
+
+            ::log::debug!("Greylisted prefix {}", prefix);
+
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Remove prefixes that are neither greylisted nor in a failure window
+    pub fn clean(&mut self, now: SecondsSinceServerStart) {
+        let now = now.0;
+        let window = self.window;
+
+        self.prefixes.retain(|_, state| {
+            state.greylisted_until > now || now.saturating_sub(state.window_start) < window
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+    use super::*;
+
+    fn addr(ip: impl Into<IpAddr>) -> CanonicalSocketAddr {
+        CanonicalSocketAddr::new(SocketAddr::new(ip.into(), 1000))
+    }
+
+    #[test]
+    fn test_greylist() {
+        let config = GreylistConfig {
+            max_failures: 3,
+            window: 10,
+            duration: 100,
+            max_prefixes: 2,
+        };
+
+        let mut greylist = Greylist::new(&config);
+
+        let now = SecondsSinceServerStart::new;
+
+        let a = addr(Ipv4Addr::new(10, 0, 0, 1));
+        let same_prefix = addr(Ipv4Addr::new(10, 0, 0, 2).to_ipv6_mapped());
+        let other_prefix = addr(Ipv4Addr::new(10, 0, 1, 1));
+
+        // Failures outside of window are not counted together
+        assert!(!greylist.record_failure(a, now(0)));
+        assert!(!greylist.record_failure(a, now(5)));
+        assert!(!greylist.record_failure(a, now(10)));
+
+        assert!(!greylist.contains(a, now(10)));
+
+        assert!(!greylist.record_failure(same_prefix, now(11)));
+        assert!(greylist.record_failure(same_prefix, now(12)));
+
+        assert!(greylist.contains(a, now(12)));
+        assert!(greylist.contains(same_prefix, now(111)));
+        assert!(!greylist.contains(a, now(112)));
+        assert!(!greylist.contains(other_prefix, now(12)));
+
+        // Map is full, so failures for a third prefix are not counted
+        let ipv6 = addr(Ipv6Addr::new(1, 2, 3, 4, 0, 0, 0, 1));
+
+        greylist.record_failure(other_prefix, now(12));
+
+        for _ in 0..3 {
+            assert!(!greylist.record_failure(ipv6, now(12)));
+        }
+
+        assert!(!greylist.contains(ipv6, now(12)));
+
+        // After greylisting and windows have expired, there is room again
+        for _ in 0..3 {
+            greylist.record_failure(ipv6, now(200));
+        }
+
+        assert!(greylist.contains(addr(Ipv6Addr::new(1, 2, 3, 5, 0, 0, 0, 1)), now(200)));
+        assert!(!greylist.contains(addr(Ipv6Addr::new(1, 2, 4, 4, 0, 0, 0, 1)), now(200)));
+    }
+
+    #[test]
+    fn test_greylist_config_validation_errors() {
+        let mut config = GreylistConfig {
+            window: 0,
+            ..Default::default()
+        };
+
+        assert!(config.validation_errors().is_empty());
+
+        config.max_failures = 5;
+
+        assert_eq!(config.validation_errors().len(), 1);
+    }
+}
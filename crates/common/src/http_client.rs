@@ -0,0 +1,89 @@
+//! Helpers for outgoing HTTP requests, e.g., for fetching access lists
+
+/// Describe ureq error without including URL, which might contain
+/// credentials
+pub fn describe_request_error(err: ureq::Error) -> anyhow::Error {
+    match err {
+        ureq::Error::Status(status, _) => anyhow::anyhow!("unexpected response status {}", status),
+        ureq::Error::Transport(transport) => {
+            let mut description = transport.kind().to_string();
+
+            if let Some(message) = transport.message() {
+                description.push_str(": ");
+                description.push_str(message);
+            }
+            if let Some(source) = ::std::error::Error::source(&transport) {
+                description.push_str(": ");
+                description.push_str(&source.to_string());
+            }
+
+            anyhow::anyhow!("{}", description)
+        }
+    }
+}
+
+/// Minimal HTTP server for testing HTTP clients
+#[cfg(any(test, feature = "test-helpers"))]
+pub mod test_helpers {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{SocketAddr, TcpListener};
+    use std::thread::JoinHandle;
+
+    /// Request received by test server
+    pub struct ReceivedRequest {
+        /// Request line and headers, without the empty line ending them
+        pub head: String,
+        pub body: Vec<u8>,
+    }
+
+    /// Spawn thread accepting one connection per response, reading a
+    /// request from it and sending the response (including status line and
+    /// headers) before closing it. Joining the thread returns the received
+    /// requests.
+    pub fn spawn_test_http_server(
+        responses: Vec<String>,
+    ) -> (SocketAddr, JoinHandle<Vec<ReceivedRequest>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let handle = ::std::thread::spawn(move || {
+            let mut requests = Vec::new();
+
+            for response in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut head = String::new();
+                let mut content_length = 0;
+
+                loop {
+                    let mut line = String::new();
+
+                    reader.read_line(&mut line).unwrap();
+
+                    if line == "\r\n" {
+                        break;
+                    }
+
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            content_length = value.trim().parse().unwrap();
+                        }
+                    }
+
+                    head.push_str(&line);
+                }
+
+                let mut body = vec![0; content_length];
+
+                reader.read_exact(&mut body).unwrap();
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+
+                requests.push(ReceivedRequest { head, body });
+            }
+
+            requests
+        });
+
+        (addr, handle)
+    }
+}
@@ -0,0 +1,191 @@
+use std::fmt::Display;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use anyhow::Context;
+
+/// IP network in CIDR notation, e.g., 10.0.0.0/8
+///
+/// Host bits of the address are cleared, so networks can be compared and
+/// used as map keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IpNetwork {
+    address: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpNetwork {
+    /// Create network with given address and prefix length, clearing host
+    /// bits of address
+    pub fn new(address: IpAddr, prefix_len: u8) -> anyhow::Result<Self> {
+        let max_prefix_len = max_prefix_len(address);
+
+        if prefix_len > max_prefix_len {
+            return Err(anyhow::anyhow!(
+                "prefix length must be at most {}",
+                max_prefix_len
+            ));
+        }
+
+        Ok(Self::containing(address, prefix_len))
+    }
+
+    /// Network with given prefix length containing address. Prefix length
+    /// is capped to the number of bits in the address.
+    pub fn containing(address: IpAddr, prefix_len: u8) -> Self {
+        let prefix_len = prefix_len.min(max_prefix_len(address));
+
+        let address = match address {
+            IpAddr::V4(address) => {
+                let mut octets = address.octets();
+
+                clear_host_bits(&mut octets, prefix_len);
+
+                IpAddr::V4(Ipv4Addr::from(octets))
+            }
+            IpAddr::V6(address) => {
+                let mut octets = address.octets();
+
+                clear_host_bits(&mut octets, prefix_len);
+
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+        };
+
+        Self {
+            address,
+            prefix_len,
+        }
+    }
+
+    /// Network address, with host bits cleared
+    pub fn address(&self) -> IpAddr {
+        self.address
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.address.is_ipv4() == ip.is_ipv4() && Self::containing(ip, self.prefix_len) == *self
+    }
+}
+
+impl Display for IpNetwork {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_len)
+    }
+}
+
+impl FromStr for IpNetwork {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_len) = s.split_once('/').with_context(|| "no prefix length")?;
+
+        let address = address.parse::<IpAddr>().with_context(|| "parse address")?;
+        let prefix_len = prefix_len
+            .parse::<u8>()
+            .with_context(|| "parse prefix length")?;
+
+        Self::new(address, prefix_len)
+    }
+}
+
+/// List of IP networks, e.g., trusted proxies
+#[derive(Debug, Clone, Default)]
+pub struct IpNetworks(Vec<IpNetwork>);
+
+impl IpNetworks {
+    pub fn parse(networks: &[String]) -> anyhow::Result<Self> {
+        networks
+            .iter()
+            .map(|network| {
+                network
+                    .parse()
+                    .with_context(|| format!("parse network {}", network))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()
+            .map(Self)
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.0.iter().any(|network| network.contains(ip))
+    }
+}
+
+fn max_prefix_len(address: IpAddr) -> u8 {
+    if address.is_ipv4() {
+        32
+    } else {
+        128
+    }
+}
+
+fn clear_host_bits(octets: &mut [u8], prefix_len: u8) {
+    for (i, octet) in octets.iter_mut().enumerate() {
+        let network_bits = usize::from(prefix_len).saturating_sub(i * 8).min(8);
+
+        *octet &= !(0xffu16 >> network_bits) as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv6Addr;
+
+    use super::*;
+
+    #[test]
+    fn test_ip_network() {
+        let network: IpNetwork = "172.16.0.0/12".parse().unwrap();
+
+        assert!(network.contains(IpAddr::from([172, 16, 0, 1])));
+        assert!(network.contains(IpAddr::from([172, 31, 255, 255])));
+        assert!(!network.contains(IpAddr::from([172, 32, 0, 0])));
+        assert!(!network.contains(IpAddr::from(Ipv6Addr::LOCALHOST)));
+
+        let network: IpNetwork = "fc00::/7".parse().unwrap();
+
+        assert!(network.contains("fdab::1".parse().unwrap()));
+        assert!(!network.contains("fe80::1".parse().unwrap()));
+
+        let network: IpNetwork = "0.0.0.0/0".parse().unwrap();
+
+        assert!(network.contains(IpAddr::from([1, 2, 3, 4])));
+
+        let networks = IpNetworks::parse(&["127.0.0.0/8".into(), "::1/128".into()]).unwrap();
+
+        assert!(networks.contains(IpAddr::from([127, 0, 0, 1])));
+        assert!(networks.contains(IpAddr::from(Ipv6Addr::LOCALHOST)));
+        assert!(!networks.contains(IpAddr::from([10, 0, 0, 1])));
+
+        assert!(IpNetworks::parse(&["10.0.0.1".into()]).is_err());
+
+        assert_eq!(
+            IpNetwork::containing(IpAddr::from([10, 1, 2, 3]), 12),
+            "10.0.0.0/12".parse().unwrap()
+        );
+        assert_eq!(
+            "2001:db8:1234::1/36"
+                .parse::<IpNetwork>()
+                .unwrap()
+                .address(),
+            "2001:db8:1000::".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            IpNetwork::containing(IpAddr::from([10, 1, 2, 3]), 64).prefix_len(),
+            32
+        );
+
+        assert_eq!(
+            IpNetwork::containing("2001:db8::1".parse().unwrap(), 48).to_string(),
+            "2001:db8::/48"
+        );
+
+        assert!("10.0.0.0".parse::<IpNetwork>().is_err());
+        assert!("10.0.0.0/33".parse::<IpNetwork>().is_err());
+        assert!("::/129".parse::<IpNetwork>().is_err());
+    }
+}
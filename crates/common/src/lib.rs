@@ -1,16 +1,31 @@
+use std::any::Any;
 use std::fmt::Display;
 use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
 use std::time::Instant;
 
 use ahash::RandomState;
+use rand::Rng;
 
 pub mod access_list;
+pub mod ban_list;
+pub mod bogon_filter;
 pub mod cli;
+pub mod client_filter;
 #[cfg(feature = "cpu-pinning")]
 pub mod cpu_pinning;
+pub mod greylist;
+pub mod http_client;
+pub mod ip_network;
+pub mod load_shedding;
 pub mod privileges;
+pub mod rlimit;
 #[cfg(feature = "rustls")]
 pub mod rustls_config;
+pub mod seccomp;
+pub mod signed_urls;
+pub mod systemd;
 
 /// IndexMap using AHash hasher
 pub type IndexMap<K, V> = indexmap::IndexMap<K, V, RandomState>;
@@ -21,9 +36,9 @@ pub struct ValidUntil(SecondsSinceServerStart);
 
 impl ValidUntil {
     #[inline]
-    pub fn new(start_instant: ServerStartInstant, offset_seconds: u32) -> Self {
+    pub fn new(clock: impl Clock, offset_seconds: u32) -> Self {
         Self(SecondsSinceServerStart(
-            start_instant.seconds_elapsed().0 + offset_seconds,
+            clock.seconds_elapsed().0 + offset_seconds,
         ))
     }
     pub fn new_with_now(now: SecondsSinceServerStart, offset_seconds: u32) -> Self {
@@ -32,6 +47,13 @@ impl ValidUntil {
     pub fn valid(&self, now: SecondsSinceServerStart) -> bool {
         self.0 .0 > now.0
     }
+    /// Seconds from `earlier` to this instant, or 0 if `earlier` is later
+    ///
+    /// For instants created with the same offset, this is the time elapsed
+    /// between their creation.
+    pub fn seconds_since(&self, earlier: Self) -> u32 {
+        self.0 .0.saturating_sub(earlier.0 .0)
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -53,9 +75,56 @@ impl ServerStartInstant {
     }
 }
 
+impl Clock for ServerStartInstant {
+    fn seconds_elapsed(&self) -> SecondsSinceServerStart {
+        ServerStartInstant::seconds_elapsed(self)
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct SecondsSinceServerStart(u32);
 
+impl SecondsSinceServerStart {
+    /// Construct from a fixed number of seconds, e.g., to drive cleaning
+    /// deterministically in tests
+    pub fn new(seconds: u32) -> Self {
+        Self(seconds)
+    }
+}
+
+/// Source of the current time, used for peer and connection expiry
+///
+/// Implemented by [`ServerStartInstant`]. Use [`ManualClock`] to control
+/// time, e.g., in tests.
+pub trait Clock {
+    fn seconds_elapsed(&self) -> SecondsSinceServerStart;
+}
+
+impl<T: Clock> Clock for &T {
+    fn seconds_elapsed(&self) -> SecondsSinceServerStart {
+        (*self).seconds_elapsed()
+    }
+}
+
+/// Clock that only moves when told to. Clones share the same time.
+#[derive(Debug, Clone)]
+pub struct ManualClock(Arc<AtomicU32>);
+
+impl ManualClock {
+    pub fn new(now: SecondsSinceServerStart) -> Self {
+        Self(Arc::new(AtomicU32::new(now.0)))
+    }
+    pub fn advance(&self, seconds: u32) {
+        self.0.fetch_add(seconds, Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn seconds_elapsed(&self) -> SecondsSinceServerStart {
+        SecondsSinceServerStart(self.0.load(Ordering::Relaxed))
+    }
+}
+
 /// SocketAddr that is not an IPv6-mapped IPv4 address
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
 pub struct CanonicalSocketAddr(SocketAddr);
@@ -164,6 +233,10 @@ pub enum WorkerType {
     Statistics,
     Signals,
     Cleaning,
+    PacketDump,
+    TlsReload,
+    AccessListSync,
+    Control,
     #[cfg(feature = "prometheus")]
     Prometheus,
 }
@@ -176,8 +249,95 @@ impl Display for WorkerType {
             Self::Statistics => f.write_str("Statistics worker"),
             Self::Signals => f.write_str("Signals worker"),
             Self::Cleaning => f.write_str("Cleaning worker"),
+            Self::PacketDump => f.write_str("Packet dump worker"),
+            Self::TlsReload => f.write_str("TLS reload worker"),
+            Self::AccessListSync => f.write_str("Access list sync worker"),
+            Self::Control => f.write_str("Control socket worker"),
             #[cfg(feature = "prometheus")]
             Self::Prometheus => f.write_str("Prometheus worker"),
         }
     }
 }
+
+/// Extract message from the payload of a panicked thread
+pub fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.as_str()
+    } else {
+        "unknown panic payload"
+    }
+}
+
+/// Randomize announce interval by up to `jitter_percent` percent in either
+/// direction, so that peers don't keep announcing in sync
+pub fn jitter_announce_interval(rng: &mut impl Rng, interval: usize, jitter_percent: u8) -> usize {
+    let max_deviation = max_announce_interval_deviation(interval, jitter_percent);
+
+    if max_deviation == 0 {
+        return interval;
+    }
+
+    rng.gen_range(interval.saturating_sub(max_deviation)..=interval + max_deviation)
+}
+
+/// Largest interval that [`jitter_announce_interval`] may return
+pub fn max_jittered_announce_interval(interval: usize, jitter_percent: u8) -> usize {
+    interval + max_announce_interval_deviation(interval, jitter_percent)
+}
+
+/// Smallest interval that [`jitter_announce_interval`] may return
+pub fn min_jittered_announce_interval(interval: usize, jitter_percent: u8) -> usize {
+    interval.saturating_sub(max_announce_interval_deviation(interval, jitter_percent))
+}
+
+fn max_announce_interval_deviation(interval: usize, jitter_percent: u8) -> usize {
+    interval * usize::from(jitter_percent) / 100
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn test_valid_until_with_manual_clock() {
+        let clock = ManualClock::new(SecondsSinceServerStart::new(100));
+        let valid_until = ValidUntil::new(&clock, 10);
+
+        clock.advance(9);
+
+        assert!(valid_until.valid(clock.seconds_elapsed()));
+
+        clock.advance(1);
+
+        assert!(!valid_until.valid(clock.seconds_elapsed()));
+    }
+
+    #[test]
+    fn test_valid_until_seconds_since() {
+        let earlier = ValidUntil::new_with_now(SecondsSinceServerStart::new(100), 10);
+        let later = ValidUntil::new_with_now(SecondsSinceServerStart::new(105), 10);
+
+        assert_eq!(later.seconds_since(earlier), 5);
+        assert_eq!(earlier.seconds_since(later), 0);
+    }
+
+    #[test]
+    fn test_jitter_announce_interval() {
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        assert_eq!(jitter_announce_interval(&mut rng, 120, 0), 120);
+
+        let intervals = (0..1000)
+            .map(|_| jitter_announce_interval(&mut rng, 120, 10))
+            .collect::<Vec<_>>();
+
+        assert!(intervals.iter().all(|i| (108..=132).contains(i)));
+        assert!(intervals.iter().any(|i| *i != 120));
+        assert_eq!(max_jittered_announce_interval(120, 10), 132);
+        assert_eq!(min_jittered_announce_interval(120, 10), 108);
+    }
+}
@@ -0,0 +1,85 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use crate::SecondsSinceServerStart;
+
+/// Limits number of announce requests processed per second, shared by all
+/// socket workers
+///
+/// Counting is approximate: requests arriving while a new second is being
+/// started may be counted towards the previous one.
+#[derive(Debug, Default)]
+pub struct AnnounceCeiling {
+    /// Maximum number of announce requests per second. 0 = unlimited
+    max_per_second: u64,
+    second: AtomicU32,
+    count: AtomicU64,
+}
+
+impl AnnounceCeiling {
+    pub fn new(max_per_second: u64) -> Self {
+        Self {
+            max_per_second,
+            second: AtomicU32::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.max_per_second != 0
+    }
+
+    /// Count announce request, returning false if it exceeds ceiling and
+    /// should be shed
+    pub fn try_acquire(&self, now: SecondsSinceServerStart) -> bool {
+        if !self.active() {
+            return true;
+        }
+
+        let second = self.second.load(Ordering::Relaxed);
+
+        // Workers may have slightly different ideas of the current second,
+        // so only ever move forward, or the count would be reset repeatedly
+        if now.0 > second
+            && self
+                .second
+                .compare_exchange(second, now.0, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            self.count.store(0, Ordering::Relaxed);
+        }
+
+        self.count.fetch_add(1, Ordering::Relaxed) < self.max_per_second
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_announce_ceiling() {
+        let ceiling = AnnounceCeiling::new(3);
+
+        for now in [0, 1, 5] {
+            let now = SecondsSinceServerStart::new(now);
+
+            assert!(ceiling.try_acquire(now));
+            assert!(ceiling.try_acquire(now));
+            assert!(ceiling.try_acquire(now));
+            assert!(!ceiling.try_acquire(now));
+            assert!(!ceiling.try_acquire(now));
+        }
+
+        // Requests with an earlier timestamp don't reset the count
+        assert!(!ceiling.try_acquire(SecondsSinceServerStart::new(4)));
+        assert!(!ceiling.try_acquire(SecondsSinceServerStart::new(5)));
+    }
+
+    #[test]
+    fn test_announce_ceiling_inactive() {
+        let ceiling = AnnounceCeiling::new(0);
+
+        assert!(!ceiling.active());
+        assert!((0..1000).all(|_| ceiling.try_acquire(SecondsSinceServerStart::new(0))));
+    }
+}
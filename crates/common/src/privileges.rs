@@ -1,6 +1,10 @@
 use std::{
     path::PathBuf,
-    sync::{Arc, Barrier},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Barrier, Condvar, Mutex,
+    },
+    time::Duration,
 };
 
 use anyhow::Context;
@@ -20,6 +24,15 @@ pub struct PrivilegeConfig {
     pub group: String,
     /// User to switch to after chrooting
     pub user: String,
+    /// Block a fixed deny list of system calls that are never needed after
+    /// startup, such as the ones used for executing programs, debugging
+    /// other processes and changing credentials, using a seccomp filter
+    /// (Linux on x86_64 and aarch64 only)
+    ///
+    /// All other system calls remain allowed. The filter is applied to the
+    /// whole process after sockets have been created and privileges have
+    /// been dropped (if enabled). Filesystem access is not restricted.
+    pub seccomp_deny_list: bool,
 }
 
 impl Default for PrivilegeConfig {
@@ -29,6 +42,7 @@ impl Default for PrivilegeConfig {
             chroot_path: ".".into(),
             user: "nobody".to_string(),
             group: "nogroup".to_string(),
+            seccomp_deny_list: false,
         }
     }
 }
@@ -36,6 +50,11 @@ impl Default for PrivilegeConfig {
 #[derive(Clone)]
 pub struct PrivilegeDropper {
     barrier: Arc<Barrier>,
+    /// Set by leader if dropping privileges failed
+    failed: Arc<AtomicBool>,
+    /// Number of sockets for which `after_socket_creation` succeeded
+    num_ready: Arc<(Mutex<usize>, Condvar)>,
+    num_sockets: usize,
     config: Arc<PrivilegeConfig>,
 }
 
@@ -43,20 +62,136 @@ impl PrivilegeDropper {
     pub fn new(config: PrivilegeConfig, num_sockets: usize) -> Self {
         Self {
             barrier: Arc::new(Barrier::new(num_sockets)),
+            failed: Arc::new(AtomicBool::new(false)),
+            num_ready: Default::default(),
+            num_sockets,
             config: Arc::new(config),
         }
     }
 
+    /// Wait until all sockets have been created, then drop privileges
+    ///
+    /// Returns only after privileges have been dropped, so that no worker
+    /// starts processing requests while still running as root. If dropping
+    /// privileges fails, all callers return an error.
     pub fn after_socket_creation(self) -> anyhow::Result<()> {
-        if self.config.drop_privileges && self.barrier.wait().is_leader() {
-            PrivDrop::default()
-                .chroot(self.config.chroot_path.clone())
-                .group(self.config.group.clone())
-                .user(self.config.user.clone())
-                .apply()
-                .with_context(|| "couldn't drop privileges after socket creation")?;
+        if self.config.drop_privileges {
+            let result = if self.barrier.wait().is_leader() {
+                let result = PrivDrop::default()
+                    .chroot(self.config.chroot_path.clone())
+                    .group(self.config.group.clone())
+                    .user(self.config.user.clone())
+                    .apply()
+                    .with_context(|| "couldn't drop privileges after socket creation");
+
+                if result.is_err() {
+                    self.failed.store(true, Ordering::SeqCst);
+                }
+
+                result
+            } else {
+                Ok(())
+            };
+
+            // Always wait, so that other callers don't block forever if
+            // dropping privileges failed
+            self.barrier.wait();
+
+            result?;
+
+            if self.failed.load(Ordering::SeqCst) {
+                return Err(anyhow::anyhow!(
+                    "couldn't drop privileges after socket creation"
+                ));
+            }
         }
 
+        if self.config.seccomp_deny_list {
+            crate::seccomp::apply_deny_list()?;
+        }
+
+        let (num_ready, condvar) = &*self.num_ready;
+
+        *num_ready.lock().unwrap() += 1;
+        condvar.notify_all();
+
         Ok(())
     }
+
+    /// Block until `after_socket_creation` has succeeded for all sockets,
+    /// e.g., before notifying systemd that the service is ready
+    ///
+    /// Returns false without waiting further once `worker_quit` returns
+    /// true, since the remaining sockets might then never be created.
+    pub fn wait_until_sockets_ready(&self, mut worker_quit: impl FnMut() -> bool) -> bool {
+        let (num_ready, condvar) = &*self.num_ready;
+
+        let mut num_ready = num_ready.lock().unwrap();
+
+        while *num_ready < self.num_sockets {
+            if worker_quit() {
+                return false;
+            }
+
+            num_ready = condvar
+                .wait_timeout(num_ready, Duration::from_millis(100))
+                .unwrap()
+                .0;
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failed_privilege_drop_does_not_block() {
+        let config = PrivilegeConfig {
+            drop_privileges: true,
+            chroot_path: "/nonexistent/aquatic/chroot".into(),
+            user: "aquatic-nonexistent-user".into(),
+            group: "aquatic-nonexistent-group".into(),
+            ..Default::default()
+        };
+
+        let dropper = PrivilegeDropper::new(config, 3);
+
+        let handles = (0..3)
+            .map(|_| {
+                let dropper = dropper.clone();
+
+                ::std::thread::spawn(move || dropper.after_socket_creation())
+            })
+            .collect::<Vec<_>>();
+
+        for handle in handles {
+            assert!(handle.join().unwrap().is_err());
+        }
+
+        assert!(!dropper.wait_until_sockets_ready(|| true));
+    }
+
+    #[test]
+    fn test_wait_until_sockets_ready() {
+        let dropper = PrivilegeDropper::new(PrivilegeConfig::default(), 2);
+
+        assert!(!dropper.wait_until_sockets_ready(|| true));
+
+        let handles = (0..2)
+            .map(|_| {
+                let dropper = dropper.clone();
+
+                ::std::thread::spawn(move || dropper.after_socket_creation())
+            })
+            .collect::<Vec<_>>();
+
+        assert!(dropper.wait_until_sockets_ready(|| false));
+
+        for handle in handles {
+            handle.join().unwrap().unwrap();
+        }
+    }
 }
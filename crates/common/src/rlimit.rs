@@ -0,0 +1,95 @@
+//! Raising resource limits at startup
+
+use std::io;
+
+use anyhow::Context;
+
+/// Raise soft limit on number of open files (RLIMIT_NOFILE) to at least
+/// `target`
+///
+/// The hard limit is raised too if needed, which usually requires root or
+/// CAP_SYS_RESOURCE. If that fails, the soft limit is set to the hard limit
+/// and a warning is logged. Never lowers limits. Returns the achieved soft
+/// limit.
+pub fn raise_open_file_limit(target: u64) -> anyhow::Result<u64> {
+    let target = target as libc::rlim_t;
+
+    let mut limit = get_open_file_limit().context("get open file limit")?;
+
+    if limit.rlim_cur >= target {
+        ::log::info!(
+            "Open file limit is {}, which is at least the configured {}",
+            limit.rlim_cur,
+            target
+        );
+
+        return Ok(limit.rlim_cur as u64);
+    }
+
+    if limit.rlim_max < target {
+        let raised = libc::rlimit {
+            rlim_cur: target,
+            rlim_max: target,
+        };
+
+        if set_open_file_limit(&raised).is_ok() {
+            ::log::info!("Raised open file limit to {}", target);
+
+            return Ok(target as u64);
+        }
+
+        limit.rlim_cur = limit.rlim_max;
+    } else {
+        limit.rlim_cur = target;
+    }
+
+    set_open_file_limit(&limit).context("set open file limit")?;
+
+    if limit.rlim_cur < target {
+        ::log::warn!(
+            "Open file limit was clamped to hard limit {} instead of configured {}. Raise the hard limit (e.g., LimitNOFILE in systemd unit) or run as root",
+            limit.rlim_cur,
+            target
+        );
+    } else {
+        ::log::info!("Raised open file limit to {}", limit.rlim_cur);
+    }
+
+    Ok(limit.rlim_cur as u64)
+}
+
+fn get_open_file_limit() -> io::Result<libc::rlimit> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(limit)
+}
+
+fn set_open_file_limit(limit: &libc::rlimit) -> io::Result<()> {
+    if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_raise_open_file_limit_never_lowers() {
+        let before = get_open_file_limit().unwrap();
+
+        let achieved = raise_open_file_limit(1).unwrap();
+
+        assert_eq!(achieved, before.rlim_cur as u64);
+        assert_eq!(get_open_file_limit().unwrap().rlim_cur, before.rlim_cur);
+    }
+}
@@ -1,9 +1,81 @@
-use std::{fs::File, io::BufReader, path::Path};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::{fs::File, io::BufReader};
 
 use anyhow::Context;
+use arc_swap::ArcSwap;
 
 pub type RustlsConfig = rustls::ServerConfig;
 
+/// TLS config shared with socket workers, which is replaced when the
+/// certificate or private key files change
+pub struct RustlsConfigUpdater {
+    tls_certificate_path: PathBuf,
+    tls_private_key_path: PathBuf,
+    /// Application protocols to negotiate with ALPN
+    alpn_protocols: Vec<Vec<u8>>,
+    pub tls_config: Arc<ArcSwap<RustlsConfig>>,
+    /// Contents of certificate and private key files that the current TLS
+    /// config was created from
+    file_data: Mutex<(Vec<u8>, Vec<u8>)>,
+}
+
+impl RustlsConfigUpdater {
+    pub fn new(
+        tls_certificate_path: &Path,
+        tls_private_key_path: &Path,
+        alpn_protocols: Vec<Vec<u8>>,
+    ) -> anyhow::Result<Self> {
+        let file_data = read_tls_files(tls_certificate_path, tls_private_key_path)?;
+        let mut tls_config = create_rustls_config(tls_certificate_path, tls_private_key_path)?;
+
+        tls_config.alpn_protocols = alpn_protocols.clone();
+
+        Ok(Self {
+            tls_certificate_path: tls_certificate_path.to_path_buf(),
+            tls_private_key_path: tls_private_key_path.to_path_buf(),
+            alpn_protocols,
+            tls_config: Arc::new(ArcSwap::from_pointee(tls_config)),
+            file_data: Mutex::new(file_data),
+        })
+    }
+
+    /// Replace TLS config if certificate or private key files have changed.
+    /// Returns true if it was replaced.
+    pub fn update(&self) -> anyhow::Result<bool> {
+        let mut current_file_data = self.file_data.lock().unwrap();
+
+        let file_data = read_tls_files(&self.tls_certificate_path, &self.tls_private_key_path)?;
+
+        if file_data == *current_file_data {
+            return Ok(false);
+        }
+
+        let mut tls_config =
+            create_rustls_config(&self.tls_certificate_path, &self.tls_private_key_path)?;
+
+        tls_config.alpn_protocols = self.alpn_protocols.clone();
+
+        self.tls_config.store(Arc::new(tls_config));
+
+        *current_file_data = file_data;
+
+        Ok(true)
+    }
+}
+
+fn read_tls_files(
+    tls_certificate_path: &Path,
+    tls_private_key_path: &Path,
+) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+    let certificate =
+        ::std::fs::read(tls_certificate_path).with_context(|| "read tls certificate file")?;
+    let private_key =
+        ::std::fs::read(tls_private_key_path).with_context(|| "read tls private key file")?;
+
+    Ok((certificate, private_key))
+}
+
 pub fn create_rustls_config(
     tls_certificate_path: &Path,
     tls_private_key_path: &Path,
@@ -0,0 +1,221 @@
+//! Block a deny list of system calls that are never needed after startup
+//!
+//! Uses a seccomp-bpf deny list rather than an allow list, since the set of
+//! system calls used by the event loops (mio, io_uring, glommio) and by
+//! the allocator varies with kernel and library versions. The denied calls
+//! are the ones typically needed to turn code execution into a more serious
+//! compromise: executing programs, debugging other processes, loading kernel
+//! code, changing namespaces, mounts and credentials.
+
+use std::sync::OnceLock;
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+const DENIED_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_execve,
+    libc::SYS_execveat,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_fork,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_vfork,
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_readv,
+    libc::SYS_process_vm_writev,
+    libc::SYS_personality,
+    libc::SYS_mount,
+    libc::SYS_umount2,
+    libc::SYS_pivot_root,
+    libc::SYS_chroot,
+    libc::SYS_unshare,
+    libc::SYS_setns,
+    libc::SYS_setuid,
+    libc::SYS_setgid,
+    libc::SYS_setreuid,
+    libc::SYS_setregid,
+    libc::SYS_setresuid,
+    libc::SYS_setresgid,
+    libc::SYS_setgroups,
+    libc::SYS_init_module,
+    libc::SYS_finit_module,
+    libc::SYS_delete_module,
+    libc::SYS_kexec_load,
+    libc::SYS_kexec_file_load,
+    libc::SYS_bpf,
+    libc::SYS_perf_event_open,
+    libc::SYS_userfaultfd,
+    libc::SYS_keyctl,
+    libc::SYS_add_key,
+    libc::SYS_request_key,
+    libc::SYS_acct,
+    libc::SYS_swapon,
+    libc::SYS_swapoff,
+    libc::SYS_reboot,
+];
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+const AUDIT_ARCH: u32 = 0xC000_003E;
+#[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+const AUDIT_ARCH: u32 = 0xC000_00B7;
+
+/// System call numbers at or above this are x32 ABI calls on x86_64
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+static APPLIED: OnceLock<Result<(), String>> = OnceLock::new();
+
+/// Apply filter to all threads of the process, once
+///
+/// Threads spawned later inherit the filter.
+pub fn apply_deny_list() -> anyhow::Result<()> {
+    APPLIED
+        .get_or_init(|| {
+            #[cfg(all(
+                target_os = "linux",
+                any(target_arch = "x86_64", target_arch = "aarch64")
+            ))]
+            let result = install_filter(libc::SECCOMP_FILTER_FLAG_TSYNC);
+            #[cfg(not(all(
+                target_os = "linux",
+                any(target_arch = "x86_64", target_arch = "aarch64")
+            )))]
+            let result: anyhow::Result<()> = Err(anyhow::anyhow!(
+                "only supported on Linux on x86_64 and aarch64"
+            ));
+
+            result.map_err(|err| format!("restrict system calls: {:#}", err))
+        })
+        .clone()
+        .map_err(|err| anyhow::anyhow!(err))?;
+
+    ::log::info!("Restricted system calls with seccomp filter");
+
+    Ok(())
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+fn install_filter(flags: libc::c_ulong) -> anyhow::Result<()> {
+    use libc::{sock_filter, sock_fprog};
+
+    const OFFSET_NR: u32 = 0;
+    const OFFSET_ARCH: u32 = 4;
+
+    fn statement(code: u32, k: u32) -> sock_filter {
+        sock_filter {
+            code: code as u16,
+            jt: 0,
+            jf: 0,
+            k,
+        }
+    }
+    fn jump(code: u32, k: u32, jt: u8, jf: u8) -> sock_filter {
+        sock_filter {
+            code: code as u16,
+            jt,
+            jf,
+            k,
+        }
+    }
+
+    let ret_allow = statement(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ALLOW);
+    let ret_deny = statement(
+        libc::BPF_RET | libc::BPF_K,
+        libc::SECCOMP_RET_ERRNO | (libc::EPERM as u32),
+    );
+
+    let mut program = vec![
+        // Kill process if system call is made with another ABI
+        statement(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, OFFSET_ARCH),
+        jump(
+            libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+            AUDIT_ARCH,
+            1,
+            0,
+        ),
+        statement(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+        statement(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, OFFSET_NR),
+    ];
+
+    #[cfg(target_arch = "x86_64")]
+    program.extend([
+        jump(
+            libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K,
+            X32_SYSCALL_BIT,
+            0,
+            1,
+        ),
+        ret_deny,
+    ]);
+
+    let num_denied = DENIED_SYSCALLS.len();
+
+    // Each comparison jumps to the deny statement after the allow statement
+    for (i, nr) in DENIED_SYSCALLS.iter().enumerate() {
+        program.push(jump(
+            libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+            *nr as u32,
+            (num_denied - i) as u8,
+            0,
+        ));
+    }
+
+    program.push(ret_allow);
+    program.push(ret_deny);
+
+    let fprog = sock_fprog {
+        len: program.len() as u16,
+        filter: program.as_mut_ptr(),
+    };
+
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        return Err(anyhow::anyhow!(
+            "set no_new_privs: {}",
+            ::std::io::Error::last_os_error()
+        ));
+    }
+
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_seccomp,
+            libc::SECCOMP_SET_MODE_FILTER,
+            flags,
+            &fprog as *const sock_fprog,
+        )
+    };
+
+    if result != 0 {
+        return Err(anyhow::anyhow!(
+            "install seccomp filter: {}",
+            ::std::io::Error::last_os_error()
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(all(
+    test,
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_blocks_execve() {
+        // Only filter the spawned thread, not the whole test process
+        ::std::thread::spawn(|| {
+            assert!(::std::process::Command::new("true").status().is_ok());
+
+            install_filter(0).unwrap();
+
+            assert!(::std::process::Command::new("true").status().is_err());
+        })
+        .join()
+        .unwrap();
+    }
+}
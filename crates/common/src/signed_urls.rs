@@ -0,0 +1,205 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use aquatic_toml_config::TomlConfig;
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Default, PartialEq, TomlConfig, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SignedUrlConfig {
+    /// Only serve requests with announce URLs signed by an external site
+    ///
+    /// The last path segment of the announce URL (e.g.,
+    /// `/announce/<token>`) must be a token of the form
+    /// `<user id>.<expiry>.<signature>`, where expiry is a unix timestamp
+    /// and signature is the hex-encoded HMAC-SHA256 of
+    /// `<info hash>.<user id>.<expiry>` using `secret` as key. The info hash
+    /// is hex-encoded in lowercase. The token is percent-decoded before it
+    /// is verified. Since signatures cover a single info hash, scrapes only
+    /// return statistics for that torrent.
+    ///
+    /// aquatic_http limits path segments to 256 bytes, so user ids should
+    /// be at most 150 bytes long (before percent-encoding).
+    pub enabled: bool,
+    /// Secret key shared with the site generating announce URLs. Must be at
+    /// least 32 bytes long.
+    pub secret: String,
+}
+
+impl SignedUrlConfig {
+    pub const MIN_SECRET_LEN: usize = 32;
+
+    pub fn validation_errors(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if self.enabled && self.secret.len() < Self::MIN_SECRET_LEN {
+            errors.push(format!(
+                "signed_urls.secret must be at least {} bytes long",
+                Self::MIN_SECRET_LEN
+            ));
+        }
+
+        errors
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignedUrlError {
+    /// Token is missing, malformed or has an invalid signature
+    Invalid,
+    Expired,
+}
+
+impl SignedUrlError {
+    pub fn failure_reason(&self) -> &'static str {
+        match self {
+            Self::Invalid => "Invalid announce URL signature",
+            Self::Expired => "Announce URL has expired",
+        }
+    }
+}
+
+/// Verifies announce URL tokens signed with secret from config
+#[derive(Clone)]
+pub struct SignedUrlVerifier(hmac::Key);
+
+impl SignedUrlVerifier {
+    pub fn new(config: &SignedUrlConfig) -> Self {
+        Self(hmac::Key::new(hmac::HMAC_SHA256, config.secret.as_bytes()))
+    }
+
+    /// Create token for info hash, user id and expiry (unix timestamp)
+    pub fn sign(&self, info_hash: &[u8; 20], user_id: &str, expiry: u64) -> String {
+        let message = Self::message(info_hash, user_id, expiry);
+        let tag = hmac::sign(&self.0, message.as_bytes());
+
+        format!("{}.{}.{}", user_id, expiry, hex::encode(tag.as_ref()))
+    }
+
+    /// Check that token was signed for info hash and hasn't expired
+    pub fn verify(
+        &self,
+        opt_token: Option<&str>,
+        info_hash: &[u8; 20],
+    ) -> Result<(), SignedUrlError> {
+        self.verify_at(opt_token, info_hash, unix_timestamp_now())
+    }
+
+    fn verify_at(
+        &self,
+        opt_token: Option<&str>,
+        info_hash: &[u8; 20],
+        now: u64,
+    ) -> Result<(), SignedUrlError> {
+        let token = opt_token.ok_or(SignedUrlError::Invalid)?;
+
+        let mut parts = token.rsplitn(3, '.');
+
+        let (Some(signature), Some(expiry), Some(user_id)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(SignedUrlError::Invalid);
+        };
+
+        if expiry.is_empty() || !expiry.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(SignedUrlError::Invalid);
+        }
+
+        let expiry: u64 = expiry.parse().map_err(|_| SignedUrlError::Invalid)?;
+        let signature = hex::decode(signature).map_err(|_| SignedUrlError::Invalid)?;
+
+        let message = Self::message(info_hash, user_id, expiry);
+
+        hmac::verify(&self.0, message.as_bytes(), &signature)
+            .map_err(|_| SignedUrlError::Invalid)?;
+
+        // Only check expiry after signature, so that it can't be probed
+        // without a valid signature
+        if expiry <= now {
+            return Err(SignedUrlError::Expired);
+        }
+
+        Ok(())
+    }
+
+    fn message(info_hash: &[u8; 20], user_id: &str, expiry: u64) -> String {
+        format!("{}.{}.{}", hex::encode(info_hash), user_id, expiry)
+    }
+}
+
+fn unix_timestamp_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signed_urls() {
+        let config = SignedUrlConfig {
+            enabled: true,
+            secret: "0123456789abcdef0123456789abcdef".into(),
+        };
+        let verifier = SignedUrlVerifier::new(&config);
+
+        let info_hash = [1u8; 20];
+        let token = verifier.sign(&info_hash, "user.1", 1000);
+
+        assert_eq!(verifier.verify_at(Some(&token), &info_hash, 999), Ok(()));
+        assert_eq!(
+            verifier.verify_at(Some(&token), &info_hash, 1000),
+            Err(SignedUrlError::Expired)
+        );
+        assert_eq!(
+            verifier.verify_at(Some(&token), &[2u8; 20], 999),
+            Err(SignedUrlError::Invalid)
+        );
+        assert_eq!(
+            verifier.verify_at(None, &info_hash, 999),
+            Err(SignedUrlError::Invalid)
+        );
+
+        // Tampered user id, expiry and signature
+        for tampered in [
+            token.replacen("user.1", "user.2", 1),
+            token.replacen("1000", "2000", 1),
+            token.replacen(".1000.", ".+1000.", 1),
+            format!("{}00", token),
+            "user.1000".into(),
+            "".into(),
+        ] {
+            assert_eq!(
+                verifier.verify_at(Some(&tampered), &info_hash, 999),
+                Err(SignedUrlError::Invalid),
+                "{}",
+                tampered
+            );
+        }
+
+        // Matches signature from external implementation:
+        // printf '0101010101010101010101010101010101010101.user.1.1000' |
+        //   openssl dgst -sha256 -hmac 0123456789abcdef0123456789abcdef
+        assert_eq!(
+            token,
+            "user.1.1000.5018aeb219f65aca72f76d65a661a7a9a9fe82da3e7b998771daa1ff932a8057"
+        );
+    }
+
+    #[test]
+    fn test_signed_url_config_validation_errors() {
+        let mut config = SignedUrlConfig {
+            secret: "short".into(),
+            ..Default::default()
+        };
+
+        assert!(config.validation_errors().is_empty());
+
+        config.enabled = true;
+
+        assert_eq!(config.validation_errors().len(), 1);
+    }
+}
@@ -0,0 +1,237 @@
+//! systemd socket activation and service status notification
+//!
+//! Implements the relevant parts of sd_listen_fds(3) and sd_notify(3)
+//! without depending on libsystemd.
+
+use std::ffi::OsStr;
+use std::os::fd::{BorrowedFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::UnixDatagram;
+use std::sync::mpsc::Sender;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use anyhow::Context;
+
+/// First file descriptor passed by systemd
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+static LISTEN_FDS: OnceLock<Vec<(RawFd, String)>> = OnceLock::new();
+
+/// File descriptors passed to this process by systemd socket activation,
+/// with their names (`FileDescriptorName=` in socket unit, `unknown` if not
+/// set)
+pub fn listen_fds() -> &'static [(RawFd, String)] {
+    LISTEN_FDS.get_or_init(|| {
+        let Ok(pid) = ::std::env::var("LISTEN_PID") else {
+            return Vec::new();
+        };
+
+        if pid.parse::<u32>().ok() != Some(::std::process::id()) {
+            return Vec::new();
+        }
+
+        let num_fds = ::std::env::var("LISTEN_FDS")
+            .ok()
+            .and_then(|n| n.parse::<RawFd>().ok())
+            .unwrap_or(0);
+        let names = ::std::env::var("LISTEN_FDNAMES").unwrap_or_default();
+        let mut names = names.split(':').filter(|name| !name.is_empty());
+
+        (SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + num_fds)
+            .map(|fd| {
+                unsafe {
+                    libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+                }
+
+                (fd, names.next().unwrap_or("unknown").to_string())
+            })
+            .collect()
+    })
+}
+
+/// Check if systemd passed a file descriptor with this name
+pub fn has_listen_fd_named(name: &str) -> bool {
+    listen_fds().iter().any(|(_, fd_name)| fd_name == name)
+}
+
+/// Duplicate the socket named `name` passed by systemd socket activation,
+/// or the only socket passed if none has that name
+///
+/// Every caller gets its own file descriptor referring to the same socket.
+pub fn duplicate_listen_fd(name: &str) -> anyhow::Result<OwnedFd> {
+    let fd = select_listen_fd(listen_fds(), name)?;
+
+    // Safety: fd was passed to us by systemd and is never closed
+    let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+
+    fd.try_clone_to_owned()
+        .context("systemd socket activation: duplicate file descriptor")
+}
+
+fn select_listen_fd(fds: &[(RawFd, String)], name: &str) -> anyhow::Result<RawFd> {
+    if let Some((fd, _)) = fds.iter().find(|(_, fd_name)| fd_name == name) {
+        return Ok(*fd);
+    }
+
+    match fds {
+        [] => Err(anyhow::anyhow!(
+            "systemd socket activation: no file descriptors were passed"
+        )),
+        [(fd, _)] => Ok(*fd),
+        _ => Err(anyhow::anyhow!(
+            "systemd socket activation: {} file descriptors were passed, but none is named {} (set FileDescriptorName= in socket unit)",
+            fds.len(),
+            name
+        )),
+    }
+}
+
+/// Send status update (e.g., `READY=1`) to systemd
+///
+/// Does nothing if the process wasn't started by systemd with notification
+/// support enabled.
+pub fn notify(state: &str) -> anyhow::Result<()> {
+    match ::std::env::var_os("NOTIFY_SOCKET") {
+        Some(path) => notify_to(&path, state),
+        None => Ok(()),
+    }
+}
+
+/// Send status update to notification socket at `path`, which may be an
+/// abstract socket address starting with `@`
+fn notify_to(path: &OsStr, state: &str) -> anyhow::Result<()> {
+    let socket = UnixDatagram::unbound().context("sd_notify: create socket")?;
+
+    if let Some(name) = path.as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::linux::net::SocketAddrExt;
+
+            let addr = ::std::os::unix::net::SocketAddr::from_abstract_name(name)
+                .context("sd_notify: parse abstract socket address")?;
+
+            socket
+                .send_to_addr(state.as_bytes(), &addr)
+                .context("sd_notify: send")?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = name;
+
+            return Err(anyhow::anyhow!(
+                "sd_notify: abstract socket addresses are only supported on Linux"
+            ));
+        }
+    } else {
+        socket
+            .send_to(state.as_bytes(), path)
+            .context("sd_notify: send")?;
+    }
+
+    Ok(())
+}
+
+/// Log failures instead of returning them, since notifications are not
+/// essential to running the tracker
+pub fn notify_or_log(state: &str) {
+    if let Err(err) = notify(state) {
+        ::log::error!("{:#}", err);
+    }
+}
+
+/// Interval at which `WATCHDOG=1` should be sent, if the systemd watchdog is
+/// enabled for this process
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = ::std::env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(::std::process::id()) {
+            return None;
+        }
+    }
+
+    let usec = ::std::env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+
+    // Notify twice per timeout period, as recommended by sd_watchdog_enabled(3)
+    Some(Duration::from_micros(usec / 2))
+}
+
+/// Where a tracker sends service status notifications
+#[derive(Clone)]
+pub enum StatusNotifier {
+    /// Notify systemd, if the process was started by it
+    Systemd,
+    /// Tracker is one of several run in the same process. Report readiness
+    /// through the channel and leave notifying systemd to the caller.
+    Embedded(Sender<()>),
+}
+
+impl StatusNotifier {
+    pub fn ready(&self) {
+        match self {
+            Self::Systemd => notify_or_log("READY=1"),
+            Self::Embedded(sender) => {
+                let _ = sender.send(());
+            }
+        }
+    }
+
+    pub fn stopping(&self) {
+        if let Self::Systemd = self {
+            notify_or_log("STOPPING=1");
+        }
+    }
+
+    pub fn watchdog(&self) {
+        if let Self::Systemd = self {
+            notify_or_log("WATCHDOG=1");
+        }
+    }
+
+    /// Interval at which `watchdog` should be called, if the systemd
+    /// watchdog is enabled and this tracker is responsible for it
+    pub fn watchdog_interval(&self) -> Option<Duration> {
+        match self {
+            Self::Systemd => watchdog_interval(),
+            Self::Embedded(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_listen_fd() {
+        let fds = [(3, "udp".to_string()), (4, "http".to_string())];
+
+        assert_eq!(select_listen_fd(&fds, "udp").unwrap(), 3);
+        assert_eq!(select_listen_fd(&fds, "http").unwrap(), 4);
+        assert!(select_listen_fd(&fds, "ws").is_err());
+
+        assert_eq!(
+            select_listen_fd(&[(3, "unknown".to_string())], "ws").unwrap(),
+            3
+        );
+        assert!(select_listen_fd(&[], "ws").is_err());
+    }
+
+    #[test]
+    fn test_notify_to() {
+        let path = ::std::env::temp_dir()
+            .join(format!("aquatic-test-notify-{}.sock", ::std::process::id()));
+
+        let _ = ::std::fs::remove_file(&path);
+
+        let receiver = UnixDatagram::bind(&path).unwrap();
+
+        notify_to(path.as_os_str(), "READY=1").unwrap();
+
+        let mut buf = [0u8; 64];
+        let len = receiver.recv(&mut buf).unwrap();
+
+        assert_eq!(&buf[..len], b"READY=1");
+
+        ::std::fs::remove_file(&path).unwrap();
+    }
+}
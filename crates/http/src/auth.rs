@@ -0,0 +1,317 @@
+use std::collections::HashSet;
+use std::io::Read;
+use std::net::IpAddr;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use aquatic_common::http_client::describe_request_error;
+use aquatic_http_protocol::request::AnnounceRequest;
+use futures::future::{ready, FutureExt, LocalBoxFuture};
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::config::{AuthBackend, AuthConfig};
+
+/// Maximum size of callback response body
+const MAX_CALLBACK_RESPONSE_SIZE: u64 = 64 * 1024;
+
+/// Announce request details passed to authentication hooks
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AuthRequest {
+    #[serde(serialize_with = "serialize_hex")]
+    pub peer_id: [u8; 20],
+    #[serde(serialize_with = "serialize_hex")]
+    pub info_hash: [u8; 20],
+    pub passkey: Option<String>,
+    /// IP address of the peer, before peer IP override policy is applied
+    pub ip: IpAddr,
+}
+
+impl AuthRequest {
+    pub fn new(request: &AnnounceRequest, ip: IpAddr) -> Self {
+        Self {
+            peer_id: request.peer_id.0,
+            info_hash: request.info_hash.0,
+            passkey: request.passkey.as_ref().map(|passkey| passkey.to_string()),
+            ip,
+        }
+    }
+}
+
+/// Authentication hook decision
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct AuthVerdict {
+    pub allow: bool,
+    /// Failure reason sent to peer if request is denied
+    #[serde(default)]
+    pub message: Option<String>,
+    /// Send this announce interval (seconds) instead of the configured one.
+    /// 0 is ignored and shorter intervals than
+    /// `protocol.min_announce_interval` are raised to it.
+    #[serde(default)]
+    pub interval: Option<usize>,
+}
+
+impl AuthVerdict {
+    pub fn allow() -> Self {
+        Self {
+            allow: true,
+            message: None,
+            interval: None,
+        }
+    }
+
+    pub fn deny(message: &str) -> Self {
+        Self {
+            allow: false,
+            message: Some(message.into()),
+            interval: None,
+        }
+    }
+
+    pub fn failure_reason(&self) -> String {
+        self.message
+            .clone()
+            .unwrap_or_else(|| "Not authorized".into())
+    }
+
+    /// Announce interval to send instead of the configured one, if any.
+    /// Never shorter than `min_announce_interval`, so that peers announcing
+    /// as asked aren't rejected.
+    pub fn announce_interval(&self, min_announce_interval: u32) -> Option<usize> {
+        self.interval
+            .filter(|interval| *interval != 0)
+            .map(|interval| interval.max(min_announce_interval as usize))
+    }
+}
+
+/// Decides whether to serve announce requests
+///
+/// Hooks are called on socket worker executors, so they must not block.
+/// Blocking work can be moved to the executor blocking thread pool.
+pub trait AuthHook {
+    fn authenticate(&self, request: AuthRequest) -> LocalBoxFuture<'static, AuthVerdict>;
+}
+
+/// Create hook for configured backend
+pub fn create_auth_hook(config: &AuthConfig) -> Rc<dyn AuthHook> {
+    match config.backend {
+        AuthBackend::None => Rc::new(NoAuth),
+        AuthBackend::StaticTokens => Rc::new(StaticTokens::new(&config.tokens)),
+        AuthBackend::HttpCallback => Rc::new(HttpCallback::new(config)),
+    }
+}
+
+/// Serves all requests
+pub struct NoAuth;
+
+impl AuthHook for NoAuth {
+    fn authenticate(&self, _request: AuthRequest) -> LocalBoxFuture<'static, AuthVerdict> {
+        ready(AuthVerdict::allow()).boxed_local()
+    }
+}
+
+/// Serves requests with passkey present in token list
+pub struct StaticTokens(HashSet<String>);
+
+impl StaticTokens {
+    pub fn new(tokens: &[String]) -> Self {
+        Self(tokens.iter().cloned().collect())
+    }
+}
+
+impl AuthHook for StaticTokens {
+    fn authenticate(&self, request: AuthRequest) -> LocalBoxFuture<'static, AuthVerdict> {
+        let verdict = if request
+            .passkey
+            .is_some_and(|passkey| self.0.contains(&passkey))
+        {
+            AuthVerdict::allow()
+        } else {
+            AuthVerdict::deny("Invalid token")
+        };
+
+        ready(verdict).boxed_local()
+    }
+}
+
+/// Asks an HTTP server whether to serve requests
+///
+/// Requests to the server are made on the executor blocking thread pool.
+pub struct HttpCallback {
+    agent: ureq::Agent,
+    url: Arc<str>,
+    allow_on_error: bool,
+}
+
+impl HttpCallback {
+    pub fn new(config: &AuthConfig) -> Self {
+        Self {
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_millis(config.callback_timeout_ms))
+                .redirects(0)
+                .build(),
+            url: config.callback_url.as_str().into(),
+            allow_on_error: config.callback_allow_on_error,
+        }
+    }
+}
+
+impl AuthHook for HttpCallback {
+    fn authenticate(&self, request: AuthRequest) -> LocalBoxFuture<'static, AuthVerdict> {
+        let agent = self.agent.clone();
+        let url = self.url.clone();
+        let allow_on_error = self.allow_on_error;
+
+        glommio::executor()
+            .spawn_blocking(move || match call_auth_callback(&agent, &url, &request) {
+                Ok(verdict) => verdict,
+                Err(err) => {
+                    ::log::warn!("Authentication callback failed: {:#}", err);
+
+                    if allow_on_error {
+                        AuthVerdict::allow()
+                    } else {
+                        AuthVerdict::deny("Authentication unavailable")
+                    }
+                }
+            })
+            .boxed_local()
+    }
+}
+
+fn call_auth_callback(
+    agent: &ureq::Agent,
+    url: &str,
+    request: &AuthRequest,
+) -> anyhow::Result<AuthVerdict> {
+    let body = serde_json::to_string(request).context("serialize request")?;
+
+    let response = agent
+        .post(url)
+        .set("Content-Type", "application/json")
+        .send_string(&body)
+        .map_err(describe_request_error)
+        .context("request authentication callback")?;
+
+    if response.status() != 200 {
+        return Err(anyhow::anyhow!(
+            "unexpected response status {}",
+            response.status()
+        ));
+    }
+
+    let mut body = Vec::new();
+
+    response
+        .into_reader()
+        .take(MAX_CALLBACK_RESPONSE_SIZE)
+        .read_to_end(&mut body)
+        .context("read response")?;
+
+    serde_json::from_slice(&body).context("parse response")
+}
+
+fn serialize_hex<S: Serializer>(bytes: &[u8; 20], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&hex::encode(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use aquatic_common::http_client::test_helpers::spawn_test_http_server;
+
+    use super::*;
+
+    fn auth_request(passkey: Option<&str>) -> AuthRequest {
+        AuthRequest {
+            peer_id: *b"-qB4650-0123456789ab",
+            info_hash: [0xab; 20],
+            passkey: passkey.map(Into::into),
+            ip: [10, 0, 0, 1].into(),
+        }
+    }
+
+    #[test]
+    fn test_static_tokens() {
+        let hook = StaticTokens::new(&["0123abcd".into()]);
+
+        let verdict = |passkey| {
+            hook.authenticate(auth_request(passkey))
+                .now_or_never()
+                .unwrap()
+        };
+
+        assert_eq!(verdict(Some("0123abcd")), AuthVerdict::allow());
+        assert!(!verdict(Some("0123abce")).allow);
+        assert!(!verdict(None).allow);
+    }
+
+    #[test]
+    fn test_announce_interval() {
+        let verdict = |interval| AuthVerdict {
+            interval,
+            ..AuthVerdict::allow()
+        };
+
+        assert_eq!(verdict(None).announce_interval(60), None);
+        assert_eq!(verdict(Some(0)).announce_interval(0), None);
+        assert_eq!(verdict(Some(0)).announce_interval(60), None);
+        assert_eq!(verdict(Some(30)).announce_interval(60), Some(60));
+        assert_eq!(verdict(Some(900)).announce_interval(60), Some(900));
+        assert_eq!(verdict(Some(900)).announce_interval(0), Some(900));
+    }
+
+    #[test]
+    fn test_call_auth_callback() {
+        let (addr, server) = spawn_test_http_server(
+            [
+                r#"{"allow":false,"message":"Ratio too low"}"#,
+                r#"{"allow":true,"interval":60}"#,
+                r#"{"allow":"#,
+            ]
+            .map(|body| {
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+            })
+            .to_vec(),
+        );
+
+        let agent = ureq::Agent::new();
+        let url = format!("http://{}/auth", addr);
+
+        assert_eq!(
+            call_auth_callback(&agent, &url, &auth_request(Some("0123abcd"))).unwrap(),
+            AuthVerdict::deny("Ratio too low")
+        );
+        assert_eq!(
+            call_auth_callback(&agent, &url, &auth_request(None)).unwrap(),
+            AuthVerdict {
+                allow: true,
+                message: None,
+                interval: Some(60),
+            }
+        );
+        assert!(call_auth_callback(&agent, &url, &auth_request(None)).is_err());
+
+        let request_bodies: Vec<String> = server
+            .join()
+            .unwrap()
+            .into_iter()
+            .map(|request| String::from_utf8(request.body).unwrap())
+            .collect();
+
+        assert_eq!(
+            request_bodies[0],
+            format!(
+                r#"{{"peer_id":"{}","info_hash":"{}","passkey":"0123abcd","ip":"10.0.0.1"}}"#,
+                hex::encode(b"-qB4650-0123456789ab"),
+                "ab".repeat(20),
+            )
+        );
+        assert!(request_bodies[1].contains(r#""passkey":null"#));
+    }
+}
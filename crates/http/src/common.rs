@@ -1,19 +1,24 @@
 use std::sync::Arc;
 
 use aquatic_common::access_list::AccessListArcSwap;
+use aquatic_common::ban_list::BanListArcSwap;
+use aquatic_common::load_shedding::AnnounceCeiling;
+use aquatic_common::signed_urls::SignedUrlError;
 use aquatic_common::CanonicalSocketAddr;
+use arc_swap::ArcSwap;
 
 pub use aquatic_common::ValidUntil;
 
 use aquatic_http_protocol::{
+    common::InfoHash,
     request::{AnnounceRequest, ScrapeRequest},
-    response::{AnnounceResponse, ScrapeResponse},
+    response::{FailureResponse, Response, ScrapeResponse, ScrapeStatistics},
 };
 use glommio::channels::shared_channel::SharedSender;
 use slotmap::new_key_type;
 
-#[derive(Copy, Clone, Debug)]
-pub struct ConsumerId(pub usize);
+use crate::config::{Config, StatusCodesConfig};
+use crate::passkeys::PasskeysArcSwap;
 
 new_key_type! {
     pub struct ConnectionId;
@@ -24,7 +29,7 @@ pub enum ChannelRequest {
     Announce {
         request: AnnounceRequest,
         peer_addr: CanonicalSocketAddr,
-        response_sender: SharedSender<AnnounceResponse>,
+        response_sender: SharedSender<Response>,
     },
     Scrape {
         request: ScrapeRequest,
@@ -33,7 +38,157 @@ pub enum ChannelRequest {
     },
 }
 
-#[derive(Default, Clone)]
+/// Reason for refusing to serve a request
+///
+/// Converts into a failure response, so that clients get the reason as the
+/// `failure reason` key of the response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackerError {
+    InvalidRequest,
+    InvalidPasskey,
+    SignedUrl(SignedUrlError),
+    /// Client filter rejected peer id, with failure reason naming policy
+    ClientNotAllowed(&'static str),
+    InfoHashNotAllowed,
+    AnnouncingTooOften,
+    /// Event `stopped` or `completed` from peer not stored with event
+    /// `started`
+    StartedEventRequired,
+    CompactRequired,
+    FullScrapeNotAllowed,
+}
+
+impl TrackerError {
+    pub fn failure_reason(&self) -> &'static str {
+        match self {
+            Self::InvalidRequest => "Invalid request",
+            Self::InvalidPasskey => "Invalid passkey",
+            Self::SignedUrl(err) => err.failure_reason(),
+            Self::ClientNotAllowed(reason) => reason,
+            Self::InfoHashNotAllowed => "Info hash not allowed",
+            Self::AnnouncingTooOften => "Announcing too often",
+            Self::StartedEventRequired => "Peer must announce with event started first",
+            Self::CompactRequired => "Only compact peer lists are supported",
+            Self::FullScrapeNotAllowed => "Full scrape not allowed",
+        }
+    }
+
+    /// HTTP status code of failure response. Zero means closing connection
+    /// without responding.
+    pub fn status_code(&self, config: &StatusCodesConfig) -> u16 {
+        match self {
+            Self::InvalidRequest => config.invalid_request,
+            Self::InvalidPasskey | Self::SignedUrl(_) => config.invalid_passkey,
+            _ => config.request_rejected,
+        }
+    }
+}
+
+/// Convert outcome of handling request into response and HTTP status code
+///
+/// Failure responses from swarm workers are requests that were rejected
+/// there, such as peers announcing too often.
+pub fn response_with_status(
+    config: &StatusCodesConfig,
+    result: Result<Response, TrackerError>,
+) -> (Response, u16) {
+    match result {
+        Ok(response @ Response::Failure(_)) => (response, config.request_rejected),
+        Ok(response) => (response, 200),
+        Err(err) => (err.into(), err.status_code(config)),
+    }
+}
+
+impl From<TrackerError> for Response {
+    fn from(err: TrackerError) -> Self {
+        Response::Failure(FailureResponse::new(err.failure_reason()))
+    }
+}
+
+/// Scrape statistics for all torrents of one swarm worker and IP version,
+/// sorted by info hash
+#[derive(Default)]
+pub struct FullScrapeFiles {
+    pub files: Vec<(InfoHash, ScrapeStatistics)>,
+    /// Length of file entries when bencoded
+    pub encoded_len: usize,
+}
+
+#[derive(Default)]
+pub struct FullScrapeSnapshot {
+    pub ipv4: FullScrapeFiles,
+    pub ipv6: FullScrapeFiles,
+}
+
+/// Latest full scrape snapshot of each swarm worker, indexed by worker index
+pub type FullScrapeSnapshots = Arc<[ArcSwap<FullScrapeSnapshot>]>;
+
+#[derive(Clone)]
 pub struct State {
     pub access_list: Arc<AccessListArcSwap>,
+    pub ban_list: Arc<BanListArcSwap>,
+    pub passkeys: Arc<PasskeysArcSwap>,
+    pub full_scrape_snapshots: FullScrapeSnapshots,
+    pub announce_ceiling: Arc<AnnounceCeiling>,
+}
+
+impl State {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            access_list: Default::default(),
+            ban_list: Default::default(),
+            passkeys: Default::default(),
+            full_scrape_snapshots: (0..config.swarm_workers)
+                .map(|_| Default::default())
+                .collect(),
+            announce_ceiling: Arc::new(AnnounceCeiling::new(
+                config.protocol.max_announces_per_second,
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracker_error_response() {
+        let mut bytes = Vec::new();
+
+        Response::from(TrackerError::InvalidPasskey)
+            .write_bytes(&mut bytes)
+            .unwrap();
+
+        assert_eq!(bytes, b"d14:failure reason15:Invalid passkeye");
+
+        let response = Response::from(TrackerError::ClientNotAllowed("Client not allowed"));
+
+        assert!(matches!(
+            response,
+            Response::Failure(FailureResponse { failure_reason }) if failure_reason == "Client not allowed"
+        ));
+    }
+
+    #[test]
+    fn test_response_with_status() {
+        let config = StatusCodesConfig {
+            invalid_passkey: 0,
+            request_rejected: 403,
+            ..Default::default()
+        };
+
+        let status = |result| response_with_status(&config, result).1;
+
+        assert_eq!(status(Err(TrackerError::InvalidRequest)), 400);
+        assert_eq!(status(Err(TrackerError::InvalidPasskey)), 0);
+        assert_eq!(status(Err(TrackerError::InfoHashNotAllowed)), 403);
+        assert_eq!(status(Ok(TrackerError::AnnouncingTooOften.into())), 403);
+        assert_eq!(
+            status(Ok(Response::Scrape(ScrapeResponse {
+                files: Default::default()
+            }))),
+            200
+        );
+    }
 }
@@ -1,6 +1,11 @@
 use std::{net::SocketAddr, path::PathBuf};
 
-use aquatic_common::{access_list::AccessListConfig, privileges::PrivilegeConfig};
+use aquatic_common::{
+    access_list::AccessListConfig, ban_list::BanListConfig, bogon_filter::BogonFilterConfig,
+    client_filter::ClientFilterConfig, greylist::GreylistConfig, ip_network::IpNetwork,
+    max_jittered_announce_interval, min_jittered_announce_interval, privileges::PrivilegeConfig,
+    signed_urls::SignedUrlConfig,
+};
 use aquatic_toml_config::TomlConfig;
 use serde::{Deserialize, Serialize};
 
@@ -13,6 +18,20 @@ pub enum ReverseProxyPeerIpHeaderFormat {
     LastAddress,
 }
 
+/// When to use peer IP addresses sent by clients in announce requests
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, TomlConfig, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerIpOverridePolicy {
+    /// Always use connection source address
+    #[default]
+    Never,
+    /// Use sent addresses for requests from `peer_ip_override_trusted_networks`
+    TrustedNetworks,
+    /// Always use sent addresses. Allows anyone to announce arbitrary
+    /// addresses, so only use this if all clients are trusted.
+    Always,
+}
+
 /// aquatic_http configuration
 #[derive(Clone, Debug, PartialEq, TomlConfig, Deserialize)]
 #[serde(default, deny_unknown_fields)]
@@ -40,8 +59,68 @@ pub struct Config {
     /// emitting of an error-level log message, while successful updates of the
     /// access list result in emitting of an info-level log message.
     pub access_list: AccessListConfig,
+    /// Ban list configuration
+    ///
+    /// Requests (aquatic_udp) and connections (aquatic_http and
+    /// aquatic_ws) from banned IP addresses are ignored before any parsing.
+    /// If initial parsing of the file fails, the program exits.
+    pub ban_list: BanListConfig,
+    /// Greylist configuration
+    ///
+    /// Temporarily close connections from network prefixes with many failed
+    /// TLS handshakes or malformed requests right after accepting them.
+    /// Connections from trusted reverse proxies are exempt.
+    pub greylist: GreylistConfig,
+    /// Bogon filter configuration
+    ///
+    /// Keep peers announcing from reserved or private addresses out of
+    /// swarms.
+    pub bogon_filter: BogonFilterConfig,
+    /// Client filter configuration
+    ///
+    /// Restrict which client software may announce by peer id prefix.
+    pub client_filter: ClientFilterConfig,
+    /// Passkey configuration
+    ///
+    /// Passkeys are sent by clients as part of the announce URL path
+    /// (`/announce/<passkey>`, and `/scrape/<passkey>` for scrapes). The
+    /// passkey file is read on start and when the program receives
+    /// `SIGUSR1`, in the same way as the access list file.
+    pub passkeys: PasskeyConfig,
+    /// Signed announce URL configuration
+    ///
+    /// Only serve requests with announce URLs signed by an external site,
+    /// e.g., for tokenized public access. Tokens are sent in the same path
+    /// segment as passkeys (`/announce/<token>`), so passkeys must be off.
+    /// Full scrapes are never allowed when this is enabled.
+    pub signed_urls: SignedUrlConfig,
+    /// Announce authentication configuration
+    ///
+    /// Ask an authentication hook whether to serve each announce request,
+    /// after passkey, client and access list checks have passed but before
+    /// the peer is added to the swarm. The hook gets the peer id, info hash,
+    /// passkey and IP address of the peer and may deny the request with a
+    /// failure reason or override the announce interval.
+    pub auth: AuthConfig,
+    /// Static routes configuration
+    ///
+    /// Serve fixed responses at a few well-known paths, so that the tracker
+    /// can be exposed directly without a separate web server in front of
+    /// it. The files are read on start.
+    pub static_routes: StaticRoutesConfig,
+    /// HTTP status codes of error responses
+    ///
+    /// Set a status code to 0 to close the connection without responding
+    /// instead (HTTP/2 streams are reset), e.g., to make private trackers
+    /// harder to discover by scanning.
+    pub status_codes: StatusCodesConfig,
     #[cfg(feature = "metrics")]
     pub metrics: MetricsConfig,
+    /// Pin worker threads to cpu cores
+    ///
+    /// Socket workers are pinned first, followed by swarm workers.
+    #[cfg(feature = "cpu-pinning")]
+    pub cpu_pinning: aquatic_common::cpu_pinning::asc::CpuPinningConfigAsc,
 }
 
 impl Default for Config {
@@ -55,8 +134,19 @@ impl Default for Config {
             cleaning: CleaningConfig::default(),
             privileges: PrivilegeConfig::default(),
             access_list: AccessListConfig::default(),
+            ban_list: BanListConfig::default(),
+            greylist: GreylistConfig::default(),
+            bogon_filter: BogonFilterConfig::default(),
+            client_filter: ClientFilterConfig::default(),
+            passkeys: PasskeyConfig::default(),
+            signed_urls: SignedUrlConfig::default(),
+            auth: AuthConfig::default(),
+            static_routes: StaticRoutesConfig::default(),
+            status_codes: StatusCodesConfig::default(),
             #[cfg(feature = "metrics")]
             metrics: Default::default(),
+            #[cfg(feature = "cpu-pinning")]
+            cpu_pinning: Default::default(),
         }
     }
 }
@@ -65,6 +155,121 @@ impl aquatic_common::cli::Config for Config {
     fn get_log_level(&self) -> Option<LogLevel> {
         Some(self.log_level)
     }
+    fn validation_errors(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if self.socket_workers == 0 {
+            errors.push("socket_workers must be greater than 0".into());
+        }
+        if self.swarm_workers == 0 {
+            errors.push("swarm_workers must be greater than 0".into());
+        }
+        if self.network.enable_tls {
+            if self.network.tls_certificate_path.as_os_str().is_empty() {
+                errors.push(
+                    "network.enable_tls is set, but network.tls_certificate_path is empty".into(),
+                );
+            }
+            if self.network.tls_private_key_path.as_os_str().is_empty() {
+                errors.push(
+                    "network.enable_tls is set, but network.tls_private_key_path is empty".into(),
+                );
+            }
+        }
+        if self.network.enable_http2 && !self.network.enable_tls {
+            errors.push("network.enable_http2 is set, but network.enable_tls is not".into());
+        }
+        if self.network.enable_http2 && self.network.http2_max_concurrent_streams == 0 {
+            errors.push("network.http2_max_concurrent_streams must be greater than 0".into());
+        }
+        if self.network.only_ipv6 && self.network.address.is_ipv4() {
+            errors.push("network.only_ipv6 is set, but network.address is an IPv4 address".into());
+        }
+        if self.protocol.max_scrape_torrents == 0 {
+            errors.push("protocol.max_scrape_torrents must be greater than 0".into());
+        }
+        if self.protocol.peer_announce_interval == 0 {
+            errors.push("protocol.peer_announce_interval must be greater than 0".into());
+        }
+        if self.protocol.max_announces_per_second != 0
+            && self.protocol.overloaded_announce_interval == 0
+        {
+            errors.push("protocol.overloaded_announce_interval must be greater than 0".into());
+        }
+        if self.protocol.enable_full_scrape && self.protocol.full_scrape_update_interval == 0 {
+            errors.push("protocol.full_scrape_update_interval must be greater than 0".into());
+        }
+        if self.cleaning.torrent_cleaning_interval == 0 {
+            errors.push("cleaning.torrent_cleaning_interval must be greater than 0".into());
+        }
+        if self.cleaning.connection_cleaning_interval == 0 {
+            errors.push("cleaning.connection_cleaning_interval must be greater than 0".into());
+        }
+        if self.protocol.peer_announce_interval_jitter >= 100 {
+            errors.push("protocol.peer_announce_interval_jitter must be less than 100".into());
+        }
+
+        let max_announce_interval = max_jittered_announce_interval(
+            self.protocol.peer_announce_interval,
+            self.protocol.peer_announce_interval_jitter,
+        );
+
+        if self.cleaning.max_peer_age as usize <= max_announce_interval {
+            errors.push(format!(
+                "cleaning.max_peer_age ({}) must be greater than protocol.peer_announce_interval ({}) including jitter, or peers will be removed before they reannounce",
+                self.cleaning.max_peer_age, max_announce_interval
+            ));
+        }
+
+        let min_announce_interval = min_jittered_announce_interval(
+            self.protocol.peer_announce_interval,
+            self.protocol.peer_announce_interval_jitter,
+        );
+
+        if self.protocol.min_announce_interval as usize >= min_announce_interval {
+            errors.push(format!(
+                "protocol.min_announce_interval ({}) must be less than protocol.peer_announce_interval ({}) including jitter, or peers announcing as asked will be rejected",
+                self.protocol.min_announce_interval, min_announce_interval
+            ));
+        }
+
+        for (key, networks) in [
+            (
+                "network.peer_ip_override_trusted_networks",
+                &self.network.peer_ip_override_trusted_networks,
+            ),
+            (
+                "network.reverse_proxy_trusted_networks",
+                &self.network.reverse_proxy_trusted_networks,
+            ),
+        ] {
+            for network in networks.iter() {
+                if let Err(err) = network.parse::<IpNetwork>() {
+                    errors.push(format!("{}: invalid network {:?}: {:#}", key, network, err));
+                }
+            }
+        }
+        if self.network.runs_behind_reverse_proxy
+            && self.network.reverse_proxy_trusted_networks.is_empty()
+        {
+            errors.push(
+                "network.runs_behind_reverse_proxy is set, but network.reverse_proxy_trusted_networks is empty".into(),
+            );
+        }
+
+        if self.signed_urls.enabled && self.passkeys.backend.is_on() {
+            errors.push("signed_urls.enabled is set, but passkeys.backend is not off".into());
+        }
+
+        errors.extend(self.access_list.validation_errors());
+        errors.extend(self.greylist.validation_errors());
+        errors.extend(self.client_filter.validation_errors());
+        errors.extend(self.signed_urls.validation_errors());
+        errors.extend(self.auth.validation_errors());
+        errors.extend(self.status_codes.validation_errors());
+
+        errors
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, TomlConfig, Deserialize)]
@@ -76,6 +281,23 @@ pub struct NetworkConfig {
     pub only_ipv6: bool,
     /// Maximum number of pending TCP connections
     pub tcp_backlog: i32,
+    /// Raise the open file limit (RLIMIT_NOFILE) to at least this value on
+    /// startup. Use 0 to leave it unchanged.
+    ///
+    /// Each connection uses a file descriptor, so this is effectively the
+    /// maximum number of concurrent connections. Raising the hard limit
+    /// requires root or CAP_SYS_RESOURCE, otherwise the soft limit is raised
+    /// to the hard limit and a warning is logged.
+    pub open_file_limit: u64,
+    /// Use socket passed by systemd (socket activation) instead of creating
+    /// one and binding it to `address`
+    ///
+    /// The socket is shared by all socket workers and must already be in
+    /// listening mode.
+    ///
+    /// If several sockets are passed, the one with `FileDescriptorName=http`
+    /// in the systemd socket unit is used.
+    pub systemd_socket_activation: bool,
     /// Enable TLS
     ///
     /// The TLS files are read on start and when the program receives `SIGUSR1`.
@@ -88,15 +310,49 @@ pub struct NetworkConfig {
     pub tls_certificate_path: PathBuf,
     /// Path to TLS private key (DER-encoded ASN.1 in PKCS#8 or PKCS#1 format)
     pub tls_private_key_path: PathBuf,
+    /// Check TLS certificate and private key files for changes this often
+    /// (seconds), and reload them if they have changed. 0 = disabled
+    ///
+    /// Useful for picking up automatically renewed certificates. Files are
+    /// reloaded as with `SIGUSR1`, so existing connections are kept open.
+    pub tls_reload_interval: u64,
+    /// Offer HTTP/2 to clients during TLS handshake (ALPN). Requires
+    /// enable_tls
+    ///
+    /// Requests on HTTP/2 connections are handled like HTTP/1.1 requests,
+    /// but several of them can be in flight on the same connection at once.
+    /// Clients not supporting HTTP/2 keep using HTTP/1.1.
+    pub enable_http2: bool,
+    /// Maximum number of concurrent requests (streams) per HTTP/2
+    /// connection
+    pub http2_max_concurrent_streams: u32,
     /// Keep connections alive after sending a response
+    ///
+    /// Connections are still closed if the client sends `Connection: close`
+    /// (or uses HTTP/1.0 without `Connection: keep-alive`), after failure
+    /// responses and when `max_requests_per_connection` is reached.
     pub keep_alive: bool,
+    /// Close kept-alive connections after serving this many requests.
+    /// 0 = unlimited
+    pub max_requests_per_connection: usize,
+    /// Close kept-alive connections if no further request is received
+    /// within this many seconds after sending a response. 0 = only close
+    /// them when `cleaning.max_connection_idle` has passed
+    pub keep_alive_timeout: u64,
+    /// Compress announce, scrape and failure responses of at least this
+    /// many bytes with gzip if the client sends `Accept-Encoding: gzip`.
+    /// 0 = disabled
+    ///
+    /// Mostly useful for scrape responses with many info hashes. Full scrape
+    /// responses and static routes are never compressed.
+    pub gzip_min_response_size: usize,
     /// Does tracker run behind reverse proxy?
     ///
     /// MUST be set to false if not running behind reverse proxy.
     ///
-    /// If set to true, make sure that reverse_proxy_ip_header_name and
-    /// reverse_proxy_ip_header_format are set to match your reverse proxy
-    /// setup.
+    /// If set to true, make sure that reverse_proxy_ip_header_name,
+    /// reverse_proxy_ip_header_format and reverse_proxy_trusted_networks are
+    /// set to match your reverse proxy setup.
     ///
     /// More info on what can go wrong when running behind reverse proxies:
     /// https://adam-p.ca/blog/2022/03/x-forwarded-for/
@@ -110,6 +366,29 @@ pub struct NetworkConfig {
     ///   header. Works with typical multi-IP setups (e.g., "X-Forwarded-For")
     ///   as well as for single-IP setups (e.g., nginx "X-Real-IP")
     pub reverse_proxy_ip_header_format: ReverseProxyPeerIpHeaderFormat,
+    /// Networks in CIDR notation (e.g., "10.0.0.0/8") of reverse proxies
+    ///
+    /// The peer IP header is only used for connections from these networks.
+    /// For other connections, the connection source address is used, so
+    /// that peers connecting directly can't send arbitrary addresses.
+    pub reverse_proxy_trusted_networks: Vec<String>,
+    /// When to use peer IP addresses sent by clients in announce requests
+    /// (`ip`, `ipv4` and `ipv6` query parameters) instead of the connection
+    /// source address
+    ///
+    /// Needed if peers connect through NAT local to the tracker. The `ip`
+    /// parameter is preferred, followed by the parameter matching the IP
+    /// version of the source address.
+    ///
+    /// Options:
+    /// - never
+    /// - trusted_networks: only if source address is in
+    ///   peer_ip_override_trusted_networks
+    /// - always
+    pub peer_ip_override_policy: PeerIpOverridePolicy,
+    /// Networks in CIDR notation (e.g., "10.0.0.0/8") allowed to send peer IP
+    /// addresses when peer_ip_override_policy is set to trusted_networks
+    pub peer_ip_override_trusted_networks: Vec<String>,
 }
 
 impl Default for NetworkConfig {
@@ -119,12 +398,28 @@ impl Default for NetworkConfig {
             enable_tls: false,
             tls_certificate_path: "".into(),
             tls_private_key_path: "".into(),
+            tls_reload_interval: 0,
+            enable_http2: false,
+            http2_max_concurrent_streams: 100,
             only_ipv6: false,
             tcp_backlog: 1024,
+            open_file_limit: 0,
+            systemd_socket_activation: false,
             keep_alive: true,
+            max_requests_per_connection: 0,
+            keep_alive_timeout: 0,
+            gzip_min_response_size: 0,
             runs_behind_reverse_proxy: false,
             reverse_proxy_ip_header_name: "X-Forwarded-For".into(),
             reverse_proxy_ip_header_format: Default::default(),
+            reverse_proxy_trusted_networks: vec!["127.0.0.0/8".into(), "::1/128".into()],
+            peer_ip_override_policy: Default::default(),
+            peer_ip_override_trusted_networks: vec![
+                "10.0.0.0/8".into(),
+                "172.16.0.0/12".into(),
+                "192.168.0.0/16".into(),
+                "fc00::/7".into(),
+            ],
         }
     }
 }
@@ -134,10 +429,89 @@ impl Default for NetworkConfig {
 pub struct ProtocolConfig {
     /// Maximum number of torrents to accept in scrape request
     pub max_scrape_torrents: usize,
-    /// Maximum number of requested peers to accept in announce request
+    /// Maximum number of peers to return in announce response
+    ///
+    /// Peers asking for fewer (with the `numwant` query parameter) get
+    /// fewer. Peers not specifying a number get this many. The requesting
+    /// peer is never included.
     pub max_peers: usize,
     /// Ask peers to announce this often (seconds)
     pub peer_announce_interval: usize,
+    /// Randomize announce interval in each response by up to this many
+    /// percent in either direction, to prevent peers from announcing in sync
+    /// (e.g., after a restart). 0 = disabled
+    pub peer_announce_interval_jitter: u8,
+    /// Send failure responses to announce requests without an event from
+    /// stored peers that announced less than this many seconds ago.
+    /// 0 = disabled
+    ///
+    /// Protects the tracker from clients announcing too aggressively.
+    /// Requests with events (started, stopped, completed) are never rejected.
+    /// Connections are closed after sending failure responses.
+    pub min_announce_interval: u32,
+    /// Send failure responses to announce requests with event `stopped` or
+    /// `completed` from peers that are not stored as having announced with
+    /// event `started`
+    ///
+    /// Useful on private trackers that credit uploads and completions to
+    /// users, since it makes forged events from peers that never joined the
+    /// swarm harder to pass off. Peers stored before a tracker restart need
+    /// to announce `started` again. Combine with `peer_sessions` to match
+    /// peers whose address changed.
+    pub require_started_event: bool,
+    /// Maximum number of announce requests to pass on to swarm workers per
+    /// second, across all socket workers. 0 = unlimited
+    ///
+    /// Further announce requests get responses without peers asking clients
+    /// to announce again after `overloaded_announce_interval` seconds. This
+    /// keeps swarm workers responsive during flash crowds. Shed requests are
+    /// counted in the `aquatic_shed_announces_total` metric.
+    pub max_announces_per_second: u64,
+    /// Ask peers to announce this often (seconds) when
+    /// `max_announces_per_second` is exceeded
+    pub overloaded_announce_interval: usize,
+    /// Also return peers that announced over the other IP version (IPv6
+    /// peers in `peers6` to IPv4 peers and vice versa), up to `max_peers` of
+    /// each
+    ///
+    /// Useful if many peers are dual-stack but only announce over one IP
+    /// version. Seeder and leecher counts only include peers of the
+    /// requesting peer's IP version.
+    pub include_other_ip_version_peers: bool,
+    /// Send failure responses to announce requests asking for non-compact
+    /// peer lists (`compact=0`)
+    ///
+    /// Non-compact peer lists take several times more space, so this keeps
+    /// response sizes bounded. Peer IDs are never included in non-compact
+    /// peer lists, regardless of this setting.
+    pub compact_only: bool,
+    /// Send a tracker id in announce responses and remember the `key`
+    /// parameter of peers
+    ///
+    /// Announce requests with event `stopped` or `completed` that arrive
+    /// from an unknown address (e.g., because the peer's IP or port changed)
+    /// are then applied to the stored peer that was issued the same tracker
+    /// id (`trackerid` parameter) or that sent the same key, instead of
+    /// leaving it to linger until it is cleaned. Only peers of the same IP
+    /// version are matched.
+    pub peer_sessions: bool,
+    /// Answer scrape requests without info hashes (`/scrape`) with
+    /// statistics on all torrents
+    ///
+    /// Responses are streamed from snapshots that swarm workers update
+    /// periodically, so they may be up to `full_scrape_update_interval`
+    /// seconds out of date. When disabled, such requests get failure
+    /// responses.
+    pub enable_full_scrape: bool,
+    /// Update full scrape snapshots this often (seconds)
+    pub full_scrape_update_interval: u64,
+    /// Include the IP address of the requesting peer as seen by the tracker
+    /// in announce responses (`external ip`, BEP 24)
+    ///
+    /// Lets clients behind NAT learn their public address. When running
+    /// behind a reverse proxy, the address from the proxy's IP header is
+    /// sent. IP addresses passed in request parameters are ignored.
+    pub send_external_ip: bool,
 }
 
 impl Default for ProtocolConfig {
@@ -146,6 +520,17 @@ impl Default for ProtocolConfig {
             max_scrape_torrents: 100,
             max_peers: 50,
             peer_announce_interval: 120,
+            peer_announce_interval_jitter: 0,
+            min_announce_interval: 0,
+            require_started_event: false,
+            max_announces_per_second: 0,
+            overloaded_announce_interval: 600,
+            include_other_ip_version_peers: false,
+            compact_only: false,
+            peer_sessions: false,
+            enable_full_scrape: false,
+            full_scrape_update_interval: 60,
+            send_external_ip: false,
         }
     }
 }
@@ -161,6 +546,14 @@ pub struct CleaningConfig {
     pub max_peer_age: u32,
     /// Remove connections that haven't seen valid requests for this long (seconds)
     pub max_connection_idle: u32,
+    /// Maximum number of torrents per IP version and swarm worker to
+    /// remember the number of completed downloads for after they are
+    /// removed because they have no peers left
+    ///
+    /// The count is reported in scrape responses and restored if peers
+    /// announce to the torrent again. Counts of torrents removed when the
+    /// limit has been reached are lost. 0 = don't remember any.
+    pub max_removed_torrent_completed_counts: usize,
 }
 
 impl Default for CleaningConfig {
@@ -170,6 +563,215 @@ impl Default for CleaningConfig {
             connection_cleaning_interval: 60,
             max_peer_age: 1800,
             max_connection_idle: 180,
+            max_removed_torrent_completed_counts: 100_000,
+        }
+    }
+}
+
+/// Passkey backend. Available backends are off and file.
+///
+/// To check passkeys against a database or another service, turn this off
+/// and use the http_callback authentication backend, which gets the passkey
+/// of each announce request.
+#[derive(Clone, Copy, Debug, PartialEq, TomlConfig, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PasskeyBackend {
+    /// Only serve requests with passkey present in file
+    File,
+    /// Don't require passkeys. Passkeys sent by clients are ignored.
+    Off,
+}
+
+impl PasskeyBackend {
+    pub fn is_on(&self) -> bool {
+        !matches!(self, Self::Off)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, TomlConfig, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PasskeyConfig {
+    pub backend: PasskeyBackend,
+    /// Path to passkey file consisting of newline-separated passkeys
+    ///
+    /// If using chroot mode, path must be relative to new root.
+    pub path: PathBuf,
+}
+
+impl Default for PasskeyConfig {
+    fn default() -> Self {
+        Self {
+            backend: PasskeyBackend::Off,
+            path: "./passkeys.txt".into(),
+        }
+    }
+}
+
+/// Announce authentication backend. Available backends are none,
+/// static_tokens and http_callback.
+#[derive(Clone, Copy, Debug, PartialEq, TomlConfig, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthBackend {
+    /// Serve all announce requests
+    None,
+    /// Only serve announce requests with passkey present in `tokens`
+    StaticTokens,
+    /// Ask HTTP server at `callback_url` about each announce request
+    HttpCallback,
+}
+
+#[derive(Clone, Debug, PartialEq, TomlConfig, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub backend: AuthBackend,
+    /// Tokens accepted by static_tokens backend
+    pub tokens: Vec<String>,
+    /// URL of callback used by http_callback backend
+    ///
+    /// For each announce request, a POST request with JSON body
+    /// `{"peer_id": "<hex>", "info_hash": "<hex>", "passkey": "<passkey>"
+    /// or null, "ip": "<address>"}` is sent to it. The server answers with
+    /// status 200 and JSON body `{"allow": <bool>, "message": "<failure
+    /// reason>", "interval": <seconds>}`, where `message` and `interval`
+    /// are optional. An `interval` of 0 is ignored and shorter intervals
+    /// than `protocol.min_announce_interval` are raised to it.
+    ///
+    /// If using chroot mode, resolving the host name might require files
+    /// such as `/etc/resolv.conf` to be present in new root.
+    pub callback_url: String,
+    /// Callback request timeout (milliseconds)
+    pub callback_timeout_ms: u64,
+    /// Number of threads per socket worker making callback requests.
+    /// Callbacks block these threads, so this limits the number of
+    /// concurrent callback requests.
+    pub callback_threads: usize,
+    /// Serve announce requests if callback request fails or response
+    /// can't be parsed. Otherwise, they are denied.
+    pub callback_allow_on_error: bool,
+}
+
+impl AuthConfig {
+    fn validation_errors(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        match self.backend {
+            AuthBackend::None => (),
+            AuthBackend::StaticTokens => {
+                if self.tokens.is_empty() {
+                    errors.push("auth.backend is static_tokens, but auth.tokens is empty".into());
+                }
+            }
+            AuthBackend::HttpCallback => {
+                if !(self.callback_url.starts_with("https://")
+                    || self.callback_url.starts_with("http://"))
+                {
+                    errors.push("auth.callback_url must start with https:// or http://".into());
+                }
+                if self.callback_timeout_ms == 0 {
+                    errors.push("auth.callback_timeout_ms must be greater than 0".into());
+                }
+                if self.callback_threads == 0 {
+                    errors.push("auth.callback_threads must be greater than 0".into());
+                }
+            }
+        }
+
+        errors
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            backend: AuthBackend::None,
+            tokens: Vec::new(),
+            callback_url: "".into(),
+            callback_timeout_ms: 1000,
+            callback_threads: 4,
+            callback_allow_on_error: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, TomlConfig, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StaticRoutesConfig {
+    /// Serve contents of this file as HTML landing page at `/`. Leave empty
+    /// to disable
+    pub index_file: PathBuf,
+    /// Serve contents of this file at `/robots.txt`. Leave empty to disable
+    pub robots_txt_file: PathBuf,
+    /// Answer requests to `/healthz` with `200 OK`, without caching
+    pub health_check: bool,
+    /// Allow clients and proxies to cache the landing page and robots.txt
+    /// for this long (seconds)
+    pub cache_max_age: u64,
+}
+
+impl Default for StaticRoutesConfig {
+    fn default() -> Self {
+        Self {
+            index_file: "".into(),
+            robots_txt_file: "".into(),
+            health_check: false,
+            cache_max_age: 3600,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, TomlConfig, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StatusCodesConfig {
+    /// Requests with methods other than GET. Sent without body.
+    pub method_not_allowed: u16,
+    /// Requests for paths other than announce, scrape and enabled static
+    /// routes. Sent without body.
+    pub not_found: u16,
+    /// Announce and scrape requests that can't be parsed. Sent with
+    /// bencoded failure reason.
+    pub invalid_request: u16,
+    /// Requests with missing or invalid passkeys or signed announce URLs.
+    /// Sent with bencoded failure reason.
+    pub invalid_passkey: u16,
+    /// Other rejected requests, e.g., from clients or for info hashes that
+    /// aren't allowed. Sent with bencoded failure reason. Many clients
+    /// only display the failure reason if status is 200.
+    pub request_rejected: u16,
+}
+
+impl StatusCodesConfig {
+    fn validation_errors(&self) -> Vec<String> {
+        [
+            ("method_not_allowed", self.method_not_allowed),
+            ("not_found", self.not_found),
+            ("invalid_request", self.invalid_request),
+            ("invalid_passkey", self.invalid_passkey),
+            ("request_rejected", self.request_rejected),
+        ]
+        .into_iter()
+        .filter(|(_, status)| {
+            let allows_body = (200..=599).contains(status) && ![204, 205, 304].contains(status);
+
+            *status != 0 && !allows_body
+        })
+        .map(|(key, status)| {
+            format!(
+                "status_codes.{} ({}) must be 0 or a status between 200 and 599 that allows a response body",
+                key, status
+            )
+        })
+        .collect()
+    }
+}
+
+impl Default for StatusCodesConfig {
+    fn default() -> Self {
+        Self {
+            method_not_allowed: 405,
+            not_found: 404,
+            invalid_request: 400,
+            invalid_passkey: 200,
+            request_rejected: 200,
         }
     }
 }
@@ -199,7 +801,83 @@ impl Default for MetricsConfig {
 
 #[cfg(test)]
 mod tests {
-    use super::Config;
+    use aquatic_common::cli::Config as _;
+
+    use super::{AuthBackend, Config, PasskeyBackend};
 
     ::aquatic_toml_config::gen_serialize_deserialize_test!(Config);
+
+    #[test]
+    fn test_default_config_is_valid() {
+        assert_eq!(Config::default().validation_errors(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_http2_requires_tls() {
+        let mut config = Config::default();
+
+        config.network.enable_http2 = true;
+
+        assert_eq!(config.validation_errors().len(), 1);
+
+        config.network.enable_tls = true;
+        config.network.tls_certificate_path = "cert.pem".into();
+        config.network.tls_private_key_path = "key.pem".into();
+
+        assert_eq!(config.validation_errors(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_status_codes() {
+        let mut config = Config::default();
+
+        config.status_codes.not_found = 0;
+        config.status_codes.invalid_passkey = 403;
+
+        assert_eq!(config.validation_errors(), Vec::<String>::new());
+
+        config.status_codes.not_found = 100;
+        config.status_codes.invalid_request = 304;
+        config.status_codes.request_rejected = 600;
+
+        assert_eq!(config.validation_errors().len(), 3);
+    }
+
+    #[test]
+    fn test_auth() {
+        let mut config = Config::default();
+
+        config.auth.backend = AuthBackend::StaticTokens;
+
+        assert_eq!(config.validation_errors().len(), 1);
+
+        config.auth.tokens.push("0123abcd".into());
+
+        assert_eq!(config.validation_errors(), Vec::<String>::new());
+
+        config.auth.backend = AuthBackend::HttpCallback;
+        config.auth.callback_threads = 0;
+
+        assert_eq!(config.validation_errors().len(), 2);
+
+        config.auth.callback_url = "http://127.0.0.1:8080/auth".into();
+        config.auth.callback_threads = 1;
+
+        assert_eq!(config.validation_errors(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_signed_urls() {
+        let mut config = Config::default();
+
+        config.signed_urls.enabled = true;
+        config.passkeys.backend = PasskeyBackend::File;
+
+        assert_eq!(config.validation_errors().len(), 2);
+
+        config.signed_urls.secret = "0123456789abcdef0123456789abcdef".into();
+        config.passkeys.backend = PasskeyBackend::Off;
+
+        assert_eq!(config.validation_errors(), Vec::<String>::new());
+    }
 }
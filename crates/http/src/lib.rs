@@ -1,22 +1,36 @@
 use anyhow::Context;
+#[cfg(feature = "cpu-pinning")]
+use aquatic_common::cpu_pinning::{pin_current_if_configured_to, WorkerIndex};
 use aquatic_common::{
-    access_list::update_access_list, privileges::PrivilegeDropper,
-    rustls_config::create_rustls_config, ServerStartInstant, WorkerType,
+    access_list::{spawn_access_list_sync_worker, update_access_list},
+    ban_list::update_ban_list,
+    panic_message,
+    privileges::PrivilegeDropper,
+    rlimit::raise_open_file_limit,
+    rustls_config::RustlsConfigUpdater,
+    systemd::StatusNotifier,
+    ServerStartInstant, WorkerType,
 };
-use arc_swap::ArcSwap;
 use common::State;
 use glommio::{channels::channel_mesh::MeshBuilder, prelude::*};
-use signal_hook::{consts::SIGUSR1, iterator::Signals};
+use passkeys::update_passkeys;
+use signal_hook::{
+    consts::{SIGINT, SIGTERM, SIGUSR1},
+    iterator::Signals,
+};
 use std::{
     sync::Arc,
-    thread::{sleep, Builder, JoinHandle},
+    thread::{park_timeout, sleep, Builder, JoinHandle},
     time::Duration,
 };
 
-use crate::config::Config;
+use crate::config::{AuthBackend, Config};
 
+mod auth;
 mod common;
 pub mod config;
+mod passkeys;
+mod peer_ip;
 mod workers;
 
 pub const APP_NAME: &str = "aquatic_http: HTTP BitTorrent tracker";
@@ -25,11 +39,26 @@ pub const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
 const SHARED_CHANNEL_SIZE: usize = 1024;
 
 pub fn run(config: Config) -> ::anyhow::Result<()> {
-    let mut signals = Signals::new([SIGUSR1])?;
+    run_with_status_notifier(config, StatusNotifier::Systemd)
+}
+
+/// Run tracker, sending service status notifications through
+/// `status_notifier`
+pub fn run_with_status_notifier(
+    config: Config,
+    status_notifier: StatusNotifier,
+) -> ::anyhow::Result<()> {
+    if config.network.open_file_limit != 0 {
+        raise_open_file_limit(config.network.open_file_limit)?;
+    }
+
+    let mut signals = Signals::new([SIGUSR1, SIGTERM, SIGINT])?;
 
-    let state = State::default();
+    let state = State::new(&config);
 
     update_access_list(&config.access_list, &state.access_list)?;
+    update_ban_list(&config.ban_list, &state.ban_list)?;
+    update_passkeys(&config.passkeys, &state.passkeys)?;
 
     let request_mesh_builder = MeshBuilder::partial(
         config.socket_workers + config.swarm_workers,
@@ -37,14 +66,27 @@ pub fn run(config: Config) -> ::anyhow::Result<()> {
     );
     let priv_dropper = PrivilegeDropper::new(config.privileges.clone(), config.socket_workers);
 
-    let opt_tls_config = if config.network.enable_tls {
-        Some(Arc::new(ArcSwap::from_pointee(create_rustls_config(
-            &config.network.tls_certificate_path,
-            &config.network.tls_private_key_path,
-        )?)))
+    let opt_tls_updater = if config.network.enable_tls {
+        let alpn_protocols = if config.network.enable_http2 {
+            vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+        } else {
+            Vec::new()
+        };
+
+        Some(Arc::new(
+            RustlsConfigUpdater::new(
+                &config.network.tls_certificate_path,
+                &config.network.tls_private_key_path,
+                alpn_protocols,
+            )
+            .with_context(|| "create rustls config")?,
+        ))
     } else {
         None
     };
+    let opt_tls_config = opt_tls_updater
+        .as_ref()
+        .map(|updater| updater.tls_config.clone());
 
     let server_start_instant = ServerStartInstant::new();
 
@@ -60,7 +102,25 @@ pub fn run(config: Config) -> ::anyhow::Result<()> {
         let handle = Builder::new()
             .name(format!("socket-{:02}", i + 1))
             .spawn(move || {
-                LocalExecutorBuilder::default()
+                #[cfg(feature = "cpu-pinning")]
+                pin_current_if_configured_to(
+                    &config.cpu_pinning,
+                    config.socket_workers,
+                    config.swarm_workers,
+                    WorkerIndex::SocketWorker(i),
+                );
+
+                let mut executor_builder = LocalExecutorBuilder::default();
+
+                // Authentication callback requests are made on the blocking
+                // thread pool
+                if config.auth.backend == AuthBackend::HttpCallback {
+                    executor_builder = executor_builder.blocking_thread_pool_placement(
+                        PoolPlacement::Unbound(config.auth.callback_threads),
+                    );
+                }
+
+                executor_builder
                     .make()
                     .map_err(|err| anyhow::anyhow!("Spawning executor failed: {:#}", err))?
                     .run(workers::socket::run_socket_worker(
@@ -86,6 +146,14 @@ pub fn run(config: Config) -> ::anyhow::Result<()> {
         let handle = Builder::new()
             .name(format!("swarm-{:02}", i + 1))
             .spawn(move || {
+                #[cfg(feature = "cpu-pinning")]
+                pin_current_if_configured_to(
+                    &config.cpu_pinning,
+                    config.socket_workers,
+                    config.swarm_workers,
+                    WorkerIndex::SwarmWorker(i),
+                );
+
                 LocalExecutorBuilder::default()
                     .make()
                     .map_err(|err| anyhow::anyhow!("Spawning executor failed: {:#}", err))?
@@ -120,8 +188,41 @@ pub fn run(config: Config) -> ::anyhow::Result<()> {
         join_handles.push((WorkerType::Prometheus, handle));
     }
 
+    // Spawn TLS reload thread
+    if let Some(tls_updater) = opt_tls_updater.clone() {
+        if config.network.tls_reload_interval != 0 {
+            let interval = Duration::from_secs(config.network.tls_reload_interval);
+
+            let handle = Builder::new()
+                .name("tls-reload".into())
+                .spawn(move || loop {
+                    sleep(interval);
+
+                    match tls_updater.update() {
+                        Ok(true) => {
+                            ::log::info!("successfully updated tls config after files changed")
+                        }
+                        Ok(false) => (),
+                        Err(err) => ::log::error!("could not update tls config: {:#}", err),
+                    }
+                })
+                .context("spawn tls reload worker")?;
+
+            join_handles.push((WorkerType::TlsReload, handle));
+        }
+    }
+
+    // Spawn access list sync thread
+    if config.access_list.sync_active() {
+        let handle = spawn_access_list_sync_worker(&config.access_list, state.access_list.clone())?;
+
+        join_handles.push((WorkerType::AccessListSync, handle));
+    }
+
     // Spawn signal handler thread
     {
+        let main_thread = ::std::thread::current();
+
         let handle: JoinHandle<anyhow::Result<()>> = Builder::new()
             .name("signals".into())
             .spawn(move || {
@@ -129,23 +230,26 @@ pub fn run(config: Config) -> ::anyhow::Result<()> {
                     match signal {
                         SIGUSR1 => {
                             let _ = update_access_list(&config.access_list, &state.access_list);
+                            let _ = update_ban_list(&config.ban_list, &state.ban_list);
+                            let _ = update_passkeys(&config.passkeys, &state.passkeys);
 
-                            if let Some(tls_config) = opt_tls_config.as_ref() {
-                                match create_rustls_config(
-                                    &config.network.tls_certificate_path,
-                                    &config.network.tls_private_key_path,
-                                ) {
-                                    Ok(config) => {
-                                        tls_config.store(Arc::new(config));
-
-                                        ::log::info!("successfully updated tls config");
-                                    }
-                                    Err(err) => {
-                                        ::log::error!("could not update tls config: {:#}", err)
-                                    }
+                            if let Some(tls_updater) = opt_tls_updater.as_ref() {
+                                match tls_updater.update() {
+                                    Ok(true) => ::log::info!("successfully updated tls config"),
+                                    Ok(false) => ::log::info!("skipping tls config update: certificate and private key identical to currently loaded"),
+                                    Err(err) => ::log::error!("could not update tls config: {:#}", err),
                                 }
                             }
                         }
+                        SIGTERM | SIGINT => {
+                            ::log::info!("shutting down");
+
+                            // Let main thread notice that this thread has
+                            // finished
+                            main_thread.unpark();
+
+                            break;
+                        }
                         _ => unreachable!(),
                     }
                 }
@@ -157,25 +261,54 @@ pub fn run(config: Config) -> ::anyhow::Result<()> {
         join_handles.push((WorkerType::Signals, handle));
     }
 
+    // Only report readiness once all sockets have been created and
+    // privileges have been dropped
+    let ready = priv_dropper
+        .wait_until_sockets_ready(|| join_handles.iter().any(|(_, handle)| handle.is_finished()));
+
+    if ready {
+        status_notifier.ready();
+    }
+
+    let opt_watchdog_interval = status_notifier.watchdog_interval();
+    let sleep_duration = opt_watchdog_interval
+        .unwrap_or(Duration::MAX)
+        .min(Duration::from_secs(5));
+
     loop {
         for (i, (_, handle)) in join_handles.iter().enumerate() {
             if handle.is_finished() {
                 let (worker_type, handle) = join_handles.remove(i);
 
+                status_notifier.stopping();
+
                 match handle.join() {
+                    Ok(Ok(())) if matches!(worker_type, WorkerType::Signals) => {
+                        ::log::info!("shut down");
+
+                        return Ok(());
+                    }
                     Ok(Ok(())) => {
                         return Err(anyhow::anyhow!("{} stopped", worker_type));
                     }
                     Ok(Err(err)) => {
                         return Err(err.context(format!("{} stopped", worker_type)));
                     }
-                    Err(_) => {
-                        return Err(anyhow::anyhow!("{} panicked", worker_type));
+                    Err(payload) => {
+                        return Err(anyhow::anyhow!(
+                            "{} panicked: {}",
+                            worker_type,
+                            panic_message(payload.as_ref())
+                        ));
                     }
                 }
             }
         }
 
-        sleep(Duration::from_secs(5));
+        if opt_watchdog_interval.is_some() {
+            status_notifier.watchdog();
+        }
+
+        park_timeout(sleep_duration);
     }
 }
@@ -0,0 +1,101 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Context;
+use arc_swap::{ArcSwap, Cache};
+
+use crate::config::{PasskeyBackend, PasskeyConfig};
+
+/// Passkeys allowed to make requests
+#[derive(Default, Clone)]
+pub struct Passkeys(HashSet<String>);
+
+impl Passkeys {
+    pub fn create_from_path(path: &Path) -> anyhow::Result<Self> {
+        let file = File::open(path)?;
+        let reader = BufReader::new(file);
+
+        let mut passkeys = Self::default();
+
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            passkeys.0.insert(line.to_owned());
+        }
+
+        Ok(passkeys)
+    }
+
+    pub fn allows(&self, backend: PasskeyBackend, opt_passkey: Option<&str>) -> bool {
+        match backend {
+            PasskeyBackend::File => opt_passkey.is_some_and(|passkey| self.0.contains(passkey)),
+            PasskeyBackend::Off => true,
+        }
+    }
+
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+pub type PasskeysArcSwap = ArcSwap<Passkeys>;
+pub type PasskeysCache = Cache<Arc<PasskeysArcSwap>, Arc<Passkeys>>;
+
+pub fn create_passkeys_cache(arc_swap: &Arc<PasskeysArcSwap>) -> PasskeysCache {
+    Cache::from(Arc::clone(arc_swap))
+}
+
+pub fn update_passkeys(
+    config: &PasskeyConfig,
+    passkeys: &Arc<PasskeysArcSwap>,
+) -> anyhow::Result<()> {
+    match config.backend {
+        PasskeyBackend::File => {
+            match Passkeys::create_from_path(&config.path)
+                .with_context(|| format!("read passkey file {}", config.path.display()))
+            {
+                Ok(new_passkeys) => {
+                    ::log::info!("Passkeys updated ({} passkeys)", new_passkeys.len());
+
+                    passkeys.store(Arc::new(new_passkeys));
+                }
+                Err(err) => {
+                    ::log::error!("Updating passkeys failed: {:#}", err);
+
+                    return Err(err);
+                }
+            }
+        }
+        PasskeyBackend::Off => (),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_passkeys_allows() {
+        let mut passkeys = Passkeys::default();
+
+        passkeys.0.insert("0123abcd".into());
+
+        assert!(passkeys.allows(PasskeyBackend::File, Some("0123abcd")));
+        assert!(!passkeys.allows(PasskeyBackend::File, Some("0123abce")));
+        assert!(!passkeys.allows(PasskeyBackend::File, None));
+
+        assert!(passkeys.allows(PasskeyBackend::Off, Some("0123abce")));
+        assert!(passkeys.allows(PasskeyBackend::Off, None));
+    }
+}
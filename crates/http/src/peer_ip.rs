@@ -0,0 +1,136 @@
+use std::net::{IpAddr, SocketAddr};
+
+use aquatic_common::ip_network::IpNetworks;
+use aquatic_common::CanonicalSocketAddr;
+use aquatic_http_protocol::request::AnnounceRequest;
+
+use crate::config::{Config, PeerIpOverridePolicy};
+
+/// Decides which peer address to use for announce requests, based on
+/// `network.peer_ip_override_policy`
+pub struct PeerIpOverride {
+    policy: PeerIpOverridePolicy,
+    trusted_networks: IpNetworks,
+}
+
+impl PeerIpOverride {
+    pub fn new(config: &Config) -> anyhow::Result<Self> {
+        let trusted_networks =
+            IpNetworks::parse(&config.network.peer_ip_override_trusted_networks)?;
+
+        Ok(Self {
+            policy: config.network.peer_ip_override_policy,
+            trusted_networks,
+        })
+    }
+
+    /// Return peer address with IP address sent by client, if allowed
+    pub fn apply(
+        &self,
+        peer_addr: CanonicalSocketAddr,
+        request: &AnnounceRequest,
+    ) -> CanonicalSocketAddr {
+        let source = peer_addr.get();
+
+        let trusted = match self.policy {
+            PeerIpOverridePolicy::Never => false,
+            PeerIpOverridePolicy::TrustedNetworks => self.trusted_networks.contains(source.ip()),
+            PeerIpOverridePolicy::Always => true,
+        };
+
+        if !trusted {
+            return peer_addr;
+        }
+
+        let ipv4 = request.ipv4.map(IpAddr::V4);
+        let ipv6 = request.ipv6.map(IpAddr::V6);
+
+        let opt_ip = if source.is_ipv4() {
+            request.ip.or(ipv4).or(ipv6)
+        } else {
+            request.ip.or(ipv6).or(ipv4)
+        };
+
+        match opt_ip {
+            Some(ip) => CanonicalSocketAddr::new(SocketAddr::new(ip, source.port())),
+            None => peer_addr,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use aquatic_http_protocol::common::{AnnounceEvent, InfoHash, PeerId};
+
+    use super::*;
+
+    #[test]
+    fn test_peer_ip_override() {
+        let request = AnnounceRequest {
+            info_hash: InfoHash([0; 20]),
+            peer_id: PeerId([0; 20]),
+            port: 1000,
+            bytes_uploaded: 0,
+            bytes_downloaded: 0,
+            bytes_left: 0,
+            event: AnnounceEvent::Started,
+            numwant: None,
+            key: None,
+            tracker_id: None,
+            compact: true,
+            no_peer_id: false,
+            passkey: None,
+            ip: None,
+            ipv4: Some(Ipv4Addr::new(1, 2, 3, 4)),
+            ipv6: Some(Ipv6Addr::LOCALHOST),
+        };
+
+        let private_addr = CanonicalSocketAddr::new(SocketAddr::from(([10, 0, 0, 1], 2000)));
+        let public_addr = CanonicalSocketAddr::new(SocketAddr::from(([5, 6, 7, 8], 2000)));
+
+        let mut config = Config::default();
+
+        let apply = |config: &Config, peer_addr, request: &AnnounceRequest| {
+            PeerIpOverride::new(config)
+                .unwrap()
+                .apply(peer_addr, request)
+                .get()
+                .ip()
+        };
+
+        assert_eq!(
+            apply(&config, private_addr, &request),
+            IpAddr::from([10, 0, 0, 1])
+        );
+
+        config.network.peer_ip_override_policy = PeerIpOverridePolicy::TrustedNetworks;
+
+        assert_eq!(
+            apply(&config, private_addr, &request),
+            IpAddr::from([1, 2, 3, 4])
+        );
+        assert_eq!(
+            apply(&config, public_addr, &request),
+            IpAddr::from([5, 6, 7, 8])
+        );
+
+        config.network.peer_ip_override_policy = PeerIpOverridePolicy::Always;
+
+        assert_eq!(
+            apply(&config, public_addr, &request),
+            IpAddr::from([1, 2, 3, 4])
+        );
+
+        let request = AnnounceRequest {
+            ip: Some(IpAddr::from([9, 9, 9, 9])),
+            ..request
+        };
+
+        assert_eq!(
+            apply(&config, public_addr, &request),
+            IpAddr::from([9, 9, 9, 9])
+        );
+    }
+}
@@ -1,76 +1,133 @@
 use std::cell::RefCell;
-use std::collections::BTreeMap;
+use std::io::Write;
 use std::net::SocketAddr;
 use std::rc::Rc;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Context;
-use aquatic_common::access_list::{create_access_list_cache, AccessListArcSwap, AccessListCache};
+use aquatic_common::access_list::AccessListArcSwap;
+use aquatic_common::ban_list::BanListArcSwap;
+use aquatic_common::greylist::Greylist;
+use aquatic_common::ip_network::IpNetworks;
+use aquatic_common::load_shedding::AnnounceCeiling;
 use aquatic_common::rustls_config::RustlsConfig;
+use aquatic_common::signed_urls::SignedUrlVerifier;
 use aquatic_common::{CanonicalSocketAddr, ServerStartInstant};
-use aquatic_http_protocol::common::InfoHash;
-use aquatic_http_protocol::request::{Request, ScrapeRequest};
-use aquatic_http_protocol::response::{
-    FailureResponse, Response, ScrapeResponse, ScrapeStatistics,
-};
+use aquatic_http_protocol::request::Request;
+use aquatic_http_protocol::response::Response;
 use arc_swap::ArcSwap;
 use either::Either;
-use futures::stream::FuturesUnordered;
-use futures_lite::{AsyncReadExt, AsyncWriteExt, StreamExt};
+use futures_lite::future::race;
+use futures_lite::{AsyncReadExt, AsyncWriteExt};
 use futures_rustls::TlsAcceptor;
 use glommio::channels::channel_mesh::Senders;
-use glommio::channels::shared_channel::{self, SharedReceiver};
 use glommio::net::TcpStream;
 use once_cell::sync::Lazy;
 
+use crate::auth::AuthHook;
 use crate::common::*;
 use crate::config::Config;
+use crate::passkeys::PasskeysArcSwap;
+use crate::peer_ip::PeerIpOverride;
 
-#[cfg(feature = "metrics")]
-use super::peer_addr_to_ip_version_str;
-use super::request::{parse_request, RequestParseError};
+use super::full_scrape::FullScrapeBody;
+use super::gzip::compress_body;
+use super::handler::RequestHandler;
+use super::http2::{run_http2_connection, HTTP2_ALPN_PROTOCOL};
+use super::request::{parse_request, LimitExceeded, ParsedRequest, RequestParseError};
+use super::static_routes::{StaticResponse, StaticRoutes};
 
 const REQUEST_BUFFER_SIZE: usize = 2048;
 const RESPONSE_BUFFER_SIZE: usize = 4096;
+pub(super) const FULL_SCRAPE_CHUNK_SIZE: usize = 1024 * 16;
 
 const RESPONSE_HEADER_A: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: ";
 const RESPONSE_HEADER_B: &[u8] = b"        ";
-const RESPONSE_HEADER_C: &[u8] = b"\r\n\r\n";
-
-static RESPONSE_HEADER: Lazy<Vec<u8>> =
-    Lazy::new(|| [RESPONSE_HEADER_A, RESPONSE_HEADER_B, RESPONSE_HEADER_C].concat());
-
-struct PendingScrapeResponse {
-    pending_worker_responses: usize,
-    stats: BTreeMap<InfoHash, ScrapeStatistics>,
+const RESPONSE_HEADER_C: &[u8] = b"\r\nConnection: ";
+const RESPONSE_HEADER_D: &[u8] = b"          ";
+const RESPONSE_HEADER_E: &[u8] = b"\r\n\r\n";
+
+static RESPONSE_HEADER: Lazy<Vec<u8>> = Lazy::new(|| {
+    [
+        RESPONSE_HEADER_A,
+        RESPONSE_HEADER_B,
+        RESPONSE_HEADER_C,
+        RESPONSE_HEADER_D,
+        RESPONSE_HEADER_E,
+    ]
+    .concat()
+});
+
+/// Response sent without passing request on to swarm workers
+enum DirectResponse {
+    /// Send failure response
+    Failure(TrackerError),
+    /// Send bare HTTP error status and close connection
+    LimitExceeded(LimitExceeded),
+    /// Send bare configurable HTTP error status and close connection
+    Status(u16),
+    /// Send static route response, keeping connection alive if client
+    /// allows it
+    Static(StaticResponse, bool),
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum ConnectionError {
     #[error("inactive")]
     Inactive,
+    #[error("keep-alive timeout")]
+    KeepAliveTimeout,
     #[error("socket peer addr extraction failed")]
     NoSocketPeerAddr(String),
-    #[error("request buffer full")]
-    RequestBufferFull,
+    #[error("tls accept: {0}")]
+    TlsAccept(::std::io::Error),
+    #[error("invalid request")]
+    InvalidRequest,
+    #[error("peer address banned or greylisted")]
+    PeerRejected,
+    #[error("{0}")]
+    RequestLimitExceeded(LimitExceeded),
     #[error("response buffer full")]
     ResponseBufferFull,
     #[error("response buffer write error: {0}")]
     ResponseBufferWrite(::std::io::Error),
     #[error("peer closed")]
     PeerClosed,
+    #[error("closed without response")]
+    ClosedWithoutResponse,
     #[error("response sender closed")]
     ResponseSenderClosed,
     #[error("scrape channel error: {0}")]
     ScrapeChannelError(&'static str),
+    #[error("http/2 error: {0}")]
+    Http2(#[from] h2::Error),
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
 
+impl ConnectionError {
+    /// Returns true if error is caused by peer misbehaving and should count
+    /// towards greylisting its network prefix
+    pub fn is_greylist_failure(&self) -> bool {
+        matches!(self, Self::TlsAccept(_) | Self::InvalidRequest)
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 pub(super) async fn run_connection(
     config: Rc<Config>,
+    static_routes: Rc<StaticRoutes>,
     access_list: Arc<AccessListArcSwap>,
+    passkeys: Arc<PasskeysArcSwap>,
+    ban_list: Arc<BanListArcSwap>,
+    greylist: Rc<RefCell<Greylist>>,
+    full_scrape_snapshots: FullScrapeSnapshots,
+    announce_ceiling: Arc<AnnounceCeiling>,
+    peer_ip_override: Rc<PeerIpOverride>,
+    auth_hook: Rc<dyn AuthHook>,
+    opt_signed_url_verifier: Option<Rc<SignedUrlVerifier>>,
+    trusted_proxies: Rc<IpNetworks>,
     request_senders: Rc<Senders<ChannelRequest>>,
     server_start_instant: ServerStartInstant,
     opt_tls_config: Option<Arc<ArcSwap<RustlsConfig>>>,
@@ -78,97 +135,242 @@ pub(super) async fn run_connection(
     stream: TcpStream,
     worker_index: usize,
 ) -> Result<(), ConnectionError> {
-    let access_list_cache = create_access_list_cache(&access_list);
-    let request_buffer = Box::new([0u8; REQUEST_BUFFER_SIZE]);
-
-    let mut response_buffer = Box::new([0; RESPONSE_BUFFER_SIZE]);
-
-    response_buffer[..RESPONSE_HEADER.len()].copy_from_slice(&RESPONSE_HEADER);
-
     let remote_addr = stream
         .peer_addr()
         .map_err(|err| ConnectionError::NoSocketPeerAddr(err.to_string()))?;
 
-    let opt_peer_addr = if config.network.runs_behind_reverse_proxy {
-        None
-    } else {
-        Some(CanonicalSocketAddr::new(remote_addr))
-    };
-
-    let peer_port = remote_addr.port();
+    // Only trust peer IP headers if direct peer is a known reverse proxy
+    let peer_is_trusted_proxy = config.network.runs_behind_reverse_proxy
+        && trusted_proxies.contains(CanonicalSocketAddr::new(remote_addr).get().ip());
+
+    let handler = RequestHandler::new(
+        config.clone(),
+        &access_list,
+        &passkeys,
+        &ban_list,
+        greylist,
+        full_scrape_snapshots,
+        announce_ceiling,
+        peer_ip_override,
+        auth_hook,
+        opt_signed_url_verifier,
+        request_senders,
+        valid_until,
+        server_start_instant,
+        worker_index,
+    );
 
     if let Some(tls_config) = opt_tls_config {
         let tls_acceptor: TlsAcceptor = tls_config.load_full().into();
         let stream = tls_acceptor
             .accept(stream)
             .await
-            .with_context(|| "tls accept")?;
+            .map_err(ConnectionError::TlsAccept)?;
+
+        if stream.get_ref().1.alpn_protocol() == Some(HTTP2_ALPN_PROTOCOL) {
+            return run_http2_connection(
+                config,
+                static_routes,
+                handler,
+                stream,
+                remote_addr,
+                peer_is_trusted_proxy,
+            )
+            .await;
+        }
 
-        let mut conn = Connection {
+        let mut conn = Connection::new(
             config,
-            access_list_cache,
-            request_senders,
-            valid_until,
-            server_start_instant,
-            opt_peer_addr,
-            peer_port,
-            request_buffer,
-            request_buffer_position: 0,
-            response_buffer,
+            static_routes,
+            handler,
             stream,
-            worker_index_string: worker_index.to_string(),
-        };
+            remote_addr,
+            peer_is_trusted_proxy,
+        );
 
         conn.run().await
     } else {
-        let mut conn = Connection {
+        let mut conn = Connection::new(
             config,
-            access_list_cache,
-            request_senders,
-            valid_until,
-            server_start_instant,
-            opt_peer_addr,
-            peer_port,
-            request_buffer,
-            request_buffer_position: 0,
-            response_buffer,
+            static_routes,
+            handler,
             stream,
-            worker_index_string: worker_index.to_string(),
-        };
+            remote_addr,
+            peer_is_trusted_proxy,
+        );
 
         conn.run().await
     }
 }
 
+/// HTTP/1.x connection
 struct Connection<S> {
     config: Rc<Config>,
-    access_list_cache: AccessListCache,
-    request_senders: Rc<Senders<ChannelRequest>>,
-    valid_until: Rc<RefCell<ValidUntil>>,
-    server_start_instant: ServerStartInstant,
+    static_routes: Rc<StaticRoutes>,
+    handler: RequestHandler,
     opt_peer_addr: Option<CanonicalSocketAddr>,
+    peer_is_trusted_proxy: bool,
     peer_port: u16,
+    /// Set for each request
+    client_accepts_gzip: bool,
     request_buffer: Box<[u8; REQUEST_BUFFER_SIZE]>,
     request_buffer_position: usize,
     response_buffer: Box<[u8; RESPONSE_BUFFER_SIZE]>,
     stream: S,
-    worker_index_string: String,
+    num_requests_served: usize,
 }
 
 impl<S> Connection<S>
 where
     S: futures::AsyncRead + futures::AsyncWrite + Unpin + 'static,
 {
+    fn new(
+        config: Rc<Config>,
+        static_routes: Rc<StaticRoutes>,
+        handler: RequestHandler,
+        stream: S,
+        remote_addr: SocketAddr,
+        peer_is_trusted_proxy: bool,
+    ) -> Self {
+        let opt_peer_addr = if peer_is_trusted_proxy {
+            None
+        } else {
+            Some(CanonicalSocketAddr::new(remote_addr))
+        };
+
+        let mut response_buffer = Box::new([0; RESPONSE_BUFFER_SIZE]);
+
+        response_buffer[..RESPONSE_HEADER.len()].copy_from_slice(&RESPONSE_HEADER);
+
+        Self {
+            config,
+            static_routes,
+            handler,
+            opt_peer_addr,
+            peer_is_trusted_proxy,
+            peer_port: remote_addr.port(),
+            client_accepts_gzip: false,
+            request_buffer: Box::new([0u8; REQUEST_BUFFER_SIZE]),
+            request_buffer_position: 0,
+            response_buffer,
+            stream,
+            num_requests_served: 0,
+        }
+    }
+
     async fn run(&mut self) -> Result<(), ConnectionError> {
         loop {
-            let response = match self.read_request().await? {
-                Either::Left(response) => Response::Failure(response),
-                Either::Right(request) => self.handle_request(request).await?,
+            let (response, client_keep_alive) = match self.read_next_request().await? {
+                Either::Left(DirectResponse::Failure(err)) => (Err(err), false),
+                Either::Left(DirectResponse::LimitExceeded(limit)) => {
+                    self.stream
+                        .write_all(limit.response_bytes())
+                        .await
+                        .with_context(|| "write")?;
+                    self.stream.flush().await.with_context(|| "flush")?;
+
+                    return Err(ConnectionError::RequestLimitExceeded(limit));
+                }
+                Either::Left(DirectResponse::Status(status)) => {
+                    if status == 0 {
+                        return Err(ConnectionError::ClosedWithoutResponse);
+                    }
+
+                    let mut output = Vec::new();
+
+                    write_status_line(&mut output, status);
+
+                    if status == 405 {
+                        output.extend_from_slice(b"Allow: GET\r\n");
+                    }
+
+                    output.extend_from_slice(b"Content-Length: 0\r\nConnection: close\r\n\r\n");
+
+                    self.stream
+                        .write_all(&output)
+                        .await
+                        .with_context(|| "write")?;
+                    self.stream.flush().await.with_context(|| "flush")?;
+
+                    break;
+                }
+                Either::Left(DirectResponse::Static(response, client_keep_alive)) => {
+                    self.handler.update_valid_until();
+                    self.num_requests_served += 1;
+
+                    let keep_alive = client_keep_alive && self.keep_alive_allowed_after_success();
+
+                    let mut output = Vec::new();
+
+                    response.write_http1(&mut output, keep_alive);
+
+                    self.stream
+                        .write_all(&output)
+                        .await
+                        .with_context(|| "write")?;
+                    self.stream.flush().await.with_context(|| "flush")?;
+
+                    if keep_alive {
+                        continue;
+                    } else {
+                        break;
+                    }
+                }
+                Either::Right((Request::FullScrape(request), client_keep_alive)) => {
+                    match self
+                        .handler
+                        .handle_full_scrape_request(request, self.peer_addr())
+                    {
+                        Ok(body) => {
+                            self.num_requests_served += 1;
+
+                            let keep_alive =
+                                client_keep_alive && self.keep_alive_allowed_after_success();
+
+                            self.write_full_scrape_response(body, keep_alive).await?;
+
+                            if keep_alive {
+                                continue;
+                            } else {
+                                break;
+                            }
+                        }
+                        Err(err) => (Err(err), client_keep_alive),
+                    }
+                }
+                Either::Right((request, keep_alive)) => {
+                    let response = self
+                        .handler
+                        .handle_request(request, self.peer_addr())
+                        .await?;
+
+                    (response, keep_alive)
+                }
             };
 
-            self.write_response(&response).await?;
+            let invalid_request = matches!(response, Err(TrackerError::InvalidRequest));
+
+            let (response, status) = response_with_status(&self.config.status_codes, response);
 
-            if matches!(response, Response::Failure(_)) || !self.config.network.keep_alive {
+            if status == 0 {
+                return Err(if invalid_request {
+                    ConnectionError::InvalidRequest
+                } else {
+                    ConnectionError::ClosedWithoutResponse
+                });
+            }
+
+            self.num_requests_served += 1;
+
+            let keep_alive = client_keep_alive && self.keep_alive_allowed(&response);
+
+            self.write_response(&response, status, keep_alive).await?;
+
+            if invalid_request {
+                return Err(ConnectionError::InvalidRequest);
+            }
+
+            if !keep_alive {
                 break;
             }
         }
@@ -176,12 +378,52 @@ where
         Ok(())
     }
 
-    async fn read_request(&mut self) -> Result<Either<FailureResponse, Request>, ConnectionError> {
+    fn peer_addr(&self) -> CanonicalSocketAddr {
+        self.opt_peer_addr
+            .expect("peer addr should already have been extracted by now")
+    }
+
+    fn keep_alive_allowed(&self, response: &Response) -> bool {
+        !matches!(response, Response::Failure(_)) && self.keep_alive_allowed_after_success()
+    }
+
+    fn keep_alive_allowed_after_success(&self) -> bool {
+        let max_requests = self.config.network.max_requests_per_connection;
+
+        self.config.network.keep_alive
+            && (max_requests == 0 || self.num_requests_served < max_requests)
+    }
+
+    /// Read request, timing out if connection has been kept alive after a
+    /// previous response and `network.keep_alive_timeout` is set
+    async fn read_next_request(
+        &mut self,
+    ) -> Result<Either<DirectResponse, (Request, bool)>, ConnectionError> {
+        let keep_alive_timeout = self.config.network.keep_alive_timeout;
+
+        if self.num_requests_served == 0 || keep_alive_timeout == 0 {
+            return self.read_request().await;
+        }
+
+        let timeout = async {
+            glommio::timer::sleep(Duration::from_secs(keep_alive_timeout)).await;
+
+            Err(ConnectionError::KeepAliveTimeout)
+        };
+
+        race(self.read_request(), timeout).await
+    }
+
+    async fn read_request(
+        &mut self,
+    ) -> Result<Either<DirectResponse, (Request, bool)>, ConnectionError> {
         self.request_buffer_position = 0;
 
         loop {
             if self.request_buffer_position == self.request_buffer.len() {
-                return Err(ConnectionError::RequestBufferFull);
+                let limit = LimitExceeded::from_incomplete_request(&self.request_buffer[..]);
+
+                return Ok(Either::Left(DirectResponse::LimitExceeded(limit)));
             }
 
             let bytes_read = self
@@ -198,192 +440,93 @@ where
 
             let buffer_slice = &self.request_buffer[..self.request_buffer_position];
 
-            match parse_request(&self.config, buffer_slice) {
-                Ok((request, opt_peer_ip)) => {
-                    if self.config.network.runs_behind_reverse_proxy {
+            match parse_request(
+                &self.config,
+                &self.static_routes,
+                buffer_slice,
+                self.peer_is_trusted_proxy,
+            ) {
+                Ok(ParsedRequest {
+                    request: Either::Left(response),
+                    keep_alive,
+                    ..
+                }) => {
+                    return Ok(Either::Left(DirectResponse::Static(response, keep_alive)));
+                }
+                Ok(ParsedRequest {
+                    request: Either::Right(request),
+                    opt_peer_ip,
+                    keep_alive,
+                    accepts_gzip,
+                }) => {
+                    self.client_accepts_gzip = accepts_gzip;
+
+                    if self.peer_is_trusted_proxy {
                         let peer_ip = opt_peer_ip
                             .expect("logic error: peer ip must have been extracted at this point");
 
-                        self.opt_peer_addr = Some(CanonicalSocketAddr::new(SocketAddr::new(
-                            peer_ip,
-                            self.peer_port,
-                        )));
+                        let peer_addr =
+                            CanonicalSocketAddr::new(SocketAddr::new(peer_ip, self.peer_port));
+
+                        // Requests from a single reverse proxy connection
+                        // can't be rejected individually, so close it
+                        if self.handler.reject_forwarded_peer(peer_addr) {
+                            return Err(ConnectionError::PeerRejected);
+                        }
+
+                        self.opt_peer_addr = Some(peer_addr);
                     }
 
-                    return Ok(Either::Right(request));
+                    return Ok(Either::Right((request, keep_alive)));
                 }
                 Err(RequestParseError::MoreDataNeeded) => continue,
-                Err(RequestParseError::RequiredPeerIpHeaderMissing(err)) => {
-                    panic!("Tracker configured as running behind reverse proxy, but no corresponding IP header set in request. Please check your reverse proxy setup as well as your aquatic configuration. Error: {:#}", err);
+                Err(RequestParseError::LimitExceeded(limit)) => {
+                    return Ok(Either::Left(DirectResponse::LimitExceeded(limit)));
                 }
-                Err(RequestParseError::Other(err)) => {
-                    ::log::debug!("Failed parsing request: {:#}", err);
-
-                    let response = FailureResponse {
-                        failure_reason: "Invalid request".into(),
-                    };
+                Err(RequestParseError::MethodNotAllowed) => {
+                    let status = self.config.status_codes.method_not_allowed;
 
-                    return Ok(Either::Left(response));
+                    return Ok(Either::Left(DirectResponse::Status(status)));
                 }
-            }
-        }
-    }
-
-    /// Take a request and:
-    /// - Update connection ValidUntil
-    /// - Return error response if request is not allowed
-    /// - If it is an announce request, send it to swarm workers an await a
-    ///   response
-    /// - If it is a scrape requests, split it up, pass on the parts to
-    ///   relevant swarm workers and await a response
-    async fn handle_request(&mut self, request: Request) -> Result<Response, ConnectionError> {
-        let peer_addr = self
-            .opt_peer_addr
-            .expect("peer addr should already have been extracted by now");
-
-        *self.valid_until.borrow_mut() = ValidUntil::new(
-            self.server_start_instant,
-            self.config.cleaning.max_connection_idle,
-        );
+                Err(RequestParseError::NotFound) => {
+                    let status = self.config.status_codes.not_found;
 
-        match request {
-            Request::Announce(request) => {
-                #[cfg(feature = "metrics")]
-                ::metrics::counter!(
-                    "aquatic_requests_total",
-                    "type" => "announce",
-                    "ip_version" => peer_addr_to_ip_version_str(&peer_addr),
-                    "worker_index" => self.worker_index_string.clone(),
-                )
-                .increment(1);
-
-                let info_hash = request.info_hash;
-
-                if self
-                    .access_list_cache
-                    .load()
-                    .allows(self.config.access_list.mode, &info_hash.0)
-                {
-                    let (response_sender, response_receiver) = shared_channel::new_bounded(1);
-
-                    let request = ChannelRequest::Announce {
-                        request,
-                        peer_addr,
-                        response_sender,
-                    };
-
-                    let consumer_index = calculate_request_consumer_index(&self.config, info_hash);
-
-                    // Only fails when receiver is closed
-                    self.request_senders
-                        .send_to(consumer_index, request)
-                        .await
-                        .unwrap();
-
-                    response_receiver
-                        .connect()
-                        .await
-                        .recv()
-                        .await
-                        .ok_or(ConnectionError::ResponseSenderClosed)
-                        .map(Response::Announce)
-                } else {
-                    let response = Response::Failure(FailureResponse {
-                        failure_reason: "Info hash not allowed".into(),
-                    });
-
-                    Ok(response)
+                    return Ok(Either::Left(DirectResponse::Status(status)));
                 }
-            }
-            Request::Scrape(ScrapeRequest { info_hashes }) => {
-                #[cfg(feature = "metrics")]
-                ::metrics::counter!(
-                    "aquatic_requests_total",
-                    "type" => "scrape",
-                    "ip_version" => peer_addr_to_ip_version_str(&peer_addr),
-                    "worker_index" => self.worker_index_string.clone(),
-                )
-                .increment(1);
-
-                let mut info_hashes_by_worker: BTreeMap<usize, Vec<InfoHash>> = BTreeMap::new();
-
-                for info_hash in info_hashes.into_iter() {
-                    let info_hashes = info_hashes_by_worker
-                        .entry(calculate_request_consumer_index(&self.config, info_hash))
-                        .or_default();
-
-                    info_hashes.push(info_hash);
+                Err(RequestParseError::RequiredPeerIpHeaderMissing(err)) => {
+                    panic!("Tracker configured as running behind reverse proxy, but no corresponding IP header set in request. Please check your reverse proxy setup as well as your aquatic configuration. Error: {:#}", err);
                 }
+                Err(RequestParseError::InvalidTrackerRequest(err, opt_peer_ip)) => {
+                    ::log::debug!("Failed parsing request: {:#}", err);
 
-                let pending_worker_responses = info_hashes_by_worker.len();
-                let mut response_receivers = Vec::with_capacity(pending_worker_responses);
-
-                for (consumer_index, info_hashes) in info_hashes_by_worker {
-                    let (response_sender, response_receiver) = shared_channel::new_bounded(1);
-
-                    response_receivers.push(response_receiver);
-
-                    let request = ChannelRequest::Scrape {
-                        request: ScrapeRequest { info_hashes },
-                        peer_addr,
-                        response_sender,
-                    };
+                    if let Some(peer_ip) = opt_peer_ip {
+                        self.handler
+                            .record_forwarded_peer_failure(CanonicalSocketAddr::new(
+                                SocketAddr::new(peer_ip, self.peer_port),
+                            ));
+                    }
 
-                    // Only fails when receiver is closed
-                    self.request_senders
-                        .send_to(consumer_index, request)
-                        .await
-                        .unwrap();
+                    return Ok(Either::Left(DirectResponse::Failure(
+                        TrackerError::InvalidRequest,
+                    )));
                 }
+                Err(RequestParseError::Other(err)) => {
+                    ::log::debug!("Failed parsing request: {:#}", err);
 
-                let pending_scrape_response = PendingScrapeResponse {
-                    pending_worker_responses,
-                    stats: Default::default(),
-                };
-
-                self.wait_for_scrape_responses(response_receivers, pending_scrape_response)
-                    .await
-            }
-        }
-    }
-
-    /// Wait for partial scrape responses to arrive,
-    /// return full response
-    async fn wait_for_scrape_responses(
-        &self,
-        response_receivers: Vec<SharedReceiver<ScrapeResponse>>,
-        mut pending: PendingScrapeResponse,
-    ) -> Result<Response, ConnectionError> {
-        let mut responses = response_receivers
-            .into_iter()
-            .map(|receiver| async { receiver.connect().await.recv().await })
-            .collect::<FuturesUnordered<_>>();
-
-        loop {
-            let response = responses
-                .next()
-                .await
-                .ok_or_else(|| {
-                    ConnectionError::ScrapeChannelError(
-                        "stream ended before all partial scrape responses received",
-                    )
-                })?
-                .ok_or_else(|| ConnectionError::ScrapeChannelError("sender is closed"))?;
-
-            pending.stats.extend(response.files);
-            pending.pending_worker_responses -= 1;
-
-            if pending.pending_worker_responses == 0 {
-                let response = Response::Scrape(ScrapeResponse {
-                    files: pending.stats,
-                });
-
-                break Ok(response);
+                    return Ok(Either::Left(DirectResponse::Failure(
+                        TrackerError::InvalidRequest,
+                    )));
+                }
             }
         }
     }
 
-    async fn write_response(&mut self, response: &Response) -> Result<(), ConnectionError> {
+    async fn write_response(
+        &mut self,
+        response: &Response,
+        status: u16,
+        keep_alive: bool,
+    ) -> Result<(), ConnectionError> {
         // Write body and final newline to response buffer
 
         let mut position = RESPONSE_HEADER.len();
@@ -402,36 +545,82 @@ where
 
         position += 2;
 
-        let content_len = body_len + 2;
+        let body = &self.response_buffer[RESPONSE_HEADER.len()..position];
 
-        // Clear content-len header value
+        let opt_compressed = compress_body(&self.config, self.client_accepts_gzip, body);
 
-        {
-            let start = RESPONSE_HEADER_A.len();
-            let end = start + RESPONSE_HEADER_B.len();
+        // Prepared header in response buffer can only be used for uncompressed
+        // responses with status 200
+        if opt_compressed.is_some() || status != 200 {
+            let gzip = opt_compressed.is_some();
+            let body = opt_compressed.as_deref().unwrap_or(body);
 
-            self.response_buffer[start..end].copy_from_slice(RESPONSE_HEADER_B);
-        }
+            let mut output = Vec::with_capacity(RESPONSE_HEADER.len() + 64 + body.len());
 
-        // Set content-len header value
+            write_status_line(&mut output, status);
 
-        {
-            let mut buf = ::itoa::Buffer::new();
-            let content_len_bytes = buf.format(content_len).as_bytes();
+            output.extend_from_slice(b"Content-Length: ");
+            output.extend_from_slice(::itoa::Buffer::new().format(body.len()).as_bytes());
 
-            let start = RESPONSE_HEADER_A.len();
-            let end = start + content_len_bytes.len();
+            if gzip {
+                output.extend_from_slice(b"\r\nContent-Encoding: gzip");
+            }
 
-            self.response_buffer[start..end].copy_from_slice(content_len_bytes);
-        }
+            output.extend_from_slice(RESPONSE_HEADER_C);
+            output.extend_from_slice(if keep_alive { b"keep-alive" } else { b"close" });
+            output.extend_from_slice(RESPONSE_HEADER_E);
+            output.extend_from_slice(body);
 
-        // Write buffer to stream
+            self.stream
+                .write_all(&output)
+                .await
+                .with_context(|| "write")?;
+            self.stream.flush().await.with_context(|| "flush")?;
+        } else {
+            let content_len = body_len + 2;
 
-        self.stream
-            .write(&self.response_buffer[..position])
-            .await
-            .with_context(|| "write")?;
-        self.stream.flush().await.with_context(|| "flush")?;
+            // Clear content-len header value
+
+            {
+                let start = RESPONSE_HEADER_A.len();
+                let end = start + RESPONSE_HEADER_B.len();
+
+                self.response_buffer[start..end].copy_from_slice(RESPONSE_HEADER_B);
+            }
+
+            // Set content-len header value
+
+            {
+                let mut buf = ::itoa::Buffer::new();
+                let content_len_bytes = buf.format(content_len).as_bytes();
+
+                let start = RESPONSE_HEADER_A.len();
+                let end = start + content_len_bytes.len();
+
+                self.response_buffer[start..end].copy_from_slice(content_len_bytes);
+            }
+
+            // Set connection header value
+
+            {
+                let value: &[u8] = if keep_alive { b"keep-alive" } else { b"close" };
+
+                let start =
+                    RESPONSE_HEADER_A.len() + RESPONSE_HEADER_B.len() + RESPONSE_HEADER_C.len();
+                let end = start + RESPONSE_HEADER_D.len();
+
+                self.response_buffer[start..end].copy_from_slice(RESPONSE_HEADER_D);
+                self.response_buffer[start..start + value.len()].copy_from_slice(value);
+            }
+
+            // Write buffer to stream
+
+            self.stream
+                .write(&self.response_buffer[..position])
+                .await
+                .with_context(|| "write")?;
+            self.stream.flush().await.with_context(|| "flush")?;
+        }
 
         #[cfg(feature = "metrics")]
         {
@@ -441,23 +630,57 @@ where
                 Response::Failure(_) => "error",
             };
 
-            let peer_addr = self
-                .opt_peer_addr
-                .expect("peer addr should already have been extracted by now");
+            self.handler
+                .record_response(response_type, self.peer_addr());
+        }
 
-            ::metrics::counter!(
-                "aquatic_responses_total",
-                "type" => response_type,
-                "ip_version" => peer_addr_to_ip_version_str(&peer_addr),
-                "worker_index" => self.worker_index_string.clone(),
-            )
-            .increment(1);
+        Ok(())
+    }
+
+    /// Write full scrape response in chunks, without buffering whole body
+    async fn write_full_scrape_response(
+        &mut self,
+        mut body: FullScrapeBody,
+        keep_alive: bool,
+    ) -> Result<(), ConnectionError> {
+        let mut chunk = Vec::with_capacity(FULL_SCRAPE_CHUNK_SIZE * 2);
+
+        chunk.extend_from_slice(RESPONSE_HEADER_A);
+        chunk.extend_from_slice(::itoa::Buffer::new().format(body.content_len()).as_bytes());
+        chunk.extend_from_slice(RESPONSE_HEADER_C);
+        chunk.extend_from_slice(if keep_alive { b"keep-alive" } else { b"close" });
+        chunk.extend_from_slice(RESPONSE_HEADER_E);
+
+        loop {
+            let more = body.write_chunk(&mut chunk, FULL_SCRAPE_CHUNK_SIZE);
+
+            self.stream
+                .write_all(&chunk)
+                .await
+                .with_context(|| "write")?;
+
+            chunk.clear();
+
+            if !more {
+                break;
+            }
         }
 
+        self.stream.flush().await.with_context(|| "flush")?;
+
+        #[cfg(feature = "metrics")]
+        self.handler.record_response("scrape", self.peer_addr());
+
         Ok(())
     }
 }
 
-fn calculate_request_consumer_index(config: &Config, info_hash: InfoHash) -> usize {
-    (info_hash.0[0] as usize) % config.swarm_workers
+/// Write HTTP/1.1 status line, with standard reason phrase if there is one
+fn write_status_line(output: &mut Vec<u8>, status: u16) {
+    let reason = http::StatusCode::from_u16(status)
+        .ok()
+        .and_then(|status| status.canonical_reason())
+        .unwrap_or_default();
+
+    write!(output, "HTTP/1.1 {} {}\r\n", status, reason).expect("write to vec");
 }
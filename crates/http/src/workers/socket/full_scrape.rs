@@ -0,0 +1,196 @@
+use std::sync::Arc;
+
+use aquatic_http_protocol::common::InfoHash;
+use aquatic_http_protocol::response::ScrapeResponse;
+
+use crate::common::{FullScrapeFiles, FullScrapeSnapshot, FullScrapeSnapshots};
+
+/// Bencoded full scrape response body, written in chunks
+///
+/// Holds on to the swarm worker snapshots that were current when the
+/// request arrived and merges their (individually sorted) file entries, so
+/// that the whole response never needs to be in memory at once.
+pub struct FullScrapeBody {
+    snapshots: Vec<Arc<FullScrapeSnapshot>>,
+    ipv4: bool,
+    /// Index of next file entry to write, per snapshot
+    positions: Vec<usize>,
+    started: bool,
+}
+
+impl FullScrapeBody {
+    pub fn new(snapshots: &FullScrapeSnapshots, ipv4: bool) -> Self {
+        let snapshots: Vec<_> = snapshots.iter().map(|s| s.load_full()).collect();
+        let positions = vec![0; snapshots.len()];
+
+        Self {
+            snapshots,
+            ipv4,
+            positions,
+            started: false,
+        }
+    }
+
+    pub fn content_len(&self) -> usize {
+        let files_len: usize = (0..self.snapshots.len())
+            .map(|i| self.files(i).encoded_len)
+            .sum();
+
+        ScrapeResponse::FILES_START.len() + files_len + ScrapeResponse::FILES_END.len()
+    }
+
+    /// Append bencoded data to output until it is at least `min_len` bytes
+    /// long or the body is complete
+    ///
+    /// Returns false once the body is complete.
+    pub fn write_chunk(&mut self, output: &mut Vec<u8>, min_len: usize) -> bool {
+        if !self.started {
+            output.extend_from_slice(ScrapeResponse::FILES_START);
+
+            self.started = true;
+        }
+
+        while output.len() < min_len {
+            let Some(snapshot_index) = self.next_snapshot_index() else {
+                output.extend_from_slice(ScrapeResponse::FILES_END);
+
+                return false;
+            };
+
+            let position = self.positions[snapshot_index];
+            let (info_hash, statistics) = &self.files(snapshot_index).files[position];
+
+            ScrapeResponse::write_file_entry(output, info_hash, statistics).expect("write to vec");
+
+            self.positions[snapshot_index] += 1;
+        }
+
+        true
+    }
+
+    /// Index of snapshot with lowest next info hash, since bencoded
+    /// dictionary keys must be sorted
+    fn next_snapshot_index(&self) -> Option<usize> {
+        let mut opt_next: Option<(usize, &InfoHash)> = None;
+
+        for (snapshot_index, position) in self.positions.iter().copied().enumerate() {
+            let Some((info_hash, _)) = self.files(snapshot_index).files.get(position) else {
+                continue;
+            };
+
+            if opt_next.map_or(true, |(_, next_info_hash)| info_hash < next_info_hash) {
+                opt_next = Some((snapshot_index, info_hash));
+            }
+        }
+
+        opt_next.map(|(snapshot_index, _)| snapshot_index)
+    }
+
+    fn files(&self, snapshot_index: usize) -> &FullScrapeFiles {
+        let snapshot = &self.snapshots[snapshot_index];
+
+        if self.ipv4 {
+            &snapshot.ipv4
+        } else {
+            &snapshot.ipv6
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use aquatic_http_protocol::response::ScrapeStatistics;
+    use arc_swap::ArcSwap;
+
+    use super::*;
+
+    fn create_files(info_hashes: &[u8]) -> FullScrapeFiles {
+        let mut files = Vec::new();
+        let mut encoded_len = 0;
+
+        for (i, byte) in info_hashes.iter().copied().enumerate() {
+            let info_hash = InfoHash([byte; 20]);
+            let statistics = ScrapeStatistics {
+                complete: i,
+                incomplete: 1,
+                downloaded: 1000 * i,
+            };
+
+            encoded_len +=
+                ScrapeResponse::write_file_entry(&mut Vec::new(), &info_hash, &statistics).unwrap();
+
+            files.push((info_hash, statistics));
+        }
+
+        FullScrapeFiles { files, encoded_len }
+    }
+
+    #[test]
+    fn test_full_scrape_body() {
+        let snapshots: FullScrapeSnapshots = [
+            FullScrapeSnapshot {
+                ipv4: create_files(&[1, 4, 200]),
+                ipv6: create_files(&[9]),
+            },
+            FullScrapeSnapshot {
+                ipv4: create_files(&[]),
+                ipv6: Default::default(),
+            },
+            FullScrapeSnapshot {
+                ipv4: create_files(&[0, 2, 3, 255]),
+                ipv6: Default::default(),
+            },
+        ]
+        .into_iter()
+        .map(ArcSwap::from_pointee)
+        .collect();
+
+        let mut reference = ScrapeResponse {
+            files: BTreeMap::new(),
+        };
+
+        for snapshot in snapshots.iter() {
+            reference
+                .files
+                .extend(snapshot.load().ipv4.files.iter().cloned());
+        }
+
+        let mut reference_bytes = Vec::new();
+
+        reference.write_bytes(&mut reference_bytes).unwrap();
+
+        // Small minimum chunk length to exercise resuming
+        for min_len in [1, 64, 4096] {
+            let mut body = FullScrapeBody::new(&snapshots, true);
+            let mut bytes = Vec::new();
+            let mut num_chunks = 0;
+
+            loop {
+                let mut chunk = Vec::new();
+                let more = body.write_chunk(&mut chunk, min_len);
+
+                bytes.extend_from_slice(&chunk);
+                num_chunks += 1;
+
+                if !more {
+                    break;
+                }
+            }
+
+            assert_eq!(body.content_len(), reference_bytes.len());
+            assert_eq!(bytes, reference_bytes);
+
+            if min_len == 4096 {
+                assert_eq!(num_chunks, 1);
+            }
+        }
+
+        let mut body = FullScrapeBody::new(&snapshots, false);
+        let mut bytes = Vec::new();
+
+        assert!(!body.write_chunk(&mut bytes, usize::MAX));
+        assert_eq!(bytes.len(), body.content_len());
+    }
+}
@@ -0,0 +1,124 @@
+use std::io::Write;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::config::Config;
+
+use super::request::Header;
+
+/// Check if client accepts gzip content coding in `Accept-Encoding` headers
+///
+/// Codings with quality value zero are refused. A wildcard only applies if
+/// gzip isn't listed explicitly.
+pub fn client_accepts_gzip(headers: &[Header]) -> bool {
+    let mut opt_wildcard = None;
+
+    for (name, value) in headers {
+        if !name.eq_ignore_ascii_case("accept-encoding") {
+            continue;
+        }
+
+        let Ok(value) = ::std::str::from_utf8(value) else {
+            continue;
+        };
+
+        for item in value.split(',') {
+            let mut parts = item.split(';').map(str::trim);
+
+            let coding = parts.next().unwrap_or_default();
+
+            let accepted = parts
+                .filter_map(|param| param.split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+                .map_or(true, |(_, q)| {
+                    q.trim().parse::<f32>().is_ok_and(|q| q > 0.0)
+                });
+
+            if coding.eq_ignore_ascii_case("gzip") || coding.eq_ignore_ascii_case("x-gzip") {
+                return accepted;
+            } else if coding == "*" {
+                opt_wildcard = Some(accepted);
+            }
+        }
+    }
+
+    opt_wildcard.unwrap_or(false)
+}
+
+/// Compress response body if gzip compression is enabled, the client
+/// accepts it and the body is large enough
+pub fn compress_body(config: &Config, client_accepts_gzip: bool, body: &[u8]) -> Option<Vec<u8>> {
+    let min_size = config.network.gzip_min_response_size;
+
+    if !client_accepts_gzip || min_size == 0 || body.len() < min_size {
+        return None;
+    }
+
+    // Responses are small, so favor speed over compression ratio
+    let mut encoder = GzEncoder::new(Vec::with_capacity(body.len() / 2), Compression::fast());
+
+    // Writing to vec doesn't fail
+    encoder.write_all(body).ok()?;
+
+    let compressed = encoder.finish().ok()?;
+
+    // Incompressible bodies are sent as is
+    (compressed.len() < body.len()).then_some(compressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+
+    #[test]
+    fn test_client_accepts_gzip() {
+        let accepts = |value: &str| client_accepts_gzip(&[("Accept-Encoding", value.as_bytes())]);
+
+        assert!(accepts("gzip"));
+        assert!(accepts("deflate, GZIP;q=0.5, br"));
+        assert!(accepts("x-gzip"));
+        assert!(accepts("*"));
+        assert!(accepts("gzip;q=1, *;q=0"));
+
+        assert!(!accepts(""));
+        assert!(!accepts("deflate, br"));
+        assert!(!accepts("gzip;q=0"));
+        assert!(!accepts("gzip; q=0.000, *"));
+        assert!(!accepts("*;q=0"));
+        assert!(!accepts("gzip;q=x"));
+
+        assert!(!client_accepts_gzip(&[]));
+        assert!(!client_accepts_gzip(&[("Content-Encoding", b"gzip")]));
+    }
+
+    #[test]
+    fn test_compress_body() {
+        let mut config = Config::default();
+
+        let body = b"d5:filesd".repeat(100);
+
+        assert!(compress_body(&config, true, &body).is_none());
+
+        config.network.gzip_min_response_size = 512;
+
+        assert!(compress_body(&config, false, &body).is_none());
+        assert!(compress_body(&config, true, &body[..511]).is_none());
+
+        let compressed = compress_body(&config, true, &body).unwrap();
+
+        assert!(compressed.len() < body.len());
+
+        let mut decompressed = Vec::new();
+
+        GzDecoder::new(&compressed[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+
+        assert_eq!(decompressed, body);
+    }
+}
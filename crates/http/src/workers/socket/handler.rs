@@ -0,0 +1,540 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use aquatic_common::access_list::{create_access_list_cache, AccessListArcSwap, AccessListCache};
+use aquatic_common::ban_list::{create_ban_list_cache, BanListArcSwap, BanListCache};
+use aquatic_common::greylist::Greylist;
+use aquatic_common::load_shedding::AnnounceCeiling;
+use aquatic_common::signed_urls::SignedUrlVerifier;
+use aquatic_common::{CanonicalSocketAddr, ServerStartInstant};
+use aquatic_http_protocol::common::InfoHash;
+use aquatic_http_protocol::request::{AnnounceRequest, FullScrapeRequest, Request, ScrapeRequest};
+use aquatic_http_protocol::response::{
+    AnnounceResponse, FailureResponse, Response, ScrapeResponse, ScrapeStatistics,
+};
+use futures::stream::FuturesUnordered;
+use futures_lite::StreamExt;
+use glommio::channels::channel_mesh::Senders;
+use glommio::channels::shared_channel::{self, SharedReceiver};
+
+use crate::auth::{AuthHook, AuthRequest};
+use crate::common::*;
+use crate::config::Config;
+use crate::passkeys::{create_passkeys_cache, PasskeysArcSwap, PasskeysCache};
+use crate::peer_ip::PeerIpOverride;
+
+use super::connection::ConnectionError;
+use super::full_scrape::FullScrapeBody;
+#[cfg(feature = "metrics")]
+use super::peer_addr_to_ip_version_str;
+use super::record_greylist_failure;
+
+struct PendingScrapeResponse {
+    pending_worker_responses: usize,
+    stats: BTreeMap<InfoHash, ScrapeStatistics>,
+}
+
+/// Handles parsed requests of a single connection, regardless of HTTP
+/// version
+///
+/// Methods take `&self`, so that HTTP/2 connections can handle several
+/// requests concurrently.
+pub(super) struct RequestHandler {
+    config: Rc<Config>,
+    access_list_cache: RefCell<AccessListCache>,
+    passkeys_cache: RefCell<PasskeysCache>,
+    ban_list_cache: RefCell<BanListCache>,
+    greylist: Rc<RefCell<Greylist>>,
+    opt_signed_url_verifier: Option<Rc<SignedUrlVerifier>>,
+    full_scrape_snapshots: FullScrapeSnapshots,
+    announce_ceiling: Arc<AnnounceCeiling>,
+    peer_ip_override: Rc<PeerIpOverride>,
+    auth_hook: Rc<dyn AuthHook>,
+    request_senders: Rc<Senders<ChannelRequest>>,
+    valid_until: Rc<RefCell<ValidUntil>>,
+    server_start_instant: ServerStartInstant,
+    worker_index_string: String,
+}
+
+impl RequestHandler {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        config: Rc<Config>,
+        access_list: &Arc<AccessListArcSwap>,
+        passkeys: &Arc<PasskeysArcSwap>,
+        ban_list: &Arc<BanListArcSwap>,
+        greylist: Rc<RefCell<Greylist>>,
+        full_scrape_snapshots: FullScrapeSnapshots,
+        announce_ceiling: Arc<AnnounceCeiling>,
+        peer_ip_override: Rc<PeerIpOverride>,
+        auth_hook: Rc<dyn AuthHook>,
+        opt_signed_url_verifier: Option<Rc<SignedUrlVerifier>>,
+        request_senders: Rc<Senders<ChannelRequest>>,
+        valid_until: Rc<RefCell<ValidUntil>>,
+        server_start_instant: ServerStartInstant,
+        worker_index: usize,
+    ) -> Self {
+        Self {
+            config,
+            access_list_cache: RefCell::new(create_access_list_cache(access_list)),
+            passkeys_cache: RefCell::new(create_passkeys_cache(passkeys)),
+            ban_list_cache: RefCell::new(create_ban_list_cache(ban_list)),
+            greylist,
+            opt_signed_url_verifier,
+            full_scrape_snapshots,
+            announce_ceiling,
+            peer_ip_override,
+            auth_hook,
+            request_senders,
+            valid_until,
+            server_start_instant,
+            worker_index_string: worker_index.to_string(),
+        }
+    }
+
+    /// Take a request and:
+    /// - Update connection ValidUntil
+    /// - Return error if passkey, signed URL, client or request is not allowed
+    /// - If it is an announce request and `protocol.max_announces_per_second`
+    ///   was reached, return a response without peers asking the peer to
+    ///   announce again later
+    /// - If it is an announce request, ask authentication hook whether to
+    ///   serve it, apply peer IP override policy, send it to swarm workers
+    ///   an await a response, and add the external IP of the peer and any
+    ///   announce interval override to it
+    /// - If it is a scrape requests, split it up, pass on the parts to
+    ///   relevant swarm workers and await a response
+    ///
+    /// Full scrape requests must be passed to `handle_full_scrape_request`
+    /// instead.
+    pub async fn handle_request(
+        &self,
+        request: Request,
+        peer_addr: CanonicalSocketAddr,
+    ) -> Result<Result<Response, TrackerError>, ConnectionError> {
+        self.update_valid_until();
+
+        match request {
+            Request::Announce(request) => {
+                #[cfg(feature = "metrics")]
+                ::metrics::counter!(
+                    "aquatic_requests_total",
+                    "type" => "announce",
+                    "ip_version" => peer_addr_to_ip_version_str(&peer_addr),
+                    "worker_index" => self.worker_index_string.clone(),
+                )
+                .increment(1);
+
+                if let Err(err) = self.check_announce_request(&request, peer_addr) {
+                    return Ok(Err(err));
+                }
+
+                if let Some(response) = self.shed_announce_if_overloaded(&request, peer_addr) {
+                    return Ok(Ok(response));
+                }
+
+                let verdict = self
+                    .auth_hook
+                    .authenticate(AuthRequest::new(&request, peer_addr.get().ip()))
+                    .await;
+
+                if !verdict.allow {
+                    return Ok(Ok(Response::Failure(FailureResponse::new(
+                        verdict.failure_reason(),
+                    ))));
+                }
+
+                let info_hash = request.info_hash;
+                let opt_external_ip = self
+                    .config
+                    .protocol
+                    .send_external_ip
+                    .then(|| peer_addr.get().ip());
+                let peer_addr = self.peer_ip_override.apply(peer_addr, &request);
+
+                let (response_sender, response_receiver) = shared_channel::new_bounded(1);
+
+                let request = ChannelRequest::Announce {
+                    request,
+                    peer_addr,
+                    response_sender,
+                };
+
+                let consumer_index = calculate_request_consumer_index(&self.config, info_hash);
+
+                // Only fails when receiver is closed
+                self.request_senders
+                    .send_to(consumer_index, request)
+                    .await
+                    .unwrap();
+
+                let mut response = response_receiver
+                    .connect()
+                    .await
+                    .recv()
+                    .await
+                    .ok_or(ConnectionError::ResponseSenderClosed)?;
+
+                if let Response::Announce(response) = &mut response {
+                    response.external_ip = opt_external_ip;
+
+                    if let Some(interval) =
+                        verdict.announce_interval(self.config.protocol.min_announce_interval)
+                    {
+                        response.announce_interval = interval;
+                    }
+                }
+
+                Ok(Ok(response))
+            }
+            Request::Scrape(ScrapeRequest {
+                info_hashes,
+                passkey,
+            }) => {
+                #[cfg(feature = "metrics")]
+                ::metrics::counter!(
+                    "aquatic_requests_total",
+                    "type" => "scrape",
+                    "ip_version" => peer_addr_to_ip_version_str(&peer_addr),
+                    "worker_index" => self.worker_index_string.clone(),
+                )
+                .increment(1);
+
+                if let Err(err) = self.check_passkey(passkey.as_deref()) {
+                    return Ok(Err(err));
+                }
+
+                let mut info_hashes_by_worker: BTreeMap<usize, Vec<InfoHash>> = BTreeMap::new();
+
+                // Limit total number of info hashes before they are split up
+                // between swarm workers, which only limit their own share
+                for info_hash in info_hashes
+                    .into_iter()
+                    .take(self.config.protocol.max_scrape_torrents)
+                    .filter(|info_hash| {
+                        self.check_signed_url(passkey.as_deref(), info_hash).is_ok()
+                    })
+                    .filter(|info_hash| self.check_access_list(info_hash, "scrape", peer_addr))
+                {
+                    let info_hashes = info_hashes_by_worker
+                        .entry(calculate_request_consumer_index(&self.config, info_hash))
+                        .or_default();
+
+                    info_hashes.push(info_hash);
+                }
+
+                // All info hashes were filtered out by access list
+                if info_hashes_by_worker.is_empty() {
+                    return Ok(Ok(Response::Scrape(ScrapeResponse {
+                        files: Default::default(),
+                    })));
+                }
+
+                let pending_worker_responses = info_hashes_by_worker.len();
+                let mut response_receivers = Vec::with_capacity(pending_worker_responses);
+
+                for (consumer_index, info_hashes) in info_hashes_by_worker {
+                    let (response_sender, response_receiver) = shared_channel::new_bounded(1);
+
+                    response_receivers.push(response_receiver);
+
+                    let request = ChannelRequest::Scrape {
+                        request: ScrapeRequest {
+                            info_hashes,
+                            passkey: passkey.clone(),
+                        },
+                        peer_addr,
+                        response_sender,
+                    };
+
+                    // Only fails when receiver is closed
+                    self.request_senders
+                        .send_to(consumer_index, request)
+                        .await
+                        .unwrap();
+                }
+
+                let pending_scrape_response = PendingScrapeResponse {
+                    pending_worker_responses,
+                    stats: Default::default(),
+                };
+
+                self.wait_for_scrape_responses(response_receivers, pending_scrape_response)
+                    .await
+                    .map(Ok)
+            }
+            Request::FullScrape(_) => {
+                unreachable!("full scrape requests are handled separately")
+            }
+        }
+    }
+
+    /// Take a full scrape request and:
+    /// - Update connection ValidUntil
+    /// - Return error if full scrapes are disabled (or signed URLs are
+    ///   enabled) or passkey is not allowed
+    /// - Otherwise, return body with statistics for torrents of the IP
+    ///   version of the peer, based on latest swarm worker snapshots
+    pub fn handle_full_scrape_request(
+        &self,
+        request: FullScrapeRequest,
+        peer_addr: CanonicalSocketAddr,
+    ) -> Result<FullScrapeBody, TrackerError> {
+        self.update_valid_until();
+
+        #[cfg(feature = "metrics")]
+        ::metrics::counter!(
+            "aquatic_requests_total",
+            "type" => "scrape",
+            "ip_version" => peer_addr_to_ip_version_str(&peer_addr),
+            "worker_index" => self.worker_index_string.clone(),
+        )
+        .increment(1);
+
+        // Signed URLs only cover single info hashes
+        if !self.config.protocol.enable_full_scrape || self.opt_signed_url_verifier.is_some() {
+            return Err(TrackerError::FullScrapeNotAllowed);
+        }
+
+        self.check_passkey(request.passkey.as_deref())?;
+
+        Ok(FullScrapeBody::new(
+            &self.full_scrape_snapshots,
+            peer_addr.get().ip().is_ipv4(),
+        ))
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn record_response(&self, response_type: &'static str, peer_addr: CanonicalSocketAddr) {
+        ::metrics::counter!(
+            "aquatic_responses_total",
+            "type" => response_type,
+            "ip_version" => peer_addr_to_ip_version_str(&peer_addr),
+            "worker_index" => self.worker_index_string.clone(),
+        )
+        .increment(1);
+    }
+
+    /// Check if peer address is banned or its network prefix is greylisted
+    ///
+    /// Only needed for addresses forwarded by reverse proxies, since socket
+    /// peer addresses are checked when accepting connections.
+    pub fn reject_forwarded_peer(&self, peer_addr: CanonicalSocketAddr) -> bool {
+        if self.config.ban_list.enabled
+            && self.ban_list_cache.borrow_mut().load().is_banned(peer_addr)
+        {
+            #[cfg(feature = "metrics")]
+            ::metrics::counter!(
+                "aquatic_banned_requests_total",
+                "worker_index" => self.worker_index_string.clone(),
+            )
+            .increment(1);
+
+            return true;
+        }
+
+        if self.config.greylist.active()
+            && self
+                .greylist
+                .borrow()
+                .contains(peer_addr, self.server_start_instant.seconds_elapsed())
+        {
+            #[cfg(feature = "metrics")]
+            ::metrics::counter!(
+                "aquatic_greylisted_requests_total",
+                "worker_index" => self.worker_index_string.clone(),
+            )
+            .increment(1);
+
+            return true;
+        }
+
+        false
+    }
+
+    /// Count malformed request towards greylisting network prefix of peer
+    /// address forwarded by reverse proxy
+    pub fn record_forwarded_peer_failure(&self, peer_addr: CanonicalSocketAddr) {
+        if self.config.greylist.active() {
+            record_greylist_failure(
+                &self.greylist,
+                peer_addr,
+                self.server_start_instant,
+                &self.worker_index_string,
+            );
+        }
+    }
+
+    pub fn update_valid_until(&self) {
+        *self.valid_until.borrow_mut() = ValidUntil::new(
+            self.server_start_instant,
+            self.config.cleaning.max_connection_idle,
+        );
+    }
+
+    fn check_announce_request(
+        &self,
+        request: &AnnounceRequest,
+        peer_addr: CanonicalSocketAddr,
+    ) -> Result<(), TrackerError> {
+        self.check_passkey(request.passkey.as_deref())?;
+        self.check_signed_url(request.passkey.as_deref(), &request.info_hash)?;
+
+        if !self.config.client_filter.allows(&request.peer_id.0) {
+            return Err(TrackerError::ClientNotAllowed(
+                self.config.client_filter.failure_reason(),
+            ));
+        }
+
+        if !self.check_access_list(&request.info_hash, "announce", peer_addr) {
+            return Err(TrackerError::InfoHashNotAllowed);
+        }
+
+        if self.config.protocol.compact_only && !request.compact {
+            return Err(TrackerError::CompactRequired);
+        }
+
+        Ok(())
+    }
+
+    /// Count announce request towards `protocol.max_announces_per_second`
+    /// and return response without peers if it exceeds it
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+    fn shed_announce_if_overloaded(
+        &self,
+        request: &AnnounceRequest,
+        peer_addr: CanonicalSocketAddr,
+    ) -> Option<Response> {
+        if !self.announce_ceiling.active()
+            || self
+                .announce_ceiling
+                .try_acquire(self.server_start_instant.seconds_elapsed())
+        {
+            return None;
+        }
+
+        #[cfg(feature = "metrics")]
+        ::metrics::counter!(
+            "aquatic_shed_announces_total",
+            "ip_version" => peer_addr_to_ip_version_str(&peer_addr),
+            "worker_index" => self.worker_index_string.clone(),
+        )
+        .increment(1);
+
+        Some(Response::Announce(AnnounceResponse {
+            announce_interval: self.config.protocol.overloaded_announce_interval,
+            complete: 0,
+            incomplete: 0,
+            external_ip: self
+                .config
+                .protocol
+                .send_external_ip
+                .then(|| peer_addr.get().ip()),
+            peers: Default::default(),
+            peers6: Default::default(),
+            tracker_id: None,
+            warning_message: Some("Tracker overloaded, try again later".into()),
+            compact: request.compact,
+        }))
+    }
+
+    /// Check if info hash is allowed by access list, counting hits
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+    fn check_access_list(
+        &self,
+        info_hash: &InfoHash,
+        request_type: &'static str,
+        peer_addr: CanonicalSocketAddr,
+    ) -> bool {
+        let mode = self.config.access_list.mode;
+        let check = self
+            .access_list_cache
+            .borrow_mut()
+            .load()
+            .check(mode, &info_hash.0);
+
+        #[cfg(feature = "metrics")]
+        if check.hit {
+            ::metrics::counter!(
+                "aquatic_access_list_hits_total",
+                "list" => mode.as_str(),
+                "type" => request_type,
+                "ip_version" => peer_addr_to_ip_version_str(&peer_addr),
+                "worker_index" => self.worker_index_string.clone(),
+            )
+            .increment(1);
+        }
+
+        check.allowed
+    }
+
+    fn check_passkey(&self, opt_passkey: Option<&str>) -> Result<(), TrackerError> {
+        if self
+            .passkeys_cache
+            .borrow_mut()
+            .load()
+            .allows(self.config.passkeys.backend, opt_passkey)
+        {
+            Ok(())
+        } else {
+            Err(TrackerError::InvalidPasskey)
+        }
+    }
+
+    /// Check token from announce URL path segment, if signed URLs are
+    /// enabled
+    fn check_signed_url(
+        &self,
+        opt_token: Option<&str>,
+        info_hash: &InfoHash,
+    ) -> Result<(), TrackerError> {
+        match self.opt_signed_url_verifier.as_ref() {
+            Some(verifier) => verifier
+                .verify(opt_token, &info_hash.0)
+                .map_err(TrackerError::SignedUrl),
+            None => Ok(()),
+        }
+    }
+
+    /// Wait for partial scrape responses to arrive,
+    /// return full response
+    async fn wait_for_scrape_responses(
+        &self,
+        response_receivers: Vec<SharedReceiver<ScrapeResponse>>,
+        mut pending: PendingScrapeResponse,
+    ) -> Result<Response, ConnectionError> {
+        let mut responses = response_receivers
+            .into_iter()
+            .map(|receiver| async { receiver.connect().await.recv().await })
+            .collect::<FuturesUnordered<_>>();
+
+        loop {
+            let response = responses
+                .next()
+                .await
+                .ok_or_else(|| {
+                    ConnectionError::ScrapeChannelError(
+                        "stream ended before all partial scrape responses received",
+                    )
+                })?
+                .ok_or_else(|| ConnectionError::ScrapeChannelError("sender is closed"))?;
+
+            pending.stats.extend(response.files);
+            pending.pending_worker_responses -= 1;
+
+            if pending.pending_worker_responses == 0 {
+                let response = Response::Scrape(ScrapeResponse {
+                    files: pending.stats,
+                });
+
+                break Ok(response);
+            }
+        }
+    }
+}
+
+fn calculate_request_consumer_index(config: &Config, info_hash: InfoHash) -> usize {
+    (info_hash.0[0] as usize) % config.swarm_workers
+}
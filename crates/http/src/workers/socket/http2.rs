@@ -0,0 +1,388 @@
+use std::net::SocketAddr;
+use std::rc::Rc;
+
+use aquatic_common::CanonicalSocketAddr;
+use aquatic_http_protocol::request::Request;
+use aquatic_http_protocol::response::Response;
+use arrayvec::ArrayVec;
+use bytes::Bytes;
+use either::Either;
+use futures::stream::FuturesUnordered;
+use futures_lite::future::{or, poll_fn};
+use futures_lite::StreamExt;
+use h2::server::SendResponse;
+use h2::{RecvStream, SendStream};
+use http::header::{ALLOW, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, ETAG};
+use tokio_util::compat::FuturesAsyncReadCompatExt;
+
+use crate::common::{response_with_status, TrackerError};
+use crate::config::Config;
+
+use super::connection::{ConnectionError, FULL_SCRAPE_CHUNK_SIZE};
+use super::full_scrape::FullScrapeBody;
+use super::gzip::{client_accepts_gzip, compress_body};
+use super::handler::RequestHandler;
+use super::request::{
+    parse_request_parts, Header, LimitExceeded, RequestParseError, MAX_HEADERS, MAX_HEADER_LEN,
+};
+use super::static_routes::{StaticResponse, StaticRoutes};
+
+pub(super) const HTTP2_ALPN_PROTOCOL: &[u8] = b"h2";
+
+enum Event<A, S> {
+    Accepted(A),
+    StreamDone(S),
+}
+
+/// Serve HTTP/2 connection, handling streams concurrently
+///
+/// Streams are handled on the connection task itself, so that they can
+/// share the request handler (including its access list and passkey
+/// caches).
+pub(super) async fn run_http2_connection<S>(
+    config: Rc<Config>,
+    static_routes: Rc<StaticRoutes>,
+    handler: RequestHandler,
+    stream: S,
+    remote_addr: SocketAddr,
+    peer_is_trusted_proxy: bool,
+) -> Result<(), ConnectionError>
+where
+    S: futures::AsyncRead + futures::AsyncWrite + Unpin + 'static,
+{
+    let mut connection = h2::server::Builder::new()
+        .max_concurrent_streams(config.network.http2_max_concurrent_streams)
+        .max_header_list_size((MAX_HEADERS * MAX_HEADER_LEN) as u32)
+        .handshake::<_, Bytes>(stream.compat())
+        .await?;
+
+    let max_requests = config.network.max_requests_per_connection;
+
+    let mut num_requests_accepted = 0usize;
+    let mut invalid_request_received = false;
+    let mut streams = FuturesUnordered::new();
+
+    loop {
+        // The connection needs to be polled (through accept) for in-flight
+        // streams to make progress, so wait for both at the same time
+        let event = if streams.is_empty() {
+            Event::Accepted(connection.accept().await)
+        } else {
+            or(
+                async { Event::Accepted(connection.accept().await) },
+                async { Event::StreamDone(streams.next().await) },
+            )
+            .await
+        };
+
+        match event {
+            Event::Accepted(Some(accepted)) => {
+                let (request, respond) = accepted?;
+
+                num_requests_accepted += 1;
+
+                if max_requests != 0 && num_requests_accepted == max_requests {
+                    connection.graceful_shutdown();
+                }
+
+                streams.push(handle_stream(
+                    &config,
+                    &static_routes,
+                    &handler,
+                    request,
+                    respond,
+                    remote_addr,
+                    peer_is_trusted_proxy,
+                ));
+            }
+            Event::Accepted(None) => break,
+            // Like HTTP/1.x connections, close connection after responding
+            // to invalid request, but let other streams finish
+            Event::StreamDone(Some(Err(ConnectionError::InvalidRequest))) => {
+                if !invalid_request_received {
+                    invalid_request_received = true;
+
+                    connection.graceful_shutdown();
+                }
+            }
+            Event::StreamDone(Some(Err(err))) => {
+                ::log::debug!("http/2 stream error: {:#}", err);
+            }
+            Event::StreamDone(_) => (),
+        }
+    }
+
+    if invalid_request_received {
+        Err(ConnectionError::InvalidRequest)
+    } else {
+        Ok(())
+    }
+}
+
+async fn handle_stream(
+    config: &Config,
+    static_routes: &StaticRoutes,
+    handler: &RequestHandler,
+    request: http::Request<RecvStream>,
+    respond: SendResponse<Bytes>,
+    remote_addr: SocketAddr,
+    peer_is_trusted_proxy: bool,
+) -> Result<(), ConnectionError> {
+    let path = request
+        .uri()
+        .path_and_query()
+        .map(|path_and_query| path_and_query.as_str())
+        .unwrap_or("/");
+
+    let mut headers: ArrayVec<Header, MAX_HEADERS> = ArrayVec::new();
+
+    for (name, value) in request.headers() {
+        if headers.try_push((name.as_str(), value.as_bytes())).is_err() {
+            return send_status(respond, LimitExceeded::TooManyHeaders.status_code());
+        }
+    }
+
+    let accepts_gzip = client_accepts_gzip(&headers);
+
+    let (request, opt_peer_ip) = match parse_request_parts(
+        config,
+        static_routes,
+        request.method().as_str(),
+        path,
+        &headers,
+        peer_is_trusted_proxy,
+    ) {
+        Ok(parsed) => parsed,
+        Err(RequestParseError::LimitExceeded(limit)) => {
+            return send_status(respond, limit.status_code());
+        }
+        Err(RequestParseError::MethodNotAllowed) => {
+            return send_status(respond, config.status_codes.method_not_allowed);
+        }
+        Err(RequestParseError::NotFound) => {
+            return send_status(respond, config.status_codes.not_found);
+        }
+        Err(RequestParseError::RequiredPeerIpHeaderMissing(err)) => {
+            panic!("Tracker configured as running behind reverse proxy, but no corresponding IP header set in request. Please check your reverse proxy setup as well as your aquatic configuration. Error: {:#}", err);
+        }
+        Err(err) => {
+            ::log::debug!("Failed parsing request: {:#}", err);
+
+            if let RequestParseError::InvalidTrackerRequest(_, Some(peer_ip)) = err {
+                handler.record_forwarded_peer_failure(CanonicalSocketAddr::new(SocketAddr::new(
+                    peer_ip,
+                    remote_addr.port(),
+                )));
+            }
+
+            send_response(
+                config,
+                handler,
+                respond,
+                Err(TrackerError::InvalidRequest),
+                CanonicalSocketAddr::new(remote_addr),
+                accepts_gzip,
+            )?;
+
+            return Err(ConnectionError::InvalidRequest);
+        }
+    };
+
+    let request = match request {
+        Either::Left(response) => {
+            handler.update_valid_until();
+
+            return send_static_response(respond, &response);
+        }
+        Either::Right(request) => request,
+    };
+
+    let peer_addr = if let Some(peer_ip) = opt_peer_ip {
+        let peer_addr = CanonicalSocketAddr::new(SocketAddr::new(peer_ip, remote_addr.port()));
+
+        // Reset stream, since other streams may be from other peers
+        if handler.reject_forwarded_peer(peer_addr) {
+            return send_status(respond, 0);
+        }
+
+        peer_addr
+    } else {
+        CanonicalSocketAddr::new(remote_addr)
+    };
+
+    match request {
+        Request::FullScrape(request) => {
+            match handler.handle_full_scrape_request(request, peer_addr) {
+                Ok(body) => send_full_scrape_response(handler, respond, body, peer_addr).await,
+                Err(err) => {
+                    send_response(config, handler, respond, Err(err), peer_addr, accepts_gzip)
+                }
+            }
+        }
+        request => {
+            let result = handler.handle_request(request, peer_addr).await?;
+
+            send_response(config, handler, respond, result, peer_addr, accepts_gzip)
+        }
+    }
+}
+
+fn send_static_response(
+    mut respond: SendResponse<Bytes>,
+    response: &StaticResponse,
+) -> Result<(), ConnectionError> {
+    let route = &response.route;
+
+    let mut builder = http::Response::builder()
+        .status(response.status_code())
+        .header(CACHE_CONTROL, route.cache_control.as_str());
+
+    if let Some(etag) = route.opt_etag.as_ref() {
+        builder = builder.header(ETAG, etag.as_str());
+    }
+
+    if let Some(body) = response.body() {
+        let response = builder
+            .header(CONTENT_TYPE, route.content_type)
+            .header(CONTENT_LENGTH, body.len())
+            .body(())
+            .expect("valid response");
+
+        respond
+            .send_response(response, false)?
+            .send_data(body.clone(), true)?;
+    } else {
+        respond.send_response(builder.body(()).expect("valid response"), true)?;
+    }
+
+    Ok(())
+}
+
+/// Send bare HTTP error status, or reset stream if status is zero
+fn send_status(mut respond: SendResponse<Bytes>, status: u16) -> Result<(), ConnectionError> {
+    if status == 0 {
+        respond.send_reset(h2::Reason::CANCEL);
+
+        return Ok(());
+    }
+
+    let mut builder = http::Response::builder()
+        .status(status)
+        .header(CONTENT_LENGTH, 0);
+
+    if status == 405 {
+        builder = builder.header(ALLOW, "GET");
+    }
+
+    let response = builder.body(()).expect("valid response");
+
+    respond.send_response(response, true)?;
+
+    Ok(())
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+fn send_response(
+    config: &Config,
+    handler: &RequestHandler,
+    mut respond: SendResponse<Bytes>,
+    result: Result<Response, TrackerError>,
+    peer_addr: CanonicalSocketAddr,
+    accepts_gzip: bool,
+) -> Result<(), ConnectionError> {
+    let (response, status) = response_with_status(&config.status_codes, result);
+
+    if status == 0 {
+        respond.send_reset(h2::Reason::CANCEL);
+
+        return Ok(());
+    }
+
+    let mut body = Vec::new();
+
+    response
+        .write_bytes(&mut body)
+        .map_err(ConnectionError::ResponseBufferWrite)?;
+
+    let (body, gzip) = match compress_body(config, accepts_gzip, &body) {
+        Some(compressed) => (compressed, true),
+        None => (body, false),
+    };
+
+    let mut send = start_response(respond, status, body.len(), gzip)?;
+
+    // Data is buffered by h2 if it exceeds the flow control window, which is
+    // fine for regular responses since they are small
+    send.send_data(body.into(), true)?;
+
+    #[cfg(feature = "metrics")]
+    {
+        let response_type = match response {
+            Response::Announce(_) => "announce",
+            Response::Scrape(_) => "scrape",
+            Response::Failure(_) => "error",
+        };
+
+        handler.record_response(response_type, peer_addr);
+    }
+
+    Ok(())
+}
+
+/// Send full scrape response in chunks, only writing as much data as the
+/// client is ready to receive, so that the whole body is never buffered
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+async fn send_full_scrape_response(
+    handler: &RequestHandler,
+    respond: SendResponse<Bytes>,
+    mut body: FullScrapeBody,
+    peer_addr: CanonicalSocketAddr,
+) -> Result<(), ConnectionError> {
+    let mut send = start_response(respond, 200, body.content_len(), false)?;
+
+    loop {
+        send.reserve_capacity(FULL_SCRAPE_CHUNK_SIZE);
+
+        let mut capacity = send.capacity();
+
+        while capacity == 0 {
+            capacity = poll_fn(|cx| send.poll_capacity(cx))
+                .await
+                .ok_or(ConnectionError::PeerClosed)??;
+        }
+
+        let mut chunk = Vec::with_capacity(capacity.min(FULL_SCRAPE_CHUNK_SIZE) * 2);
+
+        let more = body.write_chunk(&mut chunk, capacity.min(FULL_SCRAPE_CHUNK_SIZE));
+
+        send.send_data(chunk.into(), !more)?;
+
+        if !more {
+            break;
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    handler.record_response("scrape", peer_addr);
+
+    Ok(())
+}
+
+fn start_response(
+    mut respond: SendResponse<Bytes>,
+    status: u16,
+    content_len: usize,
+    gzip: bool,
+) -> Result<SendStream<Bytes>, ConnectionError> {
+    let mut builder = http::Response::builder()
+        .status(status)
+        .header(CONTENT_LENGTH, content_len);
+
+    if gzip {
+        builder = builder.header(CONTENT_ENCODING, "gzip");
+    }
+
+    let response = builder.body(()).expect("valid response");
+
+    Ok(respond.send_response(response, false)?)
+}
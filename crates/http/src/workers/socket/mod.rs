@@ -1,5 +1,10 @@
 mod connection;
+mod full_scrape;
+mod gzip;
+mod handler;
+mod http2;
 mod request;
+mod static_routes;
 
 use std::cell::RefCell;
 use std::os::unix::prelude::{FromRawFd, IntoRawFd};
@@ -8,8 +13,13 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::Context;
+use aquatic_common::ban_list::create_ban_list_cache;
+use aquatic_common::greylist::Greylist;
+use aquatic_common::ip_network::IpNetworks;
 use aquatic_common::privileges::PrivilegeDropper;
 use aquatic_common::rustls_config::RustlsConfig;
+use aquatic_common::signed_urls::SignedUrlVerifier;
+use aquatic_common::systemd;
 use aquatic_common::{CanonicalSocketAddr, ServerStartInstant};
 use arc_swap::ArcSwap;
 use futures_lite::future::race;
@@ -21,9 +31,12 @@ use glommio::timer::TimerActionRepeat;
 use glommio::{enclose, prelude::*};
 use slotmap::HopSlotMap;
 
+use crate::auth::create_auth_hook;
 use crate::common::*;
 use crate::config::Config;
+use crate::peer_ip::PeerIpOverride;
 use crate::workers::socket::connection::{run_connection, ConnectionError};
+use crate::workers::socket::static_routes::StaticRoutes;
 
 struct ConnectionHandle {
     close_conn_sender: LocalSender<()>,
@@ -42,6 +55,25 @@ pub async fn run_socket_worker(
 ) -> anyhow::Result<()> {
     let config = Rc::new(config);
     let access_list = state.access_list;
+    let passkeys = state.passkeys;
+    let ban_list = state.ban_list;
+    let mut ban_list_cache = create_ban_list_cache(&ban_list);
+    let full_scrape_snapshots = state.full_scrape_snapshots;
+    let announce_ceiling = state.announce_ceiling;
+    let static_routes =
+        Rc::new(StaticRoutes::new(&config.static_routes).context("load static routes")?);
+    let peer_ip_override =
+        Rc::new(PeerIpOverride::new(&config).context("create peer ip override")?);
+    let auth_hook = create_auth_hook(&config.auth);
+    let opt_signed_url_verifier = config
+        .signed_urls
+        .enabled
+        .then(|| Rc::new(SignedUrlVerifier::new(&config.signed_urls)));
+    let trusted_proxies = Rc::new(
+        IpNetworks::parse(&config.network.reverse_proxy_trusted_networks)
+            .context("parse reverse proxy trusted networks")?,
+    );
+    let greylist = Rc::new(RefCell::new(Greylist::new(&config.greylist)));
 
     let listener = create_tcp_listener(&config, priv_dropper).context("create tcp listener")?;
 
@@ -66,6 +98,56 @@ pub async fn run_socket_worker(
     while let Some(stream) = incoming.next().await {
         match stream {
             Ok(stream) => {
+                // Close connection by dropping stream
+                if config.ban_list.enabled
+                    && stream.peer_addr().is_ok_and(|addr| {
+                        ban_list_cache
+                            .load()
+                            .is_banned(CanonicalSocketAddr::new(addr))
+                    })
+                {
+                    ::log::debug!("closed connection from banned address");
+
+                    #[cfg(feature = "metrics")]
+                    ::metrics::counter!(
+                        "aquatic_banned_connections_total",
+                        "worker_index" => worker_index.to_string(),
+                    )
+                    .increment(1);
+
+                    continue;
+                }
+
+                // Failures of connections from trusted reverse proxies can't
+                // be attributed to peers
+                let opt_greylist_addr = stream
+                    .peer_addr()
+                    .ok()
+                    .filter(|_| config.greylist.active())
+                    .map(CanonicalSocketAddr::new)
+                    .filter(|addr| {
+                        !(config.network.runs_behind_reverse_proxy
+                            && trusted_proxies.contains(addr.get().ip()))
+                    });
+
+                // Close connection by dropping stream
+                if opt_greylist_addr.is_some_and(|addr| {
+                    greylist
+                        .borrow()
+                        .contains(addr, server_start_instant.seconds_elapsed())
+                }) {
+                    ::log::debug!("closed connection from greylisted network prefix");
+
+                    #[cfg(feature = "metrics")]
+                    ::metrics::counter!(
+                        "aquatic_greylisted_connections_total",
+                        "worker_index" => worker_index.to_string(),
+                    )
+                    .increment(1);
+
+                    continue;
+                }
+
                 let (close_conn_sender, close_conn_receiver) = new_bounded(1);
 
                 let valid_until = Rc::new(RefCell::new(ValidUntil::new(
@@ -81,11 +163,21 @@ pub async fn run_socket_worker(
                 spawn_local(enclose!(
                     (
                         config,
+                        static_routes,
                         access_list,
+                        passkeys,
+                        ban_list,
+                        full_scrape_snapshots,
+                        announce_ceiling,
+                        peer_ip_override,
+                        auth_hook,
+                        opt_signed_url_verifier,
+                        trusted_proxies,
                         request_senders,
                         opt_tls_config,
                         connection_handles,
                         valid_until,
+                        greylist,
                     )
                     async move {
                         #[cfg(feature = "metrics")]
@@ -99,7 +191,17 @@ pub async fn run_socket_worker(
 
                         let f1 = async { run_connection(
                                 config,
+                                static_routes,
                                 access_list,
+                                passkeys,
+                                ban_list,
+                                greylist.clone(),
+                                full_scrape_snapshots,
+                                announce_ceiling,
+                                peer_ip_override,
+                                auth_hook,
+                                opt_signed_url_verifier,
+                                trusted_proxies,
                                 request_senders,
                                 server_start_instant,
                                 opt_tls_config,
@@ -119,6 +221,17 @@ pub async fn run_socket_worker(
                         #[cfg(feature = "metrics")]
                         active_connections_gauge.decrement(1.0);
 
+                        if let (Some(addr), Err(err)) = (opt_greylist_addr, &result) {
+                            if err.is_greylist_failure() {
+                                record_greylist_failure(
+                                    &greylist,
+                                    addr,
+                                    server_start_instant,
+                                    &worker_index.to_string(),
+                                );
+                            }
+                        }
+
                         match result {
                             Ok(()) => (),
                             Err(err@(
@@ -129,7 +242,7 @@ pub async fn run_socket_worker(
                             )) => {
                                 ::log::error!("connection closed: {:#}", err);
                             }
-                            Err(err@ConnectionError::RequestBufferFull) => {
+                            Err(err@ConnectionError::RequestLimitExceeded(_)) => {
                                 ::log::info!("connection closed: {:#}", err);
                             }
                             Err(err) => {
@@ -177,6 +290,14 @@ fn create_tcp_listener(
     config: &Config,
     priv_dropper: PrivilegeDropper,
 ) -> anyhow::Result<TcpListener> {
+    if config.network.systemd_socket_activation {
+        let fd = systemd::duplicate_listen_fd("http")?;
+
+        priv_dropper.after_socket_creation()?;
+
+        return Ok(unsafe { TcpListener::from_raw_fd(fd.into_raw_fd()) });
+    }
+
     let domain = if config.network.address.is_ipv4() {
         socket2::Domain::IPV4
     } else {
@@ -208,6 +329,28 @@ fn create_tcp_listener(
     Ok(unsafe { TcpListener::from_raw_fd(socket.into_raw_fd()) })
 }
 
+/// Count failure towards greylisting network prefix of peer
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+fn record_greylist_failure(
+    greylist: &RefCell<Greylist>,
+    addr: CanonicalSocketAddr,
+    server_start_instant: ServerStartInstant,
+    worker_index: &str,
+) {
+    if greylist
+        .borrow_mut()
+        .record_failure(addr, server_start_instant.seconds_elapsed())
+    {
+        #[cfg(feature = "metrics")]
+        ::metrics::counter!(
+            "aquatic_greylisted_prefixes_total",
+            "ip_version" => peer_addr_to_ip_version_str(&addr),
+            "worker_index" => worker_index.to_string(),
+        )
+        .increment(1);
+    }
+}
+
 #[cfg(feature = "metrics")]
 fn peer_addr_to_ip_version_str(addr: &CanonicalSocketAddr) -> &'static str {
     if addr.is_ipv4() {
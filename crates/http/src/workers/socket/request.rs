@@ -2,63 +2,279 @@ use std::net::IpAddr;
 
 use anyhow::Context;
 use aquatic_http_protocol::request::Request;
+use arrayvec::ArrayVec;
+use either::Either;
 
 use crate::config::{Config, ReverseProxyPeerIpHeaderFormat};
 
+use super::gzip::client_accepts_gzip;
+use super::static_routes::{StaticResponse, StaticRoutes};
+
+/// Maximum length of request path, including query string
+const MAX_PATH_LEN: usize = 1536;
+/// Maximum number of query string parameters
+const MAX_QUERY_PARAMETERS: usize = 128;
+/// Maximum number of headers
+pub const MAX_HEADERS: usize = 16;
+/// Maximum length of a single header (name and value)
+pub const MAX_HEADER_LEN: usize = 512;
+
+/// Request header name and value, independent of HTTP version
+pub type Header<'a> = (&'a str, &'a [u8]);
+
 #[derive(Debug, thiserror::Error)]
 pub enum RequestParseError {
     #[error("required peer ip header missing or invalid")]
     RequiredPeerIpHeaderMissing(anyhow::Error),
     #[error("more data needed")]
     MoreDataNeeded,
+    #[error("request limit exceeded: {0}")]
+    LimitExceeded(LimitExceeded),
+    #[error("method not allowed")]
+    MethodNotAllowed,
+    #[error("not found")]
+    NotFound,
+    /// Announce or scrape request couldn't be parsed. Includes peer IP if it
+    /// was extracted from reverse proxy header.
+    #[error("invalid tracker request: {0:#}")]
+    InvalidTrackerRequest(anyhow::Error, Option<IpAddr>),
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
 
+/// Request exceeds parser limits. Answered with a bare HTTP error status
+/// instead of a failure response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum LimitExceeded {
+    #[error("request path too long")]
+    PathTooLong,
+    #[error("too many query parameters")]
+    TooManyQueryParameters,
+    #[error("too many headers")]
+    TooManyHeaders,
+    #[error("header too long")]
+    HeaderTooLong,
+}
+
+impl LimitExceeded {
+    pub fn status_code(&self) -> u16 {
+        match self {
+            Self::PathTooLong => 414,
+            Self::TooManyQueryParameters | Self::TooManyHeaders | Self::HeaderTooLong => 400,
+        }
+    }
+
+    /// Full HTTP response to send before closing connection
+    pub fn response_bytes(&self) -> &'static [u8] {
+        match self {
+            Self::PathTooLong => {
+                b"HTTP/1.1 414 URI Too Long\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+            }
+            Self::TooManyQueryParameters | Self::TooManyHeaders | Self::HeaderTooLong => {
+                b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+            }
+        }
+    }
+
+    /// Figure out which limit was exceeded when request buffer is full but
+    /// request is still incomplete
+    pub fn from_incomplete_request(buffer: &[u8]) -> Self {
+        if buffer.windows(2).any(|window| window == b"\r\n") {
+            Self::HeaderTooLong
+        } else {
+            Self::PathTooLong
+        }
+    }
+}
+
+pub struct ParsedRequest {
+    pub request: Either<StaticResponse, Request>,
+    pub opt_peer_ip: Option<IpAddr>,
+    /// Client allows keeping connection open after response
+    pub keep_alive: bool,
+    /// Client accepts gzip-compressed response body
+    pub accepts_gzip: bool,
+}
+
+/// Parse HTTP/1.x request. Peer IP is extracted from reverse proxy header
+/// if `peer_is_trusted_proxy` is set.
 pub fn parse_request(
     config: &Config,
+    static_routes: &StaticRoutes,
     buffer: &[u8],
-) -> Result<(Request, Option<IpAddr>), RequestParseError> {
-    let mut headers = [httparse::EMPTY_HEADER; 16];
+    peer_is_trusted_proxy: bool,
+) -> Result<ParsedRequest, RequestParseError> {
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
     let mut http_request = httparse::Request::new(&mut headers);
 
-    match http_request.parse(buffer).with_context(|| "httparse")? {
+    let status = match http_request.parse(buffer) {
+        Ok(status) => status,
+        Err(httparse::Error::TooManyHeaders) => {
+            return Err(RequestParseError::LimitExceeded(
+                LimitExceeded::TooManyHeaders,
+            ));
+        }
+        Err(err) => return Err(anyhow::Error::from(err).context("httparse").into()),
+    };
+
+    match status {
         httparse::Status::Complete(_) => {
+            let method = http_request
+                .method
+                .ok_or(anyhow::anyhow!("no http method"))?;
             let path = http_request.path.ok_or(anyhow::anyhow!("no http path"))?;
-            let request = Request::parse_http_get_path(path)?;
 
-            let opt_peer_ip = if config.network.runs_behind_reverse_proxy {
-                let header_name = &config.network.reverse_proxy_ip_header_name;
-                let header_format = config.network.reverse_proxy_ip_header_format;
+            let headers: ArrayVec<Header, MAX_HEADERS> = http_request
+                .headers
+                .iter()
+                .map(|header| (header.name, header.value))
+                .collect();
 
-                match parse_forwarded_header(header_name, header_format, http_request.headers) {
-                    Ok(peer_ip) => Some(peer_ip),
-                    Err(err) => {
-                        return Err(RequestParseError::RequiredPeerIpHeaderMissing(err));
-                    }
-                }
-            } else {
-                None
-            };
+            let (request, opt_peer_ip) = parse_request_parts(
+                config,
+                static_routes,
+                method,
+                path,
+                &headers,
+                peer_is_trusted_proxy,
+            )?;
 
-            Ok((request, opt_peer_ip))
+            let keep_alive = client_allows_keep_alive(http_request.version, http_request.headers);
+            let accepts_gzip = client_accepts_gzip(&headers);
+
+            Ok(ParsedRequest {
+                request,
+                opt_peer_ip,
+                keep_alive,
+                accepts_gzip,
+            })
         }
         httparse::Status::Partial => Err(RequestParseError::MoreDataNeeded),
     }
 }
 
+/// Parse request from path and headers, regardless of HTTP version. Peer IP
+/// is extracted from reverse proxy header if `peer_is_trusted_proxy` is set.
+///
+/// Only GET requests are allowed. Requests to static routes don't need a
+/// peer IP, so no header is required for them.
+pub fn parse_request_parts(
+    config: &Config,
+    static_routes: &StaticRoutes,
+    method: &str,
+    path: &str,
+    headers: &[Header],
+    peer_is_trusted_proxy: bool,
+) -> Result<(Either<StaticResponse, Request>, Option<IpAddr>), RequestParseError> {
+    check_limits(path, headers).map_err(RequestParseError::LimitExceeded)?;
+
+    if method != "GET" {
+        return Err(RequestParseError::MethodNotAllowed);
+    }
+
+    if let Some(response) = static_routes.response(path, headers) {
+        return Ok((Either::Left(response), None));
+    }
+
+    if !is_tracker_path(path) {
+        return Err(RequestParseError::NotFound);
+    }
+
+    let opt_peer_ip = if peer_is_trusted_proxy {
+        let header_name = &config.network.reverse_proxy_ip_header_name;
+        let header_format = config.network.reverse_proxy_ip_header_format;
+
+        match parse_forwarded_header(header_name, header_format, headers) {
+            Ok(peer_ip) => Some(peer_ip),
+            Err(err) => {
+                return Err(RequestParseError::RequiredPeerIpHeaderMissing(err));
+            }
+        }
+    } else {
+        None
+    };
+
+    let request = Request::parse_http_get_path(path)
+        .map_err(|err| RequestParseError::InvalidTrackerRequest(err, opt_peer_ip))?;
+
+    Ok((Either::Right(request), opt_peer_ip))
+}
+
+fn check_limits(path: &str, headers: &[Header]) -> Result<(), LimitExceeded> {
+    if path.len() > MAX_PATH_LEN {
+        return Err(LimitExceeded::PathTooLong);
+    }
+
+    if let Some((_, query_string)) = path.split_once('?') {
+        if ::memchr::memchr_iter(b'&', query_string.as_bytes()).count() >= MAX_QUERY_PARAMETERS {
+            return Err(LimitExceeded::TooManyQueryParameters);
+        }
+    }
+
+    if headers.len() > MAX_HEADERS {
+        return Err(LimitExceeded::TooManyHeaders);
+    }
+
+    if headers
+        .iter()
+        .any(|(name, value)| name.len() + value.len() > MAX_HEADER_LEN)
+    {
+        return Err(LimitExceeded::HeaderTooLong);
+    }
+
+    Ok(())
+}
+
+/// Check if path is an announce or scrape location, optionally followed by
+/// a passkey
+fn is_tracker_path(path: &str) -> bool {
+    let location = path.split_once('?').map_or(path, |(location, _)| location);
+
+    ["/announce", "/scrape"].into_iter().any(|prefix| {
+        location
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })
+}
+
+/// HTTP/1.1 connections are persistent unless client sends
+/// `Connection: close`, HTTP/1.0 connections only if client sends
+/// `Connection: keep-alive`
+fn client_allows_keep_alive(version: Option<u8>, headers: &[httparse::Header<'_>]) -> bool {
+    let mut keep_alive = version == Some(1);
+
+    for header in headers.iter() {
+        if !header.name.eq_ignore_ascii_case("connection") {
+            continue;
+        }
+
+        if let Ok(value) = ::std::str::from_utf8(header.value) {
+            for token in value.split(',').map(str::trim) {
+                if token.eq_ignore_ascii_case("close") {
+                    return false;
+                } else if token.eq_ignore_ascii_case("keep-alive") {
+                    keep_alive = true;
+                }
+            }
+        }
+    }
+
+    keep_alive
+}
+
 fn parse_forwarded_header(
     header_name: &str,
     header_format: ReverseProxyPeerIpHeaderFormat,
-    headers: &[httparse::Header<'_>],
+    headers: &[Header],
 ) -> anyhow::Result<IpAddr> {
-    for header in headers.iter().rev() {
-        if header.name == header_name {
+    for (name, value) in headers.iter().rev() {
+        // HTTP/2 header names are always lowercase
+        if name.eq_ignore_ascii_case(header_name) {
             match header_format {
                 ReverseProxyPeerIpHeaderFormat::LastAddress => {
-                    return ::std::str::from_utf8(header.value)?
+                    return ::std::str::from_utf8(value)?
                         .split(',')
-                        .last()
+                        .next_back()
                         .ok_or(anyhow::anyhow!("no header value"))?
                         .trim()
                         .parse::<IpAddr>()
@@ -94,10 +310,15 @@ mod tests {
         let expected_ip = IpAddr::from([9, 10, 11, 12]);
 
         assert_eq!(
-            parse_request(&config, request.as_bytes())
-                .unwrap()
-                .1
-                .unwrap(),
+            parse_request(
+                &config,
+                &Default::default(),
+                request.as_bytes(),
+                config.network.runs_behind_reverse_proxy
+            )
+            .unwrap()
+            .opt_peer_ip
+            .unwrap(),
             expected_ip
         )
     }
@@ -119,10 +340,15 @@ mod tests {
         let expected_ip = IpAddr::from([200, 0, 0, 1]);
 
         assert_eq!(
-            parse_request(&config, request.as_bytes())
-                .unwrap()
-                .1
-                .unwrap(),
+            parse_request(
+                &config,
+                &Default::default(),
+                request.as_bytes(),
+                config.network.runs_behind_reverse_proxy
+            )
+            .unwrap()
+            .opt_peer_ip
+            .unwrap(),
             expected_ip
         )
     }
@@ -137,11 +363,205 @@ mod tests {
 
         request.push_str("\r\n");
 
-        let res = parse_request(&config, request.as_bytes());
+        let res = parse_request(
+            &config,
+            &Default::default(),
+            request.as_bytes(),
+            config.network.runs_behind_reverse_proxy,
+        );
 
         assert!(matches!(
             res,
             Err(RequestParseError::RequiredPeerIpHeaderMissing(_))
         ));
     }
+
+    #[test]
+    fn test_parse_peer_ip_header_untrusted_proxy() {
+        let mut config = Config::default();
+
+        config.network.runs_behind_reverse_proxy = true;
+
+        let mut request = REQUEST_START.to_string();
+
+        request.push_str("X-Forwarded-For: 1.2.3.4\r\n");
+        request.push_str("\r\n");
+
+        let parsed =
+            parse_request(&config, &Default::default(), request.as_bytes(), false).unwrap();
+
+        assert_eq!(parsed.opt_peer_ip, None);
+    }
+
+    #[test]
+    fn test_parse_peer_ip_header_invalid_request() {
+        let mut config = Config::default();
+
+        config.network.runs_behind_reverse_proxy = true;
+
+        let mut request = REQUEST_START.replacen("info_hash", "info_hush", 1);
+
+        request.push_str("X-Forwarded-For: 1.2.3.4\r\n");
+        request.push_str("\r\n");
+
+        let res = parse_request(&config, &Default::default(), request.as_bytes(), true);
+
+        assert!(matches!(
+            res,
+            Err(RequestParseError::InvalidTrackerRequest(_, Some(ip))) if ip == IpAddr::from([1, 2, 3, 4])
+        ));
+    }
+
+    #[test]
+    fn test_parse_keep_alive() {
+        let config = Config::default();
+
+        let parse_keep_alive = |request_start: &str, headers: &str| {
+            let request = format!("{}{}\r\n", request_start, headers);
+
+            parse_request(
+                &config,
+                &Default::default(),
+                request.as_bytes(),
+                config.network.runs_behind_reverse_proxy,
+            )
+            .unwrap()
+            .keep_alive
+        };
+
+        let request_start_http_1_0 = REQUEST_START.replacen("HTTP/1.1", "HTTP/1.0", 1);
+
+        assert!(parse_keep_alive(REQUEST_START, ""));
+        assert!(parse_keep_alive(
+            REQUEST_START,
+            "Connection: keep-alive\r\n"
+        ));
+        assert!(!parse_keep_alive(REQUEST_START, "Connection: close\r\n"));
+        assert!(!parse_keep_alive(
+            REQUEST_START,
+            "connection: Upgrade, Close\r\n"
+        ));
+
+        assert!(!parse_keep_alive(&request_start_http_1_0, ""));
+        assert!(parse_keep_alive(
+            &request_start_http_1_0,
+            "Connection: Keep-Alive\r\n"
+        ));
+    }
+
+    #[test]
+    fn test_parse_limits() {
+        let config = Config::default();
+
+        let parse_limit_exceeded = |request: &str| match parse_request(
+            &config,
+            &Default::default(),
+            request.as_bytes(),
+            config.network.runs_behind_reverse_proxy,
+        ) {
+            Err(RequestParseError::LimitExceeded(limit)) => Some(limit),
+            _ => None,
+        };
+
+        let mut request = REQUEST_START.to_string();
+
+        request.push_str("\r\n");
+
+        assert_eq!(parse_limit_exceeded(&request), None);
+
+        let padding = "&a=b".repeat(MAX_QUERY_PARAMETERS);
+
+        let request = REQUEST_START.replacen(" HTTP/1.1", &format!("{} HTTP/1.1", padding), 1);
+
+        assert_eq!(
+            parse_limit_exceeded(&format!("{}\r\n", request)),
+            Some(LimitExceeded::TooManyQueryParameters)
+        );
+
+        let padding = "&a=".to_string() + &"b".repeat(MAX_PATH_LEN);
+
+        let request = REQUEST_START.replacen(" HTTP/1.1", &format!("{} HTTP/1.1", padding), 1);
+
+        assert_eq!(
+            parse_limit_exceeded(&format!("{}\r\n", request)),
+            Some(LimitExceeded::PathTooLong)
+        );
+
+        let mut request = REQUEST_START.to_string();
+
+        request.push_str(&format!("User-Agent: {}\r\n", "a".repeat(MAX_HEADER_LEN)));
+        request.push_str("\r\n");
+
+        assert_eq!(
+            parse_limit_exceeded(&request),
+            Some(LimitExceeded::HeaderTooLong)
+        );
+
+        let mut request = REQUEST_START.to_string();
+
+        for _ in 0..MAX_HEADERS {
+            request.push_str("X-Header: a\r\n");
+        }
+
+        request.push_str("\r\n");
+
+        assert_eq!(
+            parse_limit_exceeded(&request),
+            Some(LimitExceeded::TooManyHeaders)
+        );
+    }
+
+    #[test]
+    fn test_limit_exceeded_from_incomplete_request() {
+        assert_eq!(
+            LimitExceeded::from_incomplete_request(b"GET /announce?info_hash=aaaa"),
+            LimitExceeded::PathTooLong
+        );
+        assert_eq!(
+            LimitExceeded::from_incomplete_request(REQUEST_START.as_bytes()),
+            LimitExceeded::HeaderTooLong
+        );
+    }
+
+    #[test]
+    fn test_parse_method_and_path() {
+        let config = Config::default();
+
+        let parse = |request: &str| {
+            parse_request(
+                &config,
+                &Default::default(),
+                request.as_bytes(),
+                config.network.runs_behind_reverse_proxy,
+            )
+        };
+
+        let request = format!("{}\r\n", REQUEST_START);
+
+        assert!(parse(&request).is_ok());
+        assert!(matches!(
+            parse(&request.replacen("GET", "POST", 1)),
+            Err(RequestParseError::MethodNotAllowed)
+        ));
+        assert!(matches!(
+            parse(&request.replacen("/announce", "/", 1)),
+            Err(RequestParseError::NotFound)
+        ));
+        assert!(matches!(
+            parse(&request.replacen("/announce", "/announcement", 1)),
+            Err(RequestParseError::NotFound)
+        ));
+        assert!(matches!(
+            parse(&request.replacen("info_hash", "info_hush", 1)),
+            Err(RequestParseError::InvalidTrackerRequest(_, None))
+        ));
+
+        assert!(is_tracker_path("/announce"));
+        assert!(is_tracker_path("/announce/passkey?a=b"));
+        assert!(is_tracker_path("/scrape"));
+        assert!(is_tracker_path("/scrape?info_hash=a"));
+        assert!(!is_tracker_path("/robots.txt"));
+        assert!(!is_tracker_path("/scraper"));
+        assert!(!is_tracker_path("announce"));
+    }
 }
@@ -0,0 +1,237 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::path::Path;
+use std::rc::Rc;
+
+use anyhow::Context;
+use bytes::Bytes;
+
+use crate::config::StaticRoutesConfig;
+
+use super::request::Header;
+
+const HTML_CONTENT_TYPE: &str = "text/html; charset=utf-8";
+const TEXT_CONTENT_TYPE: &str = "text/plain; charset=utf-8";
+
+/// Path with fixed response body
+pub struct StaticRoute {
+    pub path: &'static str,
+    pub content_type: &'static str,
+    pub cache_control: String,
+    /// Only set for cacheable routes
+    pub opt_etag: Option<String>,
+    pub body: Bytes,
+}
+
+/// Routes answered directly by socket workers, regardless of HTTP version
+#[derive(Default)]
+pub struct StaticRoutes {
+    routes: Vec<Rc<StaticRoute>>,
+}
+
+impl StaticRoutes {
+    pub fn new(config: &StaticRoutesConfig) -> anyhow::Result<Self> {
+        let mut routes = Vec::new();
+
+        let cache_control = format!("public, max-age={}", config.cache_max_age);
+
+        for (path, content_type, file_path) in [
+            ("/", HTML_CONTENT_TYPE, &config.index_file),
+            ("/robots.txt", TEXT_CONTENT_TYPE, &config.robots_txt_file),
+        ] {
+            if file_path.as_os_str().is_empty() {
+                continue;
+            }
+
+            routes.push(Rc::new(StaticRoute::from_file(
+                path,
+                content_type,
+                cache_control.clone(),
+                file_path,
+            )?));
+        }
+
+        if config.health_check {
+            routes.push(Rc::new(StaticRoute {
+                path: "/healthz",
+                content_type: TEXT_CONTENT_TYPE,
+                cache_control: "no-store".into(),
+                opt_etag: None,
+                body: Bytes::from_static(b"ok\n"),
+            }));
+        }
+
+        Ok(Self { routes })
+    }
+
+    /// Get response if path (ignoring query string) belongs to a static
+    /// route
+    pub fn response(&self, path: &str, headers: &[Header]) -> Option<StaticResponse> {
+        let path = path.split_once('?').map_or(path, |(path, _)| path);
+
+        let route = self.routes.iter().find(|route| route.path == path)?;
+
+        let not_modified = route.opt_etag.as_ref().is_some_and(|etag| {
+            headers.iter().any(|(name, value)| {
+                name.eq_ignore_ascii_case("if-none-match") && etag_matches(etag, value)
+            })
+        });
+
+        Some(StaticResponse {
+            route: route.clone(),
+            not_modified,
+        })
+    }
+}
+
+impl StaticRoute {
+    fn from_file(
+        path: &'static str,
+        content_type: &'static str,
+        cache_control: String,
+        file_path: &Path,
+    ) -> anyhow::Result<Self> {
+        let body = ::std::fs::read(file_path)
+            .with_context(|| format!("read static route file {}", file_path.display()))?;
+
+        let mut hasher = DefaultHasher::new();
+
+        body.hash(&mut hasher);
+
+        Ok(Self {
+            path,
+            content_type,
+            cache_control,
+            opt_etag: Some(format!("\"{:016x}\"", hasher.finish())),
+            body: body.into(),
+        })
+    }
+}
+
+pub struct StaticResponse {
+    pub route: Rc<StaticRoute>,
+    /// Client sent ETag of current body in `If-None-Match`, so respond with
+    /// `304 Not Modified` and no body
+    pub not_modified: bool,
+}
+
+impl StaticResponse {
+    pub fn status_code(&self) -> u16 {
+        if self.not_modified {
+            304
+        } else {
+            200
+        }
+    }
+
+    pub fn body(&self) -> Option<&Bytes> {
+        (!self.not_modified).then_some(&self.route.body)
+    }
+
+    /// Write full HTTP/1.1 response
+    pub fn write_http1(&self, output: &mut Vec<u8>, keep_alive: bool) {
+        let route = &self.route;
+
+        if let Some(body) = self.body() {
+            write!(
+                output,
+                "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\n",
+                route.content_type,
+                body.len()
+            )
+            .expect("write to vec");
+        } else {
+            output.extend_from_slice(b"HTTP/1.1 304 Not Modified\r\n");
+        }
+
+        write!(output, "Cache-Control: {}\r\n", route.cache_control).expect("write to vec");
+
+        if let Some(etag) = route.opt_etag.as_ref() {
+            write!(output, "ETag: {}\r\n", etag).expect("write to vec");
+        }
+
+        output.extend_from_slice(if keep_alive {
+            b"Connection: keep-alive\r\n\r\n"
+        } else {
+            b"Connection: close\r\n\r\n"
+        });
+
+        if let Some(body) = self.body() {
+            output.extend_from_slice(body);
+        }
+    }
+}
+
+/// Check if `If-None-Match` header value contains ETag (or is `*`)
+fn etag_matches(etag: &str, header_value: &[u8]) -> bool {
+    let Ok(header_value) = ::std::str::from_utf8(header_value) else {
+        return false;
+    };
+
+    header_value.split(',').map(str::trim).any(|candidate| {
+        candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == etag
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_static_routes() {
+        let index_path = ::std::env::temp_dir().join("aquatic_http_test_static_routes.html");
+
+        ::std::fs::write(&index_path, b"<h1>tracker</h1>").unwrap();
+
+        let config = StaticRoutesConfig {
+            index_file: index_path.clone(),
+            robots_txt_file: "".into(),
+            health_check: true,
+            cache_max_age: 60,
+        };
+
+        let routes = StaticRoutes::new(&config).unwrap();
+
+        ::std::fs::remove_file(index_path).unwrap();
+
+        assert!(routes.response("/robots.txt", &[]).is_none());
+        assert!(routes.response("/announce", &[]).is_none());
+
+        let response = routes.response("/?a=b", &[]).unwrap();
+        let etag = response.route.opt_etag.clone().unwrap();
+
+        assert!(!response.not_modified);
+
+        let mut output = Vec::new();
+
+        response.write_http1(&mut output, true);
+
+        let expected = format!("HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: 16\r\nCache-Control: public, max-age=60\r\nETag: {}\r\nConnection: keep-alive\r\n\r\n<h1>tracker</h1>", etag);
+
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+
+        let if_none_match = format!("\"other\", W/{}", etag);
+        let response = routes
+            .response("/", &[("If-None-Match", if_none_match.as_bytes())])
+            .unwrap();
+
+        assert!(response.not_modified);
+        assert!(response.body().is_none());
+
+        let mut output = Vec::new();
+
+        response.write_http1(&mut output, false);
+
+        let expected = format!("HTTP/1.1 304 Not Modified\r\nCache-Control: public, max-age=60\r\nETag: {}\r\nConnection: close\r\n\r\n", etag);
+
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+
+        let response = routes
+            .response("/healthz", &[("if-none-match", b"*")])
+            .unwrap();
+
+        assert!(!response.not_modified);
+        assert_eq!(response.body().unwrap().as_ref(), b"ok\n");
+    }
+}
@@ -2,6 +2,7 @@ mod storage;
 
 use std::cell::RefCell;
 use std::rc::Rc;
+use std::sync::Arc;
 use std::time::Duration;
 
 use futures_lite::{Stream, StreamExt};
@@ -32,6 +33,7 @@ pub async fn run_swarm_worker(
 
     let torrents = Rc::new(RefCell::new(TorrentMaps::new(worker_index)));
     let access_list = state.access_list;
+    let full_scrape_snapshots = state.full_scrape_snapshots;
 
     // Periodically clean torrents
     TimerActionRepeat::repeat(enclose!((config, torrents, access_list) move || {
@@ -42,6 +44,21 @@ pub async fn run_swarm_worker(
         })()
     }));
 
+    // Periodically publish scrape statistics for all torrents, so that
+    // socket workers can stream full scrape responses without involving
+    // swarm workers
+    if config.protocol.enable_full_scrape {
+        TimerActionRepeat::repeat(enclose!((config, torrents, full_scrape_snapshots) move || {
+            enclose!((config, torrents, full_scrape_snapshots) move || async move {
+                let snapshot = torrents.borrow().full_scrape_snapshot();
+
+                full_scrape_snapshots[worker_index].store(Arc::new(snapshot));
+
+                Some(Duration::from_secs(config.protocol.full_scrape_update_interval))
+            })()
+        }));
+    }
+
     let max_peer_age = config.cleaning.max_peer_age;
     let peer_valid_until = Rc::new(RefCell::new(ValidUntil::new(
         server_start_instant,
@@ -1,19 +1,25 @@
-use std::collections::BTreeMap;
+use std::collections::hash_map::{Entry, RandomState};
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{BuildHasher, Hash, Hasher};
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::num::NonZeroU64;
 use std::sync::Arc;
 
 use arrayvec::ArrayVec;
+use compact_str::{format_compact, CompactString};
 use rand::Rng;
 
 use aquatic_common::access_list::{create_access_list_cache, AccessListArcSwap, AccessListCache};
 use aquatic_common::{
-    CanonicalSocketAddr, IndexMap, SecondsSinceServerStart, ServerStartInstant, ValidUntil,
+    jitter_announce_interval, CanonicalSocketAddr, Clock, IndexMap, SecondsSinceServerStart,
+    ValidUntil,
 };
 use aquatic_http_protocol::common::*;
 use aquatic_http_protocol::request::*;
 use aquatic_http_protocol::response::ResponsePeer;
 use aquatic_http_protocol::response::*;
 
+use crate::common::{FullScrapeFiles, FullScrapeSnapshot, TrackerError};
 use crate::config::Config;
 
 const SMALL_PEER_MAP_CAPACITY: usize = 4;
@@ -26,6 +32,8 @@ impl Ip for Ipv6Addr {}
 pub struct TorrentMaps {
     pub ipv4: TorrentMap<Ipv4Addr>,
     pub ipv6: TorrentMap<Ipv6Addr>,
+    /// Hashes `key` parameters, so that they don't need to be stored
+    key_hasher: RandomState,
 }
 
 impl TorrentMaps {
@@ -33,6 +41,7 @@ impl TorrentMaps {
         Self {
             ipv4: TorrentMap::new(worker_index, true),
             ipv6: TorrentMap::new(worker_index, false),
+            key_hasher: RandomState::new(),
         }
     }
 
@@ -43,47 +52,101 @@ impl TorrentMaps {
         valid_until: ValidUntil,
         peer_addr: CanonicalSocketAddr,
         request: AnnounceRequest,
-    ) -> AnnounceResponse {
-        match peer_addr.get().ip() {
+    ) -> Response {
+        let info_hash = request.info_hash;
+        let compact = request.compact;
+        let max_num_peers_to_take = max_num_peers_to_take(config, request.numwant);
+        let credentials = SessionCredentials::new(config, &self.key_hasher, &request);
+        let store_peer = !config.bogon_filter.blocks(peer_addr.get().ip());
+
+        let response = match peer_addr.get().ip() {
             IpAddr::V4(peer_ip_address) => {
-                let (seeders, leechers, response_peers) =
-                    self.ipv4.upsert_peer_and_get_response_peers(
-                        config,
-                        rng,
-                        valid_until,
-                        peer_ip_address,
-                        request,
-                    );
+                let AnnounceResponseData {
+                    seeders,
+                    leechers,
+                    response_peers,
+                    opt_tracker_id,
+                } = match self.ipv4.upsert_peer_and_get_response_peers(
+                    config,
+                    rng,
+                    valid_until,
+                    store_peer,
+                    peer_ip_address,
+                    request,
+                    credentials,
+                ) {
+                    Ok(response_data) => response_data,
+                    Err(err) => return err.into(),
+                };
+
+                let response_peers_v6 = if config.protocol.include_other_ip_version_peers {
+                    self.ipv6
+                        .get_response_peers(rng, &info_hash, max_num_peers_to_take)
+                } else {
+                    Vec::new()
+                };
 
                 AnnounceResponse {
                     complete: seeders,
                     incomplete: leechers,
-                    announce_interval: config.protocol.peer_announce_interval,
+                    announce_interval: jitter_announce_interval(
+                        rng,
+                        config.protocol.peer_announce_interval,
+                        config.protocol.peer_announce_interval_jitter,
+                    ),
                     peers: ResponsePeerListV4(response_peers),
-                    peers6: ResponsePeerListV6(vec![]),
+                    peers6: ResponsePeerListV6(response_peers_v6),
+                    tracker_id: opt_tracker_id.map(format_tracker_id),
                     warning_message: None,
+                    external_ip: None,
+                    compact,
                 }
             }
             IpAddr::V6(peer_ip_address) => {
-                let (seeders, leechers, response_peers) =
-                    self.ipv6.upsert_peer_and_get_response_peers(
-                        config,
-                        rng,
-                        valid_until,
-                        peer_ip_address,
-                        request,
-                    );
+                let AnnounceResponseData {
+                    seeders,
+                    leechers,
+                    response_peers,
+                    opt_tracker_id,
+                } = match self.ipv6.upsert_peer_and_get_response_peers(
+                    config,
+                    rng,
+                    valid_until,
+                    store_peer,
+                    peer_ip_address,
+                    request,
+                    credentials,
+                ) {
+                    Ok(response_data) => response_data,
+                    Err(err) => return err.into(),
+                };
+
+                let response_peers_v4 = if config.protocol.include_other_ip_version_peers {
+                    self.ipv4
+                        .get_response_peers(rng, &info_hash, max_num_peers_to_take)
+                } else {
+                    Vec::new()
+                };
 
                 AnnounceResponse {
                     complete: seeders,
                     incomplete: leechers,
-                    announce_interval: config.protocol.peer_announce_interval,
-                    peers: ResponsePeerListV4(vec![]),
+                    announce_interval: jitter_announce_interval(
+                        rng,
+                        config.protocol.peer_announce_interval,
+                        config.protocol.peer_announce_interval_jitter,
+                    ),
+                    peers: ResponsePeerListV4(response_peers_v4),
                     peers6: ResponsePeerListV6(response_peers),
+                    tracker_id: opt_tracker_id.map(format_tracker_id),
                     warning_message: None,
+                    external_ip: None,
+                    compact,
                 }
             }
-        }
+        };
+
+        Response::Announce(response)
     }
 
     pub fn handle_scrape_request(
@@ -99,6 +162,13 @@ impl TorrentMaps {
         }
     }
 
+    pub fn full_scrape_snapshot(&self) -> FullScrapeSnapshot {
+        FullScrapeSnapshot {
+            ipv4: self.ipv4.full_scrape_files(),
+            ipv6: self.ipv6.full_scrape_files(),
+        }
+    }
+
     #[cfg(feature = "metrics")]
     pub fn update_torrent_metrics(&self) {
         self.ipv4.torrent_gauge.set(self.ipv4.torrents.len() as f64);
@@ -109,11 +179,10 @@ impl TorrentMaps {
         &mut self,
         config: &Config,
         access_list: &Arc<AccessListArcSwap>,
-        server_start_instant: ServerStartInstant,
+        clock: impl Clock,
     ) {
         let mut access_list_cache = create_access_list_cache(access_list);
-
-        let now = server_start_instant.seconds_elapsed();
+        let now = clock.seconds_elapsed();
 
         self.ipv4.clean(config, &mut access_list_cache, now);
         self.ipv6.clean(config, &mut access_list_cache, now);
@@ -122,6 +191,9 @@ impl TorrentMaps {
 
 pub struct TorrentMap<I: Ip> {
     torrents: IndexMap<InfoHash, TorrentData<I>>,
+    /// Number of completed announces of torrents that were removed because
+    /// they had no peers left, restored if peers announce to them again
+    removed_torrent_completed_counts: HashMap<InfoHash, usize>,
     #[cfg(feature = "metrics")]
     peer_gauge: ::metrics::Gauge,
     #[cfg(feature = "metrics")]
@@ -161,6 +233,7 @@ impl<I: Ip> TorrentMap<I> {
 
         Self {
             torrents: Default::default(),
+            removed_torrent_completed_counts: Default::default(),
             #[cfg(feature = "metrics")]
             peer_gauge,
             #[cfg(feature = "metrics")]
@@ -168,28 +241,57 @@ impl<I: Ip> TorrentMap<I> {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn upsert_peer_and_get_response_peers(
         &mut self,
         config: &Config,
         rng: &mut impl Rng,
         valid_until: ValidUntil,
+        store_peer: bool,
         peer_ip_address: I,
         request: AnnounceRequest,
-    ) -> (usize, usize, Vec<ResponsePeer<I>>) {
+        credentials: SessionCredentials,
+    ) -> Result<AnnounceResponseData<I>, TrackerError> {
         self.torrents
             .entry(request.info_hash)
-            .or_default()
+            .or_insert_with(|| TorrentData {
+                num_completed: self
+                    .removed_torrent_completed_counts
+                    .remove(&request.info_hash)
+                    .unwrap_or(0),
+                ..Default::default()
+            })
             .upsert_peer_and_get_response_peers(
                 config,
                 rng,
                 request,
+                credentials,
                 peer_ip_address,
                 valid_until,
+                store_peer,
                 #[cfg(feature = "metrics")]
                 &self.peer_gauge,
             )
     }
 
+    /// Get response peers for a torrent without announcing to it
+    fn get_response_peers(
+        &self,
+        rng: &mut impl Rng,
+        info_hash: &InfoHash,
+        max_num_peers_to_take: usize,
+    ) -> Vec<ResponsePeer<I>> {
+        match self.torrents.get(info_hash).map(|t| &t.peer_map) {
+            Some(PeerMap::Small(peer_map)) => {
+                peer_map.extract_response_peers(max_num_peers_to_take)
+            }
+            Some(PeerMap::Large(peer_map)) => {
+                peer_map.extract_response_peers(rng, max_num_peers_to_take)
+            }
+            None => Vec::new(),
+        }
+    }
+
     fn handle_scrape_request(&mut self, config: &Config, request: ScrapeRequest) -> ScrapeResponse {
         let num_to_take = request
             .info_hashes
@@ -205,10 +307,14 @@ impl<I: Ip> TorrentMap<I> {
                 .torrents
                 .get(&info_hash)
                 .map(|torrent_data| torrent_data.scrape_statistics())
-                .unwrap_or(ScrapeStatistics {
+                .unwrap_or_else(|| ScrapeStatistics {
                     complete: 0,
                     incomplete: 0,
-                    downloaded: 0,
+                    downloaded: self
+                        .removed_torrent_completed_counts
+                        .get(&info_hash)
+                        .copied()
+                        .unwrap_or(0),
                 });
 
             response.files.insert(info_hash, stats);
@@ -217,6 +323,39 @@ impl<I: Ip> TorrentMap<I> {
         response
     }
 
+    fn full_scrape_files(&self) -> FullScrapeFiles {
+        let mut files = Vec::with_capacity(self.torrents.len());
+        let mut encoded_len = 0;
+
+        for (info_hash, torrent_data) in self.torrents.iter() {
+            let statistics = torrent_data.scrape_statistics();
+
+            encoded_len +=
+                ScrapeResponse::write_file_entry(&mut ::std::io::sink(), info_hash, &statistics)
+                    .expect("write to sink");
+
+            files.push((*info_hash, statistics));
+        }
+
+        for (info_hash, num_completed) in self.removed_torrent_completed_counts.iter() {
+            let statistics = ScrapeStatistics {
+                complete: 0,
+                incomplete: 0,
+                downloaded: *num_completed,
+            };
+
+            encoded_len +=
+                ScrapeResponse::write_file_entry(&mut ::std::io::sink(), info_hash, &statistics)
+                    .expect("write to sink");
+
+            files.push((*info_hash, statistics));
+        }
+
+        files.sort_unstable_by_key(|(info_hash, _)| *info_hash);
+
+        FullScrapeFiles { files, encoded_len }
+    }
+
     fn clean(
         &mut self,
         config: &Config,
@@ -225,6 +364,13 @@ impl<I: Ip> TorrentMap<I> {
     ) {
         let mut total_num_peers = 0;
 
+        self.removed_torrent_completed_counts
+            .retain(|info_hash, _| {
+                access_list_cache
+                    .load()
+                    .allows(config.access_list.mode, &info_hash.0)
+            });
+
         self.torrents.retain(|info_hash, torrent_data| {
             if !access_list_cache
                 .load()
@@ -233,13 +379,22 @@ impl<I: Ip> TorrentMap<I> {
                 return false;
             }
 
-            let num_peers = match torrent_data {
-                TorrentData::Small(t) => t.clean_and_get_num_peers(now),
-                TorrentData::Large(t) => t.clean_and_get_num_peers(now),
+            let num_peers = match &mut torrent_data.peer_map {
+                PeerMap::Small(t) => t.clean_and_get_num_peers(now),
+                PeerMap::Large(t) => t.clean_and_get_num_peers(now),
             };
 
             total_num_peers += num_peers as u64;
 
+            if num_peers == 0
+                && torrent_data.num_completed > 0
+                && self.removed_torrent_completed_counts.len()
+                    < config.cleaning.max_removed_torrent_completed_counts
+            {
+                self.removed_torrent_completed_counts
+                    .insert(*info_hash, torrent_data.num_completed);
+            }
+
             num_peers > 0
         });
 
@@ -250,39 +405,230 @@ impl<I: Ip> TorrentMap<I> {
     }
 }
 
-pub enum TorrentData<I: Ip> {
-    Small(SmallPeerMap<I>),
-    Large(LargePeerMap<I>),
+fn max_num_peers_to_take(config: &Config, numwant: Option<usize>) -> usize {
+    match numwant {
+        Some(0) | None => config.protocol.max_peers,
+        Some(numwant) => numwant.min(config.protocol.max_peers),
+    }
+}
+
+pub struct AnnounceResponseData<I: Ip> {
+    seeders: usize,
+    leechers: usize,
+    response_peers: Vec<ResponsePeer<I>>,
+    /// Tracker id of announcing peer, if it was stored
+    opt_tracker_id: Option<u64>,
+}
+
+pub struct TorrentData<I: Ip> {
+    peer_map: PeerMap<I>,
+    /// Number of announce requests with event `completed`
+    ///
+    /// Kept when inactive peers are cleaned. When the torrent is removed
+    /// because it has no peers left, the count is moved to
+    /// [`TorrentMap::removed_torrent_completed_counts`].
+    num_completed: usize,
 }
 
 impl<I: Ip> TorrentData<I> {
+    #[allow(clippy::too_many_arguments)]
     fn upsert_peer_and_get_response_peers(
         &mut self,
         config: &Config,
         rng: &mut impl Rng,
         request: AnnounceRequest,
+        credentials: SessionCredentials,
         ip_address: I,
         valid_until: ValidUntil,
+        store_peer: bool,
         #[cfg(feature = "metrics")] peer_gauge: &::metrics::Gauge,
-    ) -> (usize, usize, Vec<ResponsePeer<I>>) {
-        let max_num_peers_to_take = match request.numwant {
-            Some(0) | None => config.protocol.max_peers,
-            Some(numwant) => numwant.min(config.protocol.max_peers),
+    ) -> Result<AnnounceResponseData<I>, TrackerError> {
+        if self
+            .peer_map
+            .announced_too_early(config, &request, ip_address, valid_until)
+        {
+            return Err(TrackerError::AnnouncingTooOften);
+        }
+
+        if self
+            .peer_map
+            .missing_started_event(config, &request, credentials, ip_address)
+        {
+            return Err(TrackerError::StartedEventRequired);
+        }
+
+        if request.event == AnnounceEvent::Completed {
+            self.num_completed = self.num_completed.saturating_add(1);
+        }
+
+        let response_data = self.peer_map.upsert_peer_and_get_response_peers(
+            config,
+            rng,
+            request,
+            credentials,
+            ip_address,
+            valid_until,
+            store_peer,
+            #[cfg(feature = "metrics")]
+            peer_gauge,
+        );
+
+        Ok(response_data)
+    }
+
+    fn scrape_statistics(&self) -> ScrapeStatistics {
+        let (seeders, leechers) = match &self.peer_map {
+            PeerMap::Small(peer_map) => peer_map.num_seeders_leechers(),
+            PeerMap::Large(peer_map) => peer_map.num_seeders_leechers(),
+        };
+
+        ScrapeStatistics {
+            complete: seeders,
+            incomplete: leechers,
+            downloaded: self.num_completed,
+        }
+    }
+}
+
+impl<I: Ip> Default for TorrentData<I> {
+    fn default() -> Self {
+        Self {
+            peer_map: Default::default(),
+            num_completed: 0,
+        }
+    }
+}
+
+pub enum PeerMap<I: Ip> {
+    Small(SmallPeerMap<I>),
+    Large(LargePeerMap<I>),
+}
+
+impl<I: Ip> PeerMap<I> {
+    /// Check if request is a regular announce (without event) from a stored
+    /// peer, sent sooner than `protocol.min_announce_interval` after its
+    /// previous accepted announce
+    fn announced_too_early(
+        &self,
+        config: &Config,
+        request: &AnnounceRequest,
+        ip_address: I,
+        valid_until: ValidUntil,
+    ) -> bool {
+        if config.protocol.min_announce_interval == 0 || request.event != AnnounceEvent::Empty {
+            return false;
+        }
+
+        let key = ResponsePeer {
+            ip_address,
+            port: request.port,
         };
 
-        let status = PeerStatus::from_event_and_bytes_left(request.event, request.bytes_left);
+        self.get(&key).is_some_and(|peer| {
+            valid_until.seconds_since(peer.valid_until) < config.protocol.min_announce_interval
+        })
+    }
+
+    /// Check if `protocol.require_started_event` is set and request has
+    /// event `stopped` or `completed`, but the peer it applies to isn't
+    /// stored or wasn't stored with event `started`
+    fn missing_started_event(
+        &self,
+        config: &Config,
+        request: &AnnounceRequest,
+        credentials: SessionCredentials,
+        ip_address: I,
+    ) -> bool {
+        if !config.protocol.require_started_event
+            || !matches!(
+                request.event,
+                AnnounceEvent::Stopped | AnnounceEvent::Completed
+            )
+        {
+            return false;
+        }
+
+        let key = ResponsePeer {
+            ip_address,
+            port: request.port,
+        };
+
+        // Look up peer the same way as upsert_peer_and_get_response_peers
+        let opt_peer = self.get(&key).or_else(|| {
+            credentials
+                .is_some()
+                .then(|| self.get_by_session(credentials))
+                .flatten()
+        });
+
+        !opt_peer.is_some_and(|peer| peer.started)
+    }
+
+    fn get(&self, key: &ResponsePeer<I>) -> Option<&Peer> {
+        match self {
+            Self::Small(peer_map) => peer_map.get(key),
+            Self::Large(peer_map) => peer_map.peers.get(key),
+        }
+    }
+
+    fn get_by_session(&self, credentials: SessionCredentials) -> Option<&Peer> {
+        match self {
+            Self::Small(peer_map) => peer_map
+                .0
+                .iter()
+                .map(|(_, peer)| peer)
+                .find(|peer| credentials.match_session(&peer.session)),
+            Self::Large(peer_map) => peer_map
+                .get_session_key(credentials)
+                .and_then(|key| peer_map.peers.get(&key)),
+        }
+    }
+
+    /// If `store_peer` is false, the announcing peer is handled as if it had
+    /// stopped
+    #[allow(clippy::too_many_arguments)]
+    fn upsert_peer_and_get_response_peers(
+        &mut self,
+        config: &Config,
+        rng: &mut impl Rng,
+        request: AnnounceRequest,
+        credentials: SessionCredentials,
+        ip_address: I,
+        valid_until: ValidUntil,
+        store_peer: bool,
+        #[cfg(feature = "metrics")] peer_gauge: &::metrics::Gauge,
+    ) -> AnnounceResponseData<I> {
+        let max_num_peers_to_take = max_num_peers_to_take(config, request.numwant);
+
+        let status = if store_peer {
+            PeerStatus::from_event_and_bytes_left(request.event, request.bytes_left)
+        } else {
+            PeerStatus::Stopped
+        };
 
         let peer_map_key = ResponsePeer {
             ip_address,
             port: request.port,
         };
 
+        // Events from peers whose address changed are applied to the peer
+        // stored under the previous address if session credentials match
+        let match_session = credentials.is_some()
+            && matches!(
+                request.event,
+                AnnounceEvent::Stopped | AnnounceEvent::Completed
+            );
+
         // Create the response before inserting the peer. This means that we
         // don't have to filter it out from the response peers, and that the
         // reported number of seeders/leechers will not include it
         let (response_data, opt_removed_peer) = match self {
             Self::Small(peer_map) => {
-                let opt_removed_peer = peer_map.remove(&peer_map_key);
+                let opt_removed_peer = peer_map.remove(&peer_map_key).or_else(|| {
+                    match_session
+                        .then(|| peer_map.remove_by_session(credentials))
+                        .flatten()
+                });
 
                 let (seeders, leechers) = peer_map.num_seeders_leechers();
                 let response_peers = peer_map.extract_response_peers(max_num_peers_to_take);
@@ -297,7 +643,11 @@ impl<I: Ip> TorrentData<I> {
                 ((seeders, leechers, response_peers), opt_removed_peer)
             }
             Self::Large(peer_map) => {
-                let opt_removed_peer = peer_map.remove_peer(&peer_map_key);
+                let opt_removed_peer = peer_map.remove_peer(&peer_map_key).or_else(|| {
+                    match_session
+                        .then(|| peer_map.remove_peer_by_session(credentials))
+                        .flatten()
+                });
 
                 let (seeders, leechers) = peer_map.num_seeders_leechers();
                 let response_peers = peer_map.extract_response_peers(rng, max_num_peers_to_take);
@@ -314,50 +664,63 @@ impl<I: Ip> TorrentData<I> {
             }
         };
 
-        match status {
+        let (seeders, leechers, response_peers) = response_data;
+
+        let opt_tracker_id = match status {
             PeerStatus::Leeching | PeerStatus::Seeding => {
                 #[cfg(feature = "metrics")]
                 if opt_removed_peer.is_none() {
                     peer_gauge.increment(1.0);
                 }
 
+                let session = if config.protocol.peer_sessions {
+                    PeerSession {
+                        tracker_id: opt_removed_peer
+                            .map(|peer| peer.session.tracker_id)
+                            .unwrap_or_else(|| rand::thread_rng().gen_range(1..=u64::MAX)),
+                        key_hash: credentials
+                            .key_hash
+                            .or(opt_removed_peer.and_then(|peer| peer.session.key_hash)),
+                    }
+                } else {
+                    PeerSession::default()
+                };
+
                 let peer = Peer {
                     is_seeder: status == PeerStatus::Seeding,
+                    started: request.event == AnnounceEvent::Started
+                        || opt_removed_peer.is_some_and(|peer| peer.started),
                     valid_until,
+                    session,
                 };
 
                 match self {
                     Self::Small(peer_map) => peer_map.insert(peer_map_key, peer),
                     Self::Large(peer_map) => peer_map.insert(peer_map_key, peer),
                 }
+
+                config.protocol.peer_sessions.then_some(session.tracker_id)
             }
-            PeerStatus::Stopped =>
-            {
+            PeerStatus::Stopped => {
                 #[cfg(feature = "metrics")]
                 if opt_removed_peer.is_some() {
                     peer_gauge.decrement(1.0);
                 }
-            }
-        };
-
-        response_data
-    }
 
-    fn scrape_statistics(&self) -> ScrapeStatistics {
-        let (seeders, leechers) = match self {
-            Self::Small(peer_map) => peer_map.num_seeders_leechers(),
-            Self::Large(peer_map) => peer_map.num_seeders_leechers(),
+                None
+            }
         };
 
-        ScrapeStatistics {
-            complete: seeders,
-            incomplete: leechers,
-            downloaded: 0,
+        AnnounceResponseData {
+            seeders,
+            leechers,
+            response_peers,
+            opt_tracker_id,
         }
     }
 }
 
-impl<I: Ip> Default for TorrentData<I> {
+impl<I: Ip> Default for PeerMap<I> {
     fn default() -> Self {
         Self::Small(SmallPeerMap(ArrayVec::default()))
     }
@@ -381,6 +744,10 @@ impl<I: Ip> SmallPeerMap<I> {
         (seeders, leechers)
     }
 
+    fn get(&self, key: &ResponsePeer<I>) -> Option<&Peer> {
+        self.0.iter().find(|(k, _)| k == key).map(|(_, peer)| peer)
+    }
+
     fn insert(&mut self, key: ResponsePeer<I>, peer: Peer) {
         self.0.push((key, peer));
     }
@@ -395,6 +762,15 @@ impl<I: Ip> SmallPeerMap<I> {
         None
     }
 
+    fn remove_by_session(&mut self, credentials: SessionCredentials) -> Option<Peer> {
+        let index = self
+            .0
+            .iter()
+            .position(|(_, peer)| credentials.match_session(&peer.session))?;
+
+        Some(self.0.remove(index).1)
+    }
+
     fn extract_response_peers(&self, max_num_peers_to_take: usize) -> Vec<ResponsePeer<I>> {
         Vec::from_iter(self.0.iter().take(max_num_peers_to_take).map(|(k, _)| *k))
     }
@@ -407,9 +783,18 @@ impl<I: Ip> SmallPeerMap<I> {
 
     fn to_large(&self) -> LargePeerMap<I> {
         let (num_seeders, _) = self.num_seeders_leechers();
-        let peers = self.0.iter().copied().collect();
+        let mut peer_map = LargePeerMap {
+            peers: self.0.iter().copied().collect(),
+            num_seeders,
+            keys_by_tracker_id: Default::default(),
+            keys_by_key_hash: Default::default(),
+        };
+
+        for (key, peer) in self.0.iter() {
+            peer_map.index_session(*key, peer.session);
+        }
 
-        LargePeerMap { peers, num_seeders }
+        peer_map
     }
 }
 
@@ -417,6 +802,10 @@ impl<I: Ip> SmallPeerMap<I> {
 pub struct LargePeerMap<I: Ip> {
     peers: IndexMap<ResponsePeer<I>, Peer>,
     num_seeders: usize,
+    /// Keys of peers with sessions, so that they can be found without
+    /// scanning all peers. Kept in sync with `peers`.
+    keys_by_tracker_id: HashMap<u64, ResponsePeer<I>>,
+    keys_by_key_hash: HashMap<NonZeroU64, ResponsePeer<I>>,
 }
 
 impl<I: Ip> LargePeerMap<I> {
@@ -429,20 +818,75 @@ impl<I: Ip> LargePeerMap<I> {
             self.num_seeders += 1;
         }
 
-        self.peers.insert(key, peer);
+        if let Some(previous_peer) = self.peers.insert(key, peer) {
+            if previous_peer.is_seeder {
+                self.num_seeders -= 1;
+            }
+
+            self.unindex_session(key, previous_peer.session);
+        }
+
+        self.index_session(key, peer.session);
     }
 
     fn remove_peer(&mut self, key: &ResponsePeer<I>) -> Option<Peer> {
-        let opt_removed_peer = self.peers.swap_remove(key);
+        let removed_peer = self.peers.swap_remove(key)?;
 
-        if let Some(Peer {
-            is_seeder: true, ..
-        }) = opt_removed_peer
-        {
+        if removed_peer.is_seeder {
             self.num_seeders -= 1;
         }
 
-        opt_removed_peer
+        self.unindex_session(*key, removed_peer.session);
+
+        Some(removed_peer)
+    }
+
+    /// Remove peer with matching session
+    fn remove_peer_by_session(&mut self, credentials: SessionCredentials) -> Option<Peer> {
+        let key = self.get_session_key(credentials)?;
+
+        self.remove_peer(&key)
+    }
+
+    /// Get key of peer with matching session, preferring tracker id matches
+    fn get_session_key(&self, credentials: SessionCredentials) -> Option<ResponsePeer<I>> {
+        credentials
+            .tracker_id
+            .and_then(|tracker_id| self.keys_by_tracker_id.get(&tracker_id))
+            .or_else(|| {
+                credentials
+                    .key_hash
+                    .and_then(|key_hash| self.keys_by_key_hash.get(&key_hash))
+            })
+            .copied()
+    }
+
+    fn index_session(&mut self, key: ResponsePeer<I>, session: PeerSession) {
+        if session.tracker_id != 0 {
+            self.keys_by_tracker_id.insert(session.tracker_id, key);
+        }
+        if let Some(key_hash) = session.key_hash {
+            self.keys_by_key_hash.insert(key_hash, key);
+        }
+    }
+
+    /// Remove index entries of session, unless they have been taken over by
+    /// another peer
+    fn unindex_session(&mut self, key: ResponsePeer<I>, session: PeerSession) {
+        if session.tracker_id != 0 {
+            if let Entry::Occupied(entry) = self.keys_by_tracker_id.entry(session.tracker_id) {
+                if *entry.get() == key {
+                    entry.remove();
+                }
+            }
+        }
+        if let Some(key_hash) = session.key_hash {
+            if let Entry::Occupied(entry) = self.keys_by_key_hash.entry(key_hash) {
+                if *entry.get() == key {
+                    entry.remove();
+                }
+            }
+        }
     }
 
     /// Extract response peers
@@ -461,23 +905,25 @@ impl<I: Ip> LargePeerMap<I> {
             self.peers.keys().copied().collect()
         } else {
             let middle_index = self.peers.len() / 2;
-            let num_to_take_per_half = max_num_peers_to_take / 2;
+            // Take the extra peer from the second half if the number is odd
+            let num_to_take_half_one = max_num_peers_to_take / 2;
+            let num_to_take_half_two = max_num_peers_to_take - num_to_take_half_one;
 
             let offset_half_one = {
                 let from = 0;
-                let to = usize::max(1, middle_index - num_to_take_per_half);
+                let to = usize::max(1, middle_index - num_to_take_half_one);
 
                 rng.gen_range(from..to)
             };
             let offset_half_two = {
                 let from = middle_index;
-                let to = usize::max(middle_index + 1, self.peers.len() - num_to_take_per_half);
+                let to = usize::max(middle_index + 1, self.peers.len() - num_to_take_half_two);
 
                 rng.gen_range(from..to)
             };
 
-            let end_half_one = offset_half_one + num_to_take_per_half;
-            let end_half_two = offset_half_two + num_to_take_per_half;
+            let end_half_one = offset_half_one + num_to_take_half_one;
+            let end_half_two = offset_half_two + num_to_take_half_two;
 
             let mut peers = Vec::with_capacity(max_num_peers_to_take);
 
@@ -493,17 +939,28 @@ impl<I: Ip> LargePeerMap<I> {
     }
 
     fn clean_and_get_num_peers(&mut self, now: SecondsSinceServerStart) -> usize {
-        self.peers.retain(|_, peer| {
+        let mut removed_sessions = Vec::new();
+
+        self.peers.retain(|key, peer| {
             let keep = peer.valid_until.valid(now);
 
             if (!keep) & peer.is_seeder {
                 self.num_seeders -= 1;
             }
+            if !keep {
+                removed_sessions.push((*key, peer.session));
+            }
 
             keep
         });
 
+        for (key, session) in removed_sessions {
+            self.unindex_session(key, session);
+        }
+
         self.peers.shrink_to_fit();
+        self.keys_by_tracker_id.shrink_to_fit();
+        self.keys_by_key_hash.shrink_to_fit();
 
         self.peers.len()
     }
@@ -521,6 +978,73 @@ impl<I: Ip> LargePeerMap<I> {
 struct Peer {
     pub valid_until: ValidUntil,
     pub is_seeder: bool,
+    /// Peer announced with event `started` when it was first stored
+    pub started: bool,
+    pub session: PeerSession,
+}
+
+/// Identifies a peer across announces from different addresses. Only set
+/// if `protocol.peer_sessions` is enabled, in which case the tracker id is
+/// never zero.
+#[derive(Debug, Clone, Copy, Default)]
+struct PeerSession {
+    /// Sent to peer as `tracker id` in announce responses
+    tracker_id: u64,
+    key_hash: Option<NonZeroU64>,
+}
+
+/// Session identifiers sent by a peer in an announce request
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SessionCredentials {
+    tracker_id: Option<u64>,
+    key_hash: Option<NonZeroU64>,
+}
+
+impl SessionCredentials {
+    fn new(config: &Config, key_hasher: &RandomState, request: &AnnounceRequest) -> Self {
+        if !config.protocol.peer_sessions {
+            return Self::default();
+        }
+
+        let tracker_id = request.tracker_id.as_deref().and_then(parse_tracker_id);
+        let key_hash = request
+            .key
+            .as_deref()
+            .filter(|key| !key.is_empty())
+            .map(|key| {
+                let mut hasher = key_hasher.build_hasher();
+
+                key.hash(&mut hasher);
+
+                NonZeroU64::new(hasher.finish()).unwrap_or(NonZeroU64::MIN)
+            });
+
+        Self {
+            tracker_id,
+            key_hash,
+        }
+    }
+
+    fn is_some(&self) -> bool {
+        self.tracker_id.is_some() || self.key_hash.is_some()
+    }
+
+    fn match_session(&self, session: &PeerSession) -> bool {
+        self.tracker_id == Some(session.tracker_id)
+            || (self.key_hash.is_some() && self.key_hash == session.key_hash)
+    }
+}
+
+fn format_tracker_id(tracker_id: u64) -> CompactString {
+    format_compact!("{:016x}", tracker_id)
+}
+
+fn parse_tracker_id(tracker_id: &str) -> Option<u64> {
+    if tracker_id.len() == 16 {
+        u64::from_str_radix(tracker_id, 16).ok()
+    } else {
+        None
+    }
 }
 
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
@@ -541,3 +1065,413 @@ impl PeerStatus {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use aquatic_common::{ManualClock, ServerStartInstant};
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use super::*;
+
+    fn announce(
+        torrent_maps: &mut TorrentMaps,
+        config: &Config,
+        port: u16,
+        event: AnnounceEvent,
+        key: Option<&str>,
+        tracker_id: Option<&str>,
+    ) -> AnnounceResponse {
+        match try_announce(torrent_maps, config, port, event, key, tracker_id) {
+            Response::Announce(response) => response,
+            response => panic!("not an announce response: {:?}", response),
+        }
+    }
+
+    fn try_announce(
+        torrent_maps: &mut TorrentMaps,
+        config: &Config,
+        port: u16,
+        event: AnnounceEvent,
+        key: Option<&str>,
+        tracker_id: Option<&str>,
+    ) -> Response {
+        let request = AnnounceRequest {
+            info_hash: InfoHash([1; 20]),
+            peer_id: PeerId([0; 20]),
+            port,
+            bytes_uploaded: 0,
+            bytes_downloaded: 0,
+            bytes_left: 1,
+            event,
+            numwant: None,
+            key: key.map(Into::into),
+            tracker_id: tracker_id.map(Into::into),
+            compact: true,
+            no_peer_id: false,
+            passkey: None,
+            ip: None,
+            ipv4: None,
+            ipv6: None,
+        };
+        let peer_addr = CanonicalSocketAddr::new(SocketAddr::from(([10, 0, 0, 1], port)));
+
+        torrent_maps.handle_announce_request(
+            config,
+            &mut SmallRng::seed_from_u64(0),
+            ValidUntil::new(ServerStartInstant::new(), 60),
+            peer_addr,
+            request,
+        )
+    }
+
+    fn num_peers(torrent_maps: &TorrentMaps) -> usize {
+        torrent_maps
+            .ipv4
+            .torrents
+            .get(&InfoHash([1; 20]))
+            .map(|torrent_data| {
+                let statistics = torrent_data.scrape_statistics();
+
+                statistics.complete + statistics.incomplete
+            })
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn test_peer_sessions() {
+        let mut config = Config::default();
+        let mut torrent_maps = TorrentMaps::new(0);
+
+        // Without peer sessions, stopped event from new port leaves peer
+        let response = announce(
+            &mut torrent_maps,
+            &config,
+            1000,
+            AnnounceEvent::Started,
+            None,
+            None,
+        );
+
+        assert_eq!(response.tracker_id, None);
+
+        announce(
+            &mut torrent_maps,
+            &config,
+            1001,
+            AnnounceEvent::Stopped,
+            Some("k"),
+            None,
+        );
+
+        assert_eq!(num_peers(&torrent_maps), 1);
+
+        config.protocol.peer_sessions = true;
+
+        let mut torrent_maps = TorrentMaps::new(0);
+
+        // Tracker id is kept across announces
+        let tracker_id = announce(
+            &mut torrent_maps,
+            &config,
+            1000,
+            AnnounceEvent::Started,
+            None,
+            None,
+        )
+        .tracker_id
+        .unwrap();
+        let response = announce(
+            &mut torrent_maps,
+            &config,
+            1000,
+            AnnounceEvent::Empty,
+            Some("k"),
+            None,
+        );
+
+        assert_eq!(response.tracker_id.as_ref(), Some(&tracker_id));
+
+        // Wrong credentials don't match
+        announce(
+            &mut torrent_maps,
+            &config,
+            1001,
+            AnnounceEvent::Stopped,
+            Some("x"),
+            Some("0000000000000000"),
+        );
+
+        assert_eq!(num_peers(&torrent_maps), 1);
+
+        // Completed event from new port with matching tracker id replaces peer
+        let response = announce(
+            &mut torrent_maps,
+            &config,
+            1001,
+            AnnounceEvent::Completed,
+            None,
+            Some(&tracker_id),
+        );
+
+        assert_eq!(response.tracker_id.as_ref(), Some(&tracker_id));
+        assert_eq!(num_peers(&torrent_maps), 1);
+
+        // Stopped event from new port with matching key removes peer
+        announce(
+            &mut torrent_maps,
+            &config,
+            1002,
+            AnnounceEvent::Stopped,
+            Some("k"),
+            None,
+        );
+
+        assert_eq!(num_peers(&torrent_maps), 0);
+    }
+
+    #[test]
+    fn test_peer_sessions_large_peer_map() {
+        let mut config = Config::default();
+
+        config.protocol.peer_sessions = true;
+
+        let mut torrent_maps = TorrentMaps::new(0);
+
+        let num_ports = SMALL_PEER_MAP_CAPACITY + 2;
+
+        let tracker_ids = (0..num_ports as u16)
+            .map(|i| {
+                let key = format!("k{}", i);
+
+                announce(
+                    &mut torrent_maps,
+                    &config,
+                    1000 + i,
+                    AnnounceEvent::Started,
+                    Some(&key),
+                    None,
+                )
+                .tracker_id
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(num_peers(&torrent_maps), num_ports);
+
+        // Completed event from new port with matching tracker id moves peer
+        let response = announce(
+            &mut torrent_maps,
+            &config,
+            2000,
+            AnnounceEvent::Completed,
+            None,
+            Some(&tracker_ids[1]),
+        );
+
+        assert_eq!(response.tracker_id.as_ref(), Some(&tracker_ids[1]));
+        assert_eq!(num_peers(&torrent_maps), num_ports);
+
+        // Moved peer can still be found by its key
+        announce(
+            &mut torrent_maps,
+            &config,
+            2001,
+            AnnounceEvent::Stopped,
+            Some("k1"),
+            None,
+        );
+
+        assert_eq!(num_peers(&torrent_maps), num_ports - 1);
+
+        // Stopped event with tracker id of removed peer doesn't match
+        announce(
+            &mut torrent_maps,
+            &config,
+            2002,
+            AnnounceEvent::Stopped,
+            None,
+            Some(&tracker_ids[1]),
+        );
+
+        assert_eq!(num_peers(&torrent_maps), num_ports - 1);
+
+        announce(
+            &mut torrent_maps,
+            &config,
+            2002,
+            AnnounceEvent::Stopped,
+            None,
+            Some(&tracker_ids[0]),
+        );
+
+        assert_eq!(num_peers(&torrent_maps), num_ports - 2);
+    }
+
+    #[test]
+    fn test_require_started_event() {
+        let mut config = Config::default();
+
+        config.protocol.require_started_event = true;
+
+        let mut torrent_maps = TorrentMaps::new(0);
+
+        let rejected = |response: Response| {
+            matches!(
+                response,
+                Response::Failure(FailureResponse { failure_reason })
+                    if failure_reason == TrackerError::StartedEventRequired.failure_reason()
+            )
+        };
+
+        // Unknown peers can't send completed or stopped events
+        for event in [AnnounceEvent::Completed, AnnounceEvent::Stopped] {
+            let response = try_announce(&mut torrent_maps, &config, 1000, event, None, None);
+
+            assert!(rejected(response));
+        }
+
+        // Peers stored without started event can't either
+        announce(
+            &mut torrent_maps,
+            &config,
+            1000,
+            AnnounceEvent::Empty,
+            None,
+            None,
+        );
+
+        let response = try_announce(
+            &mut torrent_maps,
+            &config,
+            1000,
+            AnnounceEvent::Completed,
+            None,
+            None,
+        );
+
+        assert!(rejected(response));
+        assert_eq!(num_peers(&torrent_maps), 1);
+
+        // Started event is remembered across announces
+        for event in [
+            AnnounceEvent::Started,
+            AnnounceEvent::Empty,
+            AnnounceEvent::Completed,
+            AnnounceEvent::Stopped,
+        ] {
+            announce(&mut torrent_maps, &config, 1001, event, None, None);
+        }
+
+        assert_eq!(num_peers(&torrent_maps), 1);
+
+        // Peer matched by session keeps started flag
+        config.protocol.peer_sessions = true;
+
+        announce(
+            &mut torrent_maps,
+            &config,
+            1002,
+            AnnounceEvent::Started,
+            Some("k"),
+            None,
+        );
+        announce(
+            &mut torrent_maps,
+            &config,
+            1003,
+            AnnounceEvent::Stopped,
+            Some("k"),
+            None,
+        );
+
+        assert_eq!(num_peers(&torrent_maps), 1);
+    }
+
+    #[test]
+    fn test_completed_count_kept_when_torrent_removed() {
+        let config = Config::default();
+        let access_list = Arc::new(AccessListArcSwap::default());
+        let clock = ManualClock::new(SecondsSinceServerStart::new(0));
+        let mut torrent_maps = TorrentMaps::new(0);
+
+        let scrape_downloaded = |torrent_maps: &mut TorrentMaps| {
+            let request = ScrapeRequest {
+                info_hashes: vec![InfoHash([1; 20])],
+                passkey: None,
+            };
+            let peer_addr = CanonicalSocketAddr::new(SocketAddr::from(([10, 0, 0, 1], 1000)));
+
+            torrent_maps
+                .handle_scrape_request(&config, peer_addr, request)
+                .files[&InfoHash([1; 20])]
+                .downloaded
+        };
+
+        announce(
+            &mut torrent_maps,
+            &config,
+            1000,
+            AnnounceEvent::Completed,
+            None,
+            None,
+        );
+
+        clock.advance(61);
+        torrent_maps.clean(&config, &access_list, &clock);
+
+        assert!(torrent_maps.ipv4.torrents.is_empty());
+        assert_eq!(scrape_downloaded(&mut torrent_maps), 1);
+        assert_eq!(torrent_maps.full_scrape_snapshot().ipv4.files.len(), 1);
+
+        announce(
+            &mut torrent_maps,
+            &config,
+            1000,
+            AnnounceEvent::Completed,
+            None,
+            None,
+        );
+
+        assert!(torrent_maps
+            .ipv4
+            .removed_torrent_completed_counts
+            .is_empty());
+        assert_eq!(scrape_downloaded(&mut torrent_maps), 2);
+    }
+
+    #[test]
+    fn test_bogon_filter() {
+        let mut config = Config::default();
+
+        config.bogon_filter.enabled = true;
+
+        let mut torrent_maps = TorrentMaps::new(0);
+
+        // Peer from private network gets response, but isn't stored
+        announce(
+            &mut torrent_maps,
+            &config,
+            1000,
+            AnnounceEvent::Started,
+            None,
+            None,
+        );
+
+        assert_eq!(num_peers(&torrent_maps), 0);
+
+        config.bogon_filter.allow_private_networks = true;
+
+        announce(
+            &mut torrent_maps,
+            &config,
+            1000,
+            AnnounceEvent::Started,
+            None,
+            None,
+        );
+
+        assert_eq!(num_peers(&torrent_maps), 1);
+    }
+}
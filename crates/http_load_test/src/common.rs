@@ -1,22 +1,15 @@
-use std::sync::{atomic::AtomicUsize, Arc};
+use std::sync::{atomic::AtomicUsize, Arc, Mutex};
 
+use hdrhistogram::Histogram;
+use rand::Rng;
 use rand_distr::Gamma;
 
 pub use aquatic_http_protocol::common::*;
 pub use aquatic_http_protocol::request::*;
 
-#[derive(PartialEq, Eq, Clone)]
-pub struct TorrentPeer {
-    pub info_hash: InfoHash,
-    pub scrape_hash_indeces: Vec<usize>,
-    pub peer_id: PeerId,
-    pub port: u16,
-}
-
 #[derive(Default)]
 pub struct Statistics {
     pub requests: AtomicUsize,
-    pub response_peers: AtomicUsize,
     pub responses_announce: AtomicUsize,
     pub responses_scrape: AtomicUsize,
     pub responses_failure: AtomicUsize,
@@ -29,6 +22,25 @@ pub struct LoadTestState {
     pub info_hashes: Arc<Vec<InfoHash>>,
     pub statistics: Arc<Statistics>,
     pub gamma: Arc<Gamma<f64>>,
+    /// Time from sending requests to receiving responses, in microseconds.
+    /// Recorded by socket workers and reset on each report.
+    pub response_latencies: Arc<Mutex<Histogram<u64>>>,
+}
+
+/// Peer identity used for announce requests sent over a connection
+#[derive(Clone, Copy)]
+pub struct Peer {
+    pub peer_id: PeerId,
+    pub port: u16,
+}
+
+impl Peer {
+    pub fn random(rng: &mut impl Rng) -> Self {
+        Self {
+            peer_id: PeerId(rng.gen()),
+            port: rng.gen(),
+        }
+    }
 }
 
 #[derive(PartialEq, Eq, Clone, Copy)]
@@ -55,6 +55,10 @@ pub struct TorrentConfig {
     pub number_of_torrents: usize,
     /// Probability that a generated peer is a seeder
     pub peer_seeder_probability: f64,
+    /// Probability that an announce request is sent by a new peer instead
+    /// of by the peer previously announcing over the connection. Use 1.0
+    /// to send every announce request from a new peer.
+    pub peer_churn_probability: f64,
     /// Probability that a generated request is a announce request, as part
     /// of sum of the various weight arguments.
     pub weight_announce: usize,
@@ -72,6 +76,7 @@ impl Default for TorrentConfig {
         Self {
             number_of_torrents: 10_000,
             peer_seeder_probability: 0.25,
+            peer_churn_probability: 0.1,
             weight_announce: 5,
             weight_scrape: 0,
             torrent_gamma_shape: 0.2,
@@ -1,8 +1,9 @@
-use std::sync::{atomic::Ordering, Arc};
+use std::sync::{atomic::Ordering, Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 
 use ::glommio::LocalExecutorBuilder;
+use hdrhistogram::Histogram;
 use rand::prelude::*;
 use rand_distr::Gamma;
 
@@ -21,6 +22,8 @@ static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 /// Multiply bytes during a second with this to get Mbit/s
 const MBITS_FACTOR: f64 = 1.0 / ((1024.0 * 1024.0) / 8.0);
 
+const PERCENTILES: &[f64] = &[10.0, 25.0, 50.0, 75.0, 90.0, 95.0, 99.0, 99.9, 100.0];
+
 pub fn main() {
     aquatic_common::cli::run_app_with_cli_and_config::<Config>(
         "aquatic_http_load_test: BitTorrent load tester",
@@ -34,6 +37,9 @@ fn run(config: Config) -> ::anyhow::Result<()> {
     if config.torrents.weight_announce + config.torrents.weight_scrape == 0 {
         panic!("Error: at least one weight must be larger than zero.");
     }
+    if !(0.0..=1.0).contains(&config.torrents.peer_churn_probability) {
+        panic!("Error: peer_churn_probability must be between 0.0 and 1.0.");
+    }
 
     println!("Starting client with config: {:#?}", config);
 
@@ -55,6 +61,7 @@ fn run(config: Config) -> ::anyhow::Result<()> {
         info_hashes: Arc::new(info_hashes),
         statistics: Arc::new(Statistics::default()),
         gamma: Arc::new(gamma),
+        response_latencies: Arc::new(Mutex::new(Histogram::new(2).unwrap())),
     };
 
     let opt_tls_config = if config.enable_tls {
@@ -86,6 +93,7 @@ fn run(config: Config) -> ::anyhow::Result<()> {
 fn monitor_statistics(state: LoadTestState, config: &Config) {
     let start_time = Instant::now();
     let mut report_avg_response_vec: Vec<f64> = Vec::new();
+    let mut report_response_latencies: Histogram<u64> = Histogram::new(2).unwrap();
 
     let interval = 5;
     let interval_f64 = interval as f64;
@@ -119,7 +127,17 @@ fn monitor_statistics(state: LoadTestState, config: &Config) {
             + responses_scrape_per_second
             + responses_failure_per_second;
 
+        let response_latencies = {
+            let mut response_latencies = state.response_latencies.lock().unwrap();
+            let cloned = response_latencies.clone();
+
+            response_latencies.reset();
+
+            cloned
+        };
+
         report_avg_response_vec.push(responses_per_second);
+        report_response_latencies.add(&response_latencies).unwrap();
 
         println!();
         println!("Requests out: {:.2}/second", requests_per_second);
@@ -143,6 +161,10 @@ fn monitor_statistics(state: LoadTestState, config: &Config) {
             bytes_received_per_second * MBITS_FACTOR
         );
 
+        if !response_latencies.is_empty() {
+            print_response_latencies(&response_latencies);
+        }
+
         let time_elapsed = start_time.elapsed();
         let duration = Duration::from_secs(config.duration as u64);
 
@@ -155,18 +177,29 @@ fn monitor_statistics(state: LoadTestState, config: &Config) {
                 concat!(
                     "\n# aquatic load test report\n\n",
                     "Test ran for {} seconds.\n",
-                    "Average responses per second: {:.2}\n\nConfig: {:#?}\n"
+                    "Average responses per second: {:.2}\n"
                 ),
                 time_elapsed.as_secs(),
                 report_avg,
-                config
             );
 
+            print_response_latencies(&report_response_latencies);
+
+            println!("\nConfig: {:#?}\n", config);
+
             break;
         }
     }
 }
 
+fn print_response_latencies(histogram: &Histogram<u64>) {
+    println!("Response latency (microseconds):");
+
+    for p in PERCENTILES {
+        println!("  - p{}: {}", p, histogram.value_at_percentile(*p));
+    }
+}
+
 #[derive(Debug)]
 struct FakeCertificateVerifier;
 
@@ -4,7 +4,7 @@ use std::{
     io::Cursor,
     rc::Rc,
     sync::{atomic::Ordering, Arc},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use aquatic_http_protocol::response::Response;
@@ -12,9 +12,14 @@ use futures_lite::{AsyncReadExt, AsyncWriteExt};
 use futures_rustls::TlsConnector;
 use glommio::net::TcpStream;
 use glommio::{prelude::*, timer::TimerActionRepeat};
+use hdrhistogram::Histogram;
 use rand::{prelude::SmallRng, SeedableRng};
 
-use crate::{common::LoadTestState, config::Config, utils::create_random_request};
+use crate::{
+    common::{LoadTestState, Peer},
+    config::Config,
+    utils::create_random_request,
+};
 
 pub async fn run_socket_thread(
     config: Config,
@@ -24,6 +29,33 @@ pub async fn run_socket_thread(
     let config = Rc::new(config);
     let num_active_connections = Rc::new(RefCell::new(0usize));
     let rng = Rc::new(RefCell::new(SmallRng::from_entropy()));
+    let response_latencies = Rc::new(RefCell::new(Histogram::new(2).unwrap()));
+
+    // Periodically pass on recorded latencies, avoiding contention over
+    // shared histogram
+    TimerActionRepeat::repeat({
+        let shared_response_latencies = load_test_state.response_latencies.clone();
+        let response_latencies = response_latencies.clone();
+
+        move || {
+            let shared_response_latencies = shared_response_latencies.clone();
+            let response_latencies = response_latencies.clone();
+
+            async move {
+                let mut response_latencies = response_latencies.borrow_mut();
+
+                shared_response_latencies
+                    .lock()
+                    .unwrap()
+                    .add(&*response_latencies)
+                    .unwrap();
+
+                response_latencies.reset();
+
+                Some(Duration::from_secs(1))
+            }
+        }
+    });
 
     let interval = config.connection_creation_interval_ms;
 
@@ -36,6 +68,7 @@ pub async fn run_socket_thread(
                     load_test_state.clone(),
                     num_active_connections.clone(),
                     rng.clone(),
+                    response_latencies.clone(),
                 )
                 .await
                 {
@@ -54,6 +87,7 @@ pub async fn run_socket_thread(
                 load_test_state.clone(),
                 num_active_connections.clone(),
                 rng.clone(),
+                response_latencies.clone(),
             )
         });
     }
@@ -70,6 +104,7 @@ async fn periodically_open_connections(
     load_test_state: LoadTestState,
     num_active_connections: Rc<RefCell<usize>>,
     rng: Rc<RefCell<SmallRng>>,
+    response_latencies: Rc<RefCell<Histogram<u64>>>,
 ) -> Option<Duration> {
     if *num_active_connections.borrow() < config.num_connections {
         spawn_local(async move {
@@ -79,6 +114,7 @@ async fn periodically_open_connections(
                 load_test_state,
                 num_active_connections,
                 rng.clone(),
+                response_latencies,
             )
             .await
             {
@@ -97,7 +133,9 @@ async fn run_connection(
     load_test_state: LoadTestState,
     num_active_connections: Rc<RefCell<usize>>,
     rng: Rc<RefCell<SmallRng>>,
+    response_latencies: Rc<RefCell<Histogram<u64>>>,
 ) -> anyhow::Result<()> {
+    let peer = Peer::random(&mut *rng.borrow_mut());
     let stream = TcpStream::connect(config.server_address)
         .await
         .map_err(|err| anyhow::anyhow!("connect: {:?}", err))?;
@@ -111,8 +149,11 @@ async fn run_connection(
             config,
             load_test_state,
             rng,
+            response_latencies: response_latencies.clone(),
+            peer,
             stream,
             buffer: Box::new([0; 2048]),
+            request_sent_at: None,
         };
 
         connection.run(num_active_connections).await?;
@@ -121,8 +162,11 @@ async fn run_connection(
             config,
             load_test_state,
             rng,
+            response_latencies: response_latencies.clone(),
+            peer,
             stream,
             buffer: Box::new([0; 2048]),
+            request_sent_at: None,
         };
 
         connection.run(num_active_connections).await?;
@@ -135,8 +179,11 @@ struct Connection<S> {
     config: Rc<Config>,
     load_test_state: LoadTestState,
     rng: Rc<RefCell<SmallRng>>,
+    response_latencies: Rc<RefCell<Histogram<u64>>>,
+    peer: Peer,
     stream: S,
     buffer: Box<[u8; 2048]>,
+    request_sent_at: Option<Instant>,
 }
 
 impl<S> Connection<S>
@@ -173,6 +220,7 @@ where
             &self.config,
             &self.load_test_state,
             &mut self.rng.borrow_mut(),
+            &mut self.peer,
         );
 
         let mut cursor = Cursor::new(&mut self.buffer[..]);
@@ -188,6 +236,8 @@ where
 
         self.stream.flush().await?;
 
+        self.request_sent_at = Some(Instant::now());
+
         self.load_test_state
             .statistics
             .bytes_sent
@@ -231,6 +281,12 @@ where
             if let Some(body_start_index) = opt_body_start_index {
                 match Response::parse_bytes(&interesting_bytes[body_start_index..]) {
                     Ok(response) => {
+                        if let Some(request_sent_at) = self.request_sent_at.take() {
+                            let latency = request_sent_at.elapsed().as_micros() as u64;
+
+                            self.response_latencies.borrow_mut().record(latency)?;
+                        }
+
                         match response {
                             Response::Announce(_) => {
                                 self.load_test_state
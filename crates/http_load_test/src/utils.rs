@@ -11,6 +11,7 @@ pub fn create_random_request(
     config: &Config,
     state: &LoadTestState,
     rng: &mut SmallRng,
+    peer: &mut Peer,
 ) -> Request {
     let weights = [
         config.torrents.weight_announce as u32,
@@ -22,13 +23,22 @@ pub fn create_random_request(
     let dist = WeightedIndex::new(weights).expect("random request weighted index");
 
     match items[dist.sample(rng)] {
-        RequestType::Announce => create_announce_request(config, state, rng),
+        RequestType::Announce => create_announce_request(config, state, rng, peer),
         RequestType::Scrape => create_scrape_request(config, state, rng),
     }
 }
 
 #[inline]
-fn create_announce_request(config: &Config, state: &LoadTestState, rng: &mut impl Rng) -> Request {
+fn create_announce_request(
+    config: &Config,
+    state: &LoadTestState,
+    rng: &mut impl Rng,
+    peer: &mut Peer,
+) -> Request {
+    if rng.gen_bool(config.torrents.peer_churn_probability) {
+        *peer = Peer::random(rng);
+    }
+
     let (event, bytes_left) = {
         if rng.gen_bool(config.torrents.peer_seeder_probability) {
             (AnnounceEvent::Completed, 0)
@@ -41,14 +51,21 @@ fn create_announce_request(config: &Config, state: &LoadTestState, rng: &mut imp
 
     Request::Announce(AnnounceRequest {
         info_hash: state.info_hashes[info_hash_index],
-        peer_id: PeerId(rng.gen()),
+        peer_id: peer.peer_id,
         bytes_left,
         event,
         key: None,
+        tracker_id: None,
         numwant: None,
-        port: rng.gen(),
+        port: peer.port,
         bytes_uploaded: 0,
         bytes_downloaded: 0,
+        compact: true,
+        no_peer_id: false,
+        passkey: None,
+        ip: None,
+        ipv4: None,
+        ipv6: None,
     })
 }
 
@@ -64,6 +81,7 @@ fn create_scrape_request(config: &Config, state: &LoadTestState, rng: &mut impl
 
     Request::Scrape(ScrapeRequest {
         info_hashes: scrape_hashes,
+        passkey: None,
     })
 }
 
@@ -19,9 +19,12 @@ pub fn bench(c: &mut Criterion) {
         announce_interval: 120,
         complete: 100,
         incomplete: 500,
+        external_ip: None,
         peers: ResponsePeerListV4(peers),
         peers6: ResponsePeerListV6(Vec::new()),
+        tracker_id: None,
         warning_message: None,
+        compact: true,
     };
 
     let response = Response::Announce(announce_response);
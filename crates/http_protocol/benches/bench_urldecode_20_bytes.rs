@@ -0,0 +1,76 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::time::Duration;
+
+use anyhow::Context;
+use aquatic_http_protocol::urldecode_20_bytes;
+
+static FULLY_ENCODED: &str = "%04%0b%6b%56%3f%5c%72%14%a6%b7%98%ad%43%c3%c9%2e%40%24%00%b9";
+static MIXED: &str = "%04%0bkV%3f%5cr%14%a6%b7%98%adC%c3%c9.%40%24%00%b9";
+static UNENCODED: &str = "-TR2940-5ert69muw5t8";
+
+/// Previous char-based implementation, for comparison
+fn urldecode_20_bytes_chars(value: &str) -> anyhow::Result<[u8; 20]> {
+    let mut out_arr = [0u8; 20];
+
+    let mut chars = value.chars();
+
+    for i in 0..20 {
+        let c = chars.next().with_context(|| "less than 20 chars")?;
+
+        if c as u32 > 255 {
+            return Err(anyhow::anyhow!(
+                "character not in single byte range: {:#?}",
+                c
+            ));
+        }
+
+        if c == '%' {
+            let first = chars
+                .next()
+                .with_context(|| "missing first urldecode char in pair")?;
+            let second = chars
+                .next()
+                .with_context(|| "missing second urldecode char in pair")?;
+
+            let hex = [first as u8, second as u8];
+
+            hex::decode_to_slice(hex, &mut out_arr[i..i + 1])
+                .map_err(|err| anyhow::anyhow!("hex decode error: {:?}", err))?;
+        } else {
+            out_arr[i] = c as u8;
+        }
+    }
+
+    if chars.next().is_some() {
+        return Err(anyhow::anyhow!("more than 20 chars"));
+    }
+
+    Ok(out_arr)
+}
+
+pub fn bench(c: &mut Criterion) {
+    for (name, input) in [
+        ("fully-encoded", FULLY_ENCODED),
+        ("mixed", MIXED),
+        ("unencoded", UNENCODED),
+    ] {
+        let mut group = c.benchmark_group(format!("urldecode-20-bytes-{}", name));
+
+        group.bench_function("bytes", |b| b.iter(|| urldecode_20_bytes(black_box(input))));
+        group.bench_function("chars", |b| {
+            b.iter(|| urldecode_20_bytes_chars(black_box(input)))
+        });
+
+        group.finish();
+    }
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default()
+        .sample_size(1000)
+        .measurement_time(Duration::from_secs(10))
+        .significance_level(0.01);
+    targets = bench
+}
+criterion_main!(benches);
@@ -0,0 +1,12 @@
+#![no_main]
+
+use aquatic_http_protocol::request::Request;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Request::parse_bytes(data);
+
+    if let Ok(path) = ::std::str::from_utf8(data) {
+        let _ = Request::parse_http_get_path(path);
+    }
+});
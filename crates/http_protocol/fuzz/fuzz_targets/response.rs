@@ -0,0 +1,8 @@
+#![no_main]
+
+use aquatic_http_protocol::response::Response;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Response::parse_bytes(data);
+});
@@ -2,3 +2,5 @@ pub mod common;
 pub mod request;
 pub mod response;
 mod utils;
+
+pub use utils::{urldecode_20_bytes, urlencode_20_bytes};
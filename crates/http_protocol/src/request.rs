@@ -1,4 +1,5 @@
 use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
 
 use anyhow::Context;
 use compact_str::CompactString;
@@ -6,6 +7,12 @@ use compact_str::CompactString;
 use super::common::*;
 use super::utils::*;
 
+/// Maximum length of passkey in announce and scrape paths, before
+/// url-decoding. Requests with longer passkeys are rejected.
+///
+/// Leaves room for signed URL tokens with user ids of up to 150 bytes.
+pub const MAX_PASSKEY_LEN: usize = 256;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct AnnounceRequest {
     pub info_hash: InfoHash,
@@ -18,11 +25,28 @@ pub struct AnnounceRequest {
     /// Number of response peers wanted
     pub numwant: Option<usize>,
     pub key: Option<CompactString>,
+    /// Tracker id from a previous announce response (`trackerid` parameter)
+    pub tracker_id: Option<CompactString>,
+    /// Send peers in compact format (BEP 23). Defaults to true if the
+    /// client doesn't specify it.
+    pub compact: bool,
+    /// Omit peer IDs from non-compact peer lists (`no_peer_id` parameter)
+    pub no_peer_id: bool,
+    /// User passkey from announce URL path (`/announce/<passkey>`)
+    pub passkey: Option<CompactString>,
+    /// Peer IP address sent by client (`ip` parameter). Host names are
+    /// ignored.
+    pub ip: Option<IpAddr>,
+    /// Peer IPv4 address sent by client (`ipv4` parameter, BEP 7)
+    pub ipv4: Option<Ipv4Addr>,
+    /// Peer IPv6 address sent by client (`ipv6` parameter, BEP 7)
+    pub ipv6: Option<Ipv6Addr>,
 }
 
 impl AnnounceRequest {
     fn write_bytes<W: Write>(&self, output: &mut W, url_suffix: &[u8]) -> ::std::io::Result<()> {
         output.write_all(b"GET /announce")?;
+        write_passkey(self.passkey.as_deref(), output)?;
         output.write_all(url_suffix)?;
         output.write_all(b"?info_hash=")?;
         urlencode_20_bytes(self.info_hash.0, output)?;
@@ -59,15 +83,46 @@ impl AnnounceRequest {
             output.write_all(::urlencoding::encode(key.as_str()).as_bytes())?;
         }
 
-        // Always ask for compact responses to ease load testing of non-aquatic trackers
-        output.write_all(b"&compact=1")?;
+        if let Some(ref tracker_id) = self.tracker_id {
+            output.write_all(b"&trackerid=")?;
+            output.write_all(::urlencoding::encode(tracker_id.as_str()).as_bytes())?;
+        }
+
+        if let Some(ip) = self.ip {
+            output.write_all(b"&ip=")?;
+            write_ip(ip, output)?;
+        }
+
+        if let Some(ipv4) = self.ipv4 {
+            output.write_all(b"&ipv4=")?;
+            write_ip(ipv4.into(), output)?;
+        }
+
+        if let Some(ipv6) = self.ipv6 {
+            output.write_all(b"&ipv6=")?;
+            write_ip(ipv6.into(), output)?;
+        }
+
+        // Always specify format to ease load testing of non-aquatic trackers
+        if self.compact {
+            output.write_all(b"&compact=1")?;
+        } else {
+            output.write_all(b"&compact=0")?;
+        }
+
+        if self.no_peer_id {
+            output.write_all(b"&no_peer_id=1")?;
+        }
 
         output.write_all(b" HTTP/1.1\r\nHost: localhost\r\n\r\n")?;
 
         Ok(())
     }
 
-    pub fn parse_query_string(query_string: &str) -> anyhow::Result<Self> {
+    pub fn parse_query_string(
+        query_string: &str,
+        passkey: Option<CompactString>,
+    ) -> anyhow::Result<Self> {
         // -- Parse key-value pairs
 
         let mut opt_info_hash = None;
@@ -79,6 +134,12 @@ impl AnnounceRequest {
         let mut event = AnnounceEvent::default();
         let mut opt_numwant = None;
         let mut opt_key = None;
+        let mut opt_tracker_id = None;
+        let mut compact = true;
+        let mut no_peer_id = false;
+        let mut opt_ip = None;
+        let mut opt_ipv4 = None;
+        let mut opt_ipv6 = None;
 
         let query_string_bytes = query_string.as_bytes();
 
@@ -128,9 +189,14 @@ impl AnnounceRequest {
                         .map_err(|err| anyhow::anyhow!("invalid event: {}", err))?;
                 }
                 "compact" => {
-                    if value != "1" {
-                        return Err(anyhow::anyhow!("compact set, but not to 1"));
-                    }
+                    compact = match value {
+                        "1" => true,
+                        "0" => false,
+                        _ => return Err(anyhow::anyhow!("compact set, but not to 0 or 1")),
+                    };
+                }
+                "no_peer_id" => {
+                    no_peer_id = value != "0";
                 }
                 "numwant" => {
                     opt_numwant = Some(value.parse::<usize>().with_context(|| "parse numwant")?);
@@ -141,6 +207,31 @@ impl AnnounceRequest {
                     }
                     opt_key = Some(::urlencoding::decode(value)?.into());
                 }
+                "trackerid" => {
+                    if value.len() > 100 {
+                        return Err(anyhow::anyhow!("'trackerid' is too long"));
+                    }
+                    opt_tracker_id = Some(::urlencoding::decode(value)?.into());
+                }
+                "ip" => {
+                    opt_ip = parse_ip(value, |value| value.parse::<IpAddr>().ok());
+                }
+                "ipv4" => {
+                    opt_ipv4 = parse_ip(value, |value| {
+                        value
+                            .parse::<Ipv4Addr>()
+                            .or_else(|_| value.parse::<SocketAddrV4>().map(|addr| *addr.ip()))
+                            .ok()
+                    });
+                }
+                "ipv6" => {
+                    opt_ipv6 = parse_ip(value, |value| {
+                        value
+                            .parse::<Ipv6Addr>()
+                            .or_else(|_| value.parse::<SocketAddrV6>().map(|addr| *addr.ip()))
+                            .ok()
+                    });
+                }
                 k => {
                     ::log::debug!("ignored unrecognized key: {}", k)
                 }
@@ -163,6 +254,13 @@ impl AnnounceRequest {
             event,
             numwant: opt_numwant,
             key: opt_key,
+            tracker_id: opt_tracker_id,
+            compact,
+            no_peer_id,
+            passkey,
+            ip: opt_ip,
+            ipv4: opt_ipv4,
+            ipv6: opt_ipv6,
         })
     }
 }
@@ -170,11 +268,14 @@ impl AnnounceRequest {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ScrapeRequest {
     pub info_hashes: Vec<InfoHash>,
+    /// User passkey from scrape URL path (`/scrape/<passkey>`)
+    pub passkey: Option<CompactString>,
 }
 
 impl ScrapeRequest {
     fn write_bytes<W: Write>(&self, output: &mut W, url_suffix: &[u8]) -> ::std::io::Result<()> {
         output.write_all(b"GET /scrape")?;
+        write_passkey(self.passkey.as_deref(), output)?;
         output.write_all(url_suffix)?;
         output.write_all(b"?")?;
 
@@ -196,7 +297,10 @@ impl ScrapeRequest {
         Ok(())
     }
 
-    pub fn parse_query_string(query_string: &str) -> anyhow::Result<Self> {
+    pub fn parse_query_string(
+        query_string: &str,
+        passkey: Option<CompactString>,
+    ) -> anyhow::Result<Self> {
         // -- Parse key-value pairs
 
         let mut info_hashes = Vec::new();
@@ -240,7 +344,29 @@ impl ScrapeRequest {
             return Err(anyhow::anyhow!("No info hashes sent"));
         }
 
-        Ok(ScrapeRequest { info_hashes })
+        Ok(ScrapeRequest {
+            info_hashes,
+            passkey,
+        })
+    }
+}
+
+/// Scrape request without info hashes (`/scrape` with empty query string),
+/// asking for statistics on all torrents
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FullScrapeRequest {
+    /// User passkey from scrape URL path (`/scrape/<passkey>`)
+    pub passkey: Option<CompactString>,
+}
+
+impl FullScrapeRequest {
+    fn write_bytes<W: Write>(&self, output: &mut W, url_suffix: &[u8]) -> ::std::io::Result<()> {
+        output.write_all(b"GET /scrape")?;
+        write_passkey(self.passkey.as_deref(), output)?;
+        output.write_all(url_suffix)?;
+        output.write_all(b" HTTP/1.1\r\nHost: localhost\r\n\r\n")?;
+
+        Ok(())
     }
 }
 
@@ -248,6 +374,7 @@ impl ScrapeRequest {
 pub enum Request {
     Announce(AnnounceRequest),
     Scrape(ScrapeRequest),
+    FullScrape(FullScrapeRequest),
 }
 
 impl Request {
@@ -288,16 +415,27 @@ impl Request {
         let mut split_parts = path.splitn(2, '?');
 
         let location = split_parts.next().with_context(|| "no location")?;
-        let query_string = split_parts.next().with_context(|| "no query string")?;
+        let opt_query_string = split_parts.next();
+
+        let (location, opt_passkey) = split_passkey(location)?;
 
         if location == "/announce" {
+            let query_string = opt_query_string.with_context(|| "no query string")?;
+
             Ok(Request::Announce(AnnounceRequest::parse_query_string(
                 query_string,
+                opt_passkey,
             )?))
         } else if location == "/scrape" {
-            Ok(Request::Scrape(ScrapeRequest::parse_query_string(
-                query_string,
-            )?))
+            match opt_query_string {
+                None | Some("") => Ok(Request::FullScrape(FullScrapeRequest {
+                    passkey: opt_passkey,
+                })),
+                Some(query_string) => Ok(Request::Scrape(ScrapeRequest::parse_query_string(
+                    query_string,
+                    opt_passkey,
+                )?)),
+            }
         } else {
             Err(anyhow::anyhow!("Path must be /announce or /scrape"))
         }
@@ -307,17 +445,71 @@ impl Request {
         match self {
             Self::Announce(r) => r.write_bytes(output, url_suffix),
             Self::Scrape(r) => r.write_bytes(output, url_suffix),
+            Self::FullScrape(r) => r.write_bytes(output, url_suffix),
         }
     }
 }
 
+/// Split location such as `/announce/<passkey>` into `/announce` and the
+/// url-decoded passkey
+fn split_passkey(location: &str) -> anyhow::Result<(&str, Option<CompactString>)> {
+    let Some((path, passkey)) = location
+        .strip_prefix('/')
+        .and_then(|rest| rest.split_once('/'))
+    else {
+        return Ok((location, None));
+    };
+
+    if passkey.is_empty() {
+        return Err(anyhow::anyhow!("empty passkey"));
+    }
+    if passkey.len() > MAX_PASSKEY_LEN {
+        return Err(anyhow::anyhow!("passkey is too long"));
+    }
+    if passkey.contains('/') {
+        return Err(anyhow::anyhow!("passkey contains slash"));
+    }
+
+    let location = &location[..path.len() + 1];
+    let passkey = ::urlencoding::decode(passkey)?;
+
+    Ok((location, Some(passkey.into())))
+}
+
+/// Url-decode and parse IP address parameter. Unparseable values (such as
+/// host names) are ignored, since the parameters are only advisory.
+fn parse_ip<T>(value: &str, parse: impl FnOnce(&str) -> Option<T>) -> Option<T> {
+    let opt_ip = ::urlencoding::decode(value)
+        .ok()
+        .and_then(|value| parse(&value));
+
+    if opt_ip.is_none() {
+        ::log::debug!("ignored unparseable ip address parameter: {}", value);
+    }
+
+    opt_ip
+}
+
+fn write_ip<W: Write>(ip: IpAddr, output: &mut W) -> ::std::io::Result<()> {
+    output.write_all(::urlencoding::encode(&ip.to_string()).as_bytes())
+}
+
+fn write_passkey<W: Write>(opt_passkey: Option<&str>, output: &mut W) -> ::std::io::Result<()> {
+    if let Some(passkey) = opt_passkey {
+        output.write_all(b"/")?;
+        output.write_all(::urlencoding::encode(passkey).as_bytes())?;
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use quickcheck::{quickcheck, Arbitrary, Gen, TestResult};
 
     use super::*;
 
-    static ANNOUNCE_REQUEST_PATH: &str = "/announce?info_hash=%04%0bkV%3f%5cr%14%a6%b7%98%adC%c3%c9.%40%24%00%b9&peer_id=-ABC940-5ert69muw5t8&port=12345&uploaded=1&downloaded=2&left=3&numwant=0&key=4ab4b877&compact=1&supportcrypto=1&event=started";
+    static ANNOUNCE_REQUEST_PATH: &str = "/announce?info_hash=%04%0bkV%3f%5cr%14%a6%b7%98%adC%c3%c9.%40%24%00%b9&peer_id=-ABC940-5ert69muw5t8&port=12345&uploaded=1&downloaded=2&left=3&numwant=0&key=4ab4b877&trackerid=1a2b&compact=1&supportcrypto=1&event=started";
     static SCRAPE_REQUEST_PATH: &str =
         "/scrape?info_hash=%04%0bkV%3f%5cr%14%a6%b7%98%adC%c3%c9.%40%24%00%b9";
     static REFERENCE_INFO_HASH: [u8; 20] = [
@@ -340,6 +532,13 @@ mod tests {
             event: AnnounceEvent::Started,
             numwant: Some(0),
             key: Some("4ab4b877".into()),
+            tracker_id: Some("1a2b".into()),
+            compact: true,
+            no_peer_id: false,
+            passkey: None,
+            ip: None,
+            ipv4: None,
+            ipv6: None,
         })
     }
 
@@ -357,6 +556,109 @@ mod tests {
         assert_eq!(parsed_request, reference_request);
     }
 
+    #[test]
+    fn test_announce_request_compact() {
+        let parse = |compact: &str| {
+            let path = ANNOUNCE_REQUEST_PATH.replace("&compact=1", compact);
+
+            match Request::parse_http_get_path(&path)? {
+                Request::Announce(request) => Ok(request.compact),
+                _ => Err(anyhow::anyhow!("not an announce request")),
+            }
+        };
+
+        assert!(parse("&compact=1").unwrap());
+        assert!(!parse("&compact=0").unwrap());
+        assert!(parse("").unwrap());
+        assert!(parse("&compact=2").is_err());
+    }
+
+    #[test]
+    fn test_announce_request_no_peer_id() {
+        let parse = |no_peer_id: &str| {
+            let path = format!("{}{}", ANNOUNCE_REQUEST_PATH, no_peer_id);
+
+            match Request::parse_http_get_path(&path).unwrap() {
+                Request::Announce(request) => request.no_peer_id,
+                _ => panic!("not an announce request"),
+            }
+        };
+
+        assert!(!parse(""));
+        assert!(!parse("&no_peer_id=0"));
+        assert!(parse("&no_peer_id=1"));
+    }
+
+    #[test]
+    fn test_passkey() {
+        let parse = |location: &str| {
+            let path = ANNOUNCE_REQUEST_PATH.replace("/announce", location);
+
+            match Request::parse_http_get_path(&path)? {
+                Request::Announce(request) => Ok(request.passkey),
+                _ => Err(anyhow::anyhow!("not an announce request")),
+            }
+        };
+
+        assert_eq!(parse("/announce").unwrap(), None);
+        assert_eq!(
+            parse("/announce/0123abcd").unwrap(),
+            Some("0123abcd".into())
+        );
+        assert_eq!(parse("/announce/a%2Fb").unwrap(), Some("a/b".into()));
+        assert!(parse("/announce/").is_err());
+        assert!(parse("/announce/a/b").is_err());
+
+        let passkey = "a".repeat(MAX_PASSKEY_LEN);
+
+        assert!(parse(&format!("/announce/{}", passkey)).is_ok());
+        assert!(parse(&format!("/announce/{}a", passkey)).is_err());
+
+        let path = SCRAPE_REQUEST_PATH.replace("/scrape", "/scrape/0123abcd");
+
+        assert_eq!(
+            Request::parse_http_get_path(&path).unwrap(),
+            Request::Scrape(ScrapeRequest {
+                info_hashes: vec![InfoHash(REFERENCE_INFO_HASH)],
+                passkey: Some("0123abcd".into()),
+            })
+        );
+    }
+
+    #[test]
+    fn test_announce_request_ip() {
+        let parse = |params: &str| {
+            let path = format!("{}{}", ANNOUNCE_REQUEST_PATH, params);
+
+            match Request::parse_http_get_path(&path).unwrap() {
+                Request::Announce(request) => (request.ip, request.ipv4, request.ipv6),
+                _ => panic!("not an announce request"),
+            }
+        };
+
+        assert_eq!(parse(""), (None, None, None));
+        assert_eq!(
+            parse("&ip=1.2.3.4&ipv4=5.6.7.8&ipv6=%3A%3A1"),
+            (
+                Some(IpAddr::from([1, 2, 3, 4])),
+                Some(Ipv4Addr::new(5, 6, 7, 8)),
+                Some(Ipv6Addr::LOCALHOST)
+            )
+        );
+        assert_eq!(
+            parse("&ip=::2&ipv4=5.6.7.8:1000&ipv6=[::1]:1000"),
+            (
+                Some(IpAddr::from(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 2))),
+                Some(Ipv4Addr::new(5, 6, 7, 8)),
+                Some(Ipv6Addr::LOCALHOST)
+            )
+        );
+        assert_eq!(
+            parse("&ip=example.com&ipv4=::1&ipv6=1.2.3.4"),
+            (None, None, None)
+        );
+    }
+
     #[test]
     fn test_scrape_request_from_bytes() {
         let mut bytes = Vec::new();
@@ -368,14 +670,108 @@ mod tests {
         let parsed_request = Request::parse_bytes(&bytes[..]).unwrap().unwrap();
         let reference_request = Request::Scrape(ScrapeRequest {
             info_hashes: vec![InfoHash(REFERENCE_INFO_HASH)],
+            passkey: None,
         });
 
         assert_eq!(parsed_request, reference_request);
     }
 
+    #[test]
+    fn test_shared_fixtures() {
+        use aquatic_protocol_tests as fixtures;
+
+        for (fixture_event, event) in [
+            (fixtures::Event::None, AnnounceEvent::Empty),
+            (fixtures::Event::Started, AnnounceEvent::Started),
+            (fixtures::Event::Stopped, AnnounceEvent::Stopped),
+            (fixtures::Event::Completed, AnnounceEvent::Completed),
+        ] {
+            let path = fixtures::http::announce_request_path(fixture_event);
+
+            assert_eq!(
+                Request::parse_http_get_path(&path).unwrap(),
+                Request::Announce(AnnounceRequest {
+                    info_hash: InfoHash(fixtures::INFO_HASH),
+                    peer_id: PeerId(fixtures::PEER_ID),
+                    port: fixtures::PORT,
+                    bytes_uploaded: fixtures::BYTES_UPLOADED as usize,
+                    bytes_downloaded: fixtures::BYTES_DOWNLOADED as usize,
+                    bytes_left: fixtures::BYTES_LEFT as usize,
+                    event,
+                    numwant: Some(fixtures::NUMWANT),
+                    key: Some(format!("{:08x}", fixtures::KEY).into()),
+                    tracker_id: None,
+                    compact: true,
+                    no_peer_id: false,
+                    passkey: None,
+                    ip: None,
+                    ipv4: None,
+                    ipv6: None,
+                })
+            );
+        }
+
+        assert_eq!(
+            Request::parse_http_get_path(fixtures::http::SCRAPE_REQUEST_PATH).unwrap(),
+            Request::Scrape(ScrapeRequest {
+                info_hashes: vec![InfoHash(fixtures::INFO_HASH)],
+                passkey: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_scrape_request_multiple_info_hashes() {
+        let path = format!(
+            "{}&info_hash=%ff%00aaaaaaaaaaaaaaaaaa&info_hash=%25%26bbbbbbbbbbbbbbbbbb",
+            SCRAPE_REQUEST_PATH
+        );
+
+        let mut second_info_hash = [b'a'; 20];
+        let mut third_info_hash = [b'b'; 20];
+
+        second_info_hash[..2].copy_from_slice(&[0xff, 0x00]);
+        third_info_hash[..2].copy_from_slice(b"%&");
+
+        assert_eq!(
+            Request::parse_http_get_path(&path).unwrap(),
+            Request::Scrape(ScrapeRequest {
+                info_hashes: vec![
+                    InfoHash(REFERENCE_INFO_HASH),
+                    InfoHash(second_info_hash),
+                    InfoHash(third_info_hash),
+                ],
+                passkey: None,
+            })
+        );
+
+        // Info hashes must decode to exactly 20 bytes
+        assert!(Request::parse_http_get_path("/scrape?info_hash=%ff%00aaa").is_err());
+        assert!(Request::parse_http_get_path("/scrape?info_hash=").is_err());
+    }
+
+    #[test]
+    fn test_full_scrape_request() {
+        assert_eq!(
+            Request::parse_http_get_path("/scrape").unwrap(),
+            Request::FullScrape(FullScrapeRequest { passkey: None })
+        );
+        assert_eq!(
+            Request::parse_http_get_path("/scrape/0123abcd?").unwrap(),
+            Request::FullScrape(FullScrapeRequest {
+                passkey: Some("0123abcd".into())
+            })
+        );
+
+        // Query string without info hashes is not a full scrape request
+        assert!(Request::parse_http_get_path("/scrape?a=b").is_err());
+        assert!(Request::parse_http_get_path("/announce").is_err());
+    }
+
     impl Arbitrary for AnnounceRequest {
         fn arbitrary(g: &mut Gen) -> Self {
             let key: Option<String> = Arbitrary::arbitrary(g);
+            let tracker_id: Option<String> = Arbitrary::arbitrary(g);
 
             AnnounceRequest {
                 info_hash: Arbitrary::arbitrary(g),
@@ -387,6 +783,13 @@ mod tests {
                 event: Arbitrary::arbitrary(g),
                 numwant: Arbitrary::arbitrary(g),
                 key: key.map(|key| key.into()),
+                tracker_id: tracker_id.map(|tracker_id| tracker_id.into()),
+                compact: Arbitrary::arbitrary(g),
+                no_peer_id: Arbitrary::arbitrary(g),
+                passkey: arbitrary_passkey(g),
+                ip: Arbitrary::arbitrary(g),
+                ipv4: Arbitrary::arbitrary(g),
+                ipv6: Arbitrary::arbitrary(g),
             }
         }
     }
@@ -395,16 +798,27 @@ mod tests {
         fn arbitrary(g: &mut Gen) -> Self {
             ScrapeRequest {
                 info_hashes: Arbitrary::arbitrary(g),
+                passkey: arbitrary_passkey(g),
             }
         }
     }
 
+    fn arbitrary_passkey(g: &mut Gen) -> Option<CompactString> {
+        let passkey: Option<String> = Arbitrary::arbitrary(g);
+
+        passkey
+            .filter(|passkey| !passkey.is_empty() && passkey.len() <= 30)
+            .map(|passkey| passkey.into())
+    }
+
     impl Arbitrary for Request {
         fn arbitrary(g: &mut Gen) -> Self {
-            if Arbitrary::arbitrary(g) {
-                Self::Announce(Arbitrary::arbitrary(g))
-            } else {
-                Self::Scrape(Arbitrary::arbitrary(g))
+            match u8::arbitrary(g) % 3 {
+                0 => Self::Announce(Arbitrary::arbitrary(g)),
+                1 => Self::Scrape(Arbitrary::arbitrary(g)),
+                _ => Self::FullScrape(FullScrapeRequest {
+                    passkey: arbitrary_passkey(g),
+                }),
             }
         }
     }
@@ -415,15 +829,19 @@ mod tests {
             match request {
                 Request::Announce(AnnounceRequest {
                     key: Some(ref key), ..
-                }) => {
-                    if key.len() > 30 {
-                        return TestResult::discard();
-                    }
+                }) if key.len() > 30 => {
+                    return TestResult::discard();
                 }
-                Request::Scrape(ScrapeRequest { ref info_hashes }) => {
-                    if info_hashes.is_empty() {
-                        return TestResult::discard();
-                    }
+                Request::Announce(AnnounceRequest {
+                    tracker_id: Some(ref tracker_id),
+                    ..
+                }) if tracker_id.len() > 30 => {
+                    return TestResult::discard();
+                }
+                Request::Scrape(ScrapeRequest {
+                    ref info_hashes, ..
+                }) if info_hashes.is_empty() => {
+                    return TestResult::discard();
                 }
                 _ => {}
             }
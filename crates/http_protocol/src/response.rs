@@ -1,7 +1,8 @@
 use std::borrow::Cow;
 use std::io::Write;
-use std::net::{Ipv4Addr, Ipv6Addr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
+use compact_str::CompactString;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 
@@ -47,10 +48,29 @@ pub struct AnnounceResponse {
     pub announce_interval: usize,
     pub complete: usize,
     pub incomplete: usize,
+    /// Public IP address of the requesting peer as seen by the tracker
+    /// (BEP 24)
+    #[serde(
+        rename = "external ip",
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_optional_ip_bytes",
+        deserialize_with = "deserialize_optional_ip_bytes"
+    )]
+    pub external_ip: Option<IpAddr>,
     #[serde(default)]
     pub peers: ResponsePeerListV4,
     #[serde(default)]
     pub peers6: ResponsePeerListV6,
+    /// Opaque value that clients should send back as `trackerid` in
+    /// subsequent announce requests
+    #[serde(
+        rename = "tracker id",
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_optional_string"
+    )]
+    pub tracker_id: Option<CompactString>,
     // Serialize as string if Some, otherwise skip
     #[serde(
         rename = "warning message",
@@ -58,6 +78,17 @@ pub struct AnnounceResponse {
         serialize_with = "serialize_optional_string"
     )]
     pub warning_message: Option<String>,
+    /// Encode peers in compact format (BEP 23) in `write_bytes`
+    ///
+    /// Otherwise, both IPv4 and IPv6 peers are encoded as a list of
+    /// dictionaries in `peers` (BEP 3) and `peers6` is omitted. Peer IDs are
+    /// never included, which is what clients sending `no_peer_id` ask for.
+    #[serde(skip, default = "default_compact")]
+    pub compact: bool,
+}
+
+fn default_compact() -> bool {
+    true
 }
 
 impl AnnounceResponse {
@@ -66,8 +97,21 @@ impl AnnounceResponse {
 
         bytes_written += output.write(b"d8:completei")?;
         bytes_written += output.write(itoa::Buffer::new().format(self.complete).as_bytes())?;
+        bytes_written += output.write(b"e")?;
 
-        bytes_written += output.write(b"e10:incompletei")?;
+        match self.external_ip {
+            Some(IpAddr::V4(ip)) => {
+                bytes_written += output.write(b"11:external ip4:")?;
+                bytes_written += output.write(&ip.octets())?;
+            }
+            Some(IpAddr::V6(ip)) => {
+                bytes_written += output.write(b"11:external ip16:")?;
+                bytes_written += output.write(&ip.octets())?;
+            }
+            None => (),
+        }
+
+        bytes_written += output.write(b"10:incompletei")?;
         bytes_written += output.write(itoa::Buffer::new().format(self.incomplete).as_bytes())?;
 
         bytes_written += output.write(b"e8:intervali")?;
@@ -78,6 +122,45 @@ impl AnnounceResponse {
         )?;
 
         bytes_written += output.write(b"e5:peers")?;
+
+        if self.compact {
+            bytes_written += self.write_compact_peers(output)?;
+        } else {
+            bytes_written += self.write_non_compact_peers(output)?;
+        }
+
+        if let Some(ref tracker_id) = self.tracker_id {
+            let tracker_id_bytes = tracker_id.as_bytes();
+
+            bytes_written += output.write(b"10:tracker id")?;
+            bytes_written += output.write(
+                itoa::Buffer::new()
+                    .format(tracker_id_bytes.len())
+                    .as_bytes(),
+            )?;
+            bytes_written += output.write(b":")?;
+            bytes_written += output.write(tracker_id_bytes)?;
+        }
+
+        if let Some(ref warning_message) = self.warning_message {
+            let message_bytes = warning_message.as_bytes();
+
+            bytes_written += output.write(b"15:warning message")?;
+            bytes_written +=
+                output.write(itoa::Buffer::new().format(message_bytes.len()).as_bytes())?;
+            bytes_written += output.write(b":")?;
+            bytes_written += output.write(message_bytes)?;
+        }
+
+        bytes_written += output.write(b"e")?;
+
+        Ok(bytes_written)
+    }
+
+    /// Write value of `peers` key, followed by `peers6` key and value
+    fn write_compact_peers<W: Write>(&self, output: &mut W) -> ::std::io::Result<usize> {
+        let mut bytes_written = 0usize;
+
         bytes_written += output.write(
             itoa::Buffer::new()
                 .format(self.peers.0.len() * 6)
@@ -101,14 +184,37 @@ impl AnnounceResponse {
             bytes_written += output.write(&peer.port.to_be_bytes())?;
         }
 
-        if let Some(ref warning_message) = self.warning_message {
-            let message_bytes = warning_message.as_bytes();
+        Ok(bytes_written)
+    }
 
-            bytes_written += output.write(b"15:warning message")?;
+    /// Write value of `peers` key as list of dictionaries
+    fn write_non_compact_peers<W: Write>(&self, output: &mut W) -> ::std::io::Result<usize> {
+        let mut bytes_written = 0usize;
+
+        bytes_written += output.write(b"l")?;
+
+        let peers_v4 = self
+            .peers
+            .0
+            .iter()
+            .map(|peer| (IpAddr::from(peer.ip_address), peer.port));
+        let peers_v6 = self
+            .peers6
+            .0
+            .iter()
+            .map(|peer| (IpAddr::from(peer.ip_address), peer.port));
+
+        for (ip_address, port) in peers_v4.chain(peers_v6) {
+            let ip_address = ip_address.to_string();
+
+            bytes_written += output.write(b"d2:ip")?;
             bytes_written +=
-                output.write(itoa::Buffer::new().format(message_bytes.len()).as_bytes())?;
+                output.write(itoa::Buffer::new().format(ip_address.len()).as_bytes())?;
             bytes_written += output.write(b":")?;
-            bytes_written += output.write(message_bytes)?;
+            bytes_written += output.write(ip_address.as_bytes())?;
+            bytes_written += output.write(b"4:porti")?;
+            bytes_written += output.write(itoa::Buffer::new().format(port).as_bytes())?;
+            bytes_written += output.write(b"ee")?;
         }
 
         bytes_written += output.write(b"e")?;
@@ -124,23 +230,45 @@ pub struct ScrapeResponse {
 }
 
 impl ScrapeResponse {
+    /// Bytes preceding file entries in encoded response
+    pub const FILES_START: &'static [u8] = b"d5:filesd";
+    /// Bytes following file entries in encoded response
+    pub const FILES_END: &'static [u8] = b"ee";
+
     pub fn write_bytes<W: Write>(&self, output: &mut W) -> ::std::io::Result<usize> {
         let mut bytes_written = 0usize;
 
-        bytes_written += output.write(b"d5:filesd")?;
+        bytes_written += output.write(Self::FILES_START)?;
 
         for (info_hash, statistics) in self.files.iter() {
-            bytes_written += output.write(b"20:")?;
-            bytes_written += output.write(&info_hash.0)?;
-            bytes_written += output.write(b"d8:completei")?;
-            bytes_written +=
-                output.write(itoa::Buffer::new().format(statistics.complete).as_bytes())?;
-            bytes_written += output.write(b"e10:downloadedi0e10:incompletei")?;
-            bytes_written +=
-                output.write(itoa::Buffer::new().format(statistics.incomplete).as_bytes())?;
-            bytes_written += output.write(b"ee")?;
+            bytes_written += Self::write_file_entry(output, info_hash, statistics)?;
         }
 
+        bytes_written += output.write(Self::FILES_END)?;
+
+        Ok(bytes_written)
+    }
+
+    /// Write single file entry, allowing responses to be encoded
+    /// incrementally. Entries must be written in info hash order.
+    pub fn write_file_entry<W: Write>(
+        output: &mut W,
+        info_hash: &InfoHash,
+        statistics: &ScrapeStatistics,
+    ) -> ::std::io::Result<usize> {
+        let mut bytes_written = 0usize;
+
+        bytes_written += output.write(b"20:")?;
+        bytes_written += output.write(&info_hash.0)?;
+        bytes_written += output.write(b"d8:completei")?;
+        bytes_written +=
+            output.write(itoa::Buffer::new().format(statistics.complete).as_bytes())?;
+        bytes_written += output.write(b"e10:downloadedi")?;
+        bytes_written +=
+            output.write(itoa::Buffer::new().format(statistics.downloaded).as_bytes())?;
+        bytes_written += output.write(b"e10:incompletei")?;
+        bytes_written +=
+            output.write(itoa::Buffer::new().format(statistics.incomplete).as_bytes())?;
         bytes_written += output.write(b"ee")?;
 
         Ok(bytes_written)
@@ -236,7 +364,7 @@ impl quickcheck::Arbitrary for ScrapeStatistics {
         Self {
             complete: usize::arbitrary(g),
             incomplete: usize::arbitrary(g),
-            downloaded: 0,
+            downloaded: usize::arbitrary(g),
         }
     }
 }
@@ -248,9 +376,12 @@ impl quickcheck::Arbitrary for AnnounceResponse {
             announce_interval: usize::arbitrary(g),
             complete: usize::arbitrary(g),
             incomplete: usize::arbitrary(g),
+            external_ip: quickcheck::Arbitrary::arbitrary(g),
             peers: ResponsePeerListV4::arbitrary(g),
             peers6: ResponsePeerListV6::arbitrary(g),
+            tracker_id: Option::<String>::arbitrary(g).map(|s| s.into()),
             warning_message: quickcheck::Arbitrary::arbitrary(g),
+            compact: quickcheck::Arbitrary::arbitrary(g),
         }
     }
 }
@@ -279,8 +410,45 @@ mod tests {
 
     use super::*;
 
+    #[derive(Serialize)]
+    struct NonCompactPeerReference {
+        ip: String,
+        port: u16,
+    }
+
+    /// Announce response with peers in non-compact format, serialized with
+    /// bendy as reference
+    #[derive(Serialize)]
+    struct NonCompactAnnounceResponseReference {
+        #[serde(rename = "interval")]
+        announce_interval: usize,
+        complete: usize,
+        incomplete: usize,
+        #[serde(
+            rename = "external ip",
+            skip_serializing_if = "Option::is_none",
+            serialize_with = "serialize_optional_ip_bytes"
+        )]
+        external_ip: Option<IpAddr>,
+        peers: Vec<NonCompactPeerReference>,
+        #[serde(
+            rename = "tracker id",
+            skip_serializing_if = "Option::is_none",
+            serialize_with = "serialize_optional_string"
+        )]
+        tracker_id: Option<String>,
+        #[serde(
+            rename = "warning message",
+            skip_serializing_if = "Option::is_none",
+            serialize_with = "serialize_optional_string"
+        )]
+        warning_message: Option<String>,
+    }
+
     #[quickcheck]
-    fn test_announce_response_to_bytes(response: AnnounceResponse) -> bool {
+    fn test_announce_response_to_bytes(mut response: AnnounceResponse) -> bool {
+        response.compact = true;
+
         let reference = bendy::serde::to_bytes(&Response::Announce(response.clone())).unwrap();
 
         let mut hand_written = Vec::new();
@@ -297,6 +465,149 @@ mod tests {
         success
     }
 
+    #[quickcheck]
+    fn test_non_compact_announce_response_to_bytes(mut response: AnnounceResponse) -> bool {
+        response.compact = false;
+
+        let peers_v4 = response.peers.0.iter().map(|peer| NonCompactPeerReference {
+            ip: peer.ip_address.to_string(),
+            port: peer.port,
+        });
+        let peers_v6 = response
+            .peers6
+            .0
+            .iter()
+            .map(|peer| NonCompactPeerReference {
+                ip: peer.ip_address.to_string(),
+                port: peer.port,
+            });
+
+        let reference = bendy::serde::to_bytes(&NonCompactAnnounceResponseReference {
+            announce_interval: response.announce_interval,
+            complete: response.complete,
+            incomplete: response.incomplete,
+            external_ip: response.external_ip,
+            peers: peers_v4.chain(peers_v6).collect(),
+            tracker_id: response.tracker_id.as_ref().map(|s| s.to_string()),
+            warning_message: response.warning_message.clone(),
+        })
+        .unwrap();
+
+        let mut hand_written = Vec::new();
+
+        response.write_bytes(&mut hand_written).unwrap();
+
+        let success = hand_written == reference;
+
+        if !success {
+            println!("reference:    {}", String::from_utf8_lossy(&reference));
+            println!("hand_written: {}", String::from_utf8_lossy(&hand_written));
+        }
+
+        success
+    }
+
+    #[quickcheck]
+    fn test_announce_response_from_bytes(mut response: AnnounceResponse) -> bool {
+        // IPv6 peers can't be told apart from IPv4 peers once they are put in
+        // the same non-compact list
+        if !response.compact {
+            response.peers6.0.clear();
+        }
+
+        // serde_bencode decodes integers as i64
+        response.announce_interval %= i64::MAX as usize;
+        response.complete %= i64::MAX as usize;
+        response.incomplete %= i64::MAX as usize;
+
+        let mut bytes = Vec::new();
+
+        response.write_bytes(&mut bytes).unwrap();
+
+        match Response::parse_bytes(&bytes) {
+            Ok(Response::Announce(parsed)) => {
+                parsed.announce_interval == response.announce_interval
+                    && parsed.complete == response.complete
+                    && parsed.incomplete == response.incomplete
+                    && parsed.external_ip == response.external_ip
+                    && parsed.peers.0 == response.peers.0
+                    && parsed.peers6.0 == response.peers6.0
+                    && parsed.tracker_id == response.tracker_id
+                    && parsed.warning_message == response.warning_message
+            }
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn test_non_compact_peers_from_bytes() {
+        let bytes = b"d8:completei1e10:incompletei2e8:intervali3e5:peersld2:ip9:127.0.0.17:peer id20:aaaaaaaaaaaaaaaaaaaa4:porti1000eeee";
+
+        let Ok(Response::Announce(response)) = Response::parse_bytes(bytes) else {
+            panic!("not an announce response");
+        };
+
+        assert_eq!(
+            response.peers.0,
+            vec![ResponsePeer {
+                ip_address: Ipv4Addr::LOCALHOST,
+                port: 1000
+            }]
+        );
+        assert!(response.peers6.0.is_empty());
+
+        // IPv6 peers in non-compact list are not supported
+        let bytes = b"d8:completei1e10:incompletei2e8:intervali3e5:peersld2:ip3:::14:porti1000eeee";
+
+        assert!(Response::parse_bytes(bytes).is_err());
+    }
+
+    #[test]
+    fn test_shared_fixtures() {
+        use aquatic_protocol_tests as fixtures;
+
+        let (peer_ip, peer_port) = fixtures::RESPONSE_PEER;
+
+        let response = AnnounceResponse {
+            announce_interval: fixtures::ANNOUNCE_INTERVAL,
+            complete: fixtures::SEEDERS,
+            incomplete: fixtures::LEECHERS,
+            external_ip: None,
+            peers: ResponsePeerListV4(vec![ResponsePeer {
+                ip_address: peer_ip.into(),
+                port: peer_port,
+            }]),
+            peers6: ResponsePeerListV6(Vec::new()),
+            tracker_id: None,
+            warning_message: None,
+            compact: true,
+        };
+
+        let mut bytes = Vec::new();
+
+        response.write_bytes(&mut bytes).unwrap();
+
+        assert_eq!(bytes, fixtures::http::ANNOUNCE_RESPONSE);
+
+        let response = ScrapeResponse {
+            files: [(
+                InfoHash(fixtures::INFO_HASH),
+                ScrapeStatistics {
+                    complete: fixtures::SEEDERS,
+                    incomplete: fixtures::LEECHERS,
+                    downloaded: fixtures::DOWNLOADED,
+                },
+            )]
+            .into(),
+        };
+
+        let mut bytes = Vec::new();
+
+        response.write_bytes(&mut bytes).unwrap();
+
+        assert_eq!(bytes, fixtures::http::scrape_response());
+    }
+
     #[quickcheck]
     fn test_scrape_response_to_bytes(response: ScrapeResponse) -> bool {
         let reference = bendy::serde::to_bytes(&Response::Scrape(response.clone())).unwrap();
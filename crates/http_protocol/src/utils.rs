@@ -1,8 +1,10 @@
 use std::io::Write;
-use std::net::{Ipv4Addr, Ipv6Addr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
 
 use anyhow::Context;
-use serde::{de::Visitor, Deserializer, Serializer};
+use serde::de::{SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serializer};
 
 use super::response::ResponsePeer;
 
@@ -18,52 +20,111 @@ pub fn urlencode_20_bytes(input: [u8; 20], output: &mut impl Write) -> ::std::io
     Ok(())
 }
 
+/// Decode percent-encoded value into exactly 20 bytes, without intermediate
+/// allocation
+///
+/// Fails if the value doesn't decode to exactly 20 bytes or contains invalid
+/// percent-encoding. Bytes that aren't percent-encoded are used as is.
 pub fn urldecode_20_bytes(value: &str) -> anyhow::Result<[u8; 20]> {
-    let mut out_arr = [0u8; 20];
+    let value = value.as_bytes();
 
-    let mut chars = value.chars();
+    let mut out_arr = [0u8; 20];
 
-    for i in 0..20 {
-        let c = chars.next().with_context(|| "less than 20 chars")?;
+    // Fast path for fully percent-encoded values, as sent by many clients.
+    // Values of this length only decode to 20 bytes if every byte is encoded.
+    if value.len() == 60 {
+        for (chunk, out) in value.chunks_exact(3).zip(out_arr.iter_mut()) {
+            if chunk[0] != b'%' {
+                return Err(anyhow::anyhow!("value decodes to more than 20 bytes"));
+            }
 
-        if c as u32 > 255 {
-            return Err(anyhow::anyhow!(
-                "character not in single byte range: {:#?}",
-                c
-            ));
+            *out = decode_hex_pair(chunk[1], chunk[2])?;
         }
 
-        if c == '%' {
-            let first = chars
-                .next()
-                .with_context(|| "missing first urldecode char in pair")?;
-            let second = chars
-                .next()
-                .with_context(|| "missing second urldecode char in pair")?;
+        return Ok(out_arr);
+    }
+
+    if !(20..=60).contains(&value.len()) {
+        return Err(anyhow::anyhow!(
+            "value length {} can't decode to 20 bytes",
+            value.len()
+        ));
+    }
 
-            let hex = [first as u8, second as u8];
+    let mut position = 0;
 
-            hex::decode_to_slice(hex, &mut out_arr[i..i + 1])
-                .map_err(|err| anyhow::anyhow!("hex decode error: {:?}", err))?;
-        } else {
-            out_arr[i] = c as u8;
+    for out in out_arr.iter_mut() {
+        match value.get(position) {
+            Some(b'%') => {
+                let pair = value
+                    .get(position + 1..position + 3)
+                    .with_context(|| "incomplete percent-encoded byte")?;
+
+                *out = decode_hex_pair(pair[0], pair[1])?;
+
+                position += 3;
+            }
+            Some(b) => {
+                *out = *b;
+
+                position += 1;
+            }
+            None => return Err(anyhow::anyhow!("value decodes to less than 20 bytes")),
         }
     }
 
-    if chars.next().is_some() {
-        return Err(anyhow::anyhow!("more than 20 chars"));
+    if position != value.len() {
+        return Err(anyhow::anyhow!("value decodes to more than 20 bytes"));
     }
 
     Ok(out_arr)
 }
 
+/// Values of ASCII hex digits, 0xff for other bytes
+static HEX_DIGIT_VALUES: [u8; 256] = {
+    let mut values = [0xff; 256];
+    let mut i = 0;
+
+    while i < 10 {
+        values[b'0' as usize + i] = i as u8;
+
+        i += 1;
+    }
+
+    let mut i = 0;
+
+    while i < 6 {
+        values[b'a' as usize + i] = 10 + i as u8;
+        values[b'A' as usize + i] = 10 + i as u8;
+
+        i += 1;
+    }
+
+    values
+};
+
+fn decode_hex_pair(first: u8, second: u8) -> anyhow::Result<u8> {
+    let first_value = HEX_DIGIT_VALUES[first as usize];
+    let second_value = HEX_DIGIT_VALUES[second as usize];
+
+    if (first_value | second_value) == 0xff {
+        return Err(anyhow::anyhow!(
+            "invalid percent-encoded byte: {:?}",
+            [first, second]
+        ));
+    }
+
+    Ok((first_value << 4) | second_value)
+}
+
 #[inline]
-pub fn serialize_optional_string<S>(v: &Option<String>, serializer: S) -> Result<S::Ok, S::Error>
+pub fn serialize_optional_string<S, T>(v: &Option<T>, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
+    T: AsRef<str>,
 {
     match v {
-        Some(s) => serializer.serialize_str(s.as_str()),
+        Some(s) => serializer.serialize_str(s.as_ref()),
         None => Err(serde::ser::Error::custom("use skip_serializing_if")),
     }
 }
@@ -110,6 +171,48 @@ where
     deserializer.deserialize_any(TwentyByteVisitor)
 }
 
+/// Serialize IP address in compact format (4 or 16 bytes, BEP 24)
+pub fn serialize_optional_ip_bytes<S>(v: &Option<IpAddr>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match v {
+        Some(IpAddr::V4(ip)) => serializer.serialize_bytes(&ip.octets()),
+        Some(IpAddr::V6(ip)) => serializer.serialize_bytes(&ip.octets()),
+        None => serializer.serialize_none(),
+    }
+}
+
+struct IpBytesVisitor;
+
+impl<'de> Visitor<'de> for IpBytesVisitor {
+    type Value = IpAddr;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("4 or 16 bytes")
+    }
+
+    fn visit_bytes<E>(self, value: &[u8]) -> Result<Self::Value, E>
+    where
+        E: ::serde::de::Error,
+    {
+        if let Ok(octets) = <[u8; 4]>::try_from(value) {
+            Ok(IpAddr::from(octets))
+        } else if let Ok(octets) = <[u8; 16]>::try_from(value) {
+            Ok(IpAddr::from(octets))
+        } else {
+            Err(::serde::de::Error::custom("not 4 or 16 bytes"))
+        }
+    }
+}
+
+pub fn deserialize_optional_ip_bytes<'de, D>(deserializer: D) -> Result<Option<IpAddr>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_any(IpBytesVisitor).map(Some)
+}
+
 pub fn serialize_response_peers_ipv4<S>(
     response_peers: &[ResponsePeer<Ipv4Addr>],
     serializer: S,
@@ -150,7 +253,7 @@ impl<'de> Visitor<'de> for ResponsePeersIpv4Visitor {
     type Value = Vec<ResponsePeer<Ipv4Addr>>;
 
     fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-        formatter.write_str("byte-encoded ipv4 address-port pairs")
+        formatter.write_str("byte-encoded ipv4 address-port pairs or list of peer dictionaries")
     }
 
     #[inline]
@@ -182,6 +285,14 @@ impl<'de> Visitor<'de> for ResponsePeersIpv4Visitor {
 
         Ok(peers)
     }
+
+    #[inline]
+    fn visit_seq<A>(self, seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        visit_non_compact_response_peers(seq)
+    }
 }
 
 #[inline]
@@ -200,7 +311,7 @@ impl<'de> Visitor<'de> for ResponsePeersIpv6Visitor {
     type Value = Vec<ResponsePeer<Ipv6Addr>>;
 
     fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-        formatter.write_str("byte-encoded ipv6 address-port pairs")
+        formatter.write_str("byte-encoded ipv6 address-port pairs or list of peer dictionaries")
     }
 
     #[inline]
@@ -232,6 +343,14 @@ impl<'de> Visitor<'de> for ResponsePeersIpv6Visitor {
 
         Ok(peers)
     }
+
+    #[inline]
+    fn visit_seq<A>(self, seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        visit_non_compact_response_peers(seq)
+    }
 }
 
 #[inline]
@@ -244,6 +363,35 @@ where
     deserializer.deserialize_any(ResponsePeersIpv6Visitor)
 }
 
+/// Peer in non-compact announce response. Other keys, such as "peer id",
+/// are ignored.
+#[derive(Deserialize)]
+struct NonCompactResponsePeer {
+    ip: String,
+    port: u16,
+}
+
+fn visit_non_compact_response_peers<'de, A, I>(mut seq: A) -> Result<Vec<ResponsePeer<I>>, A::Error>
+where
+    A: SeqAccess<'de>,
+    I: FromStr + Eq,
+{
+    let mut peers = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+
+    while let Some(peer) = seq.next_element::<NonCompactResponsePeer>()? {
+        let ip_address = peer.ip.parse().map_err(|_| {
+            ::serde::de::Error::custom(format!("invalid peer ip address: {}", peer.ip))
+        })?;
+
+        peers.push(ResponsePeer {
+            ip_address,
+            port: peer.port,
+        });
+    }
+
+    Ok(peers)
+}
+
 #[cfg(test)]
 mod tests {
     use quickcheck_macros::*;
@@ -307,6 +455,36 @@ mod tests {
         input == decoded
     }
 
+    #[test]
+    fn test_urldecode_20_bytes_strict() {
+        let mixed = "%04%0bkV%3f%5cr%14%a6%b7%98%adC%c3%c9.%40%24%00%b9";
+
+        assert_eq!(
+            urldecode_20_bytes(mixed).unwrap(),
+            [
+                0x04, 0x0b, b'k', b'V', 0x3f, 0x5c, b'r', 0x14, 0xa6, 0xb7, 0x98, 0xad, b'C', 0xc3,
+                0xc9, b'.', 0x40, 0x24, 0x00, 0xb9
+            ]
+        );
+        assert_eq!(urldecode_20_bytes(&"%FF".repeat(20)).unwrap(), [0xff; 20]);
+        assert_eq!(urldecode_20_bytes(&"a".repeat(20)).unwrap(), [b'a'; 20]);
+
+        // Wrong decoded length
+        assert!(urldecode_20_bytes(&"a".repeat(19)).is_err());
+        assert!(urldecode_20_bytes(&"a".repeat(21)).is_err());
+        assert!(urldecode_20_bytes(&"%aa".repeat(19)).is_err());
+        assert!(urldecode_20_bytes(&format!("{}a", "%aa".repeat(20))).is_err());
+        assert!(urldecode_20_bytes(&format!("{}%a", "a".repeat(19))).is_err());
+
+        // Invalid percent-encoding
+        assert!(urldecode_20_bytes(&format!("{}%zz", "a".repeat(19))).is_err());
+        assert!(urldecode_20_bytes(&format!("{}%+1", "%00".repeat(19))).is_err());
+        assert!(urldecode_20_bytes(&format!("{}%\u{e9}", "a".repeat(19))).is_err());
+
+        // Non-ASCII characters are multiple bytes long
+        assert!(urldecode_20_bytes(&format!("{}\u{e9}", "a".repeat(19))).is_err());
+    }
+
     #[quickcheck]
     fn test_serde_response_peers_ipv4(peers: Vec<ResponsePeer<Ipv4Addr>>) -> bool {
         let serialized = bendy::serde::to_bytes(&peers).unwrap();
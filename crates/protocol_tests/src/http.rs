@@ -0,0 +1,50 @@
+//! HTTP tracker protocol fixtures (BEP 3, BEP 23)
+
+/// [`INFO_HASH`](crate::INFO_HASH) as query parameter value, only
+/// percent-encoding bytes that need it
+macro_rules! info_hash_param {
+    () => {
+        "%04%0bkV%3f%5cr%14%a6%b7%98%adC%c3%c9.%40%24%00%b9"
+    };
+}
+
+pub const SCRAPE_REQUEST_PATH: &str = concat!("/scrape?info_hash=", info_hash_param!());
+
+/// Compact announce response with a single IPv4 peer
+pub const ANNOUNCE_RESPONSE: &[u8] =
+    b"d8:completei2e10:incompletei1e8:intervali1800e5:peers6:\x7f\x00\x00\x01\x1a\xe16:peers60:e";
+
+/// GET path of compact announce request
+pub fn announce_request_path(event: crate::Event) -> String {
+    let mut path = format!(
+        "/announce?info_hash={}&peer_id={}&port={}&uploaded={}&downloaded={}&left={}",
+        info_hash_param!(),
+        ::std::str::from_utf8(&crate::PEER_ID).unwrap(),
+        crate::PORT,
+        crate::BYTES_UPLOADED,
+        crate::BYTES_DOWNLOADED,
+        crate::BYTES_LEFT,
+    );
+
+    if let Some(event) = event.http_param() {
+        path.push_str("&event=");
+        path.push_str(event);
+    }
+
+    path.push_str(&format!(
+        "&numwant={}&key={:08x}&compact=1",
+        crate::NUMWANT,
+        crate::KEY
+    ));
+
+    path
+}
+
+pub fn scrape_response() -> Vec<u8> {
+    [
+        b"d5:filesd20:".as_slice(),
+        &crate::INFO_HASH,
+        b"d8:completei2e10:downloadedi3e10:incompletei1eeee",
+    ]
+    .concat()
+}
@@ -0,0 +1,81 @@
+//! Canonical tracker protocol requests and responses
+//!
+//! Fixtures for the HTTP, UDP and WebTorrent protocols all describe the same
+//! announce and scrape: peer [`PEER_ID`] announcing torrent [`INFO_HASH`] on
+//! [`PORT`] and getting statistics [`SEEDERS`], [`LEECHERS`] and
+//! [`DOWNLOADED`] in return. Fixtures only consist of plain values, so that
+//! protocol crates can use them in their tests without depending on each
+//! other.
+
+pub mod http;
+pub mod udp;
+pub mod ws;
+
+/// Info hash of all fixtures
+///
+/// Contains bytes that need to be percent-encoded in query strings, escaped
+/// in JSON and that aren't valid UTF-8 on their own.
+pub const INFO_HASH: [u8; 20] = [
+    0x04, 0x0b, b'k', b'V', 0x3f, 0x5c, b'r', 0x14, 0xa6, 0xb7, 0x98, 0xad, b'C', 0xc3, 0xc9, b'.',
+    0x40, 0x24, 0x00, 0xb9,
+];
+/// Peer ID of all fixtures, in Azureus style
+pub const PEER_ID: [u8; 20] = *b"-AQ0800-5ert69muw5t8";
+
+pub const PORT: u16 = 6881;
+pub const BYTES_UPLOADED: u64 = 200;
+pub const BYTES_DOWNLOADED: u64 = 100;
+pub const BYTES_LEFT: u64 = 1000;
+pub const NUMWANT: usize = 50;
+/// Peer key, sent as eight hexadecimal digits in HTTP announce requests
+pub const KEY: u32 = 0xabcd;
+
+pub const ANNOUNCE_INTERVAL: usize = 1800;
+pub const SEEDERS: usize = 2;
+pub const LEECHERS: usize = 1;
+pub const DOWNLOADED: usize = 3;
+/// Only peer in announce responses
+pub const RESPONSE_PEER: ([u8; 4], u16) = ([127, 0, 0, 1], 6881);
+
+/// Announce event, as understood by all protocols
+///
+/// Peers announcing without an event are just updating their statistics.
+/// This is called `empty` in the HTTP protocol, `none` in the UDP protocol
+/// and `update` in the WebTorrent protocol.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Event {
+    None,
+    Started,
+    Stopped,
+    Completed,
+}
+
+impl Event {
+    pub const ALL: [Self; 4] = [Self::None, Self::Started, Self::Stopped, Self::Completed];
+
+    /// Value of `event` query parameter, which is left out for `None`
+    pub fn http_param(self) -> Option<&'static str> {
+        match self {
+            Self::None => None,
+            Self::Started => Some("started"),
+            Self::Stopped => Some("stopped"),
+            Self::Completed => Some("completed"),
+        }
+    }
+
+    /// Value of `event` field in UDP announce requests (BEP 15)
+    pub fn udp_code(self) -> u32 {
+        match self {
+            Self::None => 0,
+            Self::Completed => 1,
+            Self::Started => 2,
+            Self::Stopped => 3,
+        }
+    }
+
+    /// Value of `event` field in WebTorrent announce requests, which is left
+    /// out for `None`
+    pub fn ws_field(self) -> Option<&'static str> {
+        self.http_param()
+    }
+}
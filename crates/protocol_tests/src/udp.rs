@@ -0,0 +1,88 @@
+//! UDP tracker protocol fixtures (BEP 15)
+//!
+//! Packets are built field by field, in network byte order.
+
+pub const CONNECTION_ID: u64 = 0x0102030405060708;
+pub const TRANSACTION_ID: u32 = 0x12345678;
+
+const PROTOCOL_IDENTIFIER: u64 = 0x41727101980;
+
+const ACTION_CONNECT: u32 = 0;
+const ACTION_ANNOUNCE: u32 = 1;
+const ACTION_SCRAPE: u32 = 2;
+
+pub fn connect_request() -> Vec<u8> {
+    [
+        PROTOCOL_IDENTIFIER.to_be_bytes().as_slice(),
+        &ACTION_CONNECT.to_be_bytes(),
+        &TRANSACTION_ID.to_be_bytes(),
+    ]
+    .concat()
+}
+
+pub fn connect_response() -> Vec<u8> {
+    [
+        ACTION_CONNECT.to_be_bytes().as_slice(),
+        &TRANSACTION_ID.to_be_bytes(),
+        &CONNECTION_ID.to_be_bytes(),
+    ]
+    .concat()
+}
+
+/// Announce request without IP address, asking for
+/// [`NUMWANT`](crate::NUMWANT) peers
+pub fn announce_request(event: crate::Event) -> Vec<u8> {
+    [
+        CONNECTION_ID.to_be_bytes().as_slice(),
+        &ACTION_ANNOUNCE.to_be_bytes(),
+        &TRANSACTION_ID.to_be_bytes(),
+        &crate::INFO_HASH,
+        &crate::PEER_ID,
+        &crate::BYTES_DOWNLOADED.to_be_bytes(),
+        &crate::BYTES_LEFT.to_be_bytes(),
+        &crate::BYTES_UPLOADED.to_be_bytes(),
+        &event.udp_code().to_be_bytes(),
+        &[0; 4],
+        &crate::KEY.to_be_bytes(),
+        &(crate::NUMWANT as u32).to_be_bytes(),
+        &crate::PORT.to_be_bytes(),
+    ]
+    .concat()
+}
+
+/// Announce response with a single IPv4 peer
+pub fn announce_response_ipv4() -> Vec<u8> {
+    let (peer_ip, peer_port) = crate::RESPONSE_PEER;
+
+    [
+        ACTION_ANNOUNCE.to_be_bytes().as_slice(),
+        &TRANSACTION_ID.to_be_bytes(),
+        &(crate::ANNOUNCE_INTERVAL as u32).to_be_bytes(),
+        &(crate::LEECHERS as u32).to_be_bytes(),
+        &(crate::SEEDERS as u32).to_be_bytes(),
+        &peer_ip,
+        &peer_port.to_be_bytes(),
+    ]
+    .concat()
+}
+
+pub fn scrape_request() -> Vec<u8> {
+    [
+        CONNECTION_ID.to_be_bytes().as_slice(),
+        &ACTION_SCRAPE.to_be_bytes(),
+        &TRANSACTION_ID.to_be_bytes(),
+        &crate::INFO_HASH,
+    ]
+    .concat()
+}
+
+pub fn scrape_response() -> Vec<u8> {
+    [
+        ACTION_SCRAPE.to_be_bytes().as_slice(),
+        &TRANSACTION_ID.to_be_bytes(),
+        &(crate::SEEDERS as u32).to_be_bytes(),
+        &(crate::DOWNLOADED as u32).to_be_bytes(),
+        &(crate::LEECHERS as u32).to_be_bytes(),
+    ]
+    .concat()
+}
@@ -0,0 +1,43 @@
+//! WebTorrent tracker protocol fixtures
+//!
+//! Info hashes and peer IDs are sent as strings of 20 characters, each
+//! corresponding to one byte.
+
+/// [`INFO_HASH`](crate::INFO_HASH) as JSON string, with control and non-ASCII
+/// characters escaped
+macro_rules! info_hash_json {
+    () => {
+        r#""\u0004\u000bkV?\\r\u0014\u00a6\u00b7\u0098\u00adC\u00c3\u00c9.@$\u0000\u00b9""#
+    };
+}
+
+pub const ANNOUNCE_RESPONSE: &str = concat!(
+    r#"{"action":"announce","info_hash":"#,
+    info_hash_json!(),
+    r#","complete":2,"incomplete":1,"interval":1800}"#
+);
+
+pub const SCRAPE_REQUEST: &str =
+    concat!(r#"{"action":"scrape","info_hash":"#, info_hash_json!(), "}");
+
+pub const SCRAPE_RESPONSE: &str = concat!(
+    r#"{"action":"scrape","files":{"#,
+    info_hash_json!(),
+    r#":{"complete":2,"incomplete":1,"downloaded":3}}}"#
+);
+
+/// Announce request without offers
+pub fn announce_request(event: crate::Event) -> String {
+    let opt_event = event
+        .ws_field()
+        .map(|event| format!(r#","event":"{}""#, event))
+        .unwrap_or_default();
+
+    format!(
+        r#"{{"action":"announce","info_hash":{},"peer_id":"{}","left":{}{}}}"#,
+        info_hash_json!(),
+        ::std::str::from_utf8(&crate::PEER_ID).unwrap(),
+        crate::BYTES_LEFT,
+        opt_event,
+    )
+}
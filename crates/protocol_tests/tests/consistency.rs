@@ -0,0 +1,448 @@
+//! Check that the HTTP, UDP and WebTorrent protocol crates agree on fixtures
+//! and on info hash, peer ID and announce event semantics
+
+use std::net::Ipv4Addr;
+use std::num::NonZeroU16;
+
+use aquatic_protocol_tests::*;
+use quickcheck::{Arbitrary, Gen};
+use quickcheck_macros::quickcheck;
+
+/// Announce request fields that all protocols have in common
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Announce {
+    info_hash: [u8; 20],
+    peer_id: [u8; 20],
+    event: Event,
+    bytes_left: u64,
+}
+
+impl Announce {
+    fn fixture(event: Event) -> Self {
+        Self {
+            info_hash: INFO_HASH,
+            peer_id: PEER_ID,
+            event,
+            bytes_left: BYTES_LEFT,
+        }
+    }
+}
+
+impl Arbitrary for Announce {
+    fn arbitrary(g: &mut Gen) -> Self {
+        let mut info_hash = [0; 20];
+        let mut peer_id = [0; 20];
+
+        info_hash.fill_with(|| u8::arbitrary(g));
+        peer_id.fill_with(|| u8::arbitrary(g));
+
+        Self {
+            info_hash,
+            peer_id,
+            event: *g.choose(&Event::ALL).unwrap(),
+            // WebTorrent clients send numbers as JSON doubles
+            bytes_left: u64::arbitrary(g) >> 11,
+        }
+    }
+}
+
+/// Scrape statistics for a single torrent
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Statistics {
+    info_hash: [u8; 20],
+    seeders: usize,
+    leechers: usize,
+    downloaded: usize,
+}
+
+impl Statistics {
+    fn fixture() -> Self {
+        Self {
+            info_hash: INFO_HASH,
+            seeders: SEEDERS,
+            leechers: LEECHERS,
+            downloaded: DOWNLOADED,
+        }
+    }
+}
+
+impl Arbitrary for Statistics {
+    fn arbitrary(g: &mut Gen) -> Self {
+        let mut info_hash = [0; 20];
+
+        info_hash.fill_with(|| u8::arbitrary(g));
+
+        // UDP statistics are 32-bit signed integers
+        Self {
+            info_hash,
+            seeders: u16::arbitrary(g) as usize,
+            leechers: u16::arbitrary(g) as usize,
+            downloaded: u16::arbitrary(g) as usize,
+        }
+    }
+}
+
+mod http_tracker {
+    use aquatic_http_protocol::common::*;
+    use aquatic_http_protocol::request::*;
+    use aquatic_http_protocol::response::*;
+
+    use super::*;
+
+    pub fn parse_announce(bytes: &[u8]) -> Announce {
+        let Some(Request::Announce(request)) = Request::parse_bytes(bytes).unwrap() else {
+            panic!("not an announce request");
+        };
+
+        Announce {
+            info_hash: request.info_hash.0,
+            peer_id: request.peer_id.0,
+            event: match request.event {
+                AnnounceEvent::Empty => Event::None,
+                AnnounceEvent::Started => Event::Started,
+                AnnounceEvent::Stopped => Event::Stopped,
+                AnnounceEvent::Completed => Event::Completed,
+            },
+            bytes_left: request.bytes_left as u64,
+        }
+    }
+
+    pub fn write_announce(announce: Announce) -> Vec<u8> {
+        let request = Request::Announce(AnnounceRequest {
+            info_hash: InfoHash(announce.info_hash),
+            peer_id: PeerId(announce.peer_id),
+            port: PORT,
+            bytes_uploaded: 0,
+            bytes_downloaded: 0,
+            bytes_left: announce.bytes_left as usize,
+            event: match announce.event {
+                Event::None => AnnounceEvent::Empty,
+                Event::Started => AnnounceEvent::Started,
+                Event::Stopped => AnnounceEvent::Stopped,
+                Event::Completed => AnnounceEvent::Completed,
+            },
+            numwant: None,
+            key: None,
+            tracker_id: None,
+            compact: true,
+            no_peer_id: false,
+            passkey: None,
+            ip: None,
+            ipv4: None,
+            ipv6: None,
+        });
+
+        let mut bytes = Vec::new();
+
+        request.write(&mut bytes, b"").unwrap();
+
+        bytes
+    }
+
+    pub fn parse_scrape_response(bytes: &[u8]) -> Vec<Statistics> {
+        let Response::Scrape(response) = Response::parse_bytes(bytes).unwrap() else {
+            panic!("not a scrape response");
+        };
+
+        response
+            .files
+            .into_iter()
+            .map(|(info_hash, stats)| Statistics {
+                info_hash: info_hash.0,
+                seeders: stats.complete,
+                leechers: stats.incomplete,
+                downloaded: stats.downloaded,
+            })
+            .collect()
+    }
+
+    pub fn write_scrape_response(stats: Statistics) -> Vec<u8> {
+        let response = ScrapeResponse {
+            files: [(
+                InfoHash(stats.info_hash),
+                ScrapeStatistics {
+                    complete: stats.seeders,
+                    incomplete: stats.leechers,
+                    downloaded: stats.downloaded,
+                },
+            )]
+            .into(),
+        };
+
+        let mut bytes = Vec::new();
+
+        response.write_bytes(&mut bytes).unwrap();
+
+        bytes
+    }
+}
+
+mod udp_tracker {
+    use aquatic_udp_protocol::*;
+
+    use super::*;
+
+    pub fn parse_announce(bytes: &[u8]) -> Announce {
+        let Request::Announce(request) = Request::parse_bytes(bytes, 70).unwrap() else {
+            panic!("not an announce request");
+        };
+
+        Announce {
+            info_hash: request.info_hash.0,
+            peer_id: request.peer_id.0,
+            event: match request.event.into() {
+                AnnounceEvent::None => Event::None,
+                AnnounceEvent::Started => Event::Started,
+                AnnounceEvent::Stopped => Event::Stopped,
+                AnnounceEvent::Completed => Event::Completed,
+            },
+            bytes_left: request.bytes_left.0.get() as u64,
+        }
+    }
+
+    pub fn write_announce(announce: Announce) -> Vec<u8> {
+        let request = Request::Announce(AnnounceRequest {
+            connection_id: ConnectionId::new(udp::CONNECTION_ID as i64),
+            action_placeholder: Default::default(),
+            transaction_id: TransactionId::new(udp::TRANSACTION_ID as i32),
+            info_hash: InfoHash(announce.info_hash),
+            peer_id: PeerId(announce.peer_id),
+            bytes_downloaded: NumberOfBytes::new(0),
+            bytes_left: NumberOfBytes::new(announce.bytes_left as i64),
+            bytes_uploaded: NumberOfBytes::new(0),
+            event: match announce.event {
+                Event::None => AnnounceEvent::None,
+                Event::Started => AnnounceEvent::Started,
+                Event::Stopped => AnnounceEvent::Stopped,
+                Event::Completed => AnnounceEvent::Completed,
+            }
+            .into(),
+            ip_address: Ipv4AddrBytes([0; 4]),
+            key: PeerKey::new(KEY as i32),
+            peers_wanted: NumberOfPeers::new(-1),
+            port: Port::new(NonZeroU16::new(PORT).unwrap()),
+        });
+
+        let mut bytes = Vec::new();
+
+        request.write_bytes(&mut bytes).unwrap();
+
+        bytes
+    }
+
+    /// UDP scrape responses don't contain info hashes, so the requested one
+    /// is passed in
+    pub fn parse_scrape_response(bytes: &[u8], info_hash: [u8; 20]) -> Vec<Statistics> {
+        let Response::Scrape(response) = Response::parse_bytes(bytes, true).unwrap() else {
+            panic!("not a scrape response");
+        };
+
+        response
+            .torrent_stats
+            .into_iter()
+            .map(|stats| Statistics {
+                info_hash,
+                seeders: stats.seeders.0.get() as usize,
+                leechers: stats.leechers.0.get() as usize,
+                downloaded: stats.completed.0.get() as usize,
+            })
+            .collect()
+    }
+
+    pub fn write_scrape_response(stats: Statistics) -> Vec<u8> {
+        let response = Response::Scrape(ScrapeResponse {
+            transaction_id: TransactionId::new(udp::TRANSACTION_ID as i32),
+            torrent_stats: vec![TorrentScrapeStatistics {
+                seeders: NumberOfPeers::new(stats.seeders as i32),
+                completed: NumberOfDownloads::new(stats.downloaded as i32),
+                leechers: NumberOfPeers::new(stats.leechers as i32),
+            }],
+        });
+
+        let mut bytes = Vec::new();
+
+        response.write_bytes(&mut bytes).unwrap();
+
+        bytes
+    }
+}
+
+mod ws_tracker {
+    use aquatic_ws_protocol::common::*;
+    use aquatic_ws_protocol::incoming::*;
+    use aquatic_ws_protocol::outgoing::*;
+
+    use super::*;
+
+    pub fn parse_announce(json: &str) -> Announce {
+        let mut bytes = json.as_bytes().to_vec();
+
+        let InMessage::AnnounceRequest(request) = InMessage::from_mut_slice(&mut bytes).unwrap()
+        else {
+            panic!("not an announce request");
+        };
+
+        Announce {
+            info_hash: request.info_hash.0,
+            peer_id: request.peer_id.0,
+            event: match request.event.unwrap_or_default() {
+                AnnounceEvent::Update => Event::None,
+                AnnounceEvent::Started => Event::Started,
+                AnnounceEvent::Stopped => Event::Stopped,
+                AnnounceEvent::Completed => Event::Completed,
+            },
+            bytes_left: request.bytes_left.unwrap() as u64,
+        }
+    }
+
+    pub fn write_announce(announce: Announce) -> String {
+        let request = InMessage::AnnounceRequest(AnnounceRequest {
+            action: AnnounceAction::Announce,
+            info_hash: InfoHash(announce.info_hash),
+            peer_id: PeerId(announce.peer_id),
+            bytes_left: Some(announce.bytes_left as usize),
+            event: match announce.event {
+                Event::None => None,
+                Event::Started => Some(AnnounceEvent::Started),
+                Event::Stopped => Some(AnnounceEvent::Stopped),
+                Event::Completed => Some(AnnounceEvent::Completed),
+            },
+            offers: None,
+            numwant: None,
+            answer: None,
+            answer_to_peer_id: None,
+            answer_offer_id: None,
+        });
+
+        ::simd_json::serde::to_string(&request).unwrap()
+    }
+
+    pub fn parse_scrape_response(json: &str) -> Vec<Statistics> {
+        let mut bytes = json.as_bytes().to_vec();
+
+        let OutMessage::ScrapeResponse(response) =
+            ::simd_json::serde::from_slice(&mut bytes).unwrap()
+        else {
+            panic!("not a scrape response");
+        };
+
+        response
+            .files
+            .into_iter()
+            .map(|(info_hash, stats)| Statistics {
+                info_hash: info_hash.0,
+                seeders: stats.complete,
+                leechers: stats.incomplete,
+                downloaded: stats.downloaded,
+            })
+            .collect()
+    }
+
+    pub fn write_scrape_response(stats: Statistics) -> String {
+        let response = OutMessage::ScrapeResponse(ScrapeResponse {
+            action: ScrapeAction::Scrape,
+            files: [(
+                InfoHash(stats.info_hash),
+                ScrapeStatistics {
+                    complete: stats.seeders,
+                    incomplete: stats.leechers,
+                    downloaded: stats.downloaded,
+                },
+            )]
+            .into_iter()
+            .collect(),
+        });
+
+        ::simd_json::serde::to_string(&response).unwrap()
+    }
+}
+
+#[test]
+fn test_announce_request_fixtures() {
+    for event in Event::ALL {
+        let expected = Announce::fixture(event);
+
+        let http_request = format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            http::announce_request_path(event)
+        );
+
+        assert_eq!(
+            http_tracker::parse_announce(http_request.as_bytes()),
+            expected
+        );
+        assert_eq!(
+            udp_tracker::parse_announce(&udp::announce_request(event)),
+            expected
+        );
+        assert_eq!(
+            ws_tracker::parse_announce(&ws::announce_request(event)),
+            expected
+        );
+    }
+}
+
+#[test]
+fn test_scrape_response_fixtures() {
+    let expected = vec![Statistics::fixture()];
+
+    assert_eq!(
+        http_tracker::parse_scrape_response(&http::scrape_response()),
+        expected
+    );
+    assert_eq!(
+        udp_tracker::parse_scrape_response(&udp::scrape_response(), INFO_HASH),
+        expected
+    );
+    assert_eq!(
+        ws_tracker::parse_scrape_response(ws::SCRAPE_RESPONSE),
+        expected
+    );
+}
+
+#[test]
+fn test_udp_announce_response_fixture() {
+    use aquatic_udp_protocol::*;
+
+    let (peer_ip, peer_port) = RESPONSE_PEER;
+
+    let response = Response::AnnounceIpv4(AnnounceResponse {
+        fixed: AnnounceResponseFixedData {
+            transaction_id: TransactionId::new(udp::TRANSACTION_ID as i32),
+            announce_interval: AnnounceInterval::new(ANNOUNCE_INTERVAL as i32),
+            leechers: NumberOfPeers::new(LEECHERS as i32),
+            seeders: NumberOfPeers::new(SEEDERS as i32),
+        },
+        peers: vec![ResponsePeer {
+            ip_address: Ipv4Addr::from(peer_ip).into(),
+            port: Port::new(NonZeroU16::new(peer_port).unwrap()),
+        }],
+    });
+
+    let mut bytes = Vec::new();
+
+    response.write_bytes(&mut bytes).unwrap();
+
+    assert_eq!(bytes, udp::announce_response_ipv4());
+}
+
+#[quickcheck]
+fn quickcheck_announce_request_round_trip(announce: Announce) -> bool {
+    let from_http = http_tracker::parse_announce(&http_tracker::write_announce(announce));
+    let from_udp = udp_tracker::parse_announce(&udp_tracker::write_announce(announce));
+    let from_ws = ws_tracker::parse_announce(&ws_tracker::write_announce(announce));
+
+    from_http == announce && from_udp == announce && from_ws == announce
+}
+
+#[quickcheck]
+fn quickcheck_scrape_response_round_trip(stats: Statistics) -> bool {
+    let expected = vec![stats];
+
+    http_tracker::parse_scrape_response(&http_tracker::write_scrape_response(stats)) == expected
+        && udp_tracker::parse_scrape_response(
+            &udp_tracker::write_scrape_response(stats),
+            stats.info_hash,
+        ) == expected
+        && ws_tracker::parse_scrape_response(&ws_tracker::write_scrape_response(stats)) == expected
+}
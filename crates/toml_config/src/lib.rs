@@ -19,8 +19,8 @@ macro_rules! gen_serialize_deserialize_test {
 
 /// Export structs to toml, converting Rust doc strings to comments.
 ///
-/// Supports one level of nesting. Fields containing structs must come
-/// after regular fields.
+/// Supports nesting. Fields containing structs must come after regular
+/// fields.
 ///
 /// Usage:
 /// ```
@@ -80,11 +80,22 @@ pub mod __private {
 
     pub trait Private {
         fn __to_string(&self, comment: Option<String>, field_name: String) -> String;
+
+        /// Like `__to_string`, but with dotted path of containing table, which
+        /// is needed for correct headers of nested tables
+        fn __to_string_with_path(
+            &self,
+            comment: Option<String>,
+            field_name: String,
+            _path_prefix: &str,
+        ) -> String {
+            self.__to_string(comment, field_name)
+        }
     }
 
     macro_rules! impl_trait {
-        ($ident:ident) => {
-            impl Private for $ident {
+        ($ty:ty) => {
+            impl Private for $ty {
                 fn __to_string(&self, comment: Option<String>, field_name: String) -> String {
                     let mut output = String::new();
 
@@ -123,4 +134,8 @@ pub mod __private {
 
     impl_trait!(PathBuf);
     impl_trait!(SocketAddr);
+
+    impl_trait!(Vec<usize>);
+    impl_trait!(Vec<String>);
+    impl_trait!(Vec<SocketAddr>);
 }
@@ -8,6 +8,10 @@ struct TestConfigInnerA {
     a: String,
     /// Comment for b
     b: usize,
+    /// Comment for c
+    c: Vec<usize>,
+    /// Comment for TestConfigInnerB
+    inner_b: TestConfigInnerB,
 }
 
 impl Default for TestConfigInnerA {
@@ -15,10 +19,24 @@ impl Default for TestConfigInnerA {
         Self {
             a: "Inner hello world".into(),
             b: 100,
+            c: vec![1, 2, 3],
+            inner_b: Default::default(),
         }
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, TomlConfig, Deserialize)]
+struct TestConfigInnerB {
+    /// Comment for d
+    d: usize,
+}
+
+impl Default for TestConfigInnerB {
+    fn default() -> Self {
+        Self { d: 5 }
+    }
+}
+
 /// Comment for TestConfig
 #[derive(Clone, Debug, PartialEq, Eq, TomlConfig, Deserialize)]
 struct TestConfig {
@@ -1,6 +1,6 @@
 use proc_macro2::{TokenStream, TokenTree};
 use quote::quote;
-use syn::{parse_macro_input, Attribute, Data, DataStruct, DeriveInput, Fields, Ident, Type};
+use syn::{parse_macro_input, Attribute, Data, DataStruct, DeriveInput, Fields, Type};
 
 #[proc_macro_derive(TomlConfig)]
 pub fn derive(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
@@ -15,7 +15,19 @@ pub fn derive(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
                 let mut output = String::new();
             };
 
-            extract_from_struct(ident.clone(), struct_data, &mut output_stream);
+            // Path to the table containing the struct fields (used when
+            // nesting tables) and the values to print
+            let top_level_prologue = quote! {
+                let table_path = String::new();
+                let struct_default = #ident::default();
+                let struct_default = &struct_default;
+            };
+            let nested_prologue = quote! {
+                let table_path = format!("{}{}.", path_prefix, field_name);
+                let struct_default = self;
+            };
+
+            extract_from_struct(struct_data, &mut output_stream);
 
             proc_macro::TokenStream::from(quote! {
                 impl ::aquatic_toml_config::TomlConfig for #ident {
@@ -30,6 +42,7 @@ pub fn derive(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
                         }
 
                         let body = {
+                            #top_level_prologue
                             #output_stream
 
                             output
@@ -42,6 +55,14 @@ pub fn derive(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
                 }
                 impl ::aquatic_toml_config::__private::Private for #ident {
                     fn __to_string(&self, comment: Option<String>, field_name: String) -> String {
+                        self.__to_string_with_path(comment, field_name, "")
+                    }
+                    fn __to_string_with_path(
+                        &self,
+                        comment: Option<String>,
+                        field_name: String,
+                        path_prefix: &str,
+                    ) -> String {
                         let mut output = String::new();
 
                         output.push('\n');
@@ -49,9 +70,10 @@ pub fn derive(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
                         if let Some(comment) = comment {
                             output.push_str(&comment);
                         }
-                        output.push_str(&format!("[{}]\n", field_name));
+                        output.push_str(&format!("[{}{}]\n", path_prefix, field_name));
 
                         let body = {
+                            #nested_prologue
                             #output_stream
 
                             output
@@ -93,21 +115,13 @@ pub fn derive(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     }
 }
 
-fn extract_from_struct(
-    struct_ty_ident: Ident,
-    struct_data: DataStruct,
-    output_stream: &mut TokenStream,
-) {
+fn extract_from_struct(struct_data: DataStruct, output_stream: &mut TokenStream) {
     let fields = if let Fields::Named(fields) = struct_data.fields {
         fields
     } else {
         panic!("Fields are not named");
     };
 
-    output_stream.extend(::std::iter::once(quote! {
-        let struct_default = #struct_ty_ident::default();
-    }));
-
     for field in fields.named.into_iter() {
         let ident = field.ident.expect("Encountered unnamed field");
         let ident_string = format!("{}", ident);
@@ -117,12 +131,13 @@ fn extract_from_struct(
             output_stream.extend(::std::iter::once(quote! {
                 {
                     let comment: Option<String> = #comment;
-                    let field_default: #path = struct_default.#ident;
+                    let field_default: &#path = &struct_default.#ident;
 
-                    let s: String = ::aquatic_toml_config::__private::Private::__to_string(
-                        &field_default,
+                    let s: String = ::aquatic_toml_config::__private::Private::__to_string_with_path(
+                        field_default,
                         comment,
-                        #ident_string.to_string()
+                        #ident_string.to_string(),
+                        &table_path,
                     );
                     output.push_str(&s);
                 }
@@ -3,13 +3,15 @@ use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
 
 use aquatic_common::access_list::AccessListArcSwap;
+use aquatic_common::ban_list::BanListArcSwap;
+use aquatic_common::load_shedding::AnnounceCeiling;
 use aquatic_common::ServerStartInstant;
 use aquatic_udp_protocol::*;
 use crossbeam_utils::CachePadded;
 use hdrhistogram::Histogram;
 
 use crate::config::Config;
-use crate::swarm::TorrentMaps;
+use crate::swarm::{TorrentMaps, TorrentMapsSnapshot};
 
 pub const BUFFER_SIZE: usize = 8192;
 
@@ -39,14 +41,14 @@ impl Statistics {
     pub fn new(config: &Config) -> Self {
         Self {
             socket: repeat_with(Default::default)
-                .take(config.socket_workers)
+                .take(config.socket_workers * config.network.addresses().count())
                 .collect(),
             swarm: Default::default(),
         }
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct IpVersionStatistics<T> {
     pub ipv4: T,
     pub ipv6: T,
@@ -68,8 +70,25 @@ pub struct SocketWorkerStatistics {
     pub responses_announce: AtomicUsize,
     pub responses_scrape: AtomicUsize,
     pub responses_error: AtomicUsize,
+    pub dropped_responses_connect: AtomicUsize,
+    pub dropped_responses_announce: AtomicUsize,
+    pub dropped_responses_scrape: AtomicUsize,
+    pub dropped_responses_error: AtomicUsize,
     pub bytes_received: AtomicUsize,
     pub bytes_sent: AtomicUsize,
+    pub rejected_source_port_zero: AtomicUsize,
+    pub rejected_parse_error: AtomicUsize,
+    pub rejected_invalid_connection_id: AtomicUsize,
+    pub rejected_greylisted: AtomicUsize,
+    pub rejected_banned: AtomicUsize,
+    pub rejected_overloaded: AtomicUsize,
+    pub greylisted_prefixes: AtomicUsize,
+    pub tracked_connection_hits: AtomicUsize,
+    pub tracked_connection_misses: AtomicUsize,
+    pub tracked_connection_evictions: AtomicUsize,
+    /// Info hashes found in access list, by request type
+    pub access_list_hits_announce: AtomicUsize,
+    pub access_list_hits_scrape: AtomicUsize,
 }
 
 pub type CachePaddedArc<T> = CachePadded<Arc<CachePadded<T>>>;
@@ -90,20 +109,73 @@ pub enum StatisticsMessage {
 #[derive(Clone)]
 pub struct State {
     pub access_list: Arc<AccessListArcSwap>,
+    pub ban_list: Arc<BanListArcSwap>,
     pub torrent_maps: TorrentMaps,
+    pub announce_ceiling: Arc<AnnounceCeiling>,
     pub server_start_instant: ServerStartInstant,
 }
 
+impl State {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            announce_ceiling: Arc::new(AnnounceCeiling::new(
+                config.protocol.max_announces_per_second,
+            )),
+            ..Default::default()
+        }
+    }
+}
+
+impl State {
+    /// Copy all torrents and peers for processing outside of the torrent
+    /// map locks, see [`TorrentMaps::snapshot`]
+    pub fn snapshot(&self) -> TorrentMapsSnapshot {
+        self.torrent_maps.snapshot()
+    }
+}
+
 impl Default for State {
     fn default() -> Self {
         Self {
             access_list: Arc::new(AccessListArcSwap::default()),
+            ban_list: Arc::new(BanListArcSwap::default()),
             torrent_maps: TorrentMaps::default(),
+            announce_ceiling: Arc::new(AnnounceCeiling::new(0)),
             server_start_instant: ServerStartInstant::new(),
         }
     }
 }
 
+/// Reusable peer vectors for announce responses, so that socket workers
+/// don't need to allocate one per response
+#[derive(Default)]
+pub struct ResponsePeerBuffers {
+    pub ipv4: Vec<ResponsePeer<Ipv4AddrBytes>>,
+    pub ipv6: Vec<ResponsePeer<Ipv6AddrBytes>>,
+}
+
+impl ResponsePeerBuffers {
+    /// Take back peer vector of announce response once it has been
+    /// serialized
+    pub fn reclaim(&mut self, response: Response) {
+        match response {
+            Response::AnnounceIpv4(response) => {
+                Self::reclaim_vec(&mut self.ipv4, response.peers);
+            }
+            Response::AnnounceIpv6(response) => {
+                Self::reclaim_vec(&mut self.ipv6, response.peers);
+            }
+            _ => (),
+        }
+    }
+
+    fn reclaim_vec<T>(buffer: &mut Vec<T>, peers: Vec<T>) {
+        if peers.capacity() > buffer.capacity() {
+            *buffer = peers;
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::{net::Ipv6Addr, num::NonZeroU16};
@@ -1,6 +1,10 @@
 use std::{net::SocketAddr, path::PathBuf};
 
-use aquatic_common::{access_list::AccessListConfig, privileges::PrivilegeConfig};
+use aquatic_common::{
+    access_list::AccessListConfig, ban_list::BanListConfig, bogon_filter::BogonFilterConfig,
+    client_filter::ClientFilterConfig, greylist::GreylistConfig, max_jittered_announce_interval,
+    min_jittered_announce_interval, privileges::PrivilegeConfig,
+};
 use cfg_if::cfg_if;
 use serde::{Deserialize, Serialize};
 
@@ -14,7 +18,42 @@ pub struct Config {
     /// Number of socket workers
     ///
     /// 0 = automatically set to number of available virtual CPUs
+    ///
+    /// The number of socket workers can be changed at runtime through the
+    /// control socket (see `control_socket_path`).
     pub socket_workers: usize,
+    /// Restart socket workers that panic instead of quitting the application,
+    /// at most this many times in total
+    ///
+    /// Other workers are never restarted. Restarting is not supported when
+    /// privileges are dropped, since the sockets can't be recreated in that
+    /// case, so this must be 0 if `privileges.drop_privileges` is set.
+    pub max_worker_restarts: usize,
+    /// Path of Unix datagram socket accepting commands for changing the
+    /// number of socket workers at runtime. Leave empty to disable.
+    ///
+    /// Send `add-socket-worker` or `remove-socket-worker` to the socket,
+    /// e.g., with `printf add-socket-worker | socat - UNIX-SENDTO:<path>`.
+    /// Added workers listen on `network.address`. The most recently added
+    /// worker is removed first, and the last one is never removed. Send
+    /// `write-snapshot` to write all torrents to `snapshot_path`.
+    ///
+    /// Only aquatic_udp supports this. It can't be combined with
+    /// `privileges.drop_privileges`, since new sockets can't be created
+    /// after privileges have been dropped.
+    pub control_socket_path: PathBuf,
+    /// Path to write a snapshot of all torrents and peers to when
+    /// `write-snapshot` is sent to the control socket. Leave empty to
+    /// disable.
+    ///
+    /// Each torrent is written as a line with its info hash, completed
+    /// count and number of peers, followed by one indented line per peer
+    /// with its address, peer id, status and seconds until it expires.
+    /// Torrents are copied shard by shard without blocking announce
+    /// requests to other torrents, and the copy is written after all locks
+    /// have been released. The file is written under a temporary name and
+    /// then renamed.
+    pub snapshot_path: PathBuf,
     pub log_level: LogLevel,
     pub network: NetworkConfig,
     pub protocol: ProtocolConfig,
@@ -28,12 +67,42 @@ pub struct Config {
     /// emitting of an error-level log message, while successful updates of the
     /// access list result in emitting of an info-level log message.
     pub access_list: AccessListConfig,
+    /// Ban list configuration
+    ///
+    /// Requests (aquatic_udp) and connections (aquatic_http and
+    /// aquatic_ws) from banned IP addresses are ignored before any parsing.
+    /// If initial parsing of the file fails, the program exits.
+    pub ban_list: BanListConfig,
+    /// Greylist configuration
+    ///
+    /// Temporarily ignore network prefixes sending many requests with
+    /// invalid connection IDs or malformed requests.
+    pub greylist: GreylistConfig,
+    /// Bogon filter configuration
+    ///
+    /// Keep peers announcing from reserved or private addresses out of
+    /// swarms.
+    pub bogon_filter: BogonFilterConfig,
+    /// Client filter configuration
+    ///
+    /// Restrict which client software may announce by peer id prefix.
+    pub client_filter: ClientFilterConfig,
+    pub packet_dump: PacketDumpConfig,
+    /// Pin worker threads to cpu cores
+    ///
+    /// Socket workers are pinned first, utility threads (cleaning and
+    /// statistics) share the core after them.
+    #[cfg(feature = "cpu-pinning")]
+    pub cpu_pinning: aquatic_common::cpu_pinning::asc::CpuPinningConfigAsc,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             socket_workers: 1,
+            max_worker_restarts: 0,
+            control_socket_path: PathBuf::new(),
+            snapshot_path: PathBuf::new(),
             log_level: LogLevel::Error,
             network: NetworkConfig::default(),
             protocol: ProtocolConfig::default(),
@@ -41,6 +110,13 @@ impl Default for Config {
             cleaning: CleaningConfig::default(),
             privileges: PrivilegeConfig::default(),
             access_list: AccessListConfig::default(),
+            ban_list: BanListConfig::default(),
+            greylist: GreylistConfig::default(),
+            bogon_filter: BogonFilterConfig::default(),
+            client_filter: ClientFilterConfig::default(),
+            packet_dump: PacketDumpConfig::default(),
+            #[cfg(feature = "cpu-pinning")]
+            cpu_pinning: Default::default(),
         }
     }
 }
@@ -49,6 +125,129 @@ impl aquatic_common::cli::Config for Config {
     fn get_log_level(&self) -> Option<LogLevel> {
         Some(self.log_level)
     }
+    fn validation_errors(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if self.max_worker_restarts != 0 && self.privileges.drop_privileges {
+            errors.push(
+                "max_worker_restarts must be 0 when privileges.drop_privileges is set".into(),
+            );
+        }
+        if !self.control_socket_path.as_os_str().is_empty() && self.privileges.drop_privileges {
+            errors.push(
+                "control_socket_path is not supported with privileges.drop_privileges".into(),
+            );
+        }
+        if !self.snapshot_path.as_os_str().is_empty()
+            && self.control_socket_path.as_os_str().is_empty()
+        {
+            errors.push("snapshot_path requires control_socket_path to be set".into());
+        }
+        if self.protocol.max_scrape_torrents == 0 {
+            errors.push("protocol.max_scrape_torrents must be greater than 0".into());
+        }
+        if self.protocol.peer_announce_interval <= 0 {
+            errors.push("protocol.peer_announce_interval must be greater than 0".into());
+        }
+        if self.cleaning.torrent_cleaning_interval == 0 {
+            errors.push("cleaning.torrent_cleaning_interval must be greater than 0".into());
+        }
+        if self.protocol.peer_announce_interval_jitter >= 100 {
+            errors.push("protocol.peer_announce_interval_jitter must be less than 100".into());
+        }
+
+        let max_announce_interval = max_jittered_announce_interval(
+            self.protocol.peer_announce_interval.max(0) as usize,
+            self.protocol.peer_announce_interval_jitter,
+        );
+
+        if self.cleaning.max_peer_age as usize <= max_announce_interval {
+            errors.push(format!(
+                "cleaning.max_peer_age ({}) must be greater than protocol.peer_announce_interval ({}) including jitter, or peers will be removed before they reannounce",
+                self.cleaning.max_peer_age, max_announce_interval
+            ));
+        }
+
+        let min_announce_interval = min_jittered_announce_interval(
+            self.protocol.peer_announce_interval.max(0) as usize,
+            self.protocol.peer_announce_interval_jitter,
+        );
+
+        if self.protocol.min_announce_interval as usize >= min_announce_interval {
+            errors.push(format!(
+                "protocol.min_announce_interval ({}) must be less than protocol.peer_announce_interval ({}) including jitter, or peers announcing as asked will be rejected",
+                self.protocol.min_announce_interval, min_announce_interval
+            ));
+        }
+        if self.packet_dump.sample_one_in != 0 {
+            if self.packet_dump.path.as_os_str().is_empty() {
+                errors
+                    .push("packet_dump.sample_one_in is set, but packet_dump.path is empty".into());
+            }
+            if self.packet_dump.max_file_size == 0 {
+                errors.push("packet_dump.max_file_size must be greater than 0".into());
+            }
+            #[cfg(feature = "io-uring")]
+            if self.network.use_io_uring {
+                errors.push(
+                    "packet_dump.sample_one_in is set, but packet dumps are not supported with network.use_io_uring".into(),
+                );
+            }
+        }
+        if !(1..=1024).contains(&self.network.batch_size) {
+            errors.push("network.batch_size must be between 1 and 1024".into());
+        }
+        #[cfg(feature = "io-uring")]
+        if self.network.use_io_uring {
+            if self.network.segmentation_offload {
+                errors.push(
+                    "network.segmentation_offload is not supported with network.use_io_uring"
+                        .into(),
+                );
+            }
+            if self.network.resend_buffer_max_len != 0 {
+                errors.push(
+                    "network.resend_buffer_max_len is not supported with network.use_io_uring"
+                        .into(),
+                );
+            }
+        }
+        if self.protocol.track_connections && self.protocol.max_tracked_connections == 0 {
+            errors.push("protocol.max_tracked_connections must be greater than 0".into());
+        }
+        if self.protocol.track_connections && self.network.systemd_socket_activation {
+            errors.push(
+                "protocol.track_connections is not supported with network.systemd_socket_activation"
+                    .into(),
+            );
+        }
+        if self.cleaning.max_connection_age == 0 {
+            errors.push("cleaning.max_connection_age must be greater than 0".into());
+        }
+        if self.protocol.connection_id_key_rotation_interval != 0
+            && self.protocol.connection_id_key_rotation_interval < self.cleaning.max_connection_age
+        {
+            errors.push(
+                "protocol.connection_id_key_rotation_interval must be 0 or at least cleaning.max_connection_age"
+                    .into(),
+            );
+        }
+        if self.network.only_ipv6 && !self.network.ipv6_active() {
+            errors.push("network.only_ipv6 is set, but no IPv6 address is configured".into());
+        }
+        if self.network.systemd_socket_activation && !self.network.additional_addresses.is_empty() {
+            errors.push(
+                "network.additional_addresses is not supported with network.systemd_socket_activation"
+                    .into(),
+            );
+        }
+
+        errors.extend(self.access_list.validation_errors());
+        errors.extend(self.greylist.validation_errors());
+        errors.extend(self.client_filter.validation_errors());
+
+        errors
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, TomlConfig, Deserialize, Serialize)]
@@ -56,8 +255,30 @@ impl aquatic_common::cli::Config for Config {
 pub struct NetworkConfig {
     /// Bind to this address
     pub address: SocketAddr,
+    /// Also bind to these addresses, e.g., `["[::]:3000", "127.0.0.1:3001"]`
+    ///
+    /// `socket_workers` socket workers are spawned for each address. To
+    /// listen on both the IPv4 and IPv6 wildcard addresses with the same
+    /// port, set `only_ipv6` to true (it only applies to IPv6 addresses).
+    /// Socket workers added at runtime listen on `address`.
+    ///
+    /// All addresses share the other settings in this section and serve the
+    /// same tracker. Per-address options (e.g., a separate admin listener)
+    /// are not supported, and aquatic_http and aquatic_ws only listen on a
+    /// single address.
+    pub additional_addresses: Vec<SocketAddr>,
     /// Only allow access over IPv6
     pub only_ipv6: bool,
+    /// Use socket passed by systemd (socket activation) instead of creating
+    /// one and binding it to `address`
+    ///
+    /// `address` and `only_ipv6` should still match the passed socket, since
+    /// they are used to determine which IP versions are active. The socket
+    /// is shared by all socket workers.
+    ///
+    /// If several sockets are passed, the one with `FileDescriptorName=udp`
+    /// in the systemd socket unit is used.
+    pub systemd_socket_activation: bool,
     /// Size of socket recv buffer. Use 0 for OS default.
     ///
     /// This setting can have a big impact on dropped packages. It might
@@ -71,14 +292,42 @@ pub struct NetworkConfig {
     /// $ sudo sysctl -w net.core.rmem_max=8000000
     /// $ sudo sysctl -w net.core.rmem_default=8000000
     pub socket_recv_buffer_size: usize,
+    /// Size of socket send buffer. Use 0 for OS default.
+    ///
+    /// On Linux, the maximum is set by net.core.wmem_max. A warning is
+    /// logged if the kernel uses a smaller value than configured (this also
+    /// applies to the recv buffer).
+    pub socket_send_buffer_size: usize,
     /// Poll timeout in milliseconds (mio backend only)
     pub poll_timeout_ms: u64,
+    /// Receive and send up to this many packets per system call with
+    /// recvmmsg and sendmmsg (mio backend on Linux only)
+    ///
+    /// Reduces system call overhead at high request rates. Set to 1 to
+    /// receive and send packets one by one. Ignored with a warning when
+    /// using io_uring.
+    pub batch_size: usize,
+    /// Send consecutive responses in a batch that are going to the same
+    /// address as a single message with UDP generic segmentation offload
+    /// (mio backend on Linux 4.18 or later only, requires `batch_size` above
+    /// 1)
+    ///
+    /// The kernel then splits the message into packets, reducing per-packet
+    /// overhead. Disabled automatically with a warning if the kernel or
+    /// network device doesn't support it. Can't be combined with
+    /// `use_io_uring`.
+    pub segmentation_offload: bool,
     /// Store this many responses at most for retrying (once) on send failure
     /// (mio backend only)
     ///
     /// Useful on operating systems that do not provide an udp send buffer,
     /// such as FreeBSD. Setting the value to zero disables resending
-    /// functionality.
+    /// functionality. Must be zero when using io_uring.
+    ///
+    /// When the buffer is full, announce responses replace other responses
+    /// in it, preferably scrape responses, so that peers can keep announcing
+    /// while the tracker is overloaded. Dropped responses are counted in
+    /// statistics.
     pub resend_buffer_max_len: usize,
     #[cfg(feature = "io-uring")]
     pub use_io_uring: bool,
@@ -90,11 +339,16 @@ pub struct NetworkConfig {
 }
 
 impl NetworkConfig {
+    /// `address` followed by `additional_addresses`
+    pub fn addresses(&self) -> impl Iterator<Item = SocketAddr> + '_ {
+        ::std::iter::once(self.address).chain(self.additional_addresses.iter().copied())
+    }
     pub fn ipv4_active(&self) -> bool {
-        self.address.is_ipv4() || !self.only_ipv6
+        self.addresses()
+            .any(|address| address.is_ipv4() || !self.only_ipv6)
     }
     pub fn ipv6_active(&self) -> bool {
-        self.address.is_ipv6()
+        self.addresses().any(|address| address.is_ipv6())
     }
 }
 
@@ -102,9 +356,14 @@ impl Default for NetworkConfig {
     fn default() -> Self {
         Self {
             address: SocketAddr::from(([0, 0, 0, 0], 3000)),
+            additional_addresses: Vec::new(),
             only_ipv6: false,
+            systemd_socket_activation: false,
             socket_recv_buffer_size: 8_000_000,
+            socket_send_buffer_size: 0,
             poll_timeout_ms: 50,
+            batch_size: 32,
+            segmentation_offload: false,
             resend_buffer_max_len: 0,
             #[cfg(feature = "io-uring")]
             use_io_uring: true,
@@ -118,19 +377,113 @@ impl Default for NetworkConfig {
 #[serde(default, deny_unknown_fields)]
 pub struct ProtocolConfig {
     /// Maximum number of torrents to allow in scrape request
+    ///
+    /// Further info hashes in requests are ignored. BEP 15 notes that up to
+    /// about 74 torrents can be scraped at once.
     pub max_scrape_torrents: u8,
     /// Maximum number of peers to return in announce response
+    ///
+    /// Peers asking for fewer (with the announce request `num_want` field)
+    /// get fewer. Peers not specifying a number get this many. The
+    /// requesting peer is never included.
     pub max_response_peers: usize,
     /// Ask peers to announce this often (seconds)
     pub peer_announce_interval: i32,
+    /// Randomize announce interval in each response by up to this many
+    /// percent in either direction, to prevent peers from announcing in sync
+    /// (e.g., after a restart). 0 = disabled
+    pub peer_announce_interval_jitter: u8,
+    /// Send error responses to announce requests without an event from
+    /// stored peers that announced less than this many seconds ago.
+    /// 0 = disabled
+    ///
+    /// Protects the tracker from clients announcing too aggressively.
+    /// Requests with events (started, stopped, completed) are never rejected.
+    /// The time is measured with a resolution of a few seconds, so this
+    /// should be well below `peer_announce_interval`.
+    pub min_announce_interval: u32,
+    /// Send error responses to announce requests with event `stopped` or
+    /// `completed` from peers that are not stored as having announced with
+    /// event `started`
+    ///
+    /// Makes it harder to inflate the number of completed downloads
+    /// reported in scrape responses with forged events. Peers stored before
+    /// a tracker restart need to announce `started` again.
+    pub require_started_event: bool,
+    /// Maximum number of announce requests to process per second, across
+    /// all socket workers. 0 = unlimited
+    ///
+    /// Further announce requests get error responses asking clients to try
+    /// again later, without being passed on to the torrent maps. This keeps
+    /// the tracker responsive during flash crowds.
+    pub max_announces_per_second: u64,
+    /// Replace the secret key used for creating connection IDs this often
+    /// (seconds). 0 = never
+    ///
+    /// Connection IDs created with the previous key are accepted until they
+    /// expire, so this must be at least `cleaning.max_connection_age`.
+    pub connection_id_key_rotation_interval: u32,
+    /// Send error responses to announce and scrape requests with invalid or
+    /// expired connection IDs instead of ignoring them
+    ///
+    /// Since the source addresses of such requests haven't been verified,
+    /// this allows using the tracker to send packets to arbitrary addresses.
+    /// See `max_unverified_response_amplification` for how traffic
+    /// amplification is prevented.
+    pub respond_to_invalid_connection_ids: bool,
+    /// Never send responses to requests from unverified source addresses
+    /// that are larger than the request times this factor. 0 = no limit
+    ///
+    /// This prevents the tracker from being used to amplify traffic in
+    /// denial of service attacks using spoofed source addresses. Apart from
+    /// connect responses, which are never larger than connect requests,
+    /// this only concerns error responses to requests with invalid
+    /// connection IDs. Their messages are truncated to stay within the
+    /// limit. Announce and scrape requests always require a valid
+    /// connection ID, i.e., a previous connect request from the same
+    /// address.
+    pub max_unverified_response_amplification: usize,
+    /// Store created connection IDs and only accept those found in storage
+    ///
+    /// By default, connection IDs are validated without storing them, by
+    /// checking a cryptographic hash included in them. Tracking connections
+    /// additionally binds connection IDs to client ports and allows
+    /// monitoring connection ID use, at the cost of memory and CPU time.
+    /// Connections are tracked per socket worker, so clients need to keep
+    /// using the same port. Not supported with systemd socket activation,
+    /// since all socket workers share the same socket in that case.
+    pub track_connections: bool,
+    /// Maximum number of tracked connections per socket worker. When it is
+    /// reached, the oldest connections are evicted.
+    pub max_tracked_connections: usize,
+    /// Only let announce requests change the state of a stored peer if they
+    /// include the same key (announce request `key` field) as the request
+    /// that created it
+    ///
+    /// Peers are identified by IP address and announced port, so without
+    /// this, anyone sending requests from the same IP address (e.g., behind
+    /// the same NAT) can stop peers or change their seeder status. Requests
+    /// with another key still get response peers. Peers that restart with a
+    /// new key can't change state until their previous entry expires.
+    pub require_matching_peer_key: bool,
 }
 
 impl Default for ProtocolConfig {
     fn default() -> Self {
         Self {
             max_scrape_torrents: 70,
-            max_response_peers: 30,
+            max_response_peers: 50,
             peer_announce_interval: 60 * 15,
+            peer_announce_interval_jitter: 0,
+            min_announce_interval: 0,
+            require_started_event: false,
+            max_announces_per_second: 0,
+            connection_id_key_rotation_interval: 60 * 60,
+            respond_to_invalid_connection_ids: false,
+            max_unverified_response_amplification: 1,
+            track_connections: false,
+            max_tracked_connections: 100_000,
+            require_matching_peer_key: false,
         }
     }
 }
@@ -216,6 +569,14 @@ pub struct CleaningConfig {
     pub max_connection_age: u32,
     /// Remove peers who have not announced for this long (seconds)
     pub max_peer_age: u32,
+    /// Maximum number of torrents per IP version to remember the number of
+    /// completed downloads for after they are removed because they have no
+    /// peers left
+    ///
+    /// The count is reported in scrape responses and restored if peers
+    /// announce to the torrent again. Counts of torrents removed when the
+    /// limit has been reached are lost. 0 = don't remember any.
+    pub max_removed_torrent_completed_counts: usize,
 }
 
 impl Default for CleaningConfig {
@@ -224,13 +585,77 @@ impl Default for CleaningConfig {
             torrent_cleaning_interval: 60 * 2,
             max_connection_age: 60 * 2,
             max_peer_age: 60 * 20,
+            max_removed_torrent_completed_counts: 100_000,
+        }
+    }
+}
+
+/// Write a sample of received requests and responses to them to a file, to
+/// help diagnose interoperability problems with specific clients (mio
+/// backend only)
+///
+/// Each entry contains the time, the client address and the decoded request
+/// and response. Requests that were ignored are included without a response.
+#[derive(Clone, Debug, PartialEq, TomlConfig, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PacketDumpConfig {
+    /// Dump one in this many requests, chosen randomly. 0 = disabled
+    pub sample_one_in: u32,
+    /// Path of dump file
+    ///
+    /// The file is opened before privileges are dropped, but rotated files
+    /// are created with dropped privileges. If writing or rotating fails,
+    /// the error is logged and packet dumping is disabled.
+    pub path: PathBuf,
+    /// Rotate dump file when it reaches this size (bytes)
+    ///
+    /// The file is renamed by appending `.1` to its name, after renaming
+    /// previously rotated files by incrementing the number.
+    pub max_file_size: u64,
+    /// Keep this many rotated files
+    pub max_rotated_files: usize,
+    /// Also include raw packets in hex encoding
+    pub include_hex: bool,
+}
+
+impl Default for PacketDumpConfig {
+    fn default() -> Self {
+        Self {
+            sample_one_in: 0,
+            path: "./aquatic-udp-packets.log".into(),
+            max_file_size: 10_000_000,
+            max_rotated_files: 2,
+            include_hex: false,
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use aquatic_common::cli::Config as _;
+
     use super::Config;
 
     ::aquatic_toml_config::gen_serialize_deserialize_test!(Config);
+
+    #[test]
+    fn test_default_config_is_valid() {
+        assert_eq!(Config::default().validation_errors(), Vec::<String>::new());
+    }
+
+    #[cfg(feature = "io-uring")]
+    #[test]
+    fn test_mio_only_options_rejected_with_io_uring() {
+        let mut config = Config::default();
+
+        config.network.use_io_uring = true;
+        config.network.segmentation_offload = true;
+        config.network.resend_buffer_max_len = 1024;
+
+        assert_eq!(config.validation_errors().len(), 2);
+
+        config.network.use_io_uring = false;
+
+        assert!(config.validation_errors().is_empty());
+    }
 }
@@ -3,66 +3,135 @@ pub mod config;
 pub mod swarm;
 pub mod workers;
 
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::thread::{available_parallelism, sleep, Builder, JoinHandle};
 use std::time::Duration;
 
 use anyhow::Context;
-use aquatic_common::WorkerType;
-use crossbeam_channel::unbounded;
-use signal_hook::consts::SIGUSR1;
+use aquatic_common::{panic_message, WorkerType};
+use crossbeam_channel::{unbounded, Sender};
+use signal_hook::consts::{SIGINT, SIGTERM, SIGUSR1};
 use signal_hook::iterator::Signals;
 
-use aquatic_common::access_list::update_access_list;
+use aquatic_common::access_list::{spawn_access_list_sync_worker, update_access_list};
+use aquatic_common::ban_list::update_ban_list;
+#[cfg(feature = "cpu-pinning")]
+use aquatic_common::cpu_pinning::{pin_current_if_configured_to, WorkerIndex};
 use aquatic_common::privileges::PrivilegeDropper;
+use aquatic_common::systemd::StatusNotifier;
 
-use common::{State, Statistics};
+use common::{
+    CachePaddedArc, IpVersionStatistics, SocketWorkerStatistics, State, Statistics,
+    StatisticsMessage,
+};
 use config::Config;
+use workers::control::{create_control_socket, run_control_worker};
+use workers::packet_dump::{PacketDumpWorker, PacketDumper};
 use workers::socket::ConnectionValidator;
 
 pub const APP_NAME: &str = "aquatic_udp: UDP BitTorrent tracker";
 pub const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
 
-pub fn run(mut config: Config) -> ::anyhow::Result<()> {
-    let mut signals = Signals::new([SIGUSR1])?;
+/// Command for main thread, sent by signal and control socket workers
+#[derive(Clone, Copy, Debug)]
+enum Command {
+    AddSocketWorker,
+    RemoveSocketWorker,
+    Shutdown,
+}
+
+pub fn run(config: Config) -> ::anyhow::Result<()> {
+    run_with_status_notifier(config, StatusNotifier::Systemd)
+}
+
+/// Run tracker, sending service status notifications through
+/// `status_notifier`
+pub fn run_with_status_notifier(
+    mut config: Config,
+    status_notifier: StatusNotifier,
+) -> ::anyhow::Result<()> {
+    let mut signals = Signals::new([SIGUSR1, SIGTERM, SIGINT])?;
 
     if config.socket_workers == 0 {
         config.socket_workers = available_parallelism().map(Into::into).unwrap_or(1);
     };
 
-    let state = State::default();
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    if config.network.use_io_uring
+        && config.network.batch_size != config::NetworkConfig::default().batch_size
+    {
+        ::log::warn!("network.batch_size is ignored with network.use_io_uring");
+    }
+
+    let state = State::new(&config);
     let statistics = Statistics::new(&config);
     let connection_validator = ConnectionValidator::new(&config)?;
-    let priv_dropper = PrivilegeDropper::new(config.privileges.clone(), config.socket_workers);
+    let priv_dropper = PrivilegeDropper::new(
+        config.privileges.clone(),
+        config.socket_workers * config.network.addresses().count(),
+    );
     let (statistics_sender, statistics_receiver) = unbounded();
+    // Sender is kept alive in this function, so receiving from channel
+    // never fails because of disconnection
+    let (command_sender, command_receiver) = unbounded();
+
+    let (packet_dumper, opt_packet_dump_receiver) = PacketDumper::new(&config);
 
     update_access_list(&config.access_list, &state.access_list)?;
+    update_ban_list(&config.ban_list, &state.ban_list)?;
+
+    let socket_statistics = statistics.socket.clone();
 
     let mut join_handles = Vec::new();
 
-    // Spawn socket worker threads
-    for i in 0..config.socket_workers {
-        let state = state.clone();
-        let config = config.clone();
-        let connection_validator = connection_validator.clone();
-        let priv_dropper = priv_dropper.clone();
-        let statistics = statistics.socket[i].clone();
-        let statistics_sender = statistics_sender.clone();
+    // Config (with listen address) and stop flag of active socket workers,
+    // indexed by worker index
+    let mut active_socket_workers: Vec<(Config, Arc<AtomicBool>)> = Vec::new();
+    // Threads of socket workers that have been told to stop
+    let mut retired_socket_workers = HashSet::new();
+
+    // Spawn packet dump thread. The dump file is opened before socket
+    // workers drop privileges.
+    if let Some(receiver) = opt_packet_dump_receiver {
+        let worker = PacketDumpWorker::new(config.clone(), receiver)?;
 
         let handle = Builder::new()
-            .name(format!("socket-{:02}", i + 1))
-            .spawn(move || {
-                workers::socket::run_socket_worker(
-                    config,
-                    state,
-                    statistics,
-                    statistics_sender,
-                    connection_validator,
-                    priv_dropper,
-                )
-            })
-            .with_context(|| "spawn socket worker")?;
+            .name("packet-dump".into())
+            .spawn(move || worker.run())
+            .context("spawn packet dump worker")?;
+
+        join_handles.push((WorkerType::PacketDump, handle));
+    }
+
+    // Spawn socket worker threads, config.socket_workers for each address
+    for address in config.network.addresses() {
+        let mut listener_config = config.clone();
+
+        listener_config.network.address = address;
+        listener_config.network.additional_addresses = Vec::new();
+
+        for _ in 0..config.socket_workers {
+            let i = active_socket_workers.len();
+            let stop = Arc::new(AtomicBool::new(false));
 
-        join_handles.push((WorkerType::Socket(i), handle));
+            let handle = spawn_socket_worker(
+                &listener_config,
+                &state,
+                statistics.socket[i].clone(),
+                &statistics_sender,
+                &connection_validator,
+                &priv_dropper,
+                &packet_dumper,
+                i,
+                stop.clone(),
+            )?;
+
+            active_socket_workers.push((listener_config.clone(), stop));
+
+            join_handles.push((WorkerType::Socket(i), handle));
+        }
     }
 
     // Spawn cleaning thread
@@ -72,18 +141,28 @@ pub fn run(mut config: Config) -> ::anyhow::Result<()> {
         let statistics = statistics.swarm.clone();
         let statistics_sender = statistics_sender.clone();
 
-        let handle = Builder::new().name("cleaning".into()).spawn(move || loop {
-            sleep(Duration::from_secs(
-                config.cleaning.torrent_cleaning_interval,
-            ));
-
-            state.torrent_maps.clean_and_update_statistics(
-                &config,
-                &statistics,
-                &statistics_sender,
-                &state.access_list,
-                state.server_start_instant,
+        let handle = Builder::new().name("cleaning".into()).spawn(move || {
+            #[cfg(feature = "cpu-pinning")]
+            pin_current_if_configured_to(
+                &config.cpu_pinning,
+                config.socket_workers,
+                0,
+                WorkerIndex::Util,
             );
+
+            loop {
+                sleep(Duration::from_secs(
+                    config.cleaning.torrent_cleaning_interval,
+                ));
+
+                state.torrent_maps.clean_and_update_statistics(
+                    &config,
+                    &statistics,
+                    &statistics_sender,
+                    &state.access_list,
+                    state.server_start_instant.seconds_elapsed(),
+                );
+            }
         })?;
 
         join_handles.push((WorkerType::Cleaning, handle));
@@ -97,6 +176,14 @@ pub fn run(mut config: Config) -> ::anyhow::Result<()> {
         let handle = Builder::new()
             .name("statistics".into())
             .spawn(move || {
+                #[cfg(feature = "cpu-pinning")]
+                pin_current_if_configured_to(
+                    &config.cpu_pinning,
+                    config.socket_workers,
+                    0,
+                    WorkerIndex::Util,
+                );
+
                 workers::statistics::run_statistics_worker(
                     config,
                     state,
@@ -123,9 +210,33 @@ pub fn run(mut config: Config) -> ::anyhow::Result<()> {
         join_handles.push((WorkerType::Prometheus, handle));
     }
 
+    // Spawn access list sync thread
+    if config.access_list.sync_active() {
+        let handle = spawn_access_list_sync_worker(&config.access_list, state.access_list.clone())?;
+
+        join_handles.push((WorkerType::AccessListSync, handle));
+    }
+
+    // Spawn control socket thread
+    if !config.control_socket_path.as_os_str().is_empty() {
+        let socket = create_control_socket(&config.control_socket_path)?;
+        let command_sender = command_sender.clone();
+        let state = state.clone();
+        let snapshot_path = config.snapshot_path.clone();
+
+        let handle = Builder::new()
+            .name("control".into())
+            .spawn(move || run_control_worker(socket, command_sender, state, snapshot_path))
+            .context("spawn control socket worker")?;
+
+        join_handles.push((WorkerType::Control, handle));
+    }
+
     // Spawn signal handler thread
     {
         let config = config.clone();
+        let state = state.clone();
+        let command_sender = command_sender.clone();
 
         let handle: JoinHandle<anyhow::Result<()>> = Builder::new()
             .name("signals".into())
@@ -134,6 +245,12 @@ pub fn run(mut config: Config) -> ::anyhow::Result<()> {
                     match signal {
                         SIGUSR1 => {
                             let _ = update_access_list(&config.access_list, &state.access_list);
+                            let _ = update_ban_list(&config.ban_list, &state.ban_list);
+                        }
+                        SIGTERM | SIGINT => {
+                            ::log::info!("shutting down");
+
+                            let _ = command_sender.send(Command::Shutdown);
                         }
                         _ => unreachable!(),
                     }
@@ -146,26 +263,211 @@ pub fn run(mut config: Config) -> ::anyhow::Result<()> {
         join_handles.push((WorkerType::Signals, handle));
     }
 
-    // Quit application if any worker returns or panics
+    // Only report readiness once all sockets have been created and
+    // privileges have been dropped
+    let ready = priv_dropper
+        .wait_until_sockets_ready(|| join_handles.iter().any(|(_, handle)| handle.is_finished()));
+
+    if ready {
+        status_notifier.ready();
+    }
+
+    let opt_watchdog_interval = status_notifier.watchdog_interval();
+    let sleep_duration = opt_watchdog_interval
+        .unwrap_or(Duration::MAX)
+        .min(Duration::from_secs(5));
+
+    let mut num_worker_restarts = 0;
+
+    // Quit application if any worker returns or panics, unless it is a
+    // socket worker that may be restarted
     loop {
-        for (i, (_, handle)) in join_handles.iter().enumerate() {
-            if handle.is_finished() {
-                let (worker_type, handle) = join_handles.remove(i);
+        let opt_finished = join_handles
+            .iter()
+            .position(|(_, handle)| handle.is_finished());
 
+        if let Some(i) = opt_finished {
+            let (worker_type, handle) = join_handles.remove(i);
+
+            if retired_socket_workers.remove(&handle.thread().id()) {
                 match handle.join() {
-                    Ok(Ok(())) => {
-                        return Err(anyhow::anyhow!("{} stopped", worker_type));
-                    }
-                    Ok(Err(err)) => {
-                        return Err(err.context(format!("{} stopped", worker_type)));
-                    }
-                    Err(_) => {
-                        return Err(anyhow::anyhow!("{} panicked", worker_type));
-                    }
+                    Ok(Ok(())) => ::log::info!("{} stopped after being retired", worker_type),
+                    Ok(Err(err)) => ::log::error!("retired {} failed: {:#}", worker_type, err),
+                    Err(payload) => ::log::error!(
+                        "retired {} panicked: {}",
+                        worker_type,
+                        panic_message(payload.as_ref())
+                    ),
                 }
+
+                continue;
             }
+
+            let err = match handle.join() {
+                Ok(Ok(())) => anyhow::anyhow!("{} stopped", worker_type),
+                Ok(Err(err)) => err.context(format!("{} stopped", worker_type)),
+                Err(payload) => {
+                    let message = panic_message(payload.as_ref());
+
+                    match worker_type {
+                        WorkerType::Socket(index)
+                            if num_worker_restarts < config.max_worker_restarts =>
+                        {
+                            num_worker_restarts += 1;
+
+                            ::log::error!(
+                                "{} panicked: {}. Restarting it (restart {} of at most {})",
+                                worker_type,
+                                message,
+                                num_worker_restarts,
+                                config.max_worker_restarts
+                            );
+
+                            let (worker_config, stop) = &active_socket_workers[index];
+
+                            let handle = spawn_socket_worker(
+                                worker_config,
+                                &state,
+                                socket_statistics[index % socket_statistics.len()].clone(),
+                                &statistics_sender,
+                                &connection_validator,
+                                &priv_dropper,
+                                &packet_dumper,
+                                index,
+                                stop.clone(),
+                            )?;
+
+                            join_handles.push((worker_type, handle));
+
+                            continue;
+                        }
+                        _ => anyhow::anyhow!("{} panicked: {}", worker_type, message),
+                    }
+                }
+            };
+
+            status_notifier.stopping();
+
+            return Err(err);
+        }
+
+        if opt_watchdog_interval.is_some() {
+            status_notifier.watchdog();
         }
 
-        sleep(Duration::from_secs(5));
+        let command = match command_receiver.recv_timeout(sleep_duration) {
+            Ok(command) => command,
+            Err(_) => continue,
+        };
+
+        match command {
+            Command::Shutdown => {
+                status_notifier.stopping();
+
+                ::log::info!("shut down");
+
+                return Ok(());
+            }
+            Command::AddSocketWorker => {
+                let index = active_socket_workers.len();
+                let stop = Arc::new(AtomicBool::new(false));
+
+                // Listen on network.address, like the first worker
+                let worker_config = active_socket_workers[0].0.clone();
+
+                let handle = spawn_socket_worker(
+                    &worker_config,
+                    &state,
+                    socket_statistics[index % socket_statistics.len()].clone(),
+                    &statistics_sender,
+                    &connection_validator,
+                    &priv_dropper,
+                    &packet_dumper,
+                    index,
+                    stop.clone(),
+                )?;
+
+                active_socket_workers.push((worker_config, stop));
+                join_handles.push((WorkerType::Socket(index), handle));
+
+                ::log::info!(
+                    "Added socket worker, now running {}",
+                    active_socket_workers.len()
+                );
+            }
+            Command::RemoveSocketWorker => {
+                if active_socket_workers.len() <= 1 {
+                    ::log::warn!("Not removing last socket worker");
+
+                    continue;
+                }
+
+                let index = active_socket_workers.len() - 1;
+
+                let opt_thread_id = join_handles.iter().find_map(|(worker_type, handle)| {
+                    matches!(worker_type, WorkerType::Socket(i) if *i == index)
+                        .then(|| handle.thread().id())
+                });
+
+                // Handle may be missing if the worker panicked and wasn't
+                // restarted, but then the application is quitting anyway
+                if let Some(thread_id) = opt_thread_id {
+                    retired_socket_workers.insert(thread_id);
+                }
+
+                if let Some((_, stop)) = active_socket_workers.pop() {
+                    stop.store(true, Ordering::Relaxed);
+                }
+
+                ::log::info!(
+                    "Removed socket worker, now running {}",
+                    active_socket_workers.len()
+                );
+            }
+        }
     }
 }
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_socket_worker(
+    config: &Config,
+    state: &State,
+    statistics: CachePaddedArc<IpVersionStatistics<SocketWorkerStatistics>>,
+    statistics_sender: &Sender<StatisticsMessage>,
+    connection_validator: &ConnectionValidator,
+    priv_dropper: &PrivilegeDropper,
+    packet_dumper: &PacketDumper,
+    index: usize,
+    stop: Arc<AtomicBool>,
+) -> anyhow::Result<JoinHandle<anyhow::Result<()>>> {
+    let state = state.clone();
+    let config = config.clone();
+    let connection_validator = connection_validator.clone();
+    let priv_dropper = priv_dropper.clone();
+    let packet_dumper = packet_dumper.clone();
+    let statistics_sender = statistics_sender.clone();
+
+    Builder::new()
+        .name(format!("socket-{:02}", index + 1))
+        .spawn(move || {
+            #[cfg(feature = "cpu-pinning")]
+            pin_current_if_configured_to(
+                &config.cpu_pinning,
+                config.socket_workers,
+                0,
+                WorkerIndex::SocketWorker(index),
+            );
+
+            workers::socket::run_socket_worker(
+                config,
+                state,
+                statistics,
+                statistics_sender,
+                connection_validator,
+                priv_dropper,
+                packet_dumper,
+                stop,
+            )
+        })
+        .with_context(|| "spawn socket worker")
+}
@@ -1,17 +1,16 @@
 use std::iter::repeat_with;
 use std::net::IpAddr;
 use std::ops::DerefMut;
-use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicBool, AtomicUsize};
 use std::sync::Arc;
 
 use aquatic_common::SecondsSinceServerStart;
-use aquatic_common::ServerStartInstant;
 use aquatic_common::{
     access_list::{create_access_list_cache, AccessListArcSwap, AccessListCache, AccessListMode},
     ValidUntil,
 };
-use aquatic_common::{CanonicalSocketAddr, IndexMap};
+use aquatic_common::{jitter_announce_interval, CanonicalSocketAddr, IndexMap};
 
 use aquatic_udp_protocol::*;
 use arrayvec::ArrayVec;
@@ -48,6 +47,10 @@ impl Default for TorrentMaps {
 }
 
 impl TorrentMaps {
+    /// Handle announce request. Response peers are written into a vector
+    /// taken from `peer_buffers`, which should be returned to it with
+    /// [`ResponsePeerBuffers::reclaim`] once the response has been sent.
+    #[allow(clippy::too_many_arguments)]
     pub fn announce(
         &self,
         config: &Config,
@@ -56,47 +59,88 @@ impl TorrentMaps {
         request: &AnnounceRequest,
         src: CanonicalSocketAddr,
         valid_until: ValidUntil,
+        peer_buffers: &mut ResponsePeerBuffers,
     ) -> Response {
-        match src.get().ip() {
-            IpAddr::V4(ip_address) => Response::AnnounceIpv4(self.ipv4.announce(
-                config,
-                statistics_sender,
-                rng,
-                request,
-                ip_address.into(),
-                valid_until,
-            )),
-            IpAddr::V6(ip_address) => Response::AnnounceIpv6(self.ipv6.announce(
-                config,
-                statistics_sender,
-                rng,
-                request,
-                ip_address.into(),
-                valid_until,
-            )),
+        let store_peer = !config.bogon_filter.blocks(src.get().ip());
+
+        let opt_response = match src.get().ip() {
+            IpAddr::V4(ip_address) => self
+                .ipv4
+                .announce(
+                    config,
+                    statistics_sender,
+                    rng,
+                    request,
+                    ip_address.into(),
+                    valid_until,
+                    store_peer,
+                    &mut peer_buffers.ipv4,
+                )
+                .map(Response::AnnounceIpv4),
+            IpAddr::V6(ip_address) => self
+                .ipv6
+                .announce(
+                    config,
+                    statistics_sender,
+                    rng,
+                    request,
+                    ip_address.into(),
+                    valid_until,
+                    store_peer,
+                    &mut peer_buffers.ipv6,
+                )
+                .map(Response::AnnounceIpv6),
+        };
+
+        opt_response.unwrap_or_else(|message| {
+            Response::Error(ErrorResponse {
+                transaction_id: request.transaction_id,
+                message: message.into(),
+            })
+        })
+    }
+
+    /// Copy all torrents and peers
+    ///
+    /// Shards are copied one at a time under read locks, and each torrent is
+    /// copied under its own peer map read lock. Announce requests only wait
+    /// while the torrent they concern is being copied, and new torrents
+    /// can't be added to the shard being copied. Process the copy after
+    /// this returns, without holding any locks.
+    pub fn snapshot(&self) -> TorrentMapsSnapshot {
+        TorrentMapsSnapshot {
+            ipv4: self.ipv4.snapshot(),
+            ipv6: self.ipv6.snapshot(),
         }
     }
 
-    pub fn scrape(&self, request: ScrapeRequest, src: CanonicalSocketAddr) -> ScrapeResponse {
+    /// Info hashes not passing `allows` get zeroed statistics
+    pub fn scrape(
+        &self,
+        request: ScrapeRequest,
+        src: CanonicalSocketAddr,
+        allows: impl FnMut(&InfoHash) -> bool,
+    ) -> ScrapeResponse {
         if src.is_ipv4() {
-            self.ipv4.scrape(request)
+            self.ipv4.scrape(request, allows)
         } else {
-            self.ipv6.scrape(request)
+            self.ipv6.scrape(request, allows)
         }
     }
 
     /// Remove forbidden or inactive torrents, reclaim space and update statistics
+    ///
+    /// Peers are considered inactive if their `valid_until` is not after `now`.
     pub fn clean_and_update_statistics(
         &self,
         config: &Config,
         statistics: &CachePaddedArc<IpVersionStatistics<SwarmWorkerStatistics>>,
         statistics_sender: &Sender<StatisticsMessage>,
         access_list: &Arc<AccessListArcSwap>,
-        server_start_instant: ServerStartInstant,
+        now: SecondsSinceServerStart,
     ) {
         let mut cache = create_access_list_cache(access_list);
         let mode = config.access_list.mode;
-        let now = server_start_instant.seconds_elapsed();
 
         let mut statistics_messages = Vec::new();
 
@@ -151,6 +195,9 @@ impl<I: Ip> TorrentMapShards<I> {
         )
     }
 
+    /// Handle announce request, returning an error message if the request
+    /// is rejected
+    #[allow(clippy::too_many_arguments)]
     fn announce(
         &self,
         config: &Config,
@@ -159,53 +206,103 @@ impl<I: Ip> TorrentMapShards<I> {
         request: &AnnounceRequest,
         ip_address: I,
         valid_until: ValidUntil,
-    ) -> AnnounceResponse<I> {
+        store_peer: bool,
+        response_peers: &mut Vec<ResponsePeer<I>>,
+    ) -> Result<AnnounceResponse<I>, &'static str> {
         let torrent_data = {
             let torrent_map_shard = self.get_shard(&request.info_hash).upgradable_read();
 
             // Clone Arc here to avoid keeping lock on whole shard
-            if let Some(torrent_data) = torrent_map_shard.get(&request.info_hash) {
+            if let Some(torrent_data) = torrent_map_shard.torrents.get(&request.info_hash) {
                 torrent_data.clone()
             } else {
+                let mut torrent_map_shard = RwLockUpgradableReadGuard::upgrade(torrent_map_shard);
+                let torrent_map_shard = torrent_map_shard.deref_mut();
+
                 // Don't overwrite entry if created in the meantime
-                RwLockUpgradableReadGuard::upgrade(torrent_map_shard)
+                torrent_map_shard
+                    .torrents
                     .entry(request.info_hash)
-                    .or_default()
+                    .or_insert_with(|| {
+                        let num_completed = torrent_map_shard
+                            .removed_torrent_completed_counts
+                            .remove(&request.info_hash)
+                            .unwrap_or(0);
+
+                        Arc::new(TorrentData {
+                            num_completed: num_completed.into(),
+                            ..Default::default()
+                        })
+                    })
                     .clone()
             }
         };
 
         let mut peer_map = torrent_data.peer_map.write();
 
-        peer_map.announce(
+        if peer_map.announced_too_early(config, request, ip_address, valid_until) {
+            return Err("Announcing too often");
+        }
+        if peer_map.missing_started_event(config, request, ip_address) {
+            return Err("Peer must announce with event started first");
+        }
+
+        if AnnounceEvent::from(request.event) == AnnounceEvent::Completed {
+            torrent_data.num_completed.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let response = peer_map.announce(
             config,
             statistics_sender,
             rng,
             request,
             ip_address,
             valid_until,
-        )
+            store_peer,
+            ::std::mem::take(response_peers),
+        );
+
+        Ok(response)
     }
 
-    fn scrape(&self, request: ScrapeRequest) -> ScrapeResponse {
+    fn scrape(
+        &self,
+        request: ScrapeRequest,
+        mut allows: impl FnMut(&InfoHash) -> bool,
+    ) -> ScrapeResponse {
         let mut response = ScrapeResponse {
             transaction_id: request.transaction_id,
             torrent_stats: Vec::with_capacity(request.info_hashes.len()),
         };
 
         for info_hash in request.info_hashes {
-            let torrent_map_shard = self.get_shard(&info_hash);
-
-            let statistics = if let Some(torrent_data) = torrent_map_shard.read().get(&info_hash) {
-                torrent_data.peer_map.read().scrape_statistics()
-            } else {
-                TorrentScrapeStatistics {
-                    seeders: NumberOfPeers::new(0),
-                    leechers: NumberOfPeers::new(0),
-                    completed: NumberOfDownloads::new(0),
+            let opt_statistics = if allows(&info_hash) {
+                let torrent_map_shard = self.get_shard(&info_hash).read();
+
+                if let Some(torrent_data) = torrent_map_shard.torrents.get(&info_hash) {
+                    Some(torrent_data.scrape_statistics())
+                } else {
+                    torrent_map_shard
+                        .removed_torrent_completed_counts
+                        .get(&info_hash)
+                        .map(|completed| TorrentScrapeStatistics {
+                            seeders: NumberOfPeers::new(0),
+                            leechers: NumberOfPeers::new(0),
+                            completed: NumberOfDownloads::new(
+                                (*completed).try_into().unwrap_or(i32::MAX),
+                            ),
+                        })
                 }
+            } else {
+                None
             };
 
+            let statistics = opt_statistics.unwrap_or(TorrentScrapeStatistics {
+                seeders: NumberOfPeers::new(0),
+                leechers: NumberOfPeers::new(0),
+                completed: NumberOfDownloads::new(0),
+            });
+
             response.torrent_stats.push(statistics);
         }
 
@@ -228,8 +325,11 @@ impl<I: Ip> TorrentMapShards<I> {
             .torrent_peer_histograms
             .then(|| Histogram::new(3).expect("create peer histogram"));
 
+        let max_removed_torrent_completed_counts =
+            config.cleaning.max_removed_torrent_completed_counts / self.0.len();
+
         for torrent_map_shard in self.0.iter() {
-            for torrent_data in torrent_map_shard.read().values() {
+            for torrent_data in torrent_map_shard.read().torrents.values() {
                 let mut peer_map = torrent_data.peer_map.write();
 
                 let num_peers = match peer_map.deref_mut() {
@@ -270,8 +370,18 @@ impl<I: Ip> TorrentMapShards<I> {
             }
 
             let mut torrent_map_shard = torrent_map_shard.write();
+            let TorrentMapShard {
+                torrents,
+                removed_torrent_completed_counts,
+            } = torrent_map_shard.deref_mut();
+
+            removed_torrent_completed_counts.retain(|info_hash, _| {
+                access_list_cache
+                    .load()
+                    .allows(access_list_mode, &info_hash.0)
+            });
 
-            torrent_map_shard.retain(|info_hash, torrent_data| {
+            torrents.retain(|info_hash, torrent_data| {
                 if !access_list_cache
                     .load()
                     .allows(access_list_mode, &info_hash.0)
@@ -288,31 +398,93 @@ impl<I: Ip> TorrentMapShards<I> {
                     .fetch_and(false, Ordering::Acquire)
                     && torrent_data.peer_map.read().is_empty()
                 {
+                    let num_completed = torrent_data.num_completed.load(Ordering::Relaxed);
+
+                    if num_completed > 0
+                        && removed_torrent_completed_counts.len()
+                            < max_removed_torrent_completed_counts
+                    {
+                        removed_torrent_completed_counts.insert(*info_hash, num_completed);
+                    }
+
                     return false;
                 }
 
                 true
             });
 
-            torrent_map_shard.shrink_to_fit();
+            torrents.shrink_to_fit();
 
-            total_num_torrents += torrent_map_shard.len();
+            total_num_torrents += torrents.len();
         }
 
         (total_num_torrents, total_num_peers, opt_histogram)
     }
 
+    fn snapshot(&self) -> Vec<TorrentSnapshot<I>> {
+        let mut torrents = Vec::new();
+
+        for torrent_map_shard in self.0.iter() {
+            let torrent_map_shard = torrent_map_shard.read();
+
+            torrents.reserve(torrent_map_shard.torrents.len());
+
+            for (info_hash, torrent_data) in torrent_map_shard.torrents.iter() {
+                torrents.push(TorrentSnapshot {
+                    info_hash: *info_hash,
+                    num_completed: torrent_data.num_completed.load(Ordering::Relaxed),
+                    peers: torrent_data.peer_map.read().snapshot_peers(),
+                });
+            }
+        }
+
+        torrents
+    }
+
     fn get_shard(&self, info_hash: &InfoHash) -> &RwLock<TorrentMapShard<I>> {
         self.0.get(info_hash.0[0] as usize % self.0.len()).unwrap()
     }
 }
 
-/// Use HashMap instead of IndexMap for better lookup performance
-type TorrentMapShard<T> = HashMap<InfoHash, Arc<TorrentData<T>>>;
+struct TorrentMapShard<I: Ip> {
+    /// Use HashMap instead of IndexMap for better lookup performance
+    torrents: HashMap<InfoHash, Arc<TorrentData<I>>>,
+    /// Number of completed announces of torrents that were removed because
+    /// they had no peers left, restored if peers announce to them again
+    removed_torrent_completed_counts: HashMap<InfoHash, usize>,
+}
+
+impl<I: Ip> Default for TorrentMapShard<I> {
+    fn default() -> Self {
+        Self {
+            torrents: Default::default(),
+            removed_torrent_completed_counts: Default::default(),
+        }
+    }
+}
 
 pub struct TorrentData<T: Ip> {
     peer_map: RwLock<PeerMap<T>>,
     pending_removal: AtomicBool,
+    /// Number of announce requests with event `completed`
+    ///
+    /// Kept when inactive peers are cleaned. When the torrent is removed
+    /// because it has no peers left, the count is moved to
+    /// [`TorrentMapShard::removed_torrent_completed_counts`].
+    num_completed: AtomicUsize,
+}
+
+impl<I: Ip> TorrentData<I> {
+    fn scrape_statistics(&self) -> TorrentScrapeStatistics {
+        let (seeders, leechers) = self.peer_map.read().num_seeders_leechers();
+        let completed = self.num_completed.load(Ordering::Relaxed);
+
+        TorrentScrapeStatistics {
+            seeders: NumberOfPeers::new(seeders.try_into().unwrap_or(i32::MAX)),
+            leechers: NumberOfPeers::new(leechers.try_into().unwrap_or(i32::MAX)),
+            completed: NumberOfDownloads::new(completed.try_into().unwrap_or(i32::MAX)),
+        }
+    }
 }
 
 impl<I: Ip> Default for TorrentData<I> {
@@ -320,6 +492,7 @@ impl<I: Ip> Default for TorrentData<I> {
         Self {
             peer_map: Default::default(),
             pending_removal: Default::default(),
+            num_completed: Default::default(),
         }
     }
 }
@@ -330,6 +503,12 @@ pub enum PeerMap<I: Ip> {
 }
 
 impl<I: Ip> PeerMap<I> {
+    /// Handle announce request, extracting response peers into
+    /// `response_peers` after clearing it
+    ///
+    /// If `store_peer` is false, the announcing peer is handled as if it had
+    /// stopped.
+    #[allow(clippy::too_many_arguments)]
     fn announce(
         &mut self,
         config: &Config,
@@ -338,7 +517,11 @@ impl<I: Ip> PeerMap<I> {
         request: &AnnounceRequest,
         ip_address: I,
         valid_until: ValidUntil,
+        store_peer: bool,
+        mut response_peers: Vec<ResponsePeer<I>>,
     ) -> AnnounceResponse<I> {
+        response_peers.clear();
+
         let max_num_peers_to_take: usize = if request.peers_wanted.0.get() <= 0 {
             config.protocol.max_response_peers
         } else {
@@ -348,14 +531,41 @@ impl<I: Ip> PeerMap<I> {
             )
         };
 
-        let status =
-            PeerStatus::from_event_and_bytes_left(request.event.into(), request.bytes_left);
-
         let peer_map_key = ResponsePeer {
             ip_address,
             port: request.port,
         };
 
+        // Keep stored peer unchanged if request key doesn't match
+        let opt_protected_peer = if config.protocol.require_matching_peer_key {
+            self.get(&peer_map_key)
+                .filter(|peer| peer.key != request.key)
+                .copied()
+        } else {
+            None
+        };
+
+        let status = match opt_protected_peer {
+            _ if !store_peer => PeerStatus::Stopped,
+            Some(Peer {
+                is_seeder: true, ..
+            }) => PeerStatus::Seeding,
+            Some(Peer {
+                is_seeder: false, ..
+            }) => PeerStatus::Leeching,
+            None => PeerStatus::from_event_and_bytes_left(request.event.into(), request.bytes_left),
+        };
+
+        let announce_interval = AnnounceInterval::new(
+            jitter_announce_interval(
+                rng,
+                config.protocol.peer_announce_interval as usize,
+                config.protocol.peer_announce_interval_jitter,
+            )
+            .try_into()
+            .unwrap_or(i32::MAX),
+        );
+
         // Create the response before inserting the peer. This means that we
         // don't have to filter it out from the response peers, and that the
         // reported number of seeders/leechers will not include it
@@ -368,13 +578,15 @@ impl<I: Ip> PeerMap<I> {
                 let response = AnnounceResponse {
                     fixed: AnnounceResponseFixedData {
                         transaction_id: request.transaction_id,
-                        announce_interval: AnnounceInterval::new(
-                            config.protocol.peer_announce_interval,
-                        ),
+                        announce_interval,
                         leechers: NumberOfPeers::new(leechers.try_into().unwrap_or(i32::MAX)),
                         seeders: NumberOfPeers::new(seeders.try_into().unwrap_or(i32::MAX)),
                     },
-                    peers: peer_map.extract_response_peers(max_num_peers_to_take),
+                    peers: {
+                        peer_map.extract_response_peers(max_num_peers_to_take, &mut response_peers);
+
+                        response_peers
+                    },
                 };
 
                 // Convert peer map to large variant if it is full and
@@ -394,13 +606,19 @@ impl<I: Ip> PeerMap<I> {
                 let response = AnnounceResponse {
                     fixed: AnnounceResponseFixedData {
                         transaction_id: request.transaction_id,
-                        announce_interval: AnnounceInterval::new(
-                            config.protocol.peer_announce_interval,
-                        ),
+                        announce_interval,
                         leechers: NumberOfPeers::new(leechers.try_into().unwrap_or(i32::MAX)),
                         seeders: NumberOfPeers::new(seeders.try_into().unwrap_or(i32::MAX)),
                     },
-                    peers: peer_map.extract_response_peers(rng, max_num_peers_to_take),
+                    peers: {
+                        peer_map.extract_response_peers(
+                            rng,
+                            max_num_peers_to_take,
+                            &mut response_peers,
+                        );
+
+                        response_peers
+                    },
                 };
 
                 // Try shrinking the map if announcing peer is stopped and
@@ -417,11 +635,14 @@ impl<I: Ip> PeerMap<I> {
 
         match status {
             PeerStatus::Leeching | PeerStatus::Seeding => {
-                let peer = Peer {
+                let peer = opt_protected_peer.unwrap_or(Peer {
                     peer_id: request.peer_id,
+                    key: request.key,
                     is_seeder: status == PeerStatus::Seeding,
+                    started: AnnounceEvent::from(request.event) == AnnounceEvent::Started
+                        || opt_removed_peer.is_some_and(|peer| peer.started),
                     valid_until,
-                };
+                });
 
                 match self {
                     Self::Small(peer_map) => peer_map.insert(peer_map_key, peer),
@@ -446,16 +667,84 @@ impl<I: Ip> PeerMap<I> {
         response
     }
 
-    fn scrape_statistics(&self) -> TorrentScrapeStatistics {
-        let (seeders, leechers) = match self {
+    fn num_seeders_leechers(&self) -> (usize, usize) {
+        match self {
             Self::Small(peer_map) => peer_map.num_seeders_leechers(),
             Self::Large(peer_map) => peer_map.num_seeders_leechers(),
+        }
+    }
+
+    fn snapshot_peers(&self) -> Vec<PeerSnapshot<I>> {
+        match self {
+            Self::Small(peer_map) => peer_map
+                .0
+                .iter()
+                .map(|(address, peer)| PeerSnapshot::new(*address, peer))
+                .collect(),
+            Self::Large(peer_map) => peer_map
+                .peers
+                .iter()
+                .map(|(address, peer)| PeerSnapshot::new(*address, peer))
+                .collect(),
+        }
+    }
+
+    /// Check if request is a regular announce (without event) from a stored
+    /// peer, sent sooner than `protocol.min_announce_interval` after its
+    /// previous accepted announce
+    fn announced_too_early(
+        &self,
+        config: &Config,
+        request: &AnnounceRequest,
+        ip_address: I,
+        valid_until: ValidUntil,
+    ) -> bool {
+        if config.protocol.min_announce_interval == 0
+            || AnnounceEvent::from(request.event) != AnnounceEvent::None
+        {
+            return false;
+        }
+
+        let key = ResponsePeer {
+            ip_address,
+            port: request.port,
         };
 
-        TorrentScrapeStatistics {
-            seeders: NumberOfPeers::new(seeders.try_into().unwrap_or(i32::MAX)),
-            leechers: NumberOfPeers::new(leechers.try_into().unwrap_or(i32::MAX)),
-            completed: NumberOfDownloads::new(0),
+        self.get(&key).is_some_and(|peer| {
+            valid_until.seconds_since(peer.valid_until) < config.protocol.min_announce_interval
+        })
+    }
+
+    /// Check if `protocol.require_started_event` is set and request has
+    /// event `stopped` or `completed`, but announcing peer either isn't
+    /// stored or wasn't stored with event `started`
+    fn missing_started_event(
+        &self,
+        config: &Config,
+        request: &AnnounceRequest,
+        ip_address: I,
+    ) -> bool {
+        if !config.protocol.require_started_event
+            || !matches!(
+                AnnounceEvent::from(request.event),
+                AnnounceEvent::Stopped | AnnounceEvent::Completed
+            )
+        {
+            return false;
+        }
+
+        let key = ResponsePeer {
+            ip_address,
+            port: request.port,
+        };
+
+        !self.get(&key).is_some_and(|peer| peer.started)
+    }
+
+    fn get(&self, key: &ResponsePeer<I>) -> Option<&Peer> {
+        match self {
+            Self::Small(peer_map) => peer_map.get(key),
+            Self::Large(peer_map) => peer_map.peers.get(key),
         }
     }
 
@@ -491,6 +780,10 @@ impl<I: Ip> SmallPeerMap<I> {
         (seeders, leechers)
     }
 
+    fn get(&self, key: &ResponsePeer<I>) -> Option<&Peer> {
+        self.0.iter().find(|(k, _)| k == key).map(|(_, peer)| peer)
+    }
+
     fn insert(&mut self, key: ResponsePeer<I>, peer: Peer) {
         self.0.push((key, peer));
     }
@@ -505,8 +798,12 @@ impl<I: Ip> SmallPeerMap<I> {
         None
     }
 
-    fn extract_response_peers(&self, max_num_peers_to_take: usize) -> Vec<ResponsePeer<I>> {
-        Vec::from_iter(self.0.iter().take(max_num_peers_to_take).map(|(k, _)| *k))
+    fn extract_response_peers(
+        &self,
+        max_num_peers_to_take: usize,
+        peers: &mut Vec<ResponsePeer<I>>,
+    ) {
+        peers.extend(self.0.iter().take(max_num_peers_to_take).map(|(k, _)| *k));
     }
 
     fn clean_and_get_num_peers(
@@ -578,30 +875,31 @@ impl<I: Ip> LargePeerMap<I> {
         &self,
         rng: &mut impl Rng,
         max_num_peers_to_take: usize,
-    ) -> Vec<ResponsePeer<I>> {
+        peers: &mut Vec<ResponsePeer<I>>,
+    ) {
         if self.peers.len() <= max_num_peers_to_take {
-            self.peers.keys().copied().collect()
+            peers.extend(self.peers.keys().copied());
         } else {
             let middle_index = self.peers.len() / 2;
-            let num_to_take_per_half = max_num_peers_to_take / 2;
+            // Take the extra peer from the second half if the number is odd
+            let num_to_take_half_one = max_num_peers_to_take / 2;
+            let num_to_take_half_two = max_num_peers_to_take - num_to_take_half_one;
 
             let offset_half_one = {
                 let from = 0;
-                let to = usize::max(1, middle_index - num_to_take_per_half);
+                let to = usize::max(1, middle_index - num_to_take_half_one);
 
                 rng.gen_range(from..to)
             };
             let offset_half_two = {
                 let from = middle_index;
-                let to = usize::max(middle_index + 1, self.peers.len() - num_to_take_per_half);
+                let to = usize::max(middle_index + 1, self.peers.len() - num_to_take_half_two);
 
                 rng.gen_range(from..to)
             };
 
-            let end_half_one = offset_half_one + num_to_take_per_half;
-            let end_half_two = offset_half_two + num_to_take_per_half;
-
-            let mut peers = Vec::with_capacity(max_num_peers_to_take);
+            let end_half_one = offset_half_one + num_to_take_half_one;
+            let end_half_two = offset_half_two + num_to_take_half_two;
 
             if let Some(slice) = self.peers.get_range(offset_half_one..end_half_one) {
                 peers.extend(slice.keys().copied());
@@ -609,8 +907,6 @@ impl<I: Ip> LargePeerMap<I> {
             if let Some(slice) = self.peers.get_range(offset_half_two..end_half_two) {
                 peers.extend(slice.keys().copied());
             }
-
-            peers
         }
     }
 
@@ -654,10 +950,44 @@ impl<I: Ip> LargePeerMap<I> {
 #[derive(Clone, Copy, Debug)]
 struct Peer {
     peer_id: PeerId,
+    key: PeerKey,
     is_seeder: bool,
+    /// Peer announced with event `started` when it was first stored
+    started: bool,
     valid_until: ValidUntil,
 }
 
+/// Copy of torrent data, see [`TorrentMaps::snapshot`]
+pub struct TorrentMapsSnapshot {
+    pub ipv4: Vec<TorrentSnapshot<Ipv4AddrBytes>>,
+    pub ipv6: Vec<TorrentSnapshot<Ipv6AddrBytes>>,
+}
+
+pub struct TorrentSnapshot<I: Ip> {
+    pub info_hash: InfoHash,
+    pub num_completed: usize,
+    pub peers: Vec<PeerSnapshot<I>>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PeerSnapshot<I: Ip> {
+    pub address: ResponsePeer<I>,
+    pub peer_id: PeerId,
+    pub is_seeder: bool,
+    pub valid_until: ValidUntil,
+}
+
+impl<I: Ip> PeerSnapshot<I> {
+    fn new(address: ResponsePeer<I>, peer: &Peer) -> Self {
+        Self {
+            address,
+            peer_id: peer.peer_id,
+            is_seeder: peer.is_seeder,
+            valid_until: peer.valid_until,
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
 pub enum PeerStatus {
     Seeding,
@@ -683,8 +1013,650 @@ impl PeerStatus {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashSet;
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+    use std::num::NonZeroU16;
+
+    use crossbeam_channel::unbounded;
+    use rand::SeedableRng;
+
     use super::*;
 
+    fn announce_request(
+        info_hash: InfoHash,
+        peer_index: u32,
+        bytes_left: i64,
+        peers_wanted: i32,
+    ) -> AnnounceRequest {
+        AnnounceRequest {
+            connection_id: ConnectionId::new(0),
+            action_placeholder: Default::default(),
+            transaction_id: TransactionId::new(0),
+            info_hash,
+            peer_id: PeerId([peer_index as u8; 20]),
+            bytes_downloaded: NumberOfBytes::new(0),
+            bytes_uploaded: NumberOfBytes::new(0),
+            bytes_left: NumberOfBytes::new(bytes_left),
+            event: AnnounceEvent::Started.into(),
+            ip_address: Ipv4AddrBytes([0; 4]),
+            key: PeerKey::new(0),
+            peers_wanted: NumberOfPeers::new(peers_wanted),
+            port: Port::new(NonZeroU16::new(1000).unwrap()),
+        }
+    }
+
+    fn peer_addr(peer_index: u32) -> CanonicalSocketAddr {
+        CanonicalSocketAddr::new(SocketAddr::new(Ipv4Addr::from(peer_index + 1).into(), 1000))
+    }
+
+    fn scrape_num_peers(torrent_maps: &TorrentMaps, info_hash: InfoHash) -> i32 {
+        let request = ScrapeRequest {
+            connection_id: ConnectionId::new(0),
+            transaction_id: TransactionId::new(0),
+            info_hashes: vec![info_hash],
+        };
+
+        let stats = torrent_maps
+            .scrape(request, peer_addr(0), |_| true)
+            .torrent_stats[0];
+
+        stats.seeders.0.get() + stats.leechers.0.get()
+    }
+
+    /// Announce many peers, then return response peers for a final announce,
+    /// using an RNG seeded with `seed`
+    fn response_peers_with_seed(seed: u64) -> Vec<ResponsePeer<Ipv4AddrBytes>> {
+        const NUM_PEERS: u32 = 100;
+
+        let config = Config::default();
+        let (statistics_sender, _statistics_receiver) = unbounded();
+        let torrent_maps = TorrentMaps::default();
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let info_hash = InfoHash([0; 20]);
+        let valid_until = ValidUntil::new_with_now(
+            SecondsSinceServerStart::new(0),
+            config.cleaning.max_peer_age,
+        );
+
+        for i in 0..NUM_PEERS {
+            torrent_maps.announce(
+                &config,
+                &statistics_sender,
+                &mut rng,
+                &announce_request(info_hash, i, 1, 0),
+                peer_addr(i),
+                valid_until,
+                &mut Default::default(),
+            );
+        }
+
+        let response = torrent_maps.announce(
+            &config,
+            &statistics_sender,
+            &mut rng,
+            &announce_request(info_hash, NUM_PEERS, 1, 10),
+            peer_addr(NUM_PEERS),
+            valid_until,
+            &mut Default::default(),
+        );
+
+        match response {
+            Response::AnnounceIpv4(response) => {
+                assert_eq!(response.fixed.leechers.0.get(), NUM_PEERS as i32);
+
+                response.peers
+            }
+            _ => panic!("expected IPv4 announce response"),
+        }
+    }
+
+    #[test]
+    fn test_announce_response_peers_deterministic_with_seeded_rng() {
+        let peers = response_peers_with_seed(0);
+
+        assert_eq!(peers.len(), 10);
+        assert_eq!(peers.iter().collect::<HashSet<_>>().len(), peers.len());
+        assert!(!peers.contains(&ResponsePeer {
+            ip_address: Ipv4Addr::from(101).into(),
+            port: Port::new(NonZeroU16::new(1000).unwrap()),
+        }));
+
+        assert_eq!(peers, response_peers_with_seed(0));
+    }
+
+    #[test]
+    fn test_announce_response_peers_clamped_to_max_response_peers() {
+        let mut config = Config::default();
+
+        config.protocol.max_response_peers = 5;
+
+        let (statistics_sender, _statistics_receiver) = unbounded();
+        let torrent_maps = TorrentMaps::default();
+        let mut rng = SmallRng::seed_from_u64(0);
+        let info_hash = InfoHash([0; 20]);
+        let valid_until = ValidUntil::new_with_now(
+            SecondsSinceServerStart::new(0),
+            config.cleaning.max_peer_age,
+        );
+
+        for i in 0..10 {
+            torrent_maps.announce(
+                &config,
+                &statistics_sender,
+                &mut rng,
+                &announce_request(info_hash, i, 1, 0),
+                peer_addr(i),
+                valid_until,
+                &mut Default::default(),
+            );
+        }
+
+        for (peers_wanted, expected) in [(-1, 5), (0, 5), (3, 3), (100, 5)] {
+            let response = torrent_maps.announce(
+                &config,
+                &statistics_sender,
+                &mut rng,
+                &announce_request(info_hash, 0, 1, peers_wanted),
+                peer_addr(0),
+                valid_until,
+                &mut Default::default(),
+            );
+
+            match response {
+                Response::AnnounceIpv4(response) => {
+                    assert_eq!(response.peers.len(), expected);
+                    assert!(!response
+                        .peers
+                        .iter()
+                        .any(|peer| peer.ip_address == Ipv4Addr::from(1).into()));
+                }
+                _ => panic!("expected IPv4 announce response"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_announce_response_peers_reuse_buffer() {
+        let config = Config::default();
+        let (statistics_sender, _statistics_receiver) = unbounded();
+        let torrent_maps = TorrentMaps::default();
+        let mut rng = SmallRng::seed_from_u64(0);
+        let info_hash = InfoHash([0; 20]);
+        let valid_until = ValidUntil::new_with_now(
+            SecondsSinceServerStart::new(0),
+            config.cleaning.max_peer_age,
+        );
+
+        let mut peer_buffers = ResponsePeerBuffers::default();
+
+        for i in 0..10 {
+            let response = torrent_maps.announce(
+                &config,
+                &statistics_sender,
+                &mut rng,
+                &announce_request(info_hash, i, 1, 0),
+                peer_addr(i),
+                valid_until,
+                &mut peer_buffers,
+            );
+
+            peer_buffers.reclaim(response);
+        }
+
+        let buffer_ptr = peer_buffers.ipv4.as_ptr();
+
+        let response = torrent_maps.announce(
+            &config,
+            &statistics_sender,
+            &mut rng,
+            &announce_request(info_hash, 10, 1, 0),
+            peer_addr(10),
+            valid_until,
+            &mut peer_buffers,
+        );
+
+        match &response {
+            Response::AnnounceIpv4(response) => {
+                assert_eq!(response.peers.len(), 10);
+                assert_eq!(response.peers.as_ptr(), buffer_ptr);
+            }
+            _ => panic!("expected IPv4 announce response"),
+        }
+
+        peer_buffers.reclaim(response);
+
+        assert_eq!(peer_buffers.ipv4.as_ptr(), buffer_ptr);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let config = Config::default();
+        let (statistics_sender, _statistics_receiver) = unbounded();
+        let torrent_maps = TorrentMaps::default();
+        let mut rng = SmallRng::seed_from_u64(0);
+        let valid_until = ValidUntil::new_with_now(
+            SecondsSinceServerStart::new(0),
+            config.cleaning.max_peer_age,
+        );
+
+        // Three torrents with one to three peers each, using both small and
+        // large peer maps
+        for i in 0..3u8 {
+            for j in 0..=u32::from(i) {
+                let mut request = announce_request(InfoHash([i; 20]), j, j.into(), 0);
+
+                if j == 0 {
+                    request.event = AnnounceEvent::Completed.into();
+                }
+
+                torrent_maps.announce(
+                    &config,
+                    &statistics_sender,
+                    &mut rng,
+                    &request,
+                    peer_addr(j),
+                    valid_until,
+                    &mut Default::default(),
+                );
+            }
+        }
+
+        let ipv6_addr = CanonicalSocketAddr::new(SocketAddr::new(Ipv6Addr::LOCALHOST.into(), 1000));
+
+        torrent_maps.announce(
+            &config,
+            &statistics_sender,
+            &mut rng,
+            &announce_request(InfoHash([0; 20]), 0, 1, 0),
+            ipv6_addr,
+            valid_until,
+            &mut Default::default(),
+        );
+
+        let mut snapshot = torrent_maps.snapshot();
+
+        snapshot.ipv4.sort_by_key(|torrent| torrent.info_hash.0);
+
+        assert_eq!(snapshot.ipv4.len(), 3);
+        assert_eq!(snapshot.ipv6.len(), 1);
+
+        for (i, torrent) in snapshot.ipv4.iter().enumerate() {
+            assert_eq!(torrent.info_hash, InfoHash([i as u8; 20]));
+            assert_eq!(torrent.num_completed, 1);
+            assert_eq!(torrent.peers.len(), i + 1);
+            assert_eq!(
+                torrent.peers.iter().filter(|peer| peer.is_seeder).count(),
+                1
+            );
+        }
+
+        assert_eq!(snapshot.ipv6[0].num_completed, 0);
+        assert!(!snapshot.ipv6[0].peers[0].is_seeder);
+    }
+
+    #[test]
+    fn test_clean_removes_peers_once_expired() {
+        let mut config = Config::default();
+
+        config.cleaning.max_peer_age = 10;
+
+        let state = State::default();
+        let statistics = Statistics::new(&config);
+        let (statistics_sender, _statistics_receiver) = unbounded();
+        let mut rng = SmallRng::seed_from_u64(0);
+        let info_hash = InfoHash([0; 20]);
+        let announced_at = SecondsSinceServerStart::new(100);
+
+        // Announce enough peers for the large peer map to be used
+        for i in 0..3 {
+            state.torrent_maps.announce(
+                &config,
+                &statistics_sender,
+                &mut rng,
+                &announce_request(info_hash, i, i.into(), 0),
+                peer_addr(i),
+                ValidUntil::new_with_now(announced_at, config.cleaning.max_peer_age),
+                &mut Default::default(),
+            );
+        }
+
+        let clean = |now| {
+            state.torrent_maps.clean_and_update_statistics(
+                &config,
+                &statistics.swarm,
+                &statistics_sender,
+                &state.access_list,
+                SecondsSinceServerStart::new(now),
+            )
+        };
+
+        clean(109);
+
+        assert_eq!(scrape_num_peers(&state.torrent_maps, info_hash), 3);
+
+        clean(110);
+
+        assert_eq!(scrape_num_peers(&state.torrent_maps, info_hash), 0);
+    }
+
+    #[test]
+    fn test_scrape_zeroes_filtered_info_hashes() {
+        let config = Config::default();
+        let torrent_maps = TorrentMaps::default();
+        let (statistics_sender, _statistics_receiver) = unbounded();
+        let mut rng = SmallRng::seed_from_u64(0);
+        let info_hashes = [InfoHash([0; 20]), InfoHash([1; 20])];
+
+        for (i, info_hash) in info_hashes.into_iter().enumerate() {
+            torrent_maps.announce(
+                &config,
+                &statistics_sender,
+                &mut rng,
+                &announce_request(info_hash, i as u32, 0, 0),
+                peer_addr(i as u32),
+                ValidUntil::new_with_now(SecondsSinceServerStart::new(0), 100),
+                &mut Default::default(),
+            );
+        }
+
+        let request = ScrapeRequest {
+            connection_id: ConnectionId::new(0),
+            transaction_id: TransactionId::new(0),
+            info_hashes: info_hashes.to_vec(),
+        };
+
+        let response = torrent_maps.scrape(request, peer_addr(0), |info_hash| {
+            *info_hash == info_hashes[1]
+        });
+
+        let num_seeders: Vec<i32> = response
+            .torrent_stats
+            .iter()
+            .map(|stats| stats.seeders.0.get())
+            .collect();
+
+        assert_eq!(num_seeders, vec![0, 1]);
+    }
+
+    #[test]
+    fn test_scrape_completed_kept_when_peers_cleaned() {
+        let mut config = Config::default();
+
+        config.cleaning.max_peer_age = 10;
+
+        let state = State::default();
+        let statistics = Statistics::new(&config);
+        let (statistics_sender, _statistics_receiver) = unbounded();
+        let mut rng = SmallRng::seed_from_u64(0);
+        let info_hash = InfoHash([0; 20]);
+
+        let announce = |peer_index, event: AnnounceEvent, now, rng: &mut SmallRng| {
+            let mut request = announce_request(info_hash, peer_index, 0, 0);
+
+            request.event = event.into();
+
+            state.torrent_maps.announce(
+                &config,
+                &statistics_sender,
+                rng,
+                &request,
+                peer_addr(peer_index),
+                ValidUntil::new_with_now(
+                    SecondsSinceServerStart::new(now),
+                    config.cleaning.max_peer_age,
+                ),
+                &mut Default::default(),
+            );
+        };
+        let scrape_completed = || {
+            let request = ScrapeRequest {
+                connection_id: ConnectionId::new(0),
+                transaction_id: TransactionId::new(0),
+                info_hashes: vec![info_hash],
+            };
+
+            state
+                .torrent_maps
+                .scrape(request, peer_addr(0), |_| true)
+                .torrent_stats[0]
+                .completed
+                .0
+                .get()
+        };
+
+        announce(0, AnnounceEvent::Completed, 100, &mut rng);
+        announce(1, AnnounceEvent::Started, 105, &mut rng);
+        announce(1, AnnounceEvent::Completed, 105, &mut rng);
+        announce(1, AnnounceEvent::None, 105, &mut rng);
+
+        assert_eq!(scrape_completed(), 2);
+
+        state.torrent_maps.clean_and_update_statistics(
+            &config,
+            &statistics.swarm,
+            &statistics_sender,
+            &state.access_list,
+            SecondsSinceServerStart::new(110),
+        );
+
+        assert_eq!(scrape_num_peers(&state.torrent_maps, info_hash), 1);
+        assert_eq!(scrape_completed(), 2);
+
+        // Count survives removal of torrent and is restored when peers
+        // announce to it again
+        state.torrent_maps.clean_and_update_statistics(
+            &config,
+            &statistics.swarm,
+            &statistics_sender,
+            &state.access_list,
+            SecondsSinceServerStart::new(120),
+        );
+
+        assert!(state
+            .torrent_maps
+            .ipv4
+            .0
+            .iter()
+            .all(|shard| shard.read().torrents.is_empty()));
+        assert_eq!(scrape_completed(), 2);
+
+        announce(2, AnnounceEvent::Completed, 120, &mut rng);
+
+        assert_eq!(scrape_num_peers(&state.torrent_maps, info_hash), 1);
+        assert_eq!(scrape_completed(), 3);
+    }
+
+    #[test]
+    fn test_removed_torrent_completed_counts_bounded() {
+        let mut config = Config::default();
+
+        config.cleaning.max_removed_torrent_completed_counts = 0;
+
+        let state = State::default();
+        let statistics = Statistics::new(&config);
+        let (statistics_sender, _statistics_receiver) = unbounded();
+        let mut rng = SmallRng::seed_from_u64(0);
+        let info_hash = InfoHash([0; 20]);
+
+        let mut request = announce_request(info_hash, 0, 0, 0);
+
+        request.event = AnnounceEvent::Completed.into();
+
+        state.torrent_maps.announce(
+            &config,
+            &statistics_sender,
+            &mut rng,
+            &request,
+            peer_addr(0),
+            ValidUntil::new_with_now(SecondsSinceServerStart::new(0), 10),
+            &mut Default::default(),
+        );
+        state.torrent_maps.clean_and_update_statistics(
+            &config,
+            &statistics.swarm,
+            &statistics_sender,
+            &state.access_list,
+            SecondsSinceServerStart::new(20),
+        );
+
+        let request = ScrapeRequest {
+            connection_id: ConnectionId::new(0),
+            transaction_id: TransactionId::new(0),
+            info_hashes: vec![info_hash],
+        };
+        let stats = state
+            .torrent_maps
+            .scrape(request, peer_addr(0), |_| true)
+            .torrent_stats[0];
+
+        assert_eq!(stats.completed.0.get(), 0);
+    }
+
+    #[test]
+    fn test_require_matching_peer_key() {
+        let mut config = Config::default();
+
+        config.protocol.require_matching_peer_key = true;
+
+        let (statistics_sender, _statistics_receiver) = unbounded();
+        let torrent_maps = TorrentMaps::default();
+        let mut rng = SmallRng::seed_from_u64(0);
+        let info_hash = InfoHash([0; 20]);
+        let valid_until = ValidUntil::new_with_now(
+            SecondsSinceServerStart::new(0),
+            config.cleaning.max_peer_age,
+        );
+
+        let mut announce = |event: AnnounceEvent, key| {
+            let mut request = announce_request(info_hash, 0, 1, 0);
+
+            request.event = event.into();
+            request.key = PeerKey::new(key);
+
+            torrent_maps.announce(
+                &config,
+                &statistics_sender,
+                &mut rng,
+                &request,
+                peer_addr(0),
+                valid_until,
+                &mut Default::default(),
+            );
+
+            scrape_num_peers(&torrent_maps, info_hash)
+        };
+
+        assert_eq!(announce(AnnounceEvent::Started, 1), 1);
+        assert_eq!(announce(AnnounceEvent::Stopped, 2), 1);
+        assert_eq!(announce(AnnounceEvent::Stopped, 1), 0);
+
+        // Without a stored peer, any key is accepted
+        assert_eq!(announce(AnnounceEvent::Started, 2), 1);
+    }
+
+    #[test]
+    fn test_min_announce_interval() {
+        let mut config = Config::default();
+
+        config.protocol.min_announce_interval = 30;
+
+        let (statistics_sender, _statistics_receiver) = unbounded();
+        let torrent_maps = TorrentMaps::default();
+        let mut rng = SmallRng::seed_from_u64(0);
+        let info_hash = InfoHash([0; 20]);
+
+        let mut announce = |event: AnnounceEvent, now| {
+            let mut request = announce_request(info_hash, 0, 1, 0);
+
+            request.event = event.into();
+
+            let response = torrent_maps.announce(
+                &config,
+                &statistics_sender,
+                &mut rng,
+                &request,
+                peer_addr(0),
+                ValidUntil::new_with_now(
+                    SecondsSinceServerStart::new(now),
+                    config.cleaning.max_peer_age,
+                ),
+                &mut Default::default(),
+            );
+
+            matches!(response, Response::AnnounceIpv4(_))
+        };
+
+        assert!(announce(AnnounceEvent::Started, 0));
+        assert!(!announce(AnnounceEvent::None, 29));
+        assert!(announce(AnnounceEvent::None, 30));
+        // Rejected requests don't reset the interval
+        assert!(!announce(AnnounceEvent::None, 31));
+        assert!(announce(AnnounceEvent::None, 60));
+        // Requests with events are always accepted
+        assert!(announce(AnnounceEvent::Completed, 61));
+        assert!(announce(AnnounceEvent::Stopped, 62));
+    }
+
+    #[test]
+    fn test_require_started_event() {
+        let mut config = Config::default();
+
+        config.protocol.require_started_event = true;
+
+        let (statistics_sender, _statistics_receiver) = unbounded();
+        let torrent_maps = TorrentMaps::default();
+        let mut rng = SmallRng::seed_from_u64(0);
+        let info_hash = InfoHash([0; 20]);
+        let valid_until = ValidUntil::new_with_now(
+            SecondsSinceServerStart::new(0),
+            config.cleaning.max_peer_age,
+        );
+
+        let mut announce = |peer_index, event: AnnounceEvent| {
+            let mut request = announce_request(info_hash, peer_index, 1, 0);
+
+            request.event = event.into();
+
+            let response = torrent_maps.announce(
+                &config,
+                &statistics_sender,
+                &mut rng,
+                &request,
+                peer_addr(peer_index),
+                valid_until,
+                &mut Default::default(),
+            );
+
+            matches!(response, Response::AnnounceIpv4(_))
+        };
+
+        // Unknown peers can't send completed or stopped events
+        assert!(!announce(0, AnnounceEvent::Completed));
+        assert!(!announce(0, AnnounceEvent::Stopped));
+
+        // Peers stored without started event can't either
+        assert!(announce(0, AnnounceEvent::None));
+        assert!(!announce(0, AnnounceEvent::Completed));
+
+        // Started event is remembered across announces
+        assert!(announce(1, AnnounceEvent::Started));
+        assert!(announce(1, AnnounceEvent::None));
+        assert!(announce(1, AnnounceEvent::Completed));
+        assert!(announce(1, AnnounceEvent::Stopped));
+
+        // Rejected requests aren't counted as completed downloads
+        let request = ScrapeRequest {
+            connection_id: ConnectionId::new(0),
+            transaction_id: TransactionId::new(0),
+            info_hashes: vec![info_hash],
+        };
+        let stats = torrent_maps
+            .scrape(request, peer_addr(0), |_| true)
+            .torrent_stats[0];
+
+        assert_eq!(stats.completed.0.get(), 1);
+    }
+
     #[test]
     fn test_peer_status_from_event_and_bytes_left() {
         use PeerStatus::*;
@@ -703,4 +1675,62 @@ mod tests {
         assert_eq!(Seeding, f(AnnounceEvent::None, NumberOfBytes::new(0)));
         assert_eq!(Leeching, f(AnnounceEvent::None, NumberOfBytes::new(1)));
     }
+
+    #[test]
+    fn test_bogon_filter() {
+        let mut config = Config::default();
+
+        config.bogon_filter.enabled = true;
+
+        let (statistics_sender, _statistics_receiver) = unbounded();
+        let torrent_maps = TorrentMaps::default();
+        let mut rng = SmallRng::seed_from_u64(0);
+        let info_hash = InfoHash([0; 20]);
+        let valid_until = ValidUntil::new_with_now(
+            SecondsSinceServerStart::new(0),
+            config.cleaning.max_peer_age,
+        );
+
+        let ips: [IpAddr; 4] = [
+            Ipv4Addr::LOCALHOST.into(),
+            Ipv4Addr::new(192, 168, 0, 1).into(),
+            Ipv4Addr::new(1, 1, 1, 1).into(),
+            Ipv6Addr::LOCALHOST.into(),
+        ];
+
+        for (i, ip) in ips.into_iter().enumerate() {
+            let response = torrent_maps.announce(
+                &config,
+                &statistics_sender,
+                &mut rng,
+                &announce_request(info_hash, i as u32, 1, 0),
+                CanonicalSocketAddr::new(SocketAddr::new(ip, 1000)),
+                valid_until,
+                &mut Default::default(),
+            );
+
+            // Peers from reserved addresses still get responses
+            assert!(matches!(
+                response,
+                Response::AnnounceIpv4(_) | Response::AnnounceIpv6(_)
+            ));
+        }
+
+        assert_eq!(scrape_num_peers(&torrent_maps, info_hash), 1);
+
+        // Private networks can be allowed
+        config.bogon_filter.allow_private_networks = true;
+
+        torrent_maps.announce(
+            &config,
+            &statistics_sender,
+            &mut rng,
+            &announce_request(info_hash, 1, 1, 0),
+            CanonicalSocketAddr::new(SocketAddr::new(ips[1], 1000)),
+            valid_until,
+            &mut Default::default(),
+        );
+
+        assert_eq!(scrape_num_peers(&torrent_maps, info_hash), 2);
+    }
 }
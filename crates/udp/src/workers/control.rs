@@ -0,0 +1,233 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use aquatic_common::ValidUntil;
+use aquatic_udp_protocol::Ip;
+use crossbeam_channel::Sender;
+
+use crate::common::State;
+use crate::swarm::{TorrentMapsSnapshot, TorrentSnapshot};
+use crate::Command;
+
+/// Command received on control socket
+#[derive(Clone, Copy, Debug)]
+enum ControlCommand {
+    /// Forward command to main thread
+    Main(Command),
+    /// Write snapshot of torrents to `snapshot_path`
+    WriteSnapshot,
+}
+
+/// Create Unix datagram socket at `path`, replacing any stale socket file
+/// left by a previous run
+pub(crate) fn create_control_socket(path: &Path) -> anyhow::Result<UnixDatagram> {
+    if path.exists() {
+        ::std::fs::remove_file(path)
+            .with_context(|| format!("remove stale control socket {}", path.display()))?;
+    }
+
+    UnixDatagram::bind(path).with_context(|| format!("bind control socket {}", path.display()))
+}
+
+/// Receive commands for changing the number of socket workers and forward
+/// them to the main thread, and write torrent snapshots on request
+pub(crate) fn run_control_worker(
+    socket: UnixDatagram,
+    sender: Sender<Command>,
+    state: State,
+    snapshot_path: PathBuf,
+) -> anyhow::Result<()> {
+    let mut buffer = [0u8; 64];
+
+    loop {
+        let len = socket
+            .recv(&mut buffer)
+            .context("receive from control socket")?;
+
+        match parse_command(&buffer[..len]) {
+            Some(ControlCommand::Main(command)) => {
+                if sender.send(command).is_err() {
+                    return Ok(());
+                }
+            }
+            Some(ControlCommand::WriteSnapshot) if snapshot_path.as_os_str().is_empty() => {
+                ::log::warn!("Ignored write-snapshot command, since snapshot_path is not set");
+            }
+            Some(ControlCommand::WriteSnapshot) => {
+                let snapshot = state.snapshot();
+                let now = ValidUntil::new(state.server_start_instant, 0);
+
+                match write_snapshot(&snapshot_path, &snapshot, now) {
+                    Ok(()) => ::log::info!("Wrote snapshot to {}", snapshot_path.display()),
+                    Err(err) => ::log::error!("Couldn't write snapshot: {:#}", err),
+                }
+            }
+            None => {
+                ::log::warn!(
+                    "Ignored invalid control socket command: {}",
+                    String::from_utf8_lossy(&buffer[..len]).trim()
+                );
+            }
+        }
+    }
+}
+
+fn parse_command(bytes: &[u8]) -> Option<ControlCommand> {
+    match ::std::str::from_utf8(bytes).ok()?.trim() {
+        "add-socket-worker" => Some(ControlCommand::Main(Command::AddSocketWorker)),
+        "remove-socket-worker" => Some(ControlCommand::Main(Command::RemoveSocketWorker)),
+        "write-snapshot" => Some(ControlCommand::WriteSnapshot),
+        _ => None,
+    }
+}
+
+/// Write snapshot to temporary file, then move it to `path`, so that
+/// readers never see a partially written snapshot
+fn write_snapshot(
+    path: &Path,
+    snapshot: &TorrentMapsSnapshot,
+    now: ValidUntil,
+) -> anyhow::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+
+    tmp_path.push(".tmp");
+
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let file = File::create(&tmp_path)
+        .with_context(|| format!("create snapshot file {}", tmp_path.display()))?;
+    let mut writer = BufWriter::new(file);
+
+    write_torrents(&mut writer, &snapshot.ipv4, now, |ip| {
+        Ipv4Addr::from(ip).into()
+    })
+    .and_then(|()| {
+        write_torrents(&mut writer, &snapshot.ipv6, now, |ip| {
+            Ipv6Addr::from(ip).into()
+        })
+    })
+    .and_then(|()| writer.flush())
+    .with_context(|| format!("write snapshot file {}", tmp_path.display()))?;
+
+    ::std::fs::rename(&tmp_path, path)
+        .with_context(|| format!("move snapshot file to {}", path.display()))
+}
+
+/// Write one line per torrent, followed by one indented line per peer
+fn write_torrents<I: Ip>(
+    writer: &mut impl Write,
+    torrents: &[TorrentSnapshot<I>],
+    now: ValidUntil,
+    to_ip_addr: impl Fn(I) -> IpAddr,
+) -> ::std::io::Result<()> {
+    for torrent in torrents {
+        writeln!(
+            writer,
+            "{} completed={} peers={}",
+            hex::encode(torrent.info_hash.0),
+            torrent.num_completed,
+            torrent.peers.len()
+        )?;
+
+        for peer in torrent.peers.iter() {
+            writeln!(
+                writer,
+                "  {} {} {} expires_in={}",
+                ::std::net::SocketAddr::new(
+                    to_ip_addr(peer.address.ip_address),
+                    peer.address.port.0.get()
+                ),
+                hex::encode(peer.peer_id.0),
+                if peer.is_seeder { "seeder" } else { "leecher" },
+                peer.valid_until.seconds_since(now),
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        assert!(matches!(
+            parse_command(b"add-socket-worker\n"),
+            Some(ControlCommand::Main(Command::AddSocketWorker))
+        ));
+        assert!(matches!(
+            parse_command(b"remove-socket-worker"),
+            Some(ControlCommand::Main(Command::RemoveSocketWorker))
+        ));
+        assert!(matches!(
+            parse_command(b"write-snapshot"),
+            Some(ControlCommand::WriteSnapshot)
+        ));
+        assert!(parse_command(b"shutdown").is_none());
+        assert!(parse_command(b"\xff").is_none());
+    }
+
+    #[test]
+    fn test_write_snapshot() {
+        use std::net::SocketAddr;
+        use std::num::NonZeroU16;
+
+        use aquatic_common::{CanonicalSocketAddr, SecondsSinceServerStart};
+        use aquatic_udp_protocol::*;
+        use rand::{rngs::SmallRng, SeedableRng};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("snapshot.txt");
+
+        let config = crate::config::Config::default();
+        let state = State::default();
+        let (statistics_sender, _statistics_receiver) = crossbeam_channel::unbounded();
+
+        let request = AnnounceRequest {
+            connection_id: ConnectionId::new(0),
+            action_placeholder: Default::default(),
+            transaction_id: TransactionId::new(0),
+            info_hash: InfoHash([1; 20]),
+            peer_id: PeerId([2; 20]),
+            bytes_downloaded: NumberOfBytes::new(0),
+            bytes_uploaded: NumberOfBytes::new(0),
+            bytes_left: NumberOfBytes::new(0),
+            event: AnnounceEvent::Completed.into(),
+            ip_address: Ipv4AddrBytes([0; 4]),
+            key: PeerKey::new(0),
+            peers_wanted: NumberOfPeers::new(0),
+            port: Port::new(NonZeroU16::new(1000).unwrap()),
+        };
+
+        state.torrent_maps.announce(
+            &config,
+            &statistics_sender,
+            &mut SmallRng::seed_from_u64(0),
+            &request,
+            CanonicalSocketAddr::new(SocketAddr::from(([10, 0, 0, 1], 1000))),
+            ValidUntil::new_with_now(SecondsSinceServerStart::new(0), 60),
+            &mut Default::default(),
+        );
+
+        let now = ValidUntil::new_with_now(SecondsSinceServerStart::new(10), 0);
+
+        write_snapshot(&path, &state.snapshot(), now).unwrap();
+
+        let contents = ::std::fs::read_to_string(&path).unwrap();
+
+        assert_eq!(
+            contents,
+            format!(
+                "{} completed=1 peers=1\n  10.0.0.1:1000 {} seeder expires_in=50\n",
+                "01".repeat(20),
+                "02".repeat(20)
+            )
+        );
+    }
+}
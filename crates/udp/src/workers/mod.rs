@@ -1,2 +1,4 @@
+pub mod control;
+pub mod packet_dump;
 pub mod socket;
 pub mod statistics;
@@ -0,0 +1,340 @@
+//! Writing a sample of requests and responses to a file for debugging
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use aquatic_common::CanonicalSocketAddr;
+use aquatic_udp_protocol::{Request, Response};
+use crossbeam_channel::{bounded, Receiver, Sender, TrySendError};
+use rand::Rng;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::config::Config;
+
+/// Maximum number of records waiting to be written. Further sampled
+/// packets are not dumped.
+const CHANNEL_CAPACITY: usize = 1024;
+
+pub struct PacketDumpRecord {
+    time: OffsetDateTime,
+    src: CanonicalSocketAddr,
+    request: Vec<u8>,
+    response: Option<Response>,
+}
+
+/// Samples packets in socket workers and sends them to packet dump worker
+#[derive(Clone)]
+pub struct PacketDumper {
+    opt_sender: Option<Sender<PacketDumpRecord>>,
+    sample_one_in: u32,
+}
+
+impl PacketDumper {
+    /// Create dumper and receiver for packet dump worker, which is only
+    /// returned if packet dumping is enabled
+    pub fn new(config: &Config) -> (Self, Option<Receiver<PacketDumpRecord>>) {
+        let sample_one_in = config.packet_dump.sample_one_in;
+
+        if sample_one_in == 0 {
+            let dumper = Self {
+                opt_sender: None,
+                sample_one_in,
+            };
+
+            return (dumper, None);
+        }
+
+        let (sender, receiver) = bounded(CHANNEL_CAPACITY);
+
+        let dumper = Self {
+            opt_sender: Some(sender),
+            sample_one_in,
+        };
+
+        (dumper, Some(receiver))
+    }
+
+    /// Randomly decide if current packet should be dumped
+    pub fn sample(&self, rng: &mut impl Rng) -> bool {
+        self.opt_sender.is_some() && rng.gen_ratio(1, self.sample_one_in)
+    }
+
+    /// Send packet to packet dump worker. Sampling stops if the worker has
+    /// quit.
+    pub fn dump(
+        &mut self,
+        src: CanonicalSocketAddr,
+        request: &[u8],
+        opt_response: Option<&Response>,
+    ) {
+        if let Some(sender) = self.opt_sender.as_ref() {
+            let record = PacketDumpRecord {
+                time: OffsetDateTime::now_utc(),
+                src,
+                request: request.to_vec(),
+                response: opt_response.cloned(),
+            };
+
+            // Drop record if worker can't keep up
+            if let Err(TrySendError::Disconnected(_)) = sender.try_send(record) {
+                self.opt_sender = None;
+            }
+        }
+    }
+}
+
+pub struct PacketDumpWorker {
+    config: Config,
+    receiver: Receiver<PacketDumpRecord>,
+    file: DumpFile,
+}
+
+impl PacketDumpWorker {
+    /// Open dump file. Call before privileges are dropped.
+    pub fn new(config: Config, receiver: Receiver<PacketDumpRecord>) -> anyhow::Result<Self> {
+        let file = DumpFile::open(&config.packet_dump.path)?;
+
+        Ok(Self {
+            config,
+            receiver,
+            file,
+        })
+    }
+
+    /// Write records until all senders have been dropped
+    ///
+    /// Packet dumping is only a debugging aid, so errors don't stop the
+    /// tracker. They are logged, after which the worker quits, dropping the
+    /// receiver so that socket workers stop sampling packets.
+    pub fn run(mut self) -> anyhow::Result<()> {
+        if let Err(err) = self.write_records() {
+            ::log::error!("Packet dumping disabled after error: {:#}", err);
+        }
+
+        Ok(())
+    }
+
+    fn write_records(&mut self) -> anyhow::Result<()> {
+        let path = &self.config.packet_dump.path;
+
+        for record in self.receiver.iter() {
+            if self.file.len >= self.config.packet_dump.max_file_size {
+                rotate(path, self.config.packet_dump.max_rotated_files)?;
+
+                self.file = DumpFile::open(path)?;
+            }
+
+            let entry = format_record(&self.config, &record);
+
+            self.file
+                .write(entry.as_bytes())
+                .with_context(|| format!("write to packet dump file {}", path.display()))?;
+        }
+
+        Ok(())
+    }
+}
+
+struct DumpFile {
+    writer: BufWriter<File>,
+    len: u64,
+}
+
+impl DumpFile {
+    fn open(path: &Path) -> anyhow::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("open packet dump file {}", path.display()))?;
+
+        let len = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+
+        Ok(Self {
+            writer: BufWriter::new(file),
+            len,
+        })
+    }
+
+    /// Write entry and flush, so that entries can be read as soon as they
+    /// are written
+    fn write(&mut self, entry: &[u8]) -> ::std::io::Result<()> {
+        self.writer.write_all(entry)?;
+        self.writer.flush()?;
+
+        self.len += entry.len() as u64;
+
+        Ok(())
+    }
+}
+
+/// Rename `path` to `path.1`, `path.1` to `path.2` and so on, removing
+/// files beyond `max_rotated_files`
+fn rotate(path: &Path, max_rotated_files: usize) -> anyhow::Result<()> {
+    let rotated_path = |i: usize| {
+        let mut rotated = path.as_os_str().to_owned();
+
+        rotated.push(format!(".{}", i));
+
+        PathBuf::from(rotated)
+    };
+
+    if max_rotated_files == 0 {
+        return ::std::fs::remove_file(path)
+            .with_context(|| format!("remove packet dump file {}", path.display()));
+    }
+
+    for i in (1..max_rotated_files).rev() {
+        let from = rotated_path(i);
+
+        if from.exists() {
+            ::std::fs::rename(&from, rotated_path(i + 1))
+                .with_context(|| format!("rotate packet dump file {}", from.display()))?;
+        }
+    }
+
+    ::std::fs::rename(path, rotated_path(1))
+        .with_context(|| format!("rotate packet dump file {}", path.display()))
+}
+
+fn format_record(config: &Config, record: &PacketDumpRecord) -> String {
+    let time = record
+        .time
+        .format(&Rfc3339)
+        .unwrap_or_else(|_| "unknown time".into());
+
+    let request = Request::parse_bytes(&record.request, config.protocol.max_scrape_torrents);
+
+    let mut entry = format!("{} {}\nrequest: {:?}\n", time, record.src.get(), request);
+
+    if config.packet_dump.include_hex {
+        entry.push_str(&format!("request hex: {}\n", hex::encode(&record.request)));
+    }
+
+    match record.response.as_ref() {
+        Some(response) => {
+            entry.push_str(&format!("response: {:?}\n", response));
+
+            if config.packet_dump.include_hex {
+                let mut bytes = Vec::new();
+
+                if response.write_bytes(&mut bytes).is_ok() {
+                    entry.push_str(&format!("response hex: {}\n", hex::encode(bytes)));
+                }
+            }
+        }
+        None => entry.push_str("response: none\n"),
+    }
+
+    entry.push('\n');
+
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use aquatic_udp_protocol::{ConnectRequest, ConnectResponse, ConnectionId, TransactionId};
+
+    use super::*;
+
+    #[test]
+    fn test_packet_dump_worker() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("packets.log");
+
+        let mut config = Config::default();
+
+        config.packet_dump.sample_one_in = 1;
+        config.packet_dump.path = path.clone();
+        config.packet_dump.max_file_size = 1;
+        config.packet_dump.max_rotated_files = 2;
+        config.packet_dump.include_hex = true;
+
+        let (mut dumper, opt_receiver) = PacketDumper::new(&config);
+
+        assert!(dumper.sample(&mut rand::thread_rng()));
+
+        let src = CanonicalSocketAddr::new(SocketAddr::from((Ipv4Addr::LOCALHOST, 1000)));
+
+        let transaction_id = TransactionId::new(1);
+
+        let mut request = Vec::new();
+
+        Request::Connect(ConnectRequest { transaction_id })
+            .write_bytes(&mut request)
+            .unwrap();
+
+        let response = Response::Connect(ConnectResponse {
+            connection_id: ConnectionId::new(2),
+            transaction_id,
+        });
+
+        for _ in 0..3 {
+            dumper.dump(src, &request, Some(&response));
+        }
+        dumper.dump(src, &[0], None);
+
+        drop(dumper);
+
+        PacketDumpWorker::new(config, opt_receiver.unwrap())
+            .unwrap()
+            .run()
+            .unwrap();
+
+        // Every entry exceeds max file size, so each one ends up in its own
+        // file and the oldest one was removed
+        let newest = ::std::fs::read_to_string(&path).unwrap();
+        let older = ::std::fs::read_to_string(dir.path().join("packets.log.1")).unwrap();
+
+        assert!(dir.path().join("packets.log.2").exists());
+
+        assert!(!dir.path().join("packets.log.3").exists());
+
+        assert!(newest.contains("request: Err("));
+        assert!(newest.contains("response: none"));
+
+        assert!(older.contains("127.0.0.1:1000"));
+        assert!(older.contains("request: Ok(Connect("));
+        assert!(older.contains("request hex: 00000417271019800000000000000001"));
+        assert!(older.contains("response: Connect("));
+    }
+
+    #[test]
+    fn test_packet_dump_worker_error_is_not_fatal() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut config = Config::default();
+
+        config.packet_dump.sample_one_in = 1;
+        config.packet_dump.path = dir.path().join("packets.log");
+        config.packet_dump.max_file_size = 1;
+
+        let (mut dumper, opt_receiver) = PacketDumper::new(&config);
+
+        let worker = PacketDumpWorker::new(config, opt_receiver.unwrap()).unwrap();
+
+        // Rotating file fails after directory is removed
+        ::std::fs::remove_dir_all(dir.path()).unwrap();
+
+        let src = CanonicalSocketAddr::new(SocketAddr::from((Ipv4Addr::LOCALHOST, 1000)));
+
+        for _ in 0..3 {
+            dumper.dump(src, &[0], None);
+        }
+
+        assert!(worker.run().is_ok());
+
+        // Worker quit after the error, so the dumper stops sampling once it
+        // notices
+        assert!(dumper.sample(&mut rand::thread_rng()));
+
+        dumper.dump(src, &[0], None);
+
+        assert!(!dumper.sample(&mut rand::thread_rng()));
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::VecDeque;
+
+use aquatic_common::CanonicalSocketAddr;
+use aquatic_udp_protocol::ConnectionId;
+use hashbrown::HashMap;
+
+use crate::common::IpVersionStatistics;
+
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConnectionTrackingCounters {
+    pub hits: usize,
+    pub misses: usize,
+    pub evictions: usize,
+}
+
+/// Bounded map of issued connection IDs, for strict connection tracking
+///
+/// Since all connections are valid for the same duration, entries are
+/// queued in expiration order. When the map is full, the oldest entry is
+/// evicted.
+#[derive(Clone)]
+pub struct ConnectionMap {
+    max_len: usize,
+    connections: HashMap<(ConnectionId, CanonicalSocketAddr), u32>,
+    queue: VecDeque<(ConnectionId, CanonicalSocketAddr)>,
+    counters: IpVersionStatistics<ConnectionTrackingCounters>,
+}
+
+impl ConnectionMap {
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            max_len,
+            connections: Default::default(),
+            queue: Default::default(),
+            counters: Default::default(),
+        }
+    }
+
+    /// Insert connection, which is valid while seconds since start are lower
+    /// than `expires`
+    pub fn insert(
+        &mut self,
+        connection_id: ConnectionId,
+        addr: CanonicalSocketAddr,
+        expires: u32,
+        now: u32,
+    ) {
+        let key = (connection_id, addr);
+
+        // Connection IDs include creation time, so an existing entry has the
+        // same expiration time
+        if self.connections.contains_key(&key) {
+            return;
+        }
+
+        if self.connections.len() >= self.max_len {
+            self.clean(now);
+        }
+        if self.connections.len() >= self.max_len {
+            if let Some(evicted) = self.queue.pop_front() {
+                self.connections.remove(&evicted);
+
+                self.counters_mut(evicted.1).evictions += 1;
+            }
+        }
+
+        self.connections.insert(key, expires);
+        self.queue.push_back(key);
+    }
+
+    pub fn contains(
+        &mut self,
+        connection_id: ConnectionId,
+        addr: CanonicalSocketAddr,
+        now: u32,
+    ) -> bool {
+        let valid = self
+            .connections
+            .get(&(connection_id, addr))
+            .is_some_and(|expires| *expires > now);
+
+        let counters = self.counters_mut(addr);
+
+        if valid {
+            counters.hits += 1;
+        } else {
+            counters.misses += 1;
+        }
+
+        valid
+    }
+
+    /// Remove expired connections
+    pub fn clean(&mut self, now: u32) {
+        while let Some(key) = self.queue.front() {
+            if self
+                .connections
+                .get(key)
+                .is_some_and(|expires| *expires > now)
+            {
+                break;
+            }
+
+            self.connections.remove(key);
+            self.queue.pop_front();
+        }
+    }
+
+    /// Return counters since last call
+    pub fn take_counters(&mut self) -> IpVersionStatistics<ConnectionTrackingCounters> {
+        ::std::mem::take(&mut self.counters)
+    }
+
+    fn counters_mut(&mut self, addr: CanonicalSocketAddr) -> &mut ConnectionTrackingCounters {
+        if addr.is_ipv4() {
+            &mut self.counters.ipv4
+        } else {
+            &mut self.counters.ipv6
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use super::*;
+
+    fn addr(port: u16) -> CanonicalSocketAddr {
+        CanonicalSocketAddr::new(SocketAddr::from((Ipv4Addr::LOCALHOST, port)))
+    }
+
+    #[test]
+    fn test_connection_map() {
+        let mut map = ConnectionMap::new(2);
+
+        let connection_id = ConnectionId::new(1);
+
+        map.insert(connection_id, addr(1), 10, 0);
+
+        assert!(map.contains(connection_id, addr(1), 9));
+        assert!(!map.contains(connection_id, addr(1), 10));
+        assert!(!map.contains(connection_id, addr(2), 0));
+
+        map.insert(connection_id, addr(2), 20, 15);
+        map.insert(connection_id, addr(3), 20, 15);
+
+        // Expired entry was removed instead of evicting a valid one
+        assert_eq!(map.connections.len(), 2);
+        assert_eq!(map.take_counters().ipv4.evictions, 0);
+
+        map.insert(connection_id, addr(4), 20, 15);
+
+        assert_eq!(map.connections.len(), 2);
+        assert!(!map.contains(connection_id, addr(2), 15));
+        assert!(map.contains(connection_id, addr(4), 15));
+
+        let counters = map.take_counters().ipv4;
+
+        assert_eq!(
+            counters,
+            ConnectionTrackingCounters {
+                hits: 1,
+                misses: 1,
+                evictions: 1,
+            }
+        );
+
+        map.clean(20);
+
+        assert_eq!(map.connections.len(), 0);
+    }
+}
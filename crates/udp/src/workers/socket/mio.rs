@@ -1,16 +1,19 @@
 use std::io::{Cursor, ErrorKind};
-use std::sync::atomic::Ordering;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::Context;
 use aquatic_common::access_list::AccessListCache;
+use aquatic_common::ban_list::BanListCache;
 use crossbeam_channel::Sender;
 use mio::net::UdpSocket;
 use mio::{Events, Interest, Poll, Token};
 
 use aquatic_common::{
-    access_list::create_access_list_cache, privileges::PrivilegeDropper, CanonicalSocketAddr,
-    ValidUntil,
+    access_list::create_access_list_cache, ban_list::create_ban_list_cache,
+    privileges::PrivilegeDropper, CanonicalSocketAddr, ValidUntil,
 };
 use aquatic_udp_protocol::*;
 use rand::rngs::SmallRng;
@@ -18,9 +21,18 @@ use rand::SeedableRng;
 
 use crate::common::*;
 use crate::config::Config;
+use crate::workers::packet_dump::PacketDumper;
 
+#[cfg(target_os = "linux")]
+use super::mmsg::MmsgBuffers;
+use super::resend_buffer::ResendBuffer;
 use super::validator::ConnectionValidator;
-use super::{create_socket, EXTRA_PACKET_SIZE_IPV4, EXTRA_PACKET_SIZE_IPV6};
+use super::{
+    create_socket, handle_invalid_connection_id, record_access_list_hits, record_rejected_request,
+    reject_if_banned, reject_if_greylisted, shed_announce_if_overloaded,
+    update_validator_statistics, AccessListHitType, RejectionReason, EXTRA_PACKET_SIZE_IPV4,
+    EXTRA_PACKET_SIZE_IPV6,
+};
 
 pub struct SocketWorker {
     config: Config,
@@ -28,14 +40,21 @@ pub struct SocketWorker {
     statistics: CachePaddedArc<IpVersionStatistics<SocketWorkerStatistics>>,
     statistics_sender: Sender<StatisticsMessage>,
     access_list_cache: AccessListCache,
+    ban_list_cache: BanListCache,
     validator: ConnectionValidator,
     socket: UdpSocket,
     buffer: [u8; BUFFER_SIZE],
+    response_peer_buffers: ResponsePeerBuffers,
     rng: SmallRng,
     peer_valid_until: ValidUntil,
+    packet_dumper: PacketDumper,
+    stop: Arc<AtomicBool>,
+    #[cfg(target_os = "linux")]
+    opt_mmsg_buffers: Option<Box<MmsgBuffers>>,
 }
 
 impl SocketWorker {
+    #[allow(clippy::too_many_arguments)]
     pub fn run(
         config: Config,
         shared_state: State,
@@ -43,13 +62,33 @@ impl SocketWorker {
         statistics_sender: Sender<StatisticsMessage>,
         validator: ConnectionValidator,
         priv_dropper: PrivilegeDropper,
+        packet_dumper: PacketDumper,
+        stop: Arc<AtomicBool>,
     ) -> anyhow::Result<()> {
         let socket = UdpSocket::from_std(create_socket(&config, priv_dropper)?);
         let access_list_cache = create_access_list_cache(&shared_state.access_list);
+        let ban_list_cache = create_ban_list_cache(&shared_state.ban_list);
         let peer_valid_until = ValidUntil::new(
             shared_state.server_start_instant,
             config.cleaning.max_peer_age,
         );
+        #[cfg(target_os = "linux")]
+        let opt_mmsg_buffers = (config.network.batch_size > 1).then(|| {
+            let mut mmsg_buffers = Box::new(MmsgBuffers::new(config.network.batch_size));
+
+            if config.network.segmentation_offload {
+                use std::os::fd::AsRawFd;
+
+                if let Err(err) = mmsg_buffers.enable_segmentation_offload(socket.as_raw_fd()) {
+                    ::log::warn!(
+                        "UDP segmentation offload not supported, sending responses separately: {:#}",
+                        err
+                    );
+                }
+            }
+
+            mmsg_buffers
+        });
 
         let mut worker = Self {
             config,
@@ -58,18 +97,24 @@ impl SocketWorker {
             statistics_sender,
             validator,
             access_list_cache,
+            ban_list_cache,
             socket,
             buffer: [0; BUFFER_SIZE],
+            response_peer_buffers: Default::default(),
             rng: SmallRng::from_entropy(),
             peer_valid_until,
+            packet_dumper,
+            stop,
+            #[cfg(target_os = "linux")]
+            opt_mmsg_buffers,
         };
 
         worker.run_inner()
     }
 
     pub fn run_inner(&mut self) -> anyhow::Result<()> {
-        let mut opt_resend_buffer =
-            (self.config.network.resend_buffer_max_len > 0).then_some(Vec::new());
+        let mut opt_resend_buffer = (self.config.network.resend_buffer_max_len > 0)
+            .then(|| ResendBuffer::new(self.config.network.resend_buffer_max_len));
         let mut events = Events::with_capacity(1);
         let mut poll = Poll::new().context("create poll")?;
 
@@ -92,7 +137,7 @@ impl SocketWorker {
 
             // If resend buffer is enabled, send any responses in it
             if let Some(resend_buffer) = opt_resend_buffer.as_mut() {
-                for (addr, response) in resend_buffer.drain(..) {
+                for (addr, response) in resend_buffer.drain() {
                     self.send_response(&mut None, addr, response);
                 }
             }
@@ -106,113 +151,369 @@ impl SocketWorker {
                 );
             }
 
+            if self.stop.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+
             iter_counter = iter_counter.wrapping_add(1);
         }
     }
 
-    fn read_and_handle_requests(
-        &mut self,
-        opt_resend_buffer: &mut Option<Vec<(CanonicalSocketAddr, Response)>>,
-    ) {
+    fn read_and_handle_requests(&mut self, opt_resend_buffer: &mut Option<ResendBuffer>) {
+        #[cfg(target_os = "linux")]
+        if let Some(mut mmsg_buffers) = self.opt_mmsg_buffers.take() {
+            self.read_and_handle_requests_batched(opt_resend_buffer, &mut mmsg_buffers);
+
+            self.opt_mmsg_buffers = Some(mmsg_buffers);
+
+            return;
+        }
+
         let max_scrape_torrents = self.config.protocol.max_scrape_torrents;
 
         loop {
             match self.socket.recv_from(&mut self.buffer[..]) {
                 Ok((bytes_read, src)) => {
-                    let src_port = src.port();
-                    let src = CanonicalSocketAddr::new(src);
+                    if reject_if_banned(
+                        &self.config,
+                        &self.statistics,
+                        &mut self.ban_list_cache,
+                        CanonicalSocketAddr::new(src),
+                    ) {
+                        continue;
+                    }
 
-                    // Use canonical address for statistics
-                    let opt_statistics = if self.config.statistics.active() {
-                        if src.is_ipv4() {
-                            let statistics = &self.statistics.ipv4;
+                    let parse_result =
+                        Request::parse_bytes(&self.buffer[..bytes_read], max_scrape_torrents);
 
-                            statistics
-                                .bytes_received
-                                .fetch_add(bytes_read + EXTRA_PACKET_SIZE_IPV4, Ordering::Relaxed);
+                    let opt_response = self.handle_packet(bytes_read, src, parse_result);
 
-                            Some(statistics)
-                        } else {
-                            let statistics = &self.statistics.ipv6;
+                    if self.packet_dumper.sample(&mut self.rng) {
+                        self.packet_dumper.dump(
+                            CanonicalSocketAddr::new(src),
+                            &self.buffer[..bytes_read],
+                            opt_response.as_ref().map(|(_, response)| response),
+                        );
+                    }
 
-                            statistics
-                                .bytes_received
-                                .fetch_add(bytes_read + EXTRA_PACKET_SIZE_IPV6, Ordering::Relaxed);
+                    if let Some((src, response)) = opt_response {
+                        self.send_response(opt_resend_buffer, src, response);
+                    }
 
-                            Some(statistics)
-                        }
-                    } else {
-                        None
-                    };
+                    update_validator_statistics(
+                        &self.config,
+                        &mut self.validator,
+                        &self.statistics,
+                    );
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                    break;
+                }
+                Err(err) => {
+                    ::log::warn!("recv_from error: {:#}", err);
+                }
+            }
+        }
+    }
 
-                    if src_port == 0 {
-                        ::log::debug!("Ignored request because source port is zero");
+    /// Receive requests and send responses in batches with recvmmsg and
+    /// sendmmsg
+    #[cfg(target_os = "linux")]
+    fn read_and_handle_requests_batched(
+        &mut self,
+        opt_resend_buffer: &mut Option<ResendBuffer>,
+        mmsg_buffers: &mut MmsgBuffers,
+    ) {
+        use std::os::fd::AsRawFd;
 
-                        continue;
-                    }
+        let max_scrape_torrents = self.config.protocol.max_scrape_torrents;
+        let fd = self.socket.as_raw_fd();
 
-                    match Request::parse_bytes(&self.buffer[..bytes_read], max_scrape_torrents) {
-                        Ok(request) => {
-                            if let Some(statistics) = opt_statistics {
-                                statistics.requests.fetch_add(1, Ordering::Relaxed);
-                            }
+        let mut responses = Vec::with_capacity(mmsg_buffers.batch_size());
 
-                            if let Some(response) = self.handle_request(request, src) {
-                                self.send_response(opt_resend_buffer, src, response);
-                            }
-                        }
-                        Err(RequestParseError::Sendable {
-                            connection_id,
-                            transaction_id,
-                            err,
-                        }) if self.validator.connection_id_valid(src, connection_id) => {
-                            let response = ErrorResponse {
-                                transaction_id,
-                                message: err.into(),
-                            };
-
-                            self.send_response(opt_resend_buffer, src, Response::Error(response));
-
-                            ::log::debug!("request parse error (sent error response): {:?}", err);
-                        }
-                        Err(err) => {
-                            ::log::debug!(
-                                "request parse error (didn't send error response): {:?}",
-                                err
-                            );
-                        }
-                    };
-                }
+        loop {
+            let num_received = match mmsg_buffers.recv(fd) {
+                Ok(num_received) => num_received,
                 Err(err) if err.kind() == ErrorKind::WouldBlock => {
                     break;
                 }
                 Err(err) => {
-                    ::log::warn!("recv_from error: {:#}", err);
+                    ::log::warn!("recvmmsg error: {:#}", err);
+
+                    continue;
+                }
+            };
+
+            for i in 0..num_received {
+                let (bytes, opt_src) = mmsg_buffers.received(i);
+
+                let Some(src) = opt_src else {
+                    continue;
+                };
+
+                if reject_if_banned(
+                    &self.config,
+                    &self.statistics,
+                    &mut self.ban_list_cache,
+                    CanonicalSocketAddr::new(src),
+                ) {
+                    continue;
+                }
+
+                let bytes_read = bytes.len();
+                let parse_result = Request::parse_bytes(bytes, max_scrape_torrents);
+
+                let opt_response = self.handle_packet(bytes_read, src, parse_result);
+
+                if self.packet_dumper.sample(&mut self.rng) {
+                    self.packet_dumper.dump(
+                        CanonicalSocketAddr::new(src),
+                        bytes,
+                        opt_response.as_ref().map(|(_, response)| response),
+                    );
+                }
+
+                let Some((src, response)) = opt_response else {
+                    continue;
+                };
+
+                // Response index is never higher than index of current
+                // packet, which has already been parsed
+                let response_index = responses.len();
+                let mut buffer = Cursor::new(&mut mmsg_buffers.response_buffer(response_index)[..]);
+
+                if let Err(err) = response.write_bytes(&mut buffer) {
+                    ::log::error!("failed writing response to buffer: {:#}", err);
+
+                    continue;
+                }
+
+                let bytes_written = buffer.position() as usize;
+
+                mmsg_buffers.set_response_meta(
+                    response_index,
+                    self.response_destination(src),
+                    bytes_written,
+                );
+
+                responses.push((src, response));
+            }
+
+            let num_messages = mmsg_buffers.prepare_messages(responses.len());
+            let mut num_sent_messages = 0;
+
+            while num_sent_messages < num_messages {
+                match mmsg_buffers.send(fd, num_sent_messages, num_messages) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        num_sent_messages += n;
+                    }
+                    // Returned when segmentation offload is not supported by
+                    // network device
+                    Err(err)
+                        if mmsg_buffers.segmentation_offload()
+                            && err.raw_os_error() == Some(libc::EIO) =>
+                    {
+                        ::log::warn!(
+                            "Disabling UDP segmentation offload after sendmmsg error: {:#}",
+                            err
+                        );
+
+                        mmsg_buffers.disable_segmentation_offload();
+
+                        break;
+                    }
+                    Err(err) => {
+                        ::log::debug!("sendmmsg error: {:#}", err);
+
+                        break;
+                    }
+                }
+            }
+
+            let num_sent = mmsg_buffers.num_responses_in_messages(num_sent_messages);
+
+            // Send remaining responses one by one, adding them to resend
+            // buffer on failure if it is enabled
+            for (i, (src, response)) in responses.drain(..).enumerate() {
+                if i < num_sent {
+                    self.record_response_sent(src, &response, mmsg_buffers.response_len(i));
+                    self.response_peer_buffers.reclaim(response);
+                } else {
+                    self.send_response(opt_resend_buffer, src, response);
+                }
+            }
+
+            update_validator_statistics(&self.config, &mut self.validator, &self.statistics);
+
+            if num_received < mmsg_buffers.batch_size() {
+                break;
+            }
+        }
+    }
+
+    /// Update statistics and handle parsed request. Returns response and
+    /// canonical address to send it to, if any.
+    fn handle_packet(
+        &mut self,
+        bytes_read: usize,
+        src: SocketAddr,
+        parse_result: Result<Request, RequestParseError>,
+    ) -> Option<(CanonicalSocketAddr, Response)> {
+        let src_port = src.port();
+        let src = CanonicalSocketAddr::new(src);
+
+        // Use canonical address for statistics
+        let opt_statistics = if self.config.statistics.active() {
+            if src.is_ipv4() {
+                let statistics = &self.statistics.ipv4;
+
+                statistics
+                    .bytes_received
+                    .fetch_add(bytes_read + EXTRA_PACKET_SIZE_IPV4, Ordering::Relaxed);
+
+                Some(statistics)
+            } else {
+                let statistics = &self.statistics.ipv6;
+
+                statistics
+                    .bytes_received
+                    .fetch_add(bytes_read + EXTRA_PACKET_SIZE_IPV6, Ordering::Relaxed);
+
+                Some(statistics)
+            }
+        } else {
+            None
+        };
+
+        if src_port == 0 {
+            ::log::debug!("Ignored request because source port is zero");
+
+            record_rejected_request(
+                &self.config,
+                &self.statistics,
+                src,
+                RejectionReason::SourcePortZero,
+            );
+
+            return None;
+        }
+
+        if reject_if_greylisted(&self.config, &self.statistics, &self.validator, src) {
+            return None;
+        }
+
+        match parse_result {
+            Ok(request) => {
+                if let Some(statistics) = opt_statistics {
+                    statistics.requests.fetch_add(1, Ordering::Relaxed);
+                }
+
+                self.handle_request(request, src, bytes_read)
+                    .map(|response| (src, response))
+            }
+            Err(err) => {
+                record_rejected_request(
+                    &self.config,
+                    &self.statistics,
+                    src,
+                    RejectionReason::ParseError,
+                );
+
+                self.handle_parse_error(src, err)
+            }
+        }
+    }
+
+    fn handle_parse_error(
+        &mut self,
+        src: CanonicalSocketAddr,
+        err: RequestParseError,
+    ) -> Option<(CanonicalSocketAddr, Response)> {
+        match err {
+            RequestParseError::Sendable {
+                connection_id,
+                transaction_id,
+                err,
+            } if self.validator.connection_id_valid(src, connection_id) => {
+                self.validator.record_failure(src);
+
+                let response = ErrorResponse {
+                    transaction_id,
+                    message: err.into(),
+                };
+
+                ::log::debug!("request parse error (sent error response): {:?}", err);
+
+                Some((src, Response::Error(response)))
+            }
+            err => {
+                // Invalid connection IDs have already been counted
+                if let RequestParseError::Unsendable { .. } = err {
+                    self.validator.record_failure(src);
                 }
+
+                ::log::debug!(
+                    "request parse error (didn't send error response): {:?}",
+                    err
+                );
+
+                None
             }
         }
     }
 
-    fn handle_request(&mut self, request: Request, src: CanonicalSocketAddr) -> Option<Response> {
+    fn handle_request(
+        &mut self,
+        request: Request,
+        src: CanonicalSocketAddr,
+        request_len: usize,
+    ) -> Option<Response> {
         let access_list_mode = self.config.access_list.mode;
 
         match request {
-            Request::Connect(request) => {
-                return Some(Response::Connect(ConnectResponse {
-                    connection_id: self.validator.create_connection_id(src),
-                    transaction_id: request.transaction_id,
-                }));
-            }
+            Request::Connect(request) => Some(Response::Connect(ConnectResponse {
+                connection_id: self.validator.create_connection_id(src),
+                transaction_id: request.transaction_id,
+            })),
             Request::Announce(request) => {
                 if self
                     .validator
                     .connection_id_valid(src, request.connection_id)
                 {
-                    if self
+                    if !self.config.client_filter.allows(&request.peer_id.0) {
+                        let response = Response::Error(ErrorResponse {
+                            transaction_id: request.transaction_id,
+                            message: self.config.client_filter.failure_reason().into(),
+                        });
+
+                        return Some(response);
+                    }
+
+                    let check = self
                         .access_list_cache
                         .load()
-                        .allows(access_list_mode, &request.info_hash.0)
-                    {
+                        .check(access_list_mode, &request.info_hash.0);
+
+                    record_access_list_hits(
+                        &self.config,
+                        &self.statistics,
+                        src,
+                        AccessListHitType::Announce,
+                        usize::from(check.hit),
+                    );
+
+                    if check.allowed {
+                        if let Some(response) = shed_announce_if_overloaded(
+                            &self.config,
+                            &self.statistics,
+                            &self.shared_state,
+                            src,
+                            request.transaction_id,
+                        ) {
+                            return Some(response);
+                        }
+
                         let response = self.shared_state.torrent_maps.announce(
                             &self.config,
                             &self.statistics_sender,
@@ -220,6 +521,7 @@ impl SocketWorker {
                             &request,
                             src,
                             self.peer_valid_until,
+                            &mut self.response_peer_buffers,
                         );
 
                         return Some(response);
@@ -230,28 +532,63 @@ impl SocketWorker {
                         }));
                     }
                 }
+
+                handle_invalid_connection_id(
+                    &self.config,
+                    &self.statistics,
+                    src,
+                    request.transaction_id,
+                    request_len,
+                )
             }
             Request::Scrape(request) => {
                 if self
                     .validator
                     .connection_id_valid(src, request.connection_id)
                 {
-                    return Some(Response::Scrape(
-                        self.shared_state.torrent_maps.scrape(request, src),
-                    ));
+                    let access_list = self.access_list_cache.load();
+                    let mut hits = 0;
+
+                    let allows = |info_hash: &InfoHash| {
+                        let check = access_list.check(access_list_mode, &info_hash.0);
+
+                        hits += usize::from(check.hit);
+
+                        check.allowed
+                    };
+
+                    let response = self.shared_state.torrent_maps.scrape(request, src, allows);
+
+                    record_access_list_hits(
+                        &self.config,
+                        &self.statistics,
+                        src,
+                        AccessListHitType::Scrape,
+                        hits,
+                    );
+
+                    return Some(Response::Scrape(response));
                 }
+
+                handle_invalid_connection_id(
+                    &self.config,
+                    &self.statistics,
+                    src,
+                    request.transaction_id,
+                    request_len,
+                )
             }
         }
-
-        None
     }
 
     fn send_response(
         &mut self,
-        opt_resend_buffer: &mut Option<Vec<(CanonicalSocketAddr, Response)>>,
+        opt_resend_buffer: &mut Option<ResendBuffer>,
         canonical_addr: CanonicalSocketAddr,
         response: Response,
     ) {
+        let addr = self.response_destination(canonical_addr);
+
         let mut buffer = Cursor::new(&mut self.buffer[..]);
 
         if let Err(err) = response.write_bytes(&mut buffer) {
@@ -262,72 +599,117 @@ impl SocketWorker {
 
         let bytes_written = buffer.position() as usize;
 
-        let addr = if self.config.network.address.is_ipv4() {
-            canonical_addr
-                .get_ipv4()
-                .expect("found peer ipv6 address while running bound to ipv4 address")
-        } else {
-            canonical_addr.get_ipv6_mapped()
-        };
-
         match self
             .socket
             .send_to(&buffer.into_inner()[..bytes_written], addr)
         {
-            Ok(bytes_sent) if self.config.statistics.active() => {
-                let stats = if canonical_addr.is_ipv4() {
-                    let stats = &self.statistics.ipv4;
+            Ok(bytes_sent) => {
+                self.record_response_sent(canonical_addr, &response, bytes_sent);
 
-                    stats
-                        .bytes_sent
-                        .fetch_add(bytes_sent + EXTRA_PACKET_SIZE_IPV4, Ordering::Relaxed);
-
-                    stats
-                } else {
-                    let stats = &self.statistics.ipv6;
+                self.response_peer_buffers.reclaim(response);
+            }
+            Err(err) => {
+                match opt_resend_buffer.as_mut() {
+                    Some(resend_buffer)
+                        if (err.raw_os_error() == Some(libc::ENOBUFS))
+                            || (err.kind() == ErrorKind::WouldBlock) =>
+                    {
+                        ::log::debug!("Adding response to resend queue, since sending it to {} failed with: {:#}", addr, err);
 
-                    stats
-                        .bytes_sent
-                        .fetch_add(bytes_sent + EXTRA_PACKET_SIZE_IPV6, Ordering::Relaxed);
+                        if let Some((dropped_addr, dropped_response)) =
+                            resend_buffer.push(canonical_addr, response)
+                        {
+                            ::log::warn!("Response resend buffer full, dropping response");
 
-                    stats
-                };
-
-                match response {
-                    Response::Connect(_) => {
-                        stats.responses_connect.fetch_add(1, Ordering::Relaxed);
-                    }
-                    Response::AnnounceIpv4(_) | Response::AnnounceIpv6(_) => {
-                        stats.responses_announce.fetch_add(1, Ordering::Relaxed);
-                    }
-                    Response::Scrape(_) => {
-                        stats.responses_scrape.fetch_add(1, Ordering::Relaxed);
-                    }
-                    Response::Error(_) => {
-                        stats.responses_error.fetch_add(1, Ordering::Relaxed);
+                            self.record_response_dropped(dropped_addr, &dropped_response);
+                        }
                     }
-                }
-            }
-            Ok(_) => (),
-            Err(err) => match opt_resend_buffer.as_mut() {
-                Some(resend_buffer)
-                    if (err.raw_os_error() == Some(libc::ENOBUFS))
-                        || (err.kind() == ErrorKind::WouldBlock) =>
-                {
-                    if resend_buffer.len() < self.config.network.resend_buffer_max_len {
-                        ::log::debug!("Adding response to resend queue, since sending it to {} failed with: {:#}", addr, err);
+                    _ => {
+                        ::log::warn!("Sending response to {} failed: {:#}", addr, err);
 
-                        resend_buffer.push((canonical_addr, response));
-                    } else {
-                        ::log::warn!("Response resend buffer full, dropping response");
+                        self.record_response_dropped(canonical_addr, &response);
                     }
                 }
-                _ => {
-                    ::log::warn!("Sending response to {} failed: {:#}", addr, err);
-                }
-            },
+            }
         }
 
         ::log::debug!("send response fn finished");
     }
+
+    fn response_destination(&self, canonical_addr: CanonicalSocketAddr) -> SocketAddr {
+        if self.config.network.address.is_ipv4() {
+            canonical_addr
+                .get_ipv4()
+                .expect("found peer ipv6 address while running bound to ipv4 address")
+        } else {
+            canonical_addr.get_ipv6_mapped()
+        }
+    }
+
+    fn record_response_dropped(&self, canonical_addr: CanonicalSocketAddr, response: &Response) {
+        if !self.config.statistics.active() {
+            return;
+        }
+
+        let stats = if canonical_addr.is_ipv4() {
+            &self.statistics.ipv4
+        } else {
+            &self.statistics.ipv6
+        };
+
+        let counter = match response {
+            Response::Connect(_) => &stats.dropped_responses_connect,
+            Response::AnnounceIpv4(_) | Response::AnnounceIpv6(_) => {
+                &stats.dropped_responses_announce
+            }
+            Response::Scrape(_) => &stats.dropped_responses_scrape,
+            Response::Error(_) => &stats.dropped_responses_error,
+        };
+
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_response_sent(
+        &self,
+        canonical_addr: CanonicalSocketAddr,
+        response: &Response,
+        bytes_sent: usize,
+    ) {
+        if !self.config.statistics.active() {
+            return;
+        }
+
+        let stats = if canonical_addr.is_ipv4() {
+            let stats = &self.statistics.ipv4;
+
+            stats
+                .bytes_sent
+                .fetch_add(bytes_sent + EXTRA_PACKET_SIZE_IPV4, Ordering::Relaxed);
+
+            stats
+        } else {
+            let stats = &self.statistics.ipv6;
+
+            stats
+                .bytes_sent
+                .fetch_add(bytes_sent + EXTRA_PACKET_SIZE_IPV6, Ordering::Relaxed);
+
+            stats
+        };
+
+        match response {
+            Response::Connect(_) => {
+                stats.responses_connect.fetch_add(1, Ordering::Relaxed);
+            }
+            Response::AnnounceIpv4(_) | Response::AnnounceIpv6(_) => {
+                stats.responses_announce.fetch_add(1, Ordering::Relaxed);
+            }
+            Response::Scrape(_) => {
+                stats.responses_scrape.fetch_add(1, Ordering::Relaxed);
+            }
+            Response::Error(_) => {
+                stats.responses_error.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
 }
@@ -0,0 +1,382 @@
+//! Batched receiving and sending with recvmmsg(2) and sendmmsg(2)
+
+use std::io;
+use std::mem::{size_of, zeroed};
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::os::fd::RawFd;
+
+use socket2::SockAddr;
+
+use crate::common::BUFFER_SIZE;
+
+/// Maximum number of segments per message accepted by all kernels
+/// supporting UDP_SEGMENT
+const MAX_SEGMENTS: usize = 64;
+/// Only combine responses fitting in a packet on any IPv6 path without
+/// fragmentation, since the kernel rejects segments larger than the MTU
+const MAX_SEGMENT_SIZE: usize = 1280 - 40 - 8;
+/// Maximum UDP payload length over IPv4
+const MAX_MESSAGE_LEN: usize = 65_507;
+
+/// Control message buffer, large enough and suitably aligned for a
+/// UDP_SEGMENT control message
+type ControlBuffer = [libc::cmsghdr; 2];
+
+/// Buffers for a batch of packets
+///
+/// The same buffers are used for received requests and responses to them.
+/// Since there is at most one response per request, the response to packet
+/// `i` can be written into buffer `j <= i` once packet `i` has been parsed.
+pub struct MmsgBuffers {
+    buffers: Vec<[u8; BUFFER_SIZE]>,
+    addresses: Vec<libc::sockaddr_storage>,
+    address_lengths: Vec<libc::socklen_t>,
+    iovecs: Vec<libc::iovec>,
+    headers: Vec<libc::mmsghdr>,
+    controls: Vec<ControlBuffer>,
+    /// End of range of responses included in each prepared message
+    message_ends: Vec<usize>,
+    segmentation_offload: bool,
+}
+
+impl MmsgBuffers {
+    pub fn new(batch_size: usize) -> Self {
+        Self {
+            buffers: vec![[0; BUFFER_SIZE]; batch_size],
+            addresses: vec![unsafe { zeroed() }; batch_size],
+            address_lengths: vec![0; batch_size],
+            iovecs: vec![unsafe { zeroed() }; batch_size],
+            headers: vec![unsafe { zeroed() }; batch_size],
+            controls: vec![unsafe { zeroed() }; batch_size],
+            message_ends: Vec::with_capacity(batch_size),
+            segmentation_offload: false,
+        }
+    }
+
+    /// Combine consecutive responses to the same address into a single
+    /// message using UDP generic segmentation offload (UDP_SEGMENT)
+    ///
+    /// Returns an error if the kernel doesn't support it.
+    pub fn enable_segmentation_offload(&mut self, fd: RawFd) -> io::Result<()> {
+        let mut value: libc::c_int = 0;
+        let mut len = size_of::<libc::c_int>() as libc::socklen_t;
+
+        let result = unsafe {
+            libc::getsockopt(
+                fd,
+                libc::SOL_UDP,
+                libc::UDP_SEGMENT,
+                ::std::ptr::addr_of_mut!(value).cast(),
+                &mut len,
+            )
+        };
+
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        self.segmentation_offload = true;
+
+        Ok(())
+    }
+
+    pub fn disable_segmentation_offload(&mut self) {
+        self.segmentation_offload = false;
+    }
+
+    pub fn segmentation_offload(&self) -> bool {
+        self.segmentation_offload
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.buffers.len()
+    }
+
+    /// Receive up to `batch_size` packets without blocking. Returns number of
+    /// packets received.
+    pub fn recv(&mut self, fd: RawFd) -> io::Result<usize> {
+        for i in 0..self.batch_size() {
+            self.address_lengths[i] = size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+            self.prepare_header(i, BUFFER_SIZE);
+        }
+
+        let result = unsafe {
+            libc::recvmmsg(
+                fd,
+                self.headers.as_mut_ptr(),
+                self.batch_size() as _,
+                libc::MSG_DONTWAIT as _,
+                ::std::ptr::null_mut(),
+            )
+        };
+
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let num_received = result as usize;
+
+        for i in 0..num_received {
+            self.address_lengths[i] = self.headers[i].msg_hdr.msg_namelen;
+        }
+
+        Ok(num_received)
+    }
+
+    /// Payload and source address of received packet
+    ///
+    /// Source address is None if it is not an IP socket address.
+    pub fn received(&self, index: usize) -> (&[u8], Option<SocketAddr>) {
+        let len = self.headers[index].msg_len as usize;
+
+        let addr = unsafe { SockAddr::new(self.addresses[index], self.address_lengths[index]) }
+            .as_socket();
+
+        (&self.buffers[index][..len], addr)
+    }
+
+    /// Buffer to write response with given index into
+    pub fn response_buffer(&mut self, index: usize) -> &mut [u8; BUFFER_SIZE] {
+        &mut self.buffers[index]
+    }
+
+    /// Set destination address and length of response with given index
+    pub fn set_response_meta(&mut self, index: usize, addr: SocketAddr, len: usize) {
+        let addr = SockAddr::from(addr);
+
+        unsafe {
+            ::std::ptr::copy_nonoverlapping(
+                addr.as_ptr().cast::<u8>(),
+                ::std::ptr::addr_of_mut!(self.addresses[index]).cast::<u8>(),
+                addr.len() as usize,
+            );
+        }
+
+        self.address_lengths[index] = addr.len();
+
+        self.prepare_header(index, len);
+    }
+
+    /// Prepare messages for sending responses `0..num_responses`, after
+    /// their metadata has been set. Returns number of messages.
+    ///
+    /// Without segmentation offload, there is one message per response.
+    /// Otherwise, runs of consecutive responses to the same address are
+    /// combined as long as all but the last one have the same length, as
+    /// required by the kernel.
+    pub fn prepare_messages(&mut self, num_responses: usize) -> usize {
+        self.message_ends.clear();
+
+        if !self.segmentation_offload {
+            self.message_ends.extend(1..=num_responses);
+
+            return num_responses;
+        }
+
+        let mut start = 0;
+
+        while start < num_responses {
+            let segment_size = self.iovecs[start].iov_len;
+
+            let mut end = start + 1;
+            let mut message_len = segment_size;
+
+            if segment_size <= MAX_SEGMENT_SIZE {
+                while end < num_responses
+                    && end - start < MAX_SEGMENTS
+                    && self.iovecs[end].iov_len <= segment_size
+                    && message_len + self.iovecs[end].iov_len <= MAX_MESSAGE_LEN
+                    && self.same_address(start, end)
+                {
+                    message_len += self.iovecs[end].iov_len;
+                    end += 1;
+
+                    // Only the last segment may be shorter
+                    if self.iovecs[end - 1].iov_len < segment_size {
+                        break;
+                    }
+                }
+            }
+
+            self.prepare_message(self.message_ends.len(), start..end, segment_size);
+            self.message_ends.push(end);
+
+            start = end;
+        }
+
+        self.message_ends.len()
+    }
+
+    /// Send prepared messages `offset..num_messages` without blocking.
+    /// Returns number of messages sent, which may be lower than requested.
+    pub fn send(&mut self, fd: RawFd, offset: usize, num_messages: usize) -> io::Result<usize> {
+        let result = unsafe {
+            libc::sendmmsg(
+                fd,
+                self.headers[offset..].as_mut_ptr(),
+                (num_messages - offset) as _,
+                libc::MSG_DONTWAIT as _,
+            )
+        };
+
+        if result < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(result as usize)
+        }
+    }
+
+    /// Number of responses included in the first `num_messages` prepared
+    /// messages
+    pub fn num_responses_in_messages(&self, num_messages: usize) -> usize {
+        num_messages
+            .checked_sub(1)
+            .map(|i| self.message_ends[i])
+            .unwrap_or(0)
+    }
+
+    /// Length of response with given index
+    pub fn response_len(&self, index: usize) -> usize {
+        self.iovecs[index].iov_len
+    }
+
+    fn same_address(&self, a: usize, b: usize) -> bool {
+        let len = self.address_lengths[a];
+
+        if len != self.address_lengths[b] {
+            return false;
+        }
+
+        let bytes = |index: usize| unsafe {
+            ::std::slice::from_raw_parts(
+                ::std::ptr::addr_of!(self.addresses[index]).cast::<u8>(),
+                len as usize,
+            )
+        };
+
+        bytes(a) == bytes(b)
+    }
+
+    /// Prepare message for responses in `responses`, which must all have
+    /// the same address, storing it in header with index `message_index`
+    ///
+    /// `message_index` must not be higher than the start of the range.
+    fn prepare_message(
+        &mut self,
+        message_index: usize,
+        responses: Range<usize>,
+        segment_size: usize,
+    ) {
+        let num_segments = responses.len();
+
+        // Safety: all-zero msghdr is valid
+        let mut msg_hdr: libc::msghdr = unsafe { zeroed() };
+
+        msg_hdr.msg_name = ::std::ptr::addr_of_mut!(self.addresses[responses.start]).cast();
+        msg_hdr.msg_namelen = self.address_lengths[responses.start];
+        msg_hdr.msg_iov = ::std::ptr::addr_of_mut!(self.iovecs[responses.start]);
+        msg_hdr.msg_iovlen = num_segments as _;
+
+        if num_segments > 1 {
+            msg_hdr.msg_control = self.controls[message_index].as_mut_ptr().cast();
+            msg_hdr.msg_controllen =
+                unsafe { libc::CMSG_SPACE(size_of::<u16>() as libc::c_uint) } as _;
+
+            // Safety: control buffer is large enough and suitably aligned
+            unsafe {
+                let cmsg = libc::CMSG_FIRSTHDR(&msg_hdr);
+
+                (*cmsg).cmsg_level = libc::SOL_UDP;
+                (*cmsg).cmsg_type = libc::UDP_SEGMENT;
+                (*cmsg).cmsg_len = libc::CMSG_LEN(size_of::<u16>() as libc::c_uint) as _;
+
+                ::std::ptr::write_unaligned(
+                    libc::CMSG_DATA(cmsg).cast::<u16>(),
+                    segment_size as u16,
+                );
+            }
+        }
+
+        self.headers[message_index] = libc::mmsghdr {
+            msg_hdr,
+            msg_len: 0,
+        };
+    }
+
+    fn prepare_header(&mut self, index: usize, len: usize) {
+        self.iovecs[index] = libc::iovec {
+            iov_base: self.buffers[index].as_mut_ptr().cast(),
+            iov_len: len,
+        };
+
+        // Safety: all-zero msghdr is valid. Some fields are private on some
+        // libc implementations, so struct literals can't be used.
+        let mut msg_hdr: libc::msghdr = unsafe { zeroed() };
+
+        msg_hdr.msg_name = ::std::ptr::addr_of_mut!(self.addresses[index]).cast();
+        msg_hdr.msg_namelen = self.address_lengths[index];
+        msg_hdr.msg_iov = ::std::ptr::addr_of_mut!(self.iovecs[index]);
+        msg_hdr.msg_iovlen = 1;
+
+        self.headers[index] = libc::mmsghdr {
+            msg_hdr,
+            msg_len: 0,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, UdpSocket};
+    use std::os::fd::AsRawFd;
+
+    use super::*;
+
+    #[test]
+    fn test_segmentation_offload() {
+        let sender = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let receiver = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let other_receiver = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+
+        let mut buffers = MmsgBuffers::new(4);
+
+        if buffers
+            .enable_segmentation_offload(sender.as_raw_fd())
+            .is_err()
+        {
+            return;
+        }
+
+        let responses = [
+            (&receiver, 10),
+            (&receiver, 10),
+            (&receiver, 5),
+            (&other_receiver, 10),
+        ];
+
+        for (i, (socket, len)) in responses.iter().enumerate() {
+            buffers.response_buffer(i)[..*len].fill(i as u8);
+            buffers.set_response_meta(i, socket.local_addr().unwrap(), *len);
+        }
+
+        let num_messages = buffers.prepare_messages(responses.len());
+
+        assert_eq!(num_messages, 2);
+        assert_eq!(buffers.num_responses_in_messages(1), 3);
+
+        assert_eq!(
+            buffers.send(sender.as_raw_fd(), 0, num_messages).unwrap(),
+            2
+        );
+
+        // Kernel splits combined message into separate packets
+        let mut buffer = [0u8; 64];
+
+        for (i, (socket, len)) in responses.iter().enumerate() {
+            let bytes_read = socket.recv(&mut buffer).unwrap();
+
+            assert_eq!(&buffer[..bytes_read], &vec![i as u8; *len][..]);
+        }
+    }
+}
@@ -1,10 +1,21 @@
+mod connection_map;
 mod mio;
+#[cfg(target_os = "linux")]
+mod mmsg;
+mod resend_buffer;
 #[cfg(all(target_os = "linux", feature = "io-uring"))]
 mod uring;
 mod validator;
 
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
 use anyhow::Context;
+use aquatic_common::ban_list::BanListCache;
 use aquatic_common::privileges::PrivilegeDropper;
+use aquatic_common::systemd;
+use aquatic_common::CanonicalSocketAddr;
+use aquatic_udp_protocol::{ErrorResponse, Response, TransactionId};
 use crossbeam_channel::Sender;
 use socket2::{Domain, Protocol, Socket, Type};
 
@@ -13,6 +24,7 @@ use crate::{
         CachePaddedArc, IpVersionStatistics, SocketWorkerStatistics, State, StatisticsMessage,
     },
     config::Config,
+    workers::packet_dump::PacketDumper,
 };
 
 pub use self::validator::ConnectionValidator;
@@ -38,6 +50,251 @@ const EXTRA_PACKET_SIZE_IPV4: usize = 8 + 18 + 20 + 8;
 /// - 8 bit udp header
 const EXTRA_PACKET_SIZE_IPV6: usize = 8 + 18 + 40 + 8;
 
+/// Add connection tracking and greylist counters of validator to shared
+/// statistics
+fn update_validator_statistics(
+    config: &Config,
+    validator: &mut ConnectionValidator,
+    statistics: &IpVersionStatistics<SocketWorkerStatistics>,
+) {
+    let opt_connection_tracking_counters = validator.take_connection_tracking_counters();
+    let opt_greylist_counters = validator.take_greylist_counters();
+
+    if !config.statistics.active() {
+        return;
+    }
+
+    if let Some(counters) = opt_greylist_counters {
+        statistics
+            .ipv4
+            .greylisted_prefixes
+            .fetch_add(counters.ipv4, Ordering::Relaxed);
+        statistics
+            .ipv6
+            .greylisted_prefixes
+            .fetch_add(counters.ipv6, Ordering::Relaxed);
+    }
+
+    let Some(counters) = opt_connection_tracking_counters else {
+        return;
+    };
+
+    for (statistics, counters) in [
+        (&statistics.ipv4, counters.ipv4),
+        (&statistics.ipv6, counters.ipv6),
+    ] {
+        statistics
+            .tracked_connection_hits
+            .fetch_add(counters.hits, Ordering::Relaxed);
+        statistics
+            .tracked_connection_misses
+            .fetch_add(counters.misses, Ordering::Relaxed);
+        statistics
+            .tracked_connection_evictions
+            .fetch_add(counters.evictions, Ordering::Relaxed);
+    }
+}
+
+/// Reason for not handling a received packet as a regular request
+#[derive(Clone, Copy, Debug)]
+enum RejectionReason {
+    SourcePortZero,
+    ParseError,
+    InvalidConnectionId,
+    Greylisted,
+    Banned,
+    Overloaded,
+}
+
+fn record_rejected_request(
+    config: &Config,
+    statistics: &IpVersionStatistics<SocketWorkerStatistics>,
+    addr: CanonicalSocketAddr,
+    reason: RejectionReason,
+) {
+    if !config.statistics.active() {
+        return;
+    }
+
+    let statistics = if addr.is_ipv4() {
+        &statistics.ipv4
+    } else {
+        &statistics.ipv6
+    };
+
+    let counter = match reason {
+        RejectionReason::SourcePortZero => &statistics.rejected_source_port_zero,
+        RejectionReason::ParseError => &statistics.rejected_parse_error,
+        RejectionReason::InvalidConnectionId => &statistics.rejected_invalid_connection_id,
+        RejectionReason::Greylisted => &statistics.rejected_greylisted,
+        RejectionReason::Banned => &statistics.rejected_banned,
+        RejectionReason::Overloaded => &statistics.rejected_overloaded,
+    };
+
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Request type for access list hit counters
+#[derive(Clone, Copy, Debug)]
+enum AccessListHitType {
+    Announce,
+    Scrape,
+}
+
+fn record_access_list_hits(
+    config: &Config,
+    statistics: &IpVersionStatistics<SocketWorkerStatistics>,
+    addr: CanonicalSocketAddr,
+    hit_type: AccessListHitType,
+    hits: usize,
+) {
+    if !config.statistics.active() || hits == 0 {
+        return;
+    }
+
+    let statistics = if addr.is_ipv4() {
+        &statistics.ipv4
+    } else {
+        &statistics.ipv6
+    };
+
+    let counter = match hit_type {
+        AccessListHitType::Announce => &statistics.access_list_hits_announce,
+        AccessListHitType::Scrape => &statistics.access_list_hits_scrape,
+    };
+
+    counter.fetch_add(hits, Ordering::Relaxed);
+}
+
+/// Returns true and records rejection if network prefix of source address
+/// is greylisted
+fn reject_if_greylisted(
+    config: &Config,
+    statistics: &IpVersionStatistics<SocketWorkerStatistics>,
+    validator: &ConnectionValidator,
+    src: CanonicalSocketAddr,
+) -> bool {
+    let greylisted = validator.is_greylisted(src);
+
+    if greylisted {
+        ::log::debug!("Ignored request from greylisted network prefix");
+
+        record_rejected_request(config, statistics, src, RejectionReason::Greylisted);
+    }
+
+    greylisted
+}
+
+/// Returns true and records rejection if source address is banned
+fn reject_if_banned(
+    config: &Config,
+    statistics: &IpVersionStatistics<SocketWorkerStatistics>,
+    ban_list_cache: &mut BanListCache,
+    src: CanonicalSocketAddr,
+) -> bool {
+    let banned = config.ban_list.enabled && ban_list_cache.load().is_banned(src);
+
+    if banned {
+        ::log::debug!("Ignored request from banned address");
+
+        record_rejected_request(config, statistics, src, RejectionReason::Banned);
+    }
+
+    banned
+}
+
+/// Count announce request towards `protocol.max_announces_per_second` and
+/// return error response if it exceeds it
+fn shed_announce_if_overloaded(
+    config: &Config,
+    statistics: &IpVersionStatistics<SocketWorkerStatistics>,
+    state: &State,
+    src: CanonicalSocketAddr,
+    transaction_id: TransactionId,
+) -> Option<Response> {
+    if !state.announce_ceiling.active()
+        || state
+            .announce_ceiling
+            .try_acquire(state.server_start_instant.seconds_elapsed())
+    {
+        return None;
+    }
+
+    ::log::debug!("Shed announce request since announce ceiling was reached");
+
+    record_rejected_request(config, statistics, src, RejectionReason::Overloaded);
+
+    Some(Response::Error(ErrorResponse {
+        transaction_id,
+        message: "Tracker overloaded, try again later".into(),
+    }))
+}
+
+/// Record announce or scrape request with invalid connection ID and return
+/// error response, if configured to send one
+fn handle_invalid_connection_id(
+    config: &Config,
+    statistics: &IpVersionStatistics<SocketWorkerStatistics>,
+    src: CanonicalSocketAddr,
+    transaction_id: TransactionId,
+    request_len: usize,
+) -> Option<Response> {
+    record_rejected_request(
+        config,
+        statistics,
+        src,
+        RejectionReason::InvalidConnectionId,
+    );
+
+    if !config.protocol.respond_to_invalid_connection_ids {
+        return None;
+    }
+
+    unverified_error_response(
+        config,
+        transaction_id,
+        "Connection ID invalid or expired",
+        request_len,
+    )
+}
+
+/// Create error response to request from unverified source address,
+/// truncating the message so that the response isn't larger than allowed by
+/// `protocol.max_unverified_response_amplification`
+///
+/// Returns None if not even an empty message fits.
+fn unverified_error_response(
+    config: &Config,
+    transaction_id: TransactionId,
+    message: &'static str,
+    request_len: usize,
+) -> Option<Response> {
+    // Action and transaction ID
+    const HEADER_LEN: usize = 8;
+
+    let factor = config.protocol.max_unverified_response_amplification;
+
+    let message = if factor == 0 {
+        message
+    } else {
+        let max_message_len = request_len.saturating_mul(factor).checked_sub(HEADER_LEN)?;
+
+        let mut len = message.len().min(max_message_len);
+
+        while !message.is_char_boundary(len) {
+            len -= 1;
+        }
+
+        &message[..len]
+    };
+
+    Some(Response::Error(ErrorResponse {
+        transaction_id,
+        message: message.into(),
+    }))
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn run_socket_worker(
     config: Config,
     shared_state: State,
@@ -45,6 +302,8 @@ pub fn run_socket_worker(
     statistics_sender: Sender<StatisticsMessage>,
     validator: ConnectionValidator,
     priv_dropper: PrivilegeDropper,
+    packet_dumper: PacketDumper,
+    stop: Arc<AtomicBool>,
 ) -> anyhow::Result<()> {
     #[cfg(all(target_os = "linux", feature = "io-uring"))]
     if config.network.use_io_uring {
@@ -57,6 +316,7 @@ pub fn run_socket_worker(
             statistics_sender,
             validator,
             priv_dropper,
+            stop,
         );
     }
 
@@ -67,6 +327,8 @@ pub fn run_socket_worker(
         statistics_sender,
         validator,
         priv_dropper,
+        packet_dumper,
+        stop,
     )
 }
 
@@ -74,13 +336,25 @@ fn create_socket(
     config: &Config,
     priv_dropper: PrivilegeDropper,
 ) -> anyhow::Result<::std::net::UdpSocket> {
+    if config.network.systemd_socket_activation {
+        let socket = Socket::from(systemd::duplicate_listen_fd("udp")?);
+
+        socket
+            .set_nonblocking(true)
+            .with_context(|| "socket: set nonblocking")?;
+
+        priv_dropper.after_socket_creation()?;
+
+        return Ok(socket.into());
+    }
+
     let socket = if config.network.address.is_ipv4() {
         Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?
     } else {
         Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?
     };
 
-    if config.network.only_ipv6 {
+    if config.network.only_ipv6 && config.network.address.is_ipv6() {
         socket
             .set_only_v6(true)
             .with_context(|| "socket: set only ipv6")?;
@@ -94,16 +368,21 @@ fn create_socket(
         .set_nonblocking(true)
         .with_context(|| "socket: set nonblocking")?;
 
-    let recv_buffer_size = config.network.socket_recv_buffer_size;
-
-    if recv_buffer_size != 0 {
-        if let Err(err) = socket.set_recv_buffer_size(recv_buffer_size) {
-            ::log::error!(
-                "socket: failed setting recv buffer to {}: {:?}",
-                recv_buffer_size,
-                err
-            );
-        }
+    if config.network.socket_recv_buffer_size != 0 {
+        set_buffer_size(
+            "recv",
+            config.network.socket_recv_buffer_size,
+            |size| socket.set_recv_buffer_size(size),
+            || socket.recv_buffer_size(),
+        );
+    }
+    if config.network.socket_send_buffer_size != 0 {
+        set_buffer_size(
+            "send",
+            config.network.socket_send_buffer_size,
+            |size| socket.set_send_buffer_size(size),
+            || socket.send_buffer_size(),
+        );
     }
 
     socket
@@ -114,3 +393,49 @@ fn create_socket(
 
     Ok(socket.into())
 }
+
+/// Set socket buffer size, log achieved size and warn if the kernel clamped it
+fn set_buffer_size(
+    name: &str,
+    size: usize,
+    set: impl FnOnce(usize) -> ::std::io::Result<()>,
+    get: impl FnOnce() -> ::std::io::Result<usize>,
+) {
+    if let Err(err) = set(size) {
+        ::log::error!(
+            "socket: failed setting {} buffer to {}: {:?}",
+            name,
+            size,
+            err
+        );
+
+        return;
+    }
+
+    // Linux reports double the set value to account for bookkeeping overhead
+    let achieved = get().map(|reported| {
+        if cfg!(target_os = "linux") {
+            reported / 2
+        } else {
+            reported
+        }
+    });
+
+    match achieved {
+        Ok(achieved) if achieved < size => {
+            ::log::warn!(
+                "socket: {} buffer size was clamped to {} instead of configured {}. Consider raising system limits (e.g., net.core.{}_max on Linux)",
+                name,
+                achieved,
+                size,
+                if name == "recv" { "rmem" } else { "wmem" },
+            );
+        }
+        Ok(achieved) => {
+            ::log::info!("socket: {} buffer size is {}", name, achieved);
+        }
+        Err(err) => {
+            ::log::error!("socket: failed getting {} buffer size: {:?}", name, err);
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use aquatic_common::CanonicalSocketAddr;
+use aquatic_udp_protocol::Response;
+
+/// Bounded buffer of responses that couldn't be sent because the socket
+/// send buffer was full, for retrying them once
+///
+/// When the buffer is full, announce responses replace other queued
+/// responses, so that peers can keep announcing while the tracker is
+/// overloaded.
+pub struct ResendBuffer {
+    max_len: usize,
+    responses: Vec<(CanonicalSocketAddr, Response)>,
+    num_announce_responses: usize,
+}
+
+impl ResendBuffer {
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            max_len,
+            responses: Vec::new(),
+            num_announce_responses: 0,
+        }
+    }
+
+    /// Add response to buffer. Returns response that was dropped to stay
+    /// within the size limit, if any.
+    #[must_use]
+    pub fn push(
+        &mut self,
+        addr: CanonicalSocketAddr,
+        response: Response,
+    ) -> Option<(CanonicalSocketAddr, Response)> {
+        let is_announce = is_announce_response(&response);
+
+        if self.responses.len() < self.max_len {
+            self.insert(addr, response, is_announce);
+
+            return None;
+        }
+
+        if !is_announce || self.num_announce_responses == self.responses.len() {
+            return Some((addr, response));
+        }
+
+        // Prefer dropping scrape responses, since they are generally the
+        // least important and the most expensive to send
+        let index = self
+            .responses
+            .iter()
+            .rposition(|(_, response)| matches!(response, Response::Scrape(_)))
+            .or_else(|| {
+                self.responses
+                    .iter()
+                    .rposition(|(_, response)| !is_announce_response(response))
+            })?;
+
+        let dropped = self.responses.swap_remove(index);
+
+        self.insert(addr, response, is_announce);
+
+        Some(dropped)
+    }
+
+    pub fn drain(&mut self) -> impl Iterator<Item = (CanonicalSocketAddr, Response)> + '_ {
+        self.num_announce_responses = 0;
+
+        self.responses.drain(..)
+    }
+
+    fn insert(&mut self, addr: CanonicalSocketAddr, response: Response, is_announce: bool) {
+        if is_announce {
+            self.num_announce_responses += 1;
+        }
+
+        self.responses.push((addr, response));
+    }
+}
+
+fn is_announce_response(response: &Response) -> bool {
+    matches!(
+        response,
+        Response::AnnounceIpv4(_) | Response::AnnounceIpv6(_)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use aquatic_udp_protocol::*;
+
+    use super::*;
+
+    fn addr() -> CanonicalSocketAddr {
+        CanonicalSocketAddr::new(SocketAddr::from((Ipv4Addr::LOCALHOST, 1000)))
+    }
+
+    fn announce_response(transaction_id: i32) -> Response {
+        Response::AnnounceIpv4(AnnounceResponse {
+            fixed: AnnounceResponseFixedData {
+                transaction_id: TransactionId::new(transaction_id),
+                announce_interval: AnnounceInterval::new(0),
+                leechers: NumberOfPeers::new(0),
+                seeders: NumberOfPeers::new(0),
+            },
+            peers: Vec::new(),
+        })
+    }
+
+    fn scrape_response(transaction_id: i32) -> Response {
+        Response::Scrape(ScrapeResponse {
+            transaction_id: TransactionId::new(transaction_id),
+            torrent_stats: Vec::new(),
+        })
+    }
+
+    fn error_response(transaction_id: i32) -> Response {
+        Response::Error(ErrorResponse {
+            transaction_id: TransactionId::new(transaction_id),
+            message: "error".into(),
+        })
+    }
+
+    #[test]
+    fn test_resend_buffer_prioritizes_announce_responses() {
+        let mut buffer = ResendBuffer::new(3);
+
+        assert!(buffer.push(addr(), error_response(1)).is_none());
+        assert!(buffer.push(addr(), scrape_response(2)).is_none());
+        assert!(buffer.push(addr(), announce_response(3)).is_none());
+
+        // Other responses are dropped when buffer is full
+        assert_eq!(
+            buffer.push(addr(), scrape_response(4)),
+            Some((addr(), scrape_response(4)))
+        );
+
+        // Announce responses replace scrape responses first, then others
+        assert_eq!(
+            buffer.push(addr(), announce_response(5)),
+            Some((addr(), scrape_response(2)))
+        );
+        assert_eq!(
+            buffer.push(addr(), announce_response(6)),
+            Some((addr(), error_response(1)))
+        );
+        assert_eq!(
+            buffer.push(addr(), announce_response(7)),
+            Some((addr(), announce_response(7)))
+        );
+
+        let mut transaction_ids = buffer
+            .drain()
+            .map(|(_, response)| match response {
+                Response::AnnounceIpv4(response) => response.fixed.transaction_id.0.get(),
+                _ => panic!("not an announce response"),
+            })
+            .collect::<Vec<_>>();
+
+        transaction_ids.sort_unstable();
+
+        assert_eq!(transaction_ids, vec![3, 5, 6]);
+
+        assert!(buffer.push(addr(), scrape_response(8)).is_none());
+    }
+}
@@ -7,18 +7,20 @@ use std::collections::VecDeque;
 use std::net::UdpSocket;
 use std::ops::DerefMut;
 use std::os::fd::AsRawFd;
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 use anyhow::Context;
 use aquatic_common::access_list::AccessListCache;
+use aquatic_common::ban_list::BanListCache;
 use crossbeam_channel::Sender;
 use io_uring::opcode::Timeout;
 use io_uring::types::{Fixed, Timespec};
 use io_uring::{IoUring, Probe};
 
 use aquatic_common::{
-    access_list::create_access_list_cache, privileges::PrivilegeDropper, CanonicalSocketAddr,
-    ValidUntil,
+    access_list::create_access_list_cache, ban_list::create_ban_list_cache,
+    privileges::PrivilegeDropper, CanonicalSocketAddr, ValidUntil,
 };
 use aquatic_udp_protocol::*;
 use rand::rngs::SmallRng;
@@ -32,7 +34,12 @@ use self::recv_helper::RecvHelper;
 use self::send_buffers::{ResponseType, SendBuffers};
 
 use super::validator::ConnectionValidator;
-use super::{create_socket, EXTRA_PACKET_SIZE_IPV4, EXTRA_PACKET_SIZE_IPV6};
+use super::{
+    create_socket, handle_invalid_connection_id, record_access_list_hits, record_rejected_request,
+    reject_if_banned, reject_if_greylisted, shed_announce_if_overloaded,
+    update_validator_statistics, AccessListHitType, RejectionReason, EXTRA_PACKET_SIZE_IPV4,
+    EXTRA_PACKET_SIZE_IPV6,
+};
 
 /// Size of each request buffer
 ///
@@ -55,7 +62,7 @@ const SOCKET_IDENTIFIER: Fixed = Fixed(0);
 
 thread_local! {
     /// Store IoUring instance here so that it can be accessed in BufRing::drop
-    pub static CURRENT_RING: CurrentRing = CurrentRing(RefCell::new(None));
+    pub static CURRENT_RING: CurrentRing = const { CurrentRing(RefCell::new(None)) };
 }
 
 pub struct CurrentRing(RefCell<Option<IoUring>>);
@@ -79,6 +86,7 @@ pub struct SocketWorker {
     statistics: CachePaddedArc<IpVersionStatistics<SocketWorkerStatistics>>,
     statistics_sender: Sender<StatisticsMessage>,
     access_list_cache: AccessListCache,
+    ban_list_cache: BanListCache,
     validator: ConnectionValidator,
     #[allow(dead_code)]
     socket: UdpSocket,
@@ -86,11 +94,13 @@ pub struct SocketWorker {
     send_buffers: SendBuffers,
     recv_helper: RecvHelper,
     local_responses: VecDeque<(CanonicalSocketAddr, Response)>,
+    response_peer_buffers: ResponsePeerBuffers,
     resubmittable_sqe_buf: Vec<io_uring::squeue::Entry>,
     recv_sqe: io_uring::squeue::Entry,
     pulse_timeout_sqe: io_uring::squeue::Entry,
     peer_valid_until: ValidUntil,
     rng: SmallRng,
+    stop: Arc<AtomicBool>,
 }
 
 impl SocketWorker {
@@ -101,6 +111,7 @@ impl SocketWorker {
         statistics_sender: Sender<StatisticsMessage>,
         validator: ConnectionValidator,
         priv_dropper: PrivilegeDropper,
+        stop: Arc<AtomicBool>,
     ) -> anyhow::Result<()> {
         let ring_entries = config.network.ring_size.next_power_of_two();
         // Try to fill up the ring with send requests
@@ -108,6 +119,7 @@ impl SocketWorker {
 
         let socket = create_socket(&config, priv_dropper).expect("create socket");
         let access_list_cache = create_access_list_cache(&shared_state.access_list);
+        let ban_list_cache = create_ban_list_cache(&shared_state.ban_list);
 
         let send_buffers = SendBuffers::new(&config, send_buffer_entries as usize);
         let recv_helper = RecvHelper::new(&config);
@@ -158,9 +170,11 @@ impl SocketWorker {
             statistics_sender,
             validator,
             access_list_cache,
+            ban_list_cache,
             send_buffers,
             recv_helper,
             local_responses: Default::default(),
+            response_peer_buffers: Default::default(),
             buf_ring,
             recv_sqe,
             pulse_timeout_sqe,
@@ -168,6 +182,7 @@ impl SocketWorker {
             socket,
             peer_valid_until,
             rng: SmallRng::from_entropy(),
+            stop,
         };
 
         CurrentRing::with(|ring| worker.run_inner(ring));
@@ -192,13 +207,15 @@ impl SocketWorker {
             // Enqueue local responses
             for _ in 0..sq_space {
                 if let Some((addr, response)) = self.local_responses.pop_front() {
-                    match self.send_buffers.prepare_entry(response, addr) {
+                    match self.send_buffers.prepare_entry(&response, addr) {
                         Ok(entry) => {
                             unsafe { ring.submission().push(&entry).unwrap() };
 
                             num_send_added += 1;
+
+                            self.response_peer_buffers.reclaim(response);
                         }
-                        Err(send_buffers::Error::NoBuffers(response)) => {
+                        Err(send_buffers::Error::NoBuffers) => {
                             self.local_responses.push_front((addr, response));
 
                             break;
@@ -224,6 +241,12 @@ impl SocketWorker {
             }
 
             self.send_buffers.reset_likely_next_free_index();
+
+            // Only quit once the kernel no longer references any send buffer.
+            // The pulse timeout makes sure this is checked regularly.
+            if self.stop.load(Ordering::Relaxed) && self.send_buffers.all_free() {
+                return;
+            }
         }
     }
 
@@ -241,6 +264,8 @@ impl SocketWorker {
             USER_DATA_PULSE_TIMEOUT => {
                 self.validator.update_elapsed();
 
+                update_validator_statistics(&self.config, &mut self.validator, &self.statistics);
+
                 self.peer_valid_until = ValidUntil::new(
                     self.shared_state.server_start_instant,
                     self.config.cleaning.max_peer_age,
@@ -328,8 +353,17 @@ impl SocketWorker {
             }
         };
 
-        match self.recv_helper.parse(buffer.as_slice()) {
-            Ok((request, addr)) => {
+        let reject_if_banned = |addr| {
+            reject_if_banned(
+                &self.config,
+                &self.statistics,
+                &mut self.ban_list_cache,
+                addr,
+            )
+        };
+
+        match self.recv_helper.parse(buffer.as_slice(), reject_if_banned) {
+            Ok((request, addr, request_len)) => {
                 if self.config.statistics.active() {
                     let (statistics, extra_bytes) = if addr.is_ipv4() {
                         (&self.statistics.ipv4, EXTRA_PACKET_SIZE_IPV4)
@@ -340,10 +374,23 @@ impl SocketWorker {
                     statistics
                         .bytes_received
                         .fetch_add(buffer.len() + extra_bytes, Ordering::Relaxed);
+                }
+
+                if reject_if_greylisted(&self.config, &self.statistics, &self.validator, addr) {
+                    return None;
+                }
+
+                if self.config.statistics.active() {
+                    let statistics = if addr.is_ipv4() {
+                        &self.statistics.ipv4
+                    } else {
+                        &self.statistics.ipv6
+                    };
+
                     statistics.requests.fetch_add(1, Ordering::Relaxed);
                 }
 
-                return self.handle_request(request, addr);
+                return self.handle_request(request, addr, request_len);
             }
             Err(self::recv_helper::Error::RequestParseError(err, addr)) => {
                 if self.config.statistics.active() {
@@ -360,6 +407,17 @@ impl SocketWorker {
                     }
                 }
 
+                if reject_if_greylisted(&self.config, &self.statistics, &self.validator, addr) {
+                    return None;
+                }
+
+                record_rejected_request(
+                    &self.config,
+                    &self.statistics,
+                    addr,
+                    RejectionReason::ParseError,
+                );
+
                 match err {
                     RequestParseError::Sendable {
                         connection_id,
@@ -369,6 +427,8 @@ impl SocketWorker {
                         ::log::debug!("Couldn't parse request from {:?}: {}", addr, err);
 
                         if self.validator.connection_id_valid(addr, connection_id) {
+                            self.validator.record_failure(addr);
+
                             let response = ErrorResponse {
                                 transaction_id,
                                 message: err.into(),
@@ -379,12 +439,22 @@ impl SocketWorker {
                     }
                     RequestParseError::Unsendable { err } => {
                         ::log::debug!("Couldn't parse request from {:?}: {}", addr, err);
+
+                        self.validator.record_failure(addr);
                     }
                 }
             }
-            Err(self::recv_helper::Error::InvalidSocketAddress) => {
+            Err(self::recv_helper::Error::InvalidSocketAddress(addr)) => {
                 ::log::debug!("Ignored request claiming to be from port 0");
+
+                record_rejected_request(
+                    &self.config,
+                    &self.statistics,
+                    addr,
+                    RejectionReason::SourcePortZero,
+                );
             }
+            Err(self::recv_helper::Error::Banned) => (),
             Err(self::recv_helper::Error::RecvMsgParseError) => {
                 ::log::error!("RecvMsgOut::parse failed");
             }
@@ -400,6 +470,7 @@ impl SocketWorker {
         &mut self,
         request: Request,
         src: CanonicalSocketAddr,
+        request_len: usize,
     ) -> Option<(CanonicalSocketAddr, Response)> {
         let access_list_mode = self.config.access_list.mode;
 
@@ -410,18 +481,46 @@ impl SocketWorker {
                     transaction_id: request.transaction_id,
                 });
 
-                return Some((src, response));
+                Some((src, response))
             }
             Request::Announce(request) => {
                 if self
                     .validator
                     .connection_id_valid(src, request.connection_id)
                 {
-                    if self
+                    if !self.config.client_filter.allows(&request.peer_id.0) {
+                        let response = Response::Error(ErrorResponse {
+                            transaction_id: request.transaction_id,
+                            message: self.config.client_filter.failure_reason().into(),
+                        });
+
+                        return Some((src, response));
+                    }
+
+                    let check = self
                         .access_list_cache
                         .load()
-                        .allows(access_list_mode, &request.info_hash.0)
-                    {
+                        .check(access_list_mode, &request.info_hash.0);
+
+                    record_access_list_hits(
+                        &self.config,
+                        &self.statistics,
+                        src,
+                        AccessListHitType::Announce,
+                        usize::from(check.hit),
+                    );
+
+                    if check.allowed {
+                        if let Some(response) = shed_announce_if_overloaded(
+                            &self.config,
+                            &self.statistics,
+                            &self.shared_state,
+                            src,
+                            request.transaction_id,
+                        ) {
+                            return Some((src, response));
+                        }
+
                         let response = self.shared_state.torrent_maps.announce(
                             &self.config,
                             &self.statistics_sender,
@@ -429,6 +528,7 @@ impl SocketWorker {
                             &request,
                             src,
                             self.peer_valid_until,
+                            &mut self.response_peer_buffers,
                         );
 
                         return Some((src, response));
@@ -441,21 +541,55 @@ impl SocketWorker {
                         return Some((src, response));
                     }
                 }
+
+                handle_invalid_connection_id(
+                    &self.config,
+                    &self.statistics,
+                    src,
+                    request.transaction_id,
+                    request_len,
+                )
+                .map(|response| (src, response))
             }
             Request::Scrape(request) => {
                 if self
                     .validator
                     .connection_id_valid(src, request.connection_id)
                 {
-                    let response =
-                        Response::Scrape(self.shared_state.torrent_maps.scrape(request, src));
+                    let access_list = self.access_list_cache.load();
+                    let mut hits = 0;
+
+                    let allows = |info_hash: &InfoHash| {
+                        let check = access_list.check(access_list_mode, &info_hash.0);
+
+                        hits += usize::from(check.hit);
+
+                        check.allowed
+                    };
 
-                    return Some((src, response));
+                    let response = self.shared_state.torrent_maps.scrape(request, src, allows);
+
+                    record_access_list_hits(
+                        &self.config,
+                        &self.statistics,
+                        src,
+                        AccessListHitType::Scrape,
+                        hits,
+                    );
+
+                    return Some((src, Response::Scrape(response)));
                 }
+
+                handle_invalid_connection_id(
+                    &self.config,
+                    &self.statistics,
+                    src,
+                    request.transaction_id,
+                    request_len,
+                )
+                .map(|response| (src, response))
             }
         }
-
-        None
     }
 }
 
@@ -16,7 +16,9 @@ pub enum Error {
     RecvMsgParseError,
     RecvMsgTruncated,
     RequestParseError(RequestParseError, CanonicalSocketAddr),
-    InvalidSocketAddress,
+    InvalidSocketAddress(CanonicalSocketAddr),
+    /// Source address is banned. Rejection has already been recorded.
+    Banned,
 }
 
 pub struct RecvHelper {
@@ -89,7 +91,15 @@ impl RecvHelper {
             .user_data(USER_DATA_RECV)
     }
 
-    pub fn parse(&self, buffer: &[u8]) -> Result<(Request, CanonicalSocketAddr), Error> {
+    /// Returns request, source address and payload length
+    ///
+    /// Payload isn't parsed if `reject_if_banned` returns true for source
+    /// address.
+    pub fn parse(
+        &self,
+        buffer: &[u8],
+        reject_if_banned: impl FnOnce(CanonicalSocketAddr) -> bool,
+    ) -> Result<(Request, CanonicalSocketAddr, usize), Error> {
         let (msg, addr) = if self.socket_is_ipv4 {
             // Safe as long as kernel only reads from the pointer and doesn't
             // write to it. I think this is the case.
@@ -132,15 +142,22 @@ impl RecvHelper {
             (msg, addr)
         };
 
-        if addr.port() == 0 {
-            return Err(Error::InvalidSocketAddress);
+        let port = addr.port();
+        let addr = CanonicalSocketAddr::new(addr);
+
+        if reject_if_banned(addr) {
+            return Err(Error::Banned);
         }
 
-        let addr = CanonicalSocketAddr::new(addr);
+        if port == 0 {
+            return Err(Error::InvalidSocketAddress(addr));
+        }
+
+        let payload = msg.payload_data();
 
-        let request = Request::parse_bytes(msg.payload_data(), self.max_scrape_torrents)
+        let request = Request::parse_bytes(payload, self.max_scrape_torrents)
             .map_err(|err| Error::RequestParseError(err, addr))?;
 
-        Ok((request, addr))
+        Ok((request, addr, payload.len()))
     }
 }
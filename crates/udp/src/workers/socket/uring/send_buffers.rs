@@ -14,7 +14,7 @@ use crate::config::Config;
 use super::{RESPONSE_BUF_LEN, SOCKET_IDENTIFIER};
 
 pub enum Error {
-    NoBuffers(Response),
+    NoBuffers,
     SerializationFailed(std::io::Error),
 }
 
@@ -53,6 +53,12 @@ impl SendBuffers {
         self.buffers[index].0.free = true;
     }
 
+    /// Returns true if no buffer is referenced by in-flight io_uring queue
+    /// entries
+    pub fn all_free(&self) -> bool {
+        self.buffers.iter().all(|(meta, _)| meta.free)
+    }
+
     /// Call after going through completion queue
     pub fn reset_likely_next_free_index(&mut self) {
         self.likely_next_free_index = 0;
@@ -60,13 +66,13 @@ impl SendBuffers {
 
     pub fn prepare_entry(
         &mut self,
-        response: Response,
+        response: &Response,
         addr: CanonicalSocketAddr,
     ) -> Result<io_uring::squeue::Entry, Error> {
         let index = if let Some(index) = self.next_free_index() {
             index
         } else {
-            return Err(Error::NoBuffers(response));
+            return Err(Error::NoBuffers);
         };
 
         let (buffer_metadata, buffer) = self.buffers.get_mut(index).unwrap();
@@ -164,7 +170,7 @@ impl SendBuffer {
 
     fn prepare_entry(
         &mut self,
-        response: Response,
+        response: &Response,
         addr: CanonicalSocketAddr,
         socket_is_ipv4: bool,
         metadata: &mut SendBufferMetadata,
@@ -200,7 +206,7 @@ impl SendBuffer {
             Ok(()) => {
                 self.iovec.iov_len = cursor.position() as usize;
 
-                metadata.response_type = ResponseType::from_response(&response);
+                metadata.response_type = ResponseType::from_response(response);
 
                 Ok(SendMsg::new(SOCKET_IDENTIFIER, addr_of_mut!(self.msghdr)).build())
             }
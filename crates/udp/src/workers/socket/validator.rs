@@ -1,15 +1,22 @@
 use std::net::IpAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
 use std::time::Instant;
 
 use anyhow::Context;
 use constant_time_eq::constant_time_eq;
 use getrandom::getrandom;
+use parking_lot::Mutex;
 
-use aquatic_common::CanonicalSocketAddr;
+use aquatic_common::greylist::Greylist;
+use aquatic_common::{CanonicalSocketAddr, SecondsSinceServerStart};
 use aquatic_udp_protocol::ConnectionId;
 
+use crate::common::IpVersionStatistics;
 use crate::config::Config;
 
+use super::connection_map::{ConnectionMap, ConnectionTrackingCounters};
+
 /// HMAC (BLAKE3) based ConnectionId creator and validator
 ///
 /// Method update_elapsed must be called at least once a minute.
@@ -22,6 +29,19 @@ use crate::config::Config;
 /// `max_connection_age` seconds, a short duration to get value for the
 /// bandwidth spent brute forcing it.
 ///
+/// The key is replaced every `connection_id_key_rotation_interval` seconds.
+/// Since the creation time included in ConnectionIds determines which key was
+/// used, Ids created with the previous key remain valid until they expire.
+///
+/// If `protocol.track_connections` is set, created ConnectionIds are also
+/// stored together with client addresses (including ports) in a bounded map,
+/// and only ConnectionIds found in it are considered valid.
+///
+/// If `greylist.max_failures` is set, invalid ConnectionIds and other
+/// failures reported with `record_failure` are counted per network prefix of
+/// the client, and prefixes with too many of them are greylisted. Callers
+/// should check `is_greylisted` before handling requests.
+///
 /// Structure of created ConnectionID (bytes making up inner i64):
 /// - &[0..4]: ConnectionId creation time as number of seconds after
 ///   ConnectionValidator instance was created, encoded as u32 bytes. A u32
@@ -33,40 +53,80 @@ use crate::config::Config;
 pub struct ConnectionValidator {
     start_time: Instant,
     max_connection_age: u64,
-    keyed_hasher: blake3::Hasher,
+    key_rotation_interval: u32,
+    keys: Keys,
+    shared_keys: Arc<SharedKeys>,
     seconds_since_start: u32,
+    opt_connection_map: Option<ConnectionMap>,
+    opt_greylist: Option<Greylist>,
+    /// Number of times prefixes were greylisted since counters were taken
+    greylist_counters: IpVersionStatistics<usize>,
 }
 
 impl ConnectionValidator {
     /// Create new instance. Must be created once and cloned if used in several
     /// threads.
     pub fn new(config: &Config) -> anyhow::Result<Self> {
-        let mut key = [0; 32];
-
-        getrandom(&mut key)
-            .with_context(|| "Couldn't get random bytes for ConnectionValidator key")?;
+        let keys = Keys {
+            epoch: 0,
+            current: create_keyed_hasher()?,
+            previous: None,
+        };
 
-        let keyed_hasher = blake3::Hasher::new_keyed(&key);
+        let shared_keys = Arc::new(SharedKeys {
+            epoch: AtomicU32::new(0),
+            keys: Mutex::new(keys.clone()),
+        });
 
         Ok(Self {
-            keyed_hasher,
             start_time: Instant::now(),
             max_connection_age: config.cleaning.max_connection_age.into(),
+            key_rotation_interval: config.protocol.connection_id_key_rotation_interval,
+            keys,
+            shared_keys,
             seconds_since_start: 0,
+            opt_connection_map: config
+                .protocol
+                .track_connections
+                .then(|| ConnectionMap::new(config.protocol.max_tracked_connections)),
+            opt_greylist: config
+                .greylist
+                .active()
+                .then(|| Greylist::new(&config.greylist)),
+            greylist_counters: Default::default(),
         })
     }
 
     pub fn create_connection_id(&mut self, source_addr: CanonicalSocketAddr) -> ConnectionId {
-        let elapsed = (self.seconds_since_start).to_ne_bytes();
+        // Keys might have been rotated by another thread with a more recent
+        // view of time. In that case, use start of current key epoch as
+        // creation time.
+        let elapsed = self
+            .seconds_since_start
+            .max(self.keys.epoch * self.key_rotation_interval)
+            .to_ne_bytes();
 
-        let hash = self.hash(elapsed, source_addr.get().ip());
+        let hash = hash(&mut self.keys.current, elapsed, source_addr.get().ip());
 
         let mut connection_id_bytes = [0u8; 8];
 
         connection_id_bytes[..4].copy_from_slice(&elapsed);
         connection_id_bytes[4..].copy_from_slice(&hash);
 
-        ConnectionId::new(i64::from_ne_bytes(connection_id_bytes))
+        let connection_id = ConnectionId::new(i64::from_ne_bytes(connection_id_bytes));
+
+        if let Some(connection_map) = self.opt_connection_map.as_mut() {
+            let expires = u64::from(u32::from_ne_bytes(elapsed)) + self.max_connection_age;
+
+            connection_map.insert(
+                connection_id,
+                source_addr,
+                expires.try_into().unwrap_or(u32::MAX),
+                self.seconds_since_start,
+            );
+        }
+
+        connection_id
     }
 
     pub fn connection_id_valid(
@@ -74,16 +134,78 @@ impl ConnectionValidator {
         source_addr: CanonicalSocketAddr,
         connection_id: ConnectionId,
     ) -> bool {
+        let valid = self.connection_id_valid_inner(source_addr, connection_id);
+
+        if !valid {
+            self.record_failure(source_addr);
+        }
+
+        valid
+    }
+
+    /// Count failure (e.g., a malformed request) towards greylisting network
+    /// prefix of address, if greylisting is enabled
+    pub fn record_failure(&mut self, source_addr: CanonicalSocketAddr) {
+        let Some(greylist) = self.opt_greylist.as_mut() else {
+            return;
+        };
+
+        let now = SecondsSinceServerStart::new(self.seconds_since_start);
+
+        if greylist.record_failure(source_addr, now) {
+            if source_addr.is_ipv4() {
+                self.greylist_counters.ipv4 += 1;
+            } else {
+                self.greylist_counters.ipv6 += 1;
+            }
+        }
+    }
+
+    /// Returns true if network prefix of address is greylisted
+    pub fn is_greylisted(&self, source_addr: CanonicalSocketAddr) -> bool {
+        let now = SecondsSinceServerStart::new(self.seconds_since_start);
+
+        self.opt_greylist
+            .as_ref()
+            .is_some_and(|greylist| greylist.contains(source_addr, now))
+    }
+
+    fn connection_id_valid_inner(
+        &mut self,
+        source_addr: CanonicalSocketAddr,
+        connection_id: ConnectionId,
+    ) -> bool {
+        if let Some(connection_map) = self.opt_connection_map.as_mut() {
+            return connection_map.contains(connection_id, source_addr, self.seconds_since_start);
+        }
+
         let bytes = connection_id.0.get().to_ne_bytes();
-        let (elapsed, hash) = bytes.split_at(4);
+        let (elapsed, hash_bytes) = bytes.split_at(4);
         let elapsed: [u8; 4] = elapsed.try_into().unwrap();
 
-        if !constant_time_eq(hash, &self.hash(elapsed, source_addr.get().ip())) {
+        let client_elapsed = u32::from_ne_bytes(elapsed);
+        let client_epoch = self.epoch(client_elapsed);
+
+        // Another thread might have rotated keys after this one last did
+        if client_epoch > self.keys.epoch
+            && client_epoch <= self.shared_keys.epoch.load(Ordering::Acquire)
+        {
+            self.keys = self.shared_keys.keys.lock().clone();
+        }
+
+        let Some(keyed_hasher) = self.keys.get_mut(client_epoch) else {
+            return false;
+        };
+
+        if !constant_time_eq(
+            hash_bytes,
+            &hash(keyed_hasher, elapsed, source_addr.get().ip()),
+        ) {
             return false;
         }
 
         let seconds_since_start = self.seconds_since_start as u64;
-        let client_elapsed = u64::from(u32::from_ne_bytes(elapsed));
+        let client_elapsed = u64::from(client_elapsed);
         let client_expiration_time = client_elapsed + self.max_connection_age;
 
         // In addition to checking if the client connection is expired,
@@ -98,26 +220,123 @@ impl ConnectionValidator {
     }
 
     pub fn update_elapsed(&mut self) {
-        self.seconds_since_start = self.start_time.elapsed().as_secs() as u32;
+        self.set_seconds_since_start(self.start_time.elapsed().as_secs() as u32);
+
+        if let Some(connection_map) = self.opt_connection_map.as_mut() {
+            connection_map.clean(self.seconds_since_start);
+        }
+    }
+
+    /// Connection tracking counters since last call, if connections are
+    /// tracked
+    pub fn take_connection_tracking_counters(
+        &mut self,
+    ) -> Option<IpVersionStatistics<ConnectionTrackingCounters>> {
+        self.opt_connection_map
+            .as_mut()
+            .map(ConnectionMap::take_counters)
+    }
+
+    /// Number of times network prefixes were greylisted since last call, if
+    /// greylisting is enabled
+    pub fn take_greylist_counters(&mut self) -> Option<IpVersionStatistics<usize>> {
+        self.opt_greylist
+            .is_some()
+            .then(|| ::std::mem::take(&mut self.greylist_counters))
+    }
+
+    fn set_seconds_since_start(&mut self, seconds_since_start: u32) {
+        self.seconds_since_start = seconds_since_start;
+
+        let epoch = self.epoch(seconds_since_start);
+
+        if epoch > self.keys.epoch {
+            let mut shared_keys = self.shared_keys.keys.lock();
+
+            if epoch > shared_keys.epoch {
+                shared_keys.rotate(epoch);
+
+                self.shared_keys.epoch.store(epoch, Ordering::Release);
+            }
+
+            self.keys = shared_keys.clone();
+        }
+    }
+
+    /// Index of key rotation interval that time falls into
+    fn epoch(&self, seconds_since_start: u32) -> u32 {
+        seconds_since_start
+            .checked_div(self.key_rotation_interval)
+            .unwrap_or(0)
     }
+}
+
+struct SharedKeys {
+    /// Copy of `keys.epoch` that can be checked without locking
+    epoch: AtomicU32,
+    keys: Mutex<Keys>,
+}
 
-    fn hash(&mut self, elapsed: [u8; 4], ip_addr: IpAddr) -> [u8; 4] {
-        self.keyed_hasher.update(&elapsed);
+#[derive(Clone)]
+struct Keys {
+    epoch: u32,
+    current: blake3::Hasher,
+    /// Key of previous epoch, if it directly precedes current one
+    previous: Option<blake3::Hasher>,
+}
 
-        match ip_addr {
-            IpAddr::V4(ip) => self.keyed_hasher.update(&ip.octets()),
-            IpAddr::V6(ip) => self.keyed_hasher.update(&ip.octets()),
+impl Keys {
+    fn rotate(&mut self, epoch: u32) {
+        let current = match create_keyed_hasher() {
+            Ok(keyed_hasher) => keyed_hasher,
+            Err(err) => {
+                ::log::error!("{:#}. Keeping previous key", err);
+
+                self.current.clone()
+            }
         };
 
-        let mut hash = [0u8; 4];
+        let previous = ::std::mem::replace(&mut self.current, current);
 
-        self.keyed_hasher.finalize_xof().fill(&mut hash);
-        self.keyed_hasher.reset();
+        self.previous = (epoch == self.epoch + 1).then_some(previous);
+        self.epoch = epoch;
+    }
 
-        hash
+    fn get_mut(&mut self, epoch: u32) -> Option<&mut blake3::Hasher> {
+        if epoch == self.epoch {
+            Some(&mut self.current)
+        } else if epoch + 1 == self.epoch {
+            self.previous.as_mut()
+        } else {
+            None
+        }
     }
 }
 
+fn create_keyed_hasher() -> anyhow::Result<blake3::Hasher> {
+    let mut key = [0; 32];
+
+    getrandom(&mut key).with_context(|| "Couldn't get random bytes for ConnectionValidator key")?;
+
+    Ok(blake3::Hasher::new_keyed(&key))
+}
+
+fn hash(keyed_hasher: &mut blake3::Hasher, elapsed: [u8; 4], ip_addr: IpAddr) -> [u8; 4] {
+    keyed_hasher.update(&elapsed);
+
+    match ip_addr {
+        IpAddr::V4(ip) => keyed_hasher.update(&ip.octets()),
+        IpAddr::V6(ip) => keyed_hasher.update(&ip.octets()),
+    };
+
+    let mut hash = [0u8; 4];
+
+    keyed_hasher.finalize_xof().fill(&mut hash);
+    keyed_hasher.reset();
+
+    hash
+}
+
 #[cfg(test)]
 mod tests {
     use std::net::SocketAddr;
@@ -162,4 +381,107 @@ mod tests {
             quickcheck::TestResult::from_bool(original_valid)
         }
     }
+
+    #[test]
+    fn test_connection_validator_key_rotation() {
+        let addr = CanonicalSocketAddr::new(SocketAddr::new(IpAddr::from([1, 2, 3, 4]), 0));
+
+        let mut config = Config::default();
+
+        config.cleaning.max_connection_age = 60;
+        config.protocol.connection_id_key_rotation_interval = 100;
+
+        let mut validator = ConnectionValidator::new(&config).unwrap();
+        let mut other_validator = validator.clone();
+
+        validator.set_seconds_since_start(90);
+        other_validator.set_seconds_since_start(90);
+
+        let old_connection_id = validator.create_connection_id(addr);
+
+        // Rotate keys
+        validator.set_seconds_since_start(110);
+
+        assert_eq!(validator.keys.epoch, 1);
+        assert!(validator.connection_id_valid(addr, old_connection_id));
+
+        let new_connection_id = validator.create_connection_id(addr);
+
+        // Other thread hasn't rotated keys yet, but picks up new ones
+        assert!(other_validator.connection_id_valid(addr, new_connection_id));
+        assert!(other_validator.connection_id_valid(addr, old_connection_id));
+
+        let other_connection_id = other_validator.create_connection_id(addr);
+
+        assert!(validator.connection_id_valid(addr, other_connection_id));
+
+        // Only the key of the directly preceding epoch is kept
+        validator.set_seconds_since_start(200);
+
+        assert!(!validator.connection_id_valid(addr, old_connection_id));
+
+        validator.set_seconds_since_start(310);
+
+        assert!(!validator.connection_id_valid(addr, new_connection_id));
+    }
+
+    #[test]
+    fn test_connection_validator_tracked_connections() {
+        let addr = CanonicalSocketAddr::new(SocketAddr::new(IpAddr::from([1, 2, 3, 4]), 1));
+        let other_port_addr =
+            CanonicalSocketAddr::new(SocketAddr::new(IpAddr::from([1, 2, 3, 4]), 2));
+
+        let mut config = Config::default();
+
+        config.protocol.track_connections = true;
+
+        let mut validator = ConnectionValidator::new(&config).unwrap();
+
+        let connection_id = validator.create_connection_id(addr);
+
+        assert!(validator.connection_id_valid(addr, connection_id));
+        assert!(!validator.connection_id_valid(other_port_addr, connection_id));
+
+        let counters = validator.take_connection_tracking_counters().unwrap();
+
+        assert_eq!(counters.ipv4.hits, 1);
+        assert_eq!(counters.ipv4.misses, 1);
+
+        validator.set_seconds_since_start(config.cleaning.max_connection_age);
+
+        assert!(!validator.connection_id_valid(addr, connection_id));
+    }
+    #[test]
+    fn test_connection_validator_greylist() {
+        let addr = CanonicalSocketAddr::new(SocketAddr::new(IpAddr::from([1, 2, 3, 4]), 1));
+        let same_prefix_addr =
+            CanonicalSocketAddr::new(SocketAddr::new(IpAddr::from([1, 2, 3, 5]), 1));
+
+        let mut config = Config::default();
+
+        config.greylist.max_failures = 2;
+
+        let mut validator = ConnectionValidator::new(&config).unwrap();
+
+        let connection_id = validator.create_connection_id(addr);
+
+        assert!(validator.connection_id_valid(addr, connection_id));
+        assert!(!validator.connection_id_valid(same_prefix_addr, connection_id));
+        assert!(!validator.is_greylisted(addr));
+
+        assert!(!validator.connection_id_valid(same_prefix_addr, ConnectionId::new(0)));
+        assert!(validator.is_greylisted(addr));
+
+        assert_eq!(validator.take_greylist_counters().unwrap().ipv4, 1);
+
+        validator.set_seconds_since_start(config.greylist.duration);
+
+        assert!(!validator.is_greylisted(addr));
+
+        // Other failures, such as malformed requests, are counted too
+        validator.record_failure(addr);
+        validator.record_failure(addr);
+
+        assert!(validator.is_greylisted(same_prefix_addr));
+    }
 }
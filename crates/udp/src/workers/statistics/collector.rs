@@ -42,10 +42,7 @@ impl StatisticsCollector {
         self.last_complete_histogram = PeerHistogramStatistics::new(histogram);
     }
 
-    pub fn collect_from_shared(
-        &mut self,
-        #[cfg(feature = "prometheus")] config: &Config,
-    ) -> CollectedStatistics {
+    pub fn collect_from_shared(&mut self, config: &Config) -> CollectedStatistics {
         let mut requests = 0;
         let mut responses_connect: usize = 0;
         let mut responses_announce: usize = 0;
@@ -53,6 +50,18 @@ impl StatisticsCollector {
         let mut responses_error: usize = 0;
         let mut bytes_received: usize = 0;
         let mut bytes_sent: usize = 0;
+        let mut dropped_responses: usize = 0;
+        let mut rejected_source_port_zero: usize = 0;
+        let mut rejected_parse_error: usize = 0;
+        let mut rejected_invalid_connection_id: usize = 0;
+        let mut rejected_greylisted: usize = 0;
+        let mut rejected_banned: usize = 0;
+        let mut rejected_overloaded: usize = 0;
+        let mut greylisted_prefixes: usize = 0;
+        let mut tracked_connection_hits: usize = 0;
+        let mut tracked_connection_misses: usize = 0;
+        let mut tracked_connection_evictions: usize = 0;
+        let mut access_list_hits: usize = 0;
 
         #[cfg(feature = "prometheus")]
         let ip_version_prometheus_str = self.ip_version.prometheus_str();
@@ -175,6 +184,150 @@ impl StatisticsCollector {
                     .increment(n.try_into().unwrap());
                 }
             }
+            for (response_type, counter) in [
+                ("connect", &statistics.dropped_responses_connect),
+                ("announce", &statistics.dropped_responses_announce),
+                ("scrape", &statistics.dropped_responses_scrape),
+                ("error", &statistics.dropped_responses_error),
+            ] {
+                let n = counter.fetch_and(0, Ordering::Relaxed);
+
+                dropped_responses += n;
+
+                #[cfg(feature = "prometheus")]
+                if config.statistics.run_prometheus_endpoint {
+                    ::metrics::counter!(
+                        "aquatic_dropped_responses_total",
+                        "type" => response_type,
+                        "ip_version" => ip_version_prometheus_str,
+                        "worker_index" => i.to_string(),
+                    )
+                    .increment(n.try_into().unwrap());
+                }
+                #[cfg(not(feature = "prometheus"))]
+                let _ = response_type;
+            }
+            for (reason, counter, sum) in [
+                (
+                    "source_port_zero",
+                    &statistics.rejected_source_port_zero,
+                    &mut rejected_source_port_zero,
+                ),
+                (
+                    "parse_error",
+                    &statistics.rejected_parse_error,
+                    &mut rejected_parse_error,
+                ),
+                (
+                    "invalid_connection_id",
+                    &statistics.rejected_invalid_connection_id,
+                    &mut rejected_invalid_connection_id,
+                ),
+                (
+                    "greylisted",
+                    &statistics.rejected_greylisted,
+                    &mut rejected_greylisted,
+                ),
+                ("banned", &statistics.rejected_banned, &mut rejected_banned),
+                (
+                    "overloaded",
+                    &statistics.rejected_overloaded,
+                    &mut rejected_overloaded,
+                ),
+            ] {
+                let n = counter.fetch_and(0, Ordering::Relaxed);
+
+                *sum += n;
+
+                #[cfg(feature = "prometheus")]
+                if config.statistics.run_prometheus_endpoint {
+                    ::metrics::counter!(
+                        "aquatic_rejected_requests_total",
+                        "reason" => reason,
+                        "ip_version" => ip_version_prometheus_str,
+                        "worker_index" => i.to_string(),
+                    )
+                    .increment(n.try_into().unwrap());
+                }
+                #[cfg(not(feature = "prometheus"))]
+                let _ = reason;
+            }
+            if config.greylist.active() {
+                let n = statistics
+                    .greylisted_prefixes
+                    .fetch_and(0, Ordering::Relaxed);
+
+                greylisted_prefixes += n;
+
+                #[cfg(feature = "prometheus")]
+                if config.statistics.run_prometheus_endpoint {
+                    ::metrics::counter!(
+                        "aquatic_greylisted_prefixes_total",
+                        "ip_version" => ip_version_prometheus_str,
+                        "worker_index" => i.to_string(),
+                    )
+                    .increment(n.try_into().unwrap());
+                }
+            }
+            if config.protocol.track_connections {
+                let hits = statistics
+                    .tracked_connection_hits
+                    .fetch_and(0, Ordering::Relaxed);
+                let misses = statistics
+                    .tracked_connection_misses
+                    .fetch_and(0, Ordering::Relaxed);
+                let evictions = statistics
+                    .tracked_connection_evictions
+                    .fetch_and(0, Ordering::Relaxed);
+
+                tracked_connection_hits += hits;
+                tracked_connection_misses += misses;
+                tracked_connection_evictions += evictions;
+
+                #[cfg(feature = "prometheus")]
+                if config.statistics.run_prometheus_endpoint {
+                    for (result, n) in [("hit", hits), ("miss", misses)] {
+                        ::metrics::counter!(
+                            "aquatic_tracked_connection_lookups_total",
+                            "result" => result,
+                            "ip_version" => ip_version_prometheus_str,
+                            "worker_index" => i.to_string(),
+                        )
+                        .increment(n.try_into().unwrap());
+                    }
+
+                    ::metrics::counter!(
+                        "aquatic_tracked_connection_evictions_total",
+                        "ip_version" => ip_version_prometheus_str,
+                        "worker_index" => i.to_string(),
+                    )
+                    .increment(evictions.try_into().unwrap());
+                }
+            }
+            if config.access_list.mode.is_on() {
+                for (request_type, counter) in [
+                    ("announce", &statistics.access_list_hits_announce),
+                    ("scrape", &statistics.access_list_hits_scrape),
+                ] {
+                    let n = counter.fetch_and(0, Ordering::Relaxed);
+
+                    access_list_hits += n;
+
+                    #[cfg(feature = "prometheus")]
+                    if config.statistics.run_prometheus_endpoint {
+                        ::metrics::counter!(
+                            "aquatic_access_list_hits_total",
+                            "list" => config.access_list.mode.as_str(),
+                            "type" => request_type,
+                            "ip_version" => ip_version_prometheus_str,
+                            "worker_index" => i.to_string(),
+                        )
+                        .increment(n.try_into().unwrap());
+                    }
+                    #[cfg(not(feature = "prometheus"))]
+                    let _ = request_type;
+                }
+            }
         }
 
         let swarm_statistics = &self.statistics.swarm.by_ip_version(self.ip_version);
@@ -250,8 +403,36 @@ impl StatisticsCollector {
                 .to_formatted_string(&Locale::en),
             responses_per_second_error: (responses_per_second_error as usize)
                 .to_formatted_string(&Locale::en),
+            dropped_responses_per_second: ((dropped_responses as f64 / elapsed) as usize)
+                .to_formatted_string(&Locale::en),
             rx_mbits: format!("{:.2}", bytes_received_per_second * 8.0 / 1_000_000.0),
             tx_mbits: format!("{:.2}", bytes_sent_per_second * 8.0 / 1_000_000.0),
+            rejected_per_second_source_port_zero: ((rejected_source_port_zero as f64 / elapsed)
+                as usize)
+                .to_formatted_string(&Locale::en),
+            rejected_per_second_parse_error: ((rejected_parse_error as f64 / elapsed) as usize)
+                .to_formatted_string(&Locale::en),
+            rejected_per_second_invalid_connection_id: ((rejected_invalid_connection_id as f64
+                / elapsed) as usize)
+                .to_formatted_string(&Locale::en),
+            rejected_per_second_greylisted: ((rejected_greylisted as f64 / elapsed) as usize)
+                .to_formatted_string(&Locale::en),
+            greylisted_prefixes: greylisted_prefixes.to_formatted_string(&Locale::en),
+            rejected_per_second_banned: ((rejected_banned as f64 / elapsed) as usize)
+                .to_formatted_string(&Locale::en),
+            rejected_per_second_overloaded: ((rejected_overloaded as f64 / elapsed) as usize)
+                .to_formatted_string(&Locale::en),
+            tracked_connection_hits_per_second: ((tracked_connection_hits as f64 / elapsed)
+                as usize)
+                .to_formatted_string(&Locale::en),
+            tracked_connection_misses_per_second: ((tracked_connection_misses as f64 / elapsed)
+                as usize)
+                .to_formatted_string(&Locale::en),
+            tracked_connection_evictions_per_second: ((tracked_connection_evictions as f64
+                / elapsed) as usize)
+                .to_formatted_string(&Locale::en),
+            access_list_hits_per_second: ((access_list_hits as f64 / elapsed) as usize)
+                .to_formatted_string(&Locale::en),
             num_torrents: num_torrents.to_formatted_string(&Locale::en),
             num_peers: num_peers.to_formatted_string(&Locale::en),
             peer_histogram: self.last_complete_histogram.clone(),
@@ -267,8 +448,24 @@ pub struct CollectedStatistics {
     pub responses_per_second_announce: String,
     pub responses_per_second_scrape: String,
     pub responses_per_second_error: String,
+    pub dropped_responses_per_second: String,
     pub rx_mbits: String,
     pub tx_mbits: String,
+    pub rejected_per_second_source_port_zero: String,
+    pub rejected_per_second_parse_error: String,
+    pub rejected_per_second_invalid_connection_id: String,
+    pub rejected_per_second_greylisted: String,
+    /// Number of times network prefixes were greylisted since last collection
+    pub greylisted_prefixes: String,
+    pub rejected_per_second_banned: String,
+    /// Announce requests answered with error responses because
+    /// `protocol.max_announces_per_second` was reached
+    pub rejected_per_second_overloaded: String,
+    pub tracked_connection_hits_per_second: String,
+    pub tracked_connection_misses_per_second: String,
+    pub tracked_connection_evictions_per_second: String,
+    /// Info hashes in announce and scrape requests found in access list
+    pub access_list_hits_per_second: String,
     pub num_torrents: String,
     pub num_peers: String,
     pub peer_histogram: PeerHistogramStatistics,
@@ -34,6 +34,10 @@ struct TemplateData {
     ipv4_active: bool,
     ipv6_active: bool,
     extended_active: bool,
+    greylist_active: bool,
+    access_list_active: bool,
+    ban_list_active: bool,
+    announce_ceiling_active: bool,
     ipv4: CollectedStatistics,
     ipv6: CollectedStatistics,
     last_updated: String,
@@ -105,14 +109,8 @@ pub fn run_statistics_worker(
             }
         }
 
-        let statistics_ipv4 = ipv4_collector.collect_from_shared(
-            #[cfg(feature = "prometheus")]
-            &config,
-        );
-        let statistics_ipv6 = ipv6_collector.collect_from_shared(
-            #[cfg(feature = "prometheus")]
-            &config,
-        );
+        let statistics_ipv4 = ipv4_collector.collect_from_shared(&config);
+        let statistics_ipv6 = ipv6_collector.collect_from_shared(&config);
 
         let peer_clients = if process_peer_client_data {
             let mut clients: IndexMap<PeerClient, usize> = IndexMap::default();
@@ -194,6 +192,10 @@ pub fn run_statistics_worker(
                 ipv4_active: config.network.ipv4_active(),
                 ipv6_active: config.network.ipv6_active(),
                 extended_active: config.statistics.torrent_peer_histograms,
+                greylist_active: config.greylist.active(),
+                access_list_active: config.access_list.mode.is_on(),
+                ban_list_active: config.ban_list.enabled,
+                announce_ceiling_active: config.protocol.max_announces_per_second != 0,
                 ipv4: statistics_ipv4,
                 ipv6: statistics_ipv6,
                 last_updated: OffsetDateTime::now_utc()
@@ -249,6 +251,67 @@ fn print_to_stdout(config: &Config, statistics: &CollectedStatistics) {
         "    error:         {:>10}",
         statistics.responses_per_second_error
     );
+    println!(
+        "    dropped:       {:>10}",
+        statistics.dropped_responses_per_second
+    );
+    println!("  rejected requests/second");
+    println!(
+        "    port zero:     {:>10}",
+        statistics.rejected_per_second_source_port_zero
+    );
+    println!(
+        "    parse error:   {:>10}",
+        statistics.rejected_per_second_parse_error
+    );
+    println!(
+        "    connection id: {:>10}",
+        statistics.rejected_per_second_invalid_connection_id
+    );
+    if config.greylist.active() {
+        println!(
+            "    greylisted:    {:>10}",
+            statistics.rejected_per_second_greylisted
+        );
+        println!(
+            "  prefixes greylisted: {:>6} (since last report)",
+            statistics.greylisted_prefixes
+        );
+    }
+    if config.ban_list.enabled {
+        println!(
+            "    banned:        {:>10}",
+            statistics.rejected_per_second_banned
+        );
+    }
+    if config.protocol.max_announces_per_second != 0 {
+        println!(
+            "    overloaded:    {:>10}",
+            statistics.rejected_per_second_overloaded
+        );
+    }
+    if config.protocol.track_connections {
+        println!("  tracked connections/second");
+        println!(
+            "    hits:          {:>10}",
+            statistics.tracked_connection_hits_per_second
+        );
+        println!(
+            "    misses:        {:>10}",
+            statistics.tracked_connection_misses_per_second
+        );
+        println!(
+            "    evictions:     {:>10}",
+            statistics.tracked_connection_evictions_per_second
+        );
+    }
+    if config.access_list.mode.is_on() {
+        println!(
+            "  access list hits/second: {:>4} ({} list)",
+            statistics.access_list_hits_per_second,
+            config.access_list.mode.as_str()
+        );
+    }
     println!(
         "  torrents:        {:>10} (updated every {}s)",
         statistics.num_torrents, config.cleaning.torrent_cleaning_interval
@@ -120,6 +120,7 @@ pub fn request_and_response(
             .recv_from(&mut buffer)
             .with_context(|| "recv response")?;
 
-        Response::parse_bytes(&buffer[..bytes_read], true).with_context(|| "parse response")
+        Response::parse_bytes(&buffer[..bytes_read], tracker_addr.is_ipv4())
+            .with_context(|| "parse response")
     }
 }
@@ -0,0 +1,247 @@
+mod common;
+
+use common::*;
+
+use std::{
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    num::NonZeroU16,
+    time::Duration,
+};
+
+use anyhow::Context;
+use aquatic_common::client_filter::ClientFilterMode;
+use aquatic_udp::config::Config;
+use aquatic_udp_protocol::{
+    common::PeerId, AnnounceEvent, AnnounceRequest, ConnectionId, InfoHash, Ipv4AddrBytes,
+    NumberOfBytes, NumberOfPeers, PeerKey, Port, Request, Response, TransactionId,
+};
+
+fn announce_request(connection_id: ConnectionId, peer_port: u16, peers_wanted: i32) -> Request {
+    Request::Announce(AnnounceRequest {
+        connection_id,
+        action_placeholder: Default::default(),
+        transaction_id: TransactionId::new(peers_wanted),
+        info_hash: InfoHash([0; 20]),
+        peer_id: PeerId([1; 20]),
+        bytes_downloaded: NumberOfBytes::new(0),
+        bytes_uploaded: NumberOfBytes::new(0),
+        bytes_left: NumberOfBytes::new(1),
+        event: AnnounceEvent::Started.into(),
+        ip_address: Ipv4AddrBytes([0; 4]),
+        key: PeerKey::new(0),
+        peers_wanted: NumberOfPeers::new(peers_wanted),
+        port: Port::new(NonZeroU16::new(peer_port).unwrap()),
+    })
+}
+
+fn bind_peer_socket(ip: impl Into<std::net::IpAddr>) -> anyhow::Result<UdpSocket> {
+    let socket = UdpSocket::bind(SocketAddr::new(ip.into(), 0))?;
+
+    socket.set_read_timeout(Some(Duration::from_secs(1)))?;
+
+    Ok(socket)
+}
+
+#[test]
+fn test_peers_wanted() -> anyhow::Result<()> {
+    const TRACKER_PORT: u16 = 40_116;
+    const PEER_PORT_START: u16 = 30_100;
+    const MAX_RESPONSE_PEERS: usize = 3;
+
+    let mut config = Config::default();
+
+    config.network.address.set_port(TRACKER_PORT);
+    config.protocol.max_response_peers = MAX_RESPONSE_PEERS;
+
+    run_tracker(config);
+
+    let tracker_addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), TRACKER_PORT);
+
+    for i in 0..5 {
+        let socket = bind_peer_socket(Ipv4Addr::LOCALHOST)?;
+        let connection_id = connect(&socket, tracker_addr).with_context(|| "connect")?;
+
+        announce(
+            &socket,
+            tracker_addr,
+            connection_id,
+            NonZeroU16::new(PEER_PORT_START + i).unwrap(),
+            InfoHash([0; 20]),
+            1,
+            false,
+        )
+        .with_context(|| "announce")?;
+    }
+
+    let socket = bind_peer_socket(Ipv4Addr::LOCALHOST)?;
+    let connection_id = connect(&socket, tracker_addr).with_context(|| "connect")?;
+
+    // Zero and negative values mean that the tracker decides
+    for (peers_wanted, expected_num_peers) in
+        [(-1, MAX_RESPONSE_PEERS), (0, MAX_RESPONSE_PEERS), (2, 2)]
+    {
+        let request = announce_request(connection_id, PEER_PORT_START + 10, peers_wanted);
+
+        let response =
+            request_and_response(&socket, tracker_addr, request).with_context(|| "announce")?;
+
+        let Response::AnnounceIpv4(response) = response else {
+            return Err(anyhow::anyhow!("not announce response: {:?}", response));
+        };
+
+        assert_eq!(
+            response.fixed.transaction_id,
+            TransactionId::new(peers_wanted)
+        );
+        assert_eq!(response.peers.len(), expected_num_peers);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_max_scrape_torrents() -> anyhow::Result<()> {
+    const TRACKER_PORT: u16 = 40_117;
+    const MAX_SCRAPE_TORRENTS: u8 = 5;
+
+    let mut config = Config::default();
+
+    config.network.address.set_port(TRACKER_PORT);
+    config.protocol.max_scrape_torrents = MAX_SCRAPE_TORRENTS;
+
+    run_tracker(config);
+
+    let tracker_addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), TRACKER_PORT);
+
+    let socket = bind_peer_socket(Ipv4Addr::LOCALHOST)?;
+    let connection_id = connect(&socket, tracker_addr).with_context(|| "connect")?;
+
+    announce(
+        &socket,
+        tracker_addr,
+        connection_id,
+        NonZeroU16::new(1).unwrap(),
+        InfoHash([2; 20]),
+        1,
+        true,
+    )
+    .with_context(|| "announce")?;
+
+    // Info hashes beyond the maximum are ignored
+    for num_info_hashes in [1, MAX_SCRAPE_TORRENTS, MAX_SCRAPE_TORRENTS + 3] {
+        let info_hashes = (0..num_info_hashes).map(|i| InfoHash([i; 20])).collect();
+
+        let response =
+            scrape(&socket, tracker_addr, connection_id, info_hashes).with_context(|| "scrape")?;
+
+        assert_eq!(
+            response.torrent_stats.len(),
+            num_info_hashes.min(MAX_SCRAPE_TORRENTS) as usize
+        );
+
+        for (i, stats) in response.torrent_stats.iter().enumerate() {
+            assert_eq!(stats.seeders.0.get(), i32::from(i == 2));
+            assert_eq!(stats.leechers.0.get(), 0);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_ipv6_announce() -> anyhow::Result<()> {
+    const TRACKER_PORT: u16 = 40_118;
+    const PEER_PORT_START: u16 = 30_200;
+
+    let mut config = Config::default();
+
+    config.network.address = SocketAddr::new(Ipv6Addr::LOCALHOST.into(), TRACKER_PORT);
+
+    run_tracker(config);
+
+    let tracker_addr = SocketAddr::new(Ipv6Addr::LOCALHOST.into(), TRACKER_PORT);
+
+    for i in 0..2 {
+        let socket = bind_peer_socket(Ipv6Addr::LOCALHOST)?;
+        let connection_id = connect(&socket, tracker_addr).with_context(|| "connect")?;
+
+        let response = announce(
+            &socket,
+            tracker_addr,
+            connection_id,
+            NonZeroU16::new(PEER_PORT_START + i).unwrap(),
+            InfoHash([0; 20]),
+            10,
+            false,
+        )
+        .with_context(|| "announce")?;
+
+        let Response::AnnounceIpv6(response) = response else {
+            return Err(anyhow::anyhow!(
+                "not IPv6 announce response: {:?}",
+                response
+            ));
+        };
+
+        assert_eq!(response.fixed.leechers.0.get(), i32::from(i));
+
+        if i == 1 {
+            assert_eq!(response.peers.len(), 1);
+            assert_eq!(
+                Ipv6Addr::from(response.peers[0].ip_address),
+                Ipv6Addr::LOCALHOST
+            );
+            assert_eq!(response.peers[0].port.0.get(), PEER_PORT_START);
+        } else {
+            assert!(response.peers.is_empty());
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_client_filter() -> anyhow::Result<()> {
+    const TRACKER_PORT: u16 = 40_119;
+
+    let mut config = Config::default();
+
+    config.network.address.set_port(TRACKER_PORT);
+    config.client_filter.mode = ClientFilterMode::Deny;
+    // Peer id used by announce_request consists of bytes with value 1
+    config.client_filter.peer_id_prefixes = vec!["\u{1}\u{1}".into()];
+
+    run_tracker(config);
+
+    let tracker_addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), TRACKER_PORT);
+
+    let socket = bind_peer_socket(Ipv4Addr::LOCALHOST)?;
+    let connection_id = connect(&socket, tracker_addr).with_context(|| "connect")?;
+
+    let request = announce_request(connection_id, 1, 10);
+
+    let response =
+        request_and_response(&socket, tracker_addr, request).with_context(|| "announce")?;
+
+    let Response::Error(response) = response else {
+        return Err(anyhow::anyhow!("not error response: {:?}", response));
+    };
+
+    assert!(response.message.contains("denylist"));
+
+    // Peers with other peer ids are still served
+    let response = announce(
+        &socket,
+        tracker_addr,
+        connection_id,
+        NonZeroU16::new(1).unwrap(),
+        InfoHash([0; 20]),
+        10,
+        false,
+    )
+    .with_context(|| "announce")?;
+
+    assert!(matches!(response, Response::AnnounceIpv4(_)));
+
+    Ok(())
+}
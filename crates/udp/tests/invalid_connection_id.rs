@@ -13,7 +13,7 @@ use anyhow::Context;
 use aquatic_udp::{common::BUFFER_SIZE, config::Config};
 use aquatic_udp_protocol::{
     common::PeerId, AnnounceEvent, AnnounceRequest, ConnectionId, InfoHash, Ipv4AddrBytes,
-    NumberOfBytes, NumberOfPeers, PeerKey, Port, Request, ScrapeRequest, TransactionId,
+    NumberOfBytes, NumberOfPeers, PeerKey, Port, Request, Response, ScrapeRequest, TransactionId,
 };
 
 #[test]
@@ -67,6 +67,58 @@ fn test_invalid_connection_id() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_invalid_connection_id_error_response() -> anyhow::Result<()> {
+    const TRACKER_PORT: u16 = 40_115;
+
+    let mut config = Config::default();
+
+    config.network.address.set_port(TRACKER_PORT);
+    config.protocol.respond_to_invalid_connection_ids = true;
+
+    run_tracker(config);
+
+    let tracker_addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, TRACKER_PORT));
+    let peer_addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
+
+    let socket = UdpSocket::bind(peer_addr)?;
+
+    socket.set_read_timeout(Some(Duration::from_secs(1)))?;
+
+    let connection_id = connect(&socket, tracker_addr).with_context(|| "connect")?;
+
+    let scrape_request = Request::Scrape(ScrapeRequest {
+        connection_id: ConnectionId(!connection_id.0),
+        transaction_id: TransactionId::new(1),
+        info_hashes: vec![InfoHash([0; 20])],
+    });
+
+    match request_and_response(&socket, tracker_addr, scrape_request)? {
+        Response::Error(response) => {
+            assert_eq!(response.transaction_id, TransactionId::new(1));
+        }
+        response => return Err(anyhow::anyhow!("not error response: {:?}", response)),
+    }
+
+    // Response to scrape request without info hashes is truncated to the
+    // size of the request, so that traffic isn't amplified
+    let scrape_request = Request::Scrape(ScrapeRequest {
+        connection_id: ConnectionId(!connection_id.0),
+        transaction_id: TransactionId::new(2),
+        info_hashes: Vec::new(),
+    });
+
+    match request_and_response(&socket, tracker_addr, scrape_request)? {
+        Response::Error(response) => {
+            assert_eq!(response.transaction_id, TransactionId::new(2));
+            assert_eq!(response.message, "Connecti");
+        }
+        response => return Err(anyhow::anyhow!("not error response: {:?}", response)),
+    }
+
+    Ok(())
+}
+
 fn no_response(
     socket: &UdpSocket,
     tracker_addr: SocketAddr,
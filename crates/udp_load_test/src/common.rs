@@ -2,6 +2,7 @@ use std::sync::{atomic::AtomicUsize, Arc};
 
 use aquatic_common::IndexMap;
 use aquatic_udp_protocol::*;
+use hdrhistogram::Histogram;
 
 #[derive(Clone)]
 pub struct LoadTestState {
@@ -29,4 +30,6 @@ pub struct Peer {
 
 pub enum StatisticsMessage {
     ResponsesPerInfoHash(IndexMap<usize, u64>),
+    /// Announce and scrape response latencies in microseconds
+    ResponseLatencies(Histogram<u64>),
 }
@@ -25,7 +25,8 @@ pub struct Config {
     ///
     /// 0 = include whole run
     pub summarize_last: usize,
-    /// Display extra statistics
+    /// Display extra statistics, such as announce and scrape response
+    /// latency percentiles
     pub extra_statistics: bool,
     pub network: NetworkConfig,
     pub requests: RequestConfig,
@@ -101,6 +101,7 @@ fn monitor_statistics(
     let mut report_avg_announce: Vec<f64> = Vec::new();
     let mut report_avg_scrape: Vec<f64> = Vec::new();
     let mut report_avg_error: Vec<f64> = Vec::new();
+    let mut report_latencies: Vec<Histogram<u64>> = Vec::new();
 
     const INTERVAL: u64 = 5;
 
@@ -114,6 +115,7 @@ fn monitor_statistics(
 
         let mut opt_responses_per_info_hash: Option<IndexMap<usize, u64>> =
             config.extra_statistics.then_some(Default::default());
+        let mut latencies = Histogram::<u64>::new(2).unwrap();
 
         for message in statistics_receiver.try_iter() {
             match message {
@@ -124,6 +126,9 @@ fn monitor_statistics(
                         }
                     }
                 }
+                StatisticsMessage::ResponseLatencies(data) => {
+                    latencies.add(data).unwrap();
+                }
             }
         }
 
@@ -184,6 +189,12 @@ fn monitor_statistics(
             }
         }
 
+        if !latencies.is_empty() {
+            print_latencies(&latencies);
+        }
+
+        report_latencies.push(latencies);
+
         let time_elapsed = start_time.elapsed();
 
         if config.duration != 0 && time_elapsed >= duration {
@@ -198,6 +209,7 @@ fn monitor_statistics(
         report_avg_announce = report_avg_announce.split_off(split_at);
         report_avg_scrape = report_avg_scrape.split_off(split_at);
         report_avg_error = report_avg_error.split_off(split_at);
+        report_latencies = report_latencies.split_off(split_at);
     }
 
     let len = report_avg_connect.len() as f64;
@@ -226,11 +238,30 @@ fn monitor_statistics(
     println!("  - Announce responses: {:.2}", avg_announce);
     println!("  - Scrape responses:   {:.2}", avg_scrape);
     println!("  - Error responses:    {:.2}", avg_error);
+
+    let mut latencies = Histogram::<u64>::new(2).unwrap();
+
+    for histogram in report_latencies {
+        latencies.add(histogram).unwrap();
+    }
+
+    if !latencies.is_empty() {
+        print_latencies(&latencies);
+    }
+
     println!();
     println!("Config: {:#?}", config);
     println!();
 }
 
+fn print_latencies(histogram: &Histogram<u64>) {
+    println!("Announce and scrape response latency (microseconds):");
+
+    for p in PERCENTILES {
+        println!("  - p{}: {}", p, histogram.value_at_percentile(*p));
+    }
+}
+
 fn fetch_and_reset(atomic_usize: &AtomicUsize) -> f64 {
     atomic_usize.fetch_and(0, Ordering::Relaxed) as f64
 }
@@ -1,10 +1,11 @@
 use std::io::{Cursor, ErrorKind};
 use std::net::{SocketAddr, UdpSocket};
 use std::sync::atomic::Ordering;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use aquatic_common::IndexMap;
 use crossbeam_channel::Sender;
+use hdrhistogram::Histogram;
 use rand::Rng;
 use rand::{prelude::SmallRng, SeedableRng};
 use rand_distr::{Distribution, WeightedIndex};
@@ -18,6 +19,21 @@ use crate::StatisticsMessage;
 
 const MAX_PACKET_SIZE: usize = 8192;
 
+/// Maximum number of announce and scrape requests to measure response
+/// latency for at once
+const LATENCY_PROBE_SLOTS: u32 = 1024;
+/// Transaction IDs of requests that latency is measured for start at this
+/// value. Transaction ID u32::MAX is used when acquiring connection IDs.
+const LATENCY_PROBE_TRANSACTION_ID_START: u32 = u32::MAX - LATENCY_PROBE_SLOTS;
+/// Reuse latency probe slot if no response was received within this time
+const LATENCY_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy)]
+struct LatencyProbe {
+    sent_at: Instant,
+    peer_index: usize,
+}
+
 pub struct Worker {
     config: Config,
     shared_state: LoadTestState,
@@ -30,6 +46,10 @@ pub struct Worker {
     statistics: LocalStatistics,
     statistics_sender: Sender<StatisticsMessage>,
     announce_responses_per_info_hash: IndexMap<usize, u64>,
+    /// Empty unless extra statistics are enabled
+    latency_probes: Box<[Option<LatencyProbe>]>,
+    next_latency_probe: usize,
+    response_latencies: Histogram<u64>,
 }
 
 impl Worker {
@@ -50,6 +70,11 @@ impl Worker {
         let rng = SmallRng::seed_from_u64(0xc3aa8be617b3acce);
         let statistics = LocalStatistics::default();
         let request_type_dist = RequestTypeDist::new(&config).unwrap();
+        let latency_probes = if config.extra_statistics {
+            vec![None; LATENCY_PROBE_SLOTS as usize].into_boxed_slice()
+        } else {
+            Default::default()
+        };
 
         let mut instance = Self {
             config,
@@ -63,6 +88,9 @@ impl Worker {
             statistics,
             statistics_sender,
             announce_responses_per_info_hash: Default::default(),
+            latency_probes,
+            next_latency_probe: 0,
+            response_latencies: Histogram::new(2).unwrap(),
         };
 
         instance.run_inner();
@@ -207,6 +235,7 @@ impl Worker {
     }
 
     fn send_announce_request(&mut self, connection_ids: &[ConnectionId], peer_index: usize) {
+        let transaction_id = self.create_transaction_id(peer_index);
         let peer = self.peers.get(peer_index).unwrap();
 
         let (event, bytes_left) = {
@@ -220,9 +249,6 @@ impl Worker {
             }
         };
 
-        let transaction_id =
-            TransactionId::new(i32::from_ne_bytes((peer_index as u32).to_ne_bytes()));
-
         let request = AnnounceRequest {
             connection_id: connection_ids[peer.socket_index as usize],
             action_placeholder: Default::default(),
@@ -256,11 +282,9 @@ impl Worker {
     }
 
     fn send_scrape_request(&mut self, connection_ids: &[ConnectionId], peer_index: usize) {
+        let transaction_id = self.create_transaction_id(peer_index);
         let peer = self.peers.get(peer_index).unwrap();
 
-        let transaction_id =
-            TransactionId::new(i32::from_ne_bytes((peer_index as u32).to_ne_bytes()));
-
         let mut info_hashes = Vec::with_capacity(peer.scrape_info_hash_indices.len());
 
         for i in peer.scrape_info_hash_indices.iter() {
@@ -298,8 +322,7 @@ impl Worker {
                 self.statistics.responses_announce += 1;
                 self.statistics.response_peers += r.peers.len();
 
-                let peer_index =
-                    u32::from_ne_bytes(r.fixed.transaction_id.0.get().to_ne_bytes()) as usize;
+                let peer_index = self.response_peer_index(r.fixed.transaction_id);
 
                 if let Some(peer) = self.peers.get(peer_index) {
                     *self
@@ -312,8 +335,7 @@ impl Worker {
                 self.statistics.responses_announce += 1;
                 self.statistics.response_peers += r.peers.len();
 
-                let peer_index =
-                    u32::from_ne_bytes(r.fixed.transaction_id.0.get().to_ne_bytes()) as usize;
+                let peer_index = self.response_peer_index(r.fixed.transaction_id);
 
                 if let Some(peer) = self.peers.get(peer_index) {
                     *self
@@ -322,8 +344,10 @@ impl Worker {
                         .or_default() += 1;
                 }
             }
-            Response::Scrape(_) => {
+            Response::Scrape(r) => {
                 self.statistics.responses_scrape += 1;
+
+                self.response_peer_index(r.transaction_id);
             }
             Response::Error(_) => {
                 self.statistics.responses_error += 1;
@@ -331,6 +355,54 @@ impl Worker {
         }
     }
 
+    /// Create transaction ID for announce or scrape request, using a free
+    /// latency probe slot if available
+    fn create_transaction_id(&mut self, peer_index: usize) -> TransactionId {
+        let transaction_id = match self.latency_probes.get_mut(self.next_latency_probe) {
+            Some(slot)
+                if slot.map_or(true, |probe| {
+                    probe.sent_at.elapsed() >= LATENCY_PROBE_TIMEOUT
+                }) =>
+            {
+                *slot = Some(LatencyProbe {
+                    sent_at: Instant::now(),
+                    peer_index,
+                });
+
+                let transaction_id =
+                    LATENCY_PROBE_TRANSACTION_ID_START + self.next_latency_probe as u32;
+
+                self.next_latency_probe = (self.next_latency_probe + 1) % self.latency_probes.len();
+
+                transaction_id
+            }
+            _ => peer_index as u32,
+        };
+
+        TransactionId::new(i32::from_ne_bytes(transaction_id.to_ne_bytes()))
+    }
+
+    /// Get peer index from transaction ID of announce or scrape response,
+    /// recording response latency if it was a latency probe
+    fn response_peer_index(&mut self, transaction_id: TransactionId) -> usize {
+        let transaction_id = u32::from_ne_bytes(transaction_id.0.get().to_ne_bytes());
+
+        let opt_probe = transaction_id
+            .checked_sub(LATENCY_PROBE_TRANSACTION_ID_START)
+            .and_then(|slot_index| self.latency_probes.get_mut(slot_index as usize))
+            .and_then(Option::take);
+
+        if let Some(probe) = opt_probe {
+            let micros = probe.sent_at.elapsed().as_micros() as u64;
+
+            self.response_latencies.record(micros).unwrap();
+
+            probe.peer_index
+        } else {
+            transaction_id as usize
+        }
+    }
+
     fn update_shared_statistics(&mut self) {
         let shared_statistics = &self.shared_state.statistics;
 
@@ -359,6 +431,16 @@ impl Worker {
             );
 
             self.statistics_sender.try_send(message).unwrap();
+
+            if !self.response_latencies.is_empty() {
+                let latencies = self.response_latencies.clone();
+
+                self.response_latencies.reset();
+
+                self.statistics_sender
+                    .try_send(StatisticsMessage::ResponseLatencies(latencies))
+                    .unwrap();
+            }
         }
 
         self.statistics = LocalStatistics::default();
@@ -1,4 +1,5 @@
 use std::io::{self, Cursor, Write};
+use std::mem::size_of;
 
 use byteorder::{NetworkEndian, WriteBytesExt};
 use either::Either;
@@ -11,6 +12,11 @@ use super::common::*;
 
 const PROTOCOL_IDENTIFIER: i64 = 4_497_486_125_440;
 
+/// Announce request option types (BEP 41)
+const OPTION_END_OF_OPTIONS: u8 = 0x0;
+const OPTION_NOP: u8 = 0x1;
+const OPTION_URL_DATA: u8 = 0x2;
+
 #[derive(PartialEq, Eq, Clone, Debug)]
 pub enum Request {
     Connect(ConnectRequest),
@@ -175,6 +181,58 @@ impl AnnounceRequest {
     pub fn write_bytes(&self, bytes: &mut impl Write) -> Result<(), io::Error> {
         bytes.write_all(self.as_bytes())
     }
+
+    /// Write request followed by URLData options (BEP 41) containing
+    /// `url_data`, split into chunks of at most 255 bytes
+    pub fn write_bytes_with_url_data(
+        &self,
+        bytes: &mut impl Write,
+        url_data: &[u8],
+    ) -> Result<(), io::Error> {
+        self.write_bytes(bytes)?;
+
+        for chunk in url_data.chunks(u8::MAX.into()) {
+            bytes.write_all(&[OPTION_URL_DATA, chunk.len() as u8])?;
+            bytes.write_all(chunk)?;
+        }
+
+        Ok(())
+    }
+
+    /// Concatenated contents of URLData options (BEP 41) following announce
+    /// request in `bytes`, which should contain the whole packet
+    ///
+    /// URLData typically contains the path and query string of the tracker
+    /// URL, e.g., an authentication key. NOP and unknown options are
+    /// skipped. Parsing stops at an EndOfOptions option or at a truncated
+    /// option.
+    pub fn parse_url_data(bytes: &[u8]) -> Vec<u8> {
+        let mut url_data = Vec::new();
+        let mut options = bytes.get(size_of::<Self>()..).unwrap_or_default();
+
+        loop {
+            match options {
+                [] | [OPTION_END_OF_OPTIONS, ..] => break,
+                [OPTION_NOP, rest @ ..] => {
+                    options = rest;
+                }
+                [option_type, len, rest @ ..] => {
+                    let Some(data) = rest.get(..usize::from(*len)) else {
+                        break;
+                    };
+
+                    if *option_type == OPTION_URL_DATA {
+                        url_data.extend_from_slice(data);
+                    }
+
+                    options = &rest[data.len()..];
+                }
+                [_] => break,
+            }
+        }
+
+        url_data
+    }
 }
 
 /// Note: Request::from_bytes only creates this struct with value 1
@@ -342,7 +400,7 @@ mod tests {
         let mut buf = Vec::new();
 
         request.clone().write_bytes(&mut buf).unwrap();
-        let r2 = Request::parse_bytes(&buf[..], ::std::u8::MAX).unwrap();
+        let r2 = Request::parse_bytes(&buf[..], u8::MAX).unwrap();
 
         let success = request == r2;
 
@@ -363,6 +421,60 @@ mod tests {
         same_after_conversion(request.into())
     }
 
+    #[quickcheck]
+    fn test_announce_request_url_data_convert_identity(
+        request: AnnounceRequest,
+        url_data: Vec<u8>,
+    ) -> bool {
+        let mut buf = Vec::new();
+
+        request
+            .write_bytes_with_url_data(&mut buf, &url_data)
+            .unwrap();
+
+        let parsed = Request::parse_bytes(&buf[..], u8::MAX).unwrap();
+
+        parsed == Request::Announce(request) && AnnounceRequest::parse_url_data(&buf) == url_data
+    }
+
+    #[test]
+    fn test_announce_request_options_skipped() {
+        let request = AnnounceRequest {
+            action_placeholder: AnnounceActionPlaceholder::default(),
+            port: Port::new(1.try_into().unwrap()),
+            ..AnnounceRequest::new_zeroed()
+        };
+
+        let mut buf = Vec::new();
+
+        request.write_bytes(&mut buf).unwrap();
+
+        assert!(AnnounceRequest::parse_url_data(&buf).is_empty());
+
+        buf.extend_from_slice(&[OPTION_NOP, OPTION_URL_DATA, 2, b'/', b'a']);
+        // Unknown option
+        buf.extend_from_slice(&[0xff, 1, 0]);
+        buf.extend_from_slice(&[OPTION_URL_DATA, 1, b'b']);
+
+        assert_eq!(AnnounceRequest::parse_url_data(&buf), b"/ab");
+
+        // Truncated option
+        let mut truncated = buf.clone();
+
+        truncated.extend_from_slice(&[OPTION_URL_DATA, 2, b'c']);
+
+        assert_eq!(AnnounceRequest::parse_url_data(&truncated), b"/ab");
+
+        // Options after EndOfOptions are ignored
+        buf.extend_from_slice(&[OPTION_END_OF_OPTIONS, OPTION_URL_DATA, 1, b'c']);
+
+        assert_eq!(AnnounceRequest::parse_url_data(&buf), b"/ab");
+        assert_eq!(
+            Request::parse_bytes(&buf, u8::MAX).unwrap(),
+            Request::Announce(request)
+        );
+    }
+
     #[quickcheck]
     fn test_scrape_request_convert_identity(request: ScrapeRequest) -> TestResult {
         if request.info_hashes.is_empty() {
@@ -371,4 +483,29 @@ mod tests {
 
         TestResult::from_bool(same_after_conversion(request.into()))
     }
+
+    #[quickcheck]
+    fn test_scrape_request_info_hashes_truncated(
+        request: ScrapeRequest,
+        max_scrape_torrents: u8,
+    ) -> TestResult {
+        if request.info_hashes.is_empty() {
+            return TestResult::discard();
+        }
+
+        let mut buf = Vec::new();
+
+        Request::from(request.clone())
+            .write_bytes(&mut buf)
+            .unwrap();
+
+        let Request::Scrape(parsed) = Request::parse_bytes(&buf[..], max_scrape_torrents).unwrap()
+        else {
+            return TestResult::failed();
+        };
+
+        let expected_len = request.info_hashes.len().min(max_scrape_torrents.into());
+
+        TestResult::from_bool(parsed.info_hashes[..] == request.info_hashes[..expected_len])
+    }
 }
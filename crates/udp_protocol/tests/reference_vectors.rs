@@ -0,0 +1,419 @@
+//! Encoding and decoding of reference BEP 15 packets
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::num::NonZeroU16;
+
+use aquatic_udp_protocol::common::PeerId;
+use aquatic_udp_protocol::*;
+use pretty_assertions::assert_eq;
+
+const TRANSACTION_ID: &str = "12345678";
+const CONNECTION_ID: &str = "0102030405060708";
+
+fn bytes(hex: &str) -> Vec<u8> {
+    let hex = hex.replace(' ', "");
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+fn request_bytes(request: &Request) -> Vec<u8> {
+    let mut bytes = Vec::new();
+
+    request.write_bytes(&mut bytes).unwrap();
+
+    bytes
+}
+
+fn response_bytes(response: &Response) -> Vec<u8> {
+    let mut bytes = Vec::new();
+
+    response.write_bytes(&mut bytes).unwrap();
+
+    bytes
+}
+
+fn announce_request_hex(peers_wanted: &str, port: &str) -> String {
+    [
+        CONNECTION_ID,
+        "00000001",
+        TRANSACTION_ID,
+        &"aa".repeat(20),
+        &"bb".repeat(20),
+        "0000000000000064",
+        "00000000000003e8",
+        "00000000000000c8",
+        "00000002",
+        "00000000",
+        "0000abcd",
+        peers_wanted,
+        port,
+    ]
+    .join(" ")
+}
+
+fn announce_request(peers_wanted: i32) -> Request {
+    Request::Announce(AnnounceRequest {
+        connection_id: ConnectionId::new(0x0102030405060708),
+        action_placeholder: Default::default(),
+        transaction_id: TransactionId::new(0x12345678),
+        info_hash: InfoHash([0xaa; 20]),
+        peer_id: PeerId([0xbb; 20]),
+        bytes_downloaded: NumberOfBytes::new(100),
+        bytes_left: NumberOfBytes::new(1000),
+        bytes_uploaded: NumberOfBytes::new(200),
+        event: AnnounceEvent::Started.into(),
+        ip_address: Ipv4AddrBytes([0; 4]),
+        key: PeerKey::new(0xabcd),
+        peers_wanted: NumberOfPeers::new(peers_wanted),
+        port: Port::new(NonZeroU16::new(6881).unwrap()),
+    })
+}
+
+fn scrape_request_hex(num_info_hashes: usize) -> String {
+    let info_hashes = (0..num_info_hashes)
+        .map(|i| format!("{:02x}", i).repeat(20))
+        .collect::<String>();
+
+    [CONNECTION_ID, "00000002", TRANSACTION_ID, &info_hashes].join(" ")
+}
+
+#[test]
+fn test_connect_request() {
+    let packet = bytes(&["0000041727101980 00000000", TRANSACTION_ID].join(" "));
+
+    let request = Request::Connect(ConnectRequest {
+        transaction_id: TransactionId::new(0x12345678),
+    });
+
+    assert_eq!(Request::parse_bytes(&packet, 70).unwrap(), request);
+    assert_eq!(request_bytes(&request), packet);
+
+    // Connect requests without protocol identifier are rejected
+    let packet = bytes(&["0000041727101981 00000000", TRANSACTION_ID].join(" "));
+
+    assert!(matches!(
+        Request::parse_bytes(&packet, 70),
+        Err(RequestParseError::Unsendable { .. })
+    ));
+}
+
+#[test]
+fn test_announce_request() {
+    for (peers_wanted, peers_wanted_hex) in [(-1, "ffffffff"), (0, "00000000"), (50, "00000032")] {
+        let packet = bytes(&announce_request_hex(peers_wanted_hex, "1ae1"));
+        let request = announce_request(peers_wanted);
+
+        assert_eq!(packet.len(), 98);
+        assert_eq!(Request::parse_bytes(&packet, 70).unwrap(), request);
+        assert_eq!(request_bytes(&request), packet);
+    }
+}
+
+#[test]
+fn test_announce_request_invalid() {
+    // Port zero
+    let packet = bytes(&announce_request_hex("ffffffff", "0000"));
+
+    assert!(matches!(
+        Request::parse_bytes(&packet, 70),
+        Err(RequestParseError::Sendable { .. })
+    ));
+
+    // Unknown event
+    let mut packet = bytes(&announce_request_hex("ffffffff", "1ae1"));
+
+    packet[83] = 4;
+
+    assert!(matches!(
+        Request::parse_bytes(&packet, 70),
+        Err(RequestParseError::Sendable { .. })
+    ));
+
+    // Truncated
+    let packet = bytes(&announce_request_hex("ffffffff", "1ae1"));
+
+    assert!(matches!(
+        Request::parse_bytes(&packet[..97], 70),
+        Err(RequestParseError::Unsendable { .. })
+    ));
+}
+
+#[test]
+fn test_scrape_request() {
+    for (num_info_hashes, max_scrape_torrents, expected_info_hashes) in
+        [(1, 70, 1), (70, 70, 70), (74, 70, 70), (255, 255, 255)]
+    {
+        let packet = bytes(&scrape_request_hex(num_info_hashes));
+
+        let request = Request::Scrape(ScrapeRequest {
+            connection_id: ConnectionId::new(0x0102030405060708),
+            transaction_id: TransactionId::new(0x12345678),
+            info_hashes: (0..expected_info_hashes)
+                .map(|i| InfoHash([i as u8; 20]))
+                .collect(),
+        });
+
+        assert_eq!(
+            Request::parse_bytes(&packet, max_scrape_torrents).unwrap(),
+            request
+        );
+
+        if num_info_hashes == expected_info_hashes {
+            assert_eq!(request_bytes(&request), packet);
+        }
+    }
+
+    // Partial info hashes are rejected
+    let packet = bytes(&scrape_request_hex(2));
+
+    assert!(matches!(
+        Request::parse_bytes(&packet[..packet.len() - 1], 70),
+        Err(RequestParseError::Sendable { .. })
+    ));
+}
+
+#[test]
+fn test_connect_response() {
+    let packet = bytes(&["00000000", TRANSACTION_ID, CONNECTION_ID].join(" "));
+
+    let response = Response::Connect(ConnectResponse {
+        transaction_id: TransactionId::new(0x12345678),
+        connection_id: ConnectionId::new(0x0102030405060708),
+    });
+
+    assert_eq!(Response::parse_bytes(&packet, true).unwrap(), response);
+    assert_eq!(response_bytes(&response), packet);
+}
+
+#[test]
+fn test_announce_response_ipv4() {
+    let fixed_hex = ["00000001", TRANSACTION_ID, "00000708 00000001 00000002"].join(" ");
+
+    let fixed = AnnounceResponseFixedData {
+        transaction_id: TransactionId::new(0x12345678),
+        announce_interval: AnnounceInterval::new(1800),
+        leechers: NumberOfPeers::new(1),
+        seeders: NumberOfPeers::new(2),
+    };
+
+    let packet = bytes(&[&fixed_hex, "7f000001 1ae1 c0a80001 1ae2"].join(" "));
+
+    let response = Response::AnnounceIpv4(AnnounceResponse {
+        fixed,
+        peers: vec![
+            ResponsePeer {
+                ip_address: Ipv4Addr::LOCALHOST.into(),
+                port: Port::new(NonZeroU16::new(6881).unwrap()),
+            },
+            ResponsePeer {
+                ip_address: Ipv4Addr::new(192, 168, 0, 1).into(),
+                port: Port::new(NonZeroU16::new(6882).unwrap()),
+            },
+        ],
+    });
+
+    assert_eq!(Response::parse_bytes(&packet, true).unwrap(), response);
+    assert_eq!(response_bytes(&response), packet);
+
+    // Response without peers
+    let packet = bytes(&fixed_hex);
+
+    let response = Response::AnnounceIpv4(AnnounceResponse {
+        fixed,
+        peers: Vec::new(),
+    });
+
+    assert_eq!(Response::parse_bytes(&packet, true).unwrap(), response);
+    assert_eq!(response_bytes(&response), packet);
+}
+
+#[test]
+fn test_announce_response_ipv6() {
+    let packet = bytes(
+        &[
+            "00000001",
+            TRANSACTION_ID,
+            "00000708 00000000 00000001",
+            "00000000000000000000000000000001 1ae1",
+        ]
+        .join(" "),
+    );
+
+    let response = Response::AnnounceIpv6(AnnounceResponse {
+        fixed: AnnounceResponseFixedData {
+            transaction_id: TransactionId::new(0x12345678),
+            announce_interval: AnnounceInterval::new(1800),
+            leechers: NumberOfPeers::new(0),
+            seeders: NumberOfPeers::new(1),
+        },
+        peers: vec![ResponsePeer {
+            ip_address: Ipv6Addr::LOCALHOST.into(),
+            port: Port::new(NonZeroU16::new(6881).unwrap()),
+        }],
+    });
+
+    assert_eq!(Response::parse_bytes(&packet, false).unwrap(), response);
+    assert_eq!(response_bytes(&response), packet);
+}
+
+#[test]
+fn test_scrape_response() {
+    let packet = bytes(
+        &[
+            "00000002",
+            TRANSACTION_ID,
+            "00000001 00000002 00000003",
+            "00000000 00000000 00000000",
+        ]
+        .join(" "),
+    );
+
+    let response = Response::Scrape(ScrapeResponse {
+        transaction_id: TransactionId::new(0x12345678),
+        torrent_stats: vec![
+            TorrentScrapeStatistics {
+                seeders: NumberOfPeers::new(1),
+                completed: NumberOfDownloads::new(2),
+                leechers: NumberOfPeers::new(3),
+            },
+            TorrentScrapeStatistics {
+                seeders: NumberOfPeers::new(0),
+                completed: NumberOfDownloads::new(0),
+                leechers: NumberOfPeers::new(0),
+            },
+        ],
+    });
+
+    assert_eq!(Response::parse_bytes(&packet, true).unwrap(), response);
+    assert_eq!(response_bytes(&response), packet);
+}
+
+#[test]
+fn test_error_response() {
+    // "Invalid connection ID" in ASCII
+    let packet = bytes(
+        &[
+            "00000003",
+            TRANSACTION_ID,
+            "496e76616c696420636f6e6e656374696f6e204944",
+        ]
+        .join(" "),
+    );
+
+    let response = Response::Error(ErrorResponse {
+        transaction_id: TransactionId::new(0x12345678),
+        message: "Invalid connection ID".into(),
+    });
+
+    assert_eq!(Response::parse_bytes(&packet, true).unwrap(), response);
+    assert_eq!(response_bytes(&response), packet);
+}
+
+#[test]
+fn test_shared_fixtures() {
+    use aquatic_protocol_tests as fixtures;
+
+    let transaction_id = TransactionId::new(fixtures::udp::TRANSACTION_ID as i32);
+    let connection_id = ConnectionId::new(fixtures::udp::CONNECTION_ID as i64);
+
+    let request = Request::Connect(ConnectRequest { transaction_id });
+
+    assert_eq!(
+        Request::parse_bytes(&fixtures::udp::connect_request(), 70).unwrap(),
+        request
+    );
+    assert_eq!(request_bytes(&request), fixtures::udp::connect_request());
+
+    let response = Response::Connect(ConnectResponse {
+        transaction_id,
+        connection_id,
+    });
+
+    assert_eq!(
+        Response::parse_bytes(&fixtures::udp::connect_response(), true).unwrap(),
+        response
+    );
+    assert_eq!(response_bytes(&response), fixtures::udp::connect_response());
+
+    for (fixture_event, event) in [
+        (fixtures::Event::None, AnnounceEvent::None),
+        (fixtures::Event::Started, AnnounceEvent::Started),
+        (fixtures::Event::Stopped, AnnounceEvent::Stopped),
+        (fixtures::Event::Completed, AnnounceEvent::Completed),
+    ] {
+        let packet = fixtures::udp::announce_request(fixture_event);
+
+        let request = Request::Announce(AnnounceRequest {
+            connection_id,
+            action_placeholder: Default::default(),
+            transaction_id,
+            info_hash: InfoHash(fixtures::INFO_HASH),
+            peer_id: PeerId(fixtures::PEER_ID),
+            bytes_downloaded: NumberOfBytes::new(fixtures::BYTES_DOWNLOADED as i64),
+            bytes_left: NumberOfBytes::new(fixtures::BYTES_LEFT as i64),
+            bytes_uploaded: NumberOfBytes::new(fixtures::BYTES_UPLOADED as i64),
+            event: event.into(),
+            ip_address: Ipv4AddrBytes([0; 4]),
+            key: PeerKey::new(fixtures::KEY as i32),
+            peers_wanted: NumberOfPeers::new(fixtures::NUMWANT as i32),
+            port: Port::new(NonZeroU16::new(fixtures::PORT).unwrap()),
+        });
+
+        assert_eq!(Request::parse_bytes(&packet, 70).unwrap(), request);
+        assert_eq!(request_bytes(&request), packet);
+    }
+
+    let (peer_ip, peer_port) = fixtures::RESPONSE_PEER;
+
+    let response = Response::AnnounceIpv4(AnnounceResponse {
+        fixed: AnnounceResponseFixedData {
+            transaction_id,
+            announce_interval: AnnounceInterval::new(fixtures::ANNOUNCE_INTERVAL as i32),
+            leechers: NumberOfPeers::new(fixtures::LEECHERS as i32),
+            seeders: NumberOfPeers::new(fixtures::SEEDERS as i32),
+        },
+        peers: vec![ResponsePeer {
+            ip_address: Ipv4Addr::from(peer_ip).into(),
+            port: Port::new(NonZeroU16::new(peer_port).unwrap()),
+        }],
+    });
+
+    assert_eq!(
+        Response::parse_bytes(&fixtures::udp::announce_response_ipv4(), true).unwrap(),
+        response
+    );
+    assert_eq!(
+        response_bytes(&response),
+        fixtures::udp::announce_response_ipv4()
+    );
+
+    let request = Request::Scrape(ScrapeRequest {
+        connection_id,
+        transaction_id,
+        info_hashes: vec![InfoHash(fixtures::INFO_HASH)],
+    });
+
+    assert_eq!(
+        Request::parse_bytes(&fixtures::udp::scrape_request(), 70).unwrap(),
+        request
+    );
+    assert_eq!(request_bytes(&request), fixtures::udp::scrape_request());
+
+    let response = Response::Scrape(ScrapeResponse {
+        transaction_id,
+        torrent_stats: vec![TorrentScrapeStatistics {
+            seeders: NumberOfPeers::new(fixtures::SEEDERS as i32),
+            completed: NumberOfDownloads::new(fixtures::DOWNLOADED as i32),
+            leechers: NumberOfPeers::new(fixtures::LEECHERS as i32),
+        }],
+    });
+
+    assert_eq!(
+        Response::parse_bytes(&fixtures::udp::scrape_response(), true).unwrap(),
+        response
+    );
+    assert_eq!(response_bytes(&response), fixtures::udp::scrape_response());
+}
@@ -1,11 +1,18 @@
-use std::{net::IpAddr, sync::Arc};
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 
 use aquatic_common::access_list::AccessListArcSwap;
+use aquatic_common::ban_list::BanListArcSwap;
+use aquatic_common::load_shedding::AnnounceCeiling;
 
 pub use aquatic_common::ValidUntil;
 use aquatic_ws_protocol::common::{InfoHash, PeerId};
+use hashbrown::HashMap;
 
-#[derive(Copy, Clone, Debug)]
+use crate::enrichment::{Enrichers, PeerMetadata};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum IpVersion {
     V4,
     V6,
@@ -26,12 +33,129 @@ impl IpVersion {
 #[derive(Default, Clone)]
 pub struct State {
     pub access_list: Arc<AccessListArcSwap>,
+    pub ban_list: Arc<BanListArcSwap>,
+    pub connection_counts: Arc<ConnectionCounts>,
+    /// Set when SIGTERM or SIGINT has been received
+    pub shutting_down: Arc<AtomicBool>,
+    /// Number of open connections over all socket workers, including ones
+    /// that haven't completed the WebSocket handshake
+    pub open_connections: Arc<AtomicUsize>,
+    /// Peer metadata lookups, loaded on start
+    pub enrichers: Arc<Enrichers>,
+    pub announce_ceiling: Arc<AnnounceCeiling>,
+    /// Number of announce requests dropped since swarm worker channels were
+    /// full. Logged and reset periodically by main thread.
+    pub dropped_requests: Arc<AtomicUsize>,
+}
+
+/// Number of connections over all socket workers, for enforcing
+/// `network.max_connections` and `network.max_connections_per_ip`
+#[derive(Default)]
+pub struct ConnectionCounts {
+    total: AtomicUsize,
+    per_ip: Mutex<HashMap<IpAddr, usize>>,
+}
+
+impl ConnectionCounts {
+    /// Count connection from peer unless that would exceed a limit. Limits
+    /// set to zero are not enforced.
+    pub fn try_acquire(
+        self: &Arc<Self>,
+        max_total: usize,
+        max_per_ip: usize,
+        peer_ip: IpAddr,
+    ) -> Result<ConnectionSlot, ConnectionLimit> {
+        let mut slot = ConnectionSlot {
+            counts: self.clone(),
+            counted_in_total: false,
+            opt_ip_key: None,
+        };
+
+        if max_total != 0 {
+            if self.total.fetch_add(1, Ordering::Relaxed) >= max_total {
+                self.total.fetch_sub(1, Ordering::Relaxed);
+
+                return Err(ConnectionLimit::Total);
+            }
+
+            slot.counted_in_total = true;
+        }
+
+        if max_per_ip != 0 {
+            let ip_key = connection_limit_ip_key(peer_ip);
+            let mut per_ip = self.per_ip.lock().unwrap();
+            let count = per_ip.entry(ip_key).or_default();
+
+            if *count >= max_per_ip {
+                return Err(ConnectionLimit::PerIp);
+            }
+
+            *count += 1;
+
+            slot.opt_ip_key = Some(ip_key);
+        }
+
+        Ok(slot)
+    }
+}
+
+/// Connection limit that was reached
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionLimit {
+    Total,
+    PerIp,
+}
+
+/// Connection counted in [ConnectionCounts]. Uncounted when dropped.
+pub struct ConnectionSlot {
+    counts: Arc<ConnectionCounts>,
+    counted_in_total: bool,
+    opt_ip_key: Option<IpAddr>,
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        if self.counted_in_total {
+            self.counts.total.fetch_sub(1, Ordering::Relaxed);
+        }
+
+        if let Some(ip_key) = self.opt_ip_key {
+            let mut per_ip = self.counts.per_ip.lock().unwrap();
+
+            if let hashbrown::hash_map::Entry::Occupied(mut entry) = per_ip.entry(ip_key) {
+                *entry.get_mut() -= 1;
+
+                if *entry.get() == 0 {
+                    entry.remove();
+                }
+            }
+        }
+    }
+}
+
+/// Count IPv4-mapped IPv6 addresses as IPv4 addresses and other IPv6
+/// addresses by /64 prefix, since peers often control whole prefixes
+fn connection_limit_ip_key(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => ip,
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                IpAddr::V4(ip)
+            } else {
+                let mut segments = ip.segments();
+
+                segments[4..].fill(0);
+
+                IpAddr::V6(Ipv6Addr::from(segments))
+            }
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
 pub struct PendingScrapeId(pub u8);
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct ConsumerId(pub u8);
 
 slotmap::new_key_type! {
@@ -46,6 +170,7 @@ pub struct InMessageMeta {
     pub connection_id: ConnectionId,
     pub ip_version: IpVersion,
     pub pending_scrape_id: Option<PendingScrapeId>,
+    pub peer_metadata: PeerMetadata,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -71,6 +196,52 @@ impl From<InMessageMeta> for OutMessageMeta {
 pub enum SwarmControlMessage {
     ConnectionClosed {
         ip_version: IpVersion,
+        /// Index of socket worker responsible for the connection
+        out_message_consumer_id: ConsumerId,
+        connection_id: ConnectionId,
         announced_info_hashes: Vec<(InfoHash, PeerId)>,
     },
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connection_counts() {
+        let counts = Arc::new(ConnectionCounts::default());
+
+        let ip_a = IpAddr::from([1, 2, 3, 4]);
+        let ip_b: IpAddr = "2001:db8::1".parse().unwrap();
+        let ip_b_same_prefix: IpAddr = "2001:db8::ffff:2".parse().unwrap();
+
+        let slot_a = counts.try_acquire(3, 1, ip_a).unwrap();
+
+        // IPv4-mapped address counts as IPv4 address
+        assert_eq!(
+            counts
+                .try_acquire(3, 1, "::ffff:1.2.3.4".parse().unwrap())
+                .err(),
+            Some(ConnectionLimit::PerIp)
+        );
+
+        let slot_b = counts.try_acquire(3, 2, ip_b).unwrap();
+        let _slot_c = counts.try_acquire(3, 2, ip_b_same_prefix).unwrap();
+
+        assert_eq!(
+            counts.try_acquire(0, 2, ip_b).err(),
+            Some(ConnectionLimit::PerIp)
+        );
+        assert_eq!(
+            counts.try_acquire(3, 0, IpAddr::from([5, 6, 7, 8])).err(),
+            Some(ConnectionLimit::Total)
+        );
+
+        drop(slot_a);
+        drop(slot_b);
+
+        assert!(counts.try_acquire(3, 1, ip_a).is_ok());
+        assert_eq!(counts.total.load(Ordering::Relaxed), 1);
+        assert_eq!(counts.per_ip.lock().unwrap().len(), 1);
+    }
+}
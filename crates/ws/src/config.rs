@@ -1,16 +1,53 @@
 use std::net::SocketAddr;
 use std::path::PathBuf;
 
-use aquatic_common::{access_list::AccessListConfig, privileges::PrivilegeConfig};
-use serde::Deserialize;
+use aquatic_common::{
+    access_list::AccessListConfig, ban_list::BanListConfig, client_filter::ClientFilterConfig,
+    greylist::GreylistConfig, ip_network::IpNetwork, max_jittered_announce_interval,
+    min_jittered_announce_interval, privileges::PrivilegeConfig, signed_urls::SignedUrlConfig,
+};
+use serde::{Deserialize, Serialize};
 
 use aquatic_common::cli::LogLevel;
 use aquatic_toml_config::TomlConfig;
 
+use crate::workers::socket::origin::origin_pattern_valid;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, TomlConfig, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ReverseProxyPeerIpHeaderFormat {
+    #[default]
+    LastAddress,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, TomlConfig, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelFullPolicy {
+    Block,
+    DropNewest,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, TomlConfig, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionChannelFullPolicy {
+    DropNewest,
+    CloseConnection,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, TomlConfig, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerIdCollisionPolicy {
+    Ignore,
+    ReplaceIfStale,
+    ErrorResponse,
+}
+
 /// aquatic_ws configuration
 ///
-/// Running behind a reverse proxy is supported, but IPv4 peer requests have
-/// to be proxied to IPv4 requests, and IPv6 requests to IPv6 requests.
+/// Running behind a reverse proxy is supported. Unless
+/// `network.runs_behind_reverse_proxy` or `network.enable_proxy_protocol` is
+/// set, IPv4 peer requests have to be proxied to IPv4 requests, and IPv6
+/// requests to IPv6 requests.
 #[derive(Clone, Debug, PartialEq, TomlConfig, Deserialize)]
 #[serde(default, deny_unknown_fields)]
 pub struct Config {
@@ -32,10 +69,42 @@ pub struct Config {
     /// Swarm workers receive a number of requests from socket workers,
     /// generate responses and send them back to the socket workers.
     pub swarm_workers: usize,
+    /// Maximum number of pending requests from each socket worker to each
+    /// swarm worker
+    pub request_channel_size: usize,
+    /// What socket workers should do with announce requests when the channel
+    /// to the relevant swarm worker is full
+    ///
+    /// - block: wait until there is room in the channel. Other messages from
+    ///   the same connection will not be read in the meantime.
+    /// - drop_newest: drop the request and increment the
+    ///   `aquatic_dropped_requests_total` counter. The number of dropped
+    ///   requests is also logged as a warning every few seconds.
+    ///
+    /// Dropping the oldest request instead isn't supported, since only the
+    /// receiving swarm worker can remove requests from a glommio channel.
+    ///
+    /// Scrape requests always wait for room in the channel.
+    pub request_channel_full_policy: ChannelFullPolicy,
+    /// Maximum number of pending outgoing messages for each connection
+    ///
+    /// Each connection has its own channel, so connections that are slow to
+    /// receive messages don't delay messages to other connections.
+    pub connection_channel_size: usize,
+    /// What socket workers should do with outgoing messages when the channel
+    /// to the relevant connection is full
+    ///
+    /// - drop_newest: drop the message
+    /// - close_connection: drop the message and close the connection
+    ///
+    /// In both cases, the `aquatic_dropped_responses_total` counter is
+    /// incremented.
+    pub connection_channel_full_policy: ConnectionChannelFullPolicy,
     pub log_level: LogLevel,
     pub network: NetworkConfig,
     pub protocol: ProtocolConfig,
     pub cleaning: CleaningConfig,
+    pub enrichment: EnrichmentConfig,
     pub privileges: PrivilegeConfig,
     /// Access list configuration
     ///
@@ -44,8 +113,38 @@ pub struct Config {
     /// emitting of an error-level log message, while successful updates of the
     /// access list result in emitting of an info-level log message.
     pub access_list: AccessListConfig,
+    /// Ban list configuration
+    ///
+    /// Requests (aquatic_udp) and connections (aquatic_http and
+    /// aquatic_ws) from banned IP addresses are ignored before any parsing.
+    /// If initial parsing of the file fails, the program exits.
+    pub ban_list: BanListConfig,
+    /// Greylist configuration
+    ///
+    /// Temporarily close connections from network prefixes with many failed
+    /// TLS or WebSocket handshakes or malformed messages. When
+    /// `network.runs_behind_reverse_proxy` is set, only malformed messages
+    /// are counted, for the peer IP from the proxy header.
+    pub greylist: GreylistConfig,
+    /// Client filter configuration
+    ///
+    /// Restrict which client software may announce by peer id prefix.
+    pub client_filter: ClientFilterConfig,
+    /// Signed announce URL configuration
+    ///
+    /// Only serve announce and scrape requests for info hashes covered by a
+    /// token signed by an external site, e.g., for tokenized public access.
+    /// The token is the last segment of the WebSocket handshake request
+    /// path (`/announce/<token>`). `network.websocket_paths` is matched
+    /// against the path without it.
+    pub signed_urls: SignedUrlConfig,
     #[cfg(feature = "metrics")]
     pub metrics: MetricsConfig,
+    /// Pin worker threads to cpu cores
+    ///
+    /// Socket workers are pinned first, followed by swarm workers.
+    #[cfg(feature = "cpu-pinning")]
+    pub cpu_pinning: aquatic_common::cpu_pinning::asc::CpuPinningConfigAsc,
 }
 
 impl Default for Config {
@@ -53,14 +152,25 @@ impl Default for Config {
         Self {
             socket_workers: 1,
             swarm_workers: 1,
+            request_channel_size: 1024,
+            request_channel_full_policy: ChannelFullPolicy::Block,
+            connection_channel_size: 16,
+            connection_channel_full_policy: ConnectionChannelFullPolicy::DropNewest,
             log_level: LogLevel::default(),
             network: NetworkConfig::default(),
             protocol: ProtocolConfig::default(),
             cleaning: CleaningConfig::default(),
+            enrichment: EnrichmentConfig::default(),
             privileges: PrivilegeConfig::default(),
             access_list: AccessListConfig::default(),
+            ban_list: BanListConfig::default(),
+            greylist: GreylistConfig::default(),
+            client_filter: ClientFilterConfig::default(),
+            signed_urls: SignedUrlConfig::default(),
             #[cfg(feature = "metrics")]
             metrics: Default::default(),
+            #[cfg(feature = "cpu-pinning")]
+            cpu_pinning: Default::default(),
         }
     }
 }
@@ -69,6 +179,186 @@ impl aquatic_common::cli::Config for Config {
     fn get_log_level(&self) -> Option<LogLevel> {
         Some(self.log_level)
     }
+    fn validation_errors(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if self.socket_workers == 0 {
+            errors.push("socket_workers must be greater than 0".into());
+        }
+        if self.swarm_workers == 0 {
+            errors.push("swarm_workers must be greater than 0".into());
+        }
+        if self.request_channel_size == 0 {
+            errors.push("request_channel_size must be greater than 0".into());
+        }
+        if self.connection_channel_size == 0 {
+            errors.push("connection_channel_size must be greater than 0".into());
+        }
+        if self.network.enable_tls && self.network.enable_http_health_checks {
+            errors.push(
+                "network.enable_tls and network.enable_http_health_checks can't both be set to true"
+                    .into(),
+            );
+        }
+        if self.network.enable_tls {
+            if self.network.tls_certificate_path.as_os_str().is_empty() {
+                errors.push(
+                    "network.enable_tls is set, but network.tls_certificate_path is empty".into(),
+                );
+            }
+            if self.network.tls_private_key_path.as_os_str().is_empty() {
+                errors.push(
+                    "network.enable_tls is set, but network.tls_private_key_path is empty".into(),
+                );
+            }
+            for protocol in self.network.tls_alpn_protocols.iter() {
+                if protocol.is_empty() || protocol.len() > 255 {
+                    errors.push(format!(
+                        "network.tls_alpn_protocols contains invalid protocol \"{}\", which must be 1 to 255 bytes long",
+                        protocol
+                    ));
+                }
+            }
+        }
+        for path in self.network.websocket_paths.iter() {
+            if !path.starts_with('/') {
+                errors.push(format!(
+                    "network.websocket_paths contains invalid path \"{}\", which must start with \"/\"",
+                    path
+                ));
+            }
+        }
+        for pattern in self
+            .network
+            .allowed_origins
+            .iter()
+            .chain(self.network.denied_origins.iter())
+        {
+            if !origin_pattern_valid(pattern) {
+                errors.push(format!(
+                    "network.allowed_origins or network.denied_origins contains invalid origin \"{}\", which must be of the form \"https://example.com\" or \"https://*.example.com\"",
+                    pattern
+                ));
+            }
+        }
+        if self.network.runs_behind_reverse_proxy && self.network.enable_proxy_protocol {
+            errors.push(
+                "network.runs_behind_reverse_proxy and network.enable_proxy_protocol can't both be set to true"
+                    .into(),
+            );
+        }
+        for network in self.network.reverse_proxy_trusted_networks.iter() {
+            if let Err(err) = network.parse::<IpNetwork>() {
+                errors.push(format!(
+                    "network.reverse_proxy_trusted_networks: invalid network {:?}: {:#}",
+                    network, err
+                ));
+            }
+        }
+        if (self.network.runs_behind_reverse_proxy || self.network.enable_proxy_protocol)
+            && self.network.reverse_proxy_trusted_networks.is_empty()
+        {
+            errors.push(
+                "network.runs_behind_reverse_proxy or network.enable_proxy_protocol is set, but network.reverse_proxy_trusted_networks is empty".into(),
+            );
+        }
+        if self.network.websocket_max_frame_size > self.network.websocket_max_message_size {
+            errors.push(
+                "network.websocket_max_frame_size can't be larger than network.websocket_max_message_size"
+                    .into(),
+            );
+        }
+        if self.network.websocket_ping_interval != 0
+            && self.network.websocket_max_unanswered_pings == 0
+        {
+            errors.push(
+                "network.websocket_ping_interval is set, but network.websocket_max_unanswered_pings is 0"
+                    .into(),
+            );
+        }
+        // Codes that can't be sent in close frames according to RFC 6455
+        if !(1000..5000).contains(&self.network.shutdown_close_code)
+            || [1004, 1005, 1006, 1015].contains(&self.network.shutdown_close_code)
+        {
+            errors.push(format!(
+                "network.shutdown_close_code ({}) is not a valid WebSocket close code",
+                self.network.shutdown_close_code
+            ));
+        }
+        if self.network.shutdown_close_reason.len() > 123 {
+            errors.push("network.shutdown_close_reason can't be longer than 123 bytes".into());
+        }
+        if self.network.only_ipv6 && self.network.address.is_ipv4() {
+            errors.push("network.only_ipv6 is set, but network.address is an IPv4 address".into());
+        }
+        if self.protocol.max_scrape_torrents == 0 {
+            errors.push("protocol.max_scrape_torrents must be greater than 0".into());
+        }
+        if self.protocol.peer_announce_interval == 0 {
+            errors.push("protocol.peer_announce_interval must be greater than 0".into());
+        }
+        if self.cleaning.torrent_cleaning_interval == 0 {
+            errors.push("cleaning.torrent_cleaning_interval must be greater than 0".into());
+        }
+        if self.cleaning.connection_cleaning_interval == 0 {
+            errors.push("cleaning.connection_cleaning_interval must be greater than 0".into());
+        }
+        if self.protocol.peer_announce_interval_jitter >= 100 {
+            errors.push("protocol.peer_announce_interval_jitter must be less than 100".into());
+        }
+
+        let max_announce_interval = max_jittered_announce_interval(
+            self.protocol.peer_announce_interval,
+            self.protocol.peer_announce_interval_jitter,
+        );
+
+        if self.cleaning.max_peer_age as usize <= max_announce_interval {
+            errors.push(format!(
+                "cleaning.max_peer_age ({}) must be greater than protocol.peer_announce_interval ({}) including jitter, or peers will be removed before they reannounce",
+                self.cleaning.max_peer_age, max_announce_interval
+            ));
+        }
+
+        let min_announce_interval = min_jittered_announce_interval(
+            self.protocol.peer_announce_interval,
+            self.protocol.peer_announce_interval_jitter,
+        );
+
+        if self.protocol.min_announce_interval as usize >= min_announce_interval {
+            errors.push(format!(
+                "protocol.min_announce_interval ({}) must be less than protocol.peer_announce_interval ({}) including jitter, or peers announcing as asked will be rejected",
+                self.protocol.min_announce_interval, min_announce_interval
+            ));
+        }
+
+        if self.protocol.peer_id_collision_policy == PeerIdCollisionPolicy::ReplaceIfStale
+            && self.protocol.peer_id_collision_stale_age >= self.cleaning.max_peer_age
+        {
+            errors.push(format!(
+                "protocol.peer_id_collision_stale_age ({}) must be less than cleaning.max_peer_age ({}), or stale peers will be removed before they can be replaced",
+                self.protocol.peer_id_collision_stale_age, self.cleaning.max_peer_age
+            ));
+        }
+
+        if self.protocol.max_announces_per_second != 0
+            && self.protocol.overloaded_announce_interval == 0
+        {
+            errors.push("protocol.overloaded_announce_interval must be greater than 0".into());
+        }
+
+        if self.protocol.message_rate_limit != 0 && self.protocol.message_rate_limit_burst == 0 {
+            errors.push(
+                "protocol.message_rate_limit_burst must be greater than 0 when protocol.message_rate_limit is set".into(),
+            );
+        }
+
+        errors.extend(self.access_list.validation_errors());
+        errors.extend(self.greylist.validation_errors());
+        errors.extend(self.client_filter.validation_errors());
+        errors.extend(self.signed_urls.validation_errors());
+
+        errors
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, TomlConfig, Deserialize)]
@@ -80,6 +370,35 @@ pub struct NetworkConfig {
     pub only_ipv6: bool,
     /// Maximum number of pending TCP connections
     pub tcp_backlog: i32,
+    /// Raise the open file limit (RLIMIT_NOFILE) to at least this value on
+    /// startup. Use 0 to leave it unchanged.
+    ///
+    /// Each connection uses a file descriptor, so this is effectively the
+    /// maximum number of concurrent connections. Raising the hard limit
+    /// requires root or CAP_SYS_RESOURCE, otherwise the soft limit is raised
+    /// to the hard limit and a warning is logged.
+    pub open_file_limit: u64,
+    /// Maximum number of WebSocket connections over all socket workers.
+    /// 0 = no limit
+    ///
+    /// WebSocket handshakes beyond the limit get a HTTP 503 response.
+    pub max_connections: usize,
+    /// Maximum number of WebSocket connections from each peer IP over all
+    /// socket workers. 0 = no limit
+    ///
+    /// IPv6 addresses are grouped by /64 prefix. When running behind a
+    /// reverse proxy, the peer IP from the proxy header is used.
+    /// WebSocket handshakes beyond the limit get a HTTP 503 response.
+    pub max_connections_per_ip: usize,
+    /// Use socket passed by systemd (socket activation) instead of creating
+    /// one and binding it to `address`
+    ///
+    /// The socket is shared by all socket workers and must already be in
+    /// listening mode.
+    ///
+    /// If several sockets are passed, the one with `FileDescriptorName=ws`
+    /// in the systemd socket unit is used.
+    pub systemd_socket_activation: bool,
 
     /// Enable TLS
     ///
@@ -89,18 +408,130 @@ pub struct NetworkConfig {
     /// result in emitting of an info-level log message. Updates only affect
     /// new connections.
     pub enable_tls: bool,
-    /// Path to TLS certificate (DER-encoded X.509)
+    /// Path to TLS certificate chain (PEM-encoded X.509)
     pub tls_certificate_path: PathBuf,
-    /// Path to TLS private key (DER-encoded ASN.1 in PKCS#8 or PKCS#1 format)
+    /// Path to TLS private key (PEM-encoded PKCS#8)
     pub tls_private_key_path: PathBuf,
+    /// Check TLS certificate and private key files for changes this often
+    /// (seconds), and reload them if they have changed. 0 = disabled
+    ///
+    /// Useful for picking up automatically renewed certificates. Files are
+    /// reloaded as with `SIGUSR1`, so existing connections are kept open.
+    pub tls_reload_interval: u64,
+    /// Application protocols to negotiate with ALPN, in order of preference
+    ///
+    /// Browsers open WebSocket connections over HTTP/1.1, so "http/1.1"
+    /// should be included. If the list is empty, ALPN is not used.
+    pub tls_alpn_protocols: Vec<String>,
 
+    /// Maximum size of incoming WebSocket messages (bytes)
+    ///
+    /// Fragmented messages are limited to this size in total. Connections
+    /// sending larger messages are closed before the messages are parsed.
     pub websocket_max_message_size: usize,
+    /// Maximum size of incoming WebSocket frames (bytes)
     pub websocket_max_frame_size: usize,
+    /// Maximum combined size of request target and headers in WebSocket
+    /// handshakes (bytes)
+    ///
+    /// Larger handshake requests get a HTTP 431 response. Regardless of this
+    /// setting, at most 64 KiB is read while waiting for a complete request.
+    pub websocket_max_handshake_header_size: usize,
+    /// Only accept WebSocket handshakes for these request paths, e.g.,
+    /// "/announce". If the list is empty, all paths are accepted.
+    ///
+    /// Handshakes for other paths get a HTTP 404 response. Query strings
+    /// are ignored.
+    pub websocket_paths: Vec<String>,
+    /// Only accept WebSocket handshakes from browser pages with these
+    /// origins. If the list is empty, all origins are accepted.
+    ///
+    /// Origins are given as e.g. "https://example.com", optionally with a
+    /// wildcard in place of subdomains ("https://*.example.com"). Handshakes
+    /// from other origins get a HTTP 403 response. Handshakes without an
+    /// Origin header, which aren't sent by browsers, are always accepted.
+    pub allowed_origins: Vec<String>,
+    /// Reject WebSocket handshakes from browser pages with these origins,
+    /// even if they are included in `allowed_origins`. Same format as
+    /// `allowed_origins`.
+    pub denied_origins: Vec<String>,
     pub websocket_write_buffer_size: usize,
+    /// Close connections that have more than this many bytes of outgoing
+    /// messages waiting to be sent for longer than
+    /// `websocket_slow_client_grace_period`. 0 = no limit
+    ///
+    /// Protects against peers that don't read from their connections, e.g.,
+    /// due to suspended browser tabs. Message sizes are only calculated if
+    /// this is set.
+    pub websocket_max_pending_bytes: usize,
+    /// Grace period for connections exceeding `websocket_max_pending_bytes`
+    /// (seconds)
+    pub websocket_slow_client_grace_period: u64,
+    /// Send WebSocket pings to peers this often (seconds). 0 = disabled
+    ///
+    /// Keeps connections through proxies alive and allows detecting dead
+    /// connections before `cleaning.max_connection_idle` has passed.
+    pub websocket_ping_interval: u64,
+    /// Close connections when this many pings in a row have been sent to
+    /// them without a pong being received
+    pub websocket_max_unanswered_pings: u8,
+    /// On SIGTERM or SIGINT, stop accepting connections, send WebSocket
+    /// close frames to peers and wait for up to this many seconds for
+    /// connections to close before quitting
+    ///
+    /// Lets browser clients reconnect to other tracker instances without
+    /// waiting for timeouts.
+    pub shutdown_drain_timeout: u64,
+    /// Status code in WebSocket close frames sent on shutdown. The default
+    /// is 1001 (going away).
+    pub shutdown_close_code: u16,
+    /// Reason in WebSocket close frames sent on shutdown (at most 123 bytes)
+    pub shutdown_close_reason: String,
 
     /// Return a HTTP 200 Ok response when receiving GET /health. Can not be
     /// combined with enable_tls.
     pub enable_http_health_checks: bool,
+
+    /// Does tracker run behind reverse proxy?
+    ///
+    /// MUST be set to false if not running behind reverse proxy.
+    ///
+    /// If set to true, the peer IP is extracted from the WebSocket handshake
+    /// request and used to determine if the peer is an IPv4 or IPv6 peer.
+    /// Make sure that reverse_proxy_ip_header_name,
+    /// reverse_proxy_ip_header_format and reverse_proxy_trusted_networks are
+    /// set to match your reverse proxy setup. Handshake requests without a valid header get a HTTP 400
+    /// response.
+    ///
+    /// More info on what can go wrong when running behind reverse proxies:
+    /// https://adam-p.ca/blog/2022/03/x-forwarded-for/
+    pub runs_behind_reverse_proxy: bool,
+    /// Name of header set by reverse proxy to indicate peer ip
+    pub reverse_proxy_ip_header_name: String,
+    /// How to extract peer IP from header field
+    ///
+    /// Options:
+    /// - last_address: use the last address in the last instance of the
+    ///   header. Works with typical multi-IP setups (e.g., "X-Forwarded-For")
+    ///   as well as for single-IP setups (e.g., nginx "X-Real-IP")
+    pub reverse_proxy_ip_header_format: ReverseProxyPeerIpHeaderFormat,
+    /// Expect connections to start with a PROXY protocol (version 1 or 2)
+    /// header, as sent by e.g. HAProxy, and use the source address in it to
+    /// determine if the peer is an IPv4 or IPv6 peer
+    ///
+    /// MUST be set to false if connections are not accepted through a proxy
+    /// sending such headers. Can not be combined with
+    /// runs_behind_reverse_proxy.
+    pub enable_proxy_protocol: bool,
+    /// Networks in CIDR notation (e.g., "10.0.0.0/8") of reverse proxies
+    ///
+    /// The peer IP header (runs_behind_reverse_proxy) and PROXY protocol
+    /// headers (enable_proxy_protocol) are only used for connections from
+    /// these networks, so that peers connecting directly can't send
+    /// arbitrary addresses. For other connections, the connection source
+    /// address is used, or the connection is closed if PROXY protocol
+    /// headers are expected.
+    pub reverse_proxy_trusted_networks: Vec<String>,
 }
 
 impl Default for NetworkConfig {
@@ -109,16 +540,39 @@ impl Default for NetworkConfig {
             address: SocketAddr::from(([0, 0, 0, 0], 3000)),
             only_ipv6: false,
             tcp_backlog: 1024,
+            open_file_limit: 0,
+            max_connections: 0,
+            max_connections_per_ip: 0,
+            systemd_socket_activation: false,
 
             enable_tls: false,
             tls_certificate_path: "".into(),
             tls_private_key_path: "".into(),
+            tls_reload_interval: 0,
+            tls_alpn_protocols: vec!["http/1.1".into()],
 
             websocket_max_message_size: 64 * 1024,
             websocket_max_frame_size: 16 * 1024,
+            websocket_max_handshake_header_size: 16 * 1024,
+            websocket_paths: Vec::new(),
+            allowed_origins: Vec::new(),
+            denied_origins: Vec::new(),
             websocket_write_buffer_size: 8 * 1024,
+            websocket_max_pending_bytes: 0,
+            websocket_slow_client_grace_period: 5,
+            websocket_ping_interval: 0,
+            websocket_max_unanswered_pings: 2,
+            shutdown_drain_timeout: 5,
+            shutdown_close_code: 1001,
+            shutdown_close_reason: "Tracker shutting down".into(),
 
             enable_http_health_checks: false,
+
+            runs_behind_reverse_proxy: false,
+            reverse_proxy_ip_header_name: "X-Forwarded-For".into(),
+            reverse_proxy_ip_header_format: Default::default(),
+            enable_proxy_protocol: false,
+            reverse_proxy_trusted_networks: vec!["127.0.0.0/8".into(), "::1/128".into()],
         }
     }
 }
@@ -128,18 +582,165 @@ impl Default for NetworkConfig {
 pub struct ProtocolConfig {
     /// Maximum number of torrents to accept in scrape request
     pub max_scrape_torrents: usize,
+    /// Answer scrape requests without info hashes with statistics for the
+    /// torrents announced on the same connection, up to
+    /// `max_scrape_torrents` of them
+    ///
+    /// If set to false, such requests get an error response, since
+    /// returning statistics for all torrents would be too expensive.
+    pub scrape_announced_torrents: bool,
     /// Maximum number of offers to accept in announce request
     pub max_offers: usize,
+    /// Only pass on offers from seeders to leechers
+    ///
+    /// Seeders have no use for connections to each other. If there are no
+    /// leechers, offers from seeders are dropped.
+    pub seeder_offers_only_to_leechers: bool,
+    /// Tell peers answering offers when the peer that sent the offer is no
+    /// longer in the swarm
+    ///
+    /// By default, such answers get the same error response as answers to
+    /// unknown or expired offers, so that answers can't be used to check
+    /// which peer ids are in the swarm. With this set, they get a distinct
+    /// error response, so clients that handle it can give up on the
+    /// connection instead of waiting for it to time out. WebTorrent clients
+    /// only log error responses as warnings.
+    pub notify_when_offering_peer_gone: bool,
     /// Ask peers to announce this often (seconds)
     pub peer_announce_interval: usize,
+    /// Randomize announce interval in each response by up to this many
+    /// percent in either direction, to prevent peers from announcing in sync
+    /// (e.g., after a restart). 0 = disabled
+    pub peer_announce_interval_jitter: u8,
+    /// Send error responses to announce requests without an event, offers
+    /// or an answer from stored peers that announced less than this many
+    /// seconds ago. 0 = disabled
+    ///
+    /// Protects the tracker from clients announcing too aggressively.
+    /// Requests with events (started, stopped, completed) or used for
+    /// signaling are never rejected.
+    pub min_announce_interval: u32,
+    /// Send error responses to announce requests with event `stopped` or
+    /// `completed` from peers that are not stored as having announced with
+    /// event `started`
+    ///
+    /// Makes it harder to inflate the number of completed downloads
+    /// reported in scrape responses with forged events. Peers stored before
+    /// a tracker restart need to announce `started` again.
+    pub require_started_event: bool,
+    /// Maximum number of announce requests to pass on to swarm workers per
+    /// second, across all socket workers. 0 = unlimited
+    ///
+    /// Further announce requests get responses without offers asking peers
+    /// to announce again after `overloaded_announce_interval` seconds. This
+    /// keeps swarm workers responsive during flash crowds. Requests with
+    /// answers are never shed. Shed requests are counted in the
+    /// `aquatic_shed_announces_total` metric.
+    pub max_announces_per_second: u64,
+    /// Ask peers to announce this often (seconds) when
+    /// `max_announces_per_second` is exceeded
+    pub overloaded_announce_interval: usize,
+    /// What to do with announce requests using a peer id that is stored for
+    /// the torrent on behalf of another connection
+    ///
+    /// Since peers learn each others peer ids from offers, such requests may
+    /// be attempts to impersonate other peers, but they are also sent by
+    /// clients that reconnect before their previous connection is closed.
+    ///
+    /// - ignore: don't respond to the request
+    /// - replace_if_stale: if the stored peer hasn't announced for
+    ///   `peer_id_collision_stale_age` seconds, replace it with the
+    ///   requesting peer. Otherwise, don't respond.
+    /// - error_response: send an error response
+    ///
+    /// Collisions are counted in the `aquatic_peer_id_collisions_total`
+    /// metric, by action taken, and logged at debug level.
+    pub peer_id_collision_policy: PeerIdCollisionPolicy,
+    /// Seconds since last announce after which stored peers are considered
+    /// stale by the replace_if_stale peer id collision policy
+    pub peer_id_collision_stale_age: u32,
+    /// Maximum number of torrents that peers can announce on a single
+    /// connection at a time. Further announce requests for other torrents
+    /// get error responses. 0 = no limit
+    ///
+    /// Torrents stop counting towards the limit when the peer announces
+    /// with the stopped event.
+    pub max_torrents_per_connection: usize,
+    /// Coalesce announce requests for the same torrent on a connection that
+    /// arrive within this many milliseconds of each other. 0 = disabled
+    ///
+    /// The first announce request is passed on immediately. Further requests
+    /// within the window replace each other, and only the newest one is
+    /// passed on when the window ends, so that stale offers aren't relayed.
+    /// Requests with events (started, stopped, completed) or answers are
+    /// never delayed.
+    pub announce_coalescing_window_ms: u64,
+    /// Maximum number of peers to store per torrent. 0 = no limit
+    ///
+    /// When a new peer announces to a full torrent, the leecher that
+    /// announced least recently is removed to make room for it. If all
+    /// stored peers are seeders, the announce request gets an error
+    /// response instead. Limiting swarm size bounds the amount of memory and
+    /// offer relaying that a single popular torrent can cause.
+    pub max_peers_per_torrent: usize,
+    /// When passing on offers, prefer peers in the same country and
+    /// autonomous system as the sender, as looked up in the enrichment
+    /// databases
+    ///
+    /// WebRTC connections between nearby peers generally have lower
+    /// latency. Among equally close peers, receivers are still picked at
+    /// random.
+    pub prefer_nearby_offer_receivers: bool,
+    /// Include the IP address of the requesting peer as seen by the tracker
+    /// in announce responses (`external_ip`)
+    ///
+    /// Lets clients behind NAT learn their public address. When running
+    /// behind a reverse proxy, the address from the proxy's IP header is
+    /// sent.
+    pub send_external_ip: bool,
+    /// Maximum number of announce and scrape messages that a connection may
+    /// send per minute on average. 0 = no limit
+    ///
+    /// Messages are limited with a token bucket holding up to
+    /// `message_rate_limit_burst` tokens, so short bursts are allowed.
+    /// Messages exceeding the limit get error responses and are counted in
+    /// the `aquatic_rate_limited_requests_total` metric.
+    pub message_rate_limit: u32,
+    /// Number of messages that a connection may send in a burst before
+    /// `message_rate_limit` applies
+    pub message_rate_limit_burst: u32,
+    /// Close connections after this many of their messages have exceeded
+    /// `message_rate_limit`. 0 = never close connections
+    ///
+    /// Closed connections are counted in the
+    /// `aquatic_rate_limited_connections_closed_total` metric.
+    pub message_rate_limit_max_violations: usize,
 }
 
 impl Default for ProtocolConfig {
     fn default() -> Self {
         Self {
             max_scrape_torrents: 255,
+            scrape_announced_torrents: false,
             max_offers: 10,
+            seeder_offers_only_to_leechers: true,
+            notify_when_offering_peer_gone: false,
             peer_announce_interval: 120,
+            peer_announce_interval_jitter: 0,
+            min_announce_interval: 0,
+            require_started_event: false,
+            max_announces_per_second: 0,
+            overloaded_announce_interval: 600,
+            peer_id_collision_policy: PeerIdCollisionPolicy::Ignore,
+            peer_id_collision_stale_age: 150,
+            max_torrents_per_connection: 0,
+            announce_coalescing_window_ms: 0,
+            max_peers_per_torrent: 0,
+            prefer_nearby_offer_receivers: false,
+            send_external_ip: false,
+            message_rate_limit: 0,
+            message_rate_limit_burst: 30,
+            message_rate_limit_max_violations: 10,
         }
     }
 }
@@ -149,13 +750,31 @@ impl Default for ProtocolConfig {
 pub struct CleaningConfig {
     /// Clean peers this often (seconds)
     pub torrent_cleaning_interval: u64,
+    /// Clean at most this many torrents per IP version and swarm worker each
+    /// time cleaning is run. Remaining torrents are cleaned during the
+    /// following runs.
+    ///
+    /// Limiting this can reduce latency spikes when tracking a large number
+    /// of torrents. Set to 0 to always clean all torrents.
+    pub torrent_cleaning_batch_size: usize,
     /// Remove peers that have not announced for this long (seconds)
     pub max_peer_age: u32,
     /// Require that offers are answered to withing this period (seconds)
     pub max_offer_age: u32,
+    /// Maximum number of torrents per IP version and swarm worker to
+    /// remember the number of completed downloads for after they are
+    /// removed because they have no peers left
+    ///
+    /// The count is reported in scrape responses and restored if peers
+    /// announce to the torrent again. Counts of torrents removed when the
+    /// limit has been reached are lost. 0 = don't remember any.
+    pub max_removed_torrent_completed_counts: usize,
     // Clean connections this often (seconds)
     pub connection_cleaning_interval: u64,
     /// Close connections if no responses have been sent to them for this long (seconds)
+    ///
+    /// Pongs don't count as responses. Closed connections are counted in
+    /// the `aquatic_idle_connections_closed_total` metric.
     pub max_connection_idle: u32,
     /// After updating TLS certificates, close connections running with
     /// previous certificates after this long (seconds)
@@ -168,8 +787,10 @@ impl Default for CleaningConfig {
     fn default() -> Self {
         Self {
             torrent_cleaning_interval: 30,
+            torrent_cleaning_batch_size: 0,
             max_peer_age: 180,
             max_offer_age: 120,
+            max_removed_torrent_completed_counts: 100_000,
             max_connection_idle: 180,
             connection_cleaning_interval: 30,
             close_after_tls_update_grace_period: 60 * 60 * 60,
@@ -177,6 +798,22 @@ impl Default for CleaningConfig {
     }
 }
 
+/// Databases for looking up peer metadata when connections are opened
+///
+/// Files are read on start. Empty paths disable the relevant lookup.
+#[derive(Clone, Debug, PartialEq, TomlConfig, Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct EnrichmentConfig {
+    /// Path to CSV file mapping IP ranges to country codes, with lines in
+    /// the format `first_ip,last_ip,country_code`, such as the free
+    /// "IP to Country Lite" database from db-ip.com
+    pub country_database_path: PathBuf,
+    /// Path to CSV file mapping IP ranges to autonomous system numbers, with
+    /// lines in the format `first_ip,last_ip,asn[,...]`, such as the free
+    /// "IP to ASN Lite" database from db-ip.com
+    pub asn_database_path: PathBuf,
+}
+
 #[cfg(feature = "metrics")]
 #[derive(Clone, Debug, PartialEq, TomlConfig, Deserialize)]
 #[serde(default, deny_unknown_fields)]
@@ -214,7 +851,32 @@ impl Default for MetricsConfig {
 
 #[cfg(test)]
 mod tests {
+    use aquatic_common::cli::Config as _;
+
     use super::Config;
 
     ::aquatic_toml_config::gen_serialize_deserialize_test!(Config);
+
+    #[test]
+    fn test_default_config_is_valid() {
+        assert_eq!(Config::default().validation_errors(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_reverse_proxy_trusted_networks() {
+        let mut config = Config::default();
+
+        config.network.enable_proxy_protocol = true;
+        config.network.reverse_proxy_trusted_networks = vec!["10.0.0.1".into()];
+
+        assert_eq!(config.validation_errors().len(), 1);
+
+        config.network.reverse_proxy_trusted_networks = vec![];
+
+        assert_eq!(config.validation_errors().len(), 1);
+
+        config.network.reverse_proxy_trusted_networks = vec!["10.0.0.0/8".into()];
+
+        assert_eq!(config.validation_errors(), Vec::<String>::new());
+    }
 }
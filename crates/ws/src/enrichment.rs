@@ -0,0 +1,271 @@
+//! Peer metadata looked up from IP address when WebSocket handshake completes
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::Context;
+
+use crate::config::EnrichmentConfig;
+
+/// ISO 3166-1 alpha-2 country code, in upper case
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CountryCode(pub [u8; 2]);
+
+impl FromStr for CountryCode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.as_bytes() {
+            [a, b] if a.is_ascii_alphabetic() && b.is_ascii_alphabetic() => {
+                Ok(Self([a.to_ascii_uppercase(), b.to_ascii_uppercase()]))
+            }
+            _ => Err(anyhow::anyhow!("invalid country code: {}", s)),
+        }
+    }
+}
+
+/// Metadata about peer, filled in by enrichers
+///
+/// Fields are None when no enricher is configured for them or when the
+/// peer IP isn't covered by the relevant database.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PeerMetadata {
+    pub country: Option<CountryCode>,
+    /// Autonomous system number
+    pub asn: Option<u32>,
+}
+
+impl PeerMetadata {
+    /// Higher values mean that peers are likely to be closer to each other
+    /// in terms of network latency
+    pub fn proximity(&self, other: &Self) -> u8 {
+        let same_country = self.country.is_some() && self.country == other.country;
+        let same_asn = self.asn.is_some() && self.asn == other.asn;
+
+        u8::from(same_country) + u8::from(same_asn)
+    }
+}
+
+/// Fills in peer metadata based on IP address
+pub trait Enricher: Send + Sync {
+    fn enrich(&self, ip: IpAddr, metadata: &mut PeerMetadata);
+}
+
+/// Enrichers enabled in config, run in order
+#[derive(Default)]
+pub struct Enrichers(Vec<Box<dyn Enricher>>);
+
+impl Enrichers {
+    pub fn new(config: &EnrichmentConfig) -> anyhow::Result<Self> {
+        let mut enrichers: Vec<Box<dyn Enricher>> = Vec::new();
+
+        if !config.country_database_path.as_os_str().is_empty() {
+            let database = IpRangeDatabase::from_path(&config.country_database_path, |value| {
+                CountryCode::from_str(value)
+            })
+            .with_context(|| "load country database")?;
+
+            enrichers.push(Box::new(CountryEnricher(database)));
+        }
+        if !config.asn_database_path.as_os_str().is_empty() {
+            let database = IpRangeDatabase::from_path(&config.asn_database_path, |value| {
+                Ok(value.trim_start_matches("AS").parse::<u32>()?)
+            })
+            .with_context(|| "load ASN database")?;
+
+            enrichers.push(Box::new(AsnEnricher(database)));
+        }
+
+        Ok(Self(enrichers))
+    }
+
+    pub fn enrich(&self, ip: IpAddr) -> PeerMetadata {
+        let mut metadata = PeerMetadata::default();
+
+        for enricher in self.0.iter() {
+            enricher.enrich(ip, &mut metadata);
+        }
+
+        metadata
+    }
+}
+
+struct CountryEnricher(IpRangeDatabase<CountryCode>);
+
+impl Enricher for CountryEnricher {
+    fn enrich(&self, ip: IpAddr, metadata: &mut PeerMetadata) {
+        if let Some(country) = self.0.get(ip) {
+            metadata.country = Some(country);
+        }
+    }
+}
+
+struct AsnEnricher(IpRangeDatabase<u32>);
+
+impl Enricher for AsnEnricher {
+    fn enrich(&self, ip: IpAddr, metadata: &mut PeerMetadata) {
+        if let Some(asn) = self.0.get(ip) {
+            metadata.asn = Some(asn);
+        }
+    }
+}
+
+/// Non-overlapping IP address ranges with associated values, sorted by
+/// first address
+///
+/// IPv4 addresses are stored as IPv4-mapped IPv6 addresses.
+struct IpRangeDatabase<T> {
+    ranges: Vec<(u128, u128, T)>,
+}
+
+impl<T: Copy> IpRangeDatabase<T> {
+    /// Read database from CSV file with lines in the format
+    /// `first_ip,last_ip,value[,...]`, such as the free "IP to Country Lite"
+    /// and "IP to ASN Lite" databases from db-ip.com
+    fn from_path(
+        path: &Path,
+        parse_value: impl Fn(&str) -> anyhow::Result<T>,
+    ) -> anyhow::Result<Self> {
+        let file = File::open(path).with_context(|| format!("open {}", path.display()))?;
+
+        let mut ranges = Vec::new();
+
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let range = parse_line(&line, &parse_value)
+                .with_context(|| format!("parse line {}: {}", i + 1, line))?;
+
+            ranges.push(range);
+        }
+
+        Self::from_ranges(ranges)
+    }
+
+    fn from_ranges(mut ranges: Vec<(u128, u128, T)>) -> anyhow::Result<Self> {
+        ranges.sort_unstable_by_key(|(first, _, _)| *first);
+
+        for window in ranges.windows(2) {
+            if window[0].1 >= window[1].0 {
+                return Err(anyhow::anyhow!("database contains overlapping ranges"));
+            }
+        }
+
+        Ok(Self { ranges })
+    }
+
+    fn get(&self, ip: IpAddr) -> Option<T> {
+        let ip = ip_to_u128(ip);
+
+        // Index of first range starting after ip
+        let index = self.ranges.partition_point(|(first, _, _)| *first <= ip);
+
+        let (_, last, value) = self.ranges.get(index.checked_sub(1)?)?;
+
+        (ip <= *last).then_some(*value)
+    }
+}
+
+fn parse_line<T>(
+    line: &str,
+    parse_value: impl Fn(&str) -> anyhow::Result<T>,
+) -> anyhow::Result<(u128, u128, T)> {
+    let mut fields = line.split(',').map(|field| field.trim().trim_matches('"'));
+
+    let mut next_field = || fields.next().ok_or(anyhow::anyhow!("missing field"));
+
+    let first: IpAddr = next_field()?.parse()?;
+    let last: IpAddr = next_field()?.parse()?;
+    let value = parse_value(next_field()?)?;
+
+    if first.is_ipv4() != last.is_ipv4() {
+        return Err(anyhow::anyhow!("range mixes IPv4 and IPv6 addresses"));
+    }
+
+    let first = ip_to_u128(first);
+    let last = ip_to_u128(last);
+
+    if first > last {
+        return Err(anyhow::anyhow!("first address is after last address"));
+    }
+
+    Ok((first, last, value))
+}
+
+fn ip_to_u128(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped().into(),
+        IpAddr::V6(ip) => ip.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ip_range_database() {
+        let parse_value = |value: &str| CountryCode::from_str(value);
+
+        let ranges = [
+            "2001:db8::,2001:db8::ffff,SE",
+            "1.0.0.0,1.0.0.255,au",
+            "\"1.0.2.0\",\"1.0.3.255\",\"CN\"",
+        ]
+        .into_iter()
+        .map(|line| parse_line(line, parse_value).unwrap())
+        .collect();
+
+        let database = IpRangeDatabase::from_ranges(ranges).unwrap();
+
+        let get = |ip: &str| database.get(ip.parse().unwrap()).map(|country| country.0);
+
+        assert_eq!(get("1.0.0.0"), Some(*b"AU"));
+        assert_eq!(get("1.0.0.255"), Some(*b"AU"));
+        assert_eq!(get("1.0.1.0"), None);
+        assert_eq!(get("1.0.3.1"), Some(*b"CN"));
+        assert_eq!(get("::ffff:1.0.3.1"), Some(*b"CN"));
+        assert_eq!(get("0.255.255.255"), None);
+        assert_eq!(get("2001:db8::1"), Some(*b"SE"));
+        assert_eq!(get("2001:db8::1:0"), None);
+
+        assert!(parse_line("1.0.0.0,2001:db8::,SE", parse_value).is_err());
+        assert!(parse_line("1.0.0.255,1.0.0.0,SE", parse_value).is_err());
+        assert!(parse_line("1.0.0.0,1.0.0.255,SWE", parse_value).is_err());
+        assert!(parse_line("1.0.0.0,1.0.0.255", parse_value).is_err());
+
+        let overlapping = ["1.0.0.0,1.0.0.255,AU", "1.0.0.128,1.0.1.0,CN"]
+            .into_iter()
+            .map(|line| parse_line(line, parse_value).unwrap())
+            .collect();
+
+        assert!(IpRangeDatabase::from_ranges(overlapping).is_err());
+    }
+
+    #[test]
+    fn test_proximity() {
+        let a = PeerMetadata {
+            country: Some(CountryCode(*b"SE")),
+            asn: Some(1),
+        };
+        let b = PeerMetadata {
+            country: Some(CountryCode(*b"SE")),
+            asn: Some(2),
+        };
+
+        assert_eq!(a.proximity(&a), 2);
+        assert_eq!(a.proximity(&b), 1);
+        assert_eq!(a.proximity(&PeerMetadata::default()), 0);
+        assert_eq!(
+            PeerMetadata::default().proximity(&PeerMetadata::default()),
+            0
+        );
+    }
+}
@@ -1,23 +1,34 @@
 pub mod common;
 pub mod config;
+pub mod enrichment;
 pub mod workers;
 
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use std::thread::{sleep, Builder, JoinHandle};
-use std::time::Duration;
+use std::thread::{park_timeout, sleep, Builder, JoinHandle};
+use std::time::{Duration, Instant};
 
 use anyhow::Context;
-use aquatic_common::rustls_config::create_rustls_config;
-use aquatic_common::{ServerStartInstant, WorkerType};
-use arc_swap::ArcSwap;
+use aquatic_common::rustls_config::RustlsConfigUpdater;
+use aquatic_common::{panic_message, ServerStartInstant, WorkerType};
 use glommio::{channels::channel_mesh::MeshBuilder, prelude::*};
-use signal_hook::{consts::SIGUSR1, iterator::Signals};
-
-use aquatic_common::access_list::update_access_list;
+use signal_hook::{
+    consts::{SIGINT, SIGTERM, SIGUSR1},
+    iterator::Signals,
+};
+
+use aquatic_common::access_list::{spawn_access_list_sync_worker, update_access_list};
+use aquatic_common::ban_list::update_ban_list;
+#[cfg(feature = "cpu-pinning")]
+use aquatic_common::cpu_pinning::{pin_current_if_configured_to, WorkerIndex};
+use aquatic_common::load_shedding::AnnounceCeiling;
 use aquatic_common::privileges::PrivilegeDropper;
+use aquatic_common::rlimit::raise_open_file_limit;
+use aquatic_common::systemd::StatusNotifier;
 
 use common::*;
 use config::Config;
+use enrichment::Enrichers;
 
 pub const APP_NAME: &str = "aquatic_ws: WebTorrent tracker";
 pub const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -25,45 +36,68 @@ pub const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
 pub const SHARED_IN_CHANNEL_SIZE: usize = 1024;
 
 pub fn run(config: Config) -> ::anyhow::Result<()> {
+    run_with_status_notifier(config, StatusNotifier::Systemd)
+}
+
+/// Run tracker, sending service status notifications through
+/// `status_notifier`
+pub fn run_with_status_notifier(
+    config: Config,
+    status_notifier: StatusNotifier,
+) -> ::anyhow::Result<()> {
     if config.network.enable_tls && config.network.enable_http_health_checks {
         return Err(anyhow::anyhow!(
             "configuration: network.enable_tls and network.enable_http_health_check can't both be set to true"
         ));
     }
 
-    let mut signals = Signals::new([SIGUSR1])?;
+    if config.network.open_file_limit != 0 {
+        raise_open_file_limit(config.network.open_file_limit)?;
+    }
+
+    let mut signals = Signals::new([SIGUSR1, SIGTERM, SIGINT])?;
 
-    let state = State::default();
+    let state = State {
+        enrichers: Arc::new(
+            Enrichers::new(&config.enrichment).with_context(|| "load enrichment databases")?,
+        ),
+        announce_ceiling: Arc::new(AnnounceCeiling::new(
+            config.protocol.max_announces_per_second,
+        )),
+        ..Default::default()
+    };
 
     update_access_list(&config.access_list, &state.access_list)?;
+    update_ban_list(&config.ban_list, &state.ban_list)?;
 
     let num_mesh_peers = config.socket_workers + config.swarm_workers;
 
-    let request_mesh_builder = MeshBuilder::partial(num_mesh_peers, SHARED_IN_CHANNEL_SIZE);
+    let request_mesh_builder = MeshBuilder::partial(num_mesh_peers, config.request_channel_size);
     let response_mesh_builder = MeshBuilder::partial(num_mesh_peers, SHARED_IN_CHANNEL_SIZE * 16);
     let control_mesh_builder = MeshBuilder::partial(num_mesh_peers, SHARED_IN_CHANNEL_SIZE * 16);
 
     let priv_dropper = PrivilegeDropper::new(config.privileges.clone(), config.socket_workers);
 
-    let opt_tls_config = if config.network.enable_tls {
-        Some(Arc::new(ArcSwap::from_pointee(
-            create_rustls_config(
+    let opt_tls_updater = if config.network.enable_tls {
+        Some(Arc::new(
+            RustlsConfigUpdater::new(
                 &config.network.tls_certificate_path,
                 &config.network.tls_private_key_path,
+                config
+                    .network
+                    .tls_alpn_protocols
+                    .iter()
+                    .map(|protocol| protocol.as_bytes().to_vec())
+                    .collect(),
             )
             .with_context(|| "create rustls config")?,
-        )))
-    } else {
-        None
-    };
-    let mut opt_tls_cert_data = if config.network.enable_tls {
-        Some(
-            ::std::fs::read(&config.network.tls_certificate_path)
-                .with_context(|| "open tls certificate file")?,
-        )
+        ))
     } else {
         None
     };
+    let opt_tls_config = opt_tls_updater
+        .as_ref()
+        .map(|updater| updater.tls_config.clone());
 
     let server_start_instant = ServerStartInstant::new();
 
@@ -81,6 +115,14 @@ pub fn run(config: Config) -> ::anyhow::Result<()> {
         let handle = Builder::new()
             .name(format!("socket-{:02}", i + 1))
             .spawn(move || {
+                #[cfg(feature = "cpu-pinning")]
+                pin_current_if_configured_to(
+                    &config.cpu_pinning,
+                    config.socket_workers,
+                    config.swarm_workers,
+                    WorkerIndex::SocketWorker(i),
+                );
+
                 LocalExecutorBuilder::default()
                     .make()
                     .map_err(|err| anyhow::anyhow!("Spawning executor failed: {:#}", err))?
@@ -111,6 +153,14 @@ pub fn run(config: Config) -> ::anyhow::Result<()> {
         let handle = Builder::new()
             .name(format!("swarm-{:02}", i + 1))
             .spawn(move || {
+                #[cfg(feature = "cpu-pinning")]
+                pin_current_if_configured_to(
+                    &config.cpu_pinning,
+                    config.socket_workers,
+                    config.swarm_workers,
+                    WorkerIndex::SwarmWorker(i),
+                );
+
                 LocalExecutorBuilder::default()
                     .make()
                     .map_err(|err| anyhow::anyhow!("Spawning executor failed: {:#}", err))?
@@ -147,8 +197,42 @@ pub fn run(config: Config) -> ::anyhow::Result<()> {
         join_handles.push((WorkerType::Prometheus, handle));
     }
 
+    // Spawn TLS reload thread
+    if let Some(tls_updater) = opt_tls_updater.clone() {
+        if config.network.tls_reload_interval != 0 {
+            let interval = Duration::from_secs(config.network.tls_reload_interval);
+
+            let handle = Builder::new()
+                .name("tls-reload".into())
+                .spawn(move || loop {
+                    sleep(interval);
+
+                    match tls_updater.update() {
+                        Ok(true) => {
+                            ::log::info!("successfully updated tls config after files changed")
+                        }
+                        Ok(false) => (),
+                        Err(err) => ::log::error!("could not update tls config: {:#}", err),
+                    }
+                })
+                .context("spawn tls reload worker")?;
+
+            join_handles.push((WorkerType::TlsReload, handle));
+        }
+    }
+
+    // Spawn access list sync thread
+    if config.access_list.sync_active() {
+        let handle = spawn_access_list_sync_worker(&config.access_list, state.access_list.clone())?;
+
+        join_handles.push((WorkerType::AccessListSync, handle));
+    }
+
     // Spawn signal handler thread
     {
+        let state = state.clone();
+        let main_thread = ::std::thread::current();
+
         let handle: JoinHandle<anyhow::Result<()>> = Builder::new()
             .name("signals".into())
             .spawn(move || {
@@ -156,30 +240,41 @@ pub fn run(config: Config) -> ::anyhow::Result<()> {
                     match signal {
                         SIGUSR1 => {
                             let _ = update_access_list(&config.access_list, &state.access_list);
+                            let _ = update_ban_list(&config.ban_list, &state.ban_list);
 
-                            if let Some(tls_config) = opt_tls_config.as_ref() {
-                                match ::std::fs::read(&config.network.tls_certificate_path) {
-                                    Ok(data) if &data == opt_tls_cert_data.as_ref().unwrap() => {
-                                        ::log::info!("skipping tls config update: certificate identical to currently loaded");
-                                    }
-                                    Ok(data) => {
-                                        match create_rustls_config(
-                                            &config.network.tls_certificate_path,
-                                            &config.network.tls_private_key_path,
-                                        ) {
-                                            Ok(config) => {
-                                                tls_config.store(Arc::new(config));
-                                                opt_tls_cert_data = Some(data);
-
-                                                ::log::info!("successfully updated tls config");
-                                            }
-                                            Err(err) => ::log::error!("could not update tls config: {:#}", err),
-                                        }
-                                    }
-                                    Err(err) => ::log::error!("couldn't read tls certificate file: {:#}", err),
+                            if let Some(tls_updater) = opt_tls_updater.as_ref() {
+                                match tls_updater.update() {
+                                    Ok(true) => ::log::info!("successfully updated tls config"),
+                                    Ok(false) => ::log::info!("skipping tls config update: certificate and private key identical to currently loaded"),
+                                    Err(err) => ::log::error!("could not update tls config: {:#}", err),
                                 }
                             }
                         }
+                        SIGTERM | SIGINT => {
+                            let drain_timeout =
+                                Duration::from_secs(config.network.shutdown_drain_timeout);
+
+                            ::log::info!(
+                                "shutting down, waiting up to {} seconds for connections to close",
+                                drain_timeout.as_secs()
+                            );
+
+                            state.shutting_down.store(true, Ordering::Relaxed);
+
+                            let start = Instant::now();
+
+                            while state.open_connections.load(Ordering::Relaxed) != 0
+                                && start.elapsed() < drain_timeout
+                            {
+                                sleep(Duration::from_millis(100));
+                            }
+
+                            // Let main thread notice that this thread has
+                            // finished
+                            main_thread.unpark();
+
+                            break;
+                        }
                         _ => unreachable!(),
                     }
                 }
@@ -191,25 +286,69 @@ pub fn run(config: Config) -> ::anyhow::Result<()> {
         join_handles.push((WorkerType::Signals, handle));
     }
 
+    // Only report readiness once all sockets have been created and
+    // privileges have been dropped
+    let ready = priv_dropper
+        .wait_until_sockets_ready(|| join_handles.iter().any(|(_, handle)| handle.is_finished()));
+
+    if ready {
+        status_notifier.ready();
+    }
+
+    let opt_watchdog_interval = status_notifier.watchdog_interval();
+    let sleep_duration = opt_watchdog_interval
+        .unwrap_or(Duration::MAX)
+        .min(Duration::from_secs(5));
+
     loop {
         for (i, (_, handle)) in join_handles.iter().enumerate() {
             if handle.is_finished() {
                 let (worker_type, handle) = join_handles.remove(i);
 
+                status_notifier.stopping();
+
                 match handle.join() {
+                    Ok(Ok(())) if state.shutting_down.load(Ordering::Relaxed) => {
+                        ::log::info!("shut down");
+
+                        return Ok(());
+                    }
                     Ok(Ok(())) => {
                         return Err(anyhow::anyhow!("{} stopped", worker_type));
                     }
                     Ok(Err(err)) => {
                         return Err(err.context(format!("{} stopped", worker_type)));
                     }
-                    Err(_) => {
-                        return Err(anyhow::anyhow!("{} panicked", worker_type));
+                    Err(payload) => {
+                        return Err(anyhow::anyhow!(
+                            "{} panicked: {}",
+                            worker_type,
+                            panic_message(payload.as_ref())
+                        ));
                     }
                 }
             }
         }
 
-        sleep(Duration::from_secs(5));
+        if opt_watchdog_interval.is_some() {
+            status_notifier.watchdog();
+        }
+
+        let dropped_requests = state.dropped_requests.swap(0, Ordering::Relaxed);
+
+        if dropped_requests != 0 {
+            ::log::warn!(
+                "dropped {} announce requests since swarm worker channels were full",
+                dropped_requests
+            );
+        }
+
+        if state.shutting_down.load(Ordering::Relaxed) {
+            // Signal handler thread may not have finished when unparking
+            // this thread
+            park_timeout(Duration::from_millis(10));
+        } else {
+            park_timeout(sleep_duration);
+        }
     }
 }
@@ -1,23 +1,31 @@
 use std::borrow::Cow;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
 use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::Context;
 use aquatic_common::access_list::{create_access_list_cache, AccessListArcSwap, AccessListCache};
+use aquatic_common::ban_list::BanListArcSwap;
+use aquatic_common::greylist::Greylist;
+use aquatic_common::load_shedding::AnnounceCeiling;
 use aquatic_common::rustls_config::RustlsConfig;
-use aquatic_common::ServerStartInstant;
-use aquatic_ws_protocol::common::{InfoHash, PeerId, ScrapeAction};
+use aquatic_common::signed_urls::{SignedUrlError, SignedUrlVerifier};
+use aquatic_common::{CanonicalSocketAddr, ServerStartInstant};
+use aquatic_ws_protocol::common::{AnnounceAction, InfoHash, PeerId, ScrapeAction};
 use aquatic_ws_protocol::incoming::{
     AnnounceEvent, AnnounceRequest, InMessage, ScrapeRequest, ScrapeRequestInfoHashes,
 };
 use aquatic_ws_protocol::outgoing::{
-    ErrorResponse, ErrorResponseAction, OutMessage, ScrapeResponse, ScrapeStatistics,
+    AnnounceResponse, ErrorResponse, ErrorResponseAction, OutMessage, ScrapeResponse,
+    ScrapeStatistics,
 };
 use arc_swap::ArcSwap;
 use async_tungstenite::WebSocketStream;
+use futures::future::pending;
 use futures::stream::{SplitSink, SplitStream};
 use futures::{AsyncWriteExt, StreamExt};
 use futures_lite::future::race;
@@ -25,7 +33,7 @@ use futures_rustls::TlsAcceptor;
 use glommio::channels::channel_mesh::Senders;
 use glommio::channels::local_channel::{LocalReceiver, LocalSender};
 use glommio::net::TcpStream;
-use glommio::timer::timeout;
+use glommio::timer::{sleep, timeout};
 use glommio::{enclose, prelude::*};
 use hashbrown::hash_map::Entry;
 use hashbrown::HashMap;
@@ -35,8 +43,15 @@ use slab::Slab;
 use metrics::{Counter, Gauge};
 
 use crate::common::*;
-use crate::config::Config;
-use crate::workers::socket::calculate_in_message_consumer_index;
+use crate::config::{ChannelFullPolicy, Config};
+use crate::enrichment::{Enrichers, PeerMetadata};
+use crate::workers::socket::origin::origin_allowed;
+use crate::workers::socket::proxy::{parse_forwarded_header, read_proxy_protocol_header};
+use crate::workers::socket::token_bucket::TokenBucket;
+use crate::workers::socket::{
+    calculate_in_message_consumer_index, out_message_size, record_greylist_failure,
+    reject_if_banned, reject_if_greylisted,
+};
 
 #[cfg(feature = "metrics")]
 use crate::workers::socket::{ip_version_to_metrics_str, WORKER_INDEX};
@@ -52,38 +67,105 @@ pub struct ConnectionRunner {
     pub connection_valid_until: Rc<RefCell<ValidUntil>>,
     pub out_message_sender: Rc<LocalSender<(OutMessageMeta, OutMessage)>>,
     pub out_message_receiver: LocalReceiver<(OutMessageMeta, OutMessage)>,
+    /// Receives message when tracker is shutting down
+    pub shutdown_receiver: LocalReceiver<()>,
+    /// Size of outgoing messages waiting to be sent, if
+    /// network.websocket_max_pending_bytes is set
+    pub pending_out_bytes: Rc<Cell<usize>>,
     pub server_start_instant: ServerStartInstant,
     pub out_message_consumer_id: ConsumerId,
     pub connection_id: ConnectionId,
     pub opt_tls_config: Option<Arc<ArcSwap<RustlsConfig>>>,
-    pub ip_version: IpVersion,
+    /// Updated if peer IP is extracted from PROXY protocol or reverse proxy
+    /// header
+    pub peer_ip: IpAddr,
+    /// Connection is from a trusted reverse proxy, so PROXY protocol and
+    /// reverse proxy headers can be used, if enabled
+    pub from_trusted_proxy: bool,
+    /// Peer IP is the address of a trusted proxy, so failures can't be
+    /// counted towards greylisting it. Cleared when actual peer IP is
+    /// extracted.
+    pub peer_ip_is_proxy: bool,
+    pub connection_counts: Arc<ConnectionCounts>,
+    pub enrichers: Arc<Enrichers>,
+    pub announce_ceiling: Arc<AnnounceCeiling>,
+    pub dropped_requests: Arc<AtomicUsize>,
+    /// Verifier for signed URL tokens, if signed URLs are enabled
+    pub opt_signed_url_verifier: Option<Rc<SignedUrlVerifier>>,
+    pub ban_list: Arc<BanListArcSwap>,
+    pub greylist: Rc<RefCell<Greylist>>,
 }
 
 impl ConnectionRunner {
     pub async fn run(
-        self,
+        mut self,
         control_message_senders: Rc<Senders<SwarmControlMessage>>,
         close_conn_receiver: LocalReceiver<()>,
-        stream: TcpStream,
+        mut stream: TcpStream,
     ) {
+        let config = self.config.clone();
+        let connection_id = self.connection_id;
+
+        if config.network.enable_proxy_protocol {
+            if !self.from_trusted_proxy {
+                ::log::debug!(
+                    "connection {:?} closed: PROXY protocol header expected from untrusted address",
+                    connection_id,
+                );
+
+                return;
+            }
+
+            let result = timeout(Duration::from_secs(10), async {
+                Ok(read_proxy_protocol_header(&mut stream).await)
+            })
+            .await
+            .map_err(|err| anyhow::anyhow!("reading took too long: {:#}", err));
+
+            match result {
+                Ok(Ok(Some(addr))) => {
+                    self.peer_ip = addr.ip();
+                    self.peer_ip_is_proxy = false;
+
+                    if reject_if_banned(&config, &self.ban_list.load(), self.peer_ip)
+                        || reject_if_greylisted(
+                            &config,
+                            &self.greylist,
+                            self.peer_ip,
+                            self.server_start_instant,
+                        )
+                    {
+                        return;
+                    }
+                }
+                Ok(Ok(None)) => (),
+                Ok(Err(err)) | Err(err) => {
+                    ::log::debug!(
+                        "connection {:?} closed: couldn't read PROXY protocol header: {:#}",
+                        connection_id,
+                        err
+                    );
+
+                    return;
+                }
+            }
+        }
+
+        let ip_version = IpVersion::canonical_from_ip(self.peer_ip);
+
         let clean_up_data = ConnectionCleanupData {
             announced_info_hashes: Default::default(),
-            ip_version: self.ip_version,
+            ip_version: Rc::new(Cell::new(ip_version)),
+            out_message_consumer_id: self.out_message_consumer_id,
+            connection_id: self.connection_id,
             #[cfg(feature = "metrics")]
             opt_peer_client: Default::default(),
             #[cfg(feature = "metrics")]
-            active_connections_gauge: ::metrics::gauge!(
-                "aquatic_active_connections",
-                "ip_version" => ip_version_to_metrics_str(self.ip_version),
-                "worker_index" => WORKER_INDEX.get().to_string(),
-            ),
+            active_connections_gauge: Rc::new(RefCell::new(active_connections_gauge(ip_version))),
         };
 
         clean_up_data.before_open();
 
-        let config = self.config.clone();
-        let connection_id = self.connection_id;
-
         race(
             async {
                 if let Err(err) = self.run_inner(clean_up_data.clone(), stream).await {
@@ -105,6 +187,21 @@ impl ConnectionRunner {
         ::log::debug!("connection {:?} finished clean up", connection_id);
     }
 
+    /// Count failed TLS or WebSocket handshake towards greylisting network
+    /// prefix of peer. Skipped when connection is from a trusted proxy and
+    /// peer IP wasn't sent in a PROXY protocol header, since it isn't known
+    /// before the handshake has succeeded.
+    fn record_handshake_failure(&self) {
+        if !self.peer_ip_is_proxy {
+            record_greylist_failure(
+                &self.config,
+                &self.greylist,
+                self.peer_ip,
+                self.server_start_instant,
+            );
+        }
+    }
+
     async fn run_inner(
         self,
         clean_up_data: ConnectionCleanupData,
@@ -114,7 +211,14 @@ impl ConnectionRunner {
             let tls_config = tls_config.load_full();
             let tls_acceptor = TlsAcceptor::from(tls_config);
 
-            let stream = tls_acceptor.accept(stream).await?;
+            let stream = match tls_acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(err) => {
+                    self.record_handshake_failure();
+
+                    return Err(err.into());
+                }
+            };
 
             self.run_inner_stream_agnostic(clean_up_data, stream).await
         } else {
@@ -152,7 +256,7 @@ impl ConnectionRunner {
     }
 
     async fn run_inner_stream_agnostic<S>(
-        self,
+        mut self,
         clean_up_data: ConnectionCleanupData,
         stream: S,
     ) -> anyhow::Result<()>
@@ -166,54 +270,183 @@ impl ConnectionRunner {
             max_write_buffer_size: self.config.network.websocket_write_buffer_size * 3,
             ..Default::default()
         };
-        let stream = async_tungstenite::accept_async_with_config(stream, Some(ws_config)).await?;
+        let peer_ip = self.peer_ip;
+        let opt_forwarded_peer_ip = Rc::new(Cell::new(None));
+        let opt_connection_slot = Rc::new(Cell::new(None));
+        let opt_signed_url_token = Rc::new(RefCell::new(None));
+
+        // Error response type is determined by tungstenite
+        #[allow(clippy::result_large_err)]
+        let handshake_callback = enclose!((
+            self.config => config,
+            self.connection_counts => connection_counts,
+            self.from_trusted_proxy => from_trusted_proxy,
+            opt_forwarded_peer_ip,
+            opt_connection_slot,
+            opt_signed_url_token
+        ) move |
+            request: &tungstenite::handshake::server::Request,
+            response,
+        | {
+            let (response, opt_peer_ip) =
+                check_handshake_request(&config, from_trusted_proxy, request, response)?;
+
+            let connection_slot = acquire_connection_slot(
+                &config,
+                &connection_counts,
+                opt_peer_ip.unwrap_or(peer_ip),
+            )?;
+
+            opt_forwarded_peer_ip.set(opt_peer_ip);
+            opt_connection_slot.set(Some(connection_slot));
+            // Decode like passkeys in aquatic_http, so that the same
+            // tokens work with both
+            *opt_signed_url_token.borrow_mut() =
+                split_signed_url_token(&config, request.uri().path())
+                    .1
+                    .and_then(|token| ::urlencoding::decode(token).ok())
+                    .map(Cow::into_owned);
+
+            Ok(response)
+        });
+
+        let stream = match async_tungstenite::accept_hdr_async_with_config(
+            stream,
+            handshake_callback,
+            Some(ws_config),
+        )
+        .await
+        {
+            Ok(stream) => stream,
+            Err(err) => {
+                self.record_handshake_failure();
+
+                return Err(err.into());
+            }
+        };
+
+        // Keep connection counted until it is closed
+        let _connection_slot = opt_connection_slot.take();
+
+        if let Some(peer_ip) = opt_forwarded_peer_ip.get() {
+            self.peer_ip = peer_ip;
+            self.peer_ip_is_proxy = false;
+
+            clean_up_data.set_ip_version(IpVersion::canonical_from_ip(peer_ip));
+
+            if reject_if_banned(&self.config, &self.ban_list.load(), peer_ip) {
+                return Err(anyhow::anyhow!("peer address is banned"));
+            }
+
+            if reject_if_greylisted(
+                &self.config,
+                &self.greylist,
+                peer_ip,
+                self.server_start_instant,
+            ) {
+                return Err(anyhow::anyhow!("peer network prefix is greylisted"));
+            }
+        }
+
+        let ip_version = IpVersion::canonical_from_ip(self.peer_ip);
+        let opt_external_ip = self.config.protocol.send_external_ip.then(|| {
+            CanonicalSocketAddr::new(SocketAddr::new(self.peer_ip, 0))
+                .get()
+                .ip()
+        });
+        let peer_metadata = self.enrichers.enrich(self.peer_ip);
         let (ws_out, ws_in) = futures::StreamExt::split(stream);
 
         let pending_scrape_slab = Rc::new(RefCell::new(Slab::new()));
+        let unanswered_pings = Rc::new(Cell::new(0));
         let access_list_cache = create_access_list_cache(&self.access_list);
 
         let config = self.config.clone();
 
-        let reader_future = enclose!((pending_scrape_slab, clean_up_data) async move {
+        let pending_out_bytes = self.pending_out_bytes;
+
+        let reader_future = enclose!((pending_scrape_slab, pending_out_bytes, unanswered_pings, clean_up_data) async move {
             let mut reader = ConnectionReader {
                 config: self.config.clone(),
                 access_list_cache,
                 in_message_senders: self.in_message_senders,
                 out_message_sender: self.out_message_sender,
+                pending_out_bytes,
                 pending_scrape_slab,
                 out_message_consumer_id: self.out_message_consumer_id,
                 ws_in,
-                ip_version: self.ip_version,
+                ip_version,
+                peer_metadata,
                 connection_id: self.connection_id,
+                unanswered_pings,
                 clean_up_data: clean_up_data.clone(),
+                coalesced_announces: Default::default(),
+                opt_token_bucket: (self.config.protocol.message_rate_limit != 0).then(|| {
+                    TokenBucket::new(
+                        self.config.protocol.message_rate_limit_burst,
+                        self.config.protocol.message_rate_limit,
+                        Instant::now(),
+                    )
+                }),
+                rate_limit_violations: 0,
+                announce_ceiling: self.announce_ceiling,
+                dropped_requests: self.dropped_requests,
+                server_start_instant: self.server_start_instant,
+                greylist: self.greylist,
+                opt_greylist_peer_ip: (!self.peer_ip_is_proxy).then_some(self.peer_ip),
+                opt_signed_url_verifier: self.opt_signed_url_verifier,
+                opt_signed_url_token: opt_signed_url_token.take(),
                 #[cfg(feature = "metrics")]
                 total_announce_requests_counter: ::metrics::counter!(
                     "aquatic_requests_total",
                     "type" => "announce",
-                    "ip_version" => ip_version_to_metrics_str(self.ip_version),
+                    "ip_version" => ip_version_to_metrics_str(ip_version),
                     "worker_index" => WORKER_INDEX.with(|index| index.get()).to_string(),
                 ),
                 #[cfg(feature = "metrics")]
                 total_scrape_requests_counter: ::metrics::counter!(
                     "aquatic_requests_total",
                     "type" => "scrape",
-                    "ip_version" => ip_version_to_metrics_str(self.ip_version),
+                    "ip_version" => ip_version_to_metrics_str(ip_version),
                     "worker_index" => WORKER_INDEX.with(|index| index.get()).to_string(),
-                )
+                ),
+                #[cfg(feature = "metrics")]
+                dropped_requests_counter: ::metrics::counter!(
+                    "aquatic_dropped_requests_total",
+                    "type" => "announce",
+                    "ip_version" => ip_version_to_metrics_str(ip_version),
+                    "worker_index" => WORKER_INDEX.with(|index| index.get()).to_string(),
+                ),
+                #[cfg(feature = "metrics")]
+                coalesced_requests_counter: ::metrics::counter!(
+                    "aquatic_coalesced_requests_total",
+                    "type" => "announce",
+                    "ip_version" => ip_version_to_metrics_str(ip_version),
+                    "worker_index" => WORKER_INDEX.with(|index| index.get()).to_string(),
+                ),
             };
 
             reader.run_in_message_loop().await
         });
 
         let writer_future = async move {
+            let next_ping = (config.network.websocket_ping_interval != 0).then(|| {
+                Instant::now() + Duration::from_secs(config.network.websocket_ping_interval)
+            });
+
             let mut writer = ConnectionWriter {
                 config,
                 out_message_receiver: self.out_message_receiver,
+                shutdown_receiver: self.shutdown_receiver,
                 connection_valid_until: self.connection_valid_until,
                 ws_out,
+                pending_out_bytes,
                 pending_scrape_slab,
                 server_start_instant: self.server_start_instant,
-                ip_version: self.ip_version,
+                ip_version,
+                opt_external_ip,
+                unanswered_pings,
+                next_ping,
                 clean_up_data,
             };
 
@@ -229,43 +462,75 @@ struct ConnectionReader<S> {
     access_list_cache: AccessListCache,
     in_message_senders: Rc<Senders<(InMessageMeta, InMessage)>>,
     out_message_sender: Rc<LocalSender<(OutMessageMeta, OutMessage)>>,
+    pending_out_bytes: Rc<Cell<usize>>,
     pending_scrape_slab: Rc<RefCell<Slab<PendingScrapeResponse>>>,
     out_message_consumer_id: ConsumerId,
     ws_in: SplitStream<WebSocketStream<S>>,
     ip_version: IpVersion,
+    peer_metadata: PeerMetadata,
     connection_id: ConnectionId,
+    unanswered_pings: Rc<Cell<u8>>,
     clean_up_data: ConnectionCleanupData,
+    /// Announce windows by info hash, if announce coalescing is enabled
+    coalesced_announces: HashMap<InfoHash, CoalescedAnnounce>,
+    /// Message rate limit, if enabled
+    opt_token_bucket: Option<TokenBucket>,
+    /// Number of messages that exceeded rate limit
+    rate_limit_violations: usize,
+    announce_ceiling: Arc<AnnounceCeiling>,
+    dropped_requests: Arc<AtomicUsize>,
+    server_start_instant: ServerStartInstant,
+    greylist: Rc<RefCell<Greylist>>,
+    /// Peer IP to count failures for, unless it is the address of a trusted
+    /// proxy
+    opt_greylist_peer_ip: Option<IpAddr>,
+    /// Verifier for signed URL token, if signed URLs are enabled
+    opt_signed_url_verifier: Option<Rc<SignedUrlVerifier>>,
+    /// Percent-decoded last path segment of handshake request, if signed URLs are enabled
+    opt_signed_url_token: Option<String>,
     #[cfg(feature = "metrics")]
     total_announce_requests_counter: Counter,
     #[cfg(feature = "metrics")]
     total_scrape_requests_counter: Counter,
+    #[cfg(feature = "metrics")]
+    dropped_requests_counter: Counter,
+    #[cfg(feature = "metrics")]
+    coalesced_requests_counter: Counter,
 }
 
 impl<S: futures::AsyncRead + futures::AsyncWrite + Unpin> ConnectionReader<S> {
     async fn run_in_message_loop(&mut self) -> anyhow::Result<()> {
         loop {
-            let message = self
-                .ws_in
-                .next()
+            let opt_message = if let Some(window_end) = self
+                .coalesced_announces
+                .values()
+                .map(|announce| announce.window_end)
+                .min()
+            {
+                race(async { Some(self.ws_in.next().await) }, async {
+                    sleep(window_end.saturating_duration_since(Instant::now())).await;
+
+                    None
+                })
                 .await
-                .ok_or_else(|| anyhow::anyhow!("Stream ended"))??;
+            } else {
+                Some(self.ws_in.next().await)
+            };
 
-            match &message {
-                tungstenite::Message::Text(_) | tungstenite::Message::Binary(_) => {
-                    match InMessage::from_ws_message(message) {
-                        Ok(InMessage::AnnounceRequest(request)) => {
-                            self.handle_announce_request(request).await?;
-                        }
-                        Ok(InMessage::ScrapeRequest(request)) => {
-                            self.handle_scrape_request(request).await?;
-                        }
-                        Err(err) => {
-                            ::log::debug!("Couldn't parse in_message: {:#}", err);
+            let Some(opt_message) = opt_message else {
+                self.send_coalesced_announce_requests().await;
 
-                            self.send_error_response("Invalid request".into(), None, None)
-                                .await?;
-                        }
-                    }
+                continue;
+            };
+
+            let message = opt_message.ok_or_else(|| anyhow::anyhow!("Stream ended"))??;
+
+            match &message {
+                tungstenite::Message::Text(text) => {
+                    self.handle_request_bytes(text.as_bytes()).await?;
+                }
+                tungstenite::Message::Binary(bytes) => {
+                    self.handle_request_bytes(bytes).await?;
                 }
                 tungstenite::Message::Ping(_) => {
                     ::log::trace!("Received ping message");
@@ -273,6 +538,8 @@ impl<S: futures::AsyncRead + futures::AsyncWrite + Unpin> ConnectionReader<S> {
                 }
                 tungstenite::Message::Pong(_) => {
                     ::log::trace!("Received pong message");
+
+                    self.unanswered_pings.set(0);
                 }
                 tungstenite::Message::Close(_) => {
                     ::log::debug!("Client sent close frame");
@@ -288,6 +555,39 @@ impl<S: futures::AsyncRead + futures::AsyncWrite + Unpin> ConnectionReader<S> {
         }
     }
 
+    async fn handle_request_bytes(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
+        // Parsing modifies the buffer, so parse a copy and keep the original
+        // for creating the error response
+        match InMessage::from_mut_slice(&mut bytes.to_vec()) {
+            Ok(message) if !self.take_message_token() => {
+                self.handle_rate_limited_message(message).await
+            }
+            Ok(InMessage::AnnounceRequest(request)) => self.handle_announce_request(request).await,
+            Ok(InMessage::ScrapeRequest(request)) => self.handle_scrape_request(request).await,
+            Err(err) => {
+                ::log::debug!("Couldn't parse in_message: {:#}", err);
+
+                if let Some(peer_ip) = self.opt_greylist_peer_ip {
+                    record_greylist_failure(
+                        &self.config,
+                        &self.greylist,
+                        peer_ip,
+                        self.server_start_instant,
+                    );
+                }
+
+                let ErrorResponse {
+                    failure_reason,
+                    action,
+                    info_hash,
+                } = ErrorResponse::invalid_request(bytes);
+
+                self.send_error_response(failure_reason, action, info_hash)
+                    .await
+            }
+        }
+    }
+
     // Silence RefCell lint due to false positives
     #[allow(clippy::await_holding_refcell_ref)]
     async fn handle_announce_request(&mut self, request: AnnounceRequest) -> anyhow::Result<()> {
@@ -296,13 +596,50 @@ impl<S: futures::AsyncRead + futures::AsyncWrite + Unpin> ConnectionReader<S> {
 
         let info_hash = request.info_hash;
 
-        if self
-            .access_list_cache
-            .load()
-            .allows(self.config.access_list.mode, &info_hash.0)
-        {
+        if !self.config.client_filter.allows(&request.peer_id.0) {
+            self.send_error_response(
+                self.config.client_filter.failure_reason().into(),
+                Some(ErrorResponseAction::Announce),
+                Some(info_hash),
+            )
+            .await?;
+
+            return Ok(());
+        }
+
+        if let Err(err) = self.check_signed_url(&info_hash) {
+            self.send_error_response(
+                err.failure_reason().into(),
+                Some(ErrorResponseAction::Announce),
+                Some(info_hash),
+            )
+            .await?;
+
+            return Ok(());
+        }
+
+        if self.check_access_list(&info_hash, "announce") {
             let mut announced_info_hashes = self.clean_up_data.announced_info_hashes.borrow_mut();
 
+            let max_torrents = self.config.protocol.max_torrents_per_connection;
+
+            if max_torrents != 0
+                && announced_info_hashes.len() >= max_torrents
+                && !announced_info_hashes.contains_key(&info_hash)
+            {
+                // Drop Rc borrow before awaiting
+                drop(announced_info_hashes);
+
+                self.send_error_response(
+                    "Too many torrents announced on connection".into(),
+                    Some(ErrorResponseAction::Announce),
+                    Some(info_hash),
+                )
+                .await?;
+
+                return Ok(());
+            }
+
             // Store peer id / check if stored peer id matches
             match announced_info_hashes.entry(request.info_hash) {
                 Entry::Occupied(entry) => {
@@ -365,18 +702,13 @@ impl<S: futures::AsyncRead + futures::AsyncWrite + Unpin> ConnectionReader<S> {
             // Drop Rc borrow before awaiting
             drop(announced_info_hashes);
 
-            let in_message = InMessage::AnnounceRequest(request);
+            if self.announce_ceiling_reached(&request) {
+                self.send_overloaded_announce_response(info_hash).await?;
 
-            let consumer_index = calculate_in_message_consumer_index(&self.config, info_hash);
+                return Ok(());
+            }
 
-            // Only fails when receiver is closed
-            self.in_message_senders
-                .send_to(
-                    consumer_index,
-                    (self.make_connection_meta(None), in_message),
-                )
-                .await
-                .unwrap();
+            self.coalesce_or_send_announce_request(request).await;
         } else {
             self.send_error_response(
                 "Info hash not allowed".into(),
@@ -389,12 +721,197 @@ impl<S: futures::AsyncRead + futures::AsyncWrite + Unpin> ConnectionReader<S> {
         Ok(())
     }
 
+    /// Count announce request towards `protocol.max_announces_per_second`,
+    /// returning true if it exceeds it and should be shed
+    ///
+    /// Requests with answers are never shed, since peers are waiting for
+    /// them to complete signaling.
+    fn announce_ceiling_reached(&self, request: &AnnounceRequest) -> bool {
+        self.announce_ceiling.active()
+            && request.answer.is_none()
+            && !self
+                .announce_ceiling
+                .try_acquire(self.server_start_instant.seconds_elapsed())
+    }
+
+    /// Send announce response without offers asking peer to announce again
+    /// after `protocol.overloaded_announce_interval` seconds
+    async fn send_overloaded_announce_response(&self, info_hash: InfoHash) -> anyhow::Result<()> {
+        #[cfg(feature = "metrics")]
+        ::metrics::counter!(
+            "aquatic_shed_announces_total",
+            "ip_version" => ip_version_to_metrics_str(self.ip_version),
+            "worker_index" => WORKER_INDEX.with(|index| index.get()).to_string(),
+        )
+        .increment(1);
+
+        let out_message = OutMessage::AnnounceResponse(AnnounceResponse {
+            action: AnnounceAction::Announce,
+            info_hash,
+            complete: 0,
+            incomplete: 0,
+            announce_interval: self.config.protocol.overloaded_announce_interval,
+            external_ip: None,
+        });
+
+        self.send_out_message(out_message).await
+    }
+
+    /// Check token from handshake request path, if signed URLs are enabled
+    fn check_signed_url(&self, info_hash: &InfoHash) -> Result<(), SignedUrlError> {
+        match self.opt_signed_url_verifier.as_ref() {
+            Some(verifier) => verifier.verify(self.opt_signed_url_token.as_deref(), &info_hash.0),
+            None => Ok(()),
+        }
+    }
+
+    /// Check if info hash is allowed by access list, counting hits
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+    fn check_access_list(&mut self, info_hash: &InfoHash, request_type: &'static str) -> bool {
+        let mode = self.config.access_list.mode;
+        let check = self.access_list_cache.load().check(mode, &info_hash.0);
+
+        #[cfg(feature = "metrics")]
+        if check.hit {
+            ::metrics::counter!(
+                "aquatic_access_list_hits_total",
+                "list" => mode.as_str(),
+                "type" => request_type,
+                "ip_version" => ip_version_to_metrics_str(self.ip_version),
+                "worker_index" => WORKER_INDEX.with(|index| index.get()).to_string(),
+            )
+            .increment(1);
+        }
+
+        check.allowed
+    }
+
+    /// Pass on announce request to swarm worker, unless it should be
+    /// coalesced with following requests for the same torrent
+    async fn coalesce_or_send_announce_request(&mut self, request: AnnounceRequest) {
+        let window = self.config.protocol.announce_coalescing_window_ms;
+
+        if window == 0 {
+            self.send_announce_request(request).await;
+
+            return;
+        }
+
+        let now = Instant::now();
+
+        match request.event {
+            None | Some(AnnounceEvent::Update) if request.answer.is_none() => {
+                match self.coalesced_announces.entry(request.info_hash) {
+                    Entry::Occupied(mut entry) if entry.get().window_end > now => {
+                        if entry.get_mut().opt_request.replace(request).is_some() {
+                            #[cfg(feature = "metrics")]
+                            self.coalesced_requests_counter.increment(1);
+                        }
+
+                        return;
+                    }
+                    entry => {
+                        entry.insert(CoalescedAnnounce {
+                            window_end: now + Duration::from_millis(window),
+                            opt_request: None,
+                        });
+                    }
+                }
+            }
+            // Requests with answers don't update peer state that a held
+            // request would overwrite
+            None | Some(AnnounceEvent::Update) => (),
+            // Held request is older than request with event, so drop it
+            Some(_) => {
+                if let Some(CoalescedAnnounce {
+                    opt_request: Some(_),
+                    ..
+                }) = self.coalesced_announces.remove(&request.info_hash)
+                {
+                    #[cfg(feature = "metrics")]
+                    self.coalesced_requests_counter.increment(1);
+                }
+            }
+        }
+
+        self.send_announce_request(request).await;
+    }
+
+    /// Pass on held announce requests with ended windows to swarm workers
+    async fn send_coalesced_announce_requests(&mut self) {
+        let now = Instant::now();
+        let window = Duration::from_millis(self.config.protocol.announce_coalescing_window_ms);
+
+        let mut requests = Vec::new();
+
+        self.coalesced_announces.retain(|_, announce| {
+            if announce.window_end > now {
+                return true;
+            }
+
+            if let Some(request) = announce.opt_request.take() {
+                requests.push(request);
+
+                // Start new window, since request is passed on now
+                announce.window_end = now + window;
+
+                true
+            } else {
+                false
+            }
+        });
+
+        for request in requests {
+            self.send_announce_request(request).await;
+        }
+    }
+
+    async fn send_announce_request(&mut self, request: AnnounceRequest) {
+        let info_hash = request.info_hash;
+        let in_message = InMessage::AnnounceRequest(request);
+
+        let consumer_index = calculate_in_message_consumer_index(&self.config, info_hash);
+        let message = (self.make_connection_meta(None), in_message);
+
+        match self.config.request_channel_full_policy {
+            ChannelFullPolicy::Block => {
+                // Only fails when receiver is closed
+                self.in_message_senders
+                    .send_to(consumer_index, message)
+                    .await
+                    .unwrap();
+            }
+            ChannelFullPolicy::DropNewest => {
+                match self.in_message_senders.try_send_to(consumer_index, message) {
+                    Ok(()) => (),
+                    Err(GlommioError::WouldBlock(_)) => {
+                        ::log::debug!("swarm worker channel full, dropping announce request");
+
+                        self.dropped_requests.fetch_add(1, Ordering::Relaxed);
+
+                        #[cfg(feature = "metrics")]
+                        self.dropped_requests_counter.increment(1);
+                    }
+                    Err(err) => panic!("send announce request to swarm worker: {:#}", err),
+                }
+            }
+        }
+    }
+
     async fn handle_scrape_request(&mut self, request: ScrapeRequest) -> anyhow::Result<()> {
         #[cfg(feature = "metrics")]
         self.total_scrape_requests_counter.increment(1);
 
         let info_hashes = if let Some(info_hashes) = request.info_hashes {
-            info_hashes
+            info_hashes.as_vec()
+        } else if self.config.protocol.scrape_announced_torrents {
+            self.clean_up_data
+                .announced_info_hashes
+                .borrow()
+                .keys()
+                .take(self.config.protocol.max_scrape_torrents)
+                .copied()
+                .collect()
         } else {
             // If request.info_hashes is empty, don't return scrape for all
             // torrents, even though reference server does it. It is too expensive.
@@ -410,7 +927,13 @@ impl<S: futures::AsyncRead + futures::AsyncWrite + Unpin> ConnectionReader<S> {
 
         let mut info_hashes_by_worker: BTreeMap<usize, Vec<InfoHash>> = BTreeMap::new();
 
-        for info_hash in info_hashes.as_vec() {
+        for info_hash in info_hashes {
+            if self.check_signed_url(&info_hash).is_err()
+                || !self.check_access_list(&info_hash, "scrape")
+            {
+                continue;
+            }
+
             let info_hashes = info_hashes_by_worker
                 .entry(calculate_in_message_consumer_index(&self.config, info_hash))
                 .or_default();
@@ -418,6 +941,12 @@ impl<S: futures::AsyncRead + futures::AsyncWrite + Unpin> ConnectionReader<S> {
             info_hashes.push(info_hash);
         }
 
+        // Let a swarm worker send an empty response if there are no info
+        // hashes, since responses are only sent once all workers have replied
+        if info_hashes_by_worker.is_empty() {
+            info_hashes_by_worker.insert(0, Vec::new());
+        }
+
         let pending_worker_out_messages = info_hashes_by_worker.len();
 
         let pending_scrape_response = PendingScrapeResponse {
@@ -450,24 +979,99 @@ impl<S: futures::AsyncRead + futures::AsyncWrite + Unpin> ConnectionReader<S> {
         Ok(())
     }
 
+    /// Returns false if message exceeds rate limit
+    fn take_message_token(&mut self) -> bool {
+        self.opt_token_bucket
+            .as_mut()
+            .map_or(true, |bucket| bucket.try_take(Instant::now()))
+    }
+
+    /// Send error response to message exceeding rate limit. Returns error,
+    /// closing the connection, if it has exceeded the limit too many times.
+    async fn handle_rate_limited_message(&mut self, message: InMessage) -> anyhow::Result<()> {
+        let (action, info_hash) = match &message {
+            InMessage::AnnounceRequest(request) => {
+                (ErrorResponseAction::Announce, Some(request.info_hash))
+            }
+            InMessage::ScrapeRequest(request) => {
+                let info_hash = match request.info_hashes {
+                    Some(ScrapeRequestInfoHashes::Single(info_hash)) => Some(info_hash),
+                    _ => None,
+                };
+
+                (ErrorResponseAction::Scrape, info_hash)
+            }
+        };
+
+        #[cfg(feature = "metrics")]
+        ::metrics::counter!(
+            "aquatic_rate_limited_requests_total",
+            "type" => match action {
+                ErrorResponseAction::Announce => "announce",
+                ErrorResponseAction::Scrape => "scrape",
+            },
+            "ip_version" => ip_version_to_metrics_str(self.ip_version),
+            "worker_index" => WORKER_INDEX.with(|index| index.get()).to_string(),
+        )
+        .increment(1);
+
+        self.send_error_response("Rate limit exceeded".into(), Some(action), info_hash)
+            .await?;
+
+        self.rate_limit_violations += 1;
+
+        let max_violations = self.config.protocol.message_rate_limit_max_violations;
+
+        if max_violations != 0 && self.rate_limit_violations >= max_violations {
+            #[cfg(feature = "metrics")]
+            ::metrics::counter!(
+                "aquatic_rate_limited_connections_closed_total",
+                "ip_version" => ip_version_to_metrics_str(self.ip_version),
+                "worker_index" => WORKER_INDEX.with(|index| index.get()).to_string(),
+            )
+            .increment(1);
+
+            return Err(anyhow::anyhow!(
+                "Connection exceeded message rate limit too many times"
+            ));
+        }
+
+        Ok(())
+    }
+
     async fn send_error_response(
         &self,
         failure_reason: Cow<'static, str>,
         action: Option<ErrorResponseAction>,
         info_hash: Option<InfoHash>,
     ) -> anyhow::Result<()> {
-        let out_message = OutMessage::ErrorResponse(ErrorResponse {
+        self.send_out_message(OutMessage::ErrorResponse(ErrorResponse {
             action,
             failure_reason,
             info_hash,
-        });
+        }))
+        .await
+    }
+
+    /// Send message created by reader to writer, skipping swarm workers
+    async fn send_out_message(&self, out_message: OutMessage) -> anyhow::Result<()> {
+        let size = if self.config.network.websocket_max_pending_bytes != 0 {
+            out_message_size(&out_message)
+        } else {
+            0
+        };
 
         self.out_message_sender
             .send((self.make_connection_meta(None).into(), out_message))
             .await
             .map_err(|err| {
-                anyhow::anyhow!("ConnectionReader::send_error_response failed: {:#}", err)
-            })
+                anyhow::anyhow!("ConnectionReader::send_out_message failed: {:#}", err)
+            })?;
+
+        self.pending_out_bytes
+            .set(self.pending_out_bytes.get() + size);
+
+        Ok(())
     }
 
     fn make_connection_meta(&self, pending_scrape_id: Option<PendingScrapeId>) -> InMessageMeta {
@@ -476,6 +1080,7 @@ impl<S: futures::AsyncRead + futures::AsyncWrite + Unpin> ConnectionReader<S> {
             out_message_consumer_id: self.out_message_consumer_id,
             ip_version: self.ip_version,
             pending_scrape_id,
+            peer_metadata: self.peer_metadata,
         }
     }
 }
@@ -483,11 +1088,18 @@ impl<S: futures::AsyncRead + futures::AsyncWrite + Unpin> ConnectionReader<S> {
 struct ConnectionWriter<S> {
     config: Rc<Config>,
     out_message_receiver: LocalReceiver<(OutMessageMeta, OutMessage)>,
+    shutdown_receiver: LocalReceiver<()>,
     connection_valid_until: Rc<RefCell<ValidUntil>>,
     ws_out: SplitSink<WebSocketStream<S>, tungstenite::Message>,
+    pending_out_bytes: Rc<Cell<usize>>,
     pending_scrape_slab: Rc<RefCell<Slab<PendingScrapeResponse>>>,
     server_start_instant: ServerStartInstant,
     ip_version: IpVersion,
+    /// Sent in announce responses if set
+    opt_external_ip: Option<IpAddr>,
+    unanswered_pings: Rc<Cell<u8>>,
+    /// Time to send next ping, if pings are enabled
+    next_ping: Option<Instant>,
     clean_up_data: ConnectionCleanupData,
 }
 
@@ -496,10 +1108,62 @@ impl<S: futures::AsyncRead + futures::AsyncWrite + Unpin> ConnectionWriter<S> {
     #[allow(clippy::await_holding_refcell_ref)]
     async fn run_out_message_loop(&mut self) -> anyhow::Result<()> {
         loop {
-            let (meta, out_message) = self.out_message_receiver.recv().await.ok_or_else(|| {
+            let next_ping = self.next_ping;
+            let out_message_receiver = &self.out_message_receiver;
+            let shutdown_receiver = &self.shutdown_receiver;
+
+            let event = race(
+                async { WriterEvent::OutMessage(out_message_receiver.recv().await) },
+                race(
+                    async {
+                        // Sender is only dropped after connection is closed
+                        if shutdown_receiver.recv().await.is_some() {
+                            WriterEvent::Shutdown
+                        } else {
+                            pending().await
+                        }
+                    },
+                    async {
+                        if let Some(next_ping) = next_ping {
+                            sleep(next_ping.saturating_duration_since(Instant::now())).await;
+
+                            WriterEvent::Ping
+                        } else {
+                            pending().await
+                        }
+                    },
+                ),
+            )
+            .await;
+
+            let opt_message = match event {
+                WriterEvent::OutMessage(opt_message) => opt_message,
+                WriterEvent::Ping => {
+                    self.send_ping().await?;
+
+                    continue;
+                }
+                WriterEvent::Shutdown => {
+                    self.send_close_frame().await?;
+
+                    // Wait for peer to answer with close frame, which ends
+                    // reader, or for tracker to quit
+                    pending::<()>().await;
+
+                    continue;
+                }
+            };
+
+            let (meta, out_message) = opt_message.ok_or_else(|| {
                 anyhow::anyhow!("ConnectionWriter couldn't receive message, sender is closed")
             })?;
 
+            let size = if self.config.network.websocket_max_pending_bytes != 0 {
+                out_message_size(&out_message)
+            } else {
+                0
+            };
+
             match out_message {
                 OutMessage::ScrapeResponse(out_message) => {
                     let pending_scrape_id = meta
@@ -532,15 +1196,79 @@ impl<S: futures::AsyncRead + futures::AsyncWrite + Unpin> ConnectionWriter<S> {
                         self.send_out_message(&out_message).await?;
                     }
                 }
+                OutMessage::AnnounceResponse(mut out_message) => {
+                    out_message.external_ip = self.opt_external_ip;
+
+                    self.send_out_message(&OutMessage::AnnounceResponse(out_message))
+                        .await?;
+                }
                 out_message => {
                     self.send_out_message(&out_message).await?;
                 }
             };
 
+            self.pending_out_bytes
+                .set(self.pending_out_bytes.get().saturating_sub(size));
+
             yield_if_needed().await;
         }
     }
 
+    async fn send_close_frame(&mut self) -> anyhow::Result<()> {
+        let close_frame = tungstenite::protocol::CloseFrame {
+            code: self.config.network.shutdown_close_code.into(),
+            reason: self.config.network.shutdown_close_reason.clone().into(),
+        };
+
+        timeout(Duration::from_secs(10), async {
+            Ok(futures::SinkExt::send(
+                &mut self.ws_out,
+                tungstenite::Message::Close(Some(close_frame)),
+            )
+            .await)
+        })
+        .await
+        .map_err(|err| {
+            anyhow::anyhow!("send_close_frame: sending to peer took too long: {:#}", err)
+        })?
+        .with_context(|| "send_close_frame")
+    }
+
+    async fn send_ping(&mut self) -> anyhow::Result<()> {
+        let unanswered_pings = self.unanswered_pings.get();
+
+        if unanswered_pings >= self.config.network.websocket_max_unanswered_pings {
+            #[cfg(feature = "metrics")]
+            ::metrics::counter!(
+                "aquatic_idle_connections_closed_total",
+                "reason" => "unanswered_pings",
+                "worker_index" => WORKER_INDEX.with(|index| index.get()).to_string(),
+            )
+            .increment(1);
+
+            return Err(anyhow::anyhow!(
+                "peer didn't answer {} pings",
+                unanswered_pings
+            ));
+        }
+
+        timeout(Duration::from_secs(10), async {
+            Ok(
+                futures::SinkExt::send(&mut self.ws_out, tungstenite::Message::Ping(Vec::new()))
+                    .await,
+            )
+        })
+        .await
+        .map_err(|err| anyhow::anyhow!("send_ping: sending to peer took too long: {:#}", err))?
+        .with_context(|| "send_ping")?;
+
+        self.unanswered_pings.set(unanswered_pings + 1);
+        self.next_ping =
+            Some(Instant::now() + Duration::from_secs(self.config.network.websocket_ping_interval));
+
+        Ok(())
+    }
+
     async fn send_out_message(&mut self, out_message: &OutMessage) -> anyhow::Result<()> {
         timeout(Duration::from_secs(10), async {
             Ok(futures::SinkExt::send(&mut self.ws_out, out_message.to_ws_message()).await)
@@ -598,17 +1326,32 @@ impl<S: futures::AsyncRead + futures::AsyncWrite + Unpin> ConnectionWriter<S> {
 #[derive(Clone)]
 struct ConnectionCleanupData {
     announced_info_hashes: Rc<RefCell<HashMap<InfoHash, PeerId>>>,
-    ip_version: IpVersion,
+    /// Updated if peer IP is extracted from reverse proxy header
+    ip_version: Rc<Cell<IpVersion>>,
+    out_message_consumer_id: ConsumerId,
+    connection_id: ConnectionId,
     #[cfg(feature = "metrics")]
     opt_peer_client: Rc<RefCell<Option<PeerClientGauge>>>,
     #[cfg(feature = "metrics")]
-    active_connections_gauge: Gauge,
+    active_connections_gauge: Rc<RefCell<Gauge>>,
 }
 
 impl ConnectionCleanupData {
     fn before_open(&self) {
         #[cfg(feature = "metrics")]
-        self.active_connections_gauge.increment(1.0);
+        self.active_connections_gauge.borrow().increment(1.0);
+    }
+    fn set_ip_version(&self, ip_version: IpVersion) {
+        #[cfg(feature = "metrics")]
+        if ip_version != self.ip_version.get() {
+            let gauge = active_connections_gauge(ip_version);
+
+            gauge.increment(1.0);
+
+            self.active_connections_gauge.replace(gauge).decrement(1.0);
+        }
+
+        self.ip_version.set(ip_version);
     }
     async fn after_close(
         &self,
@@ -628,7 +1371,9 @@ impl ConnectionCleanupData {
 
         for (consumer_index, announced_info_hashes) in announced_info_hashes.into_iter() {
             let message = SwarmControlMessage::ConnectionClosed {
-                ip_version: self.ip_version,
+                ip_version: self.ip_version.get(),
+                out_message_consumer_id: self.out_message_consumer_id,
+                connection_id: self.connection_id,
                 announced_info_hashes,
             };
 
@@ -639,7 +1384,7 @@ impl ConnectionCleanupData {
         }
 
         #[cfg(feature = "metrics")]
-        self.active_connections_gauge.decrement(1.0);
+        self.active_connections_gauge.borrow().decrement(1.0);
 
         #[cfg(feature = "metrics")]
         if let Some((peer_client_gauge, opt_peer_id_prefix_gauge)) = self.opt_peer_client.take() {
@@ -652,6 +1397,175 @@ impl ConnectionCleanupData {
     }
 }
 
+#[cfg(feature = "metrics")]
+fn active_connections_gauge(ip_version: IpVersion) -> Gauge {
+    ::metrics::gauge!(
+        "aquatic_active_connections",
+        "ip_version" => ip_version_to_metrics_str(ip_version),
+        "worker_index" => WORKER_INDEX.get().to_string(),
+    )
+}
+
+/// Split signed URL token (last path segment) from handshake request path
+/// if signed URLs are enabled, e.g., `/announce/<token>` into `/announce`
+/// and `<token>`
+fn split_signed_url_token<'a>(config: &Config, path: &'a str) -> (&'a str, Option<&'a str>) {
+    if !config.signed_urls.enabled {
+        return (path, None);
+    }
+
+    match path.rsplit_once('/') {
+        Some(("", token)) => ("/", Some(token)),
+        Some((path, token)) => (path, Some(token)),
+        None => (path, None),
+    }
+}
+
+/// Check WebSocket handshake request size, path and origin and extract peer
+/// IP from reverse proxy header if configured to do so and connection is
+/// from a trusted proxy
+#[allow(clippy::result_large_err)]
+fn check_handshake_request(
+    config: &Config,
+    from_trusted_proxy: bool,
+    request: &tungstenite::handshake::server::Request,
+    response: tungstenite::handshake::server::Response,
+) -> Result<
+    (tungstenite::handshake::server::Response, Option<IpAddr>),
+    tungstenite::handshake::server::ErrorResponse,
+> {
+    let target_size = request
+        .uri()
+        .path_and_query()
+        .map(|p| p.as_str().len())
+        .unwrap_or(0);
+    let headers_size: usize = request
+        .headers()
+        .iter()
+        .map(|(name, value)| name.as_str().len() + value.len())
+        .sum();
+
+    if target_size + headers_size > config.network.websocket_max_handshake_header_size {
+        return Err(handshake_error_response(
+            tungstenite::http::StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+        ));
+    }
+
+    let (path, _) = split_signed_url_token(config, request.uri().path());
+
+    if !config.network.websocket_paths.is_empty()
+        && !config
+            .network
+            .websocket_paths
+            .iter()
+            .any(|allowed_path| allowed_path == path)
+    {
+        return Err(handshake_error_response(
+            tungstenite::http::StatusCode::NOT_FOUND,
+        ));
+    }
+
+    let opt_origin = request
+        .headers()
+        .get(tungstenite::http::header::ORIGIN)
+        .map(|origin| origin.to_str().unwrap_or_default());
+
+    if !origin_allowed(&config.network, opt_origin) {
+        ::log::debug!("rejecting handshake from origin {:?}", opt_origin);
+
+        #[cfg(feature = "metrics")]
+        ::metrics::counter!(
+            "aquatic_rejected_handshakes_total",
+            "reason" => "origin",
+            "worker_index" => WORKER_INDEX.with(|index| index.get()).to_string(),
+        )
+        .increment(1);
+
+        return Err(handshake_error_response(
+            tungstenite::http::StatusCode::FORBIDDEN,
+        ));
+    }
+
+    let opt_peer_ip = if config.network.runs_behind_reverse_proxy && from_trusted_proxy {
+        match parse_forwarded_header(
+            &config.network.reverse_proxy_ip_header_name,
+            config.network.reverse_proxy_ip_header_format,
+            request.headers(),
+        ) {
+            Ok(peer_ip) => Some(peer_ip),
+            Err(err) => {
+                ::log::warn!(
+                    "Tracker configured as running behind reverse proxy, but no valid IP header set in WebSocket handshake request: {:#}",
+                    err
+                );
+
+                return Err(handshake_error_response(
+                    tungstenite::http::StatusCode::BAD_REQUEST,
+                ));
+            }
+        }
+    } else {
+        None
+    };
+
+    Ok((response, opt_peer_ip))
+}
+
+/// Count connection towards connection limits, rejecting WebSocket handshake
+/// if a limit has been reached
+#[allow(clippy::result_large_err)]
+fn acquire_connection_slot(
+    config: &Config,
+    connection_counts: &Arc<ConnectionCounts>,
+    peer_ip: IpAddr,
+) -> Result<ConnectionSlot, tungstenite::handshake::server::ErrorResponse> {
+    connection_counts
+        .try_acquire(
+            config.network.max_connections,
+            config.network.max_connections_per_ip,
+            peer_ip,
+        )
+        .map_err(|limit| {
+            ::log::debug!("rejecting handshake, connection limit reached: {:?}", limit);
+
+            #[cfg(feature = "metrics")]
+            ::metrics::counter!(
+                "aquatic_rejected_handshakes_total",
+                "reason" => match limit {
+                    ConnectionLimit::Total => "max_connections",
+                    ConnectionLimit::PerIp => "max_connections_per_ip",
+                },
+                "worker_index" => WORKER_INDEX.with(|index| index.get()).to_string(),
+            )
+            .increment(1);
+
+            handshake_error_response(tungstenite::http::StatusCode::SERVICE_UNAVAILABLE)
+        })
+}
+
+fn handshake_error_response(
+    status: tungstenite::http::StatusCode,
+) -> tungstenite::handshake::server::ErrorResponse {
+    let mut error_response = tungstenite::handshake::server::ErrorResponse::new(None);
+
+    *error_response.status_mut() = status;
+
+    error_response
+}
+
+enum WriterEvent {
+    OutMessage(Option<(OutMessageMeta, OutMessage)>),
+    Ping,
+    Shutdown,
+}
+
+struct CoalescedAnnounce {
+    window_end: Instant,
+    /// Newest announce request received during window, to be passed on when
+    /// window ends
+    opt_request: Option<AnnounceRequest>,
+}
+
 struct PendingScrapeResponse {
     pending_worker_out_messages: usize,
     stats: HashMap<InfoHash, ScrapeStatistics>,
@@ -1,13 +1,20 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::net::{IpAddr, SocketAddr};
 use std::os::unix::prelude::{FromRawFd, IntoRawFd};
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::Context;
+use aquatic_common::ban_list::{create_ban_list_cache, BanList};
+use aquatic_common::greylist::Greylist;
+use aquatic_common::ip_network::IpNetworks;
 use aquatic_common::privileges::PrivilegeDropper;
 use aquatic_common::rustls_config::RustlsConfig;
-use aquatic_common::ServerStartInstant;
+use aquatic_common::signed_urls::SignedUrlVerifier;
+use aquatic_common::systemd;
+use aquatic_common::{CanonicalSocketAddr, ServerStartInstant};
 use aquatic_ws_protocol::common::InfoHash;
 use aquatic_ws_protocol::incoming::InMessage;
 use aquatic_ws_protocol::outgoing::OutMessage;
@@ -21,23 +28,26 @@ use glommio::timer::TimerActionRepeat;
 use glommio::{enclose, prelude::*};
 use slotmap::HopSlotMap;
 
-use crate::config::Config;
+use crate::config::{Config, ConnectionChannelFullPolicy};
 
 use crate::common::*;
 use crate::workers::socket::connection::ConnectionRunner;
 
 mod connection;
+pub(crate) mod origin;
+mod proxy;
+mod token_bucket;
 
 type ConnectionHandles = HopSlotMap<ConnectionId, ConnectionHandle>;
 
-const LOCAL_CHANNEL_SIZE: usize = 16;
-
 #[cfg(feature = "metrics")]
 thread_local! { static WORKER_INDEX: ::std::cell::Cell<usize> = Default::default() }
 
 /// Used to interact with the connection tasks
 struct ConnectionHandle {
     close_conn_sender: LocalSender<()>,
+    /// Tells connection to send close frame to peer
+    shutdown_sender: LocalSender<()>,
     /// Sender part of channel used to pass on outgoing messages from request
     /// worker
     out_message_sender: Rc<LocalSender<(OutMessageMeta, OutMessage)>>,
@@ -46,6 +56,11 @@ struct ConnectionHandle {
     /// The TLS config used for this connection
     opt_tls_config: Option<Arc<RustlsConfig>>,
     valid_until_after_tls_update: Option<ValidUntil>,
+    /// Size of outgoing messages waiting to be sent, if
+    /// network.websocket_max_pending_bytes is set
+    pending_out_bytes: Rc<Cell<usize>>,
+    /// When pending_out_bytes went above network.websocket_max_pending_bytes
+    pending_out_bytes_exceeded_at: Cell<Option<Instant>>,
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -65,6 +80,21 @@ pub async fn run_socket_worker(
 
     let config = Rc::new(config);
     let access_list = state.access_list;
+    let ban_list = state.ban_list;
+    let mut ban_list_cache = create_ban_list_cache(&ban_list);
+    let connection_counts = state.connection_counts;
+    let shutting_down = state.shutting_down;
+    let open_connections = state.open_connections;
+    let enrichers = state.enrichers;
+    let announce_ceiling = state.announce_ceiling;
+    let dropped_requests = state.dropped_requests;
+    let greylist = Rc::new(RefCell::new(Greylist::new(&config.greylist)));
+    let opt_signed_url_verifier = config
+        .signed_urls
+        .enabled
+        .then(|| Rc::new(SignedUrlVerifier::new(&config.signed_urls)));
+    let trusted_proxies = IpNetworks::parse(&config.network.reverse_proxy_trusted_networks)
+        .context("parse reverse proxy trusted networks")?;
 
     let listener = create_tcp_listener(&config, priv_dropper).context("create tcp listener")?;
 
@@ -120,9 +150,22 @@ pub async fn run_socket_worker(
     )
     .map_err(|err| anyhow::anyhow!("spawn connection cleaning task: {:#}", err))?;
 
+    // Periodically check if tracker is shutting down
+    TimerActionRepeat::repeat_into(
+        enclose!((shutting_down, connection_handles) move || {
+            check_shutdown(shutting_down.clone(), connection_handles.clone())
+        }),
+        tq_prioritized,
+    )
+    .map_err(|err| anyhow::anyhow!("spawn shutdown checking task: {:#}", err))?;
+
     for (_, out_message_receiver) in out_message_receivers.streams() {
         spawn_local_into(
-            receive_out_messages(out_message_receiver, connection_handles.clone()),
+            receive_out_messages(
+                config.clone(),
+                out_message_receiver,
+                connection_handles.clone(),
+            ),
             tq_regular,
         )
         .map_err(|err| anyhow::anyhow!("spawn out message receiving task: {:#}", err))?
@@ -137,19 +180,47 @@ pub async fn run_socket_worker(
                 ::log::error!("accept connection: {:#}", err);
             }
             Ok(stream) => {
-                let ip_version = match stream.peer_addr() {
-                    Ok(addr) => IpVersion::canonical_from_ip(addr.ip()),
+                if shutting_down.load(Ordering::Relaxed) {
+                    continue;
+                }
+
+                let peer_addr = match stream.peer_addr() {
+                    Ok(addr) => addr,
                     Err(err) => {
-                        ::log::info!("could not extract ip version (v4 or v6): {:#}", err);
+                        ::log::info!("could not extract peer address: {:#}", err);
 
                         continue;
                     }
                 };
 
-                let (out_message_sender, out_message_receiver) = new_bounded(LOCAL_CHANNEL_SIZE);
+                let peer_ip = peer_addr.ip();
+
+                // Close connection by dropping stream
+                if reject_if_banned(&config, ban_list_cache.load(), peer_ip) {
+                    continue;
+                }
+
+                // Only let trusted proxies send peer IPs
+                let from_trusted_proxy = (config.network.runs_behind_reverse_proxy
+                    || config.network.enable_proxy_protocol)
+                    && trusted_proxies.contains(CanonicalSocketAddr::new(peer_addr).get().ip());
+
+                // Proxies are shared by many peers, so only greylist peer IPs
+                // they send
+                if !from_trusted_proxy
+                    && reject_if_greylisted(&config, &greylist, peer_ip, server_start_instant)
+                {
+                    continue;
+                }
+
+                let (out_message_sender, out_message_receiver) =
+                    new_bounded(config.connection_channel_size);
                 let out_message_sender = Rc::new(out_message_sender);
 
                 let (close_conn_sender, close_conn_receiver) = new_bounded(1);
+                let (shutdown_sender, shutdown_receiver) = new_bounded(1);
+
+                let pending_out_bytes = Rc::new(Cell::new(0));
 
                 let connection_valid_until = Rc::new(RefCell::new(ValidUntil::new(
                     server_start_instant,
@@ -158,41 +229,68 @@ pub async fn run_socket_worker(
 
                 let connection_handle = ConnectionHandle {
                     close_conn_sender,
+                    shutdown_sender,
                     out_message_sender: out_message_sender.clone(),
                     valid_until: connection_valid_until.clone(),
                     opt_tls_config: opt_tls_config.as_ref().map(|c| c.load_full()),
                     valid_until_after_tls_update: None,
+                    pending_out_bytes: pending_out_bytes.clone(),
+                    pending_out_bytes_exceeded_at: Cell::new(None),
                 };
 
                 let connection_id = connection_handles.borrow_mut().insert(connection_handle);
 
+                open_connections.fetch_add(1, Ordering::Relaxed);
+
                 spawn_local_into(
                     enclose!((
                         config,
                         access_list,
+                        connection_counts,
+                        enrichers,
+                        announce_ceiling,
+                        dropped_requests,
+                        opt_signed_url_verifier,
+                        ban_list,
+                        greylist,
                         in_message_senders,
                         connection_valid_until,
+                        pending_out_bytes,
                         opt_tls_config,
                         control_message_senders,
-                        connection_handles
+                        connection_handles,
+                        open_connections
                     ) async move {
                         let runner = ConnectionRunner {
                             config,
                             access_list,
+                            connection_counts,
+                            enrichers,
+                            announce_ceiling,
+                            dropped_requests,
+                            opt_signed_url_verifier,
+                            ban_list,
+                            greylist,
                             in_message_senders,
                             connection_valid_until,
                             out_message_sender,
                             out_message_receiver,
+                            shutdown_receiver,
+                            pending_out_bytes,
                             server_start_instant,
                             out_message_consumer_id,
                             connection_id,
                             opt_tls_config,
-                            ip_version
+                            peer_ip,
+                            from_trusted_proxy,
+                            peer_ip_is_proxy: from_trusted_proxy,
                         };
 
                         runner.run(control_message_senders, close_conn_receiver, stream).await;
 
                         connection_handles.borrow_mut().remove(connection_id);
+
+                        open_connections.fetch_sub(1, Ordering::Relaxed);
                     }),
                     tq_regular,
                 )
@@ -233,7 +331,19 @@ async fn clean_connections(
             ));
         }
 
-        keep &= reference.valid_until.borrow().valid(now);
+        let idle = !reference.valid_until.borrow().valid(now);
+
+        #[cfg(feature = "metrics")]
+        if idle {
+            ::metrics::counter!(
+                "aquatic_idle_connections_closed_total",
+                "reason" => "no_responses",
+                "worker_index" => WORKER_INDEX.with(|index| index.get()).to_string(),
+            )
+            .increment(1);
+        }
+
+        keep &= !idle;
 
         if keep {
             true
@@ -282,21 +392,97 @@ async fn clean_connections(
     ))
 }
 
+async fn check_shutdown(
+    shutting_down: Arc<AtomicBool>,
+    connection_handles: Rc<RefCell<ConnectionHandles>>,
+) -> Option<Duration> {
+    if !shutting_down.load(Ordering::Relaxed) {
+        return Some(Duration::from_millis(100));
+    }
+
+    for (_, handle) in connection_handles.borrow().iter() {
+        // Fails if connection has already been told to shut down
+        let _ = handle.shutdown_sender.try_send(());
+    }
+
+    // New connections are rejected from now on, so there is no need to
+    // check again
+    None
+}
+
 async fn receive_out_messages(
+    config: Rc<Config>,
     mut out_message_receiver: ConnectedReceiver<(OutMessageMeta, OutMessage)>,
     connection_references: Rc<RefCell<ConnectionHandles>>,
 ) {
     let connection_references = &connection_references;
 
+    #[cfg(feature = "metrics")]
+    let dropped_responses_counter = ::metrics::counter!(
+        "aquatic_dropped_responses_total",
+        "worker_index" => WORKER_INDEX.with(|index| index.get()).to_string(),
+    );
+
+    #[cfg(feature = "metrics")]
+    let slow_connections_closed_counter = ::metrics::counter!(
+        "aquatic_slow_connections_closed_total",
+        "worker_index" => WORKER_INDEX.with(|index| index.get()).to_string(),
+    );
+
+    let max_pending_bytes = config.network.websocket_max_pending_bytes;
+    let grace_period = Duration::from_secs(config.network.websocket_slow_client_grace_period);
+
     while let Some((meta, out_message)) = out_message_receiver.next().await {
         if let Some(reference) = connection_references.borrow().get(meta.connection_id) {
+            let size = if max_pending_bytes != 0 {
+                out_message_size(&out_message)
+            } else {
+                0
+            };
+
             match reference.out_message_sender.try_send((meta, out_message)) {
+                Ok(()) if max_pending_bytes != 0 => {
+                    let pending_bytes = reference.pending_out_bytes.get() + size;
+
+                    reference.pending_out_bytes.set(pending_bytes);
+
+                    if pending_bytes <= max_pending_bytes {
+                        reference.pending_out_bytes_exceeded_at.set(None);
+                    } else if let Some(exceeded_at) = reference.pending_out_bytes_exceeded_at.get()
+                    {
+                        if exceeded_at.elapsed() >= grace_period
+                            && reference.close_conn_sender.try_send(()).is_ok()
+                        {
+                            ::log::debug!(
+                                "closing connection with {} bytes of pending messages",
+                                pending_bytes
+                            );
+
+                            #[cfg(feature = "metrics")]
+                            slow_connections_closed_counter.increment(1);
+                        }
+                    } else {
+                        reference
+                            .pending_out_bytes_exceeded_at
+                            .set(Some(Instant::now()));
+                    }
+                }
                 Ok(()) => {}
                 Err(GlommioError::Closed(_)) => {}
                 Err(GlommioError::WouldBlock(_)) => {
                     ::log::debug!(
                         "couldn't send OutMessage over local channel to Connection, channel full"
                     );
+
+                    #[cfg(feature = "metrics")]
+                    dropped_responses_counter.increment(1);
+
+                    if let ConnectionChannelFullPolicy::CloseConnection =
+                        config.connection_channel_full_policy
+                    {
+                        // Fails if connection has already been told to close
+                        let _ = reference.close_conn_sender.try_send(());
+                    }
                 }
                 Err(err) => {
                     ::log::debug!(
@@ -309,10 +495,41 @@ async fn receive_out_messages(
     }
 }
 
+/// Calculate size of serialized message without allocating
+fn out_message_size(message: &OutMessage) -> usize {
+    struct ByteCounter(usize);
+
+    impl ::std::io::Write for ByteCounter {
+        fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize> {
+            self.0 += buf.len();
+
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> ::std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut counter = ByteCounter(0);
+
+    ::serde_json::to_writer(&mut counter, message).unwrap();
+
+    counter.0
+}
+
 fn create_tcp_listener(
     config: &Config,
     priv_dropper: PrivilegeDropper,
 ) -> anyhow::Result<TcpListener> {
+    if config.network.systemd_socket_activation {
+        let fd = systemd::duplicate_listen_fd("ws")?;
+
+        priv_dropper.after_socket_creation()?;
+
+        return Ok(unsafe { TcpListener::from_raw_fd(fd.into_raw_fd()) });
+    }
+
     let domain = if config.network.address.is_ipv4() {
         socket2::Domain::IPV4
     } else {
@@ -359,6 +576,89 @@ fn create_tcp_listener(
     Ok(unsafe { TcpListener::from_raw_fd(socket.into_raw_fd()) })
 }
 
+/// Returns true and counts closed connection if ban list is enabled and peer
+/// IP is banned
+fn reject_if_banned(config: &Config, ban_list: &BanList, peer_ip: IpAddr) -> bool {
+    if !config.ban_list.enabled {
+        return false;
+    }
+
+    let banned = ban_list.is_banned(CanonicalSocketAddr::new(SocketAddr::new(peer_ip, 0)));
+
+    if banned {
+        ::log::debug!("closed connection from banned address");
+
+        #[cfg(feature = "metrics")]
+        ::metrics::counter!(
+            "aquatic_banned_connections_total",
+            "worker_index" => WORKER_INDEX.with(|index| index.get()).to_string(),
+        )
+        .increment(1);
+    }
+
+    banned
+}
+
+/// Returns true and counts closed connection if greylisting is enabled and
+/// network prefix of peer is greylisted
+fn reject_if_greylisted(
+    config: &Config,
+    greylist: &RefCell<Greylist>,
+    peer_ip: IpAddr,
+    server_start_instant: ServerStartInstant,
+) -> bool {
+    if !config.greylist.active() {
+        return false;
+    }
+
+    let addr = CanonicalSocketAddr::new(SocketAddr::new(peer_ip, 0));
+
+    let greylisted = greylist
+        .borrow()
+        .contains(addr, server_start_instant.seconds_elapsed());
+
+    if greylisted {
+        ::log::debug!("closed connection from greylisted network prefix");
+
+        #[cfg(feature = "metrics")]
+        ::metrics::counter!(
+            "aquatic_greylisted_connections_total",
+            "worker_index" => WORKER_INDEX.with(|index| index.get()).to_string(),
+        )
+        .increment(1);
+    }
+
+    greylisted
+}
+
+/// Count failure (e.g., a failed handshake) towards greylisting network
+/// prefix of peer, if greylisting is enabled
+fn record_greylist_failure(
+    config: &Config,
+    greylist: &RefCell<Greylist>,
+    peer_ip: IpAddr,
+    server_start_instant: ServerStartInstant,
+) {
+    if !config.greylist.active() {
+        return;
+    }
+
+    let addr = CanonicalSocketAddr::new(SocketAddr::new(peer_ip, 0));
+
+    if greylist
+        .borrow_mut()
+        .record_failure(addr, server_start_instant.seconds_elapsed())
+    {
+        #[cfg(feature = "metrics")]
+        ::metrics::counter!(
+            "aquatic_greylisted_prefixes_total",
+            "ip_version" => ip_version_to_metrics_str(IpVersion::canonical_from_ip(peer_ip)),
+            "worker_index" => WORKER_INDEX.with(|index| index.get()).to_string(),
+        )
+        .increment(1);
+    }
+}
+
 #[cfg(feature = "metrics")]
 fn ip_version_to_metrics_str(ip_version: IpVersion) -> &'static str {
     match ip_version {
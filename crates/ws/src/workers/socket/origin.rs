@@ -0,0 +1,140 @@
+use crate::config::NetworkConfig;
+
+/// Check if WebSocket handshake with given Origin header value is allowed
+///
+/// Requests without Origin header don't come from browsers and are always
+/// allowed, since other clients can set the header to anything anyway.
+pub fn origin_allowed(config: &NetworkConfig, opt_origin: Option<&str>) -> bool {
+    let Some(origin) = opt_origin else {
+        return true;
+    };
+
+    if config
+        .denied_origins
+        .iter()
+        .any(|pattern| origin_matches(pattern, origin))
+    {
+        return false;
+    }
+
+    config.allowed_origins.is_empty()
+        || config
+            .allowed_origins
+            .iter()
+            .any(|pattern| origin_matches(pattern, origin))
+}
+
+/// Check if origin matches pattern, e.g., "https://example.com" or
+/// "https://*.example.com"
+///
+/// Comparison is case-insensitive. The wildcard matches one or more
+/// subdomain labels, but not the domain itself.
+pub fn origin_matches(pattern: &str, origin: &str) -> bool {
+    let Some((prefix, suffix)) = pattern.split_once('*') else {
+        return pattern.eq_ignore_ascii_case(origin);
+    };
+
+    if origin.len() <= prefix.len() + suffix.len() {
+        return false;
+    }
+
+    let (origin_prefix, rest) = origin.split_at(prefix.len());
+
+    if !origin_prefix.eq_ignore_ascii_case(prefix) {
+        return false;
+    }
+
+    let (subdomains, origin_suffix) = rest.split_at(rest.len() - suffix.len());
+
+    origin_suffix.eq_ignore_ascii_case(suffix)
+        && !subdomains.starts_with('.')
+        && !subdomains.contains([':', '/', '@'])
+}
+
+/// Check that origin pattern is either exact or has a wildcard in place of
+/// the leftmost subdomain labels, e.g., "https://*.example.com"
+pub fn origin_pattern_valid(pattern: &str) -> bool {
+    match pattern.split_once("://") {
+        Some((scheme, host)) if !scheme.is_empty() && !scheme.contains('*') => {
+            match host.strip_prefix("*.") {
+                Some(domain) => !domain.is_empty() && !domain.contains('*'),
+                None => !host.is_empty() && !host.contains('*'),
+            }
+        }
+        // Origin of e.g. sandboxed iframes
+        _ => pattern == "null",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_origin_matches() {
+        assert!(origin_matches("https://example.com", "https://example.com"));
+        assert!(origin_matches("https://example.com", "HTTPS://Example.com"));
+        assert!(!origin_matches("https://example.com", "http://example.com"));
+        assert!(!origin_matches(
+            "https://example.com",
+            "https://example.com:8443"
+        ));
+
+        assert!(origin_matches(
+            "https://*.example.com",
+            "https://a.example.com"
+        ));
+        assert!(origin_matches(
+            "https://*.example.com",
+            "https://a.b.Example.com"
+        ));
+        assert!(!origin_matches(
+            "https://*.example.com",
+            "https://example.com"
+        ));
+        assert!(!origin_matches(
+            "https://*.example.com",
+            "https://.example.com"
+        ));
+        assert!(!origin_matches(
+            "https://*.example.com",
+            "https://evil.com/.example.com"
+        ));
+        assert!(!origin_matches(
+            "https://*.example.com",
+            "https://a.example.com.evil.com"
+        ));
+        assert!(!origin_matches(
+            "https://*.example.com",
+            "http://a.example.com"
+        ));
+    }
+
+    #[test]
+    fn test_origin_allowed() {
+        let mut config = NetworkConfig::default();
+
+        assert!(origin_allowed(&config, None));
+        assert!(origin_allowed(&config, Some("https://example.com")));
+
+        config.allowed_origins = vec!["https://*.example.com".into()];
+        config.denied_origins = vec!["https://evil.example.com".into()];
+
+        assert!(origin_allowed(&config, None));
+        assert!(origin_allowed(&config, Some("https://a.example.com")));
+        assert!(!origin_allowed(&config, Some("https://evil.example.com")));
+        assert!(!origin_allowed(&config, Some("https://example.org")));
+    }
+
+    #[test]
+    fn test_origin_pattern_valid() {
+        assert!(origin_pattern_valid("https://example.com"));
+        assert!(origin_pattern_valid("https://*.example.com"));
+        assert!(origin_pattern_valid("null"));
+
+        assert!(!origin_pattern_valid("example.com"));
+        assert!(!origin_pattern_valid("https://*"));
+        assert!(!origin_pattern_valid("https://a.*.example.com"));
+        assert!(!origin_pattern_valid("*://example.com"));
+    }
+}
@@ -0,0 +1,265 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use anyhow::Context;
+use futures::AsyncReadExt;
+use glommio::net::TcpStream;
+use tungstenite::http::HeaderMap;
+
+use crate::config::ReverseProxyPeerIpHeaderFormat;
+
+const PROXY_V1_PREFIX: &[u8] = b"PROXY ";
+const PROXY_V1_MAX_LEN: usize = 107;
+const PROXY_V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Read PROXY protocol (version 1 or 2) header from start of stream
+///
+/// Returns source address, or None if the proxy didn't provide one (e.g.,
+/// for its own health checks). Data following the header is left unread.
+pub async fn read_proxy_protocol_header(
+    stream: &mut TcpStream,
+) -> anyhow::Result<Option<SocketAddr>> {
+    // Shortest possible version 1 header is 15 bytes long, so this doesn't
+    // read past the end of the header
+    let mut start = [0u8; 12];
+
+    stream
+        .read_exact(&mut start)
+        .await
+        .with_context(|| "read start of header")?;
+
+    if start == PROXY_V2_SIGNATURE {
+        let mut fixed = [0u8; 4];
+
+        stream
+            .read_exact(&mut fixed)
+            .await
+            .with_context(|| "read version 2 header")?;
+
+        let len = u16::from_be_bytes([fixed[2], fixed[3]]) as usize;
+        let mut addresses = vec![0; len];
+
+        stream
+            .read_exact(&mut addresses)
+            .await
+            .with_context(|| "read version 2 addresses")?;
+
+        parse_proxy_v2_header(fixed, &addresses)
+    } else if start.starts_with(PROXY_V1_PREFIX) {
+        let mut line = start.to_vec();
+
+        // Read one byte at a time to avoid consuming data after the header
+        while !line.ends_with(b"\r\n") {
+            if line.len() >= PROXY_V1_MAX_LEN {
+                return Err(anyhow::anyhow!("version 1 header too long"));
+            }
+
+            let mut byte = [0u8; 1];
+
+            stream
+                .read_exact(&mut byte)
+                .await
+                .with_context(|| "read version 1 header")?;
+
+            line.push(byte[0]);
+        }
+
+        parse_proxy_v1_header(&line)
+    } else {
+        Err(anyhow::anyhow!("no PROXY protocol header"))
+    }
+}
+
+/// Parse version 1 header line, including trailing CRLF
+fn parse_proxy_v1_header(line: &[u8]) -> anyhow::Result<Option<SocketAddr>> {
+    let line = ::std::str::from_utf8(line)?
+        .strip_suffix("\r\n")
+        .ok_or(anyhow::anyhow!("no CRLF"))?;
+
+    let mut parts = line.split(' ');
+
+    if parts.next() != Some("PROXY") {
+        return Err(anyhow::anyhow!("no PROXY prefix"));
+    }
+
+    let ip = match parts.next() {
+        Some("TCP4") => IpAddr::V4(parse_next(&mut parts)?),
+        Some("TCP6") => IpAddr::V6(parse_next(&mut parts)?),
+        Some("UNKNOWN") => return Ok(None),
+        _ => return Err(anyhow::anyhow!("invalid protocol")),
+    };
+
+    // Skip destination address
+    parts.next();
+
+    let port = parse_next(&mut parts)?;
+
+    Ok(Some(SocketAddr::new(ip, port)))
+}
+
+fn parse_next<'a, T: ::std::str::FromStr>(
+    parts: &mut impl Iterator<Item = &'a str>,
+) -> anyhow::Result<T>
+where
+    T::Err: ::std::error::Error + Send + Sync + 'static,
+{
+    Ok(parts
+        .next()
+        .ok_or(anyhow::anyhow!("missing field"))?
+        .parse()?)
+}
+
+/// Parse version 2 header, given the four bytes following the signature and
+/// the address block
+fn parse_proxy_v2_header(fixed: [u8; 4], addresses: &[u8]) -> anyhow::Result<Option<SocketAddr>> {
+    let [version_command, family_protocol, _, _] = fixed;
+
+    if version_command >> 4 != 2 {
+        return Err(anyhow::anyhow!("unsupported version"));
+    }
+
+    match version_command & 0x0f {
+        // LOCAL
+        0 => return Ok(None),
+        // PROXY
+        1 => (),
+        _ => return Err(anyhow::anyhow!("invalid command")),
+    }
+
+    match family_protocol >> 4 {
+        // AF_INET
+        1 => {
+            let addresses: [u8; 12] = addresses
+                .get(..12)
+                .ok_or(anyhow::anyhow!("IPv4 address block too short"))?
+                .try_into()?;
+
+            let ip = Ipv4Addr::from([addresses[0], addresses[1], addresses[2], addresses[3]]);
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        }
+        // AF_INET6
+        2 => {
+            let addresses: [u8; 36] = addresses
+                .get(..36)
+                .ok_or(anyhow::anyhow!("IPv6 address block too short"))?
+                .try_into()?;
+
+            let mut ip = [0u8; 16];
+
+            ip.copy_from_slice(&addresses[..16]);
+
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(ip).into(), port)))
+        }
+        // AF_UNSPEC or AF_UNIX
+        _ => Ok(None),
+    }
+}
+
+/// Extract peer IP from header set by reverse proxy
+pub fn parse_forwarded_header(
+    header_name: &str,
+    header_format: ReverseProxyPeerIpHeaderFormat,
+    headers: &HeaderMap,
+) -> anyhow::Result<IpAddr> {
+    if let Some(value) = headers.get_all(header_name).iter().next_back() {
+        match header_format {
+            ReverseProxyPeerIpHeaderFormat::LastAddress => {
+                return value
+                    .to_str()?
+                    .split(',')
+                    .next_back()
+                    .ok_or(anyhow::anyhow!("no header value"))?
+                    .trim()
+                    .parse::<IpAddr>()
+                    .with_context(|| "parse ip");
+            }
+        }
+    }
+
+    Err(anyhow::anyhow!("header not present"))
+}
+
+#[cfg(test)]
+mod tests {
+    use tungstenite::http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_proxy_v1_header() {
+        assert_eq!(
+            parse_proxy_v1_header(b"PROXY TCP4 1.2.3.4 5.6.7.8 1000 443\r\n").unwrap(),
+            Some(SocketAddr::from(([1, 2, 3, 4], 1000)))
+        );
+        assert_eq!(
+            parse_proxy_v1_header(b"PROXY TCP6 2001:db8::1 2001:db8::2 1000 443\r\n").unwrap(),
+            Some("[2001:db8::1]:1000".parse().unwrap())
+        );
+        assert_eq!(parse_proxy_v1_header(b"PROXY UNKNOWN\r\n").unwrap(), None);
+
+        assert!(parse_proxy_v1_header(b"PROXY TCP4 1.2.3.4 5.6.7.8 1000 443").is_err());
+        assert!(parse_proxy_v1_header(b"PROXY TCP4 2001:db8::1 5.6.7.8 1000 443\r\n").is_err());
+        assert!(parse_proxy_v1_header(b"PROXY UDP4 1.2.3.4 5.6.7.8 1000 443\r\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_proxy_v2_header() {
+        let ipv4_addresses = [1, 2, 3, 4, 5, 6, 7, 8, 0x03, 0xe8, 0x01, 0xbb];
+
+        assert_eq!(
+            parse_proxy_v2_header([0x21, 0x11, 0, 12], &ipv4_addresses).unwrap(),
+            Some(SocketAddr::from(([1, 2, 3, 4], 1000)))
+        );
+
+        let mut ipv6_addresses = [0u8; 36];
+
+        ipv6_addresses[15] = 1;
+        ipv6_addresses[31] = 2;
+        ipv6_addresses[32..34].copy_from_slice(&1000u16.to_be_bytes());
+
+        assert_eq!(
+            parse_proxy_v2_header([0x21, 0x21, 0, 36], &ipv6_addresses).unwrap(),
+            Some(SocketAddr::from((Ipv6Addr::LOCALHOST, 1000)))
+        );
+
+        // LOCAL command
+        assert_eq!(
+            parse_proxy_v2_header([0x20, 0x00, 0, 0], &[]).unwrap(),
+            None
+        );
+
+        assert!(parse_proxy_v2_header([0x21, 0x11, 0, 4], &ipv4_addresses[..4]).is_err());
+        assert!(parse_proxy_v2_header([0x11, 0x11, 0, 12], &ipv4_addresses).is_err());
+    }
+
+    #[test]
+    fn test_parse_forwarded_header() {
+        let mut headers = HeaderMap::new();
+
+        assert!(parse_forwarded_header(
+            "X-Forwarded-For",
+            ReverseProxyPeerIpHeaderFormat::LastAddress,
+            &headers
+        )
+        .is_err());
+
+        headers.append(
+            "X-Forwarded-For",
+            HeaderValue::from_static("1.2.3.4, 5.6.7.8,9.10.11.12"),
+        );
+        headers.append("X-Forwarded-For", HeaderValue::from_static("2001:db8::1"));
+
+        assert_eq!(
+            parse_forwarded_header(
+                "X-Forwarded-For",
+                ReverseProxyPeerIpHeaderFormat::LastAddress,
+                &headers
+            )
+            .unwrap(),
+            "2001:db8::1".parse::<IpAddr>().unwrap()
+        );
+    }
+}
@@ -0,0 +1,71 @@
+use std::time::Instant;
+
+/// Token bucket for limiting message rate of a connection
+///
+/// Starts out full and is refilled continuously.
+pub struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    tokens_per_second: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(capacity: u32, tokens_per_minute: u32, now: Instant) -> Self {
+        Self {
+            capacity: capacity.into(),
+            tokens: capacity.into(),
+            tokens_per_second: f64::from(tokens_per_minute) / 60.0,
+            last_refill: now,
+        }
+    }
+
+    /// Take a token if one is available
+    pub fn try_take(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() * self.tokens_per_second).min(self.capacity);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let start = Instant::now();
+
+        let mut bucket = TokenBucket::new(3, 60, start);
+
+        assert!(bucket.try_take(start));
+        assert!(bucket.try_take(start));
+        assert!(bucket.try_take(start));
+        assert!(!bucket.try_take(start));
+
+        let now = start + Duration::from_millis(1500);
+
+        assert!(bucket.try_take(now));
+        assert!(!bucket.try_take(now));
+
+        // Refill doesn't exceed capacity
+        let now = now + Duration::from_secs(60);
+
+        assert!(bucket.try_take(now));
+        assert!(bucket.try_take(now));
+        assert!(bucket.try_take(now));
+        assert!(!bucket.try_take(now));
+    }
+}
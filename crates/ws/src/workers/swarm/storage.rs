@@ -11,12 +11,15 @@ use aquatic_ws_protocol::outgoing::{
 use hashbrown::HashMap;
 use rand::rngs::SmallRng;
 
-use aquatic_common::{IndexMap, SecondsSinceServerStart, ServerStartInstant};
+use aquatic_common::{
+    jitter_announce_interval, Clock, IndexMap, SecondsSinceServerStart, ServerStartInstant,
+};
 use aquatic_ws_protocol::common::*;
 use rand::Rng;
 
 use crate::common::*;
-use crate::config::Config;
+use crate::config::{Config, PeerIdCollisionPolicy};
+use crate::enrichment::PeerMetadata;
 
 pub struct TorrentMaps {
     ipv4: TorrentMap,
@@ -68,10 +71,10 @@ impl TorrentMaps {
         &mut self,
         config: &Config,
         access_list: &Arc<AccessListArcSwap>,
-        server_start_instant: ServerStartInstant,
+        clock: impl Clock,
     ) {
         let mut access_list_cache = create_access_list_cache(access_list);
-        let now = server_start_instant.seconds_elapsed();
+        let now = clock.seconds_elapsed();
 
         self.ipv4.clean(config, &mut access_list_cache, now);
         self.ipv6.clean(config, &mut access_list_cache, now);
@@ -83,15 +86,18 @@ impl TorrentMaps {
         self.ipv6.update_torrent_gauge();
     }
 
+    /// Remove peer that was announced over closed connection
     pub fn handle_connection_closed(
         &mut self,
         info_hash: InfoHash,
         peer_id: PeerId,
         ip_version: IpVersion,
+        consumer_id: ConsumerId,
+        connection_id: ConnectionId,
     ) {
         let torrent_map = self.get_torrent_map_by_ip_version(ip_version);
 
-        torrent_map.handle_connection_closed(info_hash, peer_id);
+        torrent_map.handle_connection_closed(info_hash, peer_id, consumer_id, connection_id);
     }
 
     fn get_torrent_map_by_ip_version(&mut self, ip_version: IpVersion) -> &mut TorrentMap {
@@ -104,10 +110,19 @@ impl TorrentMaps {
 
 struct TorrentMap {
     torrents: IndexMap<InfoHash, TorrentData>,
+    /// Number of completed announces of torrents that were removed because
+    /// they had no peers left, restored if peers announce to them again
+    removed_torrent_completed_counts: HashMap<InfoHash, usize>,
+    /// Index of next torrent to clean when cleaning in batches
+    cleaning_cursor: usize,
     #[cfg(feature = "metrics")]
     torrent_gauge: ::metrics::Gauge,
     #[cfg(feature = "metrics")]
     peer_gauge: ::metrics::Gauge,
+    #[cfg(feature = "metrics")]
+    evicted_peer_counter: ::metrics::Counter,
+    #[cfg(feature = "metrics")]
+    peer_id_collision_counters: PeerIdCollisionCounters,
 }
 
 impl TorrentMap {
@@ -139,12 +154,32 @@ impl TorrentMap {
             ),
         };
 
+        #[cfg(feature = "metrics")]
+        let evicted_peer_counter = match ip_version {
+            IpVersion::V4 => ::metrics::counter!(
+                "aquatic_evicted_peers_total",
+                "ip_version" => "4",
+                "worker_index" => worker_index.to_string(),
+            ),
+            IpVersion::V6 => ::metrics::counter!(
+                "aquatic_evicted_peers_total",
+                "ip_version" => "6",
+                "worker_index" => worker_index.to_string(),
+            ),
+        };
+
         Self {
             torrents: Default::default(),
+            removed_torrent_completed_counts: Default::default(),
+            cleaning_cursor: 0,
             #[cfg(feature = "metrics")]
             peer_gauge,
             #[cfg(feature = "metrics")]
             torrent_gauge,
+            #[cfg(feature = "metrics")]
+            evicted_peer_counter,
+            #[cfg(feature = "metrics")]
+            peer_id_collision_counters: PeerIdCollisionCounters::new(worker_index, ip_version),
         }
     }
 
@@ -157,20 +192,118 @@ impl TorrentMap {
         request_sender_meta: InMessageMeta,
         request: AnnounceRequest,
     ) {
-        let torrent_data = self.torrents.entry(request.info_hash).or_default();
-
-        // If there is already a peer with this peer_id, check that connection id
-        // is same as that of request sender. Otherwise, ignore request. Since
-        // peers have access to each others peer_id's, they could send requests
-        // using them, causing all sorts of issues.
-        if let Some(previous_peer) = torrent_data.peers.get(&request.peer_id) {
-            if request_sender_meta.connection_id != previous_peer.connection_id {
-                return;
+        let torrent_data = self
+            .torrents
+            .entry(request.info_hash)
+            .or_insert_with(|| TorrentData {
+                num_completed: self
+                    .removed_torrent_completed_counts
+                    .remove(&request.info_hash)
+                    .unwrap_or(0),
+                ..Default::default()
+            });
+
+        // If there is already a peer with this peer_id, check that it was
+        // announced over the connection of the request sender. Since peers
+        // have access to each others peer_id's, they could otherwise send
+        // requests using them, causing all sorts of issues.
+        if let Some(action) = torrent_data.handle_peer_id_collision(
+            config,
+            server_start_instant,
+            request_sender_meta,
+            request.peer_id,
+            #[cfg(feature = "metrics")]
+            &self.peer_gauge,
+        ) {
+            ::log::debug!(
+                "peer id {:?} announced over other connection {:?} ({:?})",
+                request.peer_id,
+                request_sender_meta.connection_id,
+                action
+            );
+
+            #[cfg(feature = "metrics")]
+            self.peer_id_collision_counters.increment(action);
+
+            match action {
+                PeerIdCollisionAction::Ignored => return,
+                PeerIdCollisionAction::Rejected => {
+                    let error_message = ErrorResponse {
+                        action: Some(ErrorResponseAction::Announce),
+                        info_hash: Some(request.info_hash),
+                        failure_reason: "Peer id is in use by another connection".into(),
+                    };
+
+                    out_messages.push((
+                        request_sender_meta.into(),
+                        OutMessage::ErrorResponse(error_message),
+                    ));
+
+                    return;
+                }
+                // Previous peer was removed, so request is handled as coming
+                // from a new peer
+                PeerIdCollisionAction::Replaced => (),
             }
         }
 
         ::log::trace!("received request from {:?}", request_sender_meta);
 
+        if torrent_data.announced_too_early(config, server_start_instant, &request) {
+            let error_message = ErrorResponse {
+                action: Some(ErrorResponseAction::Announce),
+                info_hash: Some(request.info_hash),
+                failure_reason: "Announcing too often".into(),
+            };
+
+            out_messages.push((
+                request_sender_meta.into(),
+                OutMessage::ErrorResponse(error_message),
+            ));
+
+            return;
+        }
+
+        if torrent_data.missing_started_event(config, &request) {
+            let error_message = ErrorResponse {
+                action: Some(ErrorResponseAction::Announce),
+                info_hash: Some(request.info_hash),
+                failure_reason: "Peer must announce with event started first".into(),
+            };
+
+            out_messages.push((
+                request_sender_meta.into(),
+                OutMessage::ErrorResponse(error_message),
+            ));
+
+            return;
+        }
+
+        if !torrent_data.peers.contains_key(&request.peer_id)
+            && request.event != Some(AnnounceEvent::Stopped)
+            && !torrent_data.make_room_for_new_peer(
+                config,
+                server_start_instant,
+                #[cfg(feature = "metrics")]
+                &self.peer_gauge,
+                #[cfg(feature = "metrics")]
+                &self.evicted_peer_counter,
+            )
+        {
+            let error_message = ErrorResponse {
+                action: Some(ErrorResponseAction::Announce),
+                info_hash: Some(request.info_hash),
+                failure_reason: "Torrent has too many peers".into(),
+            };
+
+            out_messages.push((
+                request_sender_meta.into(),
+                OutMessage::ErrorResponse(error_message),
+            ));
+
+            return;
+        }
+
         let peer_status = torrent_data.insert_or_update_peer(
             config,
             server_start_instant,
@@ -198,7 +331,9 @@ impl TorrentMap {
                 request.answer_to_peer_id,
                 request.answer_offer_id,
             ) {
-                let opt_out_message = torrent_data.handle_answer(
+                let out_message = torrent_data.handle_answer(
+                    config,
+                    server_start_instant,
                     request_sender_meta,
                     request.info_hash,
                     request.peer_id,
@@ -207,9 +342,7 @@ impl TorrentMap {
                     answer,
                 );
 
-                if let Some(out_message) = opt_out_message {
-                    out_messages.push(out_message);
-                }
+                out_messages.push(out_message);
             }
         }
 
@@ -218,7 +351,12 @@ impl TorrentMap {
             info_hash: request.info_hash,
             complete: torrent_data.num_seeders,
             incomplete: torrent_data.num_leechers(),
-            announce_interval: config.protocol.peer_announce_interval,
+            announce_interval: jitter_announce_interval(
+                rng,
+                config.protocol.peer_announce_interval,
+                config.protocol.peer_announce_interval_jitter,
+            ),
+            external_ip: None,
         });
 
         out_messages.push((request_sender_meta.into(), response));
@@ -248,10 +386,20 @@ impl TorrentMap {
             if let Some(torrent_data) = self.torrents.get(&info_hash) {
                 let stats = ScrapeStatistics {
                     complete: torrent_data.num_seeders,
-                    downloaded: 0, // No implementation planned
+                    downloaded: torrent_data.num_completed,
                     incomplete: torrent_data.num_leechers(),
                 };
 
+                out_message.files.insert(info_hash, stats);
+            } else if let Some(num_completed) =
+                self.removed_torrent_completed_counts.get(&info_hash)
+            {
+                let stats = ScrapeStatistics {
+                    complete: 0,
+                    downloaded: *num_completed,
+                    incomplete: 0,
+                };
+
                 out_message.files.insert(info_hash, stats);
             }
         }
@@ -259,10 +407,18 @@ impl TorrentMap {
         out_messages.push((meta.into(), OutMessage::ScrapeResponse(out_message)));
     }
 
-    pub fn handle_connection_closed(&mut self, info_hash: InfoHash, peer_id: PeerId) {
+    pub fn handle_connection_closed(
+        &mut self,
+        info_hash: InfoHash,
+        peer_id: PeerId,
+        consumer_id: ConsumerId,
+        connection_id: ConnectionId,
+    ) {
         if let Some(torrent_data) = self.torrents.get_mut(&info_hash) {
             torrent_data.handle_connection_closed(
                 peer_id,
+                consumer_id,
+                connection_id,
                 #[cfg(feature = "metrics")]
                 &self.peer_gauge,
             );
@@ -279,9 +435,31 @@ impl TorrentMap {
         config: &Config,
         access_list_cache: &mut AccessListCache,
         now: SecondsSinceServerStart,
+    ) {
+        let batch_size = config.cleaning.torrent_cleaning_batch_size;
+
+        if (batch_size == 0) | (batch_size >= self.torrents.len()) {
+            self.clean_all(config, access_list_cache, now);
+        } else {
+            self.clean_batch(config, access_list_cache, now, batch_size);
+        }
+
+        #[cfg(feature = "metrics")]
+        self.update_torrent_gauge();
+    }
+
+    fn clean_all(
+        &mut self,
+        config: &Config,
+        access_list_cache: &mut AccessListCache,
+        now: SecondsSinceServerStart,
     ) {
         let mut total_num_peers = 0u64;
 
+        self.clean_removed_torrent_completed_counts(config, access_list_cache);
+
+        let removed_torrent_completed_counts = &mut self.removed_torrent_completed_counts;
+
         self.torrents.retain(|info_hash, torrent_data| {
             if !access_list_cache
                 .load()
@@ -294,16 +472,111 @@ impl TorrentMap {
 
             total_num_peers += num_peers as u64;
 
+            if num_peers == 0 {
+                remember_completed_count(
+                    config,
+                    removed_torrent_completed_counts,
+                    *info_hash,
+                    torrent_data.num_completed,
+                );
+            }
+
             num_peers > 0
         });
 
         self.torrents.shrink_to_fit();
+        self.cleaning_cursor = 0;
 
         #[cfg(feature = "metrics")]
         self.peer_gauge.set(total_num_peers as f64);
+    }
 
-        #[cfg(feature = "metrics")]
-        self.update_torrent_gauge();
+    /// Clean at most `batch_size` torrents, starting where the previous batch
+    /// stopped
+    fn clean_batch(
+        &mut self,
+        config: &Config,
+        access_list_cache: &mut AccessListCache,
+        now: SecondsSinceServerStart,
+        batch_size: usize,
+    ) {
+        let mut index = if self.cleaning_cursor < self.torrents.len() {
+            self.cleaning_cursor
+        } else {
+            self.clean_removed_torrent_completed_counts(config, access_list_cache);
+
+            0
+        };
+
+        for _ in 0..batch_size {
+            let Some((info_hash, torrent_data)) = self.torrents.get_index_mut(index) else {
+                break;
+            };
+
+            let num_peers_before = torrent_data.peers.len();
+
+            let allowed = access_list_cache
+                .load()
+                .allows(config.access_list.mode, &info_hash.0);
+            let keep = allowed && torrent_data.clean_and_get_num_peers(now) > 0;
+
+            if allowed && !keep {
+                remember_completed_count(
+                    config,
+                    &mut self.removed_torrent_completed_counts,
+                    *info_hash,
+                    torrent_data.num_completed,
+                );
+            }
+
+            if keep {
+                #[cfg(feature = "metrics")]
+                self.peer_gauge
+                    .decrement((num_peers_before - torrent_data.peers.len()) as f64);
+
+                index += 1;
+            } else {
+                #[cfg(feature = "metrics")]
+                self.peer_gauge.decrement(num_peers_before as f64);
+
+                // Moves last torrent to index, so it will be checked next
+                self.torrents.swap_remove_index(index);
+            }
+        }
+
+        self.cleaning_cursor = index;
+    }
+
+    /// Forget completed counts of removed torrents no longer allowed by the
+    /// access list
+    fn clean_removed_torrent_completed_counts(
+        &mut self,
+        config: &Config,
+        access_list_cache: &mut AccessListCache,
+    ) {
+        self.removed_torrent_completed_counts
+            .retain(|info_hash, _| {
+                access_list_cache
+                    .load()
+                    .allows(config.access_list.mode, &info_hash.0)
+            });
+    }
+}
+
+/// Remember completed count of torrent that is being removed because it has
+/// no peers left, unless `cleaning.max_removed_torrent_completed_counts` has
+/// been reached
+fn remember_completed_count(
+    config: &Config,
+    removed_torrent_completed_counts: &mut HashMap<InfoHash, usize>,
+    info_hash: InfoHash,
+    num_completed: usize,
+) {
+    if num_completed > 0
+        && removed_torrent_completed_counts.len()
+            < config.cleaning.max_removed_torrent_completed_counts
+    {
+        removed_torrent_completed_counts.insert(info_hash, num_completed);
     }
 }
 
@@ -311,6 +584,12 @@ impl TorrentMap {
 struct TorrentData {
     peers: IndexMap<PeerId, Peer>,
     num_seeders: usize,
+    /// Number of announce requests with event `completed`
+    ///
+    /// Kept when inactive peers are cleaned. When the torrent is removed
+    /// because it has no peers left, the count is moved to
+    /// [`TorrentMap::removed_torrent_completed_counts`].
+    num_completed: usize,
 }
 
 impl TorrentData {
@@ -318,6 +597,150 @@ impl TorrentData {
         self.peers.len() - self.num_seeders
     }
 
+    /// Check if request is a regular announce (without event, offers or
+    /// answer) from a stored peer, sent sooner than
+    /// `protocol.min_announce_interval` after its previous accepted announce
+    fn announced_too_early(
+        &self,
+        config: &Config,
+        server_start_instant: ServerStartInstant,
+        request: &AnnounceRequest,
+    ) -> bool {
+        if config.protocol.min_announce_interval == 0
+            || request.event.unwrap_or_default() != AnnounceEvent::Update
+            || request
+                .offers
+                .as_ref()
+                .is_some_and(|offers| !offers.is_empty())
+            || request.answer.is_some()
+        {
+            return false;
+        }
+
+        let valid_until = ValidUntil::new(server_start_instant, config.cleaning.max_peer_age);
+
+        self.peers.get(&request.peer_id).is_some_and(|peer| {
+            valid_until.seconds_since(peer.valid_until) < config.protocol.min_announce_interval
+        })
+    }
+
+    /// Check if `protocol.require_started_event` is set and request has
+    /// event `stopped` or `completed`, but announcing peer either isn't
+    /// stored or wasn't stored with event `started`
+    fn missing_started_event(&self, config: &Config, request: &AnnounceRequest) -> bool {
+        if !config.protocol.require_started_event
+            || !matches!(
+                request.event,
+                Some(AnnounceEvent::Stopped | AnnounceEvent::Completed)
+            )
+        {
+            return false;
+        }
+
+        !self
+            .peers
+            .get(&request.peer_id)
+            .is_some_and(|peer| peer.started)
+    }
+
+    /// If a peer with the same peer id was announced over another connection,
+    /// apply `protocol.peer_id_collision_policy`, removing the stored peer
+    /// if it is replaced
+    ///
+    /// Returns None if there is no collision.
+    fn handle_peer_id_collision(
+        &mut self,
+        config: &Config,
+        server_start_instant: ServerStartInstant,
+        request_sender_meta: InMessageMeta,
+        peer_id: PeerId,
+        #[cfg(feature = "metrics")] peer_gauge: &::metrics::Gauge,
+    ) -> Option<PeerIdCollisionAction> {
+        let previous_peer = self.peers.get(&peer_id)?;
+
+        if previous_peer.consumer_id == request_sender_meta.out_message_consumer_id
+            && previous_peer.connection_id == request_sender_meta.connection_id
+        {
+            return None;
+        }
+
+        let action = match config.protocol.peer_id_collision_policy {
+            PeerIdCollisionPolicy::Ignore => PeerIdCollisionAction::Ignored,
+            PeerIdCollisionPolicy::ErrorResponse => PeerIdCollisionAction::Rejected,
+            PeerIdCollisionPolicy::ReplaceIfStale => {
+                // Stored peers expire no later than this
+                let latest_valid_until =
+                    ValidUntil::new(server_start_instant, config.cleaning.max_peer_age);
+
+                if latest_valid_until.seconds_since(previous_peer.valid_until)
+                    >= config.protocol.peer_id_collision_stale_age
+                {
+                    PeerIdCollisionAction::Replaced
+                } else {
+                    PeerIdCollisionAction::Ignored
+                }
+            }
+        };
+
+        if action == PeerIdCollisionAction::Replaced {
+            if let Some(peer) = self.peers.swap_remove(&peer_id) {
+                if peer.seeder {
+                    self.num_seeders -= 1;
+                }
+
+                #[cfg(feature = "metrics")]
+                peer_gauge.decrement(1.0);
+            }
+        }
+
+        Some(action)
+    }
+
+    /// If torrent is at `protocol.max_peers_per_torrent`, remove the
+    /// leecher that announced least recently. Returns false if there is no
+    /// room and no leecher could be removed.
+    fn make_room_for_new_peer(
+        &mut self,
+        config: &Config,
+        server_start_instant: ServerStartInstant,
+        #[cfg(feature = "metrics")] peer_gauge: &::metrics::Gauge,
+        #[cfg(feature = "metrics")] evicted_peer_counter: &::metrics::Counter,
+    ) -> bool {
+        let max_num_peers = config.protocol.max_peers_per_torrent;
+
+        if max_num_peers == 0 {
+            return true;
+        }
+
+        // All stored peers expire no later than this
+        let latest_valid_until =
+            ValidUntil::new(server_start_instant, config.cleaning.max_peer_age);
+
+        while self.peers.len() >= max_num_peers {
+            let opt_index = self
+                .peers
+                .values()
+                .enumerate()
+                .filter(|(_, peer)| !peer.seeder)
+                .max_by_key(|(_, peer)| latest_valid_until.seconds_since(peer.valid_until))
+                .map(|(index, _)| index);
+
+            let Some(index) = opt_index else {
+                return false;
+            };
+
+            self.peers.swap_remove_index(index);
+
+            #[cfg(feature = "metrics")]
+            {
+                peer_gauge.decrement(1.0);
+                evicted_peer_counter.increment(1);
+            }
+        }
+
+        true
+    }
+
     pub fn insert_or_update_peer(
         &mut self,
         config: &Config,
@@ -328,10 +751,13 @@ impl TorrentData {
     ) -> PeerStatus {
         let valid_until = ValidUntil::new(server_start_instant, config.cleaning.max_peer_age);
 
-        let peer_status = PeerStatus::from_event_and_bytes_left(
-            request.event.unwrap_or_default(),
-            request.bytes_left,
-        );
+        let event = request.event.unwrap_or_default();
+
+        if event == AnnounceEvent::Completed {
+            self.num_completed = self.num_completed.saturating_add(1);
+        }
+
+        let peer_status = PeerStatus::from_event_and_bytes_left(event, request.bytes_left);
 
         match self.peers.entry(request.peer_id) {
             ::indexmap::map::Entry::Occupied(mut entry) => match peer_status {
@@ -373,7 +799,9 @@ impl TorrentData {
                         consumer_id: request_sender_meta.out_message_consumer_id,
                         seeder: false,
                         valid_until,
+                        started: event == AnnounceEvent::Started,
                         expecting_answers: Default::default(),
+                        metadata: request_sender_meta.peer_metadata,
                     };
 
                     entry.insert(peer);
@@ -389,7 +817,9 @@ impl TorrentData {
                         consumer_id: request_sender_meta.out_message_consumer_id,
                         seeder: true,
                         valid_until,
+                        started: event == AnnounceEvent::Started,
                         expecting_answers: Default::default(),
+                        metadata: request_sender_meta.peer_metadata,
                     };
 
                     entry.insert(peer);
@@ -418,13 +848,34 @@ impl TorrentData {
     ) {
         let max_num_peers_to_take = offers.len().min(config.protocol.max_offers);
 
-        let offer_receivers: Vec<(PeerId, ConnectionId, ConsumerId)> = extract_response_peers(
-            rng,
-            &self.peers,
-            max_num_peers_to_take,
-            sender_peer_id,
-            |peer_id, peer| (*peer_id, peer.connection_id, peer.consumer_id),
-        );
+        let (sender_is_seeder, sender_metadata) = self
+            .peers
+            .get(&sender_peer_id)
+            .map(|peer| (peer.seeder, peer.metadata))
+            .unwrap_or_default();
+
+        let leechers_only = sender_is_seeder && config.protocol.seeder_offers_only_to_leechers;
+
+        let offer_receivers: Vec<(PeerId, ConnectionId, ConsumerId)> =
+            if config.protocol.prefer_nearby_offer_receivers {
+                self.extract_nearby_peers(
+                    rng,
+                    max_num_peers_to_take,
+                    sender_peer_id,
+                    sender_metadata,
+                    leechers_only,
+                )
+            } else if leechers_only {
+                self.extract_leechers(rng, max_num_peers_to_take)
+            } else {
+                extract_response_peers(
+                    rng,
+                    &self.peers,
+                    max_num_peers_to_take,
+                    sender_peer_id,
+                    |peer_id, peer| (*peer_id, peer.connection_id, peer.consumer_id),
+                )
+            };
 
         if let Some(peer) = self.peers.get_mut(&sender_peer_id) {
             for (
@@ -459,26 +910,103 @@ impl TorrentData {
         }
     }
 
-    /// Pass on answer to relevant peer
+    /// Extract up to `max_num_peers_to_take` leechers, starting at a random
+    /// position in the peer map
+    fn extract_leechers(
+        &self,
+        rng: &mut SmallRng,
+        max_num_peers_to_take: usize,
+    ) -> Vec<(PeerId, ConnectionId, ConsumerId)> {
+        if max_num_peers_to_take == 0 || self.num_leechers() == 0 {
+            return Vec::new();
+        }
+
+        let offset = rng.gen_range(0..self.peers.len());
+
+        self.peers
+            .iter()
+            .skip(offset)
+            .chain(self.peers.iter().take(offset))
+            .filter(|(_, peer)| !peer.seeder)
+            .take(max_num_peers_to_take)
+            .map(|(peer_id, peer)| (*peer_id, peer.connection_id, peer.consumer_id))
+            .collect()
+    }
+
+    /// Extract up to `max_num_peers_to_take` peers other than the sender,
+    /// preferring the ones closest to it
+    ///
+    /// Peers are visited starting at a random position in the peer map, so
+    /// that receivers are picked at random among equally close peers.
+    fn extract_nearby_peers(
+        &self,
+        rng: &mut SmallRng,
+        max_num_peers_to_take: usize,
+        sender_peer_id: PeerId,
+        sender_metadata: PeerMetadata,
+        leechers_only: bool,
+    ) -> Vec<(PeerId, ConnectionId, ConsumerId)> {
+        if max_num_peers_to_take == 0 || self.peers.is_empty() {
+            return Vec::new();
+        }
+
+        let offset = rng.gen_range(0..self.peers.len());
+
+        let mut candidates = self
+            .peers
+            .iter()
+            .skip(offset)
+            .chain(self.peers.iter().take(offset))
+            .filter(|(peer_id, peer)| {
+                **peer_id != sender_peer_id && !(leechers_only && peer.seeder)
+            })
+            .map(|(peer_id, peer)| {
+                (
+                    sender_metadata.proximity(&peer.metadata),
+                    (*peer_id, peer.connection_id, peer.consumer_id),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        // Stable sort, so order within each proximity level stays random
+        candidates.sort_by_key(|(proximity, _)| ::std::cmp::Reverse(*proximity));
+
+        candidates
+            .into_iter()
+            .take(max_num_peers_to_take)
+            .map(|(_, peer)| peer)
+            .collect()
+    }
+
+    /// Pass on answer to relevant peer, if it is a response to an offer
+    /// that was relayed from that peer to the sender and hasn't expired
+    ///
+    /// Other answers get the same error response whether or not the
+    /// receiver exists, so that answers can't be used to probe for peer IDs,
+    /// unless `protocol.notify_when_offering_peer_gone` is set.
+    #[allow(clippy::too_many_arguments)]
     fn handle_answer(
         &mut self,
+        config: &Config,
+        server_start_instant: ServerStartInstant,
         request_sender_meta: InMessageMeta,
         info_hash: InfoHash,
         peer_id: PeerId,
         answer_receiver_id: PeerId,
         offer_id: OfferId,
         answer: RtcAnswer,
-    ) -> Option<(OutMessageMeta, OutMessage)> {
-        if let Some(answer_receiver) = self.peers.get_mut(&answer_receiver_id) {
-            let expecting_answer = ExpectingAnswer {
-                from_peer_id: peer_id,
-                regarding_offer_id: offer_id,
-            };
+    ) -> (OutMessageMeta, OutMessage) {
+        let expecting_answer = ExpectingAnswer {
+            from_peer_id: peer_id,
+            regarding_offer_id: offer_id,
+        };
+        let now = server_start_instant.seconds_elapsed();
 
+        if let Some(answer_receiver) = self.peers.get_mut(&answer_receiver_id) {
             if answer_receiver
                 .expecting_answers
                 .swap_remove(&expecting_answer)
-                .is_some()
+                .is_some_and(|valid_until| valid_until.valid(now))
             {
                 let answer_out_message = AnswerOutMessage {
                     action: AnnounceAction::Announce,
@@ -494,31 +1022,50 @@ impl TorrentData {
                     pending_scrape_id: None,
                 };
 
-                Some((meta, OutMessage::AnswerOutMessage(answer_out_message)))
-            } else {
-                let error_message = ErrorResponse {
-                    action: Some(ErrorResponseAction::Announce),
-                    info_hash: Some(info_hash),
-                    failure_reason:
-                        "Could not find the offer corresponding to your answer. It may have expired."
-                            .into(),
-                };
-
-                Some((
-                    request_sender_meta.into(),
-                    OutMessage::ErrorResponse(error_message),
-                ))
+                return (meta, OutMessage::AnswerOutMessage(answer_out_message));
             }
-        } else {
-            None
         }
+
+        let failure_reason = if config.protocol.notify_when_offering_peer_gone
+            && !self.peers.contains_key(&answer_receiver_id)
+        {
+            "The peer that sent the offer is no longer in the swarm."
+        } else {
+            "Could not find the offer corresponding to your answer. It may have expired."
+        };
+
+        let error_message = ErrorResponse {
+            action: Some(ErrorResponseAction::Announce),
+            info_hash: Some(info_hash),
+            failure_reason: failure_reason.into(),
+        };
+
+        (
+            request_sender_meta.into(),
+            OutMessage::ErrorResponse(error_message),
+        )
     }
 
+    /// Remove peer if it was announced over the closed connection
+    ///
+    /// Connections may announce with peer IDs belonging to peers on other
+    /// connections. Such requests are ignored, but the peer IDs are still
+    /// included when the connection closes.
     pub fn handle_connection_closed(
         &mut self,
         peer_id: PeerId,
+        consumer_id: ConsumerId,
+        connection_id: ConnectionId,
         #[cfg(feature = "metrics")] peer_gauge: &::metrics::Gauge,
     ) {
+        let belongs_to_connection = self.peers.get(&peer_id).is_some_and(|peer| {
+            peer.consumer_id == consumer_id && peer.connection_id == connection_id
+        });
+
+        if !belongs_to_connection {
+            return;
+        }
+
         if let Some(peer) = self.peers.swap_remove(&peer_id) {
             if peer.seeder {
                 self.num_seeders -= 1;
@@ -555,8 +1102,59 @@ struct Peer {
     pub consumer_id: ConsumerId,
     pub connection_id: ConnectionId,
     pub seeder: bool,
+    /// Peer announced with event `started` when it was first stored
+    pub started: bool,
     pub valid_until: ValidUntil,
     pub expecting_answers: IndexMap<ExpectingAnswer, ValidUntil>,
+    pub metadata: PeerMetadata,
+}
+
+/// Action taken on announce request using peer id of peer announced over
+/// another connection
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PeerIdCollisionAction {
+    Ignored,
+    Replaced,
+    Rejected,
+}
+
+#[cfg(feature = "metrics")]
+struct PeerIdCollisionCounters {
+    ignored: ::metrics::Counter,
+    replaced: ::metrics::Counter,
+    rejected: ::metrics::Counter,
+}
+
+#[cfg(feature = "metrics")]
+impl PeerIdCollisionCounters {
+    fn new(worker_index: usize, ip_version: IpVersion) -> Self {
+        let ip_version = match ip_version {
+            IpVersion::V4 => "4",
+            IpVersion::V6 => "6",
+        };
+        let counter = |action: &'static str| {
+            ::metrics::counter!(
+                "aquatic_peer_id_collisions_total",
+                "action" => action,
+                "ip_version" => ip_version,
+                "worker_index" => worker_index.to_string(),
+            )
+        };
+
+        Self {
+            ignored: counter("ignored"),
+            replaced: counter("replaced"),
+            rejected: counter("rejected"),
+        }
+    }
+
+    fn increment(&self, action: PeerIdCollisionAction) {
+        match action {
+            PeerIdCollisionAction::Ignored => self.ignored.increment(1),
+            PeerIdCollisionAction::Replaced => self.replaced.increment(1),
+            PeerIdCollisionAction::Rejected => self.rejected.increment(1),
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
@@ -672,9 +1270,13 @@ where
 
 #[cfg(test)]
 mod tests {
+    use aquatic_common::ManualClock;
+    use aquatic_ws_protocol::incoming::ScrapeRequestInfoHashes;
     use hashbrown::HashSet;
     use rand::{rngs::SmallRng, SeedableRng};
 
+    use crate::enrichment::CountryCode;
+
     use super::*;
 
     #[test]
@@ -723,4 +1325,748 @@ mod tests {
 
         assert_eq!(response_peers.len(), unique.len(),);
     }
+
+    #[test]
+    fn test_clean_in_batches() {
+        let mut config = Config::default();
+
+        config.cleaning.torrent_cleaning_batch_size = 4;
+
+        let access_list = Arc::new(AccessListArcSwap::default());
+        let mut access_list_cache = create_access_list_cache(&access_list);
+        let now = ServerStartInstant::new().seconds_elapsed();
+
+        let mut torrent_map = TorrentMap::new(0, IpVersion::V4);
+
+        for i in 0..10u8 {
+            let mut torrent_data = TorrentData::default();
+
+            // Every other torrent only has expired peers
+            let offset_seconds = if i % 2 == 0 { 0 } else { 120 };
+
+            torrent_data.peers.insert(
+                PeerId([i; 20]),
+                Peer {
+                    consumer_id: ConsumerId(0),
+                    connection_id: ConnectionId::default(),
+                    seeder: false,
+                    started: false,
+                    valid_until: ValidUntil::new_with_now(now, offset_seconds),
+                    expecting_answers: Default::default(),
+                    metadata: Default::default(),
+                },
+            );
+
+            torrent_map.torrents.insert(InfoHash([i; 20]), torrent_data);
+        }
+
+        torrent_map.clean(&config, &mut access_list_cache, now);
+
+        assert_eq!(torrent_map.torrents.len(), 8);
+
+        torrent_map.clean(&config, &mut access_list_cache, now);
+        torrent_map.clean(&config, &mut access_list_cache, now);
+
+        assert_eq!(torrent_map.torrents.len(), 5);
+        assert!(torrent_map
+            .torrents
+            .keys()
+            .all(|info_hash| info_hash.0[0] % 2 == 1));
+    }
+
+    #[test]
+    fn test_clean_with_manual_clock() {
+        let config = Config::default();
+        let access_list = Arc::new(AccessListArcSwap::default());
+        let clock = ManualClock::new(SecondsSinceServerStart::new(0));
+
+        let mut torrent_maps = TorrentMaps::new(0);
+        let mut torrent_data = TorrentData::default();
+
+        torrent_data.peers.insert(
+            PeerId([0; 20]),
+            Peer {
+                consumer_id: ConsumerId(0),
+                connection_id: ConnectionId::default(),
+                seeder: false,
+                started: false,
+                valid_until: ValidUntil::new(&clock, 60),
+                expecting_answers: Default::default(),
+                metadata: Default::default(),
+            },
+        );
+
+        torrent_maps
+            .ipv4
+            .torrents
+            .insert(InfoHash([0; 20]), torrent_data);
+
+        clock.advance(59);
+        torrent_maps.clean(&config, &access_list, &clock);
+
+        assert_eq!(torrent_maps.ipv4.torrents.len(), 1);
+
+        clock.advance(1);
+        torrent_maps.clean(&config, &access_list, &clock);
+
+        assert!(torrent_maps.ipv4.torrents.is_empty());
+    }
+
+    #[test]
+    fn test_completed_count_kept_when_torrent_removed() {
+        let config = Config::default();
+        let access_list = Arc::new(AccessListArcSwap::default());
+        let clock = ManualClock::new(SecondsSinceServerStart::new(0));
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut out_messages = Vec::new();
+
+        let mut torrent_maps = TorrentMaps::new(0);
+        let mut torrent_data = TorrentData {
+            num_completed: 1,
+            ..Default::default()
+        };
+
+        torrent_data.peers.insert(
+            PeerId([0; 20]),
+            Peer {
+                consumer_id: ConsumerId(0),
+                connection_id: ConnectionId::default(),
+                seeder: true,
+                started: false,
+                valid_until: ValidUntil::new(&clock, 60),
+                expecting_answers: Default::default(),
+                metadata: Default::default(),
+            },
+        );
+
+        torrent_maps
+            .ipv4
+            .torrents
+            .insert(InfoHash([0; 20]), torrent_data);
+
+        clock.advance(60);
+        torrent_maps.clean(&config, &access_list, &clock);
+
+        assert!(torrent_maps.ipv4.torrents.is_empty());
+
+        let meta = InMessageMeta {
+            out_message_consumer_id: ConsumerId(0),
+            connection_id: ConnectionId::default(),
+            ip_version: IpVersion::V4,
+            pending_scrape_id: None,
+            peer_metadata: Default::default(),
+        };
+
+        torrent_maps.ipv4.handle_scrape_request(
+            &config,
+            &mut out_messages,
+            meta,
+            ScrapeRequest {
+                action: ScrapeAction::Scrape,
+                info_hashes: Some(ScrapeRequestInfoHashes::Single(InfoHash([0; 20]))),
+            },
+        );
+
+        match out_messages.pop() {
+            Some((_, OutMessage::ScrapeResponse(response))) => {
+                assert_eq!(response.files[&InfoHash([0; 20])].downloaded, 1);
+            }
+            _ => panic!("expected scrape response"),
+        }
+
+        torrent_maps.ipv4.handle_announce_request(
+            &config,
+            &mut rng,
+            &mut out_messages,
+            ServerStartInstant::new(),
+            meta,
+            AnnounceRequest {
+                action: AnnounceAction::Announce,
+                info_hash: InfoHash([0; 20]),
+                peer_id: PeerId([1; 20]),
+                bytes_left: Some(0),
+                event: Some(AnnounceEvent::Completed),
+                offers: None,
+                numwant: None,
+                answer: None,
+                answer_to_peer_id: None,
+                answer_offer_id: None,
+            },
+        );
+
+        assert!(torrent_maps
+            .ipv4
+            .removed_torrent_completed_counts
+            .is_empty());
+        assert_eq!(
+            torrent_maps.ipv4.torrents[&InfoHash([0; 20])].num_completed,
+            2
+        );
+    }
+
+    #[test]
+    fn test_announced_too_early() {
+        let mut config = Config::default();
+
+        config.protocol.min_announce_interval = 30;
+
+        let server_start_instant = ServerStartInstant::new();
+        let mut torrent_data = TorrentData::default();
+
+        torrent_data.peers.insert(
+            PeerId([0; 20]),
+            Peer {
+                consumer_id: ConsumerId(0),
+                connection_id: ConnectionId::default(),
+                seeder: false,
+                started: false,
+                valid_until: ValidUntil::new(server_start_instant, config.cleaning.max_peer_age),
+                expecting_answers: Default::default(),
+                metadata: Default::default(),
+            },
+        );
+
+        let mut request = AnnounceRequest {
+            action: AnnounceAction::Announce,
+            info_hash: InfoHash([0; 20]),
+            peer_id: PeerId([0; 20]),
+            bytes_left: Some(1),
+            event: None,
+            offers: None,
+            numwant: None,
+            answer: None,
+            answer_to_peer_id: None,
+            answer_offer_id: None,
+        };
+
+        assert!(torrent_data.announced_too_early(&config, server_start_instant, &request));
+
+        request.event = Some(AnnounceEvent::Completed);
+
+        assert!(!torrent_data.announced_too_early(&config, server_start_instant, &request));
+
+        request.event = None;
+        request.peer_id = PeerId([1; 20]);
+
+        assert!(!torrent_data.announced_too_early(&config, server_start_instant, &request));
+
+        config.protocol.min_announce_interval = 0;
+        request.peer_id = PeerId([0; 20]);
+
+        assert!(!torrent_data.announced_too_early(&config, server_start_instant, &request));
+    }
+
+    #[test]
+    fn test_connection_closed_only_removes_own_peer() {
+        let mut connection_ids = slotmap::SlotMap::<ConnectionId, ()>::with_key();
+
+        let connection_id = connection_ids.insert(());
+        let other_connection_id = connection_ids.insert(());
+
+        let server_start_instant = ServerStartInstant::new();
+        let mut torrent_maps = TorrentMaps::new(0);
+        let mut torrent_data = TorrentData::default();
+
+        torrent_data.peers.insert(
+            PeerId([0; 20]),
+            Peer {
+                consumer_id: ConsumerId(1),
+                connection_id,
+                seeder: true,
+                started: false,
+                valid_until: ValidUntil::new(server_start_instant, 60),
+                expecting_answers: Default::default(),
+                metadata: Default::default(),
+            },
+        );
+        torrent_data.num_seeders = 1;
+
+        torrent_maps
+            .ipv4
+            .torrents
+            .insert(InfoHash([0; 20]), torrent_data);
+
+        for (consumer_id, connection_id) in [
+            (ConsumerId(1), other_connection_id),
+            (ConsumerId(0), connection_id),
+        ] {
+            torrent_maps.handle_connection_closed(
+                InfoHash([0; 20]),
+                PeerId([0; 20]),
+                IpVersion::V4,
+                consumer_id,
+                connection_id,
+            );
+
+            let torrent_data = &torrent_maps.ipv4.torrents[&InfoHash([0; 20])];
+
+            assert_eq!(torrent_data.peers.len(), 1);
+            assert_eq!(torrent_data.num_seeders, 1);
+        }
+
+        torrent_maps.handle_connection_closed(
+            InfoHash([0; 20]),
+            PeerId([0; 20]),
+            IpVersion::V4,
+            ConsumerId(1),
+            connection_id,
+        );
+
+        let torrent_data = &torrent_maps.ipv4.torrents[&InfoHash([0; 20])];
+
+        assert!(torrent_data.peers.is_empty());
+        assert_eq!(torrent_data.num_seeders, 0);
+    }
+
+    #[test]
+    fn test_seeder_offers_only_to_leechers() {
+        let mut config = Config::default();
+        let mut rng = SmallRng::from_entropy();
+        let server_start_instant = ServerStartInstant::new();
+        let mut torrent_data = TorrentData::default();
+
+        for i in 0..6u8 {
+            let seeder = i < 3;
+
+            torrent_data.peers.insert(
+                PeerId([i; 20]),
+                Peer {
+                    consumer_id: ConsumerId(0),
+                    connection_id: ConnectionId::default(),
+                    seeder,
+                    started: false,
+                    valid_until: ValidUntil::new(server_start_instant, 60),
+                    expecting_answers: Default::default(),
+                    metadata: Default::default(),
+                },
+            );
+
+            if seeder {
+                torrent_data.num_seeders += 1;
+            }
+        }
+
+        let offers = || {
+            (0..10u8)
+                .map(|i| AnnounceRequestOffer {
+                    offer: RtcOffer {
+                        t: RtcOfferType::Offer,
+                        sdp: "test".into(),
+                    },
+                    offer_id: OfferId([i; 20]),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut out_messages = Vec::new();
+
+        torrent_data.handle_offers(
+            &config,
+            &mut rng,
+            server_start_instant,
+            InfoHash([0; 20]),
+            PeerId([0; 20]),
+            offers(),
+            &mut out_messages,
+        );
+
+        let offer_receivers = torrent_data.peers[&PeerId([0; 20])]
+            .expecting_answers
+            .keys()
+            .map(|expecting_answer| expecting_answer.from_peer_id)
+            .collect::<HashSet<_>>();
+
+        assert_eq!(out_messages.len(), 3);
+        assert_eq!(
+            offer_receivers,
+            HashSet::from_iter((3..6u8).map(|i| PeerId([i; 20])))
+        );
+
+        config.protocol.seeder_offers_only_to_leechers = false;
+        out_messages.clear();
+
+        torrent_data.handle_offers(
+            &config,
+            &mut rng,
+            server_start_instant,
+            InfoHash([0; 20]),
+            PeerId([1; 20]),
+            offers(),
+            &mut out_messages,
+        );
+
+        assert_eq!(out_messages.len(), 5);
+    }
+
+    #[test]
+    fn test_extract_nearby_peers() {
+        let mut rng = SmallRng::from_entropy();
+        let server_start_instant = ServerStartInstant::new();
+        let mut torrent_data = TorrentData::default();
+
+        let metadata = |asn| PeerMetadata {
+            country: Some(CountryCode(*b"SE")),
+            asn,
+        };
+
+        for (i, seeder, metadata) in [
+            (0u8, false, metadata(Some(1))),
+            (1, true, metadata(Some(1))),
+            (2, false, metadata(Some(2))),
+            (3, false, PeerMetadata::default()),
+            (4, false, metadata(None)),
+        ] {
+            torrent_data.peers.insert(
+                PeerId([i; 20]),
+                Peer {
+                    consumer_id: ConsumerId(0),
+                    connection_id: ConnectionId::default(),
+                    seeder,
+                    started: false,
+                    valid_until: ValidUntil::new(server_start_instant, 60),
+                    expecting_answers: Default::default(),
+                    metadata,
+                },
+            );
+        }
+
+        let mut extract = |max_num_peers_to_take, leechers_only| {
+            torrent_data
+                .extract_nearby_peers(
+                    &mut rng,
+                    max_num_peers_to_take,
+                    PeerId([0; 20]),
+                    metadata(Some(1)),
+                    leechers_only,
+                )
+                .into_iter()
+                .map(|(peer_id, _, _)| peer_id.0[0])
+                .collect::<Vec<_>>()
+        };
+
+        for _ in 0..10 {
+            assert_eq!(extract(1, false), vec![1]);
+            assert_eq!(extract(4, false)[..1], [1]);
+            assert_eq!(extract(4, false)[3], 3);
+            assert_eq!(extract(10, true).len(), 3);
+            assert_ne!(extract(1, true), vec![3]);
+        }
+    }
+
+    #[test]
+    fn test_handle_answer() {
+        let server_start_instant = ServerStartInstant::new();
+        let mut torrent_data = TorrentData::default();
+
+        let meta = InMessageMeta {
+            out_message_consumer_id: ConsumerId(0),
+            connection_id: ConnectionId::default(),
+            ip_version: IpVersion::V4,
+            pending_scrape_id: None,
+            peer_metadata: Default::default(),
+        };
+
+        let mut expecting_answers = IndexMap::default();
+
+        for (i, offer_age) in [(0u8, 60), (1, 0)] {
+            expecting_answers.insert(
+                ExpectingAnswer {
+                    from_peer_id: PeerId([1; 20]),
+                    regarding_offer_id: OfferId([i; 20]),
+                },
+                ValidUntil::new(server_start_instant, offer_age),
+            );
+        }
+
+        torrent_data.peers.insert(
+            PeerId([0; 20]),
+            Peer {
+                consumer_id: ConsumerId(0),
+                connection_id: ConnectionId::default(),
+                seeder: false,
+                started: false,
+                valid_until: ValidUntil::new(server_start_instant, 60),
+                expecting_answers,
+                metadata: Default::default(),
+            },
+        );
+
+        let mut handle_answer = |config: &Config, sender: u8, receiver: u8, offer_id: u8| {
+            let (_, out_message) = torrent_data.handle_answer(
+                config,
+                server_start_instant,
+                meta,
+                InfoHash([0; 20]),
+                PeerId([sender; 20]),
+                PeerId([receiver; 20]),
+                OfferId([offer_id; 20]),
+                RtcAnswer {
+                    t: RtcAnswerType::Answer,
+                    sdp: "test".into(),
+                },
+            );
+
+            match out_message {
+                OutMessage::AnswerOutMessage(_) => None,
+                OutMessage::ErrorResponse(response) => Some(response.failure_reason),
+                _ => panic!("unexpected out message"),
+            }
+        };
+
+        let mut config = Config::default();
+
+        // Offer wasn't sent to this peer
+        assert!(handle_answer(&config, 2, 0, 0).is_some());
+        // Unknown receiver
+        let not_found = handle_answer(&config, 1, 2, 0).unwrap();
+        // Expired offer
+        assert_eq!(handle_answer(&config, 1, 0, 1), Some(not_found.clone()));
+
+        assert!(handle_answer(&config, 1, 0, 0).is_none());
+        // Offers can only be answered once
+        assert_eq!(handle_answer(&config, 1, 0, 0), Some(not_found.clone()));
+
+        // Missing receiver is only reported when configured
+        config.protocol.notify_when_offering_peer_gone = true;
+
+        let gone = handle_answer(&config, 1, 2, 0).unwrap();
+
+        assert_ne!(gone, not_found);
+        assert_eq!(handle_answer(&config, 1, 0, 0), Some(not_found));
+    }
+
+    #[test]
+    fn test_max_peers_per_torrent() {
+        let mut config = Config::default();
+        let mut rng = SmallRng::from_entropy();
+        let server_start_instant = ServerStartInstant::new();
+        let mut torrent_maps = TorrentMaps::new(0);
+        let mut out_messages = Vec::new();
+
+        config.protocol.max_peers_per_torrent = 3;
+
+        let meta = InMessageMeta {
+            out_message_consumer_id: ConsumerId(0),
+            connection_id: ConnectionId::default(),
+            ip_version: IpVersion::V4,
+            pending_scrape_id: None,
+            peer_metadata: Default::default(),
+        };
+
+        let mut announce = |peer_id: u8, bytes_left: usize| {
+            out_messages.clear();
+
+            torrent_maps.handle_announce_request(
+                &config,
+                &mut rng,
+                &mut out_messages,
+                server_start_instant,
+                meta,
+                AnnounceRequest {
+                    action: AnnounceAction::Announce,
+                    info_hash: InfoHash([0; 20]),
+                    peer_id: PeerId([peer_id; 20]),
+                    bytes_left: Some(bytes_left),
+                    event: None,
+                    offers: None,
+                    numwant: None,
+                    answer: None,
+                    answer_to_peer_id: None,
+                    answer_offer_id: None,
+                },
+            );
+
+            let is_error = matches!(out_messages[0].1, OutMessage::ErrorResponse(_));
+
+            let mut peer_ids = torrent_maps.ipv4.torrents[&InfoHash([0; 20])]
+                .peers
+                .keys()
+                .map(|peer_id| peer_id.0[0])
+                .collect::<Vec<_>>();
+
+            peer_ids.sort_unstable();
+
+            (is_error, peer_ids)
+        };
+
+        // One leecher and two seeders
+        announce(0, 1);
+        announce(1, 0);
+        assert_eq!(announce(2, 0), (false, vec![0, 1, 2]));
+
+        // Stored peers can still announce
+        assert_eq!(announce(1, 0), (false, vec![0, 1, 2]));
+
+        // Leecher is removed to make room for new peer
+        assert_eq!(announce(3, 0), (false, vec![1, 2, 3]));
+
+        // Only seeders left, so new peer is rejected
+        assert_eq!(announce(4, 1), (true, vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn test_require_started_event() {
+        let mut config = Config::default();
+        let mut rng = SmallRng::from_entropy();
+        let server_start_instant = ServerStartInstant::new();
+        let mut torrent_maps = TorrentMaps::new(0);
+        let mut out_messages = Vec::new();
+
+        config.protocol.require_started_event = true;
+
+        let meta = InMessageMeta {
+            out_message_consumer_id: ConsumerId(0),
+            connection_id: ConnectionId::default(),
+            ip_version: IpVersion::V4,
+            pending_scrape_id: None,
+            peer_metadata: Default::default(),
+        };
+
+        let mut announce = |peer_id: u8, event: Option<AnnounceEvent>| {
+            out_messages.clear();
+
+            torrent_maps.handle_announce_request(
+                &config,
+                &mut rng,
+                &mut out_messages,
+                server_start_instant,
+                meta,
+                AnnounceRequest {
+                    action: AnnounceAction::Announce,
+                    info_hash: InfoHash([0; 20]),
+                    peer_id: PeerId([peer_id; 20]),
+                    bytes_left: Some(1),
+                    event,
+                    offers: None,
+                    numwant: None,
+                    answer: None,
+                    answer_to_peer_id: None,
+                    answer_offer_id: None,
+                },
+            );
+
+            let is_error = matches!(out_messages[0].1, OutMessage::ErrorResponse(_));
+            let num_completed = torrent_maps
+                .ipv4
+                .torrents
+                .get(&InfoHash([0; 20]))
+                .map(|torrent_data| torrent_data.num_completed)
+                .unwrap_or(0);
+
+            (is_error, num_completed)
+        };
+
+        // Unknown peers can't send completed or stopped events
+        assert_eq!(announce(0, Some(AnnounceEvent::Completed)), (true, 0));
+        assert_eq!(announce(0, Some(AnnounceEvent::Stopped)), (true, 0));
+
+        // Peers stored without started event can't either
+        assert_eq!(announce(0, None), (false, 0));
+        assert_eq!(announce(0, Some(AnnounceEvent::Completed)), (true, 0));
+
+        // Started event is remembered across announces
+        assert_eq!(announce(1, Some(AnnounceEvent::Started)), (false, 0));
+        assert_eq!(announce(1, None), (false, 0));
+        assert_eq!(announce(1, Some(AnnounceEvent::Completed)), (false, 1));
+        assert_eq!(announce(1, Some(AnnounceEvent::Stopped)), (false, 1));
+    }
+
+    #[test]
+    fn test_peer_id_collision_policy() {
+        let mut connection_ids = slotmap::SlotMap::<ConnectionId, ()>::with_key();
+
+        let connection_id = connection_ids.insert(());
+        let other_connection_id = connection_ids.insert(());
+
+        let mut config = Config::default();
+        let mut rng = SmallRng::from_entropy();
+        let server_start_instant = ServerStartInstant::new();
+
+        // Peer is stored as announced over first connection and then
+        // announced over second one. Returns whether second request got an
+        // error response, any response and which connection peer ends up
+        // belonging to.
+        let mut run = |config: &Config, valid_until_offset: u32| {
+            let mut torrent_maps = TorrentMaps::new(0);
+            let mut torrent_data = TorrentData::default();
+
+            torrent_data.peers.insert(
+                PeerId([0; 20]),
+                Peer {
+                    consumer_id: ConsumerId(0),
+                    connection_id,
+                    seeder: true,
+                    started: false,
+                    valid_until: ValidUntil::new(server_start_instant, valid_until_offset),
+                    expecting_answers: Default::default(),
+                    metadata: Default::default(),
+                },
+            );
+            torrent_data.num_seeders = 1;
+
+            torrent_maps
+                .ipv4
+                .torrents
+                .insert(InfoHash([0; 20]), torrent_data);
+
+            let mut out_messages = Vec::new();
+
+            torrent_maps.handle_announce_request(
+                config,
+                &mut rng,
+                &mut out_messages,
+                server_start_instant,
+                InMessageMeta {
+                    out_message_consumer_id: ConsumerId(0),
+                    connection_id: other_connection_id,
+                    ip_version: IpVersion::V4,
+                    pending_scrape_id: None,
+                    peer_metadata: Default::default(),
+                },
+                AnnounceRequest {
+                    action: AnnounceAction::Announce,
+                    info_hash: InfoHash([0; 20]),
+                    peer_id: PeerId([0; 20]),
+                    bytes_left: Some(1),
+                    event: None,
+                    offers: None,
+                    numwant: None,
+                    answer: None,
+                    answer_to_peer_id: None,
+                    answer_offer_id: None,
+                },
+            );
+
+            let torrent_data = &torrent_maps.ipv4.torrents[&InfoHash([0; 20])];
+            let peer = &torrent_data.peers[&PeerId([0; 20])];
+
+            assert_eq!(torrent_data.peers.len(), 1);
+            assert_eq!(torrent_data.num_seeders, usize::from(peer.seeder));
+
+            (
+                matches!(
+                    out_messages.first(),
+                    Some((_, OutMessage::ErrorResponse(_)))
+                ),
+                !out_messages.is_empty(),
+                peer.connection_id == other_connection_id,
+            )
+        };
+
+        let max_peer_age = config.cleaning.max_peer_age;
+        let stale_age = config.protocol.peer_id_collision_stale_age;
+
+        config.protocol.peer_id_collision_policy = PeerIdCollisionPolicy::Ignore;
+
+        assert_eq!(run(&config, 0), (false, false, false));
+
+        config.protocol.peer_id_collision_policy = PeerIdCollisionPolicy::ErrorResponse;
+
+        assert_eq!(run(&config, 0), (true, true, false));
+
+        config.protocol.peer_id_collision_policy = PeerIdCollisionPolicy::ReplaceIfStale;
+
+        assert_eq!(run(&config, max_peer_age), (false, false, false));
+        assert_eq!(run(&config, max_peer_age - stale_age), (false, true, true));
+    }
 }
@@ -1,12 +1,13 @@
-use std::sync::{atomic::AtomicUsize, Arc};
+use std::sync::{atomic::AtomicUsize, Arc, Mutex};
+use std::time::Instant;
 
 use aquatic_ws_protocol::common::InfoHash;
+use hdrhistogram::Histogram;
 use rand_distr::Gamma;
 
 #[derive(Default)]
 pub struct Statistics {
     pub requests: AtomicUsize,
-    pub response_peers: AtomicUsize,
     pub responses_announce: AtomicUsize,
     pub responses_offer: AtomicUsize,
     pub responses_answer: AtomicUsize,
@@ -20,6 +21,11 @@ pub struct LoadTestState {
     pub info_hashes: Arc<[InfoHash]>,
     pub statistics: Arc<Statistics>,
     pub gamma: Arc<Gamma<f64>>,
+    /// Offer SDPs include time sent relative to this instant
+    pub start_instant: Instant,
+    /// Time from sending offers to receiving them on other connections, in
+    /// microseconds. Recorded by socket workers and reset on each report.
+    pub offer_latencies: Arc<Mutex<Histogram<u64>>>,
 }
 
 #[derive(PartialEq, Eq, Clone, Copy)]
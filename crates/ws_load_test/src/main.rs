@@ -1,9 +1,10 @@
-use std::sync::{atomic::Ordering, Arc};
+use std::sync::{atomic::Ordering, Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 
 use aquatic_ws_protocol::common::InfoHash;
 use glommio::LocalExecutorBuilder;
+use hdrhistogram::Histogram;
 use rand::prelude::*;
 use rand_distr::Gamma;
 
@@ -19,6 +20,8 @@ use network::*;
 #[global_allocator]
 static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
+const PERCENTILES: &[f64] = &[10.0, 25.0, 50.0, 75.0, 90.0, 95.0, 99.0, 99.9, 100.0];
+
 pub fn main() {
     aquatic_common::cli::run_app_with_cli_and_config::<Config>(
         "aquatic_ws_load_test: WebTorrent load tester",
@@ -53,6 +56,8 @@ fn run(config: Config) -> ::anyhow::Result<()> {
         info_hashes: Arc::from(info_hashes.into_boxed_slice()),
         statistics: Arc::new(Statistics::default()),
         gamma: Arc::new(gamma),
+        start_instant: Instant::now(),
+        offer_latencies: Arc::new(Mutex::new(Histogram::new(2).unwrap())),
     };
 
     let tls_config = create_tls_config().unwrap();
@@ -136,6 +141,7 @@ fn monitor_statistics(state: LoadTestState, config: &Config) {
     let start_time = Instant::now();
     let mut time_max_connections_reached = None;
     let mut report_avg_response_vec: Vec<f64> = Vec::new();
+    let mut report_offer_latencies = Histogram::<u64>::new(2).unwrap();
 
     let interval = 5;
     let interval_f64 = interval as f64;
@@ -166,6 +172,15 @@ fn monitor_statistics(state: LoadTestState, config: &Config) {
 
         let connections = statistics.connections.load(Ordering::Relaxed);
 
+        let offer_latencies = {
+            let mut offer_latencies = state.offer_latencies.lock().unwrap();
+            let cloned = offer_latencies.clone();
+
+            offer_latencies.reset();
+
+            cloned
+        };
+
         let responses_per_second = responses_announce_per_second
             + responses_offer_per_second
             + responses_answer_per_second
@@ -174,6 +189,7 @@ fn monitor_statistics(state: LoadTestState, config: &Config) {
 
         if !config.measure_after_max_connections_reached || time_max_connections_reached.is_some() {
             report_avg_response_vec.push(responses_per_second);
+            report_offer_latencies.add(&offer_latencies).unwrap();
         } else if connections >= config.num_workers * config.num_connections_per_worker {
             time_max_connections_reached = Some(Instant::now());
 
@@ -195,6 +211,10 @@ fn monitor_statistics(state: LoadTestState, config: &Config) {
         println!("  - Error responses:   {:.2}", responses_error_per_second);
         println!("Active connections: {}", connections);
 
+        if !offer_latencies.is_empty() {
+            print_offer_latencies(&offer_latencies);
+        }
+
         if config.measure_after_max_connections_reached {
             if let Some(start) = time_max_connections_reached {
                 let time_elapsed = start.elapsed();
@@ -202,7 +222,12 @@ fn monitor_statistics(state: LoadTestState, config: &Config) {
                 if config.duration != 0
                     && time_elapsed >= Duration::from_secs(config.duration as u64)
                 {
-                    report(config, report_avg_response_vec, time_elapsed);
+                    report(
+                        config,
+                        report_avg_response_vec,
+                        report_offer_latencies,
+                        time_elapsed,
+                    );
 
                     break;
                 }
@@ -211,7 +236,12 @@ fn monitor_statistics(state: LoadTestState, config: &Config) {
             let time_elapsed = start_time.elapsed();
 
             if config.duration != 0 && time_elapsed >= Duration::from_secs(config.duration as u64) {
-                report(config, report_avg_response_vec, time_elapsed);
+                report(
+                    config,
+                    report_avg_response_vec,
+                    report_offer_latencies,
+                    time_elapsed,
+                );
 
                 break;
             }
@@ -219,7 +249,20 @@ fn monitor_statistics(state: LoadTestState, config: &Config) {
     }
 }
 
-fn report(config: &Config, report_avg_response_vec: Vec<f64>, time_elapsed: Duration) {
+fn print_offer_latencies(histogram: &Histogram<u64>) {
+    println!("Offer relay latency (microseconds):");
+
+    for p in PERCENTILES {
+        println!("  - p{}: {}", p, histogram.value_at_percentile(*p));
+    }
+}
+
+fn report(
+    config: &Config,
+    report_avg_response_vec: Vec<f64>,
+    report_offer_latencies: Histogram<u64>,
+    time_elapsed: Duration,
+) {
     let report_len = report_avg_response_vec.len() as f64;
     let report_sum: f64 = report_avg_response_vec.into_iter().sum();
     let report_avg: f64 = report_sum / report_len;
@@ -234,4 +277,8 @@ fn report(config: &Config, report_avg_response_vec: Vec<f64>, time_elapsed: Dura
         report_avg,
         config
     );
+
+    if !report_offer_latencies.is_empty() {
+        print_offer_latencies(&report_offer_latencies);
+    }
 }
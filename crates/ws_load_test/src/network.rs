@@ -22,6 +22,7 @@ use futures::{SinkExt, StreamExt};
 use futures_rustls::{client::TlsStream, TlsConnector};
 use glommio::net::TcpStream;
 use glommio::{prelude::*, timer::TimerActionRepeat};
+use hdrhistogram::Histogram;
 use rand::{prelude::SmallRng, Rng, SeedableRng};
 use rand_distr::{Distribution, WeightedIndex};
 
@@ -39,9 +40,36 @@ pub async fn run_socket_thread(
     let config = Rc::new(config);
     let rng = Rc::new(RefCell::new(SmallRng::from_entropy()));
     let num_active_connections = Rc::new(RefCell::new(0usize));
+    let offer_latencies = Rc::new(RefCell::new(Histogram::new(2).unwrap()));
     let connection_creation_interval =
         Duration::from_millis(config.connection_creation_interval_ms);
 
+    // Periodically pass on recorded latencies, avoiding contention over
+    // shared histogram
+    TimerActionRepeat::repeat({
+        let shared_offer_latencies = load_test_state.offer_latencies.clone();
+        let offer_latencies = offer_latencies.clone();
+
+        move || {
+            let shared_offer_latencies = shared_offer_latencies.clone();
+            let offer_latencies = offer_latencies.clone();
+
+            async move {
+                let mut offer_latencies = offer_latencies.borrow_mut();
+
+                shared_offer_latencies
+                    .lock()
+                    .unwrap()
+                    .add(&*offer_latencies)
+                    .unwrap();
+
+                offer_latencies.reset();
+
+                Some(Duration::from_secs(1))
+            }
+        }
+    });
+
     TimerActionRepeat::repeat(move || {
         periodically_open_connections(
             config.clone(),
@@ -49,6 +77,7 @@ pub async fn run_socket_thread(
             load_test_state.clone(),
             num_active_connections.clone(),
             rng.clone(),
+            offer_latencies.clone(),
             connection_creation_interval,
         )
     })
@@ -64,6 +93,7 @@ async fn periodically_open_connections(
     load_test_state: LoadTestState,
     num_active_connections: Rc<RefCell<usize>>,
     rng: Rc<RefCell<SmallRng>>,
+    offer_latencies: Rc<RefCell<Histogram<u64>>>,
     connection_creation_interval: Duration,
 ) -> Option<Duration> {
     if *num_active_connections.borrow() < config.num_connections_per_worker {
@@ -74,6 +104,7 @@ async fn periodically_open_connections(
                 load_test_state,
                 num_active_connections,
                 rng,
+                offer_latencies,
             )
             .await
             {
@@ -90,6 +121,7 @@ struct Connection {
     config: Rc<Config>,
     load_test_state: LoadTestState,
     rng: Rc<RefCell<SmallRng>>,
+    offer_latencies: Rc<RefCell<Histogram<u64>>>,
     peer_id: PeerId,
     can_send_answer: Option<(InfoHash, PeerId, OfferId)>,
     stream: WebSocketStream<TlsStream<TcpStream>>,
@@ -102,6 +134,7 @@ impl Connection {
         load_test_state: LoadTestState,
         num_active_connections: Rc<RefCell<usize>>,
         rng: Rc<RefCell<SmallRng>>,
+        offer_latencies: Rc<RefCell<Histogram<u64>>>,
     ) -> anyhow::Result<()> {
         let peer_id = PeerId(rng.borrow_mut().gen());
         let stream = TcpStream::connect(config.server_address)
@@ -123,6 +156,7 @@ impl Connection {
             config,
             load_test_state,
             rng,
+            offer_latencies,
             stream,
             peer_id,
             can_send_answer: None,
@@ -198,12 +232,19 @@ impl Connection {
 
                     let mut offers = Vec::with_capacity(self.config.torrents.offers_per_request);
 
+                    // Include time sent for measuring offer relay latency
+                    let sdp = format!(
+                        "{}-{}",
+                        SDP,
+                        self.load_test_state.start_instant.elapsed().as_micros()
+                    );
+
                     for _ in 0..self.config.torrents.offers_per_request {
                         offers.push(AnnounceRequestOffer {
                             offer_id: OfferId(rng.gen()),
                             offer: RtcOffer {
                                 t: RtcOfferType::Offer,
-                                sdp: SDP.into(),
+                                sdp: sdp.clone(),
                             },
                         })
                     }
@@ -271,6 +312,21 @@ impl Connection {
                     .responses_offer
                     .fetch_add(1, Ordering::Relaxed);
 
+                if let Some(sent_at) = offer
+                    .offer
+                    .sdp
+                    .rsplit('-')
+                    .next()
+                    .and_then(|micros| micros.parse::<u64>().ok())
+                {
+                    let now = self.load_test_state.start_instant.elapsed().as_micros() as u64;
+
+                    self.offer_latencies
+                        .borrow_mut()
+                        .record(now.saturating_sub(sent_at))
+                        .unwrap();
+                }
+
                 self.can_send_answer = Some((offer.info_hash, offer.peer_id, offer.offer_id));
             }
             Ok(OutMessage::AnswerOutMessage(_)) => {
@@ -57,21 +57,16 @@ pub struct AnnounceRequest {
     pub answer_offer_id: Option<OfferId>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum AnnounceEvent {
     Started,
     Stopped,
     Completed,
+    #[default]
     Update,
 }
 
-impl Default for AnnounceEvent {
-    fn default() -> Self {
-        Self::Update
-    }
-}
-
 /// Element of AnnounceRequest.offers
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct AnnounceRequestOffer {
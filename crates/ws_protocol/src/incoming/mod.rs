@@ -15,6 +15,15 @@ pub enum InMessage {
     ScrapeRequest(ScrapeRequest),
 }
 
+impl InMessage {
+    /// Parse message from JSON. The buffer is used as scratch space during
+    /// parsing, so its contents are modified.
+    #[inline]
+    pub fn from_mut_slice(bytes: &mut [u8]) -> ::anyhow::Result<Self> {
+        ::simd_json::serde::from_slice(bytes).context("deserialize with serde")
+    }
+}
+
 #[cfg(feature = "tungstenite")]
 impl InMessage {
     #[inline]
@@ -26,8 +26,8 @@ mod tests {
             ScrapeRequestInfoHashes,
         },
         outgoing::{
-            AnnounceResponse, AnswerOutMessage, OfferOutMessage, OutMessage, ScrapeResponse,
-            ScrapeStatistics,
+            AnnounceResponse, AnswerOutMessage, ErrorResponse, ErrorResponseAction,
+            OfferOutMessage, OutMessage, ScrapeResponse, ScrapeStatistics,
         },
     };
 
@@ -162,6 +162,7 @@ mod tests {
                 complete: Arbitrary::arbitrary(g),
                 incomplete: Arbitrary::arbitrary(g),
                 announce_interval: Arbitrary::arbitrary(g),
+                external_ip: Arbitrary::arbitrary(g),
             }
         }
     }
@@ -364,6 +365,124 @@ mod tests {
         assert_eq!(expected, observed);
     }
 
+    #[test]
+    fn test_shared_fixtures() {
+        use aquatic_protocol_tests as fixtures;
+
+        for (fixture_event, event) in [
+            (fixtures::Event::None, None),
+            (fixtures::Event::Started, Some(AnnounceEvent::Started)),
+            (fixtures::Event::Stopped, Some(AnnounceEvent::Stopped)),
+            (fixtures::Event::Completed, Some(AnnounceEvent::Completed)),
+        ] {
+            let mut json = fixtures::ws::announce_request(fixture_event).into_bytes();
+
+            assert_eq!(
+                InMessage::from_mut_slice(&mut json).unwrap(),
+                InMessage::AnnounceRequest(AnnounceRequest {
+                    action: AnnounceAction::Announce,
+                    info_hash: InfoHash(fixtures::INFO_HASH),
+                    peer_id: PeerId(fixtures::PEER_ID),
+                    bytes_left: Some(fixtures::BYTES_LEFT as usize),
+                    event,
+                    offers: None,
+                    numwant: None,
+                    answer: None,
+                    answer_to_peer_id: None,
+                    answer_offer_id: None,
+                })
+            );
+        }
+
+        let mut json = fixtures::ws::SCRAPE_REQUEST.as_bytes().to_vec();
+
+        assert_eq!(
+            InMessage::from_mut_slice(&mut json).unwrap(),
+            InMessage::ScrapeRequest(ScrapeRequest {
+                action: ScrapeAction::Scrape,
+                info_hashes: Some(ScrapeRequestInfoHashes::Single(InfoHash(
+                    fixtures::INFO_HASH
+                ))),
+            })
+        );
+
+        // Compare JSON values, since serde_json doesn't escape non-ASCII
+        // characters and field order may differ
+        let to_json_value = |out_message: OutMessage| {
+            ::serde_json::from_str::<::serde_json::Value>(
+                &::serde_json::to_string(&out_message).unwrap(),
+            )
+            .unwrap()
+        };
+
+        assert_eq!(
+            to_json_value(OutMessage::AnnounceResponse(AnnounceResponse {
+                action: AnnounceAction::Announce,
+                info_hash: InfoHash(fixtures::INFO_HASH),
+                complete: fixtures::SEEDERS,
+                incomplete: fixtures::LEECHERS,
+                announce_interval: fixtures::ANNOUNCE_INTERVAL,
+                external_ip: None,
+            })),
+            ::serde_json::from_str::<::serde_json::Value>(fixtures::ws::ANNOUNCE_RESPONSE).unwrap()
+        );
+        assert_eq!(
+            to_json_value(OutMessage::ScrapeResponse(ScrapeResponse {
+                action: ScrapeAction::Scrape,
+                files: [(
+                    InfoHash(fixtures::INFO_HASH),
+                    ScrapeStatistics {
+                        complete: fixtures::SEEDERS,
+                        incomplete: fixtures::LEECHERS,
+                        downloaded: fixtures::DOWNLOADED,
+                    },
+                )]
+                .into_iter()
+                .collect(),
+            })),
+            ::serde_json::from_str::<::serde_json::Value>(fixtures::ws::SCRAPE_RESPONSE).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_error_response_for_invalid_request() {
+        let response = ErrorResponse::invalid_request(
+            br#"{"action": "announce", "info_hash": "aaaabbbbccccddddeeee", "offers": 1}"#,
+        );
+
+        assert_eq!(
+            response,
+            ErrorResponse {
+                failure_reason: "Invalid announce request".into(),
+                action: Some(ErrorResponseAction::Announce),
+                info_hash: Some(info_hash_from_bytes(b"aaaabbbbccccddddeeee")),
+            }
+        );
+
+        // Invalid JSON
+        let response = ErrorResponse::invalid_request(
+            br#"{"action": "scrape", "info_hash": ["aaaabbbbccccddddeeee"], "x": }"#,
+        );
+
+        assert_eq!(response.action, None);
+        assert_eq!(response.failure_reason, "Invalid request");
+
+        let response = ErrorResponse::invalid_request(
+            br#"{"action": "scrape", "info_hash": ["aaaabbbbccccddddeeee"], "x": 1}"#,
+        );
+
+        assert_eq!(response.action, Some(ErrorResponseAction::Scrape));
+        assert_eq!(response.info_hash, None);
+
+        let response = ErrorResponse::invalid_request(
+            br#"{"action": "unknown", "info_hash": "aaaabbbbccccddddeeee"}"#,
+        );
+
+        assert_eq!(response.failure_reason, "Unknown action");
+        assert_eq!(response.action, None);
+        assert_eq!(response.info_hash, None);
+    }
+
     #[quickcheck]
     fn quickcheck_serde_identity_info_hashes(info_hashes: ScrapeRequestInfoHashes) -> bool {
         let deserialized: ScrapeRequestInfoHashes = unsafe {
@@ -1,3 +1,5 @@
+use std::net::IpAddr;
+
 use serde::{Deserialize, Serialize};
 
 use crate::common::*;
@@ -12,4 +14,7 @@ pub struct AnnounceResponse {
     pub incomplete: usize,
     #[serde(rename = "interval")]
     pub announce_interval: usize, // Default 2 min probably
+    /// Public IP address of the requesting peer as seen by the tracker
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub external_ip: Option<IpAddr>,
 }
@@ -16,6 +16,46 @@ pub struct ErrorResponse {
     pub info_hash: Option<InfoHash>,
 }
 
+impl ErrorResponse {
+    /// Create response to request that couldn't be parsed
+    ///
+    /// Action and info hash are included if they can be extracted from the
+    /// request, since clients use them to associate the response with the
+    /// right torrent. WebTorrent clients treat responses without a known
+    /// action as connection errors.
+    pub fn invalid_request(request: &[u8]) -> Self {
+        let opt_value = ::serde_json::from_slice::<::serde_json::Value>(request).ok();
+        let opt_object = opt_value.as_ref().and_then(|value| value.as_object());
+
+        let opt_action_str = opt_object
+            .and_then(|object| object.get("action"))
+            .and_then(|action| action.as_str());
+
+        let (failure_reason, action) = match opt_action_str {
+            Some("announce") => (
+                "Invalid announce request",
+                Some(ErrorResponseAction::Announce),
+            ),
+            Some("scrape") => ("Invalid scrape request", Some(ErrorResponseAction::Scrape)),
+            Some(_) => ("Unknown action", None),
+            None => ("Invalid request", None),
+        };
+
+        // Only single info hashes are included, as in other error responses
+        let info_hash = action
+            .as_ref()
+            .and(opt_object)
+            .and_then(|object| object.get("info_hash"))
+            .and_then(|info_hash| ::serde_json::from_value(info_hash.clone()).ok());
+
+        Self {
+            failure_reason: failure_reason.into(),
+            action,
+            info_hash,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum ErrorResponseAction {